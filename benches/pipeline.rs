@@ -0,0 +1,98 @@
+//! Throughput and latency through the whole client↔server pipeline.
+//!
+//! A [`Client`] talks to a [`MockAgent`] over an in-memory pipe, so each
+//! prompt is serialized, framed, dispatched and streamed back exactly as
+//! with a spawned agent, minus the process. Run with `cargo bench`;
+//! `acp-bench` runs the same workloads and prints messages per second and
+//! latency percentiles.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use heroacp::client::{Client, ClientEvent};
+use heroacp::protocol::*;
+use heroacp::server::Server;
+use heroacp::testing::{in_process, initialize_params, MockAgent, Scenario, Step};
+use tokio::runtime::Runtime;
+use tokio::sync::broadcast::error::RecvError;
+
+const SESSION: &str = "bench";
+
+/// A client connected to an agent answering every prompt with `chunks`
+/// short message chunks.
+async fn connect(chunks: usize) -> Client {
+    let scenario = Scenario {
+        fallback: vec![Step::Message("sixteen bytes...".to_string()); chunks],
+        ..Scenario::default()
+    };
+    let client = in_process(Server::new(MockAgent::new(scenario).unwrap())).unwrap();
+    client.initialize(initialize_params()).await.unwrap();
+    client
+        .session_new(SessionNewParams {
+            session_id: SESSION.to_string(),
+            mode: None,
+            agent: None,
+        })
+        .await
+        .unwrap();
+    client
+}
+
+/// Send a prompt and wait for its response and final update.
+async fn turn(client: &Client, content: Vec<ContentBlock>) {
+    let mut events = client.subscribe();
+    client
+        .session_prompt(SessionPromptParams {
+            session_id: SESSION.to_string(),
+            content,
+        })
+        .await
+        .unwrap();
+    loop {
+        match events.recv().await {
+            Ok(ClientEvent::SessionUpdate(update))
+                if matches!(update.update_type, SessionUpdateType::Done) =>
+            {
+                break
+            }
+            Err(RecvError::Closed) => panic!("agent disconnected"),
+            _ => {}
+        }
+    }
+    client.clear_session_history(SESSION);
+}
+
+/// Prompts answered by many small message chunks.
+fn small_chunks(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("small_chunks");
+    for chunks in [1, 10, 100] {
+        let client = rt.block_on(connect(chunks));
+        group.throughput(Throughput::Elements(chunks as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(chunks), &client, |b, client| {
+            b.to_async(&rt).iter(|| turn(client, vec![ContentBlock::text("hi")]))
+        });
+    }
+    group.finish();
+}
+
+/// Prompts carrying one large embedded resource.
+fn large_resources(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let client = rt.block_on(connect(1));
+    let mut group = c.benchmark_group("large_resources");
+    for kib in [16, 256, 1024] {
+        let resource = ContentBlock::Resource {
+            uri: "file:///bench.txt".to_string(),
+            mime_type: "text/plain".to_string(),
+            content: "x".repeat(kib * 1024),
+            annotations: None,
+        };
+        group.throughput(Throughput::Bytes(kib as u64 * 1024));
+        group.bench_with_input(BenchmarkId::new("KiB", kib), &resource, |b, resource| {
+            b.to_async(&rt).iter(|| turn(&client, vec![resource.clone()]))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, small_chunks, large_resources);
+criterion_main!(benches);
@@ -0,0 +1,144 @@
+//! Procedural macros for HeroACP agents.
+//!
+//! Use these through `heroacp::server`, which re-exports them.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Error, FnArg, ImplItem, ImplItemFn, ItemImpl, LitStr};
+
+/// Generate `CustomMethods` for an impl block.
+///
+/// Every method in the block marked `#[acp_method("vendor/name")]` becomes a
+/// JSON-RPC method of that name. Its single parameter is deserialized from
+/// the request params and its result is serialized as the response.
+///
+/// ```rust,ignore
+/// #[acp_methods]
+/// impl MyAgent {
+///     #[acp_method("myvendor/do_thing")]
+///     async fn do_thing(&self, params: DoThingParams) -> AcpResult<DoThingResult> {
+///         // ...
+///     }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn acp_methods(args: TokenStream, input: TokenStream) -> TokenStream {
+    if !args.is_empty() {
+        let args = proc_macro2::TokenStream::from(args);
+        return Error::new(args.span(), "#[acp_methods] takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+    let item = parse_macro_input!(input as ItemImpl);
+    expand(item).unwrap_or_else(Error::into_compile_error).into()
+}
+
+/// Mark a method as an ACP method. Only valid inside an `#[acp_methods]` impl block.
+#[proc_macro_attribute]
+pub fn acp_method(_args: TokenStream, input: TokenStream) -> TokenStream {
+    let input = proc_macro2::TokenStream::from(input);
+    let error = Error::new(
+        input.span(),
+        "#[acp_method] must be used on a method inside an #[acp_methods] impl block",
+    )
+    .to_compile_error();
+    quote!(#error #input).into()
+}
+
+/// A method marked with `#[acp_method]`.
+struct Marked {
+    name: LitStr,
+    ident: syn::Ident,
+}
+
+fn expand(mut item: ItemImpl) -> syn::Result<proc_macro2::TokenStream> {
+    let mut methods = Vec::new();
+    for impl_item in &mut item.items {
+        if let ImplItem::Fn(method) = impl_item {
+            if let Some(name) = take_marker(method)? {
+                check_signature(method)?;
+                methods.push(Marked {
+                    name,
+                    ident: method.sig.ident.clone(),
+                });
+            }
+        }
+    }
+
+    let (impl_generics, _, where_clause) = item.generics.split_for_impl();
+    let self_ty = &item.self_ty;
+    let names = methods.iter().map(|m| &m.name);
+    let arms = methods.iter().map(|Marked { name, ident }| {
+        quote! {
+            #name => ::core::option::Option::Some(async move {
+                let params = ::heroacp::__private::serde_json::from_value(params).map_err(|e| {
+                    ::heroacp::protocol::AcpError::InvalidParams(e.to_string())
+                })?;
+                let result = self.#ident(params).await?;
+                ::heroacp::__private::serde_json::to_value(result)
+                    .map_err(::heroacp::protocol::AcpError::from)
+            }
+            .await),
+        }
+    });
+
+    Ok(quote! {
+        #item
+
+        impl #impl_generics ::heroacp::server::CustomMethods for #self_ty #where_clause {
+            fn custom_method_names(&self) -> ::std::vec::Vec<::std::string::String> {
+                ::std::vec![#(::std::string::String::from(#names)),*]
+            }
+
+            fn call_custom_method<'a>(
+                &'a self,
+                method: &'a str,
+                params: ::heroacp::__private::serde_json::Value,
+            ) -> ::heroacp::server::CustomMethodFuture<'a> {
+                ::std::boxed::Box::pin(async move {
+                    match method {
+                        #(#arms)*
+                        _ => ::core::option::Option::None,
+                    }
+                })
+            }
+        }
+    })
+}
+
+/// Remove the `#[acp_method("...")]` attribute from `method`, returning its name.
+fn take_marker(method: &mut ImplItemFn) -> syn::Result<Option<LitStr>> {
+    let Some(index) = method
+        .attrs
+        .iter()
+        .position(|attr| attr.path().is_ident("acp_method"))
+    else {
+        return Ok(None);
+    };
+    let attr = method.attrs.remove(index);
+    let name: LitStr = attr.parse_args()?;
+    if name.value().is_empty() {
+        return Err(Error::new(name.span(), "method name must not be empty"));
+    }
+    Ok(Some(name))
+}
+
+fn check_signature(method: &ImplItemFn) -> syn::Result<()> {
+    let sig = &method.sig;
+    if sig.asyncness.is_none() {
+        return Err(Error::new(sig.fn_token.span(), "#[acp_method] methods must be async"));
+    }
+    let mut inputs = sig.inputs.iter();
+    match inputs.next() {
+        Some(FnArg::Receiver(receiver)) if receiver.reference.is_some() && receiver.mutability.is_none() => {}
+        _ => return Err(Error::new(sig.span(), "#[acp_method] methods must take &self")),
+    }
+    if inputs.len() != 1 {
+        return Err(Error::new(
+            sig.inputs.span(),
+            "#[acp_method] methods take exactly one parameter besides &self",
+        ));
+    }
+    Ok(())
+}
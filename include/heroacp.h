@@ -0,0 +1,44 @@
+/*
+ * C ABI of the heroacp client, built with the `ffi` feature:
+ *
+ *     cargo rustc --release --lib --features ffi --crate-type cdylib
+ *
+ * See src/ffi.rs for the details. Strings are UTF-8 and NUL-terminated.
+ */
+#ifndef HEROACP_H
+#define HEROACP_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* An agent with its session open. */
+typedef struct HeroacpClient HeroacpClient;
+
+/* Receives the JSON of a session update; the string is only valid during the call. */
+typedef void (*HeroacpUpdateCallback)(void *user_data, const char *update_json);
+
+/* Message of the last error on the calling thread, or NULL. */
+const char *heroacp_last_error(void);
+
+/* Spawn and initialize an agent and open a session. NULL on failure. */
+HeroacpClient *heroacp_client_spawn(const char *command, const char *client_name, const char *client_version);
+
+/* Call `callback` with every session update, and with MCP server status changes
+ * as updates of type "mcp_server_status", replacing any earlier one. 0 or -1. */
+int heroacp_client_on_update(HeroacpClient *client, HeroacpUpdateCallback callback, void *user_data);
+
+/* Send a prompt and block until the turn ends. 0 or -1. */
+int heroacp_client_prompt(HeroacpClient *client, const char *text);
+
+/* Cancel the running prompt, from another thread. 0 or -1. */
+int heroacp_client_cancel(HeroacpClient *client);
+
+/* Shut the agent down and free the client. NULL is ignored. */
+void heroacp_client_free(HeroacpClient *client);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* HEROACP_H */
@@ -0,0 +1,385 @@
+//! Example ACP agent backed by the Anthropic Messages API.
+//!
+//! Like `acp-openai-agent`, but shows more of what a session update can say:
+//! - Streams extended thinking as thought chunks and the answer as message chunks
+//! - Runs `tool_use` blocks as ACP tool calls and sends back their results
+//! - Keeps thinking blocks, signatures included, in the conversation so the
+//!   model can go on thinking after a tool call
+//! - Stops the request to the model when the prompt is cancelled
+//!
+//! Configured through the environment:
+//! - `ANTHROPIC_API_KEY`: API key
+//! - `ANTHROPIC_BASE_URL`: endpoint, defaults to `https://api.anthropic.com`
+//! - `ANTHROPIC_MODEL`: model, defaults to `claude-sonnet-4-5`
+//! - `ANTHROPIC_THINKING_BUDGET`: tokens the model may think for, defaults to
+//!   4096; 0 turns thinking off
+//!
+//! Run with: cargo run --features anthropic-agent --bin acp-anthropic-agent
+
+mod common;
+
+use async_trait::async_trait;
+use common::{model_error, tool_output, tools, MAX_TOOL_ROUNDS, SYSTEM_PROMPT};
+use heroacp::protocol::*;
+use heroacp::server::{Agent, AgentContext, Server, SessionHandle};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use tokio::sync::Mutex;
+
+/// Version of the Messages API spoken.
+const API_VERSION: &str = "2023-06-01";
+
+/// Tokens of answer, on top of the thinking budget.
+const MAX_ANSWER_TOKENS: u32 = 8192;
+
+/// Where to find the model.
+struct ModelConfig {
+    base_url: String,
+    api_key: String,
+    model: String,
+    thinking_budget: u32,
+}
+
+impl ModelConfig {
+    fn from_env() -> Self {
+        Self {
+            base_url: std::env::var("ANTHROPIC_BASE_URL")
+                .unwrap_or_else(|_| "https://api.anthropic.com".to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            api_key: std::env::var("ANTHROPIC_API_KEY").unwrap_or_default(),
+            model: std::env::var("ANTHROPIC_MODEL").unwrap_or_else(|_| "claude-sonnet-4-5".to_string()),
+            thinking_budget: std::env::var("ANTHROPIC_THINKING_BUDGET")
+                .ok()
+                .and_then(|budget| budget.parse().ok())
+                .unwrap_or(4096),
+        }
+    }
+}
+
+/// A content block of the model's answer, as it streams in.
+struct Block {
+    /// The block as the API describes it at its start.
+    block: Value,
+    /// `input` of a `tool_use` block, which streams in as pieces of JSON.
+    input_json: String,
+}
+
+/// What the model answered in one turn.
+#[derive(Default)]
+struct Turn {
+    blocks: BTreeMap<u64, Block>,
+    stop_reason: Option<String>,
+}
+
+impl Turn {
+    /// Add one streamed `event`, sending thinking and text to `session`.
+    async fn add(&mut self, event: &Value, session: &SessionHandle) -> AcpResult<()> {
+        let index = event["index"].as_u64().unwrap_or(0);
+        match event["type"].as_str().unwrap_or_default() {
+            "content_block_start" => {
+                let block = Block {
+                    block: event["content_block"].clone(),
+                    input_json: String::new(),
+                };
+                self.blocks.insert(index, block);
+            }
+            "content_block_delta" => {
+                let Some(Block { block, input_json }) = self.blocks.get_mut(&index) else {
+                    return Ok(());
+                };
+                let delta = &event["delta"];
+                match delta["type"].as_str().unwrap_or_default() {
+                    "thinking_delta" => {
+                        let thinking = delta["thinking"].as_str().unwrap_or_default();
+                        append(&mut block["thinking"], thinking);
+                        let _ = session.send_thought(thinking).await;
+                    }
+                    "text_delta" => {
+                        let text = delta["text"].as_str().unwrap_or_default();
+                        append(&mut block["text"], text);
+                        let _ = session.send_message_chunk(text).await;
+                    }
+                    "signature_delta" => append(&mut block["signature"], delta["signature"].as_str().unwrap_or_default()),
+                    "input_json_delta" => input_json.push_str(delta["partial_json"].as_str().unwrap_or_default()),
+                    _ => {}
+                }
+            }
+            "content_block_stop" => {
+                if let Some(Block { block, input_json }) = self.blocks.get_mut(&index) {
+                    if block["type"] == "tool_use" && !input_json.is_empty() {
+                        block["input"] = serde_json::from_str(input_json)?;
+                    }
+                }
+            }
+            "message_delta" => {
+                self.stop_reason = event["delta"]["stop_reason"].as_str().map(str::to_string);
+            }
+            "error" => {
+                return Err(AcpError::InternalError(format!("model failed: {}", event["error"]["message"])));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// The `tool_use` blocks of the answer.
+    fn tool_uses(&self) -> Vec<&Value> {
+        self.blocks
+            .values()
+            .map(|block| &block.block)
+            .filter(|block| block["type"] == "tool_use")
+            .collect()
+    }
+
+    /// The turn as a message of the conversation.
+    fn message(&self) -> Value {
+        let content: Vec<Value> = self.blocks.values().map(|block| block.block.clone()).collect();
+        json!({ "role": "assistant", "content": content })
+    }
+}
+
+/// Append `text` to the string `value`, which may not be there yet.
+fn append(value: &mut Value, text: &str) {
+    match value {
+        Value::String(s) => s.push_str(text),
+        _ => *value = Value::String(text.to_string()),
+    }
+}
+
+/// An agent that asks a model through the Anthropic Messages API.
+struct AnthropicAgent {
+    config: ModelConfig,
+    http: reqwest::Client,
+    /// Conversation of each session, in the Messages API format.
+    conversations: Mutex<HashMap<String, Vec<Value>>>,
+}
+
+impl AnthropicAgent {
+    fn new(config: ModelConfig) -> Self {
+        Self {
+            config,
+            http: reqwest::Client::new(),
+            conversations: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Stream the model's answer to `messages` into `session`.
+    ///
+    /// Returns `None` if the prompt was cancelled first.
+    async fn complete(&self, messages: &[Value], session: &SessionHandle, ctx: &AgentContext) -> AcpResult<Option<Turn>> {
+        let tools: Vec<Value> = ctx
+            .tools()
+            .infos()
+            .into_iter()
+            .map(|tool| json!({ "name": tool.name, "description": tool.description, "input_schema": tool.parameters }))
+            .collect();
+        let mut body = json!({
+            "model": self.config.model,
+            "max_tokens": MAX_ANSWER_TOKENS + self.config.thinking_budget,
+            "system": SYSTEM_PROMPT,
+            "messages": messages,
+            "stream": true,
+        });
+        if !tools.is_empty() {
+            body["tools"] = Value::Array(tools);
+        }
+        if self.config.thinking_budget > 0 {
+            body["thinking"] = json!({ "type": "enabled", "budget_tokens": self.config.thinking_budget });
+        }
+
+        let request = self
+            .http
+            .post(format!("{}/v1/messages", self.config.base_url))
+            .header("x-api-key", &self.config.api_key)
+            .header("anthropic-version", API_VERSION)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_string());
+
+        let cancel = ctx.cancellation_token();
+        // Dropping the response on cancellation closes the connection, so the
+        // model stops generating too
+        let mut response = tokio::select! {
+            _ = cancel.cancelled() => return Ok(None),
+            response = request.send() => response.map_err(model_error)?,
+        };
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(AcpError::InternalError(format!("model answered {}: {}", status, text)));
+        }
+
+        let mut turn = Turn::default();
+        let mut buffer = Vec::new();
+        loop {
+            let chunk = tokio::select! {
+                _ = cancel.cancelled() => return Ok(None),
+                chunk = response.chunk() => chunk.map_err(model_error)?,
+            };
+            let Some(chunk) = chunk else { break };
+            buffer.extend_from_slice(&chunk);
+            // Server-sent events; the `data:` line carries the event's type too
+            while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+                let Some(data) = line.trim().strip_prefix("data:").map(str::trim) else {
+                    continue;
+                };
+                let event: Value = serde_json::from_str(data)?;
+                if event["type"] == "message_stop" {
+                    return Ok(Some(turn));
+                }
+                turn.add(&event, session).await?;
+            }
+        }
+        Ok(Some(turn))
+    }
+
+    /// Wrap up a cancelled prompt.
+    async fn cancelled(&self, session: &SessionHandle) -> SessionPromptResult {
+        eprintln!("[AnthropicAgent] Prompt cancelled in session {}", session.session_id());
+        let _ = session.send_status(AgentState::Idle).await;
+        let _ = session.send_done().await;
+        SessionPromptResult {
+            status: "cancelled".to_string(),
+        }
+    }
+
+    /// Keep `messages` as the conversation of `session` and end the prompt.
+    async fn finish(&self, session: &SessionHandle, messages: Vec<Value>) -> SessionPromptResult {
+        self.conversations
+            .lock()
+            .await
+            .insert(session.session_id().to_string(), messages);
+        let _ = session.send_status(AgentState::Idle).await;
+        let _ = session.send_done().await;
+        SessionPromptResult {
+            status: "ok".to_string(),
+        }
+    }
+}
+
+/// The `tool_result` block answering the `tool_use` block `id` with `result`.
+fn tool_result(id: &Value, result: AcpResult<Value>) -> Value {
+    let (content, is_error) = match result {
+        Err(e) => (e.message(), true),
+        result => (tool_output(result), false),
+    };
+    json!({ "type": "tool_result", "tool_use_id": id, "content": content, "is_error": is_error })
+}
+
+#[async_trait]
+impl Agent for AnthropicAgent {
+    async fn initialize(&self, params: InitializeParams) -> AcpResult<InitializeResult> {
+        eprintln!(
+            "[AnthropicAgent] Client: {} v{}, using {} at {}",
+            params.client_info.name, params.client_info.version, self.config.model, self.config.base_url
+        );
+
+        Ok(InitializeResult {
+            agent_info: AgentInfo {
+                name: "HeroACP Anthropic Agent".to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+            },
+            capabilities: AgentCapabilities {
+                streaming: true,
+                image: true,
+                ..Default::default()
+            },
+            instructions: None,
+        })
+    }
+
+    async fn session_new(&self, params: SessionNewParams, _ctx: AgentContext) -> AcpResult<SessionNewResult> {
+        self.conversations
+            .lock()
+            .await
+            .insert(params.session_id.clone(), Vec::new());
+        Ok(SessionNewResult {
+            session_id: params.session_id,
+        })
+    }
+
+    async fn session_load(&self, params: SessionLoadParams, _ctx: AgentContext) -> AcpResult<SessionLoadResult> {
+        // Conversations only live as long as the process
+        let loaded = self.conversations.lock().await.contains_key(&params.session_id);
+        Ok(SessionLoadResult {
+            session_id: params.session_id,
+            loaded,
+        })
+    }
+
+    async fn session_prompt(
+        &self,
+        params: SessionPromptParams,
+        session: SessionHandle,
+        ctx: AgentContext,
+    ) -> AcpResult<SessionPromptResult> {
+        let content: Vec<Value> = params
+            .content
+            .iter()
+            .filter_map(|block| match block {
+                ContentBlock::Text { text, .. } => Some(json!({ "type": "text", "text": text })),
+                ContentBlock::Image { format, data, .. } => Some(json!({
+                    "type": "image",
+                    "source": { "type": "base64", "media_type": format!("image/{}", format), "data": data },
+                })),
+                _ => None,
+            })
+            .collect();
+
+        // Work on a copy so a failed or cancelled prompt leaves no half turn behind
+        let mut messages = self
+            .conversations
+            .lock()
+            .await
+            .get(&params.session_id)
+            .cloned()
+            .ok_or_else(|| AcpError::InvalidState(format!("Unknown session: {}", params.session_id)))?;
+        messages.push(json!({ "role": "user", "content": content }));
+
+        let _ = session.send_status(AgentState::Thinking).await;
+        for _ in 0..MAX_TOOL_ROUNDS {
+            let Some(turn) = self.complete(&messages, &session, &ctx).await? else {
+                return Ok(self.cancelled(&session).await);
+            };
+            messages.push(turn.message());
+            if turn.stop_reason.as_deref() != Some("tool_use") {
+                if turn.stop_reason.as_deref() == Some("max_tokens") {
+                    let _ = session.send_message_chunk("\n\nThe answer was cut off at the token limit.").await;
+                }
+                return Ok(self.finish(&session, messages).await);
+            }
+
+            let _ = session.send_status(AgentState::RunningTool).await;
+            let mut results = Vec::new();
+            for tool_use in turn.tool_uses() {
+                let name = tool_use["name"].as_str().unwrap_or_default();
+                let result = tokio::select! {
+                    _ = ctx.cancellation_token().cancelled() => return Ok(self.cancelled(&session).await),
+                    result = ctx.tools().call(&session, name, tool_use["input"].clone(), &ctx) => result,
+                };
+                results.push(tool_result(&tool_use["id"], result));
+            }
+            messages.push(json!({ "role": "user", "content": results }));
+            let _ = session.send_status(AgentState::Thinking).await;
+        }
+
+        let _ = session
+            .send_message_chunk(format!("\n\nStopped after {} rounds of tool calls.", MAX_TOOL_ROUNDS))
+            .await;
+        Ok(self.finish(&session, messages).await)
+    }
+
+    async fn session_cancel(&self, params: SessionCancelParams, _ctx: AgentContext) -> AcpResult<()> {
+        eprintln!("[AnthropicAgent] Cancelling session: {}", params.session_id);
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let agent = AnthropicAgent::new(ModelConfig::from_env());
+    Server::new(agent).with_tools(tools()).run().await?;
+    Ok(())
+}
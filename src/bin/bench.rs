@@ -0,0 +1,192 @@
+//! Measures throughput and latency through the client↔server pipeline.
+//!
+//! Connects a client to a scripted agent over an in-memory pipe, the same
+//! workloads as `cargo bench`, and prints messages per second and latency
+//! percentiles for two of them:
+//!
+//! - small chunks: each prompt is answered by `--chunks` short message
+//!   chunks
+//! - large resources: each prompt carries a `--resource-kib` resource block
+//!
+//! Latency is from sending a prompt to receiving both its response and its
+//! final `done` update.
+//!
+//! Usage: acp-bench [--prompts <n>] [--chunks <n>] [--resource-kib <n>]
+
+use heroacp::client::{Client, ClientEvent};
+use heroacp::protocol::*;
+use heroacp::server::Server;
+use heroacp::testing::{in_process, initialize_params, MockAgent, Scenario, Step};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+
+const USAGE: &str = "Usage: acp-bench [--prompts <n>] [--chunks <n>] [--resource-kib <n>]";
+
+const SESSION: &str = "bench";
+
+/// Prompts sent before measuring, as a share of the measured ones.
+const WARM_UP_DIVISOR: usize = 10;
+
+/// A client connected to an agent answering every prompt with `chunks`
+/// short message chunks.
+async fn connect(chunks: usize) -> AcpResult<Client> {
+    let scenario = Scenario {
+        fallback: vec![Step::Message("sixteen bytes...".to_string()); chunks],
+        ..Scenario::default()
+    };
+    let client = in_process(Server::new(MockAgent::new(scenario)?))?;
+    client.initialize(initialize_params()).await?;
+    client
+        .session_new(SessionNewParams {
+            session_id: SESSION.to_string(),
+            mode: None,
+            agent: None,
+        })
+        .await?;
+    Ok(client)
+}
+
+/// Send a prompt and wait for its response and final update, returning how
+/// many messages came back.
+async fn turn(client: &Client, content: Vec<ContentBlock>) -> AcpResult<usize> {
+    let mut events = client.subscribe();
+    client
+        .session_prompt(SessionPromptParams {
+            session_id: SESSION.to_string(),
+            content,
+        })
+        .await?;
+    let mut messages = 1;
+    loop {
+        match events.recv().await {
+            Ok(ClientEvent::SessionUpdate(update)) => {
+                messages += 1;
+                if matches!(update.update_type, SessionUpdateType::Done) {
+                    break;
+                }
+            }
+            Ok(_) => {}
+            Err(RecvError::Lagged(missed)) => messages += missed as usize,
+            Err(RecvError::Closed) => {
+                return Err(AcpError::InternalError("Agent disconnected".to_string()))
+            }
+        }
+    }
+    client.clear_session_history(SESSION);
+    Ok(messages)
+}
+
+/// What a workload measured.
+struct Stats {
+    elapsed: Duration,
+    messages: usize,
+    /// Latency of each prompt, sorted.
+    latencies: Vec<Duration>,
+}
+
+impl Stats {
+    fn percentile(&self, p: usize) -> Duration {
+        self.latencies[(self.latencies.len() - 1) * p / 100]
+    }
+
+    fn print(&self, bytes: Option<usize>) {
+        let secs = self.elapsed.as_secs_f64();
+        print!(
+            "  {} messages in {:.2}s: {:.0} msgs/s",
+            self.messages,
+            secs,
+            self.messages as f64 / secs
+        );
+        if let Some(bytes) = bytes {
+            print!(", {:.1} MiB/s", bytes as f64 / secs / (1024.0 * 1024.0));
+        }
+        println!();
+        println!(
+            "  latency p50 {:.3}ms  p99 {:.3}ms  max {:.3}ms",
+            ms(self.percentile(50)),
+            ms(self.percentile(99)),
+            ms(self.percentile(100))
+        );
+    }
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Warm up, then send `prompts` prompts of `content` one after another.
+async fn run(client: &Client, prompts: usize, content: Vec<ContentBlock>) -> AcpResult<Stats> {
+    for _ in 0..prompts / WARM_UP_DIVISOR {
+        turn(client, content.clone()).await?;
+    }
+
+    let mut messages = 0;
+    let mut latencies = Vec::with_capacity(prompts);
+    let started = Instant::now();
+    for _ in 0..prompts {
+        let sent = Instant::now();
+        messages += turn(client, content.clone()).await?;
+        latencies.push(sent.elapsed());
+    }
+    let elapsed = started.elapsed();
+    latencies.sort();
+    Ok(Stats {
+        elapsed,
+        messages,
+        latencies,
+    })
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let mut prompts = 500;
+    let mut chunks = 100;
+    let mut resource_kib = 1024;
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    for pair in args.chunks(2) {
+        let value = match pair.get(1).map(|value| value.parse::<usize>()) {
+            Some(Ok(value)) if value > 0 => value,
+            _ => {
+                eprintln!("{}", USAGE);
+                return ExitCode::FAILURE;
+            }
+        };
+        match pair[0].as_str() {
+            "--prompts" => prompts = value,
+            "--chunks" => chunks = value,
+            "--resource-kib" => resource_kib = value,
+            _ => {
+                eprintln!("{}", USAGE);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let result = async {
+        println!("small chunks: {} prompts of {} chunks", prompts, chunks);
+        let client = connect(chunks).await?;
+        run(&client, prompts, vec![ContentBlock::text("hi")]).await?.print(None);
+
+        println!("large resources: {} prompts of {} KiB", prompts, resource_kib);
+        let client = connect(1).await?;
+        let resource = ContentBlock::Resource {
+            uri: "file:///bench.txt".to_string(),
+            mime_type: "text/plain".to_string(),
+            content: "x".repeat(resource_kib * 1024),
+            annotations: None,
+        };
+        run(&client, prompts, vec![resource]).await?.print(Some(prompts * resource_kib * 1024));
+        AcpResult::Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Benchmark failed: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! Bridge between an editor speaking heroacp's ACP and an agent speaking
+//! another dialect.
+//!
+//! Spawns the agent and translates every message between the editor on
+//! stdin/stdout and the agent's stdin/stdout, so editors built on heroacp
+//! can drive camelCase ACP agents that they couldn't talk to directly. The
+//! agent's stderr is passed through.
+//!
+//! Usage: acp-bridge [--dialect acp|claude-code|gemini-cli|goose] <agent-command> [args...]
+//!
+//! Without `--dialect`, the dialect is picked from the agent command's name.
+
+use heroacp::bridge::dialect::relay;
+use heroacp::client::Dialect;
+use std::process::{ExitCode, Stdio};
+use tokio::process::Command;
+
+const USAGE: &str = "Usage: acp-bridge [--dialect acp|claude-code|gemini-cli|goose] <agent-command> [args...]";
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+
+    let mut dialect = None;
+    if args.first().map(String::as_str) == Some("--dialect") {
+        let Some(name) = args.get(1) else {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        };
+        match name.parse::<Dialect>() {
+            Ok(parsed) => dialect = Some(parsed),
+            Err(e) => {
+                eprintln!("{}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+        args.drain(..2);
+    }
+    let Some((command, agent_args)) = args.split_first() else {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    };
+    let dialect = dialect.unwrap_or_else(|| Dialect::for_command(command));
+
+    let mut child = match Command::new(command)
+        .args(agent_args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .kill_on_drop(true)
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("Failed to spawn agent {}: {}", command, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let agent_in = child.stdin.take().expect("agent stdin is piped");
+    let agent_out = child.stdout.take().expect("agent stdout is piped");
+
+    if let Err(e) = relay(dialect, tokio::io::stdin(), tokio::io::stdout(), agent_out, agent_in).await {
+        eprintln!("Bridge failed: {}", e);
+        return ExitCode::FAILURE;
+    }
+
+    // The agent closed its output; report how it exited
+    match child.wait().await {
+        Ok(status) if status.success() => ExitCode::SUCCESS,
+        _ => ExitCode::FAILURE,
+    }
+}
@@ -5,16 +5,39 @@
 //! - Goose AI agent
 //! - Any other ACP-compatible agent
 //!
+//! When the agent asks permission for a tool call, the user answers in the
+//! terminal.
+//!
 //! Run with: cargo run --bin acp-client [agent-command]
 //!
+//! With `ACP_TRANSCRIPT` naming a file, every message to and from the agent
+//! is logged there; see `heroacp::server::Transcript`.
+//!
 //! Examples:
 //!   cargo run --bin acp-client ./target/release/acp-server
 //!   cargo run --bin acp-client goose
-
-use heroacp::client::{default_capabilities, Client, UpdateHandler};
+//!
+//! With `--healthcheck [--timeout SECS] <agent-command> [args...]` it only
+//! starts the agent, initializes it and asks its `agent/status`, printing
+//! the status and exiting with 0, or 1 if the agent fails or doesn't answer
+//! in time (10 seconds by default). Agents without `agent/status` pass if
+//! they answer a ping.
+
+use async_trait::async_trait;
+use heroacp::client::{default_capabilities, reject_permission, Client, PermissionHandler, UpdateHandler};
+use heroacp::server::Transcript;
 use heroacp::protocol::*;
 use std::io::Write;
-use tokio::io::{self, AsyncBufReadExt, BufReader};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{self, AsyncBufReadExt, BufReader, Lines, Stdin};
+use tokio::sync::Mutex;
+
+/// Deadline of `--healthcheck` unless `--timeout` gives one.
+const HEALTHCHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Lines typed by the user, shared by the REPL and the permission prompt.
+type Input = Arc<Mutex<Lines<BufReader<Stdin>>>>;
 
 /// Terminal-based update handler that prints responses to stdout.
 struct TerminalHandler {
@@ -97,12 +120,168 @@ impl UpdateHandler for TerminalHandler {
         eprintln!("\x1b[35m[Mode Change] {}\x1b[0m", mode);
     }
 
+    fn on_model_change(&self, _session_id: &str, model: &str) {
+        eprintln!("\x1b[35m[Model Change] {}\x1b[0m", model);
+    }
+
+    fn on_queued(&self, _session_id: &str, _prompt_id: &str, position: usize) {
+        eprintln!("\x1b[90m[Queued] position {}\x1b[0m", position);
+    }
+
     fn on_done(&self, _session_id: &str) {
         // Print newline after done
         println!();
     }
 }
 
+/// Asks the user in the terminal before the agent runs a tool call.
+struct PermissionPrompt {
+    input: Input,
+}
+
+impl PermissionPrompt {
+    /// The allow and reject options, if those are the only two offered.
+    fn yes_no(options: &[PermissionOption]) -> Option<(&PermissionOption, &PermissionOption)> {
+        match options {
+            [a, b] if a.kind.is_allow() && !b.kind.is_allow() => Some((a, b)),
+            [a, b] if !a.kind.is_allow() && b.kind.is_allow() => Some((b, a)),
+            _ => None,
+        }
+    }
+
+    /// Match the user's answer to an option.
+    fn choose<'a>(options: &'a [PermissionOption], answer: &str) -> Option<&'a PermissionOption> {
+        if let Some((allow, reject)) = Self::yes_no(options) {
+            return match answer.to_lowercase().as_str() {
+                "y" | "yes" => Some(allow),
+                "n" | "no" => Some(reject),
+                _ => None,
+            };
+        }
+        let index: usize = answer.parse().ok()?;
+        options.get(index.checked_sub(1)?)
+    }
+}
+
+#[async_trait]
+impl PermissionHandler for PermissionPrompt {
+    async fn request_permission(&self, params: RequestPermissionParams) -> PermissionOutcome {
+        let tool = &params.tool_call;
+        eprintln!();
+        eprintln!("\x1b[33m[Permission] The agent wants to run {} ({})\x1b[0m", tool.name, tool.id);
+        if !tool.arguments.is_null() {
+            eprintln!(
+                "\x1b[33m  Args: {}\x1b[0m",
+                serde_json::to_string_pretty(&tool.arguments).unwrap_or_default()
+            );
+        }
+
+        let question = match Self::yes_no(&params.options) {
+            Some((allow, reject)) => format!("{} [y] / {} [n]? ", allow.name, reject.name),
+            None => {
+                for (i, option) in params.options.iter().enumerate() {
+                    eprintln!("  {}) {}", i + 1, option.name);
+                }
+                format!("Choose 1-{}: ", params.options.len())
+            }
+        };
+
+        let mut input = self.input.lock().await;
+        loop {
+            eprint!("{}", question);
+            std::io::stderr().flush().ok();
+
+            // Treat EOF or an empty answer as a refusal
+            let answer = match input.next_line().await {
+                Ok(Some(line)) if !line.trim().is_empty() => line,
+                _ => return reject_permission(&params.options),
+            };
+            match Self::choose(&params.options, answer.trim()) {
+                Some(option) => {
+                    return PermissionOutcome::Selected {
+                        option_id: option.option_id.clone(),
+                    }
+                }
+                None => eprintln!("Please answer one of the options."),
+            }
+        }
+    }
+}
+
+/// `initialize` params for this client.
+fn initialize_params(working_directory: String) -> InitializeParams {
+    InitializeParams {
+        protocol_version: PROTOCOL_VERSION.to_string(),
+        client_info: ClientInfo {
+            name: "heroacp-client".to_string(),
+            version: "0.1.0".to_string(),
+        },
+        capabilities: default_capabilities(),
+        working_directory,
+        mcp_servers: vec![],
+    }
+}
+
+/// Start the agent in `args`, initialize it and ask its status before the
+/// deadline. Returns the exit code: 0 if healthy, 1 if not, 2 on bad usage.
+async fn healthcheck(args: &[String]) -> i32 {
+    let (timeout, command) = match args {
+        [flag, secs, command @ ..] if flag == "--timeout" => match secs.parse::<f64>() {
+            Ok(secs) if secs > 0.0 && secs.is_finite() => (Duration::from_secs_f64(secs), command),
+            _ => {
+                eprintln!("Invalid --timeout: {}", secs);
+                return 2;
+            }
+        },
+        command => (HEALTHCHECK_TIMEOUT, command),
+    };
+    let Some((program, program_args)) = command.split_first() else {
+        eprintln!("Usage: acp-client --healthcheck [--timeout SECS] <agent-command> [args...]");
+        return 2;
+    };
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    let mut client = match Client::builder(program).args(program_args).spawn().await {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Unhealthy: failed to start {}: {}", program, e);
+            return 1;
+        }
+    };
+    let check = async {
+        let cwd = std::env::current_dir()?.to_string_lossy().to_string();
+        client.initialize(initialize_params(cwd)).await?;
+        match client.status().await {
+            Err(AcpError::MethodNotFound(_)) => client.ping().await.map(|_| None),
+            status => status.map(Some),
+        }
+    };
+    let code = match tokio::time::timeout_at(deadline, check).await {
+        Ok(Ok(Some(status))) => {
+            println!("{}", serde_json::to_string_pretty(&status).unwrap_or_default());
+            0
+        }
+        Ok(Ok(None)) => {
+            println!("Healthy: the agent answers ping but not agent/status");
+            0
+        }
+        Ok(Err(e)) => {
+            eprintln!("Unhealthy: {}", e);
+            1
+        }
+        Err(_) => {
+            eprintln!("Unhealthy: no answer within {:?}", timeout);
+            1
+        }
+    };
+    if code == 0 {
+        let _ = client.shutdown(Duration::from_secs(1)).await;
+    } else {
+        let _ = client.kill().await;
+    }
+    code
+}
+
 fn print_help() {
     println!("HeroACP Client - Agent Client Protocol CLI");
     println!();
@@ -111,6 +290,8 @@ fn print_help() {
     println!("  /info     - Show agent information");
     println!("  /quit     - Exit the client");
     println!("  /new      - Start a new session");
+    println!("  /models   - List the agent's models");
+    println!("  /model ID - Switch the session to a model");
     println!();
     println!("Just type your message and press Enter to send it to the agent.");
 }
@@ -119,6 +300,10 @@ fn print_help() {
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args: Vec<String> = std::env::args().collect();
 
+    if args.get(1).is_some_and(|arg| arg == "--healthcheck") {
+        std::process::exit(healthcheck(&args[2..]).await);
+    }
+
     // Determine agent command
     let agent_command = if args.len() > 1 {
         args[1].as_str()
@@ -148,8 +333,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!();
     println!("Connecting to agent: {}", agent_command);
 
+    // Log the wire traffic when ACP_TRANSCRIPT names a file
+    let transcript = Transcript::new();
+    if let Ok(path) = std::env::var("ACP_TRANSCRIPT") {
+        transcript.start(&path)?;
+        println!("Recording transcript to {}", path);
+    }
+
     // Spawn client
-    let client = match Client::spawn(agent_command).await {
+    let client = match Client::builder(agent_command).transcript(transcript).spawn().await {
         Ok(c) => c,
         Err(e) => {
             eprintln!("Failed to spawn agent: {}", e);
@@ -163,26 +355,21 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     };
 
-    // Set up update handler
+    // Set up update handler and ask the user before tool calls
+    let input: Input = Arc::new(Mutex::new(BufReader::new(io::stdin()).lines()));
     client.set_update_handler(Box::new(TerminalHandler::new())).await;
+    client
+        .set_permission_handler(Box::new(PermissionPrompt {
+            input: input.clone(),
+        }))
+        .await;
 
     // Get working directory
     let cwd = std::env::current_dir()?.to_string_lossy().to_string();
 
     // Initialize connection
     println!("Initializing connection...");
-    let init_result = client
-        .initialize(InitializeParams {
-            protocol_version: PROTOCOL_VERSION.to_string(),
-            client_info: ClientInfo {
-                name: "heroacp-client".to_string(),
-                version: "0.1.0".to_string(),
-            },
-            capabilities: default_capabilities(),
-            working_directory: cwd,
-            mcp_servers: vec![],
-        })
-        .await?;
+    let init_result = client.initialize(initialize_params(cwd)).await?;
 
     println!();
     println!("Connected to: {} v{}",
@@ -218,6 +405,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .session_new(SessionNewParams {
             session_id: session_id.clone(),
             mode: Some("agent".to_string()),
+            agent: None,
         })
         .await?;
 
@@ -229,15 +417,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!();
 
     // Interactive REPL
-    let stdin = BufReader::new(io::stdin());
-    let mut lines = stdin.lines();
     let mut current_session = session.session_id;
 
     loop {
         print!("> ");
         std::io::stdout().flush()?;
 
-        let line = match lines.next_line().await? {
+        // Released while the prompt runs, so the agent can ask for permission
+        let line = match input.lock().await.next_line().await? {
             Some(l) => l,
             None => break, // EOF
         };
@@ -271,6 +458,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     match client.session_new(SessionNewParams {
                         session_id: new_session_id.clone(),
                         mode: Some("agent".to_string()),
+                        agent: None,
                     }).await {
                         Ok(s) => {
                             current_session = s.session_id.clone();
@@ -282,6 +470,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     }
                     continue;
                 }
+                "/models" => {
+                    if init_result.capabilities.models.is_empty() {
+                        println!("The agent doesn't list models.");
+                    }
+                    for model in &init_result.capabilities.models {
+                        match &model.description {
+                            Some(description) => println!("  {} - {}", model.id, description),
+                            None => println!("  {}", model.id),
+                        }
+                    }
+                    continue;
+                }
+                _ if line.starts_with("/model ") => {
+                    let model_id = line["/model ".len()..].trim();
+                    match client.session_set_model(SessionSetModelParams {
+                        session_id: current_session.clone(),
+                        model_id: model_id.to_string(),
+                    }).await {
+                        Ok(()) => println!("Session now uses {}", model_id),
+                        Err(e) => eprintln!("Failed to switch model: {}", e),
+                    }
+                    continue;
+                }
                 _ => {
                     println!("Unknown command: {}", line);
                     println!("Type /help for available commands.");
@@ -294,9 +505,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         match client
             .session_prompt(SessionPromptParams {
                 session_id: current_session.clone(),
-                content: vec![ContentBlock::Text {
-                    text: line.to_string(),
-                }],
+                content: vec![ContentBlock::text(line)],
             })
             .await
         {
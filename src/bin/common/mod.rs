@@ -0,0 +1,62 @@
+//! What the example model agents share: the tools they offer the model and
+//! how they report its failures. Each agent keeps only the code that speaks
+//! its model's API.
+
+use heroacp::protocol::*;
+use heroacp::server::ToolRegistry;
+use serde_json::{json, Value};
+
+/// Tool calls the model may make in a row before the prompt is ended.
+pub const MAX_TOOL_ROUNDS: usize = 25;
+
+pub const SYSTEM_PROMPT: &str = "You are a coding agent working in the user's editor. \
+    Use the tools to read and change files, and answer concisely.";
+
+pub fn model_error(e: reqwest::Error) -> AcpError {
+    AcpError::InternalError(format!("model request failed: {}", e))
+}
+
+/// The text of a tool's result, for the model.
+pub fn tool_output(result: AcpResult<Value>) -> String {
+    match result {
+        Ok(Value::String(text)) => text,
+        Ok(value) => value.to_string(),
+        Err(e) => format!("Error: {}", e.message()),
+    }
+}
+
+/// Tools that work on the editor's files.
+pub fn tools() -> ToolRegistry {
+    ToolRegistry::new()
+        .register(
+            "read_file",
+            "Read a text file",
+            json!({
+                "type": "object",
+                "properties": { "path": { "type": "string", "description": "Absolute path to the file" } },
+                "required": ["path"],
+            }),
+            |args, ctx| async move {
+                let path = args["path"].as_str().unwrap_or_default();
+                Ok(Value::String(ctx.connection().read_file(path).await?))
+            },
+        )
+        .register(
+            "write_file",
+            "Replace the content of a text file, creating it if needed",
+            json!({
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string", "description": "Absolute path to the file" },
+                    "content": { "type": "string", "description": "The new content" },
+                },
+                "required": ["path", "content"],
+            }),
+            |args, ctx| async move {
+                let path = args["path"].as_str().unwrap_or_default();
+                let content = args["content"].as_str().unwrap_or_default();
+                ctx.connection().write_file(path, content).await?;
+                Ok(json!("written"))
+            },
+        )
+}
@@ -0,0 +1,48 @@
+//! Checks an ACP agent against the protocol.
+//!
+//! Spawns the agent once per check and prints a pass/fail report covering
+//! the shape of `initialize`, error codes, cancellation and update framing;
+//! exits non-zero if any check fails. See `heroacp::testing::Conformance`.
+//!
+//! Usage: acp-conformance [--timeout <secs>] <agent-command> [args...]
+
+use heroacp::testing::Conformance;
+use std::process::ExitCode;
+use std::time::Duration;
+
+const USAGE: &str = "Usage: acp-conformance [--timeout <secs>] <agent-command> [args...]";
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+
+    let mut deadline = None;
+    if args.first().map(String::as_str) == Some("--timeout") {
+        match args.get(1).map(|secs| secs.parse::<u64>()) {
+            Some(Ok(secs)) => deadline = Some(Duration::from_secs(secs)),
+            _ => {
+                eprintln!("{}", USAGE);
+                return ExitCode::FAILURE;
+            }
+        }
+        args.drain(..2);
+    }
+    let Some((command, agent_args)) = args.split_first() else {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    };
+
+    let mut conformance = Conformance::new(command).args(agent_args);
+    if let Some(deadline) = deadline {
+        conformance = conformance.deadline(deadline);
+    }
+    println!("Checking {}\n", args.join(" "));
+    let report = conformance.run().await;
+    print!("{}", report);
+
+    if report.passed() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
@@ -0,0 +1,353 @@
+//! Example ACP agent running on a local Ollama server.
+//!
+//! Everything stays on the machine, so this is the way to try heroacp offline:
+//! - Lists the models pulled into Ollama as the agent's models; clients
+//!   switch a session between them with `session/set_model`
+//! - Streams the model's tokens as message chunks, and its thinking, for
+//!   models that think, as thought chunks
+//! - Runs the tool calls the model makes and feeds the results back
+//! - Stops the request to the model when the prompt is cancelled
+//!
+//! Configured through the environment:
+//! - `OLLAMA_HOST`: server, defaults to `http://localhost:11434`
+//! - `OLLAMA_MODEL`: model new sessions start on, defaults to the first one
+//!   Ollama lists
+//!
+//! Run with: cargo run --features ollama-agent --bin acp-ollama-agent
+
+mod common;
+
+use async_trait::async_trait;
+use common::{model_error, tool_output, tools, MAX_TOOL_ROUNDS, SYSTEM_PROMPT};
+use heroacp::protocol::*;
+use heroacp::server::{Agent, AgentContext, Server, SessionHandle};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use tokio::sync::{Mutex, RwLock};
+
+/// A session's model and conversation, in the Ollama chat format.
+#[derive(Clone)]
+struct Conversation {
+    model: String,
+    messages: Vec<Value>,
+}
+
+/// What the model answered in one turn.
+#[derive(Default)]
+struct Turn {
+    text: String,
+    tool_calls: Vec<Value>,
+}
+
+/// An agent that asks a model served by Ollama.
+struct OllamaAgent {
+    host: String,
+    default_model: Option<String>,
+    http: reqwest::Client,
+    /// Models pulled into Ollama, as of `initialize`.
+    models: RwLock<Vec<ModelInfo>>,
+    conversations: Mutex<HashMap<String, Conversation>>,
+}
+
+impl OllamaAgent {
+    fn new() -> Self {
+        Self {
+            host: std::env::var("OLLAMA_HOST")
+                .unwrap_or_else(|_| "http://localhost:11434".to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            default_model: std::env::var("OLLAMA_MODEL").ok().filter(|model| !model.is_empty()),
+            http: reqwest::Client::new(),
+            models: RwLock::new(Vec::new()),
+            conversations: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The models pulled into Ollama.
+    async fn list_models(&self) -> AcpResult<Vec<ModelInfo>> {
+        let response = self
+            .http
+            .get(format!("{}/api/tags", self.host))
+            .send()
+            .await
+            .map_err(model_error)?;
+        let tags: Value = serde_json::from_str(&response.text().await.map_err(model_error)?)?;
+        let models = tags["models"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|model| {
+                let id = model["name"].as_str()?;
+                let details = &model["details"];
+                let description = match (details["parameter_size"].as_str(), details["quantization_level"].as_str()) {
+                    (Some(size), Some(quantization)) => Some(format!("{} parameters, {}", size, quantization)),
+                    (Some(size), None) => Some(format!("{} parameters", size)),
+                    _ => None,
+                };
+                Some(ModelInfo {
+                    id: id.to_string(),
+                    name: id.to_string(),
+                    description,
+                })
+            })
+            .collect();
+        Ok(models)
+    }
+
+    /// Stream the answer of `model` to `messages` into `session`.
+    ///
+    /// Returns `None` if the prompt was cancelled first.
+    async fn complete(
+        &self,
+        model: &str,
+        messages: &[Value],
+        session: &SessionHandle,
+        ctx: &AgentContext,
+    ) -> AcpResult<Option<Turn>> {
+        let tools: Vec<Value> = ctx
+            .tools()
+            .infos()
+            .into_iter()
+            .map(|tool| {
+                json!({
+                    "type": "function",
+                    "function": { "name": tool.name, "description": tool.description, "parameters": tool.parameters },
+                })
+            })
+            .collect();
+        let body = json!({ "model": model, "messages": messages, "tools": tools, "stream": true });
+        let request = self
+            .http
+            .post(format!("{}/api/chat", self.host))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_string());
+
+        let cancel = ctx.cancellation_token();
+        // Dropping the response on cancellation closes the connection, which
+        // Ollama takes as the sign to stop generating
+        let mut response = tokio::select! {
+            _ = cancel.cancelled() => return Ok(None),
+            response = request.send() => response.map_err(model_error)?,
+        };
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(AcpError::InternalError(format!("Ollama answered {}: {}", status, text)));
+        }
+
+        let mut turn = Turn::default();
+        let mut buffer = Vec::new();
+        loop {
+            let chunk = tokio::select! {
+                _ = cancel.cancelled() => return Ok(None),
+                chunk = response.chunk() => chunk.map_err(model_error)?,
+            };
+            let Some(chunk) = chunk else { break };
+            buffer.extend_from_slice(&chunk);
+            // One JSON object per line
+            while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+                let part: Value = serde_json::from_slice(&line)?;
+                if let Some(error) = part["error"].as_str() {
+                    return Err(AcpError::InternalError(format!("Ollama failed: {}", error)));
+                }
+                let message = &part["message"];
+                if let Some(thinking) = message["thinking"].as_str().filter(|text| !text.is_empty()) {
+                    let _ = session.send_thought(thinking).await;
+                }
+                if let Some(text) = message["content"].as_str().filter(|text| !text.is_empty()) {
+                    turn.text.push_str(text);
+                    let _ = session.send_message_chunk(text).await;
+                }
+                turn.tool_calls
+                    .extend(message["tool_calls"].as_array().into_iter().flatten().cloned());
+                if part["done"].as_bool().unwrap_or(false) {
+                    return Ok(Some(turn));
+                }
+            }
+        }
+        Ok(Some(turn))
+    }
+
+    /// Wrap up a cancelled prompt.
+    async fn cancelled(&self, session: &SessionHandle) -> SessionPromptResult {
+        eprintln!("[OllamaAgent] Prompt cancelled in session {}", session.session_id());
+        let _ = session.send_status(AgentState::Idle).await;
+        let _ = session.send_done().await;
+        SessionPromptResult {
+            status: "cancelled".to_string(),
+        }
+    }
+
+    /// Keep `conversation` for `session` and end the prompt.
+    async fn finish(&self, session: &SessionHandle, conversation: Conversation) -> SessionPromptResult {
+        self.conversations
+            .lock()
+            .await
+            .insert(session.session_id().to_string(), conversation);
+        let _ = session.send_status(AgentState::Idle).await;
+        let _ = session.send_done().await;
+        SessionPromptResult {
+            status: "ok".to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl Agent for OllamaAgent {
+    async fn initialize(&self, params: InitializeParams) -> AcpResult<InitializeResult> {
+        let models = match self.list_models().await {
+            Ok(models) => models,
+            Err(e) => {
+                eprintln!("[OllamaAgent] Can't list models at {}: {}", self.host, e);
+                Vec::new()
+            }
+        };
+        eprintln!(
+            "[OllamaAgent] Client: {} v{}, {} models at {}",
+            params.client_info.name,
+            params.client_info.version,
+            models.len(),
+            self.host
+        );
+        *self.models.write().await = models.clone();
+
+        Ok(InitializeResult {
+            agent_info: AgentInfo {
+                name: "HeroACP Ollama Agent".to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+            },
+            capabilities: AgentCapabilities {
+                streaming: true,
+                models,
+                ..Default::default()
+            },
+            instructions: None,
+        })
+    }
+
+    async fn session_new(&self, params: SessionNewParams, _ctx: AgentContext) -> AcpResult<SessionNewResult> {
+        let model = match &self.default_model {
+            Some(model) => model.clone(),
+            None => self
+                .models
+                .read()
+                .await
+                .first()
+                .map(|model| model.id.clone())
+                .ok_or_else(|| AcpError::InvalidState(format!("No models at {}; pull one with `ollama pull`", self.host)))?,
+        };
+        let conversation = Conversation {
+            model,
+            messages: vec![json!({ "role": "system", "content": SYSTEM_PROMPT })],
+        };
+        self.conversations
+            .lock()
+            .await
+            .insert(params.session_id.clone(), conversation);
+        Ok(SessionNewResult {
+            session_id: params.session_id,
+        })
+    }
+
+    async fn session_load(&self, params: SessionLoadParams, _ctx: AgentContext) -> AcpResult<SessionLoadResult> {
+        // Conversations only live as long as the process
+        let loaded = self.conversations.lock().await.contains_key(&params.session_id);
+        Ok(SessionLoadResult {
+            session_id: params.session_id,
+            loaded,
+        })
+    }
+
+    async fn session_set_model(&self, params: SessionSetModelParams, _ctx: AgentContext) -> AcpResult<()> {
+        let mut conversations = self.conversations.lock().await;
+        let conversation = conversations
+            .get_mut(&params.session_id)
+            .ok_or_else(|| AcpError::InvalidState(format!("Unknown session: {}", params.session_id)))?;
+        eprintln!("[OllamaAgent] Session {} switches to {}", params.session_id, params.model_id);
+        conversation.model = params.model_id;
+        Ok(())
+    }
+
+    async fn session_prompt(
+        &self,
+        params: SessionPromptParams,
+        session: SessionHandle,
+        ctx: AgentContext,
+    ) -> AcpResult<SessionPromptResult> {
+        let mut text = Vec::new();
+        let mut images = Vec::new();
+        for block in &params.content {
+            match block {
+                ContentBlock::Text { text: t, .. } => text.push(t.as_str()),
+                ContentBlock::Image { data, .. } => images.push(data.clone()),
+                _ => {}
+            }
+        }
+
+        // Work on a copy so a failed or cancelled prompt leaves no half turn behind
+        let mut conversation = self
+            .conversations
+            .lock()
+            .await
+            .get(&params.session_id)
+            .cloned()
+            .ok_or_else(|| AcpError::InvalidState(format!("Unknown session: {}", params.session_id)))?;
+        let mut message = json!({ "role": "user", "content": text.join("\n") });
+        if !images.is_empty() {
+            message["images"] = json!(images);
+        }
+        conversation.messages.push(message);
+
+        let _ = session.send_status(AgentState::Thinking).await;
+        for _ in 0..MAX_TOOL_ROUNDS {
+            let Some(turn) = self
+                .complete(&conversation.model, &conversation.messages, &session, &ctx)
+                .await?
+            else {
+                return Ok(self.cancelled(&session).await);
+            };
+            let mut message = json!({ "role": "assistant", "content": turn.text });
+            if !turn.tool_calls.is_empty() {
+                message["tool_calls"] = Value::Array(turn.tool_calls.clone());
+            }
+            conversation.messages.push(message);
+            if turn.tool_calls.is_empty() {
+                return Ok(self.finish(&session, conversation).await);
+            }
+
+            let _ = session.send_status(AgentState::RunningTool).await;
+            for call in &turn.tool_calls {
+                let function = &call["function"];
+                let name = function["name"].as_str().unwrap_or_default();
+                let result = tokio::select! {
+                    _ = ctx.cancellation_token().cancelled() => return Ok(self.cancelled(&session).await),
+                    result = ctx.tools().call(&session, name, function["arguments"].clone(), &ctx) => result,
+                };
+                conversation
+                    .messages
+                    .push(json!({ "role": "tool", "tool_name": name, "content": tool_output(result) }));
+            }
+            let _ = session.send_status(AgentState::Thinking).await;
+        }
+
+        let _ = session
+            .send_message_chunk(format!("\n\nStopped after {} rounds of tool calls.", MAX_TOOL_ROUNDS))
+            .await;
+        Ok(self.finish(&session, conversation).await)
+    }
+
+    async fn session_cancel(&self, params: SessionCancelParams, _ctx: AgentContext) -> AcpResult<()> {
+        eprintln!("[OllamaAgent] Cancelling session: {}", params.session_id);
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    Server::new(OllamaAgent::new()).with_tools(tools()).run().await?;
+    Ok(())
+}
@@ -0,0 +1,321 @@
+//! Example ACP agent backed by an OpenAI-compatible chat completions API.
+//!
+//! This is the template for a real agent:
+//! - Keeps each session's conversation and sends it to the model
+//! - Streams the model's tokens as message chunks
+//! - Runs the tool calls the model makes and feeds the results back
+//! - Stops the request to the model when the prompt is cancelled
+//!
+//! Configured through the environment:
+//! - `OPENAI_API_KEY`: API key, if the endpoint needs one
+//! - `OPENAI_BASE_URL`: endpoint, defaults to `https://api.openai.com/v1`
+//! - `OPENAI_MODEL`: model, defaults to `gpt-4o-mini`
+//!
+//! Run with: cargo run --features openai-agent --bin acp-openai-agent
+
+mod common;
+
+use async_trait::async_trait;
+use common::{model_error, tool_output, tools, MAX_TOOL_ROUNDS, SYSTEM_PROMPT};
+use heroacp::protocol::*;
+use heroacp::server::{Agent, AgentContext, Server, SessionHandle};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use tokio::sync::Mutex;
+
+/// Where to find the model.
+struct ModelConfig {
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+}
+
+impl ModelConfig {
+    fn from_env() -> Self {
+        Self {
+            base_url: std::env::var("OPENAI_BASE_URL")
+                .unwrap_or_else(|_| "https://api.openai.com/v1".to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            api_key: std::env::var("OPENAI_API_KEY").ok().filter(|key| !key.is_empty()),
+            model: std::env::var("OPENAI_MODEL").unwrap_or_else(|_| "gpt-4o-mini".to_string()),
+        }
+    }
+}
+
+/// A tool call as the model streams it, in pieces.
+#[derive(Default)]
+struct PendingToolCall {
+    id: String,
+    name: String,
+    arguments: String,
+}
+
+/// What the model answered in one turn.
+#[derive(Default)]
+struct Turn {
+    text: String,
+    tool_calls: BTreeMap<u64, PendingToolCall>,
+}
+
+impl Turn {
+    /// Add the `delta` of one streamed chunk, sending new text to `session`.
+    async fn add(&mut self, delta: &Value, session: &SessionHandle) {
+        if let Some(text) = delta["content"].as_str().filter(|text| !text.is_empty()) {
+            self.text.push_str(text);
+            let _ = session.send_message_chunk(text).await;
+        }
+        for call in delta["tool_calls"].as_array().into_iter().flatten() {
+            let pending = self.tool_calls.entry(call["index"].as_u64().unwrap_or(0)).or_default();
+            if let Some(id) = call["id"].as_str() {
+                pending.id = id.to_string();
+            }
+            if let Some(name) = call["function"]["name"].as_str() {
+                pending.name.push_str(name);
+            }
+            if let Some(arguments) = call["function"]["arguments"].as_str() {
+                pending.arguments.push_str(arguments);
+            }
+        }
+    }
+
+    /// The turn as a message of the conversation.
+    fn message(&self) -> Value {
+        let mut message = json!({ "role": "assistant", "content": self.text });
+        if !self.tool_calls.is_empty() {
+            message["tool_calls"] = self
+                .tool_calls
+                .values()
+                .map(|call| {
+                    json!({
+                        "id": call.id,
+                        "type": "function",
+                        "function": { "name": call.name, "arguments": call.arguments },
+                    })
+                })
+                .collect();
+        }
+        message
+    }
+}
+
+/// An agent that asks an OpenAI-compatible model.
+struct OpenAiAgent {
+    config: ModelConfig,
+    http: reqwest::Client,
+    /// Conversation of each session, in the chat completions format.
+    conversations: Mutex<HashMap<String, Vec<Value>>>,
+}
+
+impl OpenAiAgent {
+    fn new(config: ModelConfig) -> Self {
+        Self {
+            config,
+            http: reqwest::Client::new(),
+            conversations: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Stream the model's answer to `messages` into `session`.
+    ///
+    /// Returns `None` if the prompt was cancelled first.
+    async fn complete(&self, messages: &[Value], session: &SessionHandle, ctx: &AgentContext) -> AcpResult<Option<Turn>> {
+        let tools: Vec<Value> = ctx
+            .tools()
+            .infos()
+            .into_iter()
+            .map(|tool| {
+                json!({
+                    "type": "function",
+                    "function": { "name": tool.name, "description": tool.description, "parameters": tool.parameters },
+                })
+            })
+            .collect();
+        let mut body = json!({ "model": self.config.model, "messages": messages, "stream": true });
+        if !tools.is_empty() {
+            body["tools"] = Value::Array(tools);
+        }
+
+        let mut request = self
+            .http
+            .post(format!("{}/chat/completions", self.config.base_url))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_string());
+        if let Some(key) = &self.config.api_key {
+            request = request.bearer_auth(key);
+        }
+
+        let cancel = ctx.cancellation_token();
+        // Dropping the response on cancellation closes the connection, so the
+        // model stops generating too
+        let mut response = tokio::select! {
+            _ = cancel.cancelled() => return Ok(None),
+            response = request.send() => response.map_err(model_error)?,
+        };
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(AcpError::InternalError(format!("model answered {}: {}", status, text)));
+        }
+
+        let mut turn = Turn::default();
+        let mut buffer = Vec::new();
+        loop {
+            let chunk = tokio::select! {
+                _ = cancel.cancelled() => return Ok(None),
+                chunk = response.chunk() => chunk.map_err(model_error)?,
+            };
+            let Some(chunk) = chunk else { break };
+            buffer.extend_from_slice(&chunk);
+            // Server-sent events, one `data:` line per chunk of the answer
+            while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+                let Some(data) = line.trim().strip_prefix("data:").map(str::trim) else {
+                    continue;
+                };
+                if data == "[DONE]" {
+                    return Ok(Some(turn));
+                }
+                let event: Value = serde_json::from_str(data)?;
+                if let Some(error) = event.get("error") {
+                    return Err(AcpError::InternalError(format!("model failed: {}", error)));
+                }
+                turn.add(&event["choices"][0]["delta"], session).await;
+            }
+        }
+        Ok(Some(turn))
+    }
+
+    /// Wrap up a cancelled prompt.
+    async fn cancelled(&self, session: &SessionHandle) -> SessionPromptResult {
+        eprintln!("[OpenAiAgent] Prompt cancelled in session {}", session.session_id());
+        let _ = session.send_status(AgentState::Idle).await;
+        let _ = session.send_done().await;
+        SessionPromptResult {
+            status: "cancelled".to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl Agent for OpenAiAgent {
+    async fn initialize(&self, params: InitializeParams) -> AcpResult<InitializeResult> {
+        eprintln!(
+            "[OpenAiAgent] Client: {} v{}, using {} at {}",
+            params.client_info.name, params.client_info.version, self.config.model, self.config.base_url
+        );
+
+        Ok(InitializeResult {
+            agent_info: AgentInfo {
+                name: "HeroACP OpenAI Agent".to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+            },
+            capabilities: AgentCapabilities {
+                streaming: true,
+                ..Default::default()
+            },
+            instructions: None,
+        })
+    }
+
+    async fn session_new(&self, params: SessionNewParams, _ctx: AgentContext) -> AcpResult<SessionNewResult> {
+        let system = json!({ "role": "system", "content": SYSTEM_PROMPT });
+        self.conversations
+            .lock()
+            .await
+            .insert(params.session_id.clone(), vec![system]);
+        Ok(SessionNewResult {
+            session_id: params.session_id,
+        })
+    }
+
+    async fn session_load(&self, params: SessionLoadParams, _ctx: AgentContext) -> AcpResult<SessionLoadResult> {
+        // Conversations only live as long as the process
+        let loaded = self.conversations.lock().await.contains_key(&params.session_id);
+        Ok(SessionLoadResult {
+            session_id: params.session_id,
+            loaded,
+        })
+    }
+
+    async fn session_prompt(
+        &self,
+        params: SessionPromptParams,
+        session: SessionHandle,
+        ctx: AgentContext,
+    ) -> AcpResult<SessionPromptResult> {
+        let text: String = params
+            .content
+            .iter()
+            .filter_map(|block| match block {
+                ContentBlock::Text { text, .. } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        // Work on a copy so a failed or cancelled prompt leaves no half turn behind
+        let mut messages = self
+            .conversations
+            .lock()
+            .await
+            .get(&params.session_id)
+            .cloned()
+            .ok_or_else(|| AcpError::InvalidState(format!("Unknown session: {}", params.session_id)))?;
+        messages.push(json!({ "role": "user", "content": text }));
+
+        let _ = session.send_status(AgentState::Thinking).await;
+        for _ in 0..MAX_TOOL_ROUNDS {
+            let Some(turn) = self.complete(&messages, &session, &ctx).await? else {
+                return Ok(self.cancelled(&session).await);
+            };
+            messages.push(turn.message());
+            if turn.tool_calls.is_empty() {
+                self.conversations.lock().await.insert(params.session_id.clone(), messages);
+                let _ = session.send_status(AgentState::Idle).await;
+                let _ = session.send_done().await;
+                return Ok(SessionPromptResult {
+                    status: "ok".to_string(),
+                });
+            }
+
+            let _ = session.send_status(AgentState::RunningTool).await;
+            for call in turn.tool_calls.values() {
+                // Models occasionally produce arguments that aren't JSON; let
+                // them see the error and try again
+                let result = match serde_json::from_str(&call.arguments) {
+                    Ok(arguments) => tokio::select! {
+                        _ = ctx.cancellation_token().cancelled() => return Ok(self.cancelled(&session).await),
+                        result = ctx.tools().call(&session, &call.name, arguments, &ctx) => result,
+                    },
+                    Err(e) => Err(AcpError::InvalidParams(format!("arguments are not JSON: {}", e))),
+                };
+                messages.push(json!({ "role": "tool", "tool_call_id": call.id, "content": tool_output(result) }));
+            }
+            let _ = session.send_status(AgentState::Thinking).await;
+        }
+
+        let _ = session
+            .send_message_chunk(format!("\n\nStopped after {} rounds of tool calls.", MAX_TOOL_ROUNDS))
+            .await;
+        self.conversations.lock().await.insert(params.session_id.clone(), messages);
+        let _ = session.send_status(AgentState::Idle).await;
+        let _ = session.send_done().await;
+        Ok(SessionPromptResult {
+            status: "ok".to_string(),
+        })
+    }
+
+    async fn session_cancel(&self, params: SessionCancelParams, _ctx: AgentContext) -> AcpResult<()> {
+        eprintln!("[OpenAiAgent] Cancelling session: {}", params.session_id);
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let agent = OpenAiAgent::new(ModelConfig::from_env());
+    Server::new(agent).with_tools(tools()).run().await?;
+    Ok(())
+}
@@ -0,0 +1,12 @@
+//! Prints the JSON Schema of every heroacp protocol type.
+//!
+//! The output is one draft 2020-12 document whose `$defs` hold a
+//! definition per request, response, notification and update type, for
+//! generating types in other languages or validating messages.
+//!
+//! Run with: cargo run --features schema --bin acp-schema > heroacp.schema.json
+
+fn main() {
+    let schema = heroacp::protocol::protocol_schema();
+    println!("{}", serde_json::to_string_pretty(&schema).expect("schema serializes"));
+}
@@ -4,16 +4,31 @@
 //! - Responds to prompts with mock AI-like responses
 //! - Simulates thinking with thought chunks
 //! - Shows example tool calls
+//! - Handles a vendor-specific `bogus/echo` method
 //! - Demonstrates the ACP protocol
 //!
+//! With `ACP_SCENARIO` naming a scenario file, it plays that instead; see
+//! `heroacp::testing::MockAgent`.
+//!
+//! Built with the `metrics` feature, it serves Prometheus metrics at
+//! `http://$ACP_METRICS_ADDR/metrics` when that is set.
+//!
 //! Run with: cargo run --bin acp-server
 
 use async_trait::async_trait;
 use heroacp::protocol::*;
-use heroacp::server::{Agent, Server};
-use tokio::sync::mpsc;
+use heroacp::server::{acp_methods, Agent, AgentContext, CancellationToken, Server, SessionHandle};
+use heroacp::testing::MockAgent;
 use tokio::time::{sleep, Duration};
 
+/// Sleep for `ms` milliseconds, returning false if the prompt was cancelled first.
+async fn pause(cancel: &CancellationToken, ms: u64) -> bool {
+    tokio::select! {
+        _ = cancel.cancelled() => false,
+        _ = sleep(Duration::from_millis(ms)) => true,
+    }
+}
+
 /// A bogus AI agent that provides mock responses.
 struct BogusAgent {
     name: String,
@@ -92,6 +107,27 @@ impl BogusAgent {
     }
 }
 
+impl BogusAgent {
+    /// Wrap up a cancelled prompt.
+    async fn cancelled(&self, session: &SessionHandle) -> SessionPromptResult {
+        eprintln!("[BogusAgent] Prompt cancelled in session {}", session.session_id());
+        let _ = session.send_status(AgentState::Idle).await;
+        let _ = session.send_done().await;
+        SessionPromptResult {
+            status: "cancelled".to_string(),
+        }
+    }
+}
+
+#[acp_methods]
+impl BogusAgent {
+    /// Return the params unchanged.
+    #[acp_method("bogus/echo")]
+    async fn echo(&self, params: serde_json::Value) -> AcpResult<serde_json::Value> {
+        Ok(params)
+    }
+}
+
 #[async_trait]
 impl Agent for BogusAgent {
     async fn initialize(&self, params: InitializeParams) -> AcpResult<InitializeResult> {
@@ -148,6 +184,7 @@ impl Agent for BogusAgent {
                         }),
                     },
                 ],
+                ..Default::default()
             },
             instructions: Some(
                 "I am the HeroACP Bogus Agent, a demonstration agent for the Agent Client Protocol. \
@@ -156,7 +193,11 @@ impl Agent for BogusAgent {
         })
     }
 
-    async fn session_new(&self, params: SessionNewParams) -> AcpResult<SessionNewResult> {
+    async fn session_new(
+        &self,
+        params: SessionNewParams,
+        _ctx: AgentContext,
+    ) -> AcpResult<SessionNewResult> {
         eprintln!(
             "[BogusAgent] Creating new session: {} (mode: {:?})",
             params.session_id,
@@ -168,7 +209,11 @@ impl Agent for BogusAgent {
         })
     }
 
-    async fn session_load(&self, params: SessionLoadParams) -> AcpResult<SessionLoadResult> {
+    async fn session_load(
+        &self,
+        params: SessionLoadParams,
+        _ctx: AgentContext,
+    ) -> AcpResult<SessionLoadResult> {
         eprintln!("[BogusAgent] Loading session: {}", params.session_id);
 
         // Bogus agent doesn't persist sessions
@@ -181,16 +226,17 @@ impl Agent for BogusAgent {
     async fn session_prompt(
         &self,
         params: SessionPromptParams,
-        update_tx: mpsc::Sender<SessionUpdate>,
+        session: SessionHandle,
+        ctx: AgentContext,
     ) -> AcpResult<SessionPromptResult> {
-        let session_id = params.session_id.clone();
+        let cancel = ctx.cancellation_token();
 
         // Extract text from content blocks
         let prompt_text: String = params
             .content
             .iter()
             .filter_map(|block| {
-                if let ContentBlock::Text { text } = block {
+                if let ContentBlock::Text { text, .. } = block {
                     Some(text.clone())
                 } else {
                     None
@@ -201,54 +247,51 @@ impl Agent for BogusAgent {
 
         eprintln!(
             "[BogusAgent] Received prompt in session {}: {}",
-            session_id,
+            session.session_id(),
             prompt_text.chars().take(100).collect::<String>()
         );
 
+        let _ = session.send_status(AgentState::Thinking).await;
+
         // Send thinking update
-        let thought = self.generate_thought(&prompt_text);
-        let _ = update_tx
-            .send(SessionUpdate {
-                session_id: session_id.clone(),
-                update_type: SessionUpdateType::AgentThoughtChunk { text: thought },
-            })
-            .await;
+        let _ = session.send_thought(self.generate_thought(&prompt_text)).await;
 
-        sleep(Duration::from_millis(200)).await;
+        if !pause(cancel, 200).await {
+            return Ok(self.cancelled(&session).await);
+        }
 
         // Show a plan if the user asks for one
         if prompt_text.to_lowercase().contains("plan") {
-            let _ = update_tx
-                .send(SessionUpdate {
-                    session_id: session_id.clone(),
-                    update_type: SessionUpdateType::Plan(Plan {
-                        steps: vec![
-                            PlanStep {
-                                id: 1,
-                                description: "Analyze the request".to_string(),
-                                status: PlanStepStatus::Completed,
-                            },
-                            PlanStep {
-                                id: 2,
-                                description: "Search for relevant files".to_string(),
-                                status: PlanStepStatus::InProgress,
-                            },
-                            PlanStep {
-                                id: 3,
-                                description: "Implement the solution".to_string(),
-                                status: PlanStepStatus::Pending,
-                            },
-                            PlanStep {
-                                id: 4,
-                                description: "Test the changes".to_string(),
-                                status: PlanStepStatus::Pending,
-                            },
-                        ],
-                    }),
+            let _ = session
+                .send_plan(Plan {
+                    steps: vec![
+                        PlanStep {
+                            id: 1,
+                            description: "Analyze the request".to_string(),
+                            status: PlanStepStatus::Completed,
+                        },
+                        PlanStep {
+                            id: 2,
+                            description: "Search for relevant files".to_string(),
+                            status: PlanStepStatus::InProgress,
+                        },
+                        PlanStep {
+                            id: 3,
+                            description: "Implement the solution".to_string(),
+                            status: PlanStepStatus::Pending,
+                        },
+                        PlanStep {
+                            id: 4,
+                            description: "Test the changes".to_string(),
+                            status: PlanStepStatus::Pending,
+                        },
+                    ],
                 })
                 .await;
 
-            sleep(Duration::from_millis(200)).await;
+            if !pause(cancel, 200).await {
+                return Ok(self.cancelled(&session).await);
+            }
         }
 
         // Show a tool call if the user asks about tools/files
@@ -256,70 +299,64 @@ impl Agent for BogusAgent {
             || prompt_text.to_lowercase().contains("file")
             || prompt_text.to_lowercase().contains("read")
         {
-            let tool_id = format!("tool_{}", uuid::Uuid::new_v4());
+            let _ = session.send_status(AgentState::RunningTool).await;
 
             // Send tool call
-            let _ = update_tx
-                .send(SessionUpdate {
-                    session_id: session_id.clone(),
-                    update_type: SessionUpdateType::ToolCall(ToolCall {
-                        id: tool_id.clone(),
-                        name: "read_file".to_string(),
-                        arguments: serde_json::json!({
-                            "path": "/example/file.txt"
-                        }),
+            let tool_id = session
+                .start_tool_call(
+                    "read_file",
+                    serde_json::json!({
+                        "path": "/example/file.txt"
                     }),
-                })
-                .await;
+                )
+                .await?;
 
-            sleep(Duration::from_millis(300)).await;
+            if !pause(cancel, 300).await {
+                return Ok(self.cancelled(&session).await);
+            }
 
             // Send tool result
-            let _ = update_tx
-                .send(SessionUpdate {
-                    session_id: session_id.clone(),
-                    update_type: SessionUpdateType::ToolCallUpdate(ToolCallUpdate {
-                        id: tool_id,
-                        status: ToolCallStatus::Completed,
-                        result: Some(serde_json::json!({
-                            "content": "Example file content from bogus agent"
-                        })),
-                        error: None,
+            let _ = session
+                .complete_tool_call(
+                    tool_id,
+                    serde_json::json!({
+                        "content": "Example file content from bogus agent"
                     }),
-                })
+                )
                 .await;
 
-            sleep(Duration::from_millis(200)).await;
+            if !pause(cancel, 200).await {
+                return Ok(self.cancelled(&session).await);
+            }
+
+            let _ = session.send_status(AgentState::Thinking).await;
         }
 
         // Stream response chunks
-        let response_chunks = self.generate_response(&prompt_text);
-        for chunk in response_chunks {
-            let _ = update_tx
-                .send(SessionUpdate {
-                    session_id: session_id.clone(),
-                    update_type: SessionUpdateType::AgentMessageChunk { text: chunk },
-                })
-                .await;
+        for chunk in self.generate_response(&prompt_text) {
+            let _ = session.send_message_chunk(chunk).await;
 
             // Simulate typing delay
-            sleep(Duration::from_millis(50)).await;
+            if !pause(cancel, 50).await {
+                return Ok(self.cancelled(&session).await);
+            }
         }
 
+        let _ = session.send_status(AgentState::Idle).await;
+
         // Send done notification
-        let _ = update_tx
-            .send(SessionUpdate {
-                session_id: session_id.clone(),
-                update_type: SessionUpdateType::Done,
-            })
-            .await;
+        let _ = session.send_done().await;
 
         Ok(SessionPromptResult {
             status: "ok".to_string(),
         })
     }
 
-    async fn session_cancel(&self, params: SessionCancelParams) -> AcpResult<()> {
+    async fn session_cancel(
+        &self,
+        params: SessionCancelParams,
+        _ctx: AgentContext,
+    ) -> AcpResult<()> {
         eprintln!("[BogusAgent] Cancelling session: {}", params.session_id);
         Ok(())
     }
@@ -330,11 +367,38 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     eprintln!("[BogusAgent] Starting HeroACP Bogus Agent...");
     eprintln!("[BogusAgent] Waiting for client connection on stdio...");
 
-    let agent = BogusAgent::new();
-    let server = Server::new(agent);
-
-    server.run().await?;
+    // Play a scripted scenario when ACP_SCENARIO names a file
+    if let Ok(path) = std::env::var("ACP_SCENARIO") {
+        eprintln!("[BogusAgent] Playing scenario {}", path);
+        serve(Server::new(MockAgent::load(&path)?)).await?;
+    } else {
+        serve(Server::new(BogusAgent::new()).with_custom_methods()).await?;
+    }
 
     eprintln!("[BogusAgent] Agent shutting down.");
     Ok(())
 }
+
+/// Run `server` on stdio.
+async fn serve<A: Agent + 'static>(server: Server<A>) -> Result<(), Box<dyn std::error::Error>> {
+    // Record the wire traffic when ACP_TRANSCRIPT names a file
+    if let Ok(path) = std::env::var("ACP_TRANSCRIPT") {
+        server.transcript().start(&path)?;
+        eprintln!("[BogusAgent] Recording transcript to {}", path);
+    }
+
+    // Serve Prometheus metrics when ACP_METRICS_ADDR gives an address
+    #[cfg(feature = "metrics")]
+    let server = match std::env::var("ACP_METRICS_ADDR") {
+        Ok(addr) => {
+            let metrics = heroacp::server::Metrics::new();
+            let addr = metrics.serve(addr).await?;
+            eprintln!("[BogusAgent] Serving metrics at http://{}/metrics", addr);
+            server.with_metrics(metrics)
+        }
+        Err(_) => server,
+    };
+
+    server.run().await?;
+    Ok(())
+}
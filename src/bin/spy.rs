@@ -0,0 +1,271 @@
+//! Watches the ACP traffic between an editor and an agent.
+//!
+//! Spawns the agent and relays every line between the editor on
+//! stdin/stdout and the agent unchanged, while printing a readable view of
+//! the exchange: requests and their responses with round-trip times, errors,
+//! notifications, and session updates by type. Point an editor at
+//! `acp-spy <agent>` instead of the agent to debug a session.
+//!
+//! The view goes to stderr, or to a file with `--output`, which can be
+//! followed with `tail -f` when the editor swallows the agent's stderr. The
+//! agent's own stderr is passed through.
+//!
+//! Usage: acp-spy [--output <file>] [--no-color] <agent-command> [args...]
+
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{IsTerminal, Write};
+use std::process::{ExitCode, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+
+const USAGE: &str = "Usage: acp-spy [--output <file>] [--no-color] <agent-command> [args...]";
+
+/// Longest params, result or text shown before it's cut short.
+const MAX_SHOWN: usize = 160;
+
+const RESET: &str = "\x1b[0m";
+const DIM: &str = "\x1b[2m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const BLUE: &str = "\x1b[34m";
+const MAGENTA: &str = "\x1b[35m";
+const CYAN: &str = "\x1b[36m";
+
+/// Which way a message travels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Direction {
+    /// From the editor to the agent.
+    ToAgent,
+    /// From the agent to the editor.
+    ToEditor,
+}
+
+impl Direction {
+    fn arrow(self) -> &'static str {
+        match self {
+            Direction::ToAgent => "→",
+            Direction::ToEditor => "←",
+        }
+    }
+
+    fn reverse(self) -> Self {
+        match self {
+            Direction::ToAgent => Direction::ToEditor,
+            Direction::ToEditor => Direction::ToAgent,
+        }
+    }
+}
+
+/// Renders the messages it's shown.
+struct Spy {
+    out: Box<dyn Write + Send>,
+    color: bool,
+    started: Instant,
+    /// Method and send time of requests awaiting a response, by direction
+    /// and ID.
+    pending: HashMap<(Direction, String), (String, Instant)>,
+}
+
+impl Spy {
+    fn paint(&self, color: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", color, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn show(&mut self, direction: Direction, line: &str) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.started).as_secs_f64();
+        let prefix = self.paint(DIM, &format!("{:>9.3}s {}", elapsed, direction.arrow()));
+        let body = match serde_json::from_str::<Value>(line) {
+            Ok(message) => self.describe(direction, &message, now),
+            Err(_) => self.paint(RED, &format!("not JSON: {}", truncate(line))),
+        };
+        let _ = writeln!(self.out, "{} {}", prefix, body.trim_end());
+        let _ = self.out.flush();
+    }
+
+    fn describe(&mut self, direction: Direction, message: &Value, now: Instant) -> String {
+        let id = message.get("id").map(|id| id.to_string());
+        match (message.get("method").and_then(Value::as_str), id) {
+            (Some(method), Some(id)) => {
+                self.pending.insert((direction, id.clone()), (method.to_string(), now));
+                format!(
+                    "{} {} {}",
+                    self.paint(CYAN, method),
+                    self.paint(DIM, &format!("#{}", id)),
+                    params(message)
+                )
+            }
+            (Some("session/update"), None) => self.describe_update(&message["params"]),
+            (Some(method), None) => format!("{} {}", self.paint(BLUE, method), params(message)),
+            (None, Some(id)) => {
+                // Responses answer a request that went the other way
+                let request = self.pending.remove(&(direction.reverse(), id.clone()));
+                let (method, took) = match request {
+                    Some((method, sent)) => (method, format!("{}ms", now.duration_since(sent).as_millis())),
+                    None => ("?".to_string(), "unmatched".to_string()),
+                };
+                let label = format!("{} #{} {}", method, id, took);
+                match message.get("error") {
+                    Some(error) => format!(
+                        "{} {}",
+                        self.paint(RED, &format!("{} error", label)),
+                        truncate(&error.to_string())
+                    ),
+                    None => format!(
+                        "{} {}",
+                        self.paint(GREEN, &label),
+                        truncate(&message["result"].to_string())
+                    ),
+                }
+            }
+            (None, None) => self.paint(RED, &format!("malformed: {}", truncate(&message.to_string()))),
+        }
+    }
+
+    fn describe_update(&self, params: &Value) -> String {
+        let session = self.paint(DIM, params["session_id"].as_str().unwrap_or("?"));
+        let kind = params["type"].as_str().unwrap_or("?");
+        let data = &params["data"];
+        let (color, detail) = match kind {
+            "agent_message_chunk" => ("", quoted(&data["text"])),
+            "agent_thought_chunk" => (MAGENTA, quoted(&data["text"])),
+            "tool_call" => (YELLOW, format!("{} {}", data["name"].as_str().unwrap_or("?"), truncate(&data["arguments"].to_string()))),
+            "tool_call_update" => (YELLOW, truncate(&data.to_string())),
+            "done" => (BLUE, String::new()),
+            _ if data.is_null() => ("", String::new()),
+            _ => ("", truncate(&data.to_string())),
+        };
+        let kind = if color.is_empty() {
+            kind.to_string()
+        } else {
+            self.paint(color, kind)
+        };
+        format!("{} {} {}", session, kind, detail)
+    }
+}
+
+/// The params of `message`, cut short.
+fn params(message: &Value) -> String {
+    match message.get("params") {
+        Some(params) => truncate(&params.to_string()),
+        None => String::new(),
+    }
+}
+
+/// `text` as a quoted string, cut short.
+fn quoted(text: &Value) -> String {
+    truncate(&Value::String(text.as_str().unwrap_or_default().to_string()).to_string())
+}
+
+fn truncate(text: &str) -> String {
+    match text.char_indices().nth(MAX_SHOWN) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// Copy lines from `from` to `to` unchanged, showing each to the spy.
+async fn relay<R, W>(from: R, mut to: W, direction: Direction, spy: Arc<Mutex<Spy>>) -> std::io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut lines = BufReader::new(from).lines();
+    while let Some(line) = lines.next_line().await? {
+        to.write_all(line.as_bytes()).await?;
+        to.write_all(b"\n").await?;
+        to.flush().await?;
+        if !line.trim().is_empty() {
+            spy.lock().unwrap().show(direction, &line);
+        }
+    }
+    to.shutdown().await
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+
+    let mut output = None;
+    let mut color = std::env::var_os("NO_COLOR").is_none();
+    loop {
+        match args.first().map(String::as_str) {
+            Some("--output") if args.len() > 1 => {
+                output = Some(args[1].clone());
+                args.drain(..2);
+            }
+            Some("--no-color") => {
+                color = false;
+                args.remove(0);
+            }
+            _ => break,
+        }
+    }
+    let Some((command, agent_args)) = args.split_first() else {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    };
+
+    let out: Box<dyn Write + Send> = match &output {
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(file),
+            Err(e) => {
+                eprintln!("Failed to create {}: {}", path, e);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => {
+            color &= std::io::stderr().is_terminal();
+            Box::new(std::io::stderr())
+        }
+    };
+    let spy = Arc::new(Mutex::new(Spy {
+        out,
+        color,
+        started: Instant::now(),
+        pending: HashMap::new(),
+    }));
+
+    let mut child = match Command::new(command)
+        .args(agent_args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .kill_on_drop(true)
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("Failed to spawn agent {}: {}", command, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let agent_in = child.stdin.take().expect("agent stdin is piped");
+    let agent_out = child.stdout.take().expect("agent stdout is piped");
+
+    // When the editor closes its side, the agent's stdin is closed and its
+    // remaining output still relayed
+    let to_agent = tokio::spawn(relay(tokio::io::stdin(), agent_in, Direction::ToAgent, spy.clone()));
+    if let Err(e) = relay(agent_out, tokio::io::stdout(), Direction::ToEditor, spy).await {
+        eprintln!("Relay failed: {}", e);
+        return ExitCode::FAILURE;
+    }
+    to_agent.abort();
+
+    // Exit with the agent's status right away; returning would wait for the
+    // thread blocked reading stdin
+    let code = match child.wait().await {
+        Ok(status) => status.code().unwrap_or(1),
+        Err(_) => 1,
+    };
+    std::process::exit(code)
+}
@@ -0,0 +1,154 @@
+//! Relaying between an editor and an agent that speak different dialects.
+//!
+//! [`relay`] sits between an editor speaking ACP as defined by this crate
+//! and an agent speaking another [`Dialect`], translating every message in
+//! both directions the way [`Client`](crate::client::Client) does. The
+//! `acp-bridge` binary wraps it around a spawned agent, so an editor built
+//! on this crate can drive, say, `gemini --experimental-acp` unchanged:
+//!
+//! ```text
+//! acp-bridge --dialect gemini-cli gemini --experimental-acp
+//! ```
+
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::Mutex;
+
+use crate::client::Dialect;
+use crate::protocol::AcpResult;
+
+/// Relay NDJSON messages between an editor and an agent speaking `dialect`.
+///
+/// Messages the editor writes to `editor_in` are translated and written to
+/// `agent_in`; messages the agent writes to `agent_out` are translated back
+/// and written to `editor_out`. Requests the agent won't answer in its
+/// dialect, like `session/cancel`, are answered to the editor directly.
+///
+/// When the editor closes its side, `agent_in` is shut down so the agent
+/// can exit; the relay returns once the agent closes `agent_out`. Lines
+/// that aren't JSON are passed through as they are.
+pub async fn relay<ER, EW, AR, AW>(
+    dialect: Dialect,
+    editor_in: ER,
+    editor_out: EW,
+    agent_out: AR,
+    mut agent_in: AW,
+) -> AcpResult<()>
+where
+    ER: AsyncRead + Unpin,
+    EW: AsyncWrite + Unpin,
+    AR: AsyncRead + Unpin,
+    AW: AsyncWrite + Unpin,
+{
+    let translator = dialect.translator();
+    let editor_out = Mutex::new(editor_out);
+    let (translator, editor_out) = (&translator, &editor_out);
+
+    let to_agent = async move {
+        let mut lines = BufReader::new(editor_in).lines();
+        while let Some(line) = lines.next_line().await? {
+            if line.is_empty() {
+                continue;
+            }
+            let (line, answer) = match (translator, serde_json::from_str::<Value>(&line)) {
+                (Some(translator), Ok(msg)) => {
+                    let (msg, answer) = translator.outgoing(msg);
+                    (msg.to_string(), answer)
+                }
+                _ => (line, None),
+            };
+            write_line(&mut agent_in, &line).await?;
+            if let Some(answer) = answer {
+                write_line(&mut *editor_out.lock().await, &answer.to_string()).await?;
+            }
+        }
+        // Dropped too, as shutting down a child's stdin doesn't close it
+        agent_in.shutdown().await?;
+        drop(agent_in);
+        AcpResult::Ok(())
+    };
+
+    let to_editor = async {
+        let mut lines = BufReader::new(agent_out).lines();
+        while let Some(line) = lines.next_line().await? {
+            if line.is_empty() {
+                continue;
+            }
+            let line = match (translator, serde_json::from_str::<Value>(&line)) {
+                (Some(translator), Ok(msg)) => translator.incoming(msg).to_string(),
+                _ => line,
+            };
+            write_line(&mut *editor_out.lock().await, &line).await?;
+        }
+        AcpResult::Ok(())
+    };
+
+    tokio::pin!(to_editor);
+    tokio::select! {
+        result = to_agent => {
+            result?;
+            to_editor.await
+        }
+        result = &mut to_editor => result,
+    }
+}
+
+/// Write one NDJSON line and flush it.
+async fn write_line<W: AsyncWrite + Unpin>(writer: &mut W, line: &str) -> AcpResult<()> {
+    writer.write_all(line.as_bytes()).await?;
+    writer.write_all(b"\n").await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::io::{duplex, Lines};
+
+    async fn next<R: AsyncRead + Unpin>(lines: &mut Lines<BufReader<R>>) -> Value {
+        serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_relay_goose() {
+        let (editor, editor_end) = duplex(4096);
+        let (agent, agent_end) = duplex(4096);
+        let (editor_in, editor_out) = tokio::io::split(editor_end);
+        let (agent_out, agent_in) = tokio::io::split(agent_end);
+        let relay = tokio::spawn(relay(Dialect::Goose, editor_in, editor_out, agent_out, agent_in));
+
+        let (agent_read, mut agent_write) = tokio::io::split(agent);
+        let mut agent_lines = BufReader::new(agent_read).lines();
+        let (editor_read, mut editor_write) = tokio::io::split(editor);
+        let mut editor_lines = BufReader::new(editor_read).lines();
+
+        let initialize = json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {
+            "protocol_version": crate::protocol::PROTOCOL_VERSION,
+            "client_info": { "name": "editor", "version": "1.0" },
+            "capabilities": { "text_files": true },
+            "working_directory": "/work",
+        }});
+        write_line(&mut editor_write, &initialize.to_string()).await.unwrap();
+        let sent = next(&mut agent_lines).await;
+        assert_eq!(sent["params"]["protocolVersion"], 1);
+
+        write_line(&mut agent_write, r#"{"jsonrpc":"2.0","id":1,"result":{"protocolVersion":1}}"#).await.unwrap();
+        let init = next(&mut editor_lines).await;
+        assert_eq!(init["result"]["agent_info"]["name"], "goose");
+
+        // Answered locally, and sent on as a notification
+        let cancel = json!({ "jsonrpc": "2.0", "id": 2, "method": "session/cancel", "params": { "session_id": "s" } });
+        write_line(&mut editor_write, &cancel.to_string()).await.unwrap();
+        assert_eq!(next(&mut agent_lines).await, json!({ "jsonrpc": "2.0", "method": "session/cancel", "params": { "sessionId": "s" } }));
+        assert_eq!(next(&mut editor_lines).await, json!({ "jsonrpc": "2.0", "id": 2, "result": null }));
+
+        drop(editor_write);
+        drop(editor_lines);
+        assert!(agent_lines.next_line().await.unwrap().is_none());
+        drop(agent_write);
+        drop(agent_lines);
+        relay.await.unwrap().unwrap();
+    }
+}
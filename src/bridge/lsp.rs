@@ -0,0 +1,502 @@
+//! Agent edits as LSP workspace edits.
+//!
+//! [`apply_edit_to_workspace_edit`] and [`write_to_workspace_edit`] turn the
+//! agent's `fs/apply_edit` and `fs/write_text_file` requests into a
+//! [`WorkspaceEdit`], touching only the lines that change, so editor plugins
+//! with LSP plumbing can apply agent edits like refactorings: with undo,
+//! to open buffers, and with the editor's own conflict handling.
+//!
+//! [`LspPeer`] sends them as `workspace/applyEdit` over an LSP connection,
+//! and [`LspFileSystem`] does so for every write the agent makes:
+//!
+//! ```rust,no_run
+//! # use heroacp::client::Client;
+//! # use heroacp::bridge::lsp::{LspFileSystem, LspPeer};
+//! # use std::sync::Arc;
+//! # async fn demo(client: Client) -> std::io::Result<()> {
+//! let stream = tokio::net::TcpStream::connect("127.0.0.1:7658").await?;
+//! let (reader, writer) = stream.into_split();
+//! let peer = Arc::new(LspPeer::new(reader, writer));
+//! client.set_fs_handler(Box::new(LspFileSystem::new(peer))).await;
+//! # Ok(())
+//! # }
+//! ```
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::Mutex;
+
+use crate::client::patch;
+use crate::client::FileSystemHandler;
+use crate::client::WriteOptions;
+use crate::protocol::*;
+
+/// A position in a document: line and UTF-16 code unit offset, from 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Position {
+    /// Line, counting from 0.
+    pub line: u32,
+    /// Offset in the line in UTF-16 code units.
+    pub character: u32,
+}
+
+/// A range in a document, end exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Range {
+    /// Start of the range.
+    pub start: Position,
+    /// End of the range.
+    pub end: Position,
+}
+
+/// Replacement of a range of a document.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextEdit {
+    /// The range replaced; empty to insert.
+    pub range: Range,
+    /// The text put in its place; empty to delete.
+    pub new_text: String,
+}
+
+/// A document, at a version if known.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionedTextDocumentIdentifier {
+    /// `file://` URI of the document.
+    pub uri: String,
+    /// Version the edits were made against; `None` for whatever is current.
+    pub version: Option<i32>,
+}
+
+/// Edits to one document.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextDocumentEdit {
+    /// The document edited.
+    pub text_document: VersionedTextDocumentIdentifier,
+    /// Non-overlapping edits, in document order.
+    pub edits: Vec<TextEdit>,
+}
+
+/// One change of a [`WorkspaceEdit`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DocumentChange {
+    /// Create a file.
+    Create(CreateFile),
+    /// Edit a document.
+    Edit(TextDocumentEdit),
+}
+
+/// Creation of a file, as the `create` resource operation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateFile {
+    /// Always `"create"`.
+    pub kind: String,
+    /// `file://` URI of the file.
+    pub uri: String,
+    /// Leave an existing file alone instead of failing.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ignore_if_exists: bool,
+}
+
+/// Changes to documents, as the LSP `WorkspaceEdit` with `documentChanges`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceEdit {
+    /// Changes to apply in order.
+    pub document_changes: Vec<DocumentChange>,
+}
+
+impl WorkspaceEdit {
+    /// Check whether the edit changes nothing.
+    pub fn is_empty(&self) -> bool {
+        self.document_changes.iter().all(|change| match change {
+            DocumentChange::Create(_) => false,
+            DocumentChange::Edit(edit) => edit.edits.is_empty(),
+        })
+    }
+}
+
+/// The `file://` URI of the absolute path `path`.
+pub fn file_uri(path: &str) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => uri.push(byte as char),
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+/// The edit `fs/apply_edit` with `params` makes to `current`, the text of
+/// the file now.
+///
+/// Fails like `fs/apply_edit` if the diff is malformed, the file changed
+/// since `base_hash`, or a hunk no longer matches.
+pub fn apply_edit_to_workspace_edit(params: &FsApplyEditParams, current: &str) -> AcpResult<WorkspaceEdit> {
+    let current_hash = content_hash(current);
+    if params.base_hash.as_ref().is_some_and(|base| *base != current_hash) {
+        let conflict = EditConflict { current_hash, hunk: None };
+        return Err(conflict.into_error(format!("{} changed since the diff was made", params.path)));
+    }
+    let located = patch::locate(current, &params.unified_diff).map_err(|e| e.into_error(&params.path, current_hash))?;
+    Ok(document_edit(&params.path, current, located))
+}
+
+/// The edit `fs/write_text_file` with `params` makes to `current`, the text
+/// of the file now, or `None` if there is no such file yet.
+pub fn write_to_workspace_edit(params: &FsWriteTextFileParams, current: Option<&str>) -> WorkspaceEdit {
+    let Some(current) = current else {
+        let uri = file_uri(&params.path);
+        let insert = TextEdit {
+            range: Range {
+                start: Position { line: 0, character: 0 },
+                end: Position { line: 0, character: 0 },
+            },
+            new_text: params.content.clone(),
+        };
+        return WorkspaceEdit {
+            document_changes: vec![
+                DocumentChange::Create(CreateFile {
+                    kind: "create".to_string(),
+                    uri: uri.clone(),
+                    ignore_if_exists: false,
+                }),
+                DocumentChange::Edit(TextDocumentEdit {
+                    text_document: VersionedTextDocumentIdentifier { uri, version: None },
+                    edits: vec![insert],
+                }),
+            ],
+        };
+    };
+    let diff = patch::unified_diff(&params.path, current, &params.content);
+    if diff.is_empty() {
+        return WorkspaceEdit::default();
+    }
+    let located = patch::locate(current, &diff).expect("a diff of the text applies to it");
+    document_edit(&params.path, current, located)
+}
+
+/// An edit of the document at `path` replacing the `located` lines of `text`.
+fn document_edit(path: &str, text: &str, located: Vec<patch::Located>) -> WorkspaceEdit {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    // Where line `n` starts; past the last line that's the end of the text
+    let position = |n: usize| match lines.get(n) {
+        Some(_) => Position {
+            line: n as u32,
+            character: 0,
+        },
+        None => match lines.last() {
+            Some(last) if !last.ends_with('\n') => Position {
+                line: (lines.len() - 1) as u32,
+                character: last.encode_utf16().count() as u32,
+            },
+            _ => Position {
+                line: lines.len() as u32,
+                character: 0,
+            },
+        },
+    };
+    let edits = located
+        .into_iter()
+        .map(|hunk| {
+            // Leave out the context lines the hunk keeps as they are
+            let old = &lines[hunk.at..hunk.at + hunk.old_len];
+            let new: Vec<&str> = hunk.new.split_inclusive('\n').collect();
+            let before = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+            let after = old[before..]
+                .iter()
+                .rev()
+                .zip(new[before..].iter().rev())
+                .take_while(|(a, b)| a == b)
+                .count();
+            TextEdit {
+                range: Range {
+                    start: position(hunk.at + before),
+                    end: position(hunk.at + old.len() - after),
+                },
+                new_text: new[before..new.len() - after].concat(),
+            }
+        })
+        .collect();
+    WorkspaceEdit {
+        document_changes: vec![DocumentChange::Edit(TextDocumentEdit {
+            text_document: VersionedTextDocumentIdentifier {
+                uri: file_uri(path),
+                version: None,
+            },
+            edits,
+        })],
+    }
+}
+
+type Reader = BufReader<Box<dyn AsyncRead + Send + Unpin>>;
+type Writer = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// The side of an LSP connection that applies workspace edits, usually the
+/// editor's LSP client or a server that relays `workspace/applyEdit` to it.
+///
+/// Messages are framed with `Content-Length` headers. Requests are sent one
+/// at a time; messages from the peer other than their responses are
+/// declined or ignored.
+pub struct LspPeer {
+    io: Mutex<(Reader, Writer)>,
+    next_id: AtomicI64,
+}
+
+impl LspPeer {
+    /// Speak LSP over `reader` and `writer`.
+    pub fn new(
+        reader: impl AsyncRead + Send + Unpin + 'static,
+        writer: impl AsyncWrite + Send + Unpin + 'static,
+    ) -> Self {
+        let reader: Box<dyn AsyncRead + Send + Unpin> = Box::new(reader);
+        Self {
+            io: Mutex::new((BufReader::new(reader), Box::new(writer))),
+            next_id: AtomicI64::new(1),
+        }
+    }
+
+    /// Ask the peer to apply `edit`, shown to the user as `label`.
+    ///
+    /// Fails with `InvalidState` if the peer declines, e.g. because the
+    /// document changed in the meantime.
+    pub async fn apply_edit(&self, label: &str, edit: &WorkspaceEdit) -> AcpResult<()> {
+        let result = self
+            .request("workspace/applyEdit", json!({ "label": label, "edit": edit }))
+            .await?;
+        if result["applied"].as_bool().unwrap_or(false) {
+            return Ok(());
+        }
+        let reason = result["failureReason"].as_str().unwrap_or("no reason given");
+        Err(AcpError::InvalidState(format!("edit was not applied: {}", reason)))
+    }
+
+    /// Send a request and wait for its result.
+    async fn request(&self, method: &str, params: Value) -> AcpResult<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut io = self.io.lock().await;
+        let (reader, writer) = &mut *io;
+        write_message(writer, &json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })).await?;
+        loop {
+            let msg = read_message(reader).await?;
+            match (msg.get("method"), msg.get("id")) {
+                (None, Some(response_id)) if *response_id == id => {
+                    if let Some(error) = msg.get("error") {
+                        let error: JsonRpcError = serde_json::from_value(error.clone())?;
+                        return Err(error.into());
+                    }
+                    return Ok(msg.get("result").cloned().unwrap_or(Value::Null));
+                }
+                // A request of the peer's own, e.g. `workspace/configuration`
+                (Some(method), Some(request_id)) => {
+                    let error = json!({ "code": codes::METHOD_NOT_FOUND, "message": format!("Method not found: {}", method) });
+                    write_message(writer, &json!({ "jsonrpc": "2.0", "id": request_id, "error": error })).await?;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+async fn write_message(writer: &mut Writer, msg: &Value) -> AcpResult<()> {
+    let body = msg.to_string();
+    writer
+        .write_all(format!("Content-Length: {}\r\n\r\n{}", body.len(), body).as_bytes())
+        .await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+async fn read_message(reader: &mut Reader) -> AcpResult<Value> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).await? == 0 {
+            return Err(AcpError::ConnectionClosed);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+    let length: usize = length.ok_or_else(|| AcpError::ParseError("LSP message without Content-Length".to_string()))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// A [`FileSystemHandler`] that reads from disk and writes by asking an
+/// [`LspPeer`] to apply the change as a workspace edit.
+///
+/// `fs/apply_edit` is applied through it too, as the lines the diff
+/// changes.
+pub struct LspFileSystem {
+    peer: Arc<LspPeer>,
+}
+
+impl LspFileSystem {
+    /// Write through `peer`.
+    pub fn new(peer: Arc<LspPeer>) -> Self {
+        Self { peer }
+    }
+}
+
+#[async_trait]
+impl FileSystemHandler for LspFileSystem {
+    async fn write_text_file(&self, path: &str, content: &str) -> AcpResult<()> {
+        let current = match self.read_text_file(path).await {
+            Ok(current) => Some(current),
+            Err(AcpError::ResourceNotFound(_)) => None,
+            Err(e) => return Err(e),
+        };
+        let params = FsWriteTextFileParams {
+            path: path.to_string(),
+            content: content.to_string(),
+            ..Default::default()
+        };
+        let edit = write_to_workspace_edit(&params, current.as_deref());
+        if edit.is_empty() {
+            return Ok(());
+        }
+        self.peer.apply_edit(&format!("Agent edit to {}", path), &edit).await
+    }
+
+    /// The editor decides where and how files are written, so `options`
+    /// don't apply.
+    async fn write_text_file_with(&self, path: &str, content: &str, _options: WriteOptions) -> AcpResult<()> {
+        self.write_text_file(path, content).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "fn main() {\n    let x = 1;\n    println!(\"{}\", x);\n}\n";
+
+    fn edits(edit: &WorkspaceEdit) -> &[TextEdit] {
+        match &edit.document_changes[..] {
+            [DocumentChange::Edit(edit)] => &edit.edits,
+            other => panic!("expected one document edit, got {:?}", other),
+        }
+    }
+
+    fn range(start: (u32, u32), end: (u32, u32)) -> Range {
+        Range {
+            start: Position { line: start.0, character: start.1 },
+            end: Position { line: end.0, character: end.1 },
+        }
+    }
+
+    #[test]
+    fn test_apply_edit_to_workspace_edit() {
+        let params = FsApplyEditParams {
+            path: "/work/src/main.rs".to_string(),
+            unified_diff: "@@ -2 +2,2 @@\n-    let x = 1;\n+    let x = 2;\n+    let y = 3;\n".to_string(),
+            ..Default::default()
+        };
+        let edit = apply_edit_to_workspace_edit(&params, TEXT).unwrap();
+        assert_eq!(
+            edits(&edit),
+            [TextEdit {
+                range: range((1, 0), (2, 0)),
+                new_text: "    let x = 2;\n    let y = 3;\n".to_string(),
+            }]
+        );
+        let json = serde_json::to_value(&edit).unwrap();
+        assert_eq!(json["documentChanges"][0]["textDocument"]["uri"], "file:///work/src/main.rs");
+        assert_eq!(json["documentChanges"][0]["edits"][0]["newText"], "    let x = 2;\n    let y = 3;\n");
+
+        let stale = FsApplyEditParams {
+            base_hash: Some(content_hash("fn main() {}\n")),
+            ..params
+        };
+        let err = apply_edit_to_workspace_edit(&stale, TEXT).unwrap_err();
+        assert_eq!(err.code(), codes::EDIT_CONFLICT);
+    }
+
+    #[test]
+    fn test_write_to_workspace_edit() {
+        let write = |content: &str| FsWriteTextFileParams {
+            path: "/work/notes/todo é.md".to_string(),
+            content: content.to_string(),
+            ..Default::default()
+        };
+
+        let created = write_to_workspace_edit(&write("- ship\n"), None);
+        let json = serde_json::to_value(&created).unwrap();
+        assert_eq!(json["documentChanges"][0], json!({ "kind": "create", "uri": "file:///work/notes/todo%20%C3%A9.md" }));
+        assert_eq!(json["documentChanges"][1]["edits"][0]["newText"], "- ship\n");
+
+        // Only the changed line is replaced
+        let edit = write_to_workspace_edit(&write("a\nB\nc\nd\ne\n"), Some("a\nb\nc\nd\ne\n"));
+        assert_eq!(
+            edits(&edit),
+            [TextEdit {
+                range: range((1, 0), (2, 0)),
+                new_text: "B\n".to_string(),
+            }]
+        );
+
+        // The last line has no newline, so the end is in it
+        let edit = write_to_workspace_edit(&write("a\nb"), Some("a\nbé"));
+        assert_eq!(edits(&edit)[0].range, range((1, 0), (1, 2)));
+
+        assert!(write_to_workspace_edit(&write("same\n"), Some("same\n")).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_peer_applies_edits() {
+        let (client_io, editor_io) = tokio::io::duplex(64 * 1024);
+        let (client_read, client_write) = tokio::io::split(client_io);
+        let peer = LspPeer::new(client_read, client_write);
+
+        let editor = tokio::spawn(async move {
+            let (editor_read, editor_write) = tokio::io::split(editor_io);
+            let mut reader: Reader = BufReader::new(Box::new(editor_read));
+            let mut writer: Writer = Box::new(editor_write);
+            let mut labels = Vec::new();
+            for applied in [true, false] {
+                let request = read_message(&mut reader).await.unwrap();
+                assert_eq!(request["method"], "workspace/applyEdit");
+                labels.push(request["params"]["label"].as_str().unwrap().to_string());
+                // Ask something first, as editors may
+                let question = json!({ "jsonrpc": "2.0", "id": "q", "method": "workspace/configuration", "params": {} });
+                write_message(&mut writer, &question).await.unwrap();
+                let declined = read_message(&mut reader).await.unwrap();
+                assert_eq!(declined["error"]["code"], codes::METHOD_NOT_FOUND);
+                let result = json!({ "applied": applied, "failureReason": "document changed" });
+                write_message(&mut writer, &json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }))
+                    .await
+                    .unwrap();
+            }
+            labels
+        });
+
+        let edit = write_to_workspace_edit(
+            &FsWriteTextFileParams {
+                path: "/work/a.txt".to_string(),
+                content: "new\n".to_string(),
+                ..Default::default()
+            },
+            Some("old\n"),
+        );
+        peer.apply_edit("first", &edit).await.unwrap();
+        let err = peer.apply_edit("second", &edit).await.unwrap_err();
+        assert!(err.message().contains("document changed"), "{}", err.message());
+        assert_eq!(editor.await.unwrap(), ["first", "second"]);
+    }
+}
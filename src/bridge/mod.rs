@@ -0,0 +1,9 @@
+//! Bridges from ACP to other editor protocols.
+//!
+//! Editors that already speak another protocol can reuse that plumbing for
+//! what agents ask of them; see [`lsp`] for applying agent edits as LSP
+//! workspace edits. [`dialect`] relays between an editor and an agent that
+//! speak different flavours of ACP.
+
+pub mod dialect;
+pub mod lsp;
@@ -0,0 +1,86 @@
+//! Asking the user before the agent changes files.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::RwLock;
+
+use super::PermissionHandler;
+use crate::protocol::*;
+
+/// Whether the agent's writes wait for the user's approval, and the
+/// sessions the user has allowed every write for.
+#[derive(Debug, Clone, Default)]
+pub(super) struct WriteApprovals {
+    required: bool,
+    always_allowed: Arc<Mutex<HashSet<String>>>,
+}
+
+impl WriteApprovals {
+    pub(super) fn new(required: bool) -> Self {
+        Self {
+            required,
+            ..Self::default()
+        }
+    }
+
+    /// Whether writes in `session_id` still need asking about.
+    pub(super) fn required(&self, session_id: Option<&str>) -> bool {
+        self.required
+            && !self
+                .always_allowed
+                .lock()
+                .unwrap()
+                .contains(session_id.unwrap_or_default())
+    }
+
+    /// Ask the permission handler whether `method` may change `path`, showing
+    /// the request's `arguments`. "Allow always" covers the rest of the
+    /// session.
+    pub(super) async fn ask(
+        &self,
+        permissions: &RwLock<Box<dyn PermissionHandler>>,
+        session_id: Option<&str>,
+        method: &str,
+        path: &str,
+        arguments: serde_json::Value,
+    ) -> AcpResult<()> {
+        let session_id = session_id.unwrap_or_default().to_string();
+        let options = [
+            ("allow_once", "Allow", PermissionOptionKind::AllowOnce),
+            ("allow_always", "Allow for this session", PermissionOptionKind::AllowAlways),
+            ("reject_once", "Reject", PermissionOptionKind::RejectOnce),
+        ];
+        let params = RequestPermissionParams {
+            session_id: session_id.clone(),
+            tool_call: ToolCall {
+                id: format!("write_{}", uuid::Uuid::new_v4()),
+                name: method.to_string(),
+                arguments,
+            },
+            options: options
+                .iter()
+                .map(|(id, name, kind)| PermissionOption {
+                    option_id: id.to_string(),
+                    name: name.to_string(),
+                    kind: *kind,
+                })
+                .collect(),
+        };
+
+        let chosen = match permissions.read().await.request_permission(params).await {
+            PermissionOutcome::Selected { option_id } => {
+                options.iter().find(|(id, ..)| *id == option_id).map(|(.., kind)| *kind)
+            }
+            PermissionOutcome::Cancelled => None,
+        };
+        match chosen {
+            Some(PermissionOptionKind::AllowAlways) => {
+                self.always_allowed.lock().unwrap().insert(session_id);
+                Ok(())
+            }
+            Some(kind) if kind.is_allow() => Ok(()),
+            _ => Err(AcpError::PermissionDenied(format!("{} (change not approved)", path))),
+        }
+    }
+}
@@ -0,0 +1,419 @@
+//! Configuring how the agent process is launched.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+#[cfg(not(target_arch = "wasm32"))]
+use std::process::Stdio;
+use tokio::io::{AsyncRead, AsyncWrite};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::process::Command;
+use tokio::time::Duration;
+
+use super::approval::WriteApprovals;
+use super::{Client, Dialect, FsConfig, PathSandbox, RetryPolicy, Transport, WriteOptions};
+use crate::protocol::*;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::server::Transcript;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::testing::{Chaos, Recorder};
+
+/// How long the client waits for a response unless configured otherwise.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Response timeouts, by method. `None` waits indefinitely.
+#[derive(Debug, Clone)]
+pub(super) struct Timeouts {
+    default: Option<Duration>,
+    methods: HashMap<String, Option<Duration>>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            default: Some(DEFAULT_REQUEST_TIMEOUT),
+            methods: HashMap::new(),
+        }
+    }
+}
+
+impl Timeouts {
+    /// The timeout for requests of `method`.
+    pub(super) fn for_method(&self, method: &str) -> Option<Duration> {
+        self.methods.get(method).copied().unwrap_or(self.default)
+    }
+}
+
+/// What to do with the agent's stderr.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StderrMode {
+    /// Share the client's stderr.
+    #[default]
+    Inherit,
+    /// Discard it.
+    Null,
+    /// Pipe it to the client; read it through [`Client::take_stderr`].
+    Capture,
+    /// Pass each line to [`UpdateHandler::on_agent_log`](super::UpdateHandler::on_agent_log).
+    Log,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl StderrMode {
+    fn stdio(self) -> Stdio {
+        match self {
+            StderrMode::Inherit => Stdio::inherit(),
+            StderrMode::Null => Stdio::null(),
+            StderrMode::Capture | StderrMode::Log => Stdio::piped(),
+        }
+    }
+}
+
+/// Builder for a [`Client`] and the agent process behind it.
+///
+/// ```rust,no_run
+/// # use heroacp::client::{Client, StderrMode};
+/// # async fn demo() -> heroacp::protocol::AcpResult<()> {
+/// let client = Client::builder("goose")
+///     .arg("acp")
+///     .env("GOOSE_MODEL", "gpt-4o")
+///     .current_dir("/path/to/project")
+///     .stderr(StderrMode::Capture)
+///     .spawn()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    command: String,
+    args: Vec<String>,
+    env: HashMap<String, String>,
+    current_dir: Option<PathBuf>,
+    stderr: StderrMode,
+    timeouts: Timeouts,
+    retry: Option<RetryPolicy>,
+    sandbox: PathSandbox,
+    write_options: WriteOptions,
+    approve_writes: bool,
+    dialect: Dialect,
+    #[cfg(not(target_arch = "wasm32"))]
+    chaos: Option<Chaos>,
+    #[cfg(not(target_arch = "wasm32"))]
+    transcript: Option<Transcript>,
+}
+
+impl ClientBuilder {
+    /// Launch `command`, found on `PATH` unless it's a path.
+    ///
+    /// The ACP dialect is picked from the command's name; see
+    /// [`Dialect::for_command`].
+    pub fn new(command: impl Into<String>) -> Self {
+        let command = command.into();
+        Self {
+            dialect: Dialect::for_command(&command),
+            command,
+            args: Vec::new(),
+            env: HashMap::new(),
+            current_dir: None,
+            stderr: StderrMode::default(),
+            timeouts: Timeouts::default(),
+            retry: None,
+            sandbox: PathSandbox::default(),
+            write_options: WriteOptions::default(),
+            approve_writes: false,
+            #[cfg(not(target_arch = "wasm32"))]
+            chaos: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            transcript: None,
+        }
+    }
+
+    /// Add an argument.
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Add several arguments.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Set an environment variable for the agent, on top of the client's own.
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.insert(key.into(), value.into());
+        self
+    }
+
+    /// Run the agent in `dir`, which also becomes the client's
+    /// [`working_directory`](Client::working_directory).
+    pub fn current_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    /// Choose what happens to the agent's stderr. Defaults to
+    /// [`StderrMode::Inherit`].
+    pub fn stderr(mut self, mode: StderrMode) -> Self {
+        self.stderr = mode;
+        self
+    }
+
+    /// Wait at most `timeout` for responses, or indefinitely if `None`.
+    /// Defaults to [`DEFAULT_REQUEST_TIMEOUT`].
+    pub fn request_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeouts.default = timeout;
+        self
+    }
+
+    /// Override the response timeout for requests of `method`, e.g. to wait
+    /// longer for `session/prompt` and less for `ping`.
+    pub fn method_timeout(mut self, method: impl Into<String>, timeout: Option<Duration>) -> Self {
+        self.timeouts.methods.insert(method.into(), timeout);
+        self
+    }
+
+    /// Retry failed idempotent requests according to `policy`. By default
+    /// nothing is retried.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Restrict the paths the agent may read and write through `fs/*`
+    /// requests. By default any absolute path is allowed.
+    pub fn sandbox(mut self, sandbox: PathSandbox) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Choose how `fs/write_text_file` writes when the agent doesn't set
+    /// `create_dirs` or `atomic`. By default parent directories must exist
+    /// and files are written in place.
+    pub fn write_options(mut self, options: WriteOptions) -> Self {
+        self.write_options = options;
+        self
+    }
+
+    /// Ask the [`PermissionHandler`](super::PermissionHandler) before each
+    /// request that changes files: `fs/write_text_file`, `fs/apply_edit`,
+    /// `fs/write_file`, `fs/delete`, `fs/rename` and `fs/create_directory`.
+    /// Text changes are shown as a unified diff, the others by their
+    /// arguments. Choosing "allow always" stops the asking for the rest of
+    /// the session. By default changes aren't asked about.
+    pub fn approve_writes(mut self, approve: bool) -> Self {
+        self.approve_writes = approve;
+        self
+    }
+
+    /// Speak `dialect` with the agent, e.g. [`Dialect::ClaudeCode`] for a
+    /// wrapper script around Claude Code's ACP bridge.
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Inject the faults of `chaos` into the connection to the agent, to
+    /// check how the client copes with a slow or flaky agent.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn chaos(mut self, chaos: Chaos) -> Self {
+        self.chaos = Some(chaos);
+        self
+    }
+
+    /// Log every message to and from the agent to `transcript`, as an
+    /// agent's [`Server::with_transcript`](crate::server::Server::with_transcript)
+    /// would: what the client sends is `in`, what the agent sends is `out`.
+    ///
+    /// Messages are logged as they go over the wire, in the agent's
+    /// dialect. Start the transcript before or after connecting.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn transcript(mut self, transcript: Transcript) -> Self {
+        self.transcript = Some(transcript);
+        self
+    }
+
+    /// Start the agent and connect to it.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn spawn(self) -> AcpResult<Client> {
+        let mut command = Command::new(&self.command);
+        command
+            .args(&self.args)
+            .envs(&self.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(self.stderr.stdio());
+        if let Some(dir) = &self.current_dir {
+            command.current_dir(dir);
+        }
+        let child = command.spawn().map_err(AcpError::IoError)?;
+        self.start(Transport::from_child(child)?)
+    }
+
+    /// Connect to an agent that reads `input` and writes `output` instead
+    /// of spawning the command; see [`Client::attach`].
+    ///
+    /// The command, arguments, environment and stderr settings don't apply;
+    /// the working directory does.
+    pub fn attach<R, W>(self, output: R, input: W) -> AcpResult<Client>
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        self.start(Transport::from_streams(output, input))
+    }
+
+    #[cfg_attr(target_arch = "wasm32", allow(unused_mut))]
+    fn start(self, mut transport: Transport) -> AcpResult<Client> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(chaos) = self.chaos {
+            let (output, input) = chaos.wrap(transport.output, transport.input);
+            transport.output = Box::new(output);
+            transport.input = Box::new(input);
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(transcript) = self.transcript {
+            let (output, input) = Recorder::with_transcript(transcript).wrap(transport.output, transport.input);
+            transport.output = Box::new(output);
+            transport.input = Box::new(input);
+        }
+        let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("/"));
+        let working_directory = match self.current_dir {
+            Some(dir) => cwd.join(dir),
+            None => cwd,
+        };
+        Client::start(
+            transport,
+            working_directory.to_string_lossy().to_string(),
+            self.stderr == StderrMode::Log,
+            self.timeouts,
+            self.retry,
+            FsConfig {
+                sandbox: self.sandbox,
+                write: self.write_options,
+                approvals: WriteApprovals::new(self.approve_writes),
+                ..FsConfig::default()
+            },
+            self.dialect,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::UpdateHandler;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn test_builder_configures_process() {
+        let mut client = Client::builder("sh")
+            .args(["-c", r#"echo "$HEROACP_GREETING from $(pwd)" >&2"#])
+            .env("HEROACP_GREETING", "hello")
+            .current_dir("/tmp")
+            .stderr(StderrMode::Capture)
+            .spawn()
+            .await
+            .unwrap();
+        assert_eq!(client.working_directory(), "/tmp");
+
+        let mut stderr = String::new();
+        client
+            .take_stderr()
+            .unwrap()
+            .read_to_string(&mut stderr)
+            .await
+            .unwrap();
+        assert_eq!(stderr, "hello from /tmp\n");
+        assert!(client.take_stderr().is_none());
+    }
+
+    struct Logs(Arc<Mutex<Vec<String>>>);
+
+    impl UpdateHandler for Logs {
+        fn on_agent_log(&self, line: &str) {
+            self.0.lock().unwrap().push(line.to_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stderr_lines_reach_handler() {
+        let client = Client::builder("sh")
+            .args(["-c", r#"sleep 0.2; printf 'starting\r\nmodel: \377\nno newline' >&2"#])
+            .stderr(StderrMode::Log)
+            .spawn()
+            .await
+            .unwrap();
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        client.set_update_handler(Box::new(Logs(logs.clone()))).await;
+
+        for _ in 0..50 {
+            if logs.lock().unwrap().len() == 3 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(*logs.lock().unwrap(), vec!["starting", "model: \u{fffd}", "no newline"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_method_timeouts() {
+        // An agent that never answers
+        let client = Client::builder("sh")
+            .args(["-c", "cat > /dev/null"])
+            .request_timeout(None)
+            .method_timeout(method::PING, Some(Duration::from_millis(50)))
+            .spawn()
+            .await
+            .unwrap();
+        assert_eq!(client.timeouts.for_method(method::INITIALIZE), None);
+
+        let started = tokio::time::Instant::now();
+        let err = client.ping().await.unwrap_err();
+        assert!(matches!(err, AcpError::Timeout));
+        assert_eq!(started.elapsed(), Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn test_chaos_wraps_agent() {
+        let agent = r#"read line; echo '{"jsonrpc":"2.0","id":1,"result":{}}'; cat > /dev/null"#;
+        let latency = Duration::from_millis(50);
+        let client = Client::builder("sh")
+            .args(["-c", agent])
+            .chaos(Chaos::new().latency(latency, latency).duplicate_responses(1.0))
+            .spawn()
+            .await
+            .unwrap();
+
+        // Delayed both ways; the second copy of the response is ignored
+        let started = tokio::time::Instant::now();
+        client.ping().await.unwrap();
+        assert!(started.elapsed() >= latency * 2);
+    }
+
+    #[tokio::test]
+    async fn test_transcript_logs_frames() {
+        let agent = r#"read line; echo '{"jsonrpc":"2.0","id":1,"result":{}}'; cat > /dev/null"#;
+        let path = std::env::temp_dir().join(format!("heroacp-client-{}.jsonl", uuid::Uuid::new_v4()));
+        let transcript = Transcript::new();
+        transcript.start(&path).unwrap();
+        let client = Client::builder("sh").args(["-c", agent]).transcript(transcript).spawn().await.unwrap();
+        client.ping().await.unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        let entries: Vec<serde_json::Value> = contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["direction"], "in");
+        assert_eq!(entries[0]["message"]["method"], "ping");
+        assert_eq!(entries[1]["direction"], "out");
+        assert_eq!(entries[1]["size"], 36);
+    }
+}
@@ -0,0 +1,86 @@
+//! Spawning, initializing and opening a first session in one call.
+
+use super::{default_capabilities, Client, ClientBuilder, ClientSession};
+use crate::protocol::*;
+
+/// What [`Client::connect`] tells the agent about the client.
+#[derive(Debug, Clone)]
+pub struct ClientSetup {
+    /// Name and version of the client.
+    pub client_info: ClientInfo,
+    /// Capabilities the client offers the agent.
+    pub capabilities: ClientCapabilities,
+    /// Working directory for the agent. Defaults to the client's
+    /// [`working_directory`](Client::working_directory).
+    pub cwd: Option<String>,
+    /// MCP servers the agent may use.
+    pub mcp_servers: Vec<McpServer>,
+}
+
+impl ClientSetup {
+    /// Set up a client called `name` with the [`default_capabilities`], in
+    /// the agent's working directory and without MCP servers.
+    pub fn new(name: impl Into<String>, version: impl Into<String>) -> Self {
+        Self {
+            client_info: ClientInfo {
+                name: name.into(),
+                version: version.into(),
+            },
+            capabilities: default_capabilities(),
+            cwd: None,
+            mcp_servers: Vec::new(),
+        }
+    }
+}
+
+/// An initialized agent with a session open, from [`Client::connect`].
+pub struct Connection {
+    /// The client connected to the agent.
+    pub client: Client,
+    /// The agent's answer to `initialize`.
+    pub init: InitializeResult,
+    /// ID of the session opened by `connect`.
+    pub session_id: String,
+}
+
+impl Connection {
+    /// A handle for the session opened by `connect`.
+    pub fn session(&self) -> ClientSession<'_> {
+        self.client.session(&self.session_id)
+    }
+}
+
+impl ClientBuilder {
+    /// Spawn the agent, initialize it with `setup` and open a session.
+    ///
+    /// The agent is sent this crate's [`PROTOCOL_VERSION`]. If any step
+    /// fails the agent is killed.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn connect(self, setup: ClientSetup) -> AcpResult<Connection> {
+        let client = self.spawn().await?;
+        let working_directory = setup
+            .cwd
+            .unwrap_or_else(|| client.working_directory().to_string());
+        let init = client
+            .initialize(InitializeParams {
+                protocol_version: PROTOCOL_VERSION.to_string(),
+                client_info: setup.client_info,
+                capabilities: setup.capabilities,
+                working_directory,
+                mcp_servers: setup.mcp_servers,
+            })
+            .await?;
+        let session = client
+            .session_new(SessionNewParams {
+                session_id: uuid::Uuid::new_v4().to_string(),
+                mode: None,
+                agent: None,
+            })
+            .await?;
+        Ok(Connection {
+            client,
+            init,
+            session_id: session.session_id,
+        })
+    }
+}
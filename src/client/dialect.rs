@@ -0,0 +1,696 @@
+//! Speaking to agents that use a different flavour of ACP.
+//!
+//! Some agents speak an ACP dialect with other method parameters and update
+//! shapes than this crate. A [`Dialect`] other than [`Dialect::Acp`]
+//! translates every message on the wire, so the typed [`Client`](super::Client)
+//! API and handlers work unchanged.
+//!
+//! [`Dialect::ClaudeCode`] is the dialect of Claude Code's ACP bridge
+//! (`claude-code-acp`), [`Dialect::GeminiCli`] that of Gemini CLI's
+//! experimental ACP mode (`gemini --experimental-acp`) and [`Dialect::Goose`]
+//! that of `goose acp`. They speak the same camelCase flavour:
+//!
+//! - Fields are camelCase and `initialize` takes a numeric protocol version.
+//! - The working directory and MCP servers go in `session/new` and
+//!   `session/load`; they are taken from the earlier `initialize`.
+//! - The agent picks session IDs; use the ID `session/new` returns.
+//! - `session/cancel` is a notification, so it's answered locally.
+//! - Session updates nest their payload under `update`, tagged with
+//!   `sessionUpdate`. Kinds without an equivalent here, like
+//!   `available_commands_update`, reach
+//!   [`on_unknown_update`](super::UpdateHandler::on_unknown_update).
+//! - Terminal commands are programs with arguments, not shell command lines.
+//!
+//! Agents may leave out `agentInfo` and most capabilities; missing ones are
+//! taken as unsupported. Hosted `http(s)://` MCP servers are only passed to
+//! agents that advertise `mcpCapabilities.http`, which Gemini CLI and Goose
+//! don't, and are left out of their sessions.
+
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use crate::protocol::*;
+
+/// The flavour of ACP an agent speaks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Dialect {
+    /// ACP as defined by this crate.
+    #[default]
+    Acp,
+    /// Claude Code's ACP bridge.
+    ClaudeCode,
+    /// Gemini CLI's experimental ACP mode.
+    GeminiCli,
+    /// Goose's ACP mode.
+    Goose,
+}
+
+impl Dialect {
+    /// The dialect spoken by the agent `command`, recognized by its file
+    /// name: `claude-code-acp` speaks [`Dialect::ClaudeCode`], `gemini`
+    /// [`Dialect::GeminiCli`] and `goose` [`Dialect::Goose`]; anything else
+    /// is assumed to speak [`Dialect::Acp`].
+    pub fn for_command(command: &str) -> Self {
+        let name = Path::new(command).file_stem().and_then(|name| name.to_str());
+        match name {
+            Some("claude-code-acp") => Dialect::ClaudeCode,
+            Some("gemini") => Dialect::GeminiCli,
+            Some("goose") => Dialect::Goose,
+            _ => Dialect::Acp,
+        }
+    }
+
+    /// A translator for one connection, or `None` if nothing needs
+    /// translating.
+    pub(crate) fn translator(self) -> Option<Translator> {
+        match self {
+            Dialect::Acp => None,
+            dialect => Some(Translator {
+                dialect,
+                state: Mutex::default(),
+            }),
+        }
+    }
+
+    /// Name to report for agents that don't send their own.
+    fn agent_name(self) -> &'static str {
+        match self {
+            Dialect::Acp => "agent",
+            Dialect::ClaudeCode => "claude-code-acp",
+            Dialect::GeminiCli => "gemini",
+            Dialect::Goose => "goose",
+        }
+    }
+}
+
+impl FromStr for Dialect {
+    type Err = AcpError;
+
+    /// Parse a dialect name: `acp`, `claude-code`, `gemini-cli` or `goose`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "acp" => Ok(Dialect::Acp),
+            "claude-code" => Ok(Dialect::ClaudeCode),
+            "gemini-cli" => Ok(Dialect::GeminiCli),
+            "goose" => Ok(Dialect::Goose),
+            _ => Err(AcpError::InvalidParams(format!("Unknown dialect: {}", s))),
+        }
+    }
+}
+
+/// Translates the messages of one connection to a camelCase dialect and
+/// back.
+pub(crate) struct Translator {
+    dialect: Dialect,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Method and params of our requests awaiting a response, by ID.
+    requests: HashMap<String, (String, Value)>,
+    /// Method of the agent's requests awaiting our response, by ID.
+    agent_requests: HashMap<String, String>,
+    /// From `initialize`, for `session/new` and `session/load`.
+    working_directory: String,
+    mcp_servers: Vec<McpServer>,
+    /// Whether the agent takes hosted MCP servers.
+    mcp_http: bool,
+}
+
+impl Translator {
+    /// Translate a message for the agent.
+    ///
+    /// Also returns the response to deliver locally for requests the agent
+    /// won't answer.
+    pub(crate) fn outgoing(&self, mut msg: Value) -> (Value, Option<Value>) {
+        let mut state = self.state.lock().unwrap();
+        let method = msg["method"].as_str().map(str::to_string);
+        let id = msg.get("id").cloned();
+        match (method, id) {
+            // Notifications in this dialect
+            (Some(method), Some(id)) if method == method::SESSION_CANCEL => {
+                msg.as_object_mut().unwrap().remove("id");
+                msg["params"] = json!({ "sessionId": msg["params"]["session_id"] });
+                (msg, Some(json!({ "jsonrpc": "2.0", "id": id, "result": null })))
+            }
+            (Some(method), id) => {
+                let params = msg["params"].take();
+                msg["params"] = request_params(&mut state, &method, &params);
+                if let Some(id) = id {
+                    state.requests.insert(id.to_string(), (method, params));
+                }
+                (msg, None)
+            }
+            (None, Some(id)) => {
+                let method = state.agent_requests.remove(&id.to_string());
+                if let (Some(method), Some(result)) = (method, msg.get_mut("result")) {
+                    *result = agent_result(&method, result.take());
+                }
+                (msg, None)
+            }
+            (None, None) => (msg, None),
+        }
+    }
+
+    /// Translate a message from the agent.
+    pub(crate) fn incoming(&self, mut msg: Value) -> Value {
+        let mut state = self.state.lock().unwrap();
+        let method = msg["method"].as_str().map(str::to_string);
+        let id = msg.get("id").cloned();
+        match (method, id) {
+            (Some(method), id) => {
+                if let Some(id) = id {
+                    state.agent_requests.insert(id.to_string(), method.clone());
+                }
+                let params = msg["params"].take();
+                msg["params"] = agent_params(&state, &method, params);
+            }
+            (None, Some(id)) => {
+                let request = state.requests.remove(&id.to_string());
+                if let (Some((method, params)), Some(result)) = (request, msg.get_mut("result")) {
+                    *result = request_result(self.dialect, &mut state, &method, &params, result.take());
+                }
+            }
+            (None, None) => {}
+        }
+        msg
+    }
+}
+
+/// Params of our request or notification `method`, in the dialect.
+fn request_params(state: &mut State, method: &str, params: &Value) -> Value {
+    match method {
+        method::INITIALIZE => {
+            state.working_directory = params["working_directory"].as_str().unwrap_or_default().to_string();
+            state.mcp_servers = serde_json::from_value(params["mcp_servers"].clone()).unwrap_or_default();
+            let capabilities = &params["capabilities"];
+            let text_files = capabilities["text_files"].as_bool().unwrap_or(false);
+            json!({
+                "protocolVersion": 1,
+                "clientCapabilities": {
+                    "fs": { "readTextFile": text_files, "writeTextFile": text_files },
+                    "terminal": capabilities["terminal"].as_bool().unwrap_or(false),
+                },
+                "clientInfo": params["client_info"],
+            })
+        }
+        method::AUTHENTICATE => json!({ "methodId": params["type"] }),
+        method::SESSION_NEW => json!({
+            "cwd": state.working_directory,
+            "mcpServers": mcp_servers(state),
+        }),
+        method::SESSION_LOAD => json!({
+            "sessionId": params["session_id"],
+            "cwd": state.working_directory,
+            "mcpServers": mcp_servers(state),
+        }),
+        method::SESSION_SET_MODEL => json!({ "sessionId": params["session_id"], "modelId": params["model_id"] }),
+        method::SESSION_PROMPT => {
+            let blocks: Vec<ContentBlock> = serde_json::from_value(params["content"].clone()).unwrap_or_default();
+            json!({
+                "sessionId": params["session_id"],
+                "prompt": blocks.into_iter().map(content_to_agent).collect::<Vec<_>>(),
+            })
+        }
+        _ => params.clone(),
+    }
+}
+
+/// Result of our request `method` with `params`, from the dialect.
+fn request_result(dialect: Dialect, state: &mut State, method: &str, params: &Value, result: Value) -> Value {
+    match method {
+        method::INITIALIZE => {
+            let info = &result["agentInfo"];
+            let capabilities = &result["agentCapabilities"];
+            let prompts = &capabilities["promptCapabilities"];
+            state.mcp_http = capabilities["mcpCapabilities"]["http"].as_bool().unwrap_or(false);
+            json!({
+                "agent_info": {
+                    "name": info["name"].as_str().unwrap_or(dialect.agent_name()),
+                    "version": info["version"].as_str().unwrap_or_default(),
+                },
+                "capabilities": {
+                    "streaming": true,
+                    "audio": prompts["audio"].as_bool().unwrap_or(false),
+                    "image": prompts["image"].as_bool().unwrap_or(false),
+                },
+            })
+        }
+        method::AUTHENTICATE => json!({ "success": true }),
+        method::SESSION_NEW => json!({ "session_id": result["sessionId"] }),
+        method::SESSION_LOAD => json!({ "session_id": params["session_id"], "loaded": true }),
+        method::SESSION_PROMPT => {
+            let status = match result["stopReason"].as_str() {
+                Some("end_turn") | None => "ok",
+                Some(reason) => reason,
+            };
+            json!({ "status": status })
+        }
+        _ => result,
+    }
+}
+
+/// Params of the agent's request or notification `method`, translated.
+fn agent_params(state: &State, method: &str, params: Value) -> Value {
+    match method {
+        method::SESSION_UPDATE => session_update(params),
+        method::SESSION_REQUEST_PERMISSION => {
+            let tool_call = &params["toolCall"];
+            let options: Vec<Value> = params["options"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|option| json!({ "option_id": option["optionId"], "name": option["name"], "kind": option["kind"] }))
+                .collect();
+            json!({
+                "session_id": params["sessionId"],
+                "tool_call": tool_call_from_agent(tool_call),
+                "options": options,
+            })
+        }
+        method::FS_READ_TEXT_FILE => json!({
+            "path": params["path"],
+            "line": params["line"],
+            "limit": params["limit"],
+        }),
+        method::FS_WRITE_TEXT_FILE => json!({
+            "path": params["path"],
+            "content": params["content"],
+            "session_id": params["sessionId"],
+        }),
+        method::TERMINAL_CREATE => {
+            let env: Map<String, Value> = params["env"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|var| Some((var["name"].as_str()?.to_string(), var["value"].clone())))
+                .collect();
+            let cwd = params["cwd"].as_str().unwrap_or(&state.working_directory);
+            json!({
+                "cwd": cwd,
+                "command": params["command"],
+                "args": params.get("args").cloned().unwrap_or(json!([])),
+                "env": env,
+                "no_shell": true,
+                "output_byte_limit": params["outputByteLimit"],
+            })
+        }
+        method::TERMINAL_OUTPUT
+        | method::TERMINAL_WAIT_FOR_EXIT
+        | method::TERMINAL_KILL
+        | method::TERMINAL_RELEASE => json!({ "terminal_id": params["terminalId"] }),
+        _ => params,
+    }
+}
+
+/// Our result for the agent's request `method`, in the dialect.
+fn agent_result(method: &str, result: Value) -> Value {
+    match method {
+        method::SESSION_REQUEST_PERMISSION => {
+            let outcome = match result["outcome"]["option_id"].as_str() {
+                Some(option_id) => json!({ "outcome": "selected", "optionId": option_id }),
+                None => json!({ "outcome": "cancelled" }),
+            };
+            json!({ "outcome": outcome })
+        }
+        method::TERMINAL_CREATE => json!({ "terminalId": result["terminal_id"] }),
+        method::TERMINAL_OUTPUT => {
+            let exit_status = if result["exited"].as_bool().unwrap_or(false) {
+                json!({ "exitCode": result["exit_code"], "signal": null })
+            } else {
+                Value::Null
+            };
+            json!({
+                "output": result["output"],
+                "truncated": result["truncated"],
+                "exitStatus": exit_status,
+            })
+        }
+        method::TERMINAL_WAIT_FOR_EXIT => json!({ "exitCode": result["exit_code"], "signal": null }),
+        method::FS_WRITE_TEXT_FILE | method::TERMINAL_KILL | method::TERMINAL_RELEASE => json!({}),
+        _ => result,
+    }
+}
+
+/// A `session/update` notification, translated.
+fn session_update(params: Value) -> Value {
+    let session_id = params["sessionId"].clone();
+    let Value::Object(mut update) = params["update"].clone() else {
+        return params;
+    };
+    let kind = update
+        .remove("sessionUpdate")
+        .and_then(|kind| kind.as_str().map(str::to_string))
+        .unwrap_or_default();
+    let text = update
+        .get("content")
+        .filter(|content| content["type"] == "text")
+        .map(|content| content["text"].clone());
+    let (kind, data) = match (kind.as_str(), text) {
+        ("agent_message_chunk" | "agent_thought_chunk", Some(text)) => (kind, json!({ "text": text })),
+        ("tool_call", _) => (kind, tool_call_from_agent(&Value::Object(update))),
+        ("tool_call_update", _) => {
+            let content: Vec<Value> = update
+                .get("content")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(tool_call_content_from_agent)
+                .collect();
+            let data = json!({
+                "id": update["toolCallId"],
+                "status": tool_call_status(&update["status"]),
+                "result": update.get("rawOutput"),
+                "content": content,
+            });
+            (kind, data)
+        }
+        ("plan", _) => {
+            let steps: Vec<Value> = update["entries"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .zip(1..)
+                .map(|(entry, id)| json!({ "id": id, "description": entry["content"], "status": entry["status"] }))
+                .collect();
+            (kind, json!({ "steps": steps }))
+        }
+        ("current_mode_update", _) => ("mode_change".to_string(), json!({ "mode": update["currentModeId"] })),
+        _ => (kind, Value::Object(update)),
+    };
+    json!({ "session_id": session_id, "type": kind, "data": data })
+}
+
+/// A dialect tool call as a [`ToolCall`].
+fn tool_call_from_agent(tool_call: &Value) -> Value {
+    let name = tool_call["title"].as_str().or(tool_call["kind"].as_str()).unwrap_or("tool");
+    json!({
+        "id": tool_call["toolCallId"],
+        "name": name,
+        "arguments": tool_call.get("rawInput").cloned().unwrap_or(json!({})),
+    })
+}
+
+fn tool_call_status(status: &Value) -> &'static str {
+    match status.as_str() {
+        Some("completed") => "completed",
+        Some("failed") => "failed",
+        _ => "in_progress",
+    }
+}
+
+/// Dialect tool call content as a [`ToolCallContent`]. Diffs have no
+/// equivalent and are left out.
+fn tool_call_content_from_agent(content: &Value) -> Option<Value> {
+    match content["type"].as_str()? {
+        "content" => {
+            let block = content_from_agent(&content["content"])?;
+            Some(json!({ "type": "content", "content": block }))
+        }
+        "terminal" => Some(json!({ "type": "terminal", "terminal_id": content["terminalId"] })),
+        _ => None,
+    }
+}
+
+/// A content block as the dialect spells it.
+fn content_to_agent(block: ContentBlock) -> Value {
+    match block {
+        ContentBlock::Text { text, .. } => json!({ "type": "text", "text": text }),
+        ContentBlock::Image { format, data, .. } => {
+            json!({ "type": "image", "data": data, "mimeType": format!("image/{}", format) })
+        }
+        ContentBlock::Audio { format, data, .. } => {
+            json!({ "type": "audio", "data": data, "mimeType": format!("audio/{}", format) })
+        }
+        ContentBlock::Resource { uri, mime_type, content, .. } => json!({
+            "type": "resource",
+            "resource": { "uri": uri, "mimeType": mime_type, "text": content },
+        }),
+        ContentBlock::ResourceLink { uri, mime_type, .. } => {
+            let name = uri.rsplit('/').next().unwrap_or(&uri).to_string();
+            json!({ "type": "resource_link", "uri": uri, "name": name, "mimeType": mime_type })
+        }
+    }
+}
+
+/// A dialect content block as a [`ContentBlock`], if it has an equivalent.
+fn content_from_agent(block: &Value) -> Option<ContentBlock> {
+    let format = |prefix: &str| {
+        let mime_type = block["mimeType"].as_str().unwrap_or_default();
+        mime_type.trim_start_matches(prefix).to_string()
+    };
+    let data = || block["data"].as_str().unwrap_or_default().to_string();
+    match block["type"].as_str()? {
+        "text" => Some(ContentBlock::text(block["text"].as_str()?)),
+        "image" => Some(ContentBlock::Image {
+            format: format("image/"),
+            data: data(),
+            annotations: None,
+        }),
+        "audio" => Some(ContentBlock::Audio {
+            format: format("audio/"),
+            data: data(),
+            annotations: None,
+        }),
+        "resource" => {
+            let resource = &block["resource"];
+            Some(ContentBlock::Resource {
+                uri: resource["uri"].as_str()?.to_string(),
+                mime_type: resource["mimeType"].as_str().unwrap_or("text/plain").to_string(),
+                content: resource["text"].as_str()?.to_string(),
+                annotations: None,
+            })
+        }
+        "resource_link" => Some(ContentBlock::ResourceLink {
+            uri: block["uri"].as_str()?.to_string(),
+            mime_type: block["mimeType"].as_str().unwrap_or_default().to_string(),
+            annotations: None,
+        }),
+        _ => None,
+    }
+}
+
+/// MCP servers as the dialect configures them: hosted servers by URL and
+/// headers if the agent takes them, others as a command with its
+/// credentials in the environment.
+fn mcp_servers(state: &State) -> Vec<Value> {
+    state
+        .mcp_servers
+        .iter()
+        .filter_map(|server| {
+            let pairs: Vec<Value> = server
+                .credentials
+                .iter()
+                .map(|(name, value)| json!({ "name": name, "value": value }))
+                .collect();
+            if server.url.starts_with("http://") || server.url.starts_with("https://") {
+                if !state.mcp_http {
+                    eprintln!("The agent doesn't take hosted MCP servers, leaving out {}", server.name);
+                    return None;
+                }
+                return Some(json!({ "type": "http", "name": server.name, "url": server.url, "headers": pairs }));
+            }
+            let command_line = server.url.strip_prefix("stdio://").unwrap_or(&server.url);
+            let mut words = command_line.split_whitespace();
+            Some(json!({
+                "name": server.name,
+                "command": words.next().unwrap_or_default(),
+                "args": words.collect::<Vec<_>>(),
+                "env": pairs,
+            }))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_command() {
+        assert_eq!(Dialect::for_command("claude-code-acp"), Dialect::ClaudeCode);
+        assert_eq!(Dialect::for_command("/usr/local/bin/claude-code-acp"), Dialect::ClaudeCode);
+        assert_eq!(Dialect::for_command("gemini"), Dialect::GeminiCli);
+        assert_eq!(Dialect::for_command("goose"), Dialect::Goose);
+        assert_eq!(Dialect::for_command("codex"), Dialect::Acp);
+        assert!(Dialect::Acp.translator().is_none());
+        assert_eq!("gemini-cli".parse::<Dialect>().unwrap(), Dialect::GeminiCli);
+        assert!("zed".parse::<Dialect>().is_err());
+    }
+
+    #[test]
+    fn test_claude_code_session() {
+        let translator = Dialect::ClaudeCode.translator().unwrap();
+        let (initialize, _) = translator.outgoing(json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {
+            "protocol_version": PROTOCOL_VERSION,
+            "client_info": { "name": "editor", "version": "1.0" },
+            "capabilities": { "text_files": true, "terminal": true },
+            "working_directory": "/work",
+            "mcp_servers": [{ "name": "git", "url": "mcp-git --repo /work", "credentials": { "TOKEN": "t" } }],
+        }}));
+        assert_eq!(initialize["params"]["protocolVersion"], 1);
+        assert_eq!(initialize["params"]["clientCapabilities"]["fs"]["writeTextFile"], true);
+        let init = translator.incoming(json!({ "jsonrpc": "2.0", "id": 1, "result": {
+            "protocolVersion": 1,
+            "agentCapabilities": { "loadSession": true, "promptCapabilities": { "image": true } },
+            "authMethods": [],
+        }}));
+        let init: InitializeResult = serde_json::from_value(init["result"].clone()).unwrap();
+        assert_eq!(init.agent_info.name, "claude-code-acp");
+        assert!(init.capabilities.image && !init.capabilities.audio);
+
+        let (new, _) = translator.outgoing(json!({ "jsonrpc": "2.0", "id": 2, "method": "session/new", "params": {
+            "session_id": "ours",
+        }}));
+        assert_eq!(
+            new["params"],
+            json!({
+                "cwd": "/work",
+                "mcpServers": [{
+                    "name": "git",
+                    "command": "mcp-git",
+                    "args": ["--repo", "/work"],
+                    "env": [{ "name": "TOKEN", "value": "t" }],
+                }],
+            })
+        );
+        let new = translator.incoming(json!({ "jsonrpc": "2.0", "id": 2, "result": { "sessionId": "theirs" } }));
+        assert_eq!(new["result"], json!({ "session_id": "theirs" }));
+
+        let (prompt, _) = translator.outgoing(json!({ "jsonrpc": "2.0", "id": 3, "method": "session/prompt", "params": {
+            "session_id": "theirs",
+            "content": [{ "type": "text", "text": "Hi" }, { "type": "image", "format": "png", "data": "iVBO" }],
+        }}));
+        assert_eq!(
+            prompt["params"]["prompt"],
+            json!([{ "type": "text", "text": "Hi" }, { "type": "image", "data": "iVBO", "mimeType": "image/png" }])
+        );
+
+        let update = translator.incoming(json!({ "jsonrpc": "2.0", "method": "session/update", "params": {
+            "sessionId": "theirs",
+            "update": { "sessionUpdate": "agent_message_chunk", "content": { "type": "text", "text": "Hello" } },
+        }}));
+        let update: SessionUpdate = serde_json::from_value(update["params"].clone()).unwrap();
+        assert!(matches!(update.update_type, SessionUpdateType::AgentMessageChunk { text } if text == "Hello"));
+
+        let update = translator.incoming(json!({ "jsonrpc": "2.0", "method": "session/update", "params": {
+            "sessionId": "theirs",
+            "update": {
+                "sessionUpdate": "tool_call_update",
+                "toolCallId": "t1",
+                "status": "completed",
+                "content": [
+                    { "type": "content", "content": { "type": "text", "text": "done" } },
+                    { "type": "diff", "path": "/work/a", "newText": "b" },
+                    { "type": "terminal", "terminalId": "term-1" },
+                ],
+            },
+        }}));
+        let update: SessionUpdate = serde_json::from_value(update["params"].clone()).unwrap();
+        let SessionUpdateType::ToolCallUpdate(tool) = update.update_type else {
+            panic!("expected a tool call update");
+        };
+        assert!(matches!(tool.status, ToolCallStatus::Completed));
+        assert_eq!(tool.content.len(), 2);
+        assert!(matches!(&tool.content[1], ToolCallContent::Terminal { terminal_id } if terminal_id == "term-1"));
+
+        let update = translator.incoming(json!({ "jsonrpc": "2.0", "method": "session/update", "params": {
+            "sessionId": "theirs",
+            "update": { "sessionUpdate": "available_commands_update", "availableCommands": [] },
+        }}));
+        assert_eq!(update["params"]["type"], "available_commands_update");
+        assert_eq!(update["params"]["data"], json!({ "availableCommands": [] }));
+
+        let done = translator.incoming(json!({ "jsonrpc": "2.0", "id": 3, "result": { "stopReason": "end_turn" } }));
+        assert_eq!(done["result"], json!({ "status": "ok" }));
+
+        // The agent doesn't answer cancellations
+        let (cancel, answer) = translator.outgoing(json!({ "jsonrpc": "2.0", "id": 4, "method": "session/cancel", "params": {
+            "session_id": "theirs",
+        }}));
+        assert_eq!(cancel, json!({ "jsonrpc": "2.0", "method": "session/cancel", "params": { "sessionId": "theirs" } }));
+        assert_eq!(answer, Some(json!({ "jsonrpc": "2.0", "id": 4, "result": null })));
+    }
+
+    /// Replay a transcript, one JSON object per line: `wire` is the message
+    /// in the dialect and `acp` its translation, `to` says which way it goes
+    /// and `answer` is the response delivered locally, if any.
+    fn replay(dialect: Dialect, transcript: &str) {
+        let translator = dialect.translator().unwrap();
+        for (i, line) in transcript.lines().enumerate() {
+            let step: Value = serde_json::from_str(line).unwrap();
+            if step["to"] == "agent" {
+                let (wire, answer) = translator.outgoing(step["acp"].clone());
+                assert_eq!(wire, step["wire"], "line {}", i + 1);
+                assert_eq!(answer.as_ref(), step.get("answer"), "line {}", i + 1);
+            } else {
+                assert_eq!(translator.incoming(step["wire"].clone()), step["acp"], "line {}", i + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_gemini_cli_transcripts() {
+        replay(Dialect::GeminiCli, include_str!("../../tests/fixtures/gemini-cli/session.jsonl"));
+        replay(Dialect::GeminiCli, include_str!("../../tests/fixtures/gemini-cli/cancel.jsonl"));
+    }
+
+    #[test]
+    fn test_goose_transcripts() {
+        replay(Dialect::Goose, include_str!("../../tests/fixtures/goose/prompt.jsonl"));
+        replay(Dialect::Goose, include_str!("../../tests/fixtures/goose/load.jsonl"));
+    }
+
+    #[test]
+    fn test_claude_code_agent_requests() {
+        let translator = Dialect::ClaudeCode.translator().unwrap();
+        let request = translator.incoming(json!({ "jsonrpc": "2.0", "id": 0, "method": "session/request_permission", "params": {
+            "sessionId": "s",
+            "toolCall": { "toolCallId": "t1", "title": "Edit a.rs", "rawInput": { "path": "a.rs" } },
+            "options": [{ "optionId": "allow", "name": "Allow", "kind": "allow_once" }],
+        }}));
+        let params: RequestPermissionParams = serde_json::from_value(request["params"].clone()).unwrap();
+        assert_eq!(params.tool_call.name, "Edit a.rs");
+        assert_eq!(params.options[0].option_id, "allow");
+        let (response, _) = translator.outgoing(json!({ "jsonrpc": "2.0", "id": 0, "result": {
+            "outcome": { "outcome": "selected", "option_id": "allow" },
+        }}));
+        assert_eq!(response["result"], json!({ "outcome": { "outcome": "selected", "optionId": "allow" } }));
+
+        let request = translator.incoming(json!({ "jsonrpc": "2.0", "id": 1, "method": "terminal/create", "params": {
+            "sessionId": "s",
+            "command": "cargo",
+            "args": ["test"],
+            "env": [{ "name": "RUST_LOG", "value": "debug" }],
+            "cwd": "/work",
+        }}));
+        let params: TerminalCreateParams = serde_json::from_value(request["params"].clone()).unwrap();
+        assert!(params.no_shell);
+        assert_eq!(params.env["RUST_LOG"], "debug");
+        let (response, _) = translator.outgoing(json!({ "jsonrpc": "2.0", "id": 1, "result": { "terminal_id": "term-1" } }));
+        assert_eq!(response["result"], json!({ "terminalId": "term-1" }));
+
+        translator.incoming(json!({ "jsonrpc": "2.0", "id": 2, "method": "terminal/output", "params": {
+            "sessionId": "s",
+            "terminalId": "term-1",
+        }}));
+        let (response, _) = translator.outgoing(json!({ "jsonrpc": "2.0", "id": 2, "result": {
+            "output": "ok", "exited": true, "exit_code": 0, "truncated": false,
+        }}));
+        assert_eq!(
+            response["result"],
+            json!({ "output": "ok", "truncated": false, "exitStatus": { "exitCode": 0, "signal": null } })
+        );
+
+        // Errors pass through
+        translator.incoming(json!({ "jsonrpc": "2.0", "id": 3, "method": "fs/write_text_file", "params": {
+            "sessionId": "s", "path": "/etc/passwd", "content": "",
+        }}));
+        let error = json!({ "jsonrpc": "2.0", "id": 3, "error": { "code": -32001, "message": "denied" } });
+        assert_eq!(translator.outgoing(error.clone()).0, error);
+    }
+}
@@ -0,0 +1,48 @@
+//! Client events for any number of subscribers.
+
+use serde_json::Value;
+use tokio::sync::broadcast;
+
+use crate::protocol::*;
+
+/// Events buffered per subscriber before the oldest are dropped.
+const EVENT_CAPACITY: usize = 256;
+
+/// Something that happened on a [`Client`](super::Client).
+///
+/// Unlike the single [`UpdateHandler`](super::UpdateHandler), every
+/// subscriber from [`Client::subscribe`](super::Client::subscribe) gets every
+/// event.
+#[derive(Debug, Clone)]
+pub enum ClientEvent {
+    /// The agent answered `initialize`.
+    Initialized(InitializeResult),
+    /// A session update from the agent.
+    SessionUpdate(SessionUpdate),
+    /// The agent asked to approve a tool call. The
+    /// [`PermissionHandler`](super::PermissionHandler) answers it; this event
+    /// is for display.
+    PermissionRequested(RequestPermissionParams),
+    /// A notification other than `session/update`.
+    Notification {
+        /// Method name.
+        method: String,
+        /// Parameters, `null` if there were none.
+        params: Value,
+    },
+    /// A line the agent wrote to stderr, if built with
+    /// [`StderrMode::Log`](super::StderrMode::Log).
+    AgentLog(String),
+    /// The agent closed its stdout; no more events follow.
+    Disconnected,
+}
+
+/// Create the sender the client publishes events on.
+pub(super) fn channel() -> broadcast::Sender<ClientEvent> {
+    broadcast::channel(EVENT_CAPACITY).0
+}
+
+/// Publish `event`. Nothing happens if no one is subscribed.
+pub(super) fn emit(events: &broadcast::Sender<ClientEvent>, event: ClientEvent) {
+    let _ = events.send(event);
+}
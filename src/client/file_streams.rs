@@ -0,0 +1,131 @@
+//! Files the agent reads in chunks.
+//!
+//! `fs/read_stream_start` opens a file through
+//! [`FileSystemHandler::open_file`](super::FileSystemHandler::open_file) and
+//! keeps the reader here; each `fs/read_chunk` reads at most one chunk from
+//! it, so neither side holds more than a chunk of the file at a time.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::Mutex;
+
+use crate::protocol::*;
+
+/// A file opened for reading by a [`FileSystemHandler`](super::FileSystemHandler).
+pub type FileReader = Box<dyn AsyncRead + Send + Unpin>;
+
+/// Chunk size used when the agent doesn't ask for one.
+pub const DEFAULT_CHUNK_SIZE: u32 = 1024 * 1024;
+
+/// Largest chunk the client sends, whatever the agent asks for.
+pub const MAX_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+
+/// Most streams an agent may have open at once.
+const MAX_OPEN_STREAMS: usize = 64;
+
+struct OpenStream {
+    reader: FileReader,
+    chunk_size: usize,
+}
+
+/// The read streams the agent has open.
+#[derive(Clone, Default)]
+pub(super) struct FileStreams {
+    open: Arc<Mutex<HashMap<String, Arc<Mutex<OpenStream>>>>>,
+}
+
+impl std::fmt::Debug for FileStreams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileStreams").finish_non_exhaustive()
+    }
+}
+
+impl FileStreams {
+    /// Keep `reader` for chunked reads and return its stream ID and chunk
+    /// size.
+    pub(super) async fn open(&self, reader: FileReader, chunk_size: Option<u32>) -> AcpResult<(String, u32)> {
+        let chunk_size = chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE).clamp(1, MAX_CHUNK_SIZE);
+        let mut open = self.open.lock().await;
+        if open.len() >= MAX_OPEN_STREAMS {
+            return Err(AcpError::InvalidState(format!(
+                "{} file streams are already open",
+                MAX_OPEN_STREAMS
+            )));
+        }
+        let stream_id = format!("stream_{}", uuid::Uuid::new_v4());
+        let stream = OpenStream {
+            reader,
+            chunk_size: chunk_size as usize,
+        };
+        open.insert(stream_id.clone(), Arc::new(Mutex::new(stream)));
+        Ok((stream_id, chunk_size))
+    }
+
+    /// Read the next chunk of `stream_id`, closing the stream at the end of
+    /// the file.
+    pub(super) async fn read_chunk(&self, stream_id: &str) -> AcpResult<FsReadChunkResult> {
+        let stream = self
+            .open
+            .lock()
+            .await
+            .get(stream_id)
+            .cloned()
+            .ok_or_else(|| AcpError::ResourceNotFound(stream_id.to_string()))?;
+        let mut stream = stream.lock().await;
+
+        let mut chunk = vec![0; stream.chunk_size];
+        let mut filled = 0;
+        let mut eof = false;
+        while filled < chunk.len() {
+            match stream.reader.read(&mut chunk[filled..]).await? {
+                0 => {
+                    eof = true;
+                    break;
+                }
+                n => filled += n,
+            }
+        }
+        if eof {
+            self.open.lock().await.remove(stream_id);
+        }
+        Ok(FsReadChunkResult::new(&chunk[..filled], eof))
+    }
+
+    /// Close `stream_id` before the end of the file.
+    pub(super) async fn close(&self, stream_id: &str) -> AcpResult<()> {
+        match self.open.lock().await.remove(stream_id) {
+            Some(_) => Ok(()),
+            None => Err(AcpError::ResourceNotFound(stream_id.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_in_chunks() {
+        let streams = FileStreams::default();
+        let (id, chunk_size) = streams.open(Box::new(&b"0123456789"[..]), Some(4)).await.unwrap();
+        assert_eq!(chunk_size, 4);
+
+        let mut read = Vec::new();
+        loop {
+            let chunk = streams.read_chunk(&id).await.unwrap();
+            read.extend(chunk.bytes().unwrap());
+            if chunk.eof {
+                break;
+            }
+        }
+        assert_eq!(read, b"0123456789");
+        let err = streams.read_chunk(&id).await.unwrap_err();
+        assert_eq!(err.code(), codes::RESOURCE_NOT_FOUND);
+
+        let (id, chunk_size) = streams.open(Box::new(&b""[..]), Some(u32::MAX)).await.unwrap();
+        assert_eq!(chunk_size, MAX_CHUNK_SIZE);
+        streams.close(&id).await.unwrap();
+        assert!(streams.close(&id).await.is_err());
+    }
+}
@@ -0,0 +1,114 @@
+//! Per-session transcripts kept by the client.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::protocol::*;
+
+/// Transcripts, keyed by session ID.
+pub(super) type Transcripts = Arc<Mutex<HashMap<String, SessionTranscript>>>;
+
+/// One entry in a [`SessionTranscript`].
+#[derive(Debug, Clone)]
+pub enum TranscriptEntry {
+    /// A prompt the client sent.
+    Prompt(Vec<ContentBlock>),
+    /// An update from the agent.
+    Update(SessionUpdateType),
+}
+
+/// Everything the client sent to and received from one session, in order.
+///
+/// Consecutive message or thought chunks are joined into a single update.
+/// `status` and `queued` updates only describe the moment they were sent, so
+/// they are left out.
+#[derive(Debug, Clone, Default)]
+pub struct SessionTranscript {
+    /// Session ID.
+    pub session_id: String,
+    /// Prompts and updates, oldest first.
+    pub entries: Vec<TranscriptEntry>,
+}
+
+impl SessionTranscript {
+    fn push_update(&mut self, update: SessionUpdateType) {
+        use SessionUpdateType::*;
+        match (self.entries.last_mut(), update) {
+            (_, Status { .. } | Queued { .. }) => {}
+            (
+                Some(TranscriptEntry::Update(AgentMessageChunk { text })),
+                AgentMessageChunk { text: more },
+            )
+            | (
+                Some(TranscriptEntry::Update(AgentThoughtChunk { text })),
+                AgentThoughtChunk { text: more },
+            ) => text.push_str(&more),
+            (_, update) => self.entries.push(TranscriptEntry::Update(update)),
+        }
+    }
+}
+
+fn entry<'a>(
+    transcripts: &'a mut HashMap<String, SessionTranscript>,
+    session_id: &str,
+) -> &'a mut SessionTranscript {
+    transcripts
+        .entry(session_id.to_string())
+        .or_insert_with(|| SessionTranscript {
+            session_id: session_id.to_string(),
+            entries: Vec::new(),
+        })
+}
+
+/// Add a prompt to the transcript of `session_id`.
+pub(super) fn record_prompt(transcripts: &Transcripts, session_id: &str, content: Vec<ContentBlock>) {
+    let mut transcripts = transcripts.lock().unwrap();
+    entry(&mut transcripts, session_id)
+        .entries
+        .push(TranscriptEntry::Prompt(content));
+}
+
+/// Add an update to its session's transcript.
+pub(super) fn record_update(transcripts: &Transcripts, update: SessionUpdate) {
+    let mut transcripts = transcripts.lock().unwrap();
+    entry(&mut transcripts, &update.session_id).push_update(update.update_type);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(update_type: SessionUpdateType) -> SessionUpdate {
+        SessionUpdate {
+            session_id: "s1".to_string(),
+            update_type,
+        }
+    }
+
+    #[test]
+    fn test_transcript_joins_chunks() {
+        let transcripts = Transcripts::default();
+        record_prompt(&transcripts, "s1", vec![ContentBlock::text("Hi")]);
+        for update_type in [
+            SessionUpdateType::Status { state: AgentState::Thinking },
+            SessionUpdateType::AgentThoughtChunk { text: "Greet".to_string() },
+            SessionUpdateType::AgentMessageChunk { text: "Hel".to_string() },
+            SessionUpdateType::AgentMessageChunk { text: "lo".to_string() },
+            SessionUpdateType::Done,
+        ] {
+            record_update(&transcripts, update(update_type));
+        }
+
+        let transcript = transcripts.lock().unwrap()["s1"].clone();
+        assert_eq!(transcript.session_id, "s1");
+        assert!(matches!(
+            &transcript.entries[..],
+            [
+                TranscriptEntry::Prompt(_),
+                TranscriptEntry::Update(SessionUpdateType::AgentThoughtChunk { .. }),
+                TranscriptEntry::Update(SessionUpdateType::AgentMessageChunk { text }),
+                TranscriptEntry::Update(SessionUpdateType::Done),
+            ] if text == "Hello"
+        ));
+    }
+}
@@ -0,0 +1,190 @@
+//! Listing directories and finding files on the local disk.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use super::rt::fs;
+use crate::protocol::*;
+
+/// Most entries returned when the agent doesn't set `max_entries`.
+pub const MAX_DIRECTORY_ENTRIES: u32 = 10_000;
+
+/// Most paths returned when the agent doesn't set `max_results`.
+pub const MAX_GLOB_RESULTS: u32 = 1_000;
+
+/// List the directory at `path`, breadth first and sorted by name within
+/// each directory. Symlinks are reported but not followed.
+pub(super) async fn list_directory(
+    path: &str,
+    recursive: bool,
+    max_entries: Option<u32>,
+) -> AcpResult<FsListDirectoryResult> {
+    let metadata = fs::metadata(path).await.map_err(|e| match e.kind() {
+        std::io::ErrorKind::PermissionDenied => AcpError::PermissionDenied(path.to_string()),
+        _ => AcpError::ResourceNotFound(path.to_string()),
+    })?;
+    if !metadata.is_dir() {
+        return Err(AcpError::InvalidParams(format!("{} is not a directory", path)));
+    }
+
+    let max_entries = max_entries.unwrap_or(MAX_DIRECTORY_ENTRIES) as usize;
+    let root = Path::new(path);
+    let mut result = FsListDirectoryResult::default();
+    let mut pending = VecDeque::from([String::new()]);
+    while let Some(dir) = pending.pop_front() {
+        // Subdirectories that can't be read are skipped
+        let Ok(mut reader) = fs::read_dir(root.join(&dir)).await else {
+            continue;
+        };
+        let mut batch = Vec::new();
+        while let Ok(Some(entry)) = reader.next_entry().await {
+            let Ok(file_type) = entry.file_type().await else {
+                continue;
+            };
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let name = if dir.is_empty() {
+                file_name
+            } else {
+                format!("{}/{}", dir, file_name)
+            };
+            let (kind, size) = if file_type.is_symlink() {
+                (DirectoryEntryKind::Symlink, None)
+            } else if file_type.is_dir() {
+                (DirectoryEntryKind::Directory, None)
+            } else if file_type.is_file() {
+                let size = entry.metadata().await.ok().map(|m| m.len());
+                (DirectoryEntryKind::File, size)
+            } else {
+                (DirectoryEntryKind::Other, None)
+            };
+            batch.push(DirectoryEntry { name, kind, size });
+        }
+        batch.sort_by(|a, b| a.name.cmp(&b.name));
+
+        for entry in batch {
+            if result.entries.len() == max_entries {
+                result.truncated = true;
+                return Ok(result);
+            }
+            if recursive && entry.kind == DirectoryEntryKind::Directory {
+                pending.push_back(entry.name.clone());
+            }
+            result.entries.push(entry);
+        }
+    }
+    Ok(result)
+}
+
+/// Find the files under `cwd` whose path relative to `cwd` matches
+/// `pattern`, honoring `.gitignore` files even outside a git repository.
+pub(super) async fn glob(pattern: &str, cwd: &str, max_results: Option<u32>) -> AcpResult<FsGlobResult> {
+    let matcher = globset::GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .map_err(|e| AcpError::InvalidParams(format!("invalid pattern {}: {}", pattern, e)))?
+        .compile_matcher();
+    let metadata = fs::metadata(cwd).await.map_err(|e| match e.kind() {
+        std::io::ErrorKind::PermissionDenied => AcpError::PermissionDenied(cwd.to_string()),
+        _ => AcpError::ResourceNotFound(cwd.to_string()),
+    })?;
+    if !metadata.is_dir() {
+        return Err(AcpError::InvalidParams(format!("{} is not a directory", cwd)));
+    }
+
+    let root = PathBuf::from(cwd);
+    let max_results = max_results.unwrap_or(MAX_GLOB_RESULTS) as usize;
+    // The walk does blocking I/O
+    tokio::task::spawn_blocking(move || {
+        let walk = ignore::WalkBuilder::new(&root)
+            .require_git(false)
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .build();
+        let mut result = FsGlobResult::default();
+        for entry in walk.flatten() {
+            if !entry.file_type().is_some_and(|t| t.is_file()) {
+                continue;
+            }
+            let Ok(relative) = entry.path().strip_prefix(&root) else {
+                continue;
+            };
+            if !matcher.is_match(relative) {
+                continue;
+            }
+            if result.paths.len() == max_results {
+                result.truncated = true;
+                break;
+            }
+            result.paths.push(entry.path().to_string_lossy().into_owned());
+        }
+        result.paths.sort();
+        result
+    })
+    .await
+    .map_err(|e| AcpError::InternalError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_list_directory() {
+        let dir = std::env::temp_dir().join(format!("heroacp-listing-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("src/bin")).unwrap();
+        std::fs::write(dir.join("Cargo.toml"), "[package]").unwrap();
+        std::fs::write(dir.join("src/lib.rs"), "").unwrap();
+        std::fs::write(dir.join("src/bin/main.rs"), "fn main() {}").unwrap();
+        let path = dir.to_str().unwrap();
+
+        let flat = list_directory(path, false, None).await.unwrap();
+        let names: Vec<_> = flat.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["Cargo.toml", "src"]);
+        assert_eq!(flat.entries[0].size, Some(9));
+        assert_eq!(flat.entries[1].kind, DirectoryEntryKind::Directory);
+        assert!(!flat.truncated);
+
+        let deep = list_directory(path, true, None).await.unwrap();
+        let names: Vec<_> = deep.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["Cargo.toml", "src", "src/bin", "src/lib.rs", "src/bin/main.rs"]);
+
+        let limited = list_directory(path, true, Some(3)).await.unwrap();
+        assert_eq!(limited.entries.len(), 3);
+        assert!(limited.truncated);
+
+        let file = dir.join("Cargo.toml");
+        let err = list_directory(file.to_str().unwrap(), false, None).await.unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_PARAMS);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let err = list_directory(path, false, None).await.unwrap_err();
+        assert_eq!(err.code(), codes::RESOURCE_NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_glob() {
+        let dir = std::env::temp_dir().join(format!("heroacp-glob-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("src/client")).unwrap();
+        std::fs::create_dir_all(dir.join("target/debug")).unwrap();
+        std::fs::write(dir.join(".gitignore"), "target/\n").unwrap();
+        for file in ["build.rs", "src/lib.rs", "src/client/mod.rs", "src/notes.md", "target/debug/gen.rs"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        let cwd = dir.to_str().unwrap();
+        let relative = |result: FsGlobResult| -> Vec<String> {
+            let prefix = format!("{}/", cwd);
+            result.paths.iter().map(|p| p.strip_prefix(&prefix).unwrap().to_string()).collect()
+        };
+
+        let all = glob("**/*.rs", cwd, None).await.unwrap();
+        assert_eq!(relative(all), ["build.rs", "src/client/mod.rs", "src/lib.rs"]);
+        let top = glob("src/*.rs", cwd, None).await.unwrap();
+        assert_eq!(relative(top), ["src/lib.rs"]);
+
+        let limited = glob("**/*.rs", cwd, Some(1)).await.unwrap();
+        assert_eq!(limited.paths.len(), 1);
+        assert!(limited.truncated);
+
+        let err = glob("src/[", cwd, None).await.unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_PARAMS);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
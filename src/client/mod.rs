@@ -25,16 +25,63 @@
 //! }
 //! ```
 
+use async_trait::async_trait;
 use serde_json::Value;
 use std::collections::HashMap;
-use std::process::Stdio;
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::process::{Child, Command};
-use tokio::sync::{mpsc, oneshot, Mutex, RwLock};
-use tokio::time::{timeout, Duration};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::process::ChildStderr;
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex, RwLock};
 
 use crate::protocol::*;
+use rt::process::Child;
+use rt::time::{timeout, Duration};
+
+mod approval;
+mod builder;
+mod connect;
+mod dialect;
+mod events;
+mod file_streams;
+mod history;
+mod listing;
+mod operations;
+pub(crate) mod patch;
+#[cfg(not(target_arch = "wasm32"))]
+mod pool;
+mod retry;
+mod rt;
+mod sandbox;
+mod search;
+mod session;
+mod stream;
+mod terminal;
+mod transport;
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+mod websocket;
+
+pub use builder::{ClientBuilder, StderrMode, DEFAULT_REQUEST_TIMEOUT};
+pub use connect::{ClientSetup, Connection};
+pub use dialect::Dialect;
+pub use events::ClientEvent;
+pub use file_streams::{FileReader, DEFAULT_CHUNK_SIZE, MAX_CHUNK_SIZE};
+pub use history::{SessionTranscript, TranscriptEntry};
+pub use listing::{MAX_DIRECTORY_ENTRIES, MAX_GLOB_RESULTS};
+#[cfg(not(target_arch = "wasm32"))]
+pub use pool::AgentPool;
+pub use retry::RetryPolicy;
+pub use sandbox::PathSandbox;
+pub use search::MAX_GREP_MATCHES;
+pub use session::{ClientSession, SessionUpdates};
+pub use stream::PromptStream;
+#[cfg(not(target_arch = "wasm32"))]
+pub use terminal::{ProcessTerminals, Shell};
+pub use terminal::TerminalHandler;
+
+use builder::Timeouts;
+use terminal::{TerminalRegistry, WAIT_FOR_EXIT_TIMEOUT};
+use transport::Transport;
 
 /// Handler for session updates from the agent.
 pub trait UpdateHandler: Send + Sync {
@@ -44,159 +91,637 @@ pub trait UpdateHandler: Send + Sync {
     /// Called when the agent sends a thought chunk.
     fn on_agent_thought(&self, _session_id: &str, _text: &str) {}
 
+    /// Called when the agent streams a chunk of audio.
+    ///
+    /// Only delivered if the client advertised the `audio` capability.
+    fn on_audio_chunk(&self, _session_id: &str, _chunk: &AudioChunk) {}
+
     /// Called when the agent makes a tool call.
     fn on_tool_call(&self, _session_id: &str, _tool: &ToolCall) {}
 
     /// Called when a tool call is updated.
     fn on_tool_update(&self, _session_id: &str, _update: &ToolCallUpdate) {}
 
+    /// Called when a tool call update embeds a terminal managed by this client.
+    ///
+    /// Use [`Client::terminal_output`] to render the terminal alongside the tool call.
+    fn on_tool_terminal(&self, _session_id: &str, _tool_call_id: &str, _terminal_id: &str) {}
+
     /// Called when the agent updates its plan.
     fn on_plan(&self, _session_id: &str, _plan: &Plan) {}
 
     /// Called when the agent changes mode.
     fn on_mode_change(&self, _session_id: &str, _mode: &str) {}
 
+    /// Called when the session switches to another model.
+    fn on_model_change(&self, _session_id: &str, _model: &str) {}
+
+    /// Called when the agent reports a change in its activity state.
+    fn on_status(&self, _session_id: &str, _state: AgentState) {}
+
+    /// Called when a prompt is waiting behind others in the session.
+    ///
+    /// Pass `prompt_id` in [`SessionCancelParams::prompt_id`] to drop it.
+    fn on_queued(&self, _session_id: &str, _prompt_id: &str, _position: usize) {}
+
     /// Called when the agent is done.
     fn on_done(&self, _session_id: &str) {}
+
+    /// Called with each line the agent writes to stderr, without the line
+    /// ending.
+    ///
+    /// Only delivered if the client was built with [`StderrMode::Log`].
+    fn on_agent_log(&self, _line: &str) {}
+
+    /// Called when a configured MCP server becomes unavailable, is
+    /// connected again, or is given up on.
+    fn on_mcp_server_status(&self, _status: &McpServerStatus) {}
+
+    /// Called for notifications other than `session/update` and
+    /// `mcp/server_status`.
+    ///
+    /// `params` is `null` if the notification carried none.
+    fn on_notification(&self, _method: &str, _params: &Value) {}
+
+    /// Called for session updates of a type this client doesn't know, such
+    /// as ones added in newer protocol versions.
+    ///
+    /// `data` is `null` if the update carried none.
+    fn on_unknown_update(&self, _session_id: &str, _update_type: &str, _data: &Value) {}
 }
 
 /// Default no-op update handler.
 struct NoOpHandler;
 impl UpdateHandler for NoOpHandler {}
 
-/// ACP client for connecting to agents.
-pub struct Client {
-    /// The child process running the agent.
-    child: Child,
-    /// Channel to send messages to the agent.
-    message_tx: mpsc::Sender<String>,
-    /// Pending requests waiting for responses.
-    pending_requests: Arc<Mutex<HashMap<String, oneshot::Sender<JsonRpcResponse>>>>,
-    /// Next request ID.
-    next_id: Arc<Mutex<u64>>,
-    /// Update handler.
-    update_handler: Arc<RwLock<Box<dyn UpdateHandler>>>,
-    /// Terminal manager (kept alive for async task).
-    #[allow(dead_code)]
-    terminals: Arc<Mutex<TerminalManager>>,
-    /// Working directory.
-    working_directory: String,
-    /// Handle to the message loop task.
-    _message_loop_handle: tokio::task::JoinHandle<()>,
+/// Handler for permission requests from the agent.
+#[async_trait]
+pub trait PermissionHandler: Send + Sync {
+    /// Decide whether the agent may proceed with a tool call.
+    ///
+    /// The default rejects every request.
+    async fn request_permission(&self, params: RequestPermissionParams) -> PermissionOutcome {
+        reject_permission(&params.options)
+    }
 }
 
-struct TerminalManager {
-    terminals: HashMap<String, Child>,
-    outputs: HashMap<String, String>,
-    next_id: u64,
+/// Pick the first reject option, or dismiss the request if none is offered.
+pub fn reject_permission(options: &[PermissionOption]) -> PermissionOutcome {
+    options
+        .iter()
+        .find(|option| !option.kind.is_allow())
+        .map(|option| PermissionOutcome::Selected {
+            option_id: option.option_id.clone(),
+        })
+        .unwrap_or(PermissionOutcome::Cancelled)
 }
 
-impl TerminalManager {
-    fn new() -> Self {
-        Self {
-            terminals: HashMap::new(),
-            outputs: HashMap::new(),
-            next_id: 1,
+/// Default permission handler that rejects everything.
+struct RejectAllHandler;
+impl PermissionHandler for RejectAllHandler {}
+
+/// Handler for the agent's sampling requests, which it makes for its MCP
+/// servers.
+///
+/// Advertise the `sampling` capability when installing one.
+#[async_trait]
+pub trait SamplingHandler: Send + Sync {
+    /// Generate the next message of `params.messages` with the editor's
+    /// model, after letting the user review the request if appropriate.
+    ///
+    /// The default refuses with `CAPABILITY_NOT_SUPPORTED`.
+    async fn create_message(
+        &self,
+        _params: SessionSamplingRequestParams,
+    ) -> AcpResult<SessionSamplingRequestResult> {
+        Err(AcpError::CapabilityNotSupported("sampling".to_string()))
+    }
+}
+
+/// Default sampling handler that refuses every request.
+struct NoSampling;
+impl SamplingHandler for NoSampling {}
+
+/// The handlers that answer the agent's requests on the user's behalf.
+#[derive(Clone)]
+struct UserHandlers {
+    permissions: Arc<RwLock<Box<dyn PermissionHandler>>>,
+    sampling: Arc<RwLock<Box<dyn SamplingHandler>>>,
+}
+
+/// Handler for the agent's `fs/*` requests.
+///
+/// Paths are checked to be absolute before they reach the handler. The
+/// defaults use the local disk; override them to serve unsaved editor buffers
+/// or a virtual file system.
+#[async_trait]
+pub trait FileSystemHandler: Send + Sync {
+    /// Read the text file at `path`.
+    ///
+    /// Binary and non-UTF-8 files fail with `INVALID_ENCODING`.
+    async fn read_text_file(&self, path: &str) -> AcpResult<String> {
+        let bytes = rt::fs::read(path).await.map_err(|e| match e.kind() {
+            std::io::ErrorKind::PermissionDenied => AcpError::PermissionDenied(path.to_string()),
+            _ => AcpError::ResourceNotFound(path.to_string()),
+        })?;
+        if bytes.contains(&0) {
+            return Err(AcpError::InvalidEncoding(format!("{} is a binary file", path)));
         }
+        String::from_utf8(bytes).map_err(|e| {
+            AcpError::InvalidEncoding(format!(
+                "{} is not valid UTF-8 (invalid byte at offset {})",
+                path,
+                e.utf8_error().valid_up_to()
+            ))
+        })
     }
 
-    async fn create(&mut self, cwd: &str, command: &str) -> AcpResult<String> {
-        let id = format!("term_{}", self.next_id);
-        self.next_id += 1;
+    /// Read at most `limit` lines of the text file at `path`, starting at
+    /// `line` (counting from 1).
+    ///
+    /// The default reads the whole file with
+    /// [`read_text_file`](Self::read_text_file) and slices it; override it
+    /// to avoid loading large files.
+    async fn read_text_file_lines(
+        &self,
+        path: &str,
+        line: Option<u32>,
+        limit: Option<u32>,
+    ) -> AcpResult<String> {
+        let content = self.read_text_file(path).await?;
+        if line.is_none() && limit.is_none() {
+            return Ok(content);
+        }
+        Ok(slice_lines(&content, line, limit).to_string())
+    }
 
-        let child = Command::new("sh")
-            .arg("-c")
-            .arg(command)
-            .current_dir(cwd)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .map_err(AcpError::IoError)?;
+    /// Apply `unified_diff` to the text file at `path` and return the
+    /// [`content_hash`] of the result.
+    ///
+    /// The default reads the file with [`read_text_file`](Self::read_text_file)
+    /// and writes it with [`write_text_file_with`](Self::write_text_file_with).
+    /// If the file doesn't match `base_hash`, or a hunk's lines are no longer
+    /// in it, nothing is written and the error is an [`EditConflict`].
+    async fn apply_edit(
+        &self,
+        path: &str,
+        unified_diff: &str,
+        base_hash: Option<&str>,
+        options: WriteOptions,
+    ) -> AcpResult<String> {
+        let current = self.read_text_file(path).await?;
+        let current_hash = content_hash(&current);
+        if base_hash.is_some_and(|base| base != current_hash) {
+            let conflict = EditConflict { current_hash, hunk: None };
+            return Err(conflict.into_error(format!("{} changed since the diff was made", path)));
+        }
+        let edited = patch::apply(&current, unified_diff).map_err(|e| e.into_error(path, current_hash))?;
+        self.write_text_file_with(path, &edited, options).await?;
+        Ok(content_hash(&edited))
+    }
 
-        self.terminals.insert(id.clone(), child);
-        self.outputs.insert(id.clone(), String::new());
-        Ok(id)
+    /// Read the file at `path`, whatever its contents.
+    async fn read_file(&self, path: &str) -> AcpResult<Vec<u8>> {
+        rt::fs::read(path).await.map_err(|e| match e.kind() {
+            std::io::ErrorKind::PermissionDenied => AcpError::PermissionDenied(path.to_string()),
+            _ => AcpError::ResourceNotFound(path.to_string()),
+        })
     }
 
-    async fn get_output(&mut self, terminal_id: &str) -> AcpResult<(String, bool, Option<i32>)> {
-        let child = self
-            .terminals
-            .get_mut(terminal_id)
-            .ok_or_else(|| AcpError::ResourceNotFound(terminal_id.to_string()))?;
+    /// Replace the contents of the text file at `path`.
+    async fn write_text_file(&self, path: &str, content: &str) -> AcpResult<()> {
+        rt::fs::write(path, content)
+            .await
+            .map_err(|_| AcpError::PermissionDenied(path.to_string()))
+    }
 
-        // Check if process has exited
-        match child.try_wait() {
-            Ok(Some(status)) => {
-                let output = self.outputs.get(terminal_id).cloned().unwrap_or_default();
-                Ok((output, true, status.code()))
-            }
-            Ok(None) => {
-                let output = self.outputs.get(terminal_id).cloned().unwrap_or_default();
-                Ok((output, false, None))
-            }
-            Err(e) => Err(AcpError::IoError(e)),
+    /// Replace the contents of the text file at `path`, creating parent
+    /// directories and writing atomically as `options` ask.
+    ///
+    /// The default does both on the local disk and otherwise calls
+    /// [`write_text_file`](Self::write_text_file). Handlers that don't write
+    /// to disk should override it too.
+    async fn write_text_file_with(&self, path: &str, content: &str, options: WriteOptions) -> AcpResult<()> {
+        let parent = std::path::Path::new(path).parent();
+        if let Some(parent) = parent.filter(|_| options.create_dirs) {
+            rt::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| AcpError::PermissionDenied(format!("{}: {}", parent.display(), e)))?;
+        }
+        if !options.atomic {
+            return self.write_text_file(path, content).await;
         }
+        write_atomic(std::path::Path::new(path), content.as_bytes())
+            .await
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => AcpError::ResourceNotFound(format!("{}: {}", path, e)),
+                _ => AcpError::PermissionDenied(format!("{}: {}", path, e)),
+            })
     }
 
-    async fn kill(&mut self, terminal_id: &str) -> AcpResult<()> {
-        if let Some(mut child) = self.terminals.remove(terminal_id) {
-            child.kill().await.ok();
-            self.outputs.remove(terminal_id);
-            Ok(())
+    /// Open the file at `path` for `fs/read_stream_start`, returning a reader
+    /// and the file's size if known.
+    async fn open_file(&self, path: &str) -> AcpResult<(FileReader, Option<u64>)> {
+        let file = rt::fs::File::open(path).await.map_err(|e| match e.kind() {
+            std::io::ErrorKind::PermissionDenied => AcpError::PermissionDenied(path.to_string()),
+            _ => AcpError::ResourceNotFound(path.to_string()),
+        })?;
+        let size = file.metadata().await.ok().map(|m| m.len());
+        Ok((Box::new(file), size))
+    }
+
+    /// Replace the contents of the file at `path` with `data`, creating
+    /// parent directories and writing atomically as `options` ask.
+    async fn write_file(&self, path: &str, data: &[u8], options: WriteOptions) -> AcpResult<()> {
+        let parent = std::path::Path::new(path).parent();
+        if let Some(parent) = parent.filter(|_| options.create_dirs) {
+            rt::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| AcpError::PermissionDenied(format!("{}: {}", parent.display(), e)))?;
+        }
+        let result = if options.atomic {
+            write_atomic(std::path::Path::new(path), data).await
         } else {
-            Err(AcpError::ResourceNotFound(terminal_id.to_string()))
+            rt::fs::write(path, data).await
+        };
+        result.map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => AcpError::ResourceNotFound(format!("{}: {}", path, e)),
+            _ => AcpError::PermissionDenied(format!("{}: {}", path, e)),
+        })
+    }
+
+    /// List the directory at `path`, descending into subdirectories if
+    /// `recursive` is set, with at most `max_entries` entries
+    /// ([`MAX_DIRECTORY_ENTRIES`] if `None`).
+    ///
+    /// The default lists the local disk without following symlinks.
+    async fn list_directory(
+        &self,
+        path: &str,
+        recursive: bool,
+        max_entries: Option<u32>,
+    ) -> AcpResult<FsListDirectoryResult> {
+        listing::list_directory(path, recursive, max_entries).await
+    }
+
+    /// Find the files under `cwd` matching `pattern`, at most `max_results`
+    /// of them ([`MAX_GLOB_RESULTS`] if `None`).
+    ///
+    /// The default searches the local disk, skipping hidden files and files
+    /// ignored by `.gitignore`, `.ignore` and the global git excludes.
+    async fn glob(&self, pattern: &str, cwd: &str, max_results: Option<u32>) -> AcpResult<FsGlobResult> {
+        listing::glob(pattern, cwd, max_results).await
+    }
+
+    /// Find the lines of the files under `path` matching `query`, a regular
+    /// expression if `regex` is set, at most `max_matches` of them
+    /// ([`MAX_GREP_MATCHES`] if `None`).
+    ///
+    /// The default searches the local disk in parallel, skipping binary files
+    /// and the files [`glob`](Self::glob) skips.
+    async fn grep(
+        &self,
+        query: &str,
+        regex: bool,
+        path: &str,
+        max_matches: Option<u32>,
+    ) -> AcpResult<FsGrepResult> {
+        search::grep(query, regex, path, max_matches).await
+    }
+
+    /// Delete the file or directory at `path`, with everything in it if
+    /// `recursive` is set.
+    async fn delete(&self, path: &str, recursive: bool) -> AcpResult<()> {
+        operations::delete(path, recursive).await
+    }
+
+    /// Move `from` to `to`, replacing an existing `to` only if `overwrite`
+    /// is set.
+    async fn rename(&self, from: &str, to: &str, overwrite: bool) -> AcpResult<()> {
+        operations::rename(from, to, overwrite).await
+    }
+
+    /// Create the directory at `path`, with its missing parents if
+    /// `recursive` is set.
+    async fn create_directory(&self, path: &str, recursive: bool) -> AcpResult<()> {
+        operations::create_directory(path, recursive).await
+    }
+}
+
+/// How `fs/write_text_file` and `fs/write_file` write when the agent doesn't
+/// say.
+///
+/// ```rust
+/// use heroacp::client::WriteOptions;
+///
+/// let options = WriteOptions::new().create_dirs(true).atomic(true);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// Create missing parent directories.
+    pub create_dirs: bool,
+    /// Write to a temporary file and rename it over the target.
+    pub atomic: bool,
+}
+
+impl WriteOptions {
+    /// Plain writes: parent directories must exist and the file is written
+    /// in place.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create missing parent directories.
+    pub fn create_dirs(mut self, create_dirs: bool) -> Self {
+        self.create_dirs = create_dirs;
+        self
+    }
+
+    /// Write to a temporary file next to the target and rename it over the
+    /// target, so a crash never leaves the file half written.
+    pub fn atomic(mut self, atomic: bool) -> Self {
+        self.atomic = atomic;
+        self
+    }
+
+    /// These options with the ones the agent set taking precedence.
+    fn apply(self, create_dirs: Option<bool>, atomic: Option<bool>) -> Self {
+        Self {
+            create_dirs: create_dirs.unwrap_or(self.create_dirs),
+            atomic: atomic.unwrap_or(self.atomic),
         }
     }
+}
 
-    async fn release(&mut self, terminal_id: &str) -> AcpResult<()> {
-        self.terminals.remove(terminal_id);
-        self.outputs.remove(terminal_id);
-        Ok(())
+/// Write `content` to a temporary file in the same directory as `path` and
+/// rename it over `path`, keeping the permissions of the file it replaces.
+async fn write_atomic(path: &std::path::Path, content: &[u8]) -> std::io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.{}.tmp", name, uuid::Uuid::new_v4()));
+    let result = async {
+        let mut file = rt::fs::File::create(&temp).await?;
+        file.write_all(content).await?;
+        file.sync_all().await?;
+        if let Ok(metadata) = rt::fs::metadata(path).await {
+            rt::fs::set_permissions(&temp, metadata.permissions()).await?;
+        }
+        rt::fs::rename(&temp, path).await
+    }
+    .await;
+    if result.is_err() {
+        let _ = rt::fs::remove_file(&temp).await;
+    }
+    result
+}
+
+/// How the client serves the agent's `fs/*` requests.
+#[derive(Debug, Clone, Default)]
+struct FsConfig {
+    sandbox: PathSandbox,
+    write: WriteOptions,
+    approvals: approval::WriteApprovals,
+    streams: file_streams::FileStreams,
+}
+
+/// Check that `path` is absolute and that the sandbox lets the agent read
+/// it, returning the path to hand to the handler.
+fn check_readable(sandbox: &PathSandbox, path: &str) -> AcpResult<String> {
+    if !std::path::Path::new(path).is_absolute() {
+        return Err(AcpError::InvalidParams("Path must be absolute".to_string()));
+    }
+    sandbox.check(path, false)
+}
+
+/// Like [`check_readable`], for changing the file at `path`.
+fn check_writable(sandbox: &PathSandbox, path: &str) -> AcpResult<String> {
+    if !std::path::Path::new(path).is_absolute() {
+        return Err(AcpError::InvalidParams("Path must be absolute".to_string()));
+    }
+    sandbox.check(path, true)
+}
+
+/// Like [`check_writable`], for deleting or renaming the entry at `path`.
+fn check_entry(sandbox: &PathSandbox, path: &str) -> AcpResult<String> {
+    if !std::path::Path::new(path).is_absolute() {
+        return Err(AcpError::InvalidParams("Path must be absolute".to_string()));
     }
+    sandbox.check_entry(path)
+}
+
+/// The part of `content` from line `line` (counting from 1), at most `limit`
+/// lines long. Line endings are kept.
+fn slice_lines(content: &str, line: Option<u32>, limit: Option<u32>) -> &str {
+    let skip = line.unwrap_or(1).saturating_sub(1) as usize;
+    let mut lines = content.split_inclusive('\n');
+    let start = lines.by_ref().take(skip).map(str::len).sum::<usize>();
+    let len = match limit {
+        Some(limit) => lines.take(limit as usize).map(str::len).sum(),
+        None => content.len() - start,
+    };
+    &content[start..start + len]
+}
+
+/// File system handler that reads and writes the local disk.
+///
+/// Custom handlers can fall back to it for files they don't manage.
+pub struct DiskFileSystem;
+impl FileSystemHandler for DiskFileSystem {}
+
+/// ACP client for connecting to agents.
+pub struct Client {
+    /// The child process running the agent, unless attached to streams.
+    child: Option<Child>,
+    /// The agent's input, shared with the writer task until closed.
+    stdin: Arc<Mutex<Option<Box<dyn AsyncWrite + Send + Unpin>>>>,
+    /// The agent's stderr, if captured and not yet taken.
+    #[cfg(not(target_arch = "wasm32"))]
+    stderr: Option<ChildStderr>,
+    /// Channel to send messages to the agent.
+    message_tx: mpsc::Sender<String>,
+    /// Pending requests waiting for responses.
+    pending_requests: Arc<Mutex<HashMap<RequestId, oneshot::Sender<JsonRpcResponse>>>>,
+    /// Next request ID.
+    next_id: Arc<Mutex<i64>>,
+    /// Update handler.
+    update_handler: Arc<RwLock<Box<dyn UpdateHandler>>>,
+    /// Permission handler.
+    permission_handler: Arc<RwLock<Box<dyn PermissionHandler>>>,
+    /// Sampling handler.
+    sampling_handler: Arc<RwLock<Box<dyn SamplingHandler>>>,
+    /// File system handler.
+    fs_handler: Arc<RwLock<Box<dyn FileSystemHandler>>>,
+    /// Terminal handler.
+    terminal_handler: Arc<RwLock<Box<dyn TerminalHandler>>>,
+    /// Live terminals, shared with the message loop.
+    terminals: Arc<Mutex<TerminalRegistry>>,
+    /// Capabilities this client advertised in `initialize`.
+    client_capabilities: Arc<RwLock<ClientCapabilities>>,
+    /// Capabilities the agent returned from `initialize`.
+    agent_capabilities: Arc<RwLock<Option<AgentCapabilities>>>,
+    /// Sessions with an open [`PromptStream`].
+    prompt_streams: stream::Subscribers,
+    /// Transcript of each session, shared with the message loop.
+    transcripts: history::Transcripts,
+    /// Events for [`subscribe`](Self::subscribe).
+    events: broadcast::Sender<ClientEvent>,
+    /// Response timeouts.
+    timeouts: Timeouts,
+    /// Retry policy for idempotent requests.
+    retry: Option<RetryPolicy>,
+    /// Working directory.
+    working_directory: String,
+    /// Handle to the message loop task, which ends when the agent's
+    /// output does.
+    message_loop: rt::Task,
 }
 
 impl Client {
     /// Spawn a new agent process and create a client.
+    #[cfg(not(target_arch = "wasm32"))]
     pub async fn spawn(command: &str) -> AcpResult<Self> {
-        Self::spawn_with_args(command, &[]).await
+        Self::builder(command).spawn().await
     }
 
     /// Spawn a new agent process with arguments.
+    #[cfg(not(target_arch = "wasm32"))]
     pub async fn spawn_with_args(command: &str, args: &[&str]) -> AcpResult<Self> {
-        let mut child = Command::new(command)
-            .args(args)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
-            .spawn()
-            .map_err(AcpError::IoError)?;
+        Self::builder(command).args(args.iter().copied()).spawn().await
+    }
 
-        let stdin = child.stdin.take().ok_or_else(|| {
-            AcpError::InternalError("Failed to get stdin".to_string())
-        })?;
-        let stdout = child.stdout.take().ok_or_else(|| {
-            AcpError::InternalError("Failed to get stdout".to_string())
-        })?;
+    /// Spawn `command`, initialize it and open a session in one call.
+    ///
+    /// Use [`ClientBuilder::connect`] to configure the process first.
+    ///
+    /// ```rust,no_run
+    /// # use heroacp::client::{Client, ClientSetup};
+    /// # async fn demo() -> heroacp::protocol::AcpResult<()> {
+    /// let conn = Client::connect("./my-agent", ClientSetup::new("my-editor", "1.0.0")).await?;
+    /// println!("Connected to {}", conn.init.agent_info.name);
+    /// conn.session().prompt(vec![heroacp::protocol::ContentBlock::text("Hello")]).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn connect(command: impl Into<String>, setup: ClientSetup) -> AcpResult<Connection> {
+        Self::builder(command).connect(setup).await
+    }
+
+    /// Configure the agent process before spawning it.
+    pub fn builder(command: impl Into<String>) -> ClientBuilder {
+        ClientBuilder::new(command)
+    }
+
+    /// Connect to an agent that reads `input` and writes `output`, rather
+    /// than spawning one.
+    ///
+    /// With the ends of a [`tokio::io::duplex`] pipe this talks to a
+    /// [`Server`](crate::server::Server) in the same process, as
+    /// [`testing::in_process`](crate::testing::in_process) does. Use
+    /// [`ClientBuilder::attach`] to configure the client first.
+    pub fn attach<R, W>(output: R, input: W) -> AcpResult<Self>
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        Self::builder("").attach(output, input)
+    }
+
+    /// Connect to a freshly spawned or attached agent.
+    fn start(
+        transport: Transport,
+        working_directory: String,
+        log_stderr: bool,
+        timeouts: Timeouts,
+        retry: Option<RetryPolicy>,
+        fs: FsConfig,
+        dialect: Dialect,
+    ) -> AcpResult<Self> {
+        let Transport {
+            child,
+            input: stdin,
+            output: stdout,
+            mut stderr,
+        } = transport;
 
         let (message_tx, mut message_rx) = mpsc::channel::<String>(100);
-        let pending_requests: Arc<Mutex<HashMap<String, oneshot::Sender<JsonRpcResponse>>>> =
+        let pending_requests: Arc<Mutex<HashMap<RequestId, oneshot::Sender<JsonRpcResponse>>>> =
             Arc::new(Mutex::new(HashMap::new()));
         let update_handler: Arc<RwLock<Box<dyn UpdateHandler>>> =
             Arc::new(RwLock::new(Box::new(NoOpHandler)));
-        let terminals = Arc::new(Mutex::new(TerminalManager::new()));
+        let permission_handler: Arc<RwLock<Box<dyn PermissionHandler>>> =
+            Arc::new(RwLock::new(Box::new(RejectAllHandler)));
+        let sampling_handler: Arc<RwLock<Box<dyn SamplingHandler>>> =
+            Arc::new(RwLock::new(Box::new(NoSampling)));
+        let fs_handler: Arc<RwLock<Box<dyn FileSystemHandler>>> =
+            Arc::new(RwLock::new(Box::new(DiskFileSystem)));
+        let terminal_handler: Arc<RwLock<Box<dyn TerminalHandler>>> =
+            Arc::new(RwLock::new(Box::new(terminal::default_handler())));
+        let terminals = Arc::new(Mutex::new(TerminalRegistry::default()));
+        let client_capabilities = Arc::new(RwLock::new(ClientCapabilities::default()));
+        let prompt_streams = stream::Subscribers::default();
+        let transcripts = history::Transcripts::default();
+        let events = events::channel();
 
         // Clone for the message loop
         let pending_clone = pending_requests.clone();
         let handler_clone = update_handler.clone();
         let terminals_clone = terminals.clone();
+        let terminal_handler_clone = terminal_handler.clone();
+        let user_clone = UserHandlers {
+            permissions: permission_handler.clone(),
+            sampling: sampling_handler.clone(),
+        };
+        let fs_clone = fs_handler.clone();
+        let fs = Arc::new(fs);
         let message_tx_clone = message_tx.clone();
+        let client_capabilities_clone = client_capabilities.clone();
+        let prompt_streams_clone = prompt_streams.clone();
+        let transcripts_clone = transcripts.clone();
+        let events_clone = events.clone();
+        let translator = dialect.translator().map(Arc::new);
+        let translator_clone = translator.clone();
+
+        // Hand stderr to the update handler a line at a time
+        if let Some(stderr) = stderr.take_if(|_| log_stderr) {
+            let handler = update_handler.clone();
+            let events = events.clone();
+            rt::spawn(async move {
+                let mut reader = BufReader::new(stderr);
+                let mut line = Vec::new();
+                while matches!(reader.read_until(b'\n', &mut line).await, Ok(n) if n > 0) {
+                    let text = String::from_utf8_lossy(&line);
+                    let text = text.trim_end_matches(['\r', '\n']);
+                    handler.read().await.on_agent_log(text);
+                    events::emit(&events, ClientEvent::AgentLog(text.to_string()));
+                    line.clear();
+                }
+            });
+        }
 
         // Spawn writer task
-        let stdin = Arc::new(Mutex::new(stdin));
+        let stdin = Arc::new(Mutex::new(Some(stdin)));
         let stdin_clone = stdin.clone();
-        tokio::spawn(async move {
-            while let Some(msg) = message_rx.recv().await {
+        let pending_writer = pending_requests.clone();
+        rt::spawn(async move {
+            while let Some(mut msg) = message_rx.recv().await {
+                if let Some(translator) = &translator {
+                    let Ok(value) = serde_json::from_str(&msg) else {
+                        continue;
+                    };
+                    let (value, answer) = translator.outgoing(value);
+                    // Requests the agent won't answer in its dialect
+                    if let Some(answer) = answer {
+                        if let Ok(id) = serde_json::from_value(answer["id"].clone()) {
+                            deliver_response(&pending_writer, id, &answer).await;
+                        }
+                    }
+                    msg = value.to_string();
+                }
                 let mut stdin = stdin_clone.lock().await;
+                // Closed by `shutdown`
+                let Some(stdin) = stdin.as_mut() else {
+                    break;
+                };
                 if stdin.write_all(msg.as_bytes()).await.is_err() {
                     break;
                 }
@@ -210,7 +735,7 @@ impl Client {
         });
 
         // Spawn reader task
-        let message_loop_handle = tokio::spawn(async move {
+        let message_loop_handle = rt::spawn(async move {
             let reader = BufReader::new(stdout);
             let mut lines = reader.lines();
 
@@ -226,46 +751,89 @@ impl Client {
                         continue;
                     }
                 };
+                let msg = match &translator_clone {
+                    Some(translator) => translator.incoming(msg),
+                    None => msg,
+                };
+
+                // Some agents send `"id": null` on notifications
+                let id = msg
+                    .get("id")
+                    .and_then(|id| serde_json::from_value::<RequestId>(id.clone()).ok())
+                    .filter(|id| *id != RequestId::Null);
 
                 // Check if it's a request from the agent
-                if msg.get("method").is_some() && msg.get("id").is_some() {
-                    // Handle agent request
-                    let method = msg["method"].as_str().unwrap_or("");
-                    let id = msg["id"].clone();
+                if let (Some(method), Some(id)) = (msg.get("method"), id.clone()) {
+                    // Handle agent requests on their own task so a pending
+                    // permission prompt doesn't stall the message loop
+                    let method = method.as_str().unwrap_or("").to_string();
                     let params = msg.get("params").cloned().unwrap_or(Value::Null);
-
-                    let result = Self::handle_agent_request(
-                        method,
-                        &params,
-                        &terminals_clone,
-                    )
-                    .await;
-
-                    let response = match result {
-                        Ok(value) => serde_json::json!({
-                            "jsonrpc": "2.0",
-                            "id": id,
-                            "result": value
-                        }),
-                        Err(e) => serde_json::json!({
-                            "jsonrpc": "2.0",
-                            "id": id,
-                            "error": {
-                                "code": e.code(),
-                                "message": e.message()
+                    if method == method::SESSION_REQUEST_PERMISSION {
+                        if let Ok(request) = serde_json::from_value(params.clone()) {
+                            events::emit(&events_clone, ClientEvent::PermissionRequested(request));
+                        }
+                    }
+                    let terminals = terminals_clone.clone();
+                    let terminal_handler = terminal_handler_clone.clone();
+                    let user = user_clone.clone();
+                    let files = fs_clone.clone();
+                    let fs = fs.clone();
+                    let message_tx = message_tx_clone.clone();
+
+                    rt::spawn(async move {
+                        let result = Self::handle_agent_request(
+                            &method,
+                            &params,
+                            &terminals,
+                            &terminal_handler,
+                            &user,
+                            &files,
+                            &fs,
+                        )
+                        .await;
+
+                        let response = match result {
+                            Ok(value) => serde_json::json!({
+                                "jsonrpc": "2.0",
+                                "id": id,
+                                "result": value
+                            }),
+                            Err(e) => {
+                                let mut error = serde_json::json!({
+                                    "code": e.code(),
+                                    "message": e.message()
+                                });
+                                if let Some(data) = e.data() {
+                                    error["data"] = data.clone();
+                                }
+                                serde_json::json!({
+                                    "jsonrpc": "2.0",
+                                    "id": id,
+                                    "error": error
+                                })
                             }
-                        }),
-                    };
+                        };
 
-                    let _ = message_tx_clone.send(response.to_string()).await;
+                        let _ = message_tx.send(response.to_string()).await;
+                    });
                 } else if msg.get("method").is_some() {
                     // Notification from agent
                     let method = msg["method"].as_str().unwrap_or("");
-                    if method == "session/update" {
+                    if method == method::SESSION_UPDATE {
                         if let Some(params) = msg.get("params") {
                             let session_id = params["session_id"].as_str().unwrap_or("");
                             let update_type = params["type"].as_str().unwrap_or("");
 
+                            if let Ok(update) = serde_json::from_value::<SessionUpdate>(params.clone()) {
+                                let unwanted_audio = matches!(update.update_type, SessionUpdateType::AudioChunk(_))
+                                    && !client_capabilities_clone.read().await.audio;
+                                if !unwanted_audio {
+                                    history::record_update(&transcripts_clone, update.clone());
+                                    events::emit(&events_clone, ClientEvent::SessionUpdate(update.clone()));
+                                    stream::publish(&prompt_streams_clone, update);
+                                }
+                            }
+
                             let handler = handler_clone.read().await;
                             match update_type {
                                 "agent_message_chunk" => {
@@ -278,6 +846,17 @@ impl Client {
                                         handler.on_agent_thought(session_id, text);
                                     }
                                 }
+                                "audio_chunk" => {
+                                    let audio_supported =
+                                        client_capabilities_clone.read().await.audio;
+                                    if let Ok(chunk) =
+                                        serde_json::from_value::<AudioChunk>(params["data"].clone())
+                                    {
+                                        if audio_supported {
+                                            handler.on_audio_chunk(session_id, &chunk);
+                                        }
+                                    }
+                                }
                                 "tool_call" => {
                                     if let Ok(tool) =
                                         serde_json::from_value::<ToolCall>(params["data"].clone())
@@ -290,6 +869,23 @@ impl Client {
                                         params["data"].clone(),
                                     ) {
                                         handler.on_tool_update(session_id, &update);
+                                        for content in &update.content {
+                                            if let ToolCallContent::Terminal { terminal_id } =
+                                                content
+                                            {
+                                                let attached = terminals_clone
+                                                    .lock()
+                                                    .await
+                                                    .attach_tool_call(&update.id, terminal_id);
+                                                if attached {
+                                                    handler.on_tool_terminal(
+                                                        session_id,
+                                                        &update.id,
+                                                        terminal_id,
+                                                    );
+                                                }
+                                            }
+                                        }
                                     }
                                 }
                                 "plan" => {
@@ -304,170 +900,414 @@ impl Client {
                                         handler.on_mode_change(session_id, mode);
                                     }
                                 }
+                                "model_change" => {
+                                    if let Some(model) = params["data"]["model"].as_str() {
+                                        handler.on_model_change(session_id, model);
+                                    }
+                                }
+                                "status" => {
+                                    if let Ok(state) = serde_json::from_value::<AgentState>(
+                                        params["data"]["state"].clone(),
+                                    ) {
+                                        handler.on_status(session_id, state);
+                                    }
+                                }
+                                "queued" => {
+                                    let data = &params["data"];
+                                    if let (Some(prompt_id), Some(position)) =
+                                        (data["prompt_id"].as_str(), data["position"].as_u64())
+                                    {
+                                        handler.on_queued(session_id, prompt_id, position as usize);
+                                    }
+                                }
                                 "done" => {
                                     handler.on_done(session_id);
                                 }
-                                _ => {}
+                                other => {
+                                    handler.on_unknown_update(session_id, other, &params["data"]);
+                                }
                             }
                         }
+                    } else {
+                        let params = msg.get("params").unwrap_or(&Value::Null);
+                        let status = (method == method::MCP_SERVER_STATUS)
+                            .then(|| serde_json::from_value::<McpServerStatus>(params.clone()).ok())
+                            .flatten();
+                        match status {
+                            Some(status) => handler_clone.read().await.on_mcp_server_status(&status),
+                            None => handler_clone.read().await.on_notification(method, params),
+                        }
+                        events::emit(
+                            &events_clone,
+                            ClientEvent::Notification {
+                                method: method.to_string(),
+                                params: params.clone(),
+                            },
+                        );
                     }
-                } else if msg.get("id").is_some() {
+                } else if let Some(id) = id {
                     // Response to our request
-                    let id_str = msg["id"].to_string();
-                    let mut pending = pending_clone.lock().await;
-                    if let Some(tx) = pending.remove(&id_str) {
-                        let response = JsonRpcResponse {
-                            jsonrpc: "2.0".to_string(),
-                            id: msg["id"].clone(),
-                            result: msg.get("result").cloned(),
-                            error: msg
-                                .get("error")
-                                .and_then(|e| serde_json::from_value(e.clone()).ok()),
-                        };
-                        let _ = tx.send(response);
-                    }
+                    deliver_response(&pending_clone, id, &msg).await;
                 }
             }
+            // No answers can come now; fail waiting requests with `ConnectionClosed`
+            pending_clone.lock().await.clear();
+            events::emit(&events_clone, ClientEvent::Disconnected);
         });
 
-        let working_directory = std::env::current_dir()
-            .map(|p| p.to_string_lossy().to_string())
-            .unwrap_or_else(|_| "/".to_string());
-
         Ok(Self {
             child,
+            stdin,
+            #[cfg(not(target_arch = "wasm32"))]
+            stderr,
             message_tx,
             pending_requests,
             next_id: Arc::new(Mutex::new(1)),
             update_handler,
+            permission_handler,
+            sampling_handler,
+            fs_handler,
+            terminal_handler,
             terminals,
+            client_capabilities,
+            agent_capabilities: Arc::new(RwLock::new(None)),
+            prompt_streams,
+            transcripts,
+            events,
+            timeouts,
+            retry,
             working_directory,
-            _message_loop_handle: message_loop_handle,
+            message_loop: message_loop_handle,
         })
     }
 
     async fn handle_agent_request(
         method: &str,
         params: &Value,
-        terminals: &Arc<Mutex<TerminalManager>>,
+        terminals: &Arc<Mutex<TerminalRegistry>>,
+        terminal_handler: &Arc<RwLock<Box<dyn TerminalHandler>>>,
+        user: &UserHandlers,
+        files: &Arc<RwLock<Box<dyn FileSystemHandler>>>,
+        fs: &FsConfig,
     ) -> AcpResult<Value> {
-        match method {
-            "fs/read_text_file" => {
-                let path = params["path"]
-                    .as_str()
-                    .ok_or_else(|| AcpError::InvalidParams("Missing path".to_string()))?;
+        match method.parse::<Method>()? {
+            Method::SessionRequestPermission => {
+                let params: RequestPermissionParams = serde_json::from_value(params.clone())
+                    .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
+                let outcome = user.permissions.read().await.request_permission(params).await;
+                Ok(serde_json::to_value(RequestPermissionResult { outcome })?)
+            }
+            Method::SessionSamplingRequest => {
+                let params: SessionSamplingRequestParams = serde_json::from_value(params.clone())
+                    .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
+                let result = user.sampling.read().await.create_message(params).await?;
+                Ok(serde_json::to_value(result)?)
+            }
+            Method::FsReadTextFile => {
+                let params: FsReadTextFileParams = serde_json::from_value(params.clone())
+                    .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
+                let path = check_readable(&fs.sandbox, &params.path)?;
+                let path = path.as_str();
+
+                let content = files
+                    .read()
+                    .await
+                    .read_text_file_lines(path, params.line, params.limit)
+                    .await?;
 
-                // Validate absolute path
-                if !path.starts_with('/') {
-                    return Err(AcpError::InvalidParams(
-                        "Path must be absolute".to_string(),
-                    ));
+                Ok(serde_json::json!({ "content": content }))
+            }
+            Method::FsWriteTextFile => {
+                let params: FsWriteTextFileParams = serde_json::from_value(params.clone())
+                    .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
+                let path = check_writable(&fs.sandbox, &params.path)?;
+                let path = path.as_str();
+                let session_id = params.session_id.as_deref();
+                if fs.approvals.required(session_id) {
+                    let current = files.read().await.read_text_file(path).await.unwrap_or_default();
+                    let diff = patch::unified_diff(path, &current, &params.content);
+                    let arguments = serde_json::json!({ "path": path, "diff": diff });
+                    fs.approvals.ask(&user.permissions, session_id, method::FS_WRITE_TEXT_FILE, path, arguments).await?;
                 }
 
-                let content = tokio::fs::read_to_string(path)
+                let options = fs.write.apply(params.create_dirs, params.atomic);
+                files
+                    .read()
                     .await
-                    .map_err(|_| AcpError::ResourceNotFound(path.to_string()))?;
+                    .write_text_file_with(path, &params.content, options)
+                    .await?;
 
-                Ok(serde_json::json!({ "content": content }))
+                Ok(serde_json::json!({ "success": true }))
             }
-            "fs/write_text_file" => {
-                let path = params["path"]
-                    .as_str()
-                    .ok_or_else(|| AcpError::InvalidParams("Missing path".to_string()))?;
-                let content = params["content"]
-                    .as_str()
-                    .ok_or_else(|| AcpError::InvalidParams("Missing content".to_string()))?;
+            Method::FsApplyEdit => {
+                let params: FsApplyEditParams = serde_json::from_value(params.clone())
+                    .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
+                let path = check_writable(&fs.sandbox, &params.path)?;
+                let session_id = params.session_id.as_deref();
+                if fs.approvals.required(session_id) {
+                    let arguments = serde_json::json!({ "path": path, "diff": params.unified_diff });
+                    fs.approvals.ask(&user.permissions, session_id, method::FS_APPLY_EDIT, &path, arguments).await?;
+                }
+
+                let hash = files
+                    .read()
+                    .await
+                    .apply_edit(&path, &params.unified_diff, params.base_hash.as_deref(), fs.write)
+                    .await?;
+
+                Ok(serde_json::to_value(FsApplyEditResult { hash })?)
+            }
+            Method::FsReadFile => {
+                let params: FsReadFileParams = serde_json::from_value(params.clone())
+                    .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
+                let path = check_readable(&fs.sandbox, &params.path)?;
+                let path = path.as_str();
+
+                let bytes = files.read().await.read_file(path).await?;
+                let mime_type = mime_guess::from_path(path).first_or_octet_stream();
+
+                Ok(serde_json::to_value(FsReadFileResult::new(&bytes, mime_type.essence_str()))?)
+            }
+            Method::FsReadStreamStart => {
+                let params: FsReadStreamStartParams = serde_json::from_value(params.clone())
+                    .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
+                let path = check_readable(&fs.sandbox, &params.path)?;
+                let path = path.as_str();
+
+                let (reader, size) = files.read().await.open_file(path).await?;
+                let (stream_id, chunk_size) = fs.streams.open(reader, params.chunk_size).await?;
+                let mime_type = mime_guess::from_path(path).first_or_octet_stream();
+
+                Ok(serde_json::to_value(FsReadStreamStartResult {
+                    stream_id,
+                    size,
+                    mime_type: mime_type.essence_str().to_string(),
+                    chunk_size,
+                })?)
+            }
+            Method::FsReadChunk => {
+                let params: FsReadStreamParams = serde_json::from_value(params.clone())
+                    .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
+
+                let chunk = fs.streams.read_chunk(&params.stream_id).await?;
+
+                Ok(serde_json::to_value(chunk)?)
+            }
+            Method::FsReadStreamClose => {
+                let params: FsReadStreamParams = serde_json::from_value(params.clone())
+                    .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
+
+                fs.streams.close(&params.stream_id).await?;
 
-                // Validate absolute path
-                if !path.starts_with('/') {
-                    return Err(AcpError::InvalidParams(
-                        "Path must be absolute".to_string(),
-                    ));
+                Ok(serde_json::json!({ "success": true }))
+            }
+            Method::FsWriteFile => {
+                let params: FsWriteFileParams = serde_json::from_value(params.clone())
+                    .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
+                let path = check_writable(&fs.sandbox, &params.path)?;
+                let data = params.bytes()?;
+                let session_id = params.session_id.as_deref();
+                if fs.approvals.required(session_id) {
+                    // Text gets a diff like fs/write_text_file; other data only its size
+                    let arguments = match std::str::from_utf8(&data) {
+                        Ok(content) => {
+                            let current = files.read().await.read_text_file(&path).await.unwrap_or_default();
+                            serde_json::json!({ "path": path, "diff": patch::unified_diff(&path, &current, content) })
+                        }
+                        Err(_) => serde_json::json!({ "path": path, "size": data.len() }),
+                    };
+                    fs.approvals.ask(&user.permissions, session_id, method::FS_WRITE_FILE, &path, arguments).await?;
                 }
 
-                tokio::fs::write(path, content)
+                let options = fs.write.apply(params.create_dirs, params.atomic);
+                files
+                    .read()
                     .await
-                    .map_err(|_| AcpError::PermissionDenied(path.to_string()))?;
+                    .write_file(&path, &data, options)
+                    .await?;
 
                 Ok(serde_json::json!({ "success": true }))
             }
-            "terminal/create" => {
-                let cwd = params["cwd"]
-                    .as_str()
-                    .ok_or_else(|| AcpError::InvalidParams("Missing cwd".to_string()))?;
-                let command = params["command"]
-                    .as_str()
-                    .ok_or_else(|| AcpError::InvalidParams("Missing command".to_string()))?;
+            Method::FsListDirectory => {
+                let params: FsListDirectoryParams = serde_json::from_value(params.clone())
+                    .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
+                let path = check_readable(&fs.sandbox, &params.path)?;
+                let path = path.as_str();
+
+                let mut listing = files
+                    .read()
+                    .await
+                    .list_directory(path, params.recursive, params.max_entries)
+                    .await?;
+                // Hide entries the sandbox denies, such as `**/.env`
+                let dir = std::path::Path::new(path);
+                listing.entries.retain(|entry| {
+                    let entry_path = dir.join(&entry.name);
+                    fs.sandbox.check(&entry_path.to_string_lossy(), false).is_ok()
+                });
+
+                Ok(serde_json::to_value(listing)?)
+            }
+            Method::FsGlob => {
+                let params: FsGlobParams = serde_json::from_value(params.clone())
+                    .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
+                let cwd = check_readable(&fs.sandbox, &params.cwd)?;
+                let cwd = cwd.as_str();
+
+                let mut found = files
+                    .read()
+                    .await
+                    .glob(&params.pattern, cwd, params.max_results)
+                    .await?;
+                found.paths.retain(|path| fs.sandbox.check(path, false).is_ok());
+
+                Ok(serde_json::to_value(found)?)
+            }
+            Method::FsGrep => {
+                let params: FsGrepParams = serde_json::from_value(params.clone())
+                    .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
+                let path = check_readable(&fs.sandbox, &params.path)?;
+                let path = path.as_str();
+
+                let mut found = files
+                    .read()
+                    .await
+                    .grep(&params.query, params.regex, path, params.max_matches)
+                    .await?;
+                found.matches.retain(|m| fs.sandbox.check(&m.path, false).is_ok());
+
+                Ok(serde_json::to_value(found)?)
+            }
+            Method::FsDelete => {
+                let params: FsDeleteParams = serde_json::from_value(params.clone())
+                    .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
+                let path = check_entry(&fs.sandbox, &params.path)?;
+                let session_id = params.session_id.as_deref();
+                if fs.approvals.required(session_id) {
+                    let arguments = serde_json::json!({ "path": path, "recursive": params.recursive });
+                    fs.approvals.ask(&user.permissions, session_id, method::FS_DELETE, &path, arguments).await?;
+                }
+
+                files.read().await.delete(&path, params.recursive).await?;
+
+                Ok(serde_json::json!({ "success": true }))
+            }
+            Method::FsRename => {
+                let params: FsRenameParams = serde_json::from_value(params.clone())
+                    .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
+                let from = check_entry(&fs.sandbox, &params.from)?;
+                let to = check_entry(&fs.sandbox, &params.to)?;
+                let session_id = params.session_id.as_deref();
+                if fs.approvals.required(session_id) {
+                    let arguments = serde_json::json!({ "from": from, "to": to, "overwrite": params.overwrite });
+                    fs.approvals.ask(&user.permissions, session_id, method::FS_RENAME, &from, arguments).await?;
+                }
+
+                files
+                    .read()
+                    .await
+                    .rename(&from, &to, params.overwrite)
+                    .await?;
+
+                Ok(serde_json::json!({ "success": true }))
+            }
+            Method::FsCreateDirectory => {
+                let params: FsCreateDirectoryParams = serde_json::from_value(params.clone())
+                    .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
+                let path = check_writable(&fs.sandbox, &params.path)?;
+                let session_id = params.session_id.as_deref();
+                if fs.approvals.required(session_id) {
+                    let arguments = serde_json::json!({ "path": path, "recursive": params.recursive });
+                    fs.approvals.ask(&user.permissions, session_id, method::FS_CREATE_DIRECTORY, &path, arguments).await?;
+                }
+
+                files
+                    .read()
+                    .await
+                    .create_directory(&path, params.recursive)
+                    .await?;
+
+                Ok(serde_json::json!({ "success": true }))
+            }
+            Method::TerminalCreate => {
+                let params: TerminalCreateParams = serde_json::from_value(params.clone())
+                    .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
 
-                let mut term_mgr = terminals.lock().await;
-                let terminal_id = term_mgr.create(cwd, command).await?;
+                let terminal_id = terminal_handler.read().await.create(params).await?;
+                terminals.lock().await.add(&terminal_id);
 
                 Ok(serde_json::json!({ "terminal_id": terminal_id }))
             }
-            "terminal/output" => {
+            Method::TerminalOutput => {
                 let terminal_id = params["terminal_id"]
                     .as_str()
                     .ok_or_else(|| AcpError::InvalidParams("Missing terminal_id".to_string()))?;
 
-                let mut term_mgr = terminals.lock().await;
-                let (output, exited, exit_code) = term_mgr.get_output(terminal_id).await?;
+                let output = terminal_handler.read().await.output(terminal_id).await?;
+
+                Ok(serde_json::to_value(output)?)
+            }
+            Method::TerminalInput => {
+                let params: TerminalInputParams = serde_json::from_value(params.clone())
+                    .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
+
+                terminal_handler
+                    .read()
+                    .await
+                    .input(&params.terminal_id, &params.data, params.eof)
+                    .await?;
 
-                Ok(serde_json::json!({
-                    "output": output,
-                    "exited": exited,
-                    "exit_code": exit_code
-                }))
+                Ok(serde_json::json!({ "success": true }))
             }
-            "terminal/wait_for_exit" => {
+            Method::TerminalWaitForExit => {
                 let terminal_id = params["terminal_id"]
                     .as_str()
                     .ok_or_else(|| AcpError::InvalidParams("Missing terminal_id".to_string()))?;
 
                 // Wait for terminal to exit (with timeout)
-                let term_id = terminal_id.to_string();
-                let terminals = terminals.clone();
-
-                let result = timeout(Duration::from_secs(300), async {
-                    loop {
-                        let mut term_mgr = terminals.lock().await;
-                        let (output, exited, exit_code) = term_mgr.get_output(&term_id).await?;
-                        if exited {
-                            return Ok::<_, AcpError>((output, exit_code.unwrap_or(-1)));
-                        }
-                        drop(term_mgr);
-                        tokio::time::sleep(Duration::from_millis(100)).await;
-                    }
-                })
-                .await
-                .map_err(|_| AcpError::Timeout)?;
+                let handler = terminal_handler.read().await;
+                let result = timeout(WAIT_FOR_EXIT_TIMEOUT, handler.wait_for_exit(terminal_id))
+                    .await
+                    .map_err(|_| AcpError::Timeout)??;
 
-                let (output, exit_code) = result?;
-                Ok(serde_json::json!({
-                    "output": output,
-                    "exit_code": exit_code
-                }))
+                Ok(serde_json::to_value(result)?)
             }
-            "terminal/kill" => {
+            Method::TerminalKill => {
                 let terminal_id = params["terminal_id"]
                     .as_str()
                     .ok_or_else(|| AcpError::InvalidParams("Missing terminal_id".to_string()))?;
 
-                let mut term_mgr = terminals.lock().await;
-                term_mgr.kill(terminal_id).await?;
+                terminal_handler.read().await.kill(terminal_id).await?;
+                terminals.lock().await.forget(terminal_id);
 
                 Ok(serde_json::json!({ "success": true }))
             }
-            "terminal/release" => {
+            Method::TerminalRelease => {
                 let terminal_id = params["terminal_id"]
                     .as_str()
                     .ok_or_else(|| AcpError::InvalidParams("Missing terminal_id".to_string()))?;
 
-                let mut term_mgr = terminals.lock().await;
-                term_mgr.release(terminal_id).await?;
+                terminal_handler.read().await.release(terminal_id).await?;
+                terminals.lock().await.forget(terminal_id);
 
                 Ok(serde_json::json!({ "success": true }))
             }
-            _ => Err(AcpError::MethodNotFound(method.to_string())),
+            Method::Ping => Ok(serde_json::json!({})),
+            Method::Initialize
+            | Method::Authenticate
+            | Method::SessionNew
+            | Method::SessionLoad
+            | Method::Shutdown
+            | Method::AgentStatus
+            | Method::SessionPrompt
+            | Method::SessionPromptStreamStart
+            | Method::SessionPromptStreamChunk
+            | Method::SessionPromptStreamEnd
+            | Method::SessionCancel
+            | Method::SessionSetModel
+            | Method::SessionStats
+            | Method::SessionUpdate
+            | Method::McpServerStatus => Err(AcpError::MethodNotFound(method.to_string())),
         }
     }
 
@@ -477,12 +1317,85 @@ impl Client {
         *h = handler;
     }
 
+    /// Subscribe to the client's events.
+    ///
+    /// Each receiver gets every event from the time it subscribed, alongside
+    /// the update handler. A receiver that falls more than 256 events behind
+    /// misses the oldest ones and gets `RecvError::Lagged`.
+    pub fn subscribe(&self) -> broadcast::Receiver<ClientEvent> {
+        self.events.subscribe()
+    }
+
+    /// Set the handler that answers the agent's permission requests.
+    pub async fn set_permission_handler(&self, handler: Box<dyn PermissionHandler>) {
+        *self.permission_handler.write().await = handler;
+    }
+
+    /// Set the handler that answers the agent's sampling requests.
+    ///
+    /// Advertise the `sampling` capability in `initialize` as well, or the
+    /// agent won't send any.
+    pub async fn set_sampling_handler(&self, handler: Box<dyn SamplingHandler>) {
+        *self.sampling_handler.write().await = handler;
+    }
+
+    /// Set the handler that serves the agent's file system requests.
+    pub async fn set_fs_handler(&self, handler: Box<dyn FileSystemHandler>) {
+        *self.fs_handler.write().await = handler;
+    }
+
+    /// Set the handler that runs the agent's terminal commands.
+    pub async fn set_terminal_handler(&self, handler: Box<dyn TerminalHandler>) {
+        *self.terminal_handler.write().await = handler;
+    }
+
     /// Send a request and wait for a response.
     async fn send_request<T: serde::de::DeserializeOwned>(
         &self,
         method: &str,
         params: Value,
     ) -> AcpResult<T> {
+        self.send_request_with_timeout(method, params, self.timeouts.for_method(method))
+            .await
+    }
+
+    /// Send a request and wait at most `limit` for a response.
+    ///
+    /// Idempotent requests are retried according to the retry policy.
+    async fn send_request_with_timeout<T: serde::de::DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+        limit: Option<Duration>,
+    ) -> AcpResult<T> {
+        let mut attempt = 1;
+        loop {
+            let rx = self.start_request(method, params.clone()).await?;
+            let response = wait_response(rx, limit).await;
+
+            let code = match &response {
+                Ok(response) => response.error.as_ref().map(|e| e.code),
+                Err(e) => Some(e.code()),
+            };
+            let delay = code
+                .zip(self.retry.as_ref())
+                .and_then(|(code, policy)| policy.delay(method, attempt, code));
+            match delay {
+                Some(delay) => {
+                    rt::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                None => return read_response(response?),
+            }
+        }
+    }
+
+    /// Send a request, returning a receiver for its response.
+    async fn start_request(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> AcpResult<oneshot::Receiver<JsonRpcResponse>> {
         let id = {
             let mut next_id = self.next_id.lock().await;
             let id = *next_id;
@@ -490,20 +1403,19 @@ impl Client {
             id
         };
 
-        let id_value = Value::Number(id.into());
-        let id_str = id_value.to_string();
+        let id = RequestId::Number(id);
 
         let (tx, rx) = oneshot::channel();
         {
             let mut pending = self.pending_requests.lock().await;
-            pending.insert(id_str, tx);
+            pending.insert(id.clone(), tx);
         }
 
         let request = JsonRpcRequest {
             jsonrpc: "2.0".to_string(),
-            id: Some(id_value),
+            id: Some(id),
             method: method.to_string(),
-            params: Some(params),
+            params: Some(traced(params)),
         };
 
         let msg = serde_json::to_string(&request)?;
@@ -511,86 +1423,1261 @@ impl Client {
             .send(msg)
             .await
             .map_err(|e| AcpError::ChannelError(e.to_string()))?;
+        Ok(rx)
+    }
 
-        let response = timeout(Duration::from_secs(30), rx)
+    /// Check whether the agent advertised audio support.
+    async fn agent_supports_audio(&self) -> bool {
+        self.agent_capabilities
+            .read()
             .await
-            .map_err(|_| AcpError::Timeout)?
-            .map_err(|_| AcpError::ConnectionClosed)?;
+            .as_ref()
+            .map(|caps| caps.audio)
+            .unwrap_or(false)
+    }
 
-        if let Some(error) = response.error {
-            return Err(AcpError::InternalError(error.message));
-        }
+    /// Send any request, such as a vendor extension method, and return the
+    /// raw result.
+    ///
+    /// Timeouts and retries apply as for the typed methods.
+    pub async fn request_raw(&self, method: &str, params: Value) -> AcpResult<Value> {
+        self.send_request(method, params).await
+    }
+
+    /// Send a notification to the agent.
+    pub async fn notify(&self, method: &str, params: Value) -> AcpResult<()> {
+        let notification = JsonRpcNotification {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params: Some(traced(params)),
+        };
+        self.message_tx
+            .send(serde_json::to_string(&notification)?)
+            .await
+            .map_err(|e| AcpError::ChannelError(e.to_string()))
+    }
+
+    /// Check that the agent is responsive.
+    pub async fn ping(&self) -> AcpResult<()> {
+        let _: Value = self.send_request(method::PING, serde_json::json!({})).await?;
+        Ok(())
+    }
 
-        let result = response.result.unwrap_or(Value::Null);
-        serde_json::from_value(result).map_err(|e| AcpError::InvalidParams(e.to_string()))
+    /// Ask the agent how it's doing: uptime, sessions, prompts and the
+    /// resources its process uses.
+    ///
+    /// Agents that don't implement `agent/status` fail with
+    /// [`AcpError::MethodNotFound`]; [`ping`](Self::ping) still tells
+    /// whether they respond.
+    pub async fn status(&self) -> AcpResult<AgentStatusResult> {
+        self.send_request(method::AGENT_STATUS, serde_json::json!({})).await
     }
 
     /// Initialize the connection with the agent.
     pub async fn initialize(&self, params: InitializeParams) -> AcpResult<InitializeResult> {
-        self.send_request("initialize", serde_json::to_value(params)?).await
+        let client_capabilities = params.capabilities.clone();
+        let result: InitializeResult = self
+            .send_request(method::INITIALIZE, serde_json::to_value(params)?)
+            .await?;
+        *self.client_capabilities.write().await = client_capabilities;
+        *self.agent_capabilities.write().await = Some(result.capabilities.clone());
+        events::emit(&self.events, ClientEvent::Initialized(result.clone()));
+        Ok(result)
     }
 
     /// Create a new session.
     pub async fn session_new(&self, params: SessionNewParams) -> AcpResult<SessionNewResult> {
-        self.send_request("session/new", serde_json::to_value(params)?).await
+        self.send_request(method::SESSION_NEW, serde_json::to_value(params)?).await
     }
 
     /// Load an existing session.
     pub async fn session_load(&self, params: SessionLoadParams) -> AcpResult<SessionLoadResult> {
-        self.send_request("session/load", serde_json::to_value(params)?).await
+        self.send_request(method::SESSION_LOAD, serde_json::to_value(params)?).await
     }
 
     /// Send a prompt to the agent.
+    ///
+    /// Audio blocks are rejected with `CapabilityNotSupported` unless the agent
+    /// advertised audio support during `initialize`.
     pub async fn session_prompt(
         &self,
         params: SessionPromptParams,
     ) -> AcpResult<SessionPromptResult> {
-        self.send_request("session/prompt", serde_json::to_value(params)?).await
+        let audio_supported = self.agent_supports_audio().await;
+        validate_audio_content(&params.content, audio_supported)?;
+        self.record_prompt(&params);
+        self.send_request(method::SESSION_PROMPT, serde_json::to_value(params)?).await
+    }
+
+    /// Send a prompt, waiting at most `limit` for the response instead of
+    /// the configured timeout. `None` waits until the agent responds.
+    pub async fn session_prompt_with_timeout(
+        &self,
+        params: SessionPromptParams,
+        limit: Option<Duration>,
+    ) -> AcpResult<SessionPromptResult> {
+        validate_audio_content(&params.content, self.agent_supports_audio().await)?;
+        self.record_prompt(&params);
+        self.send_request_with_timeout(method::SESSION_PROMPT, serde_json::to_value(params)?, limit)
+            .await
+    }
+
+    /// Send a prompt and stream its updates.
+    ///
+    /// The returned [`PromptStream`] yields the session's updates until
+    /// `Done`; its [`result`](PromptStream::result) is the prompt's response.
+    /// While the stream is open the updates still reach the update handler,
+    /// but a second stream can't be opened for the same session.
+    ///
+    /// ```rust,no_run
+    /// # use heroacp::client::Client;
+    /// # use heroacp::protocol::*;
+    /// # async fn demo(client: &Client, params: SessionPromptParams) -> AcpResult<()> {
+    /// let mut stream = client.prompt_stream(params).await?;
+    /// while let Some(update) = stream.next().await {
+    ///     if let SessionUpdateType::AgentMessageChunk { text } = update.update_type {
+    ///         print!("{}", text);
+    ///     }
+    /// }
+    /// let result = stream.result().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn prompt_stream(&self, params: SessionPromptParams) -> AcpResult<PromptStream> {
+        let limit = self.timeouts.for_method(method::SESSION_PROMPT);
+        self.prompt_stream_with_timeout(params, limit).await
+    }
+
+    /// Send a prompt and stream its updates, waiting at most `limit` for the
+    /// response instead of the configured timeout.
+    pub async fn prompt_stream_with_timeout(
+        &self,
+        params: SessionPromptParams,
+        limit: Option<Duration>,
+    ) -> AcpResult<PromptStream> {
+        validate_audio_content(&params.content, self.agent_supports_audio().await)?;
+        let (updates, subscription) = stream::subscribe(&self.prompt_streams, &params.session_id)?;
+        self.record_prompt(&params);
+        let response = self
+            .start_request(method::SESSION_PROMPT, serde_json::to_value(params)?)
+            .await?;
+        Ok(PromptStream::new(updates, subscription, response, limit))
+    }
+
+    /// Get a handle for `session_id` to prompt, cancel and follow it.
+    ///
+    /// Handles for different sessions can be used concurrently; see
+    /// [`ClientSession`].
+    pub fn session<'a>(&'a self, session_id: &'a str) -> ClientSession<'a> {
+        ClientSession::new(self, session_id)
+    }
+
+    /// Send a prompt to the agent in chunks.
+    ///
+    /// Each block goes out in its own `session/prompt_stream_chunk` request,
+    /// and text longer than [`PROMPT_CHUNK_BYTES`] is split across several, so
+    /// no single message has to carry the whole prompt. Audio blocks are
+    /// checked as in [`session_prompt`](Self::session_prompt) before the
+    /// stream is started.
+    pub async fn session_prompt_stream<I>(
+        &self,
+        session_id: &str,
+        content: I,
+    ) -> AcpResult<SessionPromptResult>
+    where
+        I: IntoIterator<Item = ContentBlock>,
+    {
+        // Refuse the prompt before a stream is opened for it on the agent
+        let content: Vec<ContentBlock> = content.into_iter().collect();
+        validate_audio_content(&content, self.agent_supports_audio().await)?;
+
+        let start: PromptStreamStartResult = self
+            .send_request(
+                method::SESSION_PROMPT_STREAM_START,
+                serde_json::to_value(PromptStreamStartParams {
+                    session_id: session_id.to_string(),
+                })?,
+            )
+            .await?;
+
+        let mut sent = Vec::new();
+        for block in content {
+            sent.push(block.clone());
+            let pieces = match block {
+                ContentBlock::Text { text, annotations } if text.len() > PROMPT_CHUNK_BYTES => {
+                    split_text(&text, PROMPT_CHUNK_BYTES)
+                        .into_iter()
+                        .map(|piece| ContentBlock::Text {
+                            text: piece.to_string(),
+                            annotations: annotations.clone(),
+                        })
+                        .collect()
+                }
+                block => vec![block],
+            };
+            for (i, piece) in pieces.into_iter().enumerate() {
+                let _: Value = self
+                    .send_request(
+                        method::SESSION_PROMPT_STREAM_CHUNK,
+                        serde_json::to_value(PromptStreamChunkParams {
+                            stream_id: start.stream_id.clone(),
+                            content: vec![piece],
+                            continuation: i > 0,
+                        })?,
+                    )
+                    .await?;
+            }
+        }
+
+        history::record_prompt(&self.transcripts, session_id, sent);
+        self.send_request(
+            method::SESSION_PROMPT_STREAM_END,
+            serde_json::to_value(PromptStreamEndParams {
+                stream_id: start.stream_id,
+            })?,
+        )
+        .await
+    }
+
+    fn record_prompt(&self, params: &SessionPromptParams) {
+        history::record_prompt(&self.transcripts, &params.session_id, params.content.clone());
+    }
+
+    /// Get the prompts and updates of a session so far, e.g. to render the
+    /// conversation again after a view reload.
+    ///
+    /// Only what passed through this client is recorded; `None` if nothing has.
+    pub fn session_history(&self, session_id: &str) -> Option<SessionTranscript> {
+        self.transcripts.lock().unwrap().get(session_id).cloned()
+    }
+
+    /// Drop the recorded transcript of a session.
+    pub fn clear_session_history(&self, session_id: &str) {
+        self.transcripts.lock().unwrap().remove(session_id);
     }
 
     /// Cancel the current session operation.
     pub async fn session_cancel(&self, params: SessionCancelParams) -> AcpResult<()> {
         let _: Value = self
-            .send_request("session/cancel", serde_json::to_value(params)?)
+            .send_request(method::SESSION_CANCEL, serde_json::to_value(params)?)
             .await?;
         Ok(())
     }
 
+    /// Switch a session to another model, one of
+    /// [`AgentCapabilities::models`] if the agent lists any.
+    pub async fn session_set_model(&self, params: SessionSetModelParams) -> AcpResult<()> {
+        let _: Value = self
+            .send_request(method::SESSION_SET_MODEL, serde_json::to_value(params)?)
+            .await?;
+        Ok(())
+    }
+
+    /// Get the usage the agent counted for a session. Servers answer only
+    /// when the host enabled it; others fail with `METHOD_NOT_FOUND`.
+    pub async fn session_stats(&self, session_id: &str) -> AcpResult<SessionStats> {
+        let params = SessionStatsParams {
+            session_id: session_id.to_string(),
+        };
+        self.send_request(method::SESSION_STATS, serde_json::to_value(params)?).await
+    }
+
+    /// Get the ID of the terminal embedded in a tool call, if any.
+    pub async fn tool_call_terminal(&self, tool_call_id: &str) -> Option<String> {
+        self.terminals.lock().await.tool_call_terminal(tool_call_id)
+    }
+
+    /// Get the current output of a terminal created by the agent.
+    pub async fn terminal_output(&self, terminal_id: &str) -> AcpResult<TerminalOutputResult> {
+        self.terminal_handler.read().await.output(terminal_id).await
+    }
+
+    /// Get the capabilities the agent advertised, if initialized.
+    pub async fn agent_capabilities(&self) -> Option<AgentCapabilities> {
+        self.agent_capabilities.read().await.clone()
+    }
+
+    /// Take the agent's stderr.
+    ///
+    /// Only available once, and only if the client was built with
+    /// [`StderrMode::Capture`].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn take_stderr(&mut self) -> Option<ChildStderr> {
+        self.stderr.take()
+    }
+
     /// Get the working directory.
     pub fn working_directory(&self) -> &str {
         &self.working_directory
     }
 
-    /// Check if the agent process is still running.
+    /// Check if the agent process is still running. An attached agent runs
+    /// until it closes its output.
     pub fn is_running(&mut self) -> bool {
-        match self.child.try_wait() {
-            Ok(Some(_)) => false,
-            Ok(None) => true,
-            Err(_) => false,
+        match &mut self.child {
+            Some(child) => matches!(child.try_wait(), Ok(None)),
+            None => !self.message_loop.is_finished(),
         }
     }
 
-    /// Kill the agent process.
+    /// Kill the agent process, or close the input of an attached agent.
     pub async fn kill(&mut self) -> AcpResult<()> {
-        self.child.kill().await.map_err(AcpError::IoError)
+        match &mut self.child {
+            Some(child) => child.kill().await.map_err(AcpError::IoError),
+            None => {
+                self.close_input().await;
+                Ok(())
+            }
+        }
+    }
+
+    /// Signal end of input to the agent.
+    async fn close_input(&self) {
+        // Shut the stream down rather than only dropping it, as an
+        // in-memory pipe stays open while its read half lives
+        if let Some(mut stdin) = self.stdin.lock().await.take() {
+            let _ = stdin.shutdown().await;
+        }
+    }
+
+    /// Stop the agent gracefully, killing it if it takes longer than `grace`.
+    ///
+    /// Sends a `shutdown` request so the agent can wind down its sessions,
+    /// closes its stdin, and waits for it to exit. Agents that don't support
+    /// `shutdown` still get the end of input.
+    pub async fn shutdown(&mut self, grace: Duration) -> AcpResult<()> {
+        let deadline = rt::time::Instant::now() + grace;
+        let _ = self
+            .send_request_with_timeout::<Value>(method::SHUTDOWN, serde_json::json!({}), Some(grace))
+            .await;
+
+        self.close_input().await;
+
+        let exited = match &mut self.child {
+            Some(child) => rt::time::timeout_at(deadline, child.wait())
+                .await
+                .map(|status| status.map(|_| ())),
+            // An attached agent is done once it closes its output
+            None => rt::time::timeout_at(deadline, &mut self.message_loop)
+                .await
+                .map(|_| Ok(())),
+        };
+        match exited {
+            Ok(status) => status.map_err(AcpError::IoError),
+            Err(_) => self.kill().await,
+        }
     }
 }
 
 impl Drop for Client {
     fn drop(&mut self) {
-        // Try to kill the child process when the client is dropped
-        let _ = self.child.start_kill();
+        match &mut self.child {
+            // Try to kill the child process when the client is dropped
+            Some(child) => {
+                let _ = child.start_kill();
+            }
+            // Stop reading, so the writer task ends and closes the agent's input
+            None => self.message_loop.abort(),
+        }
+    }
+}
+
+/// Hand the response `msg` to the request `id` waiting for it.
+///
+/// Tolerates agents that echo numeric IDs as strings and send error objects
+/// without a code or message.
+async fn deliver_response(
+    pending: &Mutex<HashMap<RequestId, oneshot::Sender<JsonRpcResponse>>>,
+    id: RequestId,
+    msg: &Value,
+) {
+    let mut pending = pending.lock().await;
+    let tx = match &id {
+        RequestId::String(s) if !pending.contains_key(&id) => {
+            s.parse().ok().and_then(|n| pending.remove(&RequestId::Number(n)))
+        }
+        _ => pending.remove(&id),
+    };
+    if let Some(tx) = tx {
+        let error = msg.get("error").filter(|e| !e.is_null()).map(|e| {
+            serde_json::from_value(e.clone()).unwrap_or_else(|_| JsonRpcError {
+                code: e["code"].as_i64().map_or(codes::INTERNAL_ERROR, |code| code as i32),
+                message: e["message"].as_str().unwrap_or("malformed error response").to_string(),
+                data: e.get("data").cloned(),
+            })
+        });
+        let response = JsonRpcResponse {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: msg.get("result").cloned(),
+            error,
+        };
+        let _ = tx.send(response);
+    }
+}
+
+/// `params` with the trace context of the current span in `_meta`, unless
+/// they name one already. Only spans of an OpenTelemetry trace have one, so
+/// this needs the `otel` feature.
+fn traced(params: Value) -> Value {
+    #[cfg(feature = "otel")]
+    if let Some(context) = TraceContext::current().filter(|_| TraceContext::from_params(&params).is_none()) {
+        let mut params = params;
+        context.inject(&mut params);
+        return params;
     }
+    params
+}
+
+/// Wait at most `limit` for a response.
+async fn wait_response(
+    rx: oneshot::Receiver<JsonRpcResponse>,
+    limit: Option<Duration>,
+) -> AcpResult<JsonRpcResponse> {
+    let response = match limit {
+        Some(limit) => timeout(limit, rx).await.map_err(|_| AcpError::Timeout)?,
+        None => rx.await,
+    };
+    response.map_err(|_| AcpError::ConnectionClosed)
+}
+
+/// Turn a response into its result or error.
+fn read_response<T: serde::de::DeserializeOwned>(response: JsonRpcResponse) -> AcpResult<T> {
+    if let Some(error) = response.error {
+        return Err(error.into());
+    }
+
+    let result = response.result.unwrap_or(Value::Null);
+    serde_json::from_value(result).map_err(|e| AcpError::InvalidParams(e.to_string()))
+}
+
+/// Largest piece of text sent in one `session/prompt_stream_chunk` request.
+pub const PROMPT_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Split `text` into pieces of at most `max` bytes without breaking characters.
+fn split_text(text: &str, max: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    while rest.len() > max {
+        let mut end = max;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end == 0 {
+            end = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+        let (piece, tail) = rest.split_at(end);
+        pieces.push(piece);
+        rest = tail;
+    }
+    pieces.push(rest);
+    pieces
 }
 
 /// Create client capabilities with common defaults.
+///
+/// Terminals are only offered where the client can run processes, so not
+/// in a browser.
 pub fn default_capabilities() -> ClientCapabilities {
     ClientCapabilities {
         text_files: true,
-        terminal: true,
+        terminal: cfg!(not(target_arch = "wasm32")),
+        apply_edit: true,
+        binary_files: true,
+        read_stream: true,
+        list_directory: true,
+        glob: true,
+        grep: true,
+        manage_files: true,
         embedded_context: false,
         audio: false,
         image: true,
+        sampling: false,
         experimental: HashMap::new(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{UpdateCollector, UpdateEvent};
+
+    fn option(id: &str, kind: PermissionOptionKind) -> PermissionOption {
+        PermissionOption {
+            option_id: id.to_string(),
+            name: id.to_string(),
+            kind,
+        }
+    }
+
+    #[test]
+    fn test_slice_lines() {
+        let content = "one\ntwo\r\nthree\nfour";
+        assert_eq!(slice_lines(content, Some(2), Some(2)), "two\r\nthree\n");
+        assert_eq!(slice_lines(content, Some(3), None), "three\nfour");
+        assert_eq!(slice_lines(content, None, Some(1)), "one\n");
+        assert_eq!(slice_lines(content, Some(9), Some(1)), "");
+        assert_eq!(slice_lines(content, Some(0), Some(0)), "");
+    }
+
+    #[test]
+    fn test_reject_permission_picks_reject_option() {
+        let options = vec![
+            option("allow", PermissionOptionKind::AllowOnce),
+            option("reject", PermissionOptionKind::RejectOnce),
+        ];
+        assert_eq!(
+            reject_permission(&options),
+            PermissionOutcome::Selected {
+                option_id: "reject".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_reject_permission_without_reject_option() {
+        let options = vec![option("allow", PermissionOptionKind::AllowAlways)];
+        assert_eq!(reject_permission(&options), PermissionOutcome::Cancelled);
+    }
+
+    #[test]
+    fn test_split_text_respects_char_boundaries() {
+        let text = "ab€cd";
+        let pieces = split_text(text, 3);
+        assert_eq!(pieces, vec!["ab", "€", "cd"]);
+        assert_eq!(pieces.concat(), text);
+        assert_eq!(split_text("short", 64), vec!["short"]);
+    }
+
+    /// The handlers the message loop passes to `handle_agent_request`.
+    struct Handlers {
+        terminals: Arc<Mutex<TerminalRegistry>>,
+        terminal_handler: Arc<RwLock<Box<dyn TerminalHandler>>>,
+        user: UserHandlers,
+        files: Arc<RwLock<Box<dyn FileSystemHandler>>>,
+        fs: FsConfig,
+    }
+
+    impl Handlers {
+        fn new(files: impl FileSystemHandler + 'static, terminals: impl TerminalHandler + 'static) -> Self {
+            Self {
+                terminals: Arc::new(Mutex::new(TerminalRegistry::default())),
+                terminal_handler: Arc::new(RwLock::new(Box::new(terminals))),
+                user: UserHandlers {
+                    permissions: Arc::new(RwLock::new(Box::new(RejectAllHandler))),
+                    sampling: Arc::new(RwLock::new(Box::new(NoSampling))),
+                },
+                files: Arc::new(RwLock::new(Box::new(files))),
+                fs: FsConfig::default(),
+            }
+        }
+
+        async fn call(&self, method: &str, params: Value) -> AcpResult<Value> {
+            Client::handle_agent_request(
+                method,
+                &params,
+                &self.terminals,
+                &self.terminal_handler,
+                &self.user,
+                &self.files,
+                &self.fs,
+            )
+            .await
+        }
+    }
+
+    /// Serves one unsaved buffer and falls back to disk for everything else.
+    struct Buffers;
+
+    #[async_trait]
+    impl FileSystemHandler for Buffers {
+        async fn read_text_file(&self, path: &str) -> AcpResult<String> {
+            match path {
+                "/work/unsaved.rs" => Ok("fn dirty() {}".to_string()),
+                _ => DiskFileSystem.read_text_file(path).await,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fs_requests_use_handler() {
+        let handlers = Handlers::new(Buffers, ProcessTerminals::new());
+        let read = |path: &str| handlers.call(method::FS_READ_TEXT_FILE, serde_json::json!({ "path": path }));
+
+        let result = read("/work/unsaved.rs").await.unwrap();
+        assert_eq!(result["content"], "fn dirty() {}");
+        let result = handlers
+            .call(
+                method::FS_READ_TEXT_FILE,
+                serde_json::json!({ "path": "/work/unsaved.rs", "line": 2 }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(result["content"], "");
+        let err = read("/work/missing-heroacp-file.rs").await.unwrap_err();
+        assert_eq!(err.code(), codes::RESOURCE_NOT_FOUND);
+        let err = read("work/unsaved.rs").await.unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_PARAMS);
+    }
+
+    /// Answers every sampling request with a fixed reply.
+    struct FixedModel;
+
+    #[async_trait]
+    impl SamplingHandler for FixedModel {
+        async fn create_message(
+            &self,
+            params: SessionSamplingRequestParams,
+        ) -> AcpResult<SessionSamplingRequestResult> {
+            Ok(SessionSamplingRequestResult {
+                role: Role::Assistant,
+                content: ContentBlock::text(format!("{} messages from {}", params.messages.len(), params.server)),
+                model: "fixed".to_string(),
+                stop_reason: None,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sampling_requests_use_handler() {
+        let mut handlers = Handlers::new(DiskFileSystem, ProcessTerminals::new());
+        let params = serde_json::json!({
+            "server": "db",
+            "messages": [{ "role": "user", "content": { "type": "text", "text": "hi" } }],
+            "max_tokens": 10,
+        });
+
+        let err = handlers.call(method::SESSION_SAMPLING_REQUEST, params.clone()).await.unwrap_err();
+        assert_eq!(err.code(), codes::CAPABILITY_NOT_SUPPORTED);
+
+        handlers.user.sampling = Arc::new(RwLock::new(Box::new(FixedModel)));
+        let result = handlers.call(method::SESSION_SAMPLING_REQUEST, params).await.unwrap();
+        assert_eq!(result["content"]["text"], "1 messages from db");
+        assert_eq!(result["role"], "assistant");
+    }
+
+    #[tokio::test]
+    async fn test_disk_read_rejects_non_utf8() {
+        let dir = std::env::temp_dir().join(format!("heroacp-encoding-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let latin1 = dir.join("latin1.txt");
+        let binary = dir.join("image.bin");
+        std::fs::write(&latin1, b"caf\xe9\n").unwrap();
+        std::fs::write(&binary, b"\x89PNG\r\n\x1a\n\0\0").unwrap();
+
+        let read = |path: std::path::PathBuf| async move {
+            DiskFileSystem.read_text_file(path.to_str().unwrap()).await.unwrap_err()
+        };
+        let err = read(latin1).await;
+        assert_eq!(err.code(), codes::INVALID_ENCODING);
+        assert!(err.message().contains("offset 3"));
+        let err = read(binary).await;
+        assert_eq!(err.code(), codes::INVALID_ENCODING);
+        assert!(err.message().contains("binary"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fs_requests_respect_sandbox() {
+        let mut handlers = Handlers::new(Buffers, ProcessTerminals::new());
+        handlers.fs.sandbox = PathSandbox::new().allow_root("/work").read_only(true);
+
+        let read = |path: &str| handlers.call(method::FS_READ_TEXT_FILE, serde_json::json!({ "path": path }));
+        assert!(read("/work/unsaved.rs").await.is_ok());
+        let err = read("/work/../etc/passwd").await.unwrap_err();
+        assert_eq!(err.code(), codes::PERMISSION_DENIED);
+
+        let err = handlers
+            .call(
+                method::FS_WRITE_TEXT_FILE,
+                serde_json::json!({ "path": "/work/unsaved.rs", "content": "" }),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), codes::PERMISSION_DENIED);
+    }
+
+    #[tokio::test]
+    async fn test_directory_listing_respects_sandbox() {
+        let dir = std::env::temp_dir().join(format!("heroacp-list-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(".env"), "TOKEN=secret").unwrap();
+        std::fs::write(dir.join("main.rs"), "").unwrap();
+        let mut handlers = Handlers::new(DiskFileSystem, ProcessTerminals::new());
+        handlers.fs.sandbox = PathSandbox::new().deny("**/.env");
+
+        let params = serde_json::json!({ "path": dir.to_str().unwrap() });
+        let result = handlers.call(method::FS_LIST_DIRECTORY, params).await.unwrap();
+        assert_eq!(result["entries"], serde_json::json!([{"name": "main.rs", "kind": "file", "size": 0}]));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_file_changes_respect_sandbox() {
+        let dir = std::env::temp_dir().join(format!("heroacp-manage-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut handlers = Handlers::new(DiskFileSystem, ProcessTerminals::new());
+        handlers.fs.sandbox = PathSandbox::new().allow_root(&dir);
+        let docs = dir.join("docs");
+        let guide = dir.join("guide");
+
+        let params = serde_json::json!({ "path": docs.to_str().unwrap() });
+        handlers.call(method::FS_CREATE_DIRECTORY, params).await.unwrap();
+        let params = serde_json::json!({ "from": docs.to_str().unwrap(), "to": guide.to_str().unwrap() });
+        handlers.call(method::FS_RENAME, params).await.unwrap();
+        assert!(guide.is_dir() && !docs.exists());
+
+        // Moving out of the sandbox is denied like writing there
+        let outside = std::env::temp_dir().join(format!("heroacp-outside-{}", std::process::id()));
+        let params = serde_json::json!({ "from": guide.to_str().unwrap(), "to": outside.to_str().unwrap() });
+        let err = handlers.call(method::FS_RENAME, params).await.unwrap_err();
+        assert_eq!(err.code(), codes::PERMISSION_DENIED);
+        let params = serde_json::json!({ "path": "guide" });
+        let err = handlers.call(method::FS_DELETE, params).await.unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_PARAMS);
+
+        handlers.fs.sandbox = PathSandbox::new().read_only(true);
+        let params = serde_json::json!({ "path": guide.to_str().unwrap() });
+        let err = handlers.call(method::FS_DELETE, params.clone()).await.unwrap_err();
+        assert_eq!(err.code(), codes::PERMISSION_DENIED);
+        handlers.fs.sandbox = PathSandbox::new();
+        handlers.call(method::FS_DELETE, params).await.unwrap();
+        assert!(!guide.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Answers permission requests with scripted choices and keeps them.
+    struct Approver {
+        choices: std::sync::Mutex<Vec<&'static str>>,
+        asked: Arc<std::sync::Mutex<Vec<RequestPermissionParams>>>,
+    }
+
+    #[async_trait]
+    impl PermissionHandler for Approver {
+        async fn request_permission(&self, params: RequestPermissionParams) -> PermissionOutcome {
+            self.asked.lock().unwrap().push(params);
+            PermissionOutcome::Selected {
+                option_id: self.choices.lock().unwrap().remove(0).to_string(),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_writes_wait_for_approval() {
+        let dir = std::env::temp_dir().join(format!("heroacp-approve-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.rs");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "old\n").unwrap();
+        let asked = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut handlers = Handlers::new(DiskFileSystem, ProcessTerminals::new());
+        handlers.user.permissions = Arc::new(RwLock::new(Box::new(Approver {
+            choices: std::sync::Mutex::new(vec!["reject_once", "allow_always", "allow_once"]),
+            asked: asked.clone(),
+        })));
+        handlers.fs.approvals = approval::WriteApprovals::new(true);
+        let write = |content: &str, session: &str| {
+            serde_json::json!({ "path": path, "content": content, "session_id": session })
+        };
+
+        let err = handlers.call(method::FS_WRITE_TEXT_FILE, write("new\n", "s1")).await.unwrap_err();
+        assert_eq!(err.code(), codes::PERMISSION_DENIED);
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "old\n");
+        let request = asked.lock().unwrap()[0].clone();
+        assert_eq!(request.session_id, "s1");
+        assert_eq!(request.tool_call.name, method::FS_WRITE_TEXT_FILE);
+        assert_eq!(
+            request.tool_call.arguments["diff"],
+            format!("--- {0}\n+++ {0}\n@@ -1,1 +1,1 @@\n-old\n+new\n", path)
+        );
+
+        // "Allow always" covers later writes in the same session only
+        handlers.call(method::FS_WRITE_TEXT_FILE, write("new\n", "s1")).await.unwrap();
+        handlers.call(method::FS_WRITE_TEXT_FILE, write("newer\n", "s1")).await.unwrap();
+        assert_eq!(asked.lock().unwrap().len(), 2);
+        let diff = "@@ -1 +1 @@\n-newer\n+newest\n";
+        let params = serde_json::json!({ "path": path, "unified_diff": diff, "session_id": "s2" });
+        handlers.call(method::FS_APPLY_EDIT, params).await.unwrap();
+        let request = asked.lock().unwrap()[2].clone();
+        assert_eq!(request.tool_call.arguments["diff"], diff);
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "newest\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_refused_session_cannot_change_files() {
+        let dir = std::env::temp_dir().join(format!("heroacp-approve-all-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.rs");
+        let path = path.to_str().unwrap();
+        let moved = dir.join("lib.rs");
+        std::fs::write(path, "old\n").unwrap();
+        let asked = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut handlers = Handlers::new(DiskFileSystem, ProcessTerminals::new());
+        handlers.user.permissions = Arc::new(RwLock::new(Box::new(Approver {
+            choices: std::sync::Mutex::new(vec!["reject_once"; 5]),
+            asked: asked.clone(),
+        })));
+        handlers.fs.approvals = approval::WriteApprovals::new(true);
+
+        let params = serde_json::json!({ "path": path, "content": "new\n", "session_id": "s1" });
+        let err = handlers.call(method::FS_WRITE_TEXT_FILE, params).await.unwrap_err();
+        assert_eq!(err.code(), codes::PERMISSION_DENIED);
+        // The same change through the other methods is asked about too
+        let params = serde_json::to_value(FsWriteFileParams {
+            session_id: Some("s1".to_string()),
+            ..FsWriteFileParams::new(path, b"new\n")
+        })
+        .unwrap();
+        let err = handlers.call(method::FS_WRITE_FILE, params).await.unwrap_err();
+        assert_eq!(err.code(), codes::PERMISSION_DENIED);
+        let binary = serde_json::to_value(FsWriteFileParams {
+            session_id: Some("s1".to_string()),
+            ..FsWriteFileParams::new(path, &[0xff, 0])
+        })
+        .unwrap();
+        let err = handlers.call(method::FS_WRITE_FILE, binary).await.unwrap_err();
+        assert_eq!(err.code(), codes::PERMISSION_DENIED);
+        let params = serde_json::json!({ "path": path, "session_id": "s1" });
+        let err = handlers.call(method::FS_DELETE, params).await.unwrap_err();
+        assert_eq!(err.code(), codes::PERMISSION_DENIED);
+        let params = serde_json::json!({ "from": path, "to": moved.to_str().unwrap(), "session_id": "s1" });
+        let err = handlers.call(method::FS_RENAME, params).await.unwrap_err();
+        assert_eq!(err.code(), codes::PERMISSION_DENIED);
+
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "old\n");
+        assert!(!moved.exists());
+        let asked = asked.lock().unwrap();
+        let names: Vec<_> = asked.iter().map(|request| request.tool_call.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [method::FS_WRITE_TEXT_FILE, method::FS_WRITE_FILE, method::FS_WRITE_FILE, method::FS_DELETE, method::FS_RENAME]
+        );
+        assert_eq!(asked[1].tool_call.arguments["diff"], asked[0].tool_call.arguments["diff"]);
+        assert_eq!(asked[2].tool_call.arguments["size"], 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_denied_delete_keeps_the_file() {
+        let dir = std::env::temp_dir().join(format!("heroacp-approve-delete-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.rs");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "fn main() {}\n").unwrap();
+        let asked = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut handlers = Handlers::new(DiskFileSystem, ProcessTerminals::new());
+        handlers.user.permissions = Arc::new(RwLock::new(Box::new(Approver {
+            choices: std::sync::Mutex::new(vec!["reject_once"]),
+            asked: asked.clone(),
+        })));
+        handlers.fs.approvals = approval::WriteApprovals::new(true);
+
+        let params = serde_json::json!({ "path": path, "session_id": "s1" });
+        let err = handlers.call(method::FS_DELETE, params).await.unwrap_err();
+        assert_eq!(err.code(), codes::PERMISSION_DENIED);
+        assert!(std::path::Path::new(path).exists());
+        let request = asked.lock().unwrap()[0].clone();
+        assert_eq!(request.tool_call.name, method::FS_DELETE);
+        assert_eq!(request.tool_call.arguments["path"], path);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_apply_edit_detects_conflicts() {
+        let dir = std::env::temp_dir().join(format!("heroacp-edit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notes.md");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "one\ntwo\nthree\n").unwrap();
+        let handlers = Handlers::new(DiskFileSystem, ProcessTerminals::new());
+        let base = content_hash("one\ntwo\nthree\n");
+
+        let diff = "@@ -2 +2 @@\n-two\n+2\n";
+        let params = serde_json::json!({ "path": path, "unified_diff": diff, "base_hash": base });
+        let result = handlers.call(method::FS_APPLY_EDIT, params.clone()).await.unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "one\n2\nthree\n");
+        assert_eq!(result["hash"], content_hash("one\n2\nthree\n"));
+
+        // The same edit again is stale, by hash and by content
+        let err = handlers.call(method::FS_APPLY_EDIT, params).await.unwrap_err();
+        let conflict = EditConflict::from_error(&err).unwrap();
+        assert_eq!(conflict.current_hash, result["hash"]);
+        assert_eq!(conflict.hunk, None);
+        let params = serde_json::json!({ "path": path, "unified_diff": diff });
+        let err = handlers.call(method::FS_APPLY_EDIT, params).await.unwrap_err();
+        assert_eq!(err.code(), codes::EDIT_CONFLICT);
+        assert_eq!(EditConflict::from_error(&err).unwrap().hunk, Some(1));
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "one\n2\nthree\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_binary_files_round_trip() {
+        let dir = std::env::temp_dir().join(format!("heroacp-binary-{}", std::process::id()));
+        let path = dir.join("assets/logo.png");
+        let path = path.to_str().unwrap();
+        let mut handlers = Handlers::new(DiskFileSystem, ProcessTerminals::new());
+        let bytes = [0x89, b'P', b'N', b'G', 0, 0xff];
+
+        let params = serde_json::to_value(FsWriteFileParams {
+            create_dirs: Some(true),
+            ..FsWriteFileParams::new(path, &bytes)
+        })
+        .unwrap();
+        handlers.call(method::FS_WRITE_FILE, params).await.unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), bytes);
+
+        let result = handlers.call(method::FS_READ_FILE, serde_json::json!({ "path": path })).await.unwrap();
+        let result: FsReadFileResult = serde_json::from_value(result).unwrap();
+        assert_eq!(result.mime_type, "image/png");
+        assert_eq!(result.bytes().unwrap(), bytes);
+
+        // The text method still refuses the file
+        let err = handlers.call(method::FS_READ_TEXT_FILE, serde_json::json!({ "path": path })).await.unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_ENCODING);
+        let params = serde_json::json!({ "path": path, "data": "not base64!" });
+        let err = handlers.call(method::FS_WRITE_FILE, params).await.unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_ENCODING);
+
+        handlers.fs.sandbox = PathSandbox::new().read_only(true);
+        let params = serde_json::to_value(FsWriteFileParams::new(path, b"")).unwrap();
+        let err = handlers.call(method::FS_WRITE_FILE, params).await.unwrap_err();
+        assert_eq!(err.code(), codes::PERMISSION_DENIED);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_stream() {
+        let dir = std::env::temp_dir().join(format!("heroacp-stream-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("build.log");
+        std::fs::write(&path, "line one\nline two\n").unwrap();
+        let path = path.to_str().unwrap();
+        let mut handlers = Handlers::new(DiskFileSystem, ProcessTerminals::new());
+
+        let params = serde_json::json!({ "path": path, "chunk_size": 8 });
+        let result = handlers.call(method::FS_READ_STREAM_START, params).await.unwrap();
+        let started: FsReadStreamStartResult = serde_json::from_value(result).unwrap();
+        assert_eq!(started.size, Some(18));
+        assert_eq!(started.chunk_size, 8);
+        assert_eq!(started.mime_type, "text/plain");
+
+        let mut read = Vec::new();
+        let mut chunks = 0;
+        loop {
+            let params = serde_json::json!({ "stream_id": started.stream_id });
+            let result = handlers.call(method::FS_READ_CHUNK, params).await.unwrap();
+            let chunk: FsReadChunkResult = serde_json::from_value(result).unwrap();
+            read.extend(chunk.bytes().unwrap());
+            chunks += 1;
+            if chunk.eof {
+                break;
+            }
+        }
+        assert_eq!(read, b"line one\nline two\n");
+        assert_eq!(chunks, 3);
+
+        let params = serde_json::json!({ "stream_id": started.stream_id });
+        let err = handlers.call(method::FS_READ_STREAM_CLOSE, params).await.unwrap_err();
+        assert_eq!(err.code(), codes::RESOURCE_NOT_FOUND);
+
+        handlers.fs.sandbox = PathSandbox::new().deny("**/*.log");
+        let params = serde_json::json!({ "path": path });
+        let err = handlers.call(method::FS_READ_STREAM_START, params).await.unwrap_err();
+        assert_eq!(err.code(), codes::PERMISSION_DENIED);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_disk_writes_create_dirs_atomically() {
+        let dir = std::env::temp_dir().join(format!("heroacp-write-{}", std::process::id()));
+        let path = dir.join("src/nested/out.txt");
+        let path = path.to_str().unwrap();
+        let mut handlers = Handlers::new(DiskFileSystem, ProcessTerminals::new());
+
+        let params = serde_json::json!({ "path": path, "content": "a" });
+        let err = handlers.call(method::FS_WRITE_TEXT_FILE, params).await.unwrap_err();
+        assert_eq!(err.code(), codes::PERMISSION_DENIED);
+
+        let params = serde_json::json!({ "path": path, "content": "a", "create_dirs": true, "atomic": true });
+        handlers.call(method::FS_WRITE_TEXT_FILE, params).await.unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "a");
+
+        // The client's defaults apply when the agent doesn't say
+        handlers.fs.write = WriteOptions::new().create_dirs(true).atomic(true);
+        let other = dir.join("docs/readme.md");
+        let params = serde_json::json!({ "path": other.to_str().unwrap(), "content": "b" });
+        handlers.call(method::FS_WRITE_TEXT_FILE, params).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&other).unwrap(), "b");
+        let params = serde_json::json!({ "path": path, "content": "c" });
+        handlers.call(method::FS_WRITE_TEXT_FILE, params).await.unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "c");
+
+        // No temporary files are left behind
+        let leftovers = std::fs::read_dir(dir.join("src/nested")).unwrap().count();
+        assert_eq!(leftovers, 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A terminal backend whose commands finish instantly with canned output.
+    struct Canned;
+
+    #[async_trait]
+    impl TerminalHandler for Canned {
+        async fn create(&self, params: TerminalCreateParams) -> AcpResult<String> {
+            Ok(format!("remote:{}", params.command))
+        }
+
+        async fn output(&self, terminal_id: &str) -> AcpResult<TerminalOutputResult> {
+            Ok(TerminalOutputResult {
+                output: format!("ran {}", terminal_id),
+                stderr: String::new(),
+                exited: true,
+                exit_code: Some(0),
+                truncated: false,
+                total_bytes: 0,
+            })
+        }
+
+        async fn kill(&self, _terminal_id: &str) -> AcpResult<()> {
+            Ok(())
+        }
+
+        async fn release(&self, _terminal_id: &str) -> AcpResult<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_terminal_output_is_captured() {
+        let handlers = Handlers::new(DiskFileSystem, ProcessTerminals::new());
+
+        // More output than fits in a pipe, which blocks the command unless read
+        let created = handlers
+            .call(
+                method::TERMINAL_CREATE,
+                serde_json::json!({"cwd": "/", "command": "echo out; echo err >&2; yes | head -c 200000 >&2"}),
+            )
+            .await
+            .unwrap();
+        let id = serde_json::json!({"terminal_id": created["terminal_id"]});
+        let exited = handlers.call(method::TERMINAL_WAIT_FOR_EXIT, id.clone()).await.unwrap();
+        assert_eq!(exited["exit_code"], 0);
+        assert_eq!(exited["total_bytes"], 200_008);
+        // The two pipes are read separately, so only each one's order is kept
+        assert!(exited["output"].as_str().unwrap().contains("out\n"));
+        assert!(exited["stderr"].as_str().unwrap().starts_with("err\ny\n"));
+
+        let output = handlers.call(method::TERMINAL_OUTPUT, id).await.unwrap();
+        assert_eq!(output["exited"], true);
+        assert_eq!(output["output"], exited["output"]);
+    }
+
+    #[tokio::test]
+    async fn test_terminal_requests_use_handler() {
+        let handlers = Handlers::new(DiskFileSystem, Canned);
+
+        let created = handlers
+            .call(method::TERMINAL_CREATE, serde_json::json!({"cwd": "/", "command": "make"}))
+            .await
+            .unwrap();
+        assert_eq!(created["terminal_id"], "remote:make");
+        assert!(handlers.terminals.lock().await.attach_tool_call("tc1", "remote:make"));
+
+        let id = serde_json::json!({"terminal_id": "remote:make"});
+        let exited = handlers.call(method::TERMINAL_WAIT_FOR_EXIT, id.clone()).await.unwrap();
+        assert_eq!(exited["output"], "ran remote:make");
+        assert_eq!(exited["exit_code"], 0);
+
+        handlers.call(method::TERMINAL_RELEASE, id).await.unwrap();
+        assert_eq!(handlers.terminals.lock().await.tool_call_terminal("tc1"), None);
+        assert!(!handlers.terminals.lock().await.attach_tool_call("tc2", "remote:make"));
+    }
+
+    /// A terminal backend whose commands never exit.
+    struct Hung {
+        polls: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl TerminalHandler for Hung {
+        async fn create(&self, _params: TerminalCreateParams) -> AcpResult<String> {
+            Ok("hung".to_string())
+        }
+
+        async fn output(&self, _terminal_id: &str) -> AcpResult<TerminalOutputResult> {
+            self.polls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(TerminalOutputResult {
+                output: String::new(),
+                stderr: String::new(),
+                exited: false,
+                exit_code: None,
+                truncated: false,
+                total_bytes: 0,
+            })
+        }
+
+        async fn kill(&self, _terminal_id: &str) -> AcpResult<()> {
+            Ok(())
+        }
+
+        async fn release(&self, _terminal_id: &str) -> AcpResult<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_terminal_wait_times_out() {
+        let polls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let handlers = Handlers::new(DiskFileSystem, Hung { polls: polls.clone() });
+
+        let started = tokio::time::Instant::now();
+        let err = handlers
+            .call(method::TERMINAL_WAIT_FOR_EXIT, serde_json::json!({"terminal_id": "hung"}))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AcpError::Timeout));
+        assert_eq!(started.elapsed(), WAIT_FOR_EXIT_TIMEOUT);
+        // Polled right away, then every 100ms for the whole five minutes
+        assert_eq!(polls.load(std::sync::atomic::Ordering::SeqCst), 3001);
+    }
+
+    #[tokio::test]
+    async fn test_notify_sends_notification() {
+        let mut client = Client::builder("sh")
+            .args(["-c", r#"read line; printf '%s' "$line" >&2"#])
+            .stderr(StderrMode::Capture)
+            .spawn()
+            .await
+            .unwrap();
+        client
+            .notify("vendor/ping", serde_json::json!({"n": 1}))
+            .await
+            .unwrap();
+
+        let mut line = String::new();
+        tokio::io::AsyncReadExt::read_to_string(&mut client.take_stderr().unwrap(), &mut line)
+            .await
+            .unwrap();
+        let sent: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(sent, serde_json::json!({"jsonrpc": "2.0", "method": "vendor/ping", "params": {"n": 1}}));
+    }
+
+    #[tokio::test]
+    async fn test_agent_errors_keep_code_and_data() {
+        let agent = r#"
+            read first
+            echo '{"jsonrpc":"2.0","id":1,"error":{"code":-32002,"message":"Permission denied: /etc/shadow"}}'
+            read second
+            echo '{"jsonrpc":"2.0","id":2,"error":{"code":-32050,"message":"Quota exceeded","data":{"retry_after":30}}}'
+            cat > /dev/null
+        "#;
+        let client = Client::builder("sh").args(["-c", agent]).spawn().await.unwrap();
+
+        let err = client.ping().await.unwrap_err();
+        assert!(matches!(&err, AcpError::PermissionDenied(path) if path == "/etc/shadow"));
+
+        let err = client.ping().await.unwrap_err();
+        assert_eq!(err.code(), -32050);
+        assert_eq!(err.data().unwrap()["retry_after"], 30);
+    }
+
+    #[tokio::test]
+    async fn test_tolerates_agent_quirks() {
+        let agent = r#"
+            read first
+            echo '{"jsonrpc":"2.0","id":null,"method":"session/update","params":{"session_id":"s1","type":"done"}}'
+            echo '{"jsonrpc":"2.0","id":"1","result":{}}'
+            read second
+            echo '{"jsonrpc":"2.0","id":2,"error":{"code":-32050}}'
+            cat > /dev/null
+        "#;
+        let client = Client::builder("sh").args(["-c", agent]).spawn().await.unwrap();
+        let mut events = client.subscribe();
+
+        // The ID comes back as a string and the update carries a null ID
+        client.ping().await.unwrap();
+        let event = timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap();
+        assert!(matches!(event, ClientEvent::SessionUpdate(SessionUpdate { update_type: SessionUpdateType::Done, .. })));
+
+        // An error without a message is still an error
+        let err = client.ping().await.unwrap_err();
+        assert_eq!(err.code(), -32050);
+    }
+
+    /// An agent that logs, sends an update and a notification, then exits.
+    const CHATTY_AGENT: &str = r#"
+        read go
+        echo 'starting' >&2
+        sleep 0.2
+        echo '{"jsonrpc":"2.0","method":"session/update","params":{"session_id":"s1","type":"done"}}'
+        echo '{"jsonrpc":"2.0","method":"vendor/indexed","params":{"files":3}}'
+    "#;
+
+    #[tokio::test]
+    async fn test_subscribers_get_every_event() {
+        let client = Client::builder("sh")
+            .args(["-c", CHATTY_AGENT])
+            .stderr(StderrMode::Log)
+            .spawn()
+            .await
+            .unwrap();
+        let mut first = client.subscribe();
+        let mut second = client.subscribe();
+        client.notify("go", Value::Null).await.unwrap();
+
+        for events in [&mut first, &mut second] {
+            let mut received = Vec::new();
+            loop {
+                let event = timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap();
+                if matches!(event, ClientEvent::Disconnected) {
+                    break;
+                }
+                received.push(event);
+            }
+            assert!(received.iter().any(|e| matches!(e, ClientEvent::AgentLog(line) if line == "starting")));
+            received.retain(|e| !matches!(e, ClientEvent::AgentLog(_)));
+            assert!(matches!(
+                &received[..],
+                [
+                    ClientEvent::SessionUpdate(SessionUpdate { update_type: SessionUpdateType::Done, .. }),
+                    ClientEvent::Notification { method, params },
+                ] if method == "vendor/indexed" && params["files"] == 3
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unknown_updates_reach_handler() {
+        let agent = r#"
+            read go
+            echo '{"jsonrpc":"2.0","method":"session/update","params":{"session_id":"s1","type":"usage","data":{"tokens":42}}}'
+            echo '{"jsonrpc":"2.0","method":"session/update","params":{"session_id":"s1","type":"done"}}'
+            cat > /dev/null
+        "#;
+        let client = Client::builder("sh").args(["-c", agent]).spawn().await.unwrap();
+        let updates = UpdateCollector::new();
+        client.set_update_handler(Box::new(updates.clone())).await;
+        client.notify("go", Value::Null).await.unwrap();
+
+        // `done` comes after the unknown update, so that was handled by then
+        updates.await_done(Duration::from_secs(5)).await.unwrap();
+        assert!(matches!(
+            &updates.events()[0].event,
+            UpdateEvent::UnknownUpdate { session_id, update_type, data }
+                if session_id == "s1" && update_type == "usage" && data["tokens"] == 42
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_kills_unresponsive_agent() {
+        let mut client = Client::builder("sh")
+            .args(["-c", "trap '' TERM; while true; do sleep 1; done"])
+            .spawn()
+            .await
+            .unwrap();
+        client.shutdown(Duration::from_millis(100)).await.unwrap();
+        assert!(!client.is_running());
+    }
+}
@@ -0,0 +1,93 @@
+//! Deleting, renaming and creating paths on the local disk.
+
+use std::io::ErrorKind;
+
+use super::rt::fs;
+use crate::protocol::*;
+
+/// Delete the file, symlink or directory at `path`. Directories that aren't
+/// empty are only deleted if `recursive` is set.
+pub(super) async fn delete(path: &str, recursive: bool) -> AcpResult<()> {
+    let metadata = fs::symlink_metadata(path)
+        .await
+        .map_err(|e| disk_error(path, e))?;
+    let result = if !metadata.is_dir() {
+        fs::remove_file(path).await
+    } else if recursive {
+        fs::remove_dir_all(path).await
+    } else {
+        fs::remove_dir(path).await
+    };
+    result.map_err(|e| disk_error(path, e))
+}
+
+/// Move `from` to `to`, replacing an existing `to` only if `overwrite` is
+/// set.
+pub(super) async fn rename(from: &str, to: &str, overwrite: bool) -> AcpResult<()> {
+    fs::symlink_metadata(from)
+        .await
+        .map_err(|e| disk_error(from, e))?;
+    if !overwrite && fs::symlink_metadata(to).await.is_ok() {
+        return Err(AcpError::InvalidState(format!("{} already exists", to)));
+    }
+    fs::rename(from, to).await.map_err(|e| disk_error(to, e))
+}
+
+/// Create the directory at `path`, and its missing parents if `recursive`
+/// is set. An existing directory is only accepted with `recursive`.
+pub(super) async fn create_directory(path: &str, recursive: bool) -> AcpResult<()> {
+    let result = if recursive {
+        fs::create_dir_all(path).await
+    } else {
+        fs::create_dir(path).await
+    };
+    result.map_err(|e| disk_error(path, e))
+}
+
+fn disk_error(path: &str, e: std::io::Error) -> AcpError {
+    match e.kind() {
+        ErrorKind::NotFound => AcpError::ResourceNotFound(format!("{}: {}", path, e)),
+        ErrorKind::AlreadyExists | ErrorKind::DirectoryNotEmpty => {
+            AcpError::InvalidState(format!("{}: {}", path, e))
+        }
+        _ => AcpError::PermissionDenied(format!("{}: {}", path, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_delete_rename_and_create() {
+        let dir = std::env::temp_dir().join(format!("heroacp-operations-{}", std::process::id()));
+        let nested = dir.join("src/old");
+        let nested = nested.to_str().unwrap();
+
+        let err = create_directory(nested, false).await.unwrap_err();
+        assert_eq!(err.code(), codes::RESOURCE_NOT_FOUND);
+        create_directory(nested, true).await.unwrap();
+        create_directory(nested, true).await.unwrap();
+        let err = create_directory(nested, false).await.unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_STATE);
+
+        let file = dir.join("src/old/lib.rs");
+        std::fs::write(&file, "").unwrap();
+        let renamed = dir.join("src/new");
+        let renamed = renamed.to_str().unwrap();
+        rename(nested, renamed, false).await.unwrap();
+        assert!(dir.join("src/new/lib.rs").exists());
+        std::fs::create_dir(nested).unwrap();
+        let err = rename(renamed, nested, false).await.unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_STATE);
+
+        let err = delete(renamed, false).await.unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_STATE);
+        delete(dir.join("src/new/lib.rs").to_str().unwrap(), false).await.unwrap();
+        delete(renamed, false).await.unwrap();
+        delete(dir.to_str().unwrap(), true).await.unwrap();
+        assert!(!dir.exists());
+        let err = delete(dir.to_str().unwrap(), true).await.unwrap_err();
+        assert_eq!(err.code(), codes::RESOURCE_NOT_FOUND);
+    }
+}
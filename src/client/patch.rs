@@ -0,0 +1,373 @@
+//! Making unified diffs and applying them to text.
+
+use crate::protocol::*;
+
+/// Why a diff couldn't be applied.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum PatchError {
+    /// The diff isn't a unified diff.
+    Malformed(String),
+    /// The lines hunk `n` (counting from 1) replaces aren't in the text.
+    Mismatch(u32),
+}
+
+impl PatchError {
+    /// The error for failing to apply a diff to the file at `path`, whose
+    /// [`content_hash`] is `current_hash`.
+    pub(crate) fn into_error(self, path: &str, current_hash: String) -> AcpError {
+        match self {
+            PatchError::Malformed(reason) => AcpError::InvalidParams(format!("invalid diff: {}", reason)),
+            PatchError::Mismatch(hunk) => EditConflict {
+                current_hash,
+                hunk: Some(hunk),
+            }
+            .into_error(format!("hunk {} does not match {}", hunk, path)),
+        }
+    }
+}
+
+/// Where a hunk applies: the lines `at..at + old_len` of the text are
+/// replaced by `new`.
+pub(crate) struct Located {
+    /// First replaced line, counting from 0.
+    pub(crate) at: usize,
+    /// Number of replaced lines.
+    pub(crate) old_len: usize,
+    /// Replacement text, with the text's line endings.
+    pub(crate) new: String,
+}
+
+struct Hunk<'a> {
+    /// Line the hunk starts at in the original text, counting from 0.
+    start: usize,
+    /// Lines the hunk expects, without line endings.
+    old: Vec<&'a str>,
+    /// Lines the hunk puts in their place, without line endings.
+    new: Vec<&'a str>,
+    /// Whether the last new line has no line ending.
+    no_newline: bool,
+}
+
+/// Apply `diff` to `text`.
+pub(crate) fn apply(text: &str, diff: &str) -> Result<String, PatchError> {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let mut out = String::with_capacity(text.len());
+    let mut next = 0;
+    for located in locate(text, diff)? {
+        out.extend(lines[next..located.at].iter().copied());
+        out.push_str(&located.new);
+        next = located.at + located.old_len;
+    }
+    out.extend(lines[next..].iter().copied());
+    Ok(out)
+}
+
+/// Find where each hunk of `diff` applies to `text`, in order.
+///
+/// A hunk whose lines have moved since the diff was made is applied where
+/// they are now, as `patch` does, as long as they are still in the text
+/// after the previous hunk.
+pub(crate) fn locate(text: &str, diff: &str) -> Result<Vec<Located>, PatchError> {
+    let hunks = parse(diff)?;
+    let newline = if text.contains("\r\n") { "\r\n" } else { "\n" };
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let matches = |at: usize, old: &[&str]| {
+        at + old.len() <= lines.len()
+            && old
+                .iter()
+                .zip(&lines[at..])
+                .all(|(want, line)| *want == line.trim_end_matches(['\r', '\n']))
+    };
+
+    let mut located = Vec::with_capacity(hunks.len());
+    let mut next = 0;
+    for (index, hunk) in hunks.iter().enumerate() {
+        let at = if hunk.start >= next && matches(hunk.start, &hunk.old) {
+            hunk.start
+        } else {
+            (next..=lines.len())
+                .find(|&at| matches(at, &hunk.old))
+                .ok_or(PatchError::Mismatch(index as u32 + 1))?
+        };
+        let mut new = String::new();
+        for (i, line) in hunk.new.iter().enumerate() {
+            new.push_str(line);
+            if !(hunk.no_newline && i + 1 == hunk.new.len()) {
+                new.push_str(newline);
+            }
+        }
+        located.push(Located {
+            at,
+            old_len: hunk.old.len(),
+            new,
+        });
+        next = at + hunk.old.len();
+    }
+    Ok(located)
+}
+
+fn parse(diff: &str) -> Result<Vec<Hunk<'_>>, PatchError> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    // Lines still to come in the current hunk, from its header
+    let (mut old_left, mut new_left) = (0, 0);
+    let mut last_sign = ' ';
+    for line in diff.lines() {
+        if line.starts_with('\\') {
+            // `\ No newline at end of file`, after the line it is about
+            if let Some(hunk) = hunks.last_mut().filter(|_| last_sign != '-') {
+                hunk.no_newline = true;
+            }
+            continue;
+        }
+        if old_left == 0 && new_left == 0 {
+            // File headers and anything else between hunks
+            if let Some(header) = line.strip_prefix("@@ ") {
+                let (start, old_len, new_len) = parse_header(header)
+                    .ok_or_else(|| PatchError::Malformed(format!("bad hunk header: {}", line)))?;
+                hunks.push(Hunk {
+                    start,
+                    old: Vec::new(),
+                    new: Vec::new(),
+                    no_newline: false,
+                });
+                (old_left, new_left) = (old_len, new_len);
+            }
+            continue;
+        }
+        let hunk = hunks.last_mut().expect("a header came first");
+        let (sign, rest) = line.split_at(line.len().min(1));
+        match sign {
+            // Some tools strip the space of empty context lines
+            " " | "" if old_left > 0 && new_left > 0 => {
+                hunk.old.push(rest);
+                hunk.new.push(rest);
+                old_left -= 1;
+                new_left -= 1;
+            }
+            "-" if old_left > 0 => {
+                hunk.old.push(rest);
+                old_left -= 1;
+            }
+            "+" if new_left > 0 => {
+                hunk.new.push(rest);
+                new_left -= 1;
+            }
+            _ => return Err(PatchError::Malformed(format!("unexpected line in hunk: {}", line))),
+        }
+        last_sign = sign.chars().next().unwrap_or(' ');
+    }
+    if old_left > 0 || new_left > 0 {
+        return Err(PatchError::Malformed("last hunk is cut short".to_string()));
+    }
+    if hunks.is_empty() {
+        return Err(PatchError::Malformed("no hunks".to_string()));
+    }
+    Ok(hunks)
+}
+
+/// The original start line, counting from 0, and the old and new lengths
+/// of a hunk from its header after `@@ `, e.g. `-12,7 +12,9 @@`.
+fn parse_header(header: &str) -> Option<(usize, usize, usize)> {
+    let mut ranges = header.split_whitespace();
+    let old = ranges.next()?.strip_prefix('-')?;
+    let new = ranges.next()?.strip_prefix('+')?;
+    let range = |range: &str| -> Option<(usize, usize)> {
+        match range.split_once(',') {
+            Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    let (start, old_len) = range(old)?;
+    let (_, new_len) = range(new)?;
+    // `-0,0` adds to an empty file; otherwise lines count from 1
+    Some((start.saturating_sub(1), old_len, new_len))
+}
+
+/// Lines of context around each change in [`unified_diff`].
+const CONTEXT: usize = 3;
+
+/// Above this many line pairs the changed middle of two texts is shown as
+/// replaced wholesale instead of being compared line by line.
+const MAX_COMPARED_PAIRS: usize = 4_000_000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// A unified diff from `old` to `new`, with `path` in the file headers.
+/// Empty if the texts are equal.
+pub(crate) fn unified_diff(path: &str, old: &str, new: &str) -> String {
+    let a: Vec<&str> = old.split_inclusive('\n').collect();
+    let b: Vec<&str> = new.split_inclusive('\n').collect();
+    let ops = edit_script(&a, &b);
+    let changes: Vec<usize> = (0..ops.len()).filter(|&i| ops[i] != Op::Equal).collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    // Where each op starts in `a` and `b`
+    let mut starts = Vec::with_capacity(ops.len() + 1);
+    let (mut i, mut j) = (0, 0);
+    for op in &ops {
+        starts.push((i, j));
+        match op {
+            Op::Equal => (i, j) = (i + 1, j + 1),
+            Op::Delete => i += 1,
+            Op::Insert => j += 1,
+        }
+    }
+    starts.push((i, j));
+
+    let mut out = format!("--- {}\n+++ {}\n", path, path);
+    let mut group_start = 0;
+    while group_start < changes.len() {
+        // Changes closer than twice the context share a hunk
+        let mut group_end = group_start;
+        while group_end + 1 < changes.len() && changes[group_end + 1] - changes[group_end] <= 2 * CONTEXT + 1 {
+            group_end += 1;
+        }
+        let first = changes[group_start].saturating_sub(CONTEXT);
+        let last = (changes[group_end] + 1 + CONTEXT).min(ops.len());
+        let ((old_start, new_start), (old_end, new_end)) = (starts[first], starts[last]);
+        let range = |start: usize, len: usize| if len == 0 { start } else { start + 1 };
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            range(old_start, old_end - old_start),
+            old_end - old_start,
+            range(new_start, new_end - new_start),
+            new_end - new_start
+        ));
+        for k in first..last {
+            let (i, j) = starts[k];
+            let (sign, line) = match ops[k] {
+                Op::Equal => (' ', a[i]),
+                Op::Delete => ('-', a[i]),
+                Op::Insert => ('+', b[j]),
+            };
+            out.push(sign);
+            out.push_str(line.trim_end_matches(['\r', '\n']));
+            out.push('\n');
+            if !line.ends_with('\n') {
+                out.push_str("\\ No newline at end of file\n");
+            }
+        }
+        group_start = group_end + 1;
+    }
+    out
+}
+
+/// The shortest way to turn the lines `a` into `b`, as far as comparing them
+/// stays cheap.
+fn edit_script(a: &[&str], b: &[&str]) -> Vec<Op> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let mut ops = vec![Op::Equal; prefix];
+    if a_mid.len() * b_mid.len() > MAX_COMPARED_PAIRS {
+        ops.extend(std::iter::repeat_n(Op::Delete, a_mid.len()));
+        ops.extend(std::iter::repeat_n(Op::Insert, b_mid.len()));
+    } else {
+        // Longest common subsequence of every pair of suffixes
+        let (n, m) = (a_mid.len(), b_mid.len());
+        let mut lcs = vec![0u32; (n + 1) * (m + 1)];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[i * (m + 1) + j] = if a_mid[i] == b_mid[j] {
+                    lcs[(i + 1) * (m + 1) + j + 1] + 1
+                } else {
+                    lcs[(i + 1) * (m + 1) + j].max(lcs[i * (m + 1) + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < n || j < m {
+            if i < n && j < m && a_mid[i] == b_mid[j] {
+                ops.push(Op::Equal);
+                (i, j) = (i + 1, j + 1);
+            } else if j == m || (i < n && lcs[(i + 1) * (m + 1) + j] >= lcs[i * (m + 1) + j + 1]) {
+                ops.push(Op::Delete);
+                i += 1;
+            } else {
+                ops.push(Op::Insert);
+                j += 1;
+            }
+        }
+    }
+    ops.extend(std::iter::repeat_n(Op::Equal, suffix));
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "fn main() {\n    let x = 1;\n    println!(\"{}\", x);\n}\n";
+
+    #[test]
+    fn test_apply() {
+        let diff = "--- a/src/main.rs\n+++ b/src/main.rs\n@@ -1,4 +1,5 @@\n fn main() {\n-    let x = 1;\n+    let x = 2;\n+    let y = 3;\n     println!(\"{}\", x);\n }\n";
+        assert_eq!(
+            apply(TEXT, diff).unwrap(),
+            "fn main() {\n    let x = 2;\n    let y = 3;\n    println!(\"{}\", x);\n}\n"
+        );
+
+        // Lines that moved are still found
+        let shifted = format!("// header\n\n{}", TEXT);
+        let diff = "@@ -2,1 +2,1 @@\n-    let x = 1;\n+    let x = 5;\n";
+        assert!(apply(&shifted, diff).unwrap().contains("let x = 5;"));
+    }
+
+    #[test]
+    fn test_apply_keeps_line_endings() {
+        let diff = "@@ -4 +4,2 @@\n }\n+// end\n\\ No newline at end of file\n";
+        assert_eq!(apply(TEXT, diff).unwrap(), format!("{}// end", TEXT));
+
+        let crlf = TEXT.replace('\n', "\r\n");
+        let diff = "@@ -2 +2 @@\n-    let x = 1;\n+    let x = 9;\n";
+        assert_eq!(apply(&crlf, diff).unwrap(), crlf.replace("x = 1", "x = 9"));
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let new = TEXT.replace("let x = 1;", "let x = 2;");
+        assert_eq!(
+            unified_diff("src/main.rs", TEXT, &new),
+            "--- src/main.rs\n+++ src/main.rs\n@@ -1,4 +1,4 @@\n fn main() {\n-    let x = 1;\n+    let x = 2;\n     println!(\"{}\", x);\n }\n"
+        );
+        assert_eq!(unified_diff("a", TEXT, TEXT), "");
+
+        // Whatever the change, the diff turns one text into the other
+        let long: String = (1..=40).map(|n| format!("line {}\n", n)).collect();
+        let edited = long.replace("line 3\n", "").replace("line 30\n", "line thirty\n");
+        let cases = [
+            ("", "new file\n"),
+            (TEXT, ""),
+            ("a\nb", "a\nc"),
+            ("a\nb\n", "a\nb"),
+            (long.as_str(), edited.as_str()),
+        ];
+        for (old, new) in cases {
+            let diff = unified_diff("f", old, new);
+            assert_eq!(apply(old, &diff).unwrap(), new, "diff:\n{}", diff);
+        }
+        assert_eq!(unified_diff("f", &long, &edited).matches("@@ -").count(), 2);
+    }
+
+    #[test]
+    fn test_apply_conflicts() {
+        let diff = "@@ -1,2 +1,2 @@\n fn main() {\n-    let x = 7;\n+    let x = 8;\n";
+        assert_eq!(apply(TEXT, diff), Err(PatchError::Mismatch(1)));
+        assert!(matches!(apply(TEXT, "not a diff"), Err(PatchError::Malformed(_))));
+        let cut_short = "@@ -1,2 +1,2 @@\n fn main() {\n";
+        assert!(matches!(apply(TEXT, cut_short), Err(PatchError::Malformed(_))));
+    }
+}
@@ -0,0 +1,194 @@
+//! Several agents behind one client process.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tokio::time::Duration;
+
+use super::{Client, ClientBuilder, PromptStream};
+use crate::protocol::*;
+
+/// A set of named agents, with prompts routed to the agent that owns the
+/// session.
+///
+/// ```rust,no_run
+/// # use heroacp::client::{AgentPool, Client};
+/// # use heroacp::protocol::*;
+/// # async fn demo(init: InitializeParams) -> AcpResult<()> {
+/// let pool = AgentPool::new();
+/// pool.add("goose", Client::builder("goose").arg("acp"), init.clone()).await?;
+/// pool.add("local", Client::builder("./my-agent"), init).await?;
+///
+/// pool.session_new("local", SessionNewParams {
+///     session_id: "s1".to_string(),
+///     mode: None,
+///     agent: None,
+/// }).await?;
+/// // Goes to "local"
+/// pool.session_prompt(SessionPromptParams {
+///     session_id: "s1".to_string(),
+///     content: vec![ContentBlock::text("Hello")],
+/// }).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default)]
+pub struct AgentPool {
+    agents: RwLock<HashMap<String, Arc<Client>>>,
+    /// Owning agent of each session.
+    sessions: RwLock<HashMap<String, String>>,
+}
+
+impl AgentPool {
+    /// Create an empty pool.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spawn an agent, initialize it and add it as `name`.
+    pub async fn add(
+        &self,
+        name: impl Into<String>,
+        builder: ClientBuilder,
+        params: InitializeParams,
+    ) -> AcpResult<InitializeResult> {
+        let name = name.into();
+        if self.agent(&name).is_some() {
+            return Err(AcpError::InvalidState(format!("agent {} is already in the pool", name)));
+        }
+        let client = builder.spawn().await?;
+        let result = client.initialize(params).await?;
+        self.insert(name, client)?;
+        Ok(result)
+    }
+
+    /// Add an agent that is already initialized.
+    pub fn insert(&self, name: impl Into<String>, client: Client) -> AcpResult<()> {
+        let name = name.into();
+        let mut agents = self.agents.write().unwrap();
+        if agents.contains_key(&name) {
+            return Err(AcpError::InvalidState(format!("agent {} is already in the pool", name)));
+        }
+        agents.insert(name, Arc::new(client));
+        Ok(())
+    }
+
+    /// Remove an agent and forget its sessions.
+    pub fn remove(&self, name: &str) -> Option<Arc<Client>> {
+        let client = self.agents.write().unwrap().remove(name)?;
+        self.sessions.write().unwrap().retain(|_, agent| agent != name);
+        Some(client)
+    }
+
+    /// Get an agent by name, e.g. to set its handlers.
+    pub fn agent(&self, name: &str) -> Option<Arc<Client>> {
+        self.agents.read().unwrap().get(name).cloned()
+    }
+
+    /// Names of the agents in the pool, sorted.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.agents.read().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Name of the agent that owns `session_id`.
+    pub fn session_agent(&self, session_id: &str) -> Option<String> {
+        self.sessions.read().unwrap().get(session_id).cloned()
+    }
+
+    fn named(&self, name: &str) -> AcpResult<Arc<Client>> {
+        self.agent(name)
+            .ok_or_else(|| AcpError::ResourceNotFound(format!("agent {}", name)))
+    }
+
+    fn owner(&self, session_id: &str) -> AcpResult<Arc<Client>> {
+        let name = self
+            .session_agent(session_id)
+            .ok_or_else(|| AcpError::ResourceNotFound(format!("session {}", session_id)))?;
+        self.named(&name)
+    }
+
+    fn claim(&self, agent: &str, session_id: &str) {
+        self.sessions
+            .write()
+            .unwrap()
+            .insert(session_id.to_string(), agent.to_string());
+    }
+
+    /// Create a session on the agent named `agent`.
+    pub async fn session_new(&self, agent: &str, params: SessionNewParams) -> AcpResult<SessionNewResult> {
+        let result = self.named(agent)?.session_new(params).await?;
+        self.claim(agent, &result.session_id);
+        Ok(result)
+    }
+
+    /// Load a session on the agent named `agent`.
+    pub async fn session_load(&self, agent: &str, params: SessionLoadParams) -> AcpResult<SessionLoadResult> {
+        let session_id = params.session_id.clone();
+        let result = self.named(agent)?.session_load(params).await?;
+        self.claim(agent, &session_id);
+        Ok(result)
+    }
+
+    /// Send a prompt to the agent that owns its session.
+    pub async fn session_prompt(&self, params: SessionPromptParams) -> AcpResult<SessionPromptResult> {
+        self.owner(&params.session_id)?.session_prompt(params).await
+    }
+
+    /// Send a prompt to the agent that owns its session and stream its updates.
+    pub async fn prompt_stream(&self, params: SessionPromptParams) -> AcpResult<PromptStream> {
+        self.owner(&params.session_id)?.prompt_stream(params).await
+    }
+
+    /// Cancel work in a session on the agent that owns it.
+    pub async fn session_cancel(&self, params: SessionCancelParams) -> AcpResult<()> {
+        self.owner(&params.session_id)?.session_cancel(params).await
+    }
+
+    fn clients(&self) -> Vec<(String, Arc<Client>)> {
+        let agents = self.agents.read().unwrap();
+        let mut clients: Vec<_> = agents.iter().map(|(name, c)| (name.clone(), c.clone())).collect();
+        clients.sort_by(|a, b| a.0.cmp(&b.0));
+        clients
+    }
+
+    /// Capabilities of all agents combined with [`AgentCapabilities::merge`].
+    pub async fn capabilities(&self) -> AgentCapabilities {
+        let mut merged = AgentCapabilities::default();
+        for (_, client) in self.clients() {
+            if let Some(caps) = client.agent_capabilities().await {
+                merged.merge(caps);
+            }
+        }
+        merged
+    }
+
+    /// Every agent's tools, with the name of the agent offering each.
+    pub async fn tools(&self) -> Vec<(String, ToolInfo)> {
+        let mut tools = Vec::new();
+        for (name, client) in self.clients() {
+            if let Some(caps) = client.agent_capabilities().await {
+                tools.extend(caps.tools.into_iter().map(|tool| (name.clone(), tool)));
+            }
+        }
+        tools
+    }
+
+    /// Shut every agent down, allowing each `grace` to exit.
+    ///
+    /// Agents still referenced elsewhere through [`agent`](Self::agent) are
+    /// left running; they are killed when the last reference is dropped.
+    pub async fn shutdown(self, grace: Duration) {
+        let mut tasks = Vec::new();
+        for client in self.agents.into_inner().unwrap().into_values() {
+            if let Ok(mut client) = Arc::try_unwrap(client) {
+                tasks.push(tokio::spawn(async move {
+                    let _ = client.shutdown(grace).await;
+                }));
+            }
+        }
+        for task in tasks {
+            let _ = task.await;
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Retrying idempotent requests.
+
+use tokio::time::Duration;
+
+use crate::protocol::*;
+
+/// Methods that are safe to send again if an attempt fails.
+const IDEMPOTENT_METHODS: &[&str] = &[method::INITIALIZE, method::SESSION_LOAD, method::PING, method::AGENT_STATUS];
+
+/// When and how often to retry idempotent requests (`initialize`,
+/// `session/load` and `ping`).
+///
+/// Other requests are never retried, since the agent may have acted on them
+/// before failing.
+///
+/// ```rust
+/// use heroacp::client::RetryPolicy;
+/// use heroacp::protocol::codes;
+/// use std::time::Duration;
+///
+/// let policy = RetryPolicy::new(3)
+///     .backoff(Duration::from_millis(200), Duration::from_secs(2))
+///     .retry_on([codes::TIMEOUT, codes::RATE_LIMITED, codes::INTERNAL_ERROR]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    retry_on: Vec<i32>,
+}
+
+impl RetryPolicy {
+    /// Make up to `max_attempts` attempts in total.
+    ///
+    /// By default the wait between attempts starts at 100 ms and doubles up to
+    /// 5 s, and only `TIMEOUT` and `RATE_LIMITED` errors are retried.
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            retry_on: vec![codes::TIMEOUT, codes::RATE_LIMITED],
+        }
+    }
+
+    /// Wait `initial` before the first retry, doubling each time up to `max`.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    /// Retry errors with these JSON-RPC codes instead of the defaults.
+    pub fn retry_on(mut self, codes: impl IntoIterator<Item = i32>) -> Self {
+        self.retry_on = codes.into_iter().collect();
+        self
+    }
+
+    /// How long to wait before retrying `method` after failed attempt
+    /// number `attempt` (starting at 1) with error `code`, or `None` to give up.
+    pub(super) fn delay(&self, method: &str, attempt: u32, code: i32) -> Option<Duration> {
+        if attempt >= self.max_attempts
+            || !IDEMPOTENT_METHODS.contains(&method)
+            || !self.retry_on.contains(&code)
+        {
+            return None;
+        }
+        let factor = 2u32.saturating_pow(attempt - 1);
+        Some(self.initial_backoff.saturating_mul(factor).min(self.max_backoff))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy::new(4).backoff(Duration::from_millis(100), Duration::from_millis(300));
+
+        let delays: Vec<_> = (1..=4)
+            .map(|attempt| policy.delay(method::PING, attempt, codes::TIMEOUT))
+            .collect();
+        assert_eq!(
+            delays,
+            vec![
+                Some(Duration::from_millis(100)),
+                Some(Duration::from_millis(200)),
+                Some(Duration::from_millis(300)),
+                None,
+            ]
+        );
+
+        assert_eq!(policy.delay(method::SESSION_PROMPT, 1, codes::TIMEOUT), None);
+        assert_eq!(policy.delay(method::PING, 1, codes::INVALID_PARAMS), None);
+    }
+
+    /// An agent that rate-limits the first request and answers the second.
+    const FLAKY_AGENT: &str = r#"
+        read first
+        echo '{"jsonrpc":"2.0","id":1,"error":{"code":-32006,"message":"slow down"}}'
+        read second
+        echo '{"jsonrpc":"2.0","id":2,"result":{}}'
+        cat > /dev/null
+    "#;
+
+    #[tokio::test]
+    async fn test_retries_idempotent_requests() {
+        let client = crate::client::Client::builder("sh")
+            .args(["-c", FLAKY_AGENT])
+            .retry_policy(RetryPolicy::new(2).backoff(Duration::ZERO, Duration::ZERO))
+            .spawn()
+            .await
+            .unwrap();
+        client.ping().await.unwrap();
+
+        let client = crate::client::Client::builder("sh")
+            .args(["-c", FLAKY_AGENT])
+            .spawn()
+            .await
+            .unwrap();
+        assert!(client.ping().await.is_err());
+    }
+}
@@ -0,0 +1,260 @@
+//! What the client needs from its platform.
+//!
+//! Natively that is tokio. A browser has no tokio runtime, no processes and
+//! no disk, so on wasm32 tasks run on the page's event loop, timers on
+//! `setTimeout`, and the process and file types are stand-ins.
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::future::Future;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(super) use tokio::{fs, process, time};
+
+/// tokio's timers, implemented on the browser's.
+#[cfg(target_arch = "wasm32")]
+pub(super) mod time {
+    pub(crate) use std::time::Duration;
+    pub(crate) use wasmtimer::std::Instant;
+    pub(crate) use wasmtimer::tokio::{sleep, timeout, timeout_at, Sleep};
+}
+
+/// A task started with [`spawn`].
+#[cfg(not(target_arch = "wasm32"))]
+pub(super) type Task = tokio::task::JoinHandle<()>;
+
+/// Run `task` in the background.
+#[cfg(not(target_arch = "wasm32"))]
+pub(super) fn spawn<F>(task: F) -> Task
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(task)
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(super) use local::{spawn, Task};
+
+#[cfg(target_arch = "wasm32")]
+mod local {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use tokio::sync::oneshot;
+    use tokio_util::sync::CancellationToken;
+
+    /// A task started with [`spawn`], with the parts of tokio's
+    /// `JoinHandle` the client uses. Awaiting it waits for the task to end.
+    pub(crate) struct Task {
+        finished: Arc<AtomicBool>,
+        done: oneshot::Receiver<()>,
+        abort: CancellationToken,
+    }
+
+    impl Task {
+        /// Whether the task has ended.
+        pub(crate) fn is_finished(&self) -> bool {
+            self.finished.load(Ordering::Acquire)
+        }
+
+        /// Stop the task at its next `.await`.
+        pub(crate) fn abort(&self) {
+            self.abort.cancel();
+        }
+    }
+
+    impl Future for Task {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            Pin::new(&mut self.done).poll(cx).map(|_| ())
+        }
+    }
+
+    /// Run `task` on the page's event loop.
+    pub(crate) fn spawn<F>(task: F) -> Task
+    where
+        F: Future<Output = ()> + 'static,
+    {
+        let finished = Arc::new(AtomicBool::new(false));
+        let (done_tx, done) = oneshot::channel();
+        let abort = CancellationToken::new();
+        let (task_finished, aborted) = (finished.clone(), abort.clone());
+        wasm_bindgen_futures::spawn_local(async move {
+            tokio::select! {
+                _ = task => {}
+                _ = aborted.cancelled() => {}
+            }
+            task_finished.store(true, Ordering::Release);
+            let _ = done_tx.send(());
+        });
+        Task { finished, done, abort }
+    }
+}
+
+/// tokio's child process types, for the `child` of a [`Client`](super::Client)
+/// that a browser never spawns. They can't be created.
+#[cfg(target_arch = "wasm32")]
+pub(super) mod process {
+    use std::io;
+    use std::pin::Pin;
+    use std::process::ExitStatus;
+    use std::task::{Context, Poll};
+    use tokio::io::{AsyncRead, ReadBuf};
+
+    pub(crate) enum Child {}
+
+    impl Child {
+        pub(crate) fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+            match *self {}
+        }
+
+        pub(crate) async fn wait(&mut self) -> io::Result<ExitStatus> {
+            match *self {}
+        }
+
+        pub(crate) async fn kill(&mut self) -> io::Result<()> {
+            match *self {}
+        }
+
+        pub(crate) fn start_kill(&mut self) -> io::Result<()> {
+            match *self {}
+        }
+    }
+
+    pub(crate) enum ChildStderr {}
+
+    impl AsyncRead for ChildStderr {
+        fn poll_read(self: Pin<&mut Self>, _: &mut Context<'_>, _: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+            match *self {}
+        }
+    }
+}
+
+/// tokio's file API over `std::fs`, which fails every call with
+/// `Unsupported` in a browser. Handlers serve files there instead.
+#[cfg(target_arch = "wasm32")]
+pub(super) mod fs {
+    use std::ffi::OsString;
+    use std::fs::{FileType, Metadata, Permissions};
+    use std::io::{self, Read, Write};
+    use std::path::Path;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+    pub(crate) async fn read(path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        std::fs::read(path)
+    }
+
+    pub(crate) async fn write(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> io::Result<()> {
+        std::fs::write(path, contents)
+    }
+
+    pub(crate) async fn metadata(path: impl AsRef<Path>) -> io::Result<Metadata> {
+        std::fs::metadata(path)
+    }
+
+    pub(crate) async fn symlink_metadata(path: impl AsRef<Path>) -> io::Result<Metadata> {
+        std::fs::symlink_metadata(path)
+    }
+
+    pub(crate) async fn set_permissions(path: impl AsRef<Path>, permissions: Permissions) -> io::Result<()> {
+        std::fs::set_permissions(path, permissions)
+    }
+
+    pub(crate) async fn rename(from: impl AsRef<Path>, to: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::rename(from, to)
+    }
+
+    pub(crate) async fn remove_file(path: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::remove_file(path)
+    }
+
+    pub(crate) async fn remove_dir(path: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::remove_dir(path)
+    }
+
+    pub(crate) async fn remove_dir_all(path: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::remove_dir_all(path)
+    }
+
+    pub(crate) async fn create_dir(path: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::create_dir(path)
+    }
+
+    pub(crate) async fn create_dir_all(path: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::create_dir_all(path)
+    }
+
+    pub(crate) async fn read_dir(path: impl AsRef<Path>) -> io::Result<ReadDir> {
+        std::fs::read_dir(path).map(ReadDir)
+    }
+
+    pub(crate) struct ReadDir(std::fs::ReadDir);
+
+    impl ReadDir {
+        pub(crate) async fn next_entry(&mut self) -> io::Result<Option<DirEntry>> {
+            self.0.next().transpose().map(|entry| entry.map(DirEntry))
+        }
+    }
+
+    pub(crate) struct DirEntry(std::fs::DirEntry);
+
+    impl DirEntry {
+        pub(crate) fn file_name(&self) -> OsString {
+            self.0.file_name()
+        }
+
+        pub(crate) async fn file_type(&self) -> io::Result<FileType> {
+            self.0.file_type()
+        }
+
+        pub(crate) async fn metadata(&self) -> io::Result<Metadata> {
+            self.0.metadata()
+        }
+    }
+
+    pub(crate) struct File(std::fs::File);
+
+    impl File {
+        pub(crate) async fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+            std::fs::File::open(path).map(File)
+        }
+
+        pub(crate) async fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+            std::fs::File::create(path).map(File)
+        }
+
+        pub(crate) async fn metadata(&self) -> io::Result<Metadata> {
+            self.0.metadata()
+        }
+
+        pub(crate) async fn sync_all(&self) -> io::Result<()> {
+            self.0.sync_all()
+        }
+    }
+
+    impl AsyncRead for File {
+        fn poll_read(self: Pin<&mut Self>, _: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+            let n = self.get_mut().0.read(buf.initialize_unfilled())?;
+            buf.advance(n);
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncWrite for File {
+        fn poll_write(self: Pin<&mut Self>, _: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            Poll::Ready(self.get_mut().0.write(buf))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(self.get_mut().0.flush())
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+}
@@ -0,0 +1,288 @@
+//! Limits on the paths the agent may read and write.
+
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+
+use crate::protocol::*;
+
+/// Which paths the agent's `fs/*` requests may touch.
+///
+/// The default allows everything. Requests outside the sandbox fail with
+/// `PERMISSION_DENIED` before they reach the
+/// [`FileSystemHandler`](super::FileSystemHandler). Once roots or denied
+/// patterns are set, the handler is given paths with symlinks and `..`
+/// resolved, so it touches exactly what was checked.
+///
+/// ```rust
+/// use heroacp::client::PathSandbox;
+///
+/// let sandbox = PathSandbox::new()
+///     .allow_root("/path/to/project")
+///     .deny("~/.ssh/**")
+///     .deny("**/.env")
+///     .read_only(true);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PathSandbox {
+    roots: Vec<PathBuf>,
+    denied: Vec<String>,
+    read_only: bool,
+}
+
+impl PathSandbox {
+    /// Create a sandbox that allows everything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow paths under `root`. Once a root is added, paths outside every
+    /// root are denied.
+    pub fn allow_root(mut self, root: impl AsRef<Path>) -> Self {
+        self.roots.push(resolve(root.as_ref(), true));
+        self
+    }
+
+    /// Deny paths matching `pattern`, even under an allowed root.
+    ///
+    /// `*` matches within one path component, `**` across components and `?`
+    /// a single character. A leading `~` stands for `$HOME`. Use `/` as the
+    /// separator on Windows too.
+    ///
+    /// Symlinks in the directories before the first wildcard are resolved
+    /// like a root's, so `~/.ssh/**` still matches when `$HOME` is reached
+    /// through a symlink.
+    pub fn deny(mut self, pattern: impl Into<String>) -> Self {
+        let pattern = pattern.into();
+        let home = std::env::var("HOME").or_else(|_| std::env::var("USERPROFILE"));
+        let pattern = match (pattern.strip_prefix('~'), home) {
+            (Some(rest), Ok(home)) => format!("{}{}", home.trim_end_matches(['/', '\\']), rest),
+            _ => pattern,
+        };
+        let pattern = if cfg!(windows) { pattern.replace('\\', "/") } else { pattern };
+        self.denied.push(resolve_pattern(&pattern));
+        self
+    }
+
+    /// Deny all writes.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Check that `path` may be read, or written if `write` is set.
+    ///
+    /// Returns the path to use: resolved once roots or denied patterns are
+    /// set, as given otherwise.
+    pub fn check(&self, path: &str, write: bool) -> AcpResult<String> {
+        self.check_resolved(path, write, true)
+    }
+
+    /// Check that the directory entry at `path` may be deleted or renamed.
+    ///
+    /// Unlike [`check`](Self::check) a symlink at `path` isn't followed, as
+    /// it is the link that changes rather than what it points to.
+    pub(super) fn check_entry(&self, path: &str) -> AcpResult<String> {
+        self.check_resolved(path, true, false)
+    }
+
+    fn check_resolved(&self, path: &str, write: bool, follow: bool) -> AcpResult<String> {
+        if write && self.read_only {
+            return Err(AcpError::PermissionDenied(format!("{} (file system is read-only)", path)));
+        }
+        if self.roots.is_empty() && self.denied.is_empty() {
+            return Ok(path.to_string());
+        }
+        let resolved = resolve(Path::new(path), follow);
+        if !self.roots.is_empty() && !self.roots.iter().any(|root| resolved.starts_with(root)) {
+            return Err(AcpError::PermissionDenied(format!("{} is outside the allowed roots", path)));
+        }
+        let mut text = resolved.to_string_lossy().into_owned();
+        if cfg!(windows) {
+            // Patterns use `/` on every platform
+            text = text.replace('\\', "/");
+        }
+        if self.denied.iter().any(|pattern| glob_match(pattern, &text)) {
+            return Err(AcpError::PermissionDenied(format!("{} is denied", path)));
+        }
+        Ok(resolved.to_string_lossy().into_owned())
+    }
+}
+
+/// Most symlinks followed while resolving a path, as with `ELOOP`.
+const MAX_SYMLINKS: usize = 40;
+
+/// Make `path` absolute without `.` or `..`, following symlinks as far as
+/// the path exists so they can't lead out of the sandbox. A symlink in the
+/// last component is only followed if `follow_last` is set.
+///
+/// Each symlink is resolved before any `..` after it, as the OS would:
+/// `link/..` is the parent of the link's target, not the directory holding
+/// the link.
+fn resolve(path: &Path, follow_last: bool) -> PathBuf {
+    let mut resolved = if path.is_absolute() {
+        PathBuf::new()
+    } else {
+        std::env::current_dir().unwrap_or_default()
+    };
+    // Components still to resolve, the next one last
+    let mut pending: Vec<OsString> = path.components().rev().map(|c| c.as_os_str().to_os_string()).collect();
+    let mut followed = 0;
+    while let Some(component) = pending.pop() {
+        match Path::new(&component).components().next() {
+            Some(Component::ParentDir) => {
+                resolved.pop();
+            }
+            Some(Component::CurDir) | None => {}
+            Some(Component::Normal(name)) => {
+                let candidate = resolved.join(name);
+                let is_last = pending.is_empty();
+                let target = std::fs::symlink_metadata(&candidate)
+                    .ok()
+                    .filter(|metadata| metadata.file_type().is_symlink())
+                    .filter(|_| followed < MAX_SYMLINKS && (follow_last || !is_last))
+                    .and_then(|_| std::fs::read_link(&candidate).ok());
+                match target {
+                    Some(target) => {
+                        // An absolute target starts over from its root
+                        followed += 1;
+                        pending.extend(target.components().rev().map(|c| c.as_os_str().to_os_string()));
+                    }
+                    None => resolved = candidate,
+                }
+            }
+            // The root, or a Windows drive prefix
+            Some(root) => resolved.push(root),
+        }
+    }
+    resolved
+}
+
+/// Resolve the directories of an absolute `pattern` up to its first wildcard,
+/// as checked paths are resolved. The last component is kept as written,
+/// since [`PathSandbox::check_entry`] doesn't follow it either.
+fn resolve_pattern(pattern: &str) -> String {
+    let parts: Vec<&str> = pattern.split('/').collect();
+    let literal = parts.iter().take_while(|part| !part.contains(['*', '?'])).count();
+    let prefix = parts[..literal.min(parts.len() - 1)].join("/");
+    if prefix.is_empty() || !Path::new(&prefix).is_absolute() {
+        return pattern.to_string();
+    }
+    let mut resolved = resolve(Path::new(&prefix), true).to_string_lossy().into_owned();
+    if cfg!(windows) {
+        resolved = resolved.replace('\\', "/");
+    }
+    let rest = &pattern[prefix.len()..];
+    format!("{}{}", resolved.trim_end_matches('/'), rest)
+}
+
+/// Match `text` against a glob `pattern` with `*`, `**` and `?`.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    fn matches(p: &[u8], t: &[u8]) -> bool {
+        match p {
+            [] => t.is_empty(),
+            [b'*', b'*', b'/', rest @ ..] => {
+                // `**/` also matches no directories at all
+                matches(rest, t) || (0..t.len()).any(|i| t[i] == b'/' && matches(rest, &t[i + 1..]))
+            }
+            [b'*', b'*', rest @ ..] => (0..=t.len()).any(|i| matches(rest, &t[i..])),
+            [b'*', rest @ ..] => (0..=t.len())
+                .take_while(|&i| i == 0 || t[i - 1] != b'/')
+                .any(|i| matches(rest, &t[i..])),
+            [b'?', rest @ ..] => matches!(t, [c, tail @ ..] if *c != b'/' && matches(rest, tail)),
+            [c, rest @ ..] => matches!(t, [d, tail @ ..] if c == d && matches(rest, tail)),
+        }
+    }
+    matches(pattern.as_bytes(), text.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("/home/u/.ssh/**", "/home/u/.ssh/id_rsa"));
+        assert!(glob_match("/home/u/.ssh/**", "/home/u/.ssh/keys/id_rsa"));
+        assert!(!glob_match("/home/u/.ssh/**", "/home/u/.sshrc"));
+        assert!(glob_match("**/.env", "/srv/app/.env"));
+        assert!(glob_match("/srv/*.rs", "/srv/main.rs"));
+        assert!(!glob_match("/srv/*.rs", "/srv/src/main.rs"));
+        assert!(glob_match("/srv/?.rs", "/srv/a.rs"));
+    }
+
+    #[test]
+    fn test_sandbox_check() {
+        let sandbox = PathSandbox::new()
+            .allow_root("/heroacp-project")
+            .deny("**/.env")
+            .read_only(true);
+
+        assert!(sandbox.check("/heroacp-project/src/main.rs", false).is_ok());
+        let denied = [
+            ("/heroacp-project/src/main.rs", true),
+            ("/heroacp-project/../etc/passwd", false),
+            ("/heroacp-project-other/x", false),
+            ("/heroacp-project/.env", false),
+        ];
+        for (path, write) in denied {
+            let err = sandbox.check(path, write).unwrap_err();
+            assert_eq!(err.code(), codes::PERMISSION_DENIED, "{}", path);
+        }
+        assert!(PathSandbox::new().check("/etc/hosts", true).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sandbox_follows_symlinks_before_parent_dirs() {
+        let dir = std::env::temp_dir().join(format!("heroacp-sandbox-{}", std::process::id()));
+        let root = dir.join("project");
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::create_dir_all(dir.join("outside/deep")).unwrap();
+        std::os::unix::fs::symlink(dir.join("outside/deep"), root.join("link")).unwrap();
+        let sandbox = PathSandbox::new().allow_root(&root);
+        let path = |rest: &str| format!("{}/{}", root.display(), rest);
+
+        // `link/..` is `outside`, not the project
+        for escape in ["link/secret", "link/../secret", "src/../link/../../outside/secret"] {
+            let err = sandbox.check(&path(escape), false).unwrap_err();
+            assert_eq!(err.code(), codes::PERMISSION_DENIED, "{}", escape);
+        }
+
+        // The handler gets the path that was checked
+        let resolved = resolve(&root, true);
+        assert_eq!(
+            sandbox.check(&path("src/../main.rs"), false).unwrap(),
+            resolved.join("main.rs").to_string_lossy()
+        );
+        // Deleting the link itself stays in the project
+        assert_eq!(
+            sandbox.check_entry(&path("link")).unwrap(),
+            resolved.join("link").to_string_lossy()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_deny_patterns_follow_symlinked_roots() {
+        // Like `/home -> /usr/home`, or `/var -> /private/var` on macOS
+        let dir = std::env::temp_dir().join(format!("heroacp-sandbox-deny-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("usr/home/.ssh")).unwrap();
+        std::os::unix::fs::symlink(dir.join("usr/home"), dir.join("home")).unwrap();
+        let home = dir.join("home");
+        let sandbox = PathSandbox::new()
+            .allow_root(&home)
+            .deny(format!("{}/.ssh/**", home.display()))
+            .deny(format!("{}/.netrc", home.display()));
+
+        for denied in [".ssh/id_rsa", ".netrc"] {
+            let path = format!("{}/{}", home.display(), denied);
+            let err = sandbox.check(&path, false).unwrap_err();
+            assert_eq!(err.code(), codes::PERMISSION_DENIED, "{}", denied);
+            let err = sandbox.check_entry(&path).unwrap_err();
+            assert_eq!(err.code(), codes::PERMISSION_DENIED, "{}", denied);
+        }
+        assert!(sandbox.check(&format!("{}/notes.md", home.display()), true).is_ok());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
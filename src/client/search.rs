@@ -0,0 +1,158 @@
+//! Searching the contents of files on the local disk.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use grep_regex::RegexMatcherBuilder;
+use grep_searcher::{sinks, BinaryDetection, SearcherBuilder};
+use ignore::WalkState;
+
+use super::rt::fs;
+use crate::protocol::*;
+
+/// Most matching lines returned when the agent doesn't set `max_matches`.
+pub const MAX_GREP_MATCHES: u32 = 500;
+
+/// Longer matching lines, such as minified code, are cut to this many
+/// characters.
+const MAX_SNIPPET_CHARS: usize = 500;
+
+/// Search the files under `path` for lines matching `query`, honoring
+/// `.gitignore` files and skipping binary files.
+///
+/// Files are searched in parallel. When there are more than `max_matches`
+/// matches, which of them are kept depends on the order files finish in.
+pub(super) async fn grep(
+    query: &str,
+    regex: bool,
+    path: &str,
+    max_matches: Option<u32>,
+) -> AcpResult<FsGrepResult> {
+    let matcher = RegexMatcherBuilder::new()
+        .fixed_strings(!regex)
+        .line_terminator(Some(b'\n'))
+        .build(query)
+        .map_err(|e| AcpError::InvalidParams(format!("invalid query {}: {}", query, e)))?;
+    fs::metadata(path).await.map_err(|e| match e.kind() {
+        std::io::ErrorKind::PermissionDenied => AcpError::PermissionDenied(path.to_string()),
+        _ => AcpError::ResourceNotFound(path.to_string()),
+    })?;
+
+    let root = PathBuf::from(path);
+    let max_matches = max_matches.unwrap_or(MAX_GREP_MATCHES) as usize;
+    let found = Arc::new(Mutex::new(Vec::new()));
+    let truncated = Arc::new(AtomicBool::new(false));
+    let (walk_found, walk_truncated) = (found.clone(), truncated.clone());
+    // The walk does blocking I/O
+    tokio::task::spawn_blocking(move || {
+        ignore::WalkBuilder::new(&root)
+            .require_git(false)
+            .build_parallel()
+            .run(|| {
+                let matcher = matcher.clone();
+                let found = walk_found.clone();
+                let truncated = walk_truncated.clone();
+                let mut searcher = SearcherBuilder::new()
+                    .binary_detection(BinaryDetection::quit(b'\x00'))
+                    .build();
+                Box::new(move |entry| {
+                    let Ok(entry) = entry else {
+                        return WalkState::Continue;
+                    };
+                    if !entry.file_type().is_some_and(|t| t.is_file()) {
+                        return WalkState::Continue;
+                    }
+                    let file = entry.path().to_string_lossy().into_owned();
+                    let mut matches = Vec::new();
+                    // Files that can't be read are skipped
+                    let _ = searcher.search_path(
+                        &matcher,
+                        entry.path(),
+                        sinks::Lossy(|line, text| {
+                            let text = text.trim_end_matches(['\r', '\n']);
+                            matches.push(GrepMatch {
+                                path: file.clone(),
+                                line: line as u32,
+                                snippet: text.chars().take(MAX_SNIPPET_CHARS).collect(),
+                            });
+                            Ok(matches.len() <= max_matches)
+                        }),
+                    );
+                    if matches.is_empty() {
+                        return WalkState::Continue;
+                    }
+                    let mut found = found.lock().unwrap();
+                    found.extend(matches);
+                    if found.len() > max_matches {
+                        truncated.store(true, Ordering::Relaxed);
+                        return WalkState::Quit;
+                    }
+                    WalkState::Continue
+                })
+            });
+    })
+    .await
+    .map_err(|e| AcpError::InternalError(e.to_string()))?;
+
+    let mut matches = std::mem::take(&mut *found.lock().unwrap());
+    matches.sort_by(|a, b| a.path.cmp(&b.path).then(a.line.cmp(&b.line)));
+    matches.truncate(max_matches);
+    Ok(FsGrepResult {
+        matches,
+        truncated: truncated.load(Ordering::Relaxed),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_grep() {
+        let dir = std::env::temp_dir().join(format!("heroacp-grep-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::create_dir_all(dir.join("target")).unwrap();
+        std::fs::write(dir.join(".gitignore"), "target/\n").unwrap();
+        std::fs::write(dir.join("src/lib.rs"), "pub mod client;\npub fn connect() {}\n").unwrap();
+        std::fs::write(dir.join("src/client.rs"), "fn connect_all() {}\n// connect()\n").unwrap();
+        std::fs::write(dir.join("target/gen.rs"), "fn connect() {}\n").unwrap();
+        std::fs::write(dir.join("blob.bin"), b"connect()\x00\x01").unwrap();
+        let path = dir.to_str().unwrap();
+        let lines = |result: &FsGrepResult| -> Vec<(String, u32)> {
+            let prefix = format!("{}/", path);
+            result
+                .matches
+                .iter()
+                .map(|m| (m.path.strip_prefix(&prefix).unwrap().to_string(), m.line))
+                .collect()
+        };
+
+        let literal = grep("connect()", false, path, None).await.unwrap();
+        assert_eq!(
+            lines(&literal),
+            [("src/client.rs".to_string(), 2), ("src/lib.rs".to_string(), 2)]
+        );
+        assert_eq!(literal.matches[1].snippet, "pub fn connect() {}");
+        assert!(!literal.truncated);
+
+        let pattern = grep(r"fn connect\w*\(", true, path, None).await.unwrap();
+        assert_eq!(
+            lines(&pattern),
+            [("src/client.rs".to_string(), 1), ("src/lib.rs".to_string(), 2)]
+        );
+        let file = dir.join("src/lib.rs");
+        let single = grep("pub", false, file.to_str().unwrap(), None).await.unwrap();
+        assert_eq!(single.matches.len(), 2);
+
+        let limited = grep("connect", false, path, Some(1)).await.unwrap();
+        assert_eq!(limited.matches.len(), 1);
+        assert!(limited.truncated);
+
+        let err = grep("fn (", true, path, None).await.unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_PARAMS);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let err = grep("connect", false, path, None).await.unwrap_err();
+        assert_eq!(err.code(), codes::RESOURCE_NOT_FOUND);
+    }
+}
@@ -0,0 +1,184 @@
+//! Working with one session of a client.
+
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::Duration;
+
+use super::{Client, ClientEvent, PromptStream, SessionTranscript};
+use crate::protocol::*;
+
+/// A handle for one session, from [`Client::session`].
+///
+/// Handles for different sessions share the client's connection, so their
+/// prompts and cancellations can run at the same time:
+///
+/// ```rust,no_run
+/// # use heroacp::client::Client;
+/// # use heroacp::protocol::*;
+/// # async fn demo(client: &Client) -> AcpResult<()> {
+/// let (docs, tests) = (client.session("docs"), client.session("tests"));
+/// let (docs_result, tests_result) = tokio::join!(
+///     docs.prompt(vec![ContentBlock::text("Document the API")]),
+///     tests.prompt(vec![ContentBlock::text("Add tests")]),
+/// );
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Copy)]
+pub struct ClientSession<'a> {
+    client: &'a Client,
+    session_id: &'a str,
+    prompt_timeout: Option<Option<Duration>>,
+}
+
+impl<'a> ClientSession<'a> {
+    pub(super) fn new(client: &'a Client, session_id: &'a str) -> Self {
+        Self {
+            client,
+            session_id,
+            prompt_timeout: None,
+        }
+    }
+
+    /// The session this handle is for.
+    pub fn session_id(&self) -> &str {
+        self.session_id
+    }
+
+    /// Wait at most `limit` for this handle's prompts instead of the client's
+    /// `session/prompt` timeout. `None` waits until the agent responds.
+    pub fn prompt_timeout(mut self, limit: Option<Duration>) -> Self {
+        self.prompt_timeout = Some(limit);
+        self
+    }
+
+    fn params(&self, content: Vec<ContentBlock>) -> SessionPromptParams {
+        SessionPromptParams {
+            session_id: self.session_id.to_string(),
+            content,
+        }
+    }
+
+    /// Send a prompt to this session and wait for the response.
+    pub async fn prompt(&self, content: Vec<ContentBlock>) -> AcpResult<SessionPromptResult> {
+        let params = self.params(content);
+        match self.prompt_timeout {
+            Some(limit) => self.client.session_prompt_with_timeout(params, limit).await,
+            None => self.client.session_prompt(params).await,
+        }
+    }
+
+    /// Send a prompt to this session and stream its updates, as
+    /// [`Client::prompt_stream`] does.
+    pub async fn prompt_stream(&self, content: Vec<ContentBlock>) -> AcpResult<PromptStream> {
+        let params = self.params(content);
+        match self.prompt_timeout {
+            Some(limit) => self.client.prompt_stream_with_timeout(params, limit).await,
+            None => self.client.prompt_stream(params).await,
+        }
+    }
+
+    /// Cancel the running and queued prompts of this session.
+    pub async fn cancel(&self) -> AcpResult<()> {
+        self.client
+            .session_cancel(SessionCancelParams {
+                session_id: self.session_id.to_string(),
+                prompt_id: None,
+            })
+            .await
+    }
+
+    /// Run this session's next prompts on the model `model_id`.
+    pub async fn set_model(&self, model_id: &str) -> AcpResult<()> {
+        self.client
+            .session_set_model(SessionSetModelParams {
+                session_id: self.session_id.to_string(),
+                model_id: model_id.to_string(),
+            })
+            .await
+    }
+
+    /// Receive every update for this session from now on, whichever prompt
+    /// it belongs to.
+    pub fn updates(&self) -> SessionUpdates {
+        SessionUpdates {
+            events: self.client.subscribe(),
+            session_id: self.session_id.to_string(),
+        }
+    }
+
+    /// The transcript of this session, as [`Client::session_history`].
+    pub fn history(&self) -> Option<SessionTranscript> {
+        self.client.session_history(self.session_id)
+    }
+}
+
+/// The updates of one session, from [`ClientSession::updates`].
+///
+/// Updates arrive through the client's event channel, so a receiver that
+/// falls more than 256 events behind skips the oldest.
+pub struct SessionUpdates {
+    events: broadcast::Receiver<ClientEvent>,
+    session_id: String,
+}
+
+impl SessionUpdates {
+    /// Get the next update, or `None` once the agent has disconnected.
+    pub async fn next(&mut self) -> Option<SessionUpdate> {
+        loop {
+            match self.events.recv().await {
+                Ok(ClientEvent::SessionUpdate(update)) if update.session_id == self.session_id => {
+                    return Some(update)
+                }
+                Ok(ClientEvent::Disconnected) | Err(RecvError::Closed) => return None,
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::timeout;
+
+    /// An agent that reads two prompts and answers them in reverse order,
+    /// sending each session an update first.
+    const REVERSING_AGENT: &str = r#"
+        read a
+        read b
+        for line in "$b" "$a"; do
+            id=$(echo "$line" | sed 's/.*"id":\([0-9]*\).*/\1/')
+            session=$(echo "$line" | sed 's/.*"session_id":"\([^"]*\)".*/\1/')
+            echo '{"jsonrpc":"2.0","method":"session/update","params":{"session_id":"'$session'","type":"agent_message_chunk","data":{"text":"for '$session'"}}}'
+            echo '{"jsonrpc":"2.0","id":'$id',"result":{"status":"'$session'"}}'
+        done
+        cat > /dev/null
+    "#;
+
+    #[tokio::test]
+    async fn test_sessions_prompt_concurrently() {
+        let client = Client::builder("sh")
+            .args(["-c", REVERSING_AGENT])
+            .spawn()
+            .await
+            .unwrap();
+        let first = client.session("first");
+        let second = client.session("second").prompt_timeout(Some(Duration::from_secs(5)));
+        let mut first_updates = first.updates();
+
+        let mut stream = first.prompt_stream(vec![ContentBlock::text("one")]).await.unwrap();
+        let result = second.prompt(vec![ContentBlock::text("two")]).await.unwrap();
+        assert_eq!(result.status, "second");
+
+        let update = stream.next().await.unwrap();
+        assert!(matches!(
+            update.update_type,
+            SessionUpdateType::AgentMessageChunk { text } if text == "for first"
+        ));
+        assert_eq!(stream.result().await.unwrap().status, "first");
+
+        let update = timeout(Duration::from_secs(5), first_updates.next()).await.unwrap().unwrap();
+        assert_eq!(update.session_id, "first");
+        assert_eq!(second.history().unwrap().entries.len(), 2);
+    }
+}
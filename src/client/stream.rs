@@ -0,0 +1,155 @@
+//! Per-prompt update streams.
+//!
+//! [`Client::prompt_stream`](super::Client::prompt_stream) routes the updates
+//! of one session to a [`PromptStream`] instead of leaving it to the caller to
+//! pick them out of the global [`UpdateHandler`](super::UpdateHandler) calls.
+
+use futures_core::Stream;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::sync::{mpsc, oneshot};
+
+use super::rt::time::{sleep, Duration, Sleep};
+use crate::protocol::*;
+
+/// How long updates may trail the response before the stream gives up on `Done`.
+const RESPONSE_GRACE: Duration = Duration::from_millis(200);
+
+/// Open prompt streams, keyed by session ID.
+pub(super) type Subscribers = Arc<Mutex<HashMap<String, mpsc::UnboundedSender<SessionUpdate>>>>;
+
+/// Removes a session's subscription when the stream goes away.
+pub(super) struct Subscription {
+    subscribers: Subscribers,
+    session_id: String,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.subscribers.lock().unwrap().remove(&self.session_id);
+    }
+}
+
+/// Subscribe to the updates of `session_id`.
+///
+/// Only one stream per session can be open at a time.
+pub(super) fn subscribe(
+    subscribers: &Subscribers,
+    session_id: &str,
+) -> AcpResult<(mpsc::UnboundedReceiver<SessionUpdate>, Subscription)> {
+    let mut map = subscribers.lock().unwrap();
+    if map.contains_key(session_id) {
+        return Err(AcpError::InvalidState(format!(
+            "a prompt stream is already open for session {}",
+            session_id
+        )));
+    }
+    let (tx, rx) = mpsc::unbounded_channel();
+    map.insert(session_id.to_string(), tx);
+    let subscription = Subscription {
+        subscribers: subscribers.clone(),
+        session_id: session_id.to_string(),
+    };
+    Ok((rx, subscription))
+}
+
+/// Forward `update` to its session's stream, if one is open.
+pub(super) fn publish(subscribers: &Subscribers, update: SessionUpdate) {
+    if let Some(tx) = subscribers.lock().unwrap().get(&update.session_id) {
+        let _ = tx.send(update);
+    }
+}
+
+/// The updates of a single prompt, followed by its result.
+///
+/// The stream yields every update for the prompt's session and ends after
+/// the `Done` update. Agents may write the response before their last
+/// updates, so if the agent sends no `Done` the stream ends shortly after the
+/// response arrives.
+/// Call [`result`](Self::result) afterwards for the `session/prompt` response.
+pub struct PromptStream {
+    updates: mpsc::UnboundedReceiver<SessionUpdate>,
+    response: Option<oneshot::Receiver<JsonRpcResponse>>,
+    received: Option<Result<JsonRpcResponse, oneshot::error::RecvError>>,
+    grace: Option<Pin<Box<Sleep>>>,
+    limit: Option<Duration>,
+    done: bool,
+    _subscription: Subscription,
+}
+
+impl PromptStream {
+    pub(super) fn new(
+        updates: mpsc::UnboundedReceiver<SessionUpdate>,
+        subscription: Subscription,
+        response: oneshot::Receiver<JsonRpcResponse>,
+        limit: Option<Duration>,
+    ) -> Self {
+        Self {
+            updates,
+            response: Some(response),
+            received: None,
+            grace: None,
+            limit,
+            done: false,
+            _subscription: subscription,
+        }
+    }
+
+    /// Get the next update, or `None` once the prompt is done.
+    pub async fn next(&mut self) -> Option<SessionUpdate> {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+
+    /// Wait for the prompt's response.
+    ///
+    /// Updates not yet taken from the stream are discarded. The client's
+    /// `session/prompt` timeout applies from the time this is called.
+    pub async fn result(mut self) -> AcpResult<SessionPromptResult> {
+        let response = match (self.received.take(), self.response.take()) {
+            (Some(received), _) => received.map_err(|_| AcpError::ConnectionClosed)?,
+            (None, Some(response)) => super::wait_response(response, self.limit).await?,
+            (None, None) => return Err(AcpError::ConnectionClosed),
+        };
+        super::read_response(response)
+    }
+}
+
+impl Stream for PromptStream {
+    type Item = SessionUpdate;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<SessionUpdate>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        match self.updates.poll_recv(cx) {
+            Poll::Ready(Some(update)) => {
+                self.done = matches!(update.update_type, SessionUpdateType::Done);
+                return Poll::Ready(Some(update));
+            }
+            Poll::Ready(None) => {
+                self.done = true;
+                return Poll::Ready(None);
+            }
+            Poll::Pending => {}
+        }
+
+        // Without a `Done` update the stream ends soon after the response
+        if let Some(response) = self.response.as_mut() {
+            if let Poll::Ready(received) = Pin::new(response).poll(cx) {
+                self.response = None;
+                self.received = Some(received);
+                self.grace = Some(Box::pin(sleep(RESPONSE_GRACE)));
+            }
+        }
+        if let Some(grace) = self.grace.as_mut() {
+            if grace.as_mut().poll(cx).is_ready() {
+                self.done = true;
+                return Poll::Ready(None);
+            }
+        }
+        Poll::Pending
+    }
+}
@@ -0,0 +1,553 @@
+//! Terminals the agent runs commands in.
+//!
+//! The agent's `terminal/*` requests go to a [`TerminalHandler`]. By default
+//! that is `ProcessTerminals`, which runs each command as a local process;
+//! clients can install their own to use editor-integrated terminals, remote
+//! machines or containers. A browser has no processes, so there the default
+//! refuses every request.
+
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet};
+#[cfg(not(target_arch = "wasm32"))]
+use std::collections::VecDeque;
+#[cfg(not(target_arch = "wasm32"))]
+use std::process::Stdio;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::{Arc, Mutex as StdMutex};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::process::{Child, ChildStdin, Command};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::sync::Mutex;
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::task::JoinHandle;
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::time::timeout;
+
+use super::rt::time::{self, Duration};
+
+use crate::protocol::*;
+
+/// Handler for the agent's `terminal/*` requests.
+#[async_trait]
+pub trait TerminalHandler: Send + Sync {
+    /// Start `params.command` in `params.cwd`, returning a new terminal ID.
+    async fn create(&self, params: TerminalCreateParams) -> AcpResult<String>;
+
+    /// Get the output so far and whether the command has exited.
+    async fn output(&self, terminal_id: &str) -> AcpResult<TerminalOutputResult>;
+
+    /// Write `data` to the command's stdin, then close it if `eof` is set.
+    ///
+    /// The default fails with `CAPABILITY_NOT_SUPPORTED`.
+    async fn input(&self, terminal_id: &str, data: &str, eof: bool) -> AcpResult<()> {
+        let _ = (terminal_id, data, eof);
+        Err(AcpError::CapabilityNotSupported("terminal/input".to_string()))
+    }
+
+    /// Wait for the command to exit.
+    ///
+    /// The default polls [`output`](Self::output). The client gives up after
+    /// five minutes.
+    async fn wait_for_exit(&self, terminal_id: &str) -> AcpResult<TerminalWaitForExitResult> {
+        loop {
+            let output = self.output(terminal_id).await?;
+            if output.exited {
+                return Ok(TerminalWaitForExitResult {
+                    exit_code: output.exit_code.unwrap_or(-1),
+                    output: output.output,
+                    stderr: output.stderr,
+                    truncated: output.truncated,
+                    total_bytes: output.total_bytes,
+                });
+            }
+            time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Kill the command. The terminal ID is invalid afterwards.
+    async fn kill(&self, terminal_id: &str) -> AcpResult<()>;
+
+    /// Free the terminal without killing the command.
+    async fn release(&self, terminal_id: &str) -> AcpResult<()>;
+}
+
+/// How often the default [`TerminalHandler::wait_for_exit`] checks on the
+/// command.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long the client lets `terminal/wait_for_exit` run before failing it
+/// with `TIMEOUT`.
+pub(super) const WAIT_FOR_EXIT_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Output kept for a terminal when `terminal/create` doesn't set a limit.
+const DEFAULT_OUTPUT_BYTE_LIMIT: u64 = 1 << 20;
+
+#[cfg(not(target_arch = "wasm32"))]
+/// The most recent output of a command, up to a byte limit.
+struct OutputBuffer {
+    bytes: VecDeque<u8>,
+    limit: usize,
+    total: u64,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl OutputBuffer {
+    fn push(&mut self, chunk: &[u8]) {
+        self.total += chunk.len() as u64;
+        self.bytes.extend(chunk);
+        let excess = self.bytes.len().saturating_sub(self.limit);
+        self.bytes.drain(..excess);
+    }
+
+    fn truncated(&self) -> bool {
+        self.total > self.bytes.len() as u64
+    }
+
+    fn text(&mut self) -> String {
+        let mut bytes = &*self.bytes.make_contiguous();
+        if self.total > bytes.len() as u64 {
+            // Don't start in the middle of a character
+            while let [b, rest @ ..] = bytes {
+                if b & 0xC0 != 0x80 {
+                    break;
+                }
+                bytes = rest;
+            }
+        }
+        String::from_utf8_lossy(bytes).into_owned()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// A command's combined output, and its stderr on its own.
+struct Outputs {
+    combined: OutputBuffer,
+    stderr: OutputBuffer,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Copy a pipe into `outputs` until it closes.
+async fn capture(mut pipe: impl AsyncRead + Unpin, outputs: Arc<StdMutex<Outputs>>, is_stderr: bool) {
+    let mut chunk = [0u8; 8192];
+    while let Ok(n @ 1..) = pipe.read(&mut chunk).await {
+        let mut outputs = outputs.lock().unwrap();
+        outputs.combined.push(&chunk[..n]);
+        if is_stderr {
+            outputs.stderr.push(&chunk[..n]);
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+struct Process {
+    child: Child,
+    /// The command's stdin, until closed with `eof`.
+    stdin: Option<ChildStdin>,
+    output: Arc<StdMutex<Outputs>>,
+    /// Tasks copying stdout and stderr, until the command has exited.
+    readers: Vec<JoinHandle<()>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+struct Processes {
+    children: HashMap<String, Process>,
+    next_id: u64,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// The shell [`ProcessTerminals`] runs command lines with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Shell {
+    /// `sh -c`, the default on Unix. Arguments are available as `$1`, `$2`, ...
+    Sh,
+    /// `cmd /C`, the default on Windows. Arguments are appended to the command line.
+    Cmd,
+    /// `powershell -NoProfile -Command`. Arguments are appended to the command line.
+    PowerShell,
+    /// Another shell, run as `program flag <command line> <args>...`.
+    Custom {
+        /// Shell executable.
+        program: String,
+        /// Flag that precedes the command line, like `-c`.
+        flag: String,
+    },
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for Shell {
+    fn default() -> Self {
+        if cfg!(windows) {
+            Shell::Cmd
+        } else {
+            Shell::Sh
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Shell {
+    /// A command running `line` with `args`.
+    fn command(&self, line: &str, args: &[String]) -> Command {
+        let (program, flag) = match self {
+            Shell::Sh => ("sh", "-c"),
+            Shell::Cmd => ("cmd", "/C"),
+            Shell::PowerShell => ("powershell", "-Command"),
+            Shell::Custom { program, flag } => (program.as_str(), flag.as_str()),
+        };
+        let mut command = Command::new(program);
+        if *self == Shell::PowerShell {
+            command.arg("-NoProfile");
+        }
+        command.arg(flag).arg(line);
+        if *self == Shell::Sh {
+            // `sh` fills $0 from the first argument
+            command.arg("sh");
+        }
+        command.args(args);
+        command
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Terminal handler that runs commands as local processes, through the
+/// platform's [`Shell`] unless `no_shell` is set.
+///
+/// Stdout and stderr are collected together, and stderr also on its own.
+/// Unless `terminal/create` sets `output_byte_limit`, the last 1 MiB of each
+/// is kept.
+#[derive(Default)]
+pub struct ProcessTerminals {
+    processes: Mutex<Processes>,
+    shell: Shell,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ProcessTerminals {
+    /// Create a handler with no terminals.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a handler that runs command lines with `shell`.
+    pub fn with_shell(shell: Shell) -> Self {
+        Self {
+            processes: Mutex::default(),
+            shell,
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[async_trait]
+impl TerminalHandler for ProcessTerminals {
+    async fn create(&self, params: TerminalCreateParams) -> AcpResult<String> {
+        let mut processes = self.processes.lock().await;
+        processes.next_id += 1;
+        let id = format!("term_{}", processes.next_id);
+
+        let mut command = if params.no_shell {
+            let mut command = Command::new(&params.command);
+            command.args(&params.args);
+            command
+        } else {
+            self.shell.command(&params.command, &params.args)
+        };
+        let mut child = command
+            .envs(&params.env)
+            .current_dir(&params.cwd)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(AcpError::IoError)?;
+
+        let limit = params.output_byte_limit.unwrap_or(DEFAULT_OUTPUT_BYTE_LIMIT);
+        let buffer = || OutputBuffer {
+            bytes: VecDeque::new(),
+            limit: usize::try_from(limit).unwrap_or(usize::MAX),
+            total: 0,
+        };
+        let output = Arc::new(StdMutex::new(Outputs {
+            combined: buffer(),
+            stderr: buffer(),
+        }));
+        let mut readers = Vec::new();
+        if let Some(stdout) = child.stdout.take() {
+            readers.push(tokio::spawn(capture(stdout, output.clone(), false)));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            readers.push(tokio::spawn(capture(stderr, output.clone(), true)));
+        }
+
+        let stdin = child.stdin.take();
+        processes.children.insert(
+            id.clone(),
+            Process {
+                child,
+                stdin,
+                output,
+                readers,
+            },
+        );
+        Ok(id)
+    }
+
+    async fn output(&self, terminal_id: &str) -> AcpResult<TerminalOutputResult> {
+        let mut processes = self.processes.lock().await;
+        let process = processes
+            .children
+            .get_mut(terminal_id)
+            .ok_or_else(|| AcpError::ResourceNotFound(terminal_id.to_string()))?;
+
+        // Check if process has exited
+        let exit_code = match process.child.try_wait() {
+            Ok(Some(status)) => Some(status.code()),
+            Ok(None) => None,
+            Err(e) => return Err(AcpError::IoError(e)),
+        };
+        if exit_code.is_some() {
+            // Let the readers drain the pipes, unless a background process
+            // the command started keeps them open
+            for reader in std::mem::take(&mut process.readers) {
+                let _ = timeout(Duration::from_secs(1), reader).await;
+            }
+        }
+
+        let mut outputs = process.output.lock().unwrap();
+        Ok(TerminalOutputResult {
+            output: outputs.combined.text(),
+            stderr: outputs.stderr.text(),
+            exited: exit_code.is_some(),
+            exit_code: exit_code.flatten(),
+            truncated: outputs.combined.truncated(),
+            total_bytes: outputs.combined.total,
+        })
+    }
+
+    async fn input(&self, terminal_id: &str, data: &str, eof: bool) -> AcpResult<()> {
+        let mut processes = self.processes.lock().await;
+        let process = processes
+            .children
+            .get_mut(terminal_id)
+            .ok_or_else(|| AcpError::ResourceNotFound(terminal_id.to_string()))?;
+        let stdin = process
+            .stdin
+            .as_mut()
+            .ok_or_else(|| AcpError::InvalidState(format!("stdin of {} is closed", terminal_id)))?;
+
+        stdin.write_all(data.as_bytes()).await?;
+        stdin.flush().await?;
+        if eof {
+            process.stdin = None;
+        }
+        Ok(())
+    }
+
+    async fn kill(&self, terminal_id: &str) -> AcpResult<()> {
+        let mut processes = self.processes.lock().await;
+        if let Some(mut process) = processes.children.remove(terminal_id) {
+            process.child.kill().await.ok();
+            Ok(())
+        } else {
+            Err(AcpError::ResourceNotFound(terminal_id.to_string()))
+        }
+    }
+
+    async fn release(&self, terminal_id: &str) -> AcpResult<()> {
+        let mut processes = self.processes.lock().await;
+        processes.children.remove(terminal_id);
+        Ok(())
+    }
+}
+
+/// The handler a client starts with.
+#[cfg(not(target_arch = "wasm32"))]
+pub(super) fn default_handler() -> ProcessTerminals {
+    ProcessTerminals::new()
+}
+
+/// The handler a client starts with.
+#[cfg(target_arch = "wasm32")]
+pub(super) fn default_handler() -> NoTerminals {
+    NoTerminals
+}
+
+/// Terminal handler for a client that can't run processes.
+#[cfg(target_arch = "wasm32")]
+pub(super) struct NoTerminals;
+
+#[cfg(target_arch = "wasm32")]
+#[async_trait]
+impl TerminalHandler for NoTerminals {
+    async fn create(&self, _params: TerminalCreateParams) -> AcpResult<String> {
+        Err(AcpError::CapabilityNotSupported("terminal/create".to_string()))
+    }
+
+    async fn output(&self, terminal_id: &str) -> AcpResult<TerminalOutputResult> {
+        Err(AcpError::ResourceNotFound(terminal_id.to_string()))
+    }
+
+    async fn kill(&self, terminal_id: &str) -> AcpResult<()> {
+        Err(AcpError::ResourceNotFound(terminal_id.to_string()))
+    }
+
+    async fn release(&self, terminal_id: &str) -> AcpResult<()> {
+        Err(AcpError::ResourceNotFound(terminal_id.to_string()))
+    }
+}
+
+/// The client's record of live terminals and the tool calls that embed them.
+#[derive(Default)]
+pub(super) struct TerminalRegistry {
+    live: HashSet<String>,
+    /// Terminals embedded in tool calls, keyed by tool call ID.
+    tool_calls: HashMap<String, String>,
+}
+
+impl TerminalRegistry {
+    pub(super) fn add(&mut self, terminal_id: &str) {
+        self.live.insert(terminal_id.to_string());
+    }
+
+    /// Associate a terminal with a tool call. Returns false if the terminal is unknown.
+    pub(super) fn attach_tool_call(&mut self, tool_call_id: &str, terminal_id: &str) -> bool {
+        if !self.live.contains(terminal_id) {
+            return false;
+        }
+        self.tool_calls
+            .insert(tool_call_id.to_string(), terminal_id.to_string());
+        true
+    }
+
+    pub(super) fn tool_call_terminal(&self, tool_call_id: &str) -> Option<String> {
+        self.tool_calls.get(tool_call_id).cloned()
+    }
+
+    pub(super) fn forget(&mut self, terminal_id: &str) {
+        self.live.remove(terminal_id);
+        self.tool_calls.retain(|_, id| id != terminal_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_process_terminal_lifecycle() {
+        let terminals = ProcessTerminals::new();
+        let id = terminals
+            .create(TerminalCreateParams {
+                cwd: "/".to_string(),
+                command: "echo hello; exit 3".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(id, "term_1");
+
+        let result = terminals.wait_for_exit(&id).await.unwrap();
+        assert_eq!(result.exit_code, 3);
+        assert_eq!(result.output, "hello\n");
+        assert!(!result.truncated);
+        assert!(terminals.output(&id).await.unwrap().exited);
+
+        terminals.kill(&id).await.unwrap();
+        let err = terminals.kill(&id).await.unwrap_err();
+        assert_eq!(err.code(), codes::RESOURCE_NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_process_terminal_output_limit() {
+        let terminals = ProcessTerminals::new();
+        let id = terminals
+            .create(TerminalCreateParams {
+                cwd: "/".to_string(),
+                command: "printf 'é'; yes | head -c 1000".to_string(),
+                output_byte_limit: Some(1001),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let result = terminals.wait_for_exit(&id).await.unwrap();
+        assert!(result.truncated);
+        assert_eq!(result.total_bytes, 1002);
+        // The limit falls inside the leading "é", which is dropped whole
+        assert_eq!(result.output, "y\n".repeat(500));
+    }
+
+    #[tokio::test]
+    async fn test_process_terminal_args_and_env() {
+        let terminals = ProcessTerminals::new();
+        let run = |params: TerminalCreateParams| async {
+            let id = terminals.create(params).await.unwrap();
+            terminals.wait_for_exit(&id).await.unwrap().output
+        };
+
+        let shell = run(TerminalCreateParams {
+            cwd: "/".to_string(),
+            command: r#"echo "$GREETING, $1""#.to_string(),
+            args: vec!["it's $HOME".to_string()],
+            env: HashMap::from([("GREETING".to_string(), "hi".to_string())]),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(shell, "hi, it's $HOME\n");
+
+        let direct = run(TerminalCreateParams {
+            cwd: "/".to_string(),
+            command: "printf".to_string(),
+            args: vec!["%s|".to_string(), "a b".to_string(), "$c".to_string()],
+            no_shell: true,
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(direct, "a b|$c|");
+    }
+
+    #[tokio::test]
+    async fn test_process_terminal_input() {
+        let terminals = ProcessTerminals::new();
+        let id = terminals
+            .create(TerminalCreateParams {
+                cwd: "/".to_string(),
+                command: "read answer; echo \"got $answer\"; cat".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        terminals.input(&id, "yes\n", false).await.unwrap();
+        terminals.input(&id, "rest", true).await.unwrap();
+        let result = terminals.wait_for_exit(&id).await.unwrap();
+        assert_eq!(result.output, "got yes\nrest");
+
+        let err = terminals.input(&id, "more", false).await.unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_STATE);
+    }
+
+    #[tokio::test]
+    async fn test_custom_shell() {
+        assert_eq!(Shell::default(), if cfg!(windows) { Shell::Cmd } else { Shell::Sh });
+
+        let terminals = ProcessTerminals::with_shell(Shell::Custom {
+            program: "sh".to_string(),
+            flag: "-c".to_string(),
+        });
+        let id = terminals
+            .create(TerminalCreateParams {
+                cwd: "/".to_string(),
+                command: "echo \"$0 $1\"".to_string(),
+                args: vec!["zero".to_string(), "one".to_string()],
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(terminals.wait_for_exit(&id).await.unwrap().output, "zero one\n");
+    }
+}
@@ -0,0 +1,53 @@
+//! The streams the client talks to an agent over.
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::rt::process::{Child, ChildStderr};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::protocol::*;
+
+/// The streams to an agent: a spawned process's pipes, streams handed to
+/// [`ClientBuilder::attach`](super::ClientBuilder::attach), or a WebSocket.
+pub(super) struct Transport {
+    /// The child process running the agent, if spawned.
+    pub(super) child: Option<Child>,
+    /// What the agent reads.
+    pub(super) input: Box<dyn AsyncWrite + Send + Unpin>,
+    /// What the agent writes.
+    pub(super) output: Box<dyn AsyncRead + Send + Unpin>,
+    /// The agent's stderr, if piped.
+    pub(super) stderr: Option<ChildStderr>,
+}
+
+impl Transport {
+    /// Take the pipes of a freshly spawned `child`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(super) fn from_child(mut child: Child) -> AcpResult<Self> {
+        let input = child.stdin.take().ok_or_else(|| {
+            AcpError::InternalError("Failed to get stdin".to_string())
+        })?;
+        let output = child.stdout.take().ok_or_else(|| {
+            AcpError::InternalError("Failed to get stdout".to_string())
+        })?;
+        Ok(Self {
+            stderr: child.stderr.take(),
+            input: Box::new(input),
+            output: Box::new(output),
+            child: Some(child),
+        })
+    }
+
+    /// Talk to an agent that reads `input` and writes `output`.
+    pub(super) fn from_streams<R, W>(output: R, input: W) -> Self
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        Self {
+            child: None,
+            input: Box::new(input),
+            output: Box::new(output),
+            stderr: None,
+        }
+    }
+}
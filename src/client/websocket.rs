@@ -0,0 +1,142 @@
+//! Talking to an agent over a WebSocket, from a browser.
+//!
+//! A browser can't spawn an agent, so it reaches one through a server that
+//! relays a WebSocket to the agent's stdio. Each frame carries one message.
+
+use serde_json::Value;
+use std::io;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream};
+use tokio::sync::mpsc;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::JsCast;
+use web_sys::{BinaryType, CloseEvent, Event, MessageEvent, WebSocket};
+
+use super::{rt, Client, ClientBuilder};
+use crate::protocol::*;
+
+/// Bytes buffered between the socket and the client in each direction.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// What the socket's callbacks report.
+enum SocketEvent {
+    Open,
+    Message(String),
+    Closed(String),
+}
+
+impl ClientBuilder {
+    /// Connect to an agent behind the WebSocket at `url` instead of
+    /// spawning the command.
+    ///
+    /// Waits for the socket to open. The agent's stderr settings don't
+    /// apply; closing the client closes the socket.
+    pub async fn websocket(self, url: &str) -> AcpResult<Client> {
+        let (output, received) = tokio::io::duplex(BUFFER_SIZE);
+        let (input, sent) = tokio::io::duplex(BUFFER_SIZE);
+        open(url, received, sent).await?;
+        self.attach(output, input)
+    }
+}
+
+impl Client {
+    /// Connect to an agent behind the WebSocket at `url`.
+    ///
+    /// Use [`ClientBuilder::websocket`] to configure the client first.
+    pub async fn websocket(url: &str) -> AcpResult<Self> {
+        Self::builder("").websocket(url).await
+    }
+}
+
+/// Open the socket at `url`, then copy its frames to `received` and the
+/// lines of `sent` to it until either side closes.
+async fn open(url: &str, mut received: DuplexStream, sent: DuplexStream) -> AcpResult<()> {
+    let socket = WebSocket::new(url)
+        .map_err(|_| AcpError::InvalidParams(format!("invalid WebSocket URL {}", url)))?;
+    socket.set_binary_type(BinaryType::Arraybuffer);
+
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let on_open = callback(&events_tx, |_: Event| Some(SocketEvent::Open));
+    let on_message = callback(&events_tx, |event: MessageEvent| {
+        let data = event.data();
+        let text = match data.dyn_into::<js_sys::ArrayBuffer>() {
+            Ok(buffer) => String::from_utf8_lossy(&js_sys::Uint8Array::new(&buffer).to_vec()).into_owned(),
+            Err(data) => data.as_string()?,
+        };
+        Some(SocketEvent::Message(text))
+    });
+    let on_close = callback(&events_tx, |event: CloseEvent| {
+        Some(SocketEvent::Closed(format!("closed with code {}", event.code())))
+    });
+    let on_error = callback(&events_tx, |_: Event| Some(SocketEvent::Closed("failed".to_string())));
+    socket.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+    socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+    socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+    socket.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+
+    match events.recv().await {
+        Some(SocketEvent::Open) => {}
+        event => {
+            let reason = match event {
+                Some(SocketEvent::Closed(reason)) => reason,
+                _ => "failed".to_string(),
+            };
+            return Err(AcpError::IoError(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("WebSocket {} {}", url, reason),
+            )));
+        }
+    }
+
+    rt::spawn(async move {
+        let mut lines = BufReader::new(sent).lines();
+        loop {
+            tokio::select! {
+                line = lines.next_line() => match line {
+                    Ok(Some(line)) => {
+                        if socket.send_with_str(&line).is_err() {
+                            break;
+                        }
+                    }
+                    // The client closed its input
+                    _ => break,
+                },
+                event = events.recv() => match event {
+                    Some(SocketEvent::Message(text)) => {
+                        // The client reads a message per line
+                        let line = if text.contains('\n') {
+                            serde_json::from_str::<Value>(&text).map_or(text, |v| v.to_string())
+                        } else {
+                            text
+                        };
+                        if received.write_all(line.as_bytes()).await.is_err()
+                            || received.write_all(b"\n").await.is_err()
+                        {
+                            break;
+                        }
+                    }
+                    _ => break,
+                },
+            }
+        }
+        socket.set_onopen(None);
+        socket.set_onmessage(None);
+        socket.set_onclose(None);
+        socket.set_onerror(None);
+        let _ = socket.close();
+        drop((on_open, on_message, on_close, on_error));
+    });
+    Ok(())
+}
+
+/// A JS callback that turns its event into a [`SocketEvent`] on `events`.
+fn callback<E: wasm_bindgen::convert::FromWasmAbi + 'static>(
+    events: &mpsc::UnboundedSender<SocketEvent>,
+    to_event: impl Fn(E) -> Option<SocketEvent> + 'static,
+) -> Closure<dyn FnMut(E)> {
+    let events = events.clone();
+    Closure::new(move |event: E| {
+        if let Some(event) = to_event(event) {
+            let _ = events.send(event);
+        }
+    })
+}
@@ -0,0 +1,299 @@
+//! C ABI for embedding the client in editors not written in Rust.
+//!
+//! Enabled with the `ffi` feature. Editors in C, C++ or Zig, and plugin
+//! hosts like Neovim through LuaJIT's FFI, spawn an agent with
+//! [`heroacp_client_spawn`], receive its session updates through a callback
+//! and send prompts with [`heroacp_client_prompt`]. The declarations are in
+//! `include/heroacp.h`; build the library with:
+//!
+//! ```text
+//! cargo rustc --release --lib --features ffi --crate-type cdylib
+//! ```
+//!
+//! Each client runs its own Tokio runtime, so the functions block the
+//! calling thread instead of returning futures. Updates are passed as the
+//! JSON of their `session/update` params, including types this library
+//! doesn't know, and strings are UTF-8. Changes in an MCP server's state come
+//! the same way, with the type `mcp_server_status`, the
+//! [`McpServerStatus`] as `data` and no `session_id`. A terminal embedded in
+//! a tool call is only seen in its `tool_call_update`. Permission requests
+//! from the agent are rejected.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::ptr;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tokio::runtime::Runtime;
+
+use crate::client::{Client, ClientSetup, Connection, UpdateHandler};
+use crate::protocol::*;
+
+/// How long [`heroacp_client_free`] lets the agent wind down before killing it.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// Called with the user data given at registration and the JSON of a
+/// session update. The string is only valid during the call.
+pub type HeroacpUpdateCallback = extern "C" fn(user_data: *mut c_void, update_json: *const c_char);
+
+/// An agent spawned by [`heroacp_client_spawn`], with its session open.
+pub struct HeroacpClient {
+    runtime: Runtime,
+    connection: Connection,
+}
+
+thread_local! {
+    /// Message of the last error on this thread.
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: impl ToString) {
+    let message = CString::new(message.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Read a C string argument, recording an error if it's null or not UTF-8.
+unsafe fn arg<'a>(name: &str, s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        set_last_error(format!("{} is null", name));
+        return None;
+    }
+    match CStr::from_ptr(s).to_str() {
+        Ok(s) => Some(s),
+        Err(_) => {
+            set_last_error(format!("{} is not UTF-8", name));
+            None
+        }
+    }
+}
+
+/// Message of the last error on the calling thread, or null if there was
+/// none.
+///
+/// The string stays valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn heroacp_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
+
+/// Spawn the agent `command`, initialize it as `client_name` and open a
+/// session in the current directory.
+///
+/// Returns null on failure; see [`heroacp_last_error`]. Free the client
+/// with [`heroacp_client_free`].
+///
+/// # Safety
+///
+/// The arguments must be null or point to NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn heroacp_client_spawn(
+    command: *const c_char,
+    client_name: *const c_char,
+    client_version: *const c_char,
+) -> *mut HeroacpClient {
+    let (Some(command), Some(name), Some(version)) = (
+        arg("command", command),
+        arg("client_name", client_name),
+        arg("client_version", client_version),
+    ) else {
+        return ptr::null_mut();
+    };
+    let runtime = match Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            set_last_error(e);
+            return ptr::null_mut();
+        }
+    };
+    match runtime.block_on(Client::connect(command, ClientSetup::new(name, version))) {
+        Ok(connection) => Box::into_raw(Box::new(HeroacpClient { runtime, connection })),
+        Err(e) => {
+            set_last_error(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Call `callback` with every session update from the agent, and with
+/// changes in the state of its MCP servers, replacing any earlier callback.
+///
+/// Updates arrive on a thread of the client's runtime, in the order the
+/// agent sent them. `user_data` is passed through as is.
+///
+/// # Safety
+///
+/// `client` must come from [`heroacp_client_spawn`] and not be freed.
+/// `user_data` must be safe to use from the runtime's threads.
+#[no_mangle]
+pub unsafe extern "C" fn heroacp_client_on_update(
+    client: *mut HeroacpClient,
+    callback: HeroacpUpdateCallback,
+    user_data: *mut c_void,
+) -> c_int {
+    let Some(client) = client.as_ref() else {
+        set_last_error("client is null");
+        return -1;
+    };
+    let handler = CallbackHandler { callback, user_data };
+    client
+        .runtime
+        .block_on(client.connection.client.set_update_handler(Box::new(handler)));
+    0
+}
+
+/// Send `text` as a prompt to the client's session and wait for the turn
+/// to end.
+///
+/// Returns 0 once the agent answered, or -1 on failure; see
+/// [`heroacp_last_error`].
+///
+/// # Safety
+///
+/// `client` must come from [`heroacp_client_spawn`] and not be freed, and
+/// `text` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn heroacp_client_prompt(client: *mut HeroacpClient, text: *const c_char) -> c_int {
+    let Some(client) = client.as_ref() else {
+        set_last_error("client is null");
+        return -1;
+    };
+    let Some(text) = arg("text", text) else {
+        return -1;
+    };
+    let session = client.connection.session();
+    match client.runtime.block_on(session.prompt(vec![ContentBlock::text(text)])) {
+        Ok(_) => 0,
+        Err(e) => {
+            set_last_error(e);
+            -1
+        }
+    }
+}
+
+/// Cancel the prompt running in the client's session.
+///
+/// Call it from another thread than the one waiting in
+/// [`heroacp_client_prompt`]. Returns 0, or -1 on failure.
+///
+/// # Safety
+///
+/// `client` must come from [`heroacp_client_spawn`] and not be freed.
+#[no_mangle]
+pub unsafe extern "C" fn heroacp_client_cancel(client: *mut HeroacpClient) -> c_int {
+    let Some(client) = client.as_ref() else {
+        set_last_error("client is null");
+        return -1;
+    };
+    match client.runtime.block_on(client.connection.session().cancel()) {
+        Ok(()) => 0,
+        Err(e) => {
+            set_last_error(e);
+            -1
+        }
+    }
+}
+
+/// Shut the agent down and free the client. Null is ignored.
+///
+/// # Safety
+///
+/// `client` must come from [`heroacp_client_spawn`], not be freed already
+/// and not be in use on another thread.
+#[no_mangle]
+pub unsafe extern "C" fn heroacp_client_free(client: *mut HeroacpClient) {
+    if client.is_null() {
+        return;
+    }
+    let client = Box::from_raw(client);
+    let HeroacpClient { runtime, mut connection } = *client;
+    let _ = runtime.block_on(connection.client.shutdown(SHUTDOWN_GRACE));
+}
+
+/// Passes session updates to a C callback as JSON.
+struct CallbackHandler {
+    callback: HeroacpUpdateCallback,
+    user_data: *mut c_void,
+}
+
+// The caller of `heroacp_client_on_update` vouches for `user_data`.
+unsafe impl Send for CallbackHandler {}
+unsafe impl Sync for CallbackHandler {}
+
+impl CallbackHandler {
+    fn send(&self, session_id: &str, update_type: SessionUpdateType) {
+        let update = SessionUpdate {
+            session_id: session_id.to_string(),
+            update_type,
+        };
+        if let Ok(json) = serde_json::to_string(&update) {
+            self.send_json(json);
+        }
+    }
+
+    fn send_json(&self, json: String) {
+        let Ok(json) = CString::new(json) else {
+            return;
+        };
+        (self.callback)(self.user_data, json.as_ptr());
+    }
+}
+
+impl UpdateHandler for CallbackHandler {
+    fn on_agent_message(&self, session_id: &str, text: &str) {
+        let text = text.to_string();
+        self.send(session_id, SessionUpdateType::AgentMessageChunk { text });
+    }
+
+    fn on_agent_thought(&self, session_id: &str, text: &str) {
+        let text = text.to_string();
+        self.send(session_id, SessionUpdateType::AgentThoughtChunk { text });
+    }
+
+    fn on_audio_chunk(&self, session_id: &str, chunk: &AudioChunk) {
+        self.send(session_id, SessionUpdateType::AudioChunk(chunk.clone()));
+    }
+
+    fn on_tool_call(&self, session_id: &str, tool: &ToolCall) {
+        self.send(session_id, SessionUpdateType::ToolCall(tool.clone()));
+    }
+
+    fn on_tool_update(&self, session_id: &str, update: &ToolCallUpdate) {
+        self.send(session_id, SessionUpdateType::ToolCallUpdate(update.clone()));
+    }
+
+    fn on_plan(&self, session_id: &str, plan: &Plan) {
+        self.send(session_id, SessionUpdateType::Plan(plan.clone()));
+    }
+
+    fn on_mode_change(&self, session_id: &str, mode: &str) {
+        let mode = mode.to_string();
+        self.send(session_id, SessionUpdateType::ModeChange { mode });
+    }
+
+    fn on_model_change(&self, session_id: &str, model: &str) {
+        let model = model.to_string();
+        self.send(session_id, SessionUpdateType::ModelChange { model });
+    }
+
+    fn on_status(&self, session_id: &str, state: AgentState) {
+        self.send(session_id, SessionUpdateType::Status { state });
+    }
+
+    fn on_queued(&self, session_id: &str, prompt_id: &str, position: usize) {
+        let prompt_id = prompt_id.to_string();
+        self.send(session_id, SessionUpdateType::Queued { prompt_id, position });
+    }
+
+    fn on_done(&self, session_id: &str) {
+        self.send(session_id, SessionUpdateType::Done);
+    }
+
+    fn on_mcp_server_status(&self, status: &McpServerStatus) {
+        self.send_json(json!({ "type": "mcp_server_status", "data": status }).to_string());
+    }
+
+    fn on_unknown_update(&self, session_id: &str, update_type: &str, data: &Value) {
+        self.send_json(json!({ "session_id": session_id, "type": update_type, "data": data }).to_string());
+    }
+}
@@ -9,15 +9,16 @@
 //! - **Server SDK**: Build ACP-compliant AI agents
 //! - **Client SDK**: Build ACP-compliant editors/clients
 //! - **Protocol Types**: Complete message type definitions
+//! - **Bridges**: Agent edits as LSP workspace edits, and relaying between
+//!   ACP dialects
 //! - **Async/Await**: Built on Tokio for async operations
 //!
 //! ## Quick Start - Server
 //!
 //! ```rust,ignore
-//! use heroacp::server::{Agent, Server};
+//! use heroacp::server::{Agent, AgentContext, Server, SessionHandle};
 //! use heroacp::protocol::*;
 //! use async_trait::async_trait;
-//! use tokio::sync::mpsc;
 //!
 //! struct MyAgent;
 //!
@@ -30,10 +31,10 @@
 //!             instructions: None,
 //!         })
 //!     }
-//!     async fn session_new(&self, params: SessionNewParams) -> AcpResult<SessionNewResult> {
+//!     async fn session_new(&self, params: SessionNewParams, ctx: AgentContext) -> AcpResult<SessionNewResult> {
 //!         Ok(SessionNewResult { session_id: params.session_id })
 //!     }
-//!     async fn session_prompt(&self, params: SessionPromptParams, tx: mpsc::Sender<SessionUpdate>) -> AcpResult<SessionPromptResult> {
+//!     async fn session_prompt(&self, params: SessionPromptParams, session: SessionHandle, ctx: AgentContext) -> AcpResult<SessionPromptResult> {
 //!         Ok(SessionPromptResult { status: "ok".into() })
 //!     }
 //! }
@@ -58,8 +59,29 @@
 //! }
 //! ```
 
+// Lets code generated by `heroacp-macros` refer to `::heroacp` inside this crate too.
+extern crate self as heroacp;
+
+// In a browser only the client is available, over a WebSocket
+#[cfg(all(target_arch = "wasm32", not(feature = "wasm")))]
+compile_error!("building for wasm32 needs the `wasm` feature");
+
 pub mod protocol;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod server;
 pub mod client;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod mcp;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod bridge;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod testing;
+#[cfg(all(feature = "ffi", not(target_arch = "wasm32")))]
+pub mod ffi;
 
 pub use protocol::*;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use serde_json;
+}
@@ -0,0 +1,550 @@
+//! Connection to a single MCP server.
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio_util::sync::CancellationToken;
+
+use super::sampling::{self, Sampler};
+use super::types::*;
+use crate::protocol::*;
+
+/// How long an MCP server may take to answer the handshake.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Requests waiting for a response; `None` once the server has gone away.
+type Pending = Arc<Mutex<Option<HashMap<RequestId, oneshot::Sender<JsonRpcResponse>>>>>;
+
+/// Connection to an MCP server.
+///
+/// Cloning is cheap; all clones share the same connection. The server
+/// process is stopped when the last clone is dropped.
+#[derive(Clone)]
+pub struct McpClient {
+    name: String,
+    outgoing: mpsc::Sender<String>,
+    pending: Pending,
+    next_id: Arc<AtomicI64>,
+    info: Arc<McpInitializeResult>,
+    closed: CancellationToken,
+    process: Option<Arc<Child>>,
+}
+
+impl std::fmt::Debug for McpClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("McpClient")
+            .field("name", &self.name)
+            .field("server_info", &self.info.server_info)
+            .finish_non_exhaustive()
+    }
+}
+
+impl McpClient {
+    /// Start the MCP server described by `server` and connect to it over
+    /// stdio.
+    ///
+    /// `server.url` is the command line to run, split on whitespace and
+    /// optionally prefixed with `stdio://`; `server.credentials` are passed to
+    /// it as environment variables.
+    ///
+    /// With the `mcp-http` feature, `http://` and `https://` URLs connect to
+    /// a hosted server over the streamable HTTP transport instead, sending
+    /// `server.credentials` as request headers. Other URLs fail with
+    /// `CAPABILITY_NOT_SUPPORTED`.
+    pub async fn connect(server: &McpServer) -> AcpResult<Self> {
+        Self::open(server, None).await
+    }
+
+    /// Like [`connect`](Self::connect), but offer the server sampling and
+    /// answer its `sampling/createMessage` requests with `sampler`.
+    pub async fn connect_with_sampler(server: &McpServer, sampler: Arc<dyn Sampler>) -> AcpResult<Self> {
+        Self::open(server, Some(sampler)).await
+    }
+
+    async fn open(server: &McpServer, sampler: Option<Arc<dyn Sampler>>) -> AcpResult<Self> {
+        let command = match server.url.strip_prefix("stdio://") {
+            Some(command) => command,
+            #[cfg(feature = "mcp-http")]
+            None if server.url.starts_with("http://") || server.url.starts_with("https://") => {
+                let (outgoing, outgoing_rx) = mpsc::channel::<String>(64);
+                let inbound = Inbound::new(&server.name, &outgoing, sampler);
+                super::http::spawn(&server.url, &server.credentials, outgoing_rx, inbound.clone())?;
+                return Self::handshake(outgoing, &inbound).await;
+            }
+            None if server.url.contains("://") => {
+                return Err(AcpError::CapabilityNotSupported(format!(
+                    "MCP transport for {}",
+                    server.url
+                )))
+            }
+            None => &server.url,
+        };
+        let mut words = command.split_whitespace();
+        let program = words.next().ok_or_else(|| {
+            AcpError::InvalidParams(format!("MCP server {} has no command", server.name))
+        })?;
+        let mut child = Command::new(program)
+            .args(words)
+            .envs(&server.credentials)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| AcpError::ResourceNotFound(format!("{}: {}", program, e)))?;
+
+        let stdin = child.stdin.take().ok_or(AcpError::ConnectionClosed)?;
+        let stdout = child.stdout.take().ok_or(AcpError::ConnectionClosed)?;
+        let mut client = Self::attach(&server.name, stdout, stdin, sampler).await?;
+        client.process = Some(Arc::new(child));
+        Ok(client)
+    }
+
+    /// Connect to an MCP server that is already running, speaking
+    /// newline-delimited JSON-RPC over `reader` and `writer`.
+    pub async fn from_streams<R, W>(name: impl Into<String>, reader: R, writer: W) -> AcpResult<Self>
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        Self::attach(&name.into(), reader, writer, None).await
+    }
+
+    /// Like [`from_streams`](Self::from_streams), but offer the server
+    /// sampling and answer its `sampling/createMessage` requests with
+    /// `sampler`.
+    pub async fn from_streams_with_sampler<R, W>(
+        name: impl Into<String>,
+        reader: R,
+        writer: W,
+        sampler: Arc<dyn Sampler>,
+    ) -> AcpResult<Self>
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        Self::attach(&name.into(), reader, writer, Some(sampler)).await
+    }
+
+    async fn attach<R, W>(name: &str, reader: R, writer: W, sampler: Option<Arc<dyn Sampler>>) -> AcpResult<Self>
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        let (outgoing, mut outgoing_rx) = mpsc::channel::<String>(64);
+        let inbound = Inbound::new(name, &outgoing, sampler);
+        let reader_inbound = inbound.clone();
+
+        tokio::spawn(async move {
+            let mut writer = writer;
+            while let Some(msg) = outgoing_rx.recv().await {
+                let line = format!("{}\n", msg);
+                if writer.write_all(line.as_bytes()).await.is_err() || writer.flush().await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                reader_inbound.receive(&line).await;
+            }
+            reader_inbound.close().await;
+        });
+
+        Self::handshake(outgoing, &inbound).await
+    }
+
+    /// Perform the MCP handshake over a transport that sends what is put
+    /// into `outgoing` and hands what it receives to `inbound`.
+    async fn handshake(outgoing: mpsc::Sender<String>, inbound: &Inbound) -> AcpResult<Self> {
+        let mut client = Self {
+            name: inbound.server.clone(),
+            outgoing,
+            pending: inbound.pending.clone(),
+            next_id: Arc::new(AtomicI64::new(1)),
+            info: Arc::default(),
+            closed: inbound.closed.clone(),
+            process: None,
+        };
+        let capabilities = match inbound.sampler {
+            Some(_) => serde_json::json!({ "sampling": {} }),
+            None => serde_json::json!({}),
+        };
+        let params = serde_json::json!({
+            "protocolVersion": MCP_PROTOCOL_VERSION,
+            "capabilities": capabilities,
+            "clientInfo": { "name": "heroacp", "version": env!("CARGO_PKG_VERSION") },
+        });
+        let info = tokio::time::timeout(HANDSHAKE_TIMEOUT, client.request("initialize", params))
+            .await
+            .map_err(|_| AcpError::Timeout)??;
+        client.info = Arc::new(info);
+        client.notify("notifications/initialized").await?;
+        Ok(client)
+    }
+
+    /// Name of the server, as configured by the editor.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// What the server reported in the handshake.
+    pub fn info(&self) -> &McpInitializeResult {
+        &self.info
+    }
+
+    /// Check whether the server offers `feature`, such as `"tools"`.
+    pub fn supports(&self, feature: &str) -> bool {
+        self.info.capabilities.get(feature).is_some()
+    }
+
+    /// Check that the server is still answering.
+    ///
+    /// Fails with `CONNECTION_CLOSED` if the connection is lost; an error
+    /// the server answers with still shows it is alive.
+    pub async fn ping(&self) -> AcpResult<()> {
+        self.request::<Value>("ping", serde_json::json!({})).await.map(|_| ())
+    }
+
+    /// Check whether the connection to the server is lost, e.g. because its
+    /// process exited.
+    pub fn is_closed(&self) -> bool {
+        self.closed.is_cancelled()
+    }
+
+    /// Wait until the connection to the server is lost.
+    pub async fn closed(&self) {
+        self.closed.cancelled().await
+    }
+
+    /// List the server's tools. Servers that don't offer tools have none.
+    pub async fn list_tools(&self) -> AcpResult<Vec<McpTool>> {
+        if !self.supports("tools") {
+            return Ok(Vec::new());
+        }
+        self.list("tools/list", "tools").await
+    }
+
+    /// List the server's resources. Servers that don't offer resources have
+    /// none.
+    pub async fn list_resources(&self) -> AcpResult<Vec<McpResource>> {
+        if !self.supports("resources") {
+            return Ok(Vec::new());
+        }
+        self.list("resources/list", "resources").await
+    }
+
+    /// Call a tool on the server.
+    ///
+    /// A tool that ran but failed is not an error here; check
+    /// [`McpToolResult::is_error`].
+    pub async fn call_tool(&self, name: &str, arguments: Value) -> AcpResult<McpToolResult> {
+        let params = serde_json::json!({ "name": name, "arguments": arguments });
+        self.request("tools/call", params).await
+    }
+
+    /// Read a resource from the server.
+    pub async fn read_resource(&self, uri: &str) -> AcpResult<Vec<McpResourceContents>> {
+        let mut result: Value = self.request("resources/read", serde_json::json!({ "uri": uri })).await?;
+        serde_json::from_value(result["contents"].take()).map_err(|e| AcpError::InvalidParams(e.to_string()))
+    }
+
+    /// Collect every page of a paginated list.
+    async fn list<T: DeserializeOwned>(&self, method: &str, key: &str) -> AcpResult<Vec<T>> {
+        let mut items = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => serde_json::json!({ "cursor": cursor }),
+                None => serde_json::json!({}),
+            };
+            let mut page: Value = self.request(method, params).await?;
+            let page_items: Vec<T> = serde_json::from_value(page[key].take())
+                .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
+            items.extend(page_items);
+            cursor = page["nextCursor"].as_str().map(str::to_string);
+            if cursor.is_none() {
+                return Ok(items);
+            }
+        }
+    }
+
+    async fn request<T: DeserializeOwned>(&self, method: &str, params: Value) -> AcpResult<T> {
+        let id = RequestId::Number(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let (tx, rx) = oneshot::channel();
+        match self.pending.lock().await.as_mut() {
+            Some(pending) => pending.insert(id.clone(), tx),
+            None => return Err(AcpError::ConnectionClosed),
+        };
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: Some(id),
+            method: method.to_string(),
+            params: Some(params),
+        };
+        self.outgoing
+            .send(serde_json::to_string(&request)?)
+            .await
+            .map_err(|e| AcpError::ChannelError(e.to_string()))?;
+
+        let response = rx.await.map_err(|_| AcpError::ConnectionClosed)?;
+        if let Some(error) = response.error {
+            return Err(error.into());
+        }
+        let result = response.result.unwrap_or(Value::Null);
+        serde_json::from_value(result).map_err(|e| AcpError::InvalidParams(e.to_string()))
+    }
+
+    async fn notify(&self, method: &str) -> AcpResult<()> {
+        let notification = JsonRpcNotification {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params: None,
+        };
+        self.outgoing
+            .send(serde_json::to_string(&notification)?)
+            .await
+            .map_err(|e| AcpError::ChannelError(e.to_string()))
+    }
+}
+
+/// Messages the MCP server sends to the client, whatever the transport.
+///
+/// Cloning is cheap; all clones share the same connection.
+#[derive(Clone)]
+pub(super) struct Inbound {
+    server: String,
+    pending: Pending,
+    closed: CancellationToken,
+    // Weak, so replies to the server don't keep the connection open after
+    // the last client is dropped
+    replies: mpsc::WeakSender<String>,
+    sampler: Option<Arc<dyn Sampler>>,
+}
+
+impl Inbound {
+    fn new(server: &str, outgoing: &mpsc::Sender<String>, sampler: Option<Arc<dyn Sampler>>) -> Self {
+        Self {
+            server: server.to_string(),
+            pending: Arc::new(Mutex::new(Some(HashMap::new()))),
+            closed: CancellationToken::new(),
+            replies: outgoing.downgrade(),
+            sampler,
+        }
+    }
+
+    /// Handle a message or batch of messages from the server.
+    pub(super) async fn receive(&self, text: &str) {
+        match serde_json::from_str::<Value>(text) {
+            Ok(Value::Array(batch)) => {
+                for msg in batch {
+                    self.receive_one(msg).await;
+                }
+            }
+            Ok(msg) => self.receive_one(msg).await,
+            Err(_) => {}
+        }
+    }
+
+    async fn receive_one(&self, msg: Value) {
+        match (msg.get("method"), msg.get("id")) {
+            (None, Some(_)) => {
+                if let Ok(response) = serde_json::from_value::<JsonRpcResponse>(msg) {
+                    self.complete(response).await;
+                }
+            }
+            (Some(method), Some(id)) => match (method.as_str().unwrap_or_default(), &self.sampler) {
+                ("ping", _) => self.reply(id.clone(), Ok(serde_json::json!({}))).await,
+                ("sampling/createMessage", Some(sampler)) => {
+                    // The user may take a while to approve, so don't hold up
+                    // other messages
+                    let (inbound, sampler) = (self.clone(), sampler.clone());
+                    let (id, params) = (id.clone(), msg["params"].clone());
+                    tokio::spawn(async move {
+                        let result = sampling::create_message(sampler.as_ref(), &inbound.server, params).await;
+                        inbound.reply(id, result).await;
+                    });
+                }
+                // Roots are not offered
+                (method, _) => {
+                    let error = AcpError::MethodNotFound(method.to_string());
+                    self.reply(id.clone(), Err(error)).await;
+                }
+            },
+            // Notifications, such as tools/list_changed, are ignored
+            _ => {}
+        }
+    }
+
+    /// Answer the server's request `id`.
+    async fn reply(&self, id: Value, result: AcpResult<Value>) {
+        let id = serde_json::from_value(id).unwrap_or(RequestId::Null);
+        let response = match result {
+            Ok(result) => JsonRpcResponse {
+                jsonrpc: "2.0".to_string(),
+                id,
+                result: Some(result),
+                error: None,
+            },
+            Err(e) => JsonRpcResponse {
+                jsonrpc: "2.0".to_string(),
+                id,
+                result: None,
+                error: Some(JsonRpcError {
+                    code: e.code(),
+                    message: e.message(),
+                    data: e.data().cloned(),
+                }),
+            },
+        };
+        if let (Some(tx), Ok(msg)) = (self.replies.upgrade(), serde_json::to_string(&response)) {
+            let _ = tx.send(msg).await;
+        }
+    }
+
+    /// Fail the request `id` with `error`, e.g. when the transport couldn't
+    /// deliver it.
+    #[cfg_attr(not(feature = "mcp-http"), allow(dead_code))]
+    pub(super) async fn fail(&self, id: RequestId, error: AcpError) {
+        let error = JsonRpcError {
+            code: error.code(),
+            message: error.message(),
+            data: error.data().cloned(),
+        };
+        self.complete(JsonRpcResponse {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: None,
+            error: Some(error),
+        })
+        .await;
+    }
+
+    async fn complete(&self, response: JsonRpcResponse) {
+        let tx = self
+            .pending
+            .lock()
+            .await
+            .as_mut()
+            .and_then(|pending| pending.remove(&response.id));
+        if let Some(tx) = tx {
+            let _ = tx.send(response);
+        }
+    }
+
+    /// Fail the requests still waiting and any sent from now on.
+    pub(super) async fn close(&self) {
+        self.pending.lock().await.take();
+        self.closed.cancel();
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Connect to an in-memory MCP server offering an `echo` and an `add`
+    /// tool, listed on two pages.
+    pub(crate) async fn fake_client(name: &str) -> McpClient {
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let (client_read, client_write) = tokio::io::split(client_io);
+        let (server_read, mut server_write) = tokio::io::split(server_io);
+
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(server_read).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let msg: Value = serde_json::from_str(&line).unwrap();
+                let Some(id) = msg.get("id").cloned() else {
+                    continue;
+                };
+                let params = &msg["params"];
+                let reply = match msg["method"].as_str().unwrap_or_default() {
+                    "initialize" => json!({ "result": {
+                        "protocolVersion": MCP_PROTOCOL_VERSION,
+                        "capabilities": { "tools": {} },
+                        "serverInfo": { "name": "fake", "version": "1.0" },
+                    }}),
+                    "tools/list" if params.get("cursor").is_none() => json!({ "result": {
+                        "tools": [{
+                            "name": "echo",
+                            "description": "Echo the text",
+                            "inputSchema": { "type": "object", "properties": { "text": { "type": "string" } } },
+                        }],
+                        "nextCursor": "2",
+                    }}),
+                    "tools/list" => json!({ "result": {
+                        "tools": [{ "name": "add", "inputSchema": { "type": "object" } }],
+                    }}),
+                    "tools/call" if params["name"] == "echo" => json!({ "result": {
+                        "content": [{ "type": "text", "text": params["arguments"]["text"] }],
+                    }}),
+                    "tools/call" => json!({ "result": {
+                        "content": [{ "type": "text", "text": "no such tool" }],
+                        "isError": true,
+                    }}),
+                    _ => json!({ "error": { "code": codes::METHOD_NOT_FOUND, "message": "Method not found" } }),
+                };
+                let mut reply = reply;
+                reply["jsonrpc"] = json!("2.0");
+                reply["id"] = id;
+                let line = format!("{}\n", reply);
+                if server_write.write_all(line.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        McpClient::from_streams(name, client_read, client_write).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_handshake_and_tools() {
+        let client = fake_client("fake").await;
+        assert_eq!(client.name(), "fake");
+        assert_eq!(client.info().server_info.name, "fake");
+        assert!(client.supports("tools"));
+
+        let tools = client.list_tools().await.unwrap();
+        let names: Vec<_> = tools.iter().map(|tool| tool.name.as_str()).collect();
+        assert_eq!(names, ["echo", "add"]);
+        assert_eq!(tools[0].description.as_deref(), Some("Echo the text"));
+        assert!(client.list_resources().await.unwrap().is_empty());
+
+        let result = client.call_tool("echo", json!({ "text": "hi" })).await.unwrap();
+        assert!(!result.is_error);
+        assert_eq!(result.text(), "hi");
+        let result = client.call_tool("missing", json!({})).await.unwrap();
+        assert!(result.is_error);
+
+        let err = client.read_resource("file:///a").await.unwrap_err();
+        assert_eq!(err.code(), codes::METHOD_NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_connect_failures() {
+        let server = |url: &str| McpServer {
+            name: "db".to_string(),
+            url: url.to_string(),
+            credentials: HashMap::new(),
+        };
+        let err = McpClient::connect(&server("ws://mcp.example.com")).await.unwrap_err();
+        assert_eq!(err.code(), codes::CAPABILITY_NOT_SUPPORTED);
+        let err = McpClient::connect(&server("  ")).await.unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_PARAMS);
+        let err = McpClient::connect(&server("stdio:///nonexistent/mcp-server --stdio")).await.unwrap_err();
+        assert_eq!(err.code(), codes::RESOURCE_NOT_FOUND);
+        // A process that exits without answering the handshake
+        let err = McpClient::connect(&server("true")).await.unwrap_err();
+        assert!(matches!(err, AcpError::ConnectionClosed), "{:?}", err);
+    }
+}
@@ -0,0 +1,115 @@
+//! Turning MCP resources into ACP content blocks.
+//!
+//! Text resources are embedded as [`ContentBlock::Resource`]. Binary
+//! resources, and text larger than the size limit, become a
+//! [`ContentBlock::ResourceLink`] the other side can fetch itself.
+
+use super::{McpClient, McpResource, McpResourceContents};
+use crate::protocol::*;
+
+/// Largest resource text embedded in a block when no limit is given.
+pub const MAX_EMBEDDED_RESOURCE_BYTES: usize = 1024 * 1024;
+
+impl McpResource {
+    /// A link to this resource, for listing it without its contents.
+    pub fn to_link(&self) -> ContentBlock {
+        ContentBlock::ResourceLink {
+            uri: self.uri.clone(),
+            mime_type: mime_type(&self.uri, self.mime_type.as_deref(), false),
+            annotations: None,
+        }
+    }
+}
+
+impl McpResourceContents {
+    /// Embed these contents as a block, or link to them if they are binary
+    /// or their text is longer than `max_bytes`.
+    pub fn to_content(&self, max_bytes: usize) -> ContentBlock {
+        let is_text = self.text.is_some();
+        let mime_type = mime_type(&self.uri, self.mime_type.as_deref(), is_text);
+        match &self.text {
+            Some(text) if text.len() <= max_bytes => ContentBlock::Resource {
+                uri: self.uri.clone(),
+                mime_type,
+                content: text.clone(),
+                annotations: None,
+            },
+            _ => ContentBlock::ResourceLink {
+                uri: self.uri.clone(),
+                mime_type,
+                annotations: None,
+            },
+        }
+    }
+}
+
+impl McpClient {
+    /// Read the resource at `uri` as content blocks for a prompt or update,
+    /// one per part the server returns.
+    ///
+    /// Text up to `max_bytes` (default [`MAX_EMBEDDED_RESOURCE_BYTES`]) is
+    /// embedded; anything else is linked.
+    pub async fn fetch_resource(&self, uri: &str, max_bytes: Option<usize>) -> AcpResult<Vec<ContentBlock>> {
+        let max_bytes = max_bytes.unwrap_or(MAX_EMBEDDED_RESOURCE_BYTES);
+        let contents = self.read_resource(uri).await?;
+        Ok(contents.iter().map(|part| part.to_content(max_bytes)).collect())
+    }
+}
+
+/// The MIME type the server gave, or else one guessed from the URI.
+fn mime_type(uri: &str, given: Option<&str>, is_text: bool) -> String {
+    if let Some(given) = given.filter(|m| !m.is_empty()) {
+        return given.to_string();
+    }
+    // Drop any query or fragment so only the path's extension counts
+    let path = uri.split(['?', '#']).next().unwrap_or(uri);
+    match mime_guess::from_path(path).first() {
+        Some(guess) => guess.essence_str().to_string(),
+        None if is_text => "text/plain".to_string(),
+        None => "application/octet-stream".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resource_blocks() {
+        let contents = |uri: &str, mime: Option<&str>, text: Option<&str>| McpResourceContents {
+            uri: uri.to_string(),
+            mime_type: mime.map(str::to_string),
+            text: text.map(str::to_string),
+            blob: text.is_none().then(|| "iVBORw==".to_string()),
+        };
+
+        let block = contents("file:///src/main.rs?rev=2", None, Some("fn main() {}")).to_content(100);
+        let ContentBlock::Resource { mime_type, content, .. } = block else {
+            panic!("expected resource");
+        };
+        assert_eq!(mime_type, "text/x-rust");
+        assert_eq!(content, "fn main() {}");
+
+        let block = contents("db://tables/users", None, Some("id, name")).to_content(100);
+        assert!(matches!(block, ContentBlock::Resource { mime_type, .. } if mime_type == "text/plain"));
+
+        // Too long to embed
+        let block = contents("db://tables/users", Some("text/csv"), Some("id, name")).to_content(4);
+        assert!(matches!(block, ContentBlock::ResourceLink { mime_type, .. } if mime_type == "text/csv"));
+
+        let block = contents("file:///logo.png", None, None).to_content(100);
+        assert!(matches!(block, ContentBlock::ResourceLink { mime_type, .. } if mime_type == "image/png"));
+        let block = contents("db://dump", None, None).to_content(100);
+        assert!(
+            matches!(block, ContentBlock::ResourceLink { mime_type, .. } if mime_type == "application/octet-stream")
+        );
+
+        let resource = McpResource {
+            uri: "file:///notes.md".to_string(),
+            name: "notes".to_string(),
+            description: None,
+            mime_type: None,
+        };
+        assert!(matches!(resource.to_link(), ContentBlock::ResourceLink { mime_type, .. } if mime_type == "text/markdown"));
+    }
+}
@@ -0,0 +1,214 @@
+//! Resolving the credentials of MCP servers.
+//!
+//! A value in [`McpServer::credentials`] may name where the secret is kept
+//! instead of holding it, so the secret never travels in `initialize`:
+//!
+//! - `env:NAME` is the agent's environment variable `NAME`.
+//! - `keyring:SERVICE/ACCOUNT` is the password stored in the OS keyring,
+//!   read with `security` on macOS and `secret-tool` elsewhere.
+//!
+//! Other values are used as they are, unless a provider added with
+//! [`Credentials::provider`] resolves them.
+
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::process::Command;
+
+use crate::protocol::*;
+
+/// Looks up the secret a credential value refers to.
+#[async_trait]
+pub trait CredentialProvider: Send + Sync {
+    /// Resolve `value`, the credential `key` of the MCP server named
+    /// `server`. Return `Ok(None)` for values this provider doesn't handle.
+    ///
+    /// Errors must not include the secret.
+    async fn resolve(&self, server: &str, key: &str, value: &str) -> AcpResult<Option<String>>;
+}
+
+/// Resolves `env:NAME` from the agent's environment.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EnvCredentials;
+
+#[async_trait]
+impl CredentialProvider for EnvCredentials {
+    async fn resolve(&self, _server: &str, _key: &str, value: &str) -> AcpResult<Option<String>> {
+        let Some(name) = value.strip_prefix("env:") else {
+            return Ok(None);
+        };
+        std::env::var(name)
+            .map(Some)
+            .map_err(|_| AcpError::ResourceNotFound(format!("environment variable {} is not set", name)))
+    }
+}
+
+/// Resolves `keyring:SERVICE/ACCOUNT` from the OS keyring.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KeyringCredentials;
+
+#[async_trait]
+impl CredentialProvider for KeyringCredentials {
+    async fn resolve(&self, _server: &str, _key: &str, value: &str) -> AcpResult<Option<String>> {
+        let Some(entry) = value.strip_prefix("keyring:") else {
+            return Ok(None);
+        };
+        let (service, account) = entry
+            .split_once('/')
+            .ok_or_else(|| AcpError::InvalidParams(format!("keyring entry {} is not SERVICE/ACCOUNT", entry)))?;
+        let mut command = if cfg!(target_os = "macos") {
+            let mut command = Command::new("security");
+            command.args(["find-generic-password", "-s", service, "-a", account, "-w"]);
+            command
+        } else {
+            let mut command = Command::new("secret-tool");
+            command.args(["lookup", "service", service, "account", account]);
+            command
+        };
+        let output = command
+            .output()
+            .await
+            .map_err(|e| AcpError::CapabilityNotSupported(format!("keyring: {}", e)))?;
+        if !output.status.success() {
+            return Err(AcpError::ResourceNotFound(format!("keyring entry {}", entry)));
+        }
+        let secret = String::from_utf8(output.stdout)
+            .map_err(|_| AcpError::InvalidEncoding(format!("keyring entry {}", entry)))?;
+        Ok(Some(secret.trim_end_matches(['\r', '\n']).to_string()))
+    }
+}
+
+/// Resolves credentials with a closure taking the server name, key and
+/// value.
+///
+/// ```rust
+/// use heroacp::mcp::{CredentialFn, Credentials};
+///
+/// let vault = CredentialFn(|_server: &str, _key: &str, value: &str| {
+///     value.strip_prefix("vault:").map(|path| format!("secret from {}", path))
+/// });
+/// let credentials = Credentials::new().provider(vault);
+/// ```
+#[derive(Clone, Copy)]
+pub struct CredentialFn<F>(pub F);
+
+#[async_trait]
+impl<F> CredentialProvider for CredentialFn<F>
+where
+    F: Fn(&str, &str, &str) -> Option<String> + Send + Sync,
+{
+    async fn resolve(&self, server: &str, key: &str, value: &str) -> AcpResult<Option<String>> {
+        Ok((self.0)(server, key, value))
+    }
+}
+
+/// The providers credential values are resolved with.
+///
+/// Added providers are tried in order, before the built-in
+/// [`EnvCredentials`] and [`KeyringCredentials`]. Cloning is cheap.
+#[derive(Clone)]
+pub struct Credentials {
+    providers: Arc<Vec<Arc<dyn CredentialProvider>>>,
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("providers", &self.providers.len())
+            .finish()
+    }
+}
+
+impl Default for Credentials {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Credentials {
+    /// Resolve `env:` and `keyring:` references only.
+    pub fn new() -> Self {
+        Self {
+            providers: Arc::new(vec![Arc::new(EnvCredentials), Arc::new(KeyringCredentials)]),
+        }
+    }
+
+    /// Try `provider` after the providers added so far, but before the
+    /// built-in ones.
+    pub fn provider(mut self, provider: impl CredentialProvider + 'static) -> Self {
+        let providers = Arc::make_mut(&mut self.providers);
+        let builtin = providers.len() - 2;
+        providers.insert(builtin, Arc::new(provider));
+        self
+    }
+
+    /// A copy of `server` with every credential resolved.
+    ///
+    /// Fails naming the server and key, never the secret, if a reference
+    /// can't be resolved.
+    pub async fn resolve(&self, server: &McpServer) -> AcpResult<McpServer> {
+        let mut resolved = server.clone();
+        for (key, value) in resolved.credentials.iter_mut() {
+            for provider in self.providers.iter() {
+                let secret = provider.resolve(&server.name, key, value).await.map_err(|e| {
+                    AcpError::InvalidParams(format!(
+                        "credential {} of MCP server {}: {}",
+                        key,
+                        server.name,
+                        e.message()
+                    ))
+                })?;
+                if let Some(secret) = secret {
+                    *value = secret;
+                    break;
+                }
+            }
+        }
+        Ok(resolved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_resolve_credentials() {
+        std::env::set_var("HEROACP_TEST_MCP_TOKEN", "s3cret");
+        let server = |credentials: &[(&str, &str)]| McpServer {
+            name: "github".to_string(),
+            url: "github-mcp".to_string(),
+            credentials: credentials.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        };
+        let vault = CredentialFn(|server: &str, _key: &str, value: &str| {
+            value.strip_prefix("vault:").map(|path| format!("{}:{}", server, path))
+        });
+        let credentials = Credentials::new().provider(vault);
+
+        let resolved = credentials
+            .resolve(&server(&[
+                ("GITHUB_TOKEN", "env:HEROACP_TEST_MCP_TOKEN"),
+                ("API_KEY", "vault:api"),
+                ("REGION", "eu"),
+            ]))
+            .await
+            .unwrap();
+        assert_eq!(
+            resolved.credentials,
+            HashMap::from([
+                ("GITHUB_TOKEN".to_string(), "s3cret".to_string()),
+                ("API_KEY".to_string(), "github:api".to_string()),
+                ("REGION".to_string(), "eu".to_string()),
+            ])
+        );
+
+        let err = credentials
+            .resolve(&server(&[("TOKEN", "env:HEROACP_TEST_MCP_MISSING")]))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_PARAMS);
+        assert!(err.message().contains("credential TOKEN of MCP server github"), "{}", err.message());
+        let err = credentials.resolve(&server(&[("TOKEN", "keyring:nothing")])).await.unwrap_err();
+        assert!(err.message().contains("SERVICE/ACCOUNT"), "{}", err.message());
+    }
+}
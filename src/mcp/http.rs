@@ -0,0 +1,271 @@
+//! The streamable HTTP transport for hosted MCP servers.
+//!
+//! Every message is POSTed to the server's URL. The server answers a request
+//! with a JSON body or with a stream of server-sent events that ends with the
+//! response; notifications and responses are acknowledged with
+//! `202 Accepted`. The session ID the server hands out in the handshake is
+//! sent back with every later message.
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, CONTENT_TYPE};
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+
+use super::client::Inbound;
+use crate::protocol::*;
+
+const SESSION_HEADER: &str = "mcp-session-id";
+
+struct Transport {
+    http: reqwest::Client,
+    url: String,
+    session: Mutex<Option<String>>,
+    inbound: Inbound,
+}
+
+/// POST what is put into `outgoing` to `url`, sending `credentials` as
+/// headers, and hand what the server sends back to `inbound`.
+pub(super) fn spawn(
+    url: &str,
+    credentials: &HashMap<String, String>,
+    mut outgoing: mpsc::Receiver<String>,
+    inbound: Inbound,
+) -> AcpResult<()> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in credentials {
+        let invalid = || AcpError::InvalidParams(format!("credential {} is not a valid HTTP header", name));
+        let name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid())?;
+        let mut value = HeaderValue::from_str(value).map_err(|_| invalid())?;
+        value.set_sensitive(true);
+        headers.insert(name, value);
+    }
+    headers.insert(ACCEPT, HeaderValue::from_static("application/json, text/event-stream"));
+    let http = reqwest::Client::builder()
+        .default_headers(headers)
+        .build()
+        .map_err(|e| AcpError::InternalError(e.to_string()))?;
+
+    let transport = Arc::new(Transport {
+        http,
+        url: url.to_string(),
+        session: Mutex::new(None),
+        inbound,
+    });
+    tokio::spawn(async move {
+        while let Some(msg) = outgoing.recv().await {
+            // Each message gets its own request, so a slow tool call doesn't
+            // hold up the others
+            let transport = transport.clone();
+            tokio::spawn(async move { transport.post(msg).await });
+        }
+        transport.end_session().await;
+        transport.inbound.close().await;
+    });
+    Ok(())
+}
+
+impl Transport {
+    async fn post(&self, msg: String) {
+        // Only requests wait for an answer; responses to the server don't
+        let id = serde_json::from_str::<Value>(&msg)
+            .ok()
+            .filter(|msg| msg.get("method").is_some())
+            .and_then(|msg| serde_json::from_value::<RequestId>(msg.get("id")?.clone()).ok());
+
+        let result = self.send(msg).await;
+        match (id, result) {
+            // A request the server didn't answer before ending the response
+            (Some(id), Ok(())) => self.inbound.fail(id, AcpError::ConnectionClosed).await,
+            (Some(id), Err(e)) => self.inbound.fail(id, e).await,
+            (None, Ok(())) => {}
+            (None, Err(e)) => eprintln!("Failed to send message to MCP server {}: {}", self.url, e),
+        }
+    }
+
+    async fn send(&self, msg: String) -> AcpResult<()> {
+        let mut request = self
+            .http
+            .post(&self.url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(msg);
+        if let Some(session) = self.session.lock().await.clone() {
+            request = request.header(SESSION_HEADER, session);
+        }
+        let mut response = request.send().await.map_err(|e| self.error(e))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(match status {
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                    AcpError::PermissionDenied(format!("{} returned {}", self.url, status))
+                }
+                StatusCode::NOT_FOUND => AcpError::ResourceNotFound(format!("{} returned {}", self.url, status)),
+                StatusCode::TOO_MANY_REQUESTS => AcpError::RateLimited(self.url.clone()),
+                _ => AcpError::InternalError(format!("{} returned {}", self.url, status)),
+            });
+        }
+        if let Some(session) = response.headers().get(SESSION_HEADER).and_then(|v| v.to_str().ok()) {
+            *self.session.lock().await = Some(session.to_string());
+        }
+
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        if content_type.starts_with("text/event-stream") {
+            let mut events = EventStream::default();
+            while let Some(chunk) = response.chunk().await.map_err(|e| self.error(e))? {
+                for data in events.push(&chunk) {
+                    self.inbound.receive(&data).await;
+                }
+            }
+        } else if content_type.starts_with("application/json") {
+            let body = response.text().await.map_err(|e| self.error(e))?;
+            self.inbound.receive(&body).await;
+        }
+        Ok(())
+    }
+
+    /// Tell the server the session is over.
+    async fn end_session(&self) {
+        if let Some(session) = self.session.lock().await.take() {
+            let _ = self.http.delete(&self.url).header(SESSION_HEADER, session).send().await;
+        }
+    }
+
+    fn error(&self, e: reqwest::Error) -> AcpError {
+        AcpError::InternalError(format!("{}: {}", self.url, e))
+    }
+}
+
+/// Splits a server-sent event stream into the data of its events.
+#[derive(Default)]
+struct EventStream {
+    buffer: Vec<u8>,
+    data: Vec<String>,
+}
+
+impl EventStream {
+    /// Add a chunk of the stream and return the data of the events it
+    /// completes.
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    events.push(self.data.join("\n"));
+                    self.data.clear();
+                }
+            } else if let Some(value) = line.strip_prefix("data:") {
+                self.data.push(value.strip_prefix(' ').unwrap_or(value).to_string());
+            }
+            // Event names, IDs, retry hints and comments aren't needed
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::McpClient;
+    use serde_json::json;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_event_stream() {
+        let mut events = EventStream::default();
+        assert!(events.push(b"event: message\r\ndata: {\"a\"").is_empty());
+        assert_eq!(events.push(b":1}\r\n\r\n: keep-alive\n\n"), ["{\"a\":1}"]);
+        assert_eq!(events.push(b"data: one\ndata:two\n\ndata: three\n\n"), ["one\ntwo", "three"]);
+    }
+
+    /// Serve a single-tool MCP server over HTTP that wants the `secret`
+    /// bearer token, and return its URL.
+    async fn fake_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/mcp", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut stream = BufReader::new(stream);
+                    let mut headers = HashMap::new();
+                    let mut line = String::new();
+                    stream.read_line(&mut line).await.unwrap();
+                    let method = line.split(' ').next().unwrap_or_default().to_string();
+                    loop {
+                        line.clear();
+                        stream.read_line(&mut line).await.unwrap();
+                        let Some((name, value)) = line.trim_end().split_once(": ") else {
+                            break;
+                        };
+                        headers.insert(name.to_lowercase(), value.to_string());
+                    }
+                    let length = headers.get("content-length").map_or(0, |l| l.parse().unwrap());
+                    let mut body = vec![0; length];
+                    stream.read_exact(&mut body).await.unwrap();
+
+                    let msg: Value = serde_json::from_slice(&body).unwrap_or_default();
+                    let reply = |result: Value| json!({ "jsonrpc": "2.0", "id": msg["id"], "result": result });
+                    let (status, extra, body) = if headers.get("authorization").map(String::as_str) != Some("Bearer secret") {
+                        ("401 Unauthorized", "", String::new())
+                    } else if method == "DELETE" || msg.get("method").is_none() || msg.get("id").is_none() {
+                        ("202 Accepted", "", String::new())
+                    } else if msg["method"] == "initialize" {
+                        let result = reply(json!({
+                            "protocolVersion": "2025-03-26",
+                            "capabilities": { "tools": {} },
+                            "serverInfo": { "name": "hosted" },
+                        }));
+                        ("200 OK", "Content-Type: application/json\r\nMcp-Session-Id: s-1\r\n", result.to_string())
+                    } else if headers.get("mcp-session-id").map(String::as_str) != Some("s-1") {
+                        ("404 Not Found", "", String::new())
+                    } else {
+                        // Ask the client something before answering
+                        let ping = json!({ "jsonrpc": "2.0", "id": "ping-1", "method": "ping" });
+                        let result = reply(json!({ "tools": [{ "name": "echo", "inputSchema": {} }] }));
+                        let events = format!("data: {}\n\nevent: message\ndata: {}\n\n", ping, result);
+                        ("200 OK", "Content-Type: text/event-stream\r\n", events)
+                    };
+                    let response = format!(
+                        "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        status,
+                        extra,
+                        body.len(),
+                        body
+                    );
+                    stream.get_mut().write_all(response.as_bytes()).await.unwrap();
+                });
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_connect_over_http() {
+        let url = fake_server().await;
+        let server = |token: &str| McpServer {
+            name: "hosted".to_string(),
+            url: url.clone(),
+            credentials: HashMap::from([("Authorization".to_string(), format!("Bearer {}", token))]),
+        };
+
+        let client = McpClient::connect(&server("secret")).await.unwrap();
+        assert_eq!(client.info().server_info.name, "hosted");
+        let tools = client.list_tools().await.unwrap();
+        assert_eq!(tools.len(), 1);
+        assert_eq!(tools[0].name, "echo");
+
+        let err = McpClient::connect(&server("wrong")).await.unwrap_err();
+        assert_eq!(err.code(), codes::PERMISSION_DENIED);
+    }
+}
@@ -0,0 +1,247 @@
+//! Client for the MCP servers an editor offers the agent.
+//!
+//! Editors list MCP servers in `InitializeParams::mcp_servers`. Servers built
+//! with [`Server::with_mcp`](crate::server::Server::with_mcp) connect to them
+//! during `initialize`, and agents reach them through
+//! [`AgentContext::mcp`](crate::server::AgentContext::mcp):
+//!
+//! ```rust,no_run
+//! # use heroacp::server::AgentContext;
+//! # use heroacp::protocol::*;
+//! # async fn demo(ctx: AgentContext) -> AcpResult<()> {
+//! for client in ctx.mcp().clients().await {
+//!     for tool in client.list_tools().await? {
+//!         println!("{}: {}", client.name(), tool.name);
+//!     }
+//! }
+//! if let Some(db) = ctx.mcp().get("db").await {
+//!     let result = db.call_tool("query", serde_json::json!({ "sql": "select 1" })).await?;
+//!     println!("{}", result.text());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Servers are started and spoken to over stdio. Their credentials may be
+//! references to secrets kept elsewhere; see [`Credentials`]. With the `mcp-http` feature,
+//! hosted servers with `http(s)://` URLs are reached over the streamable HTTP
+//! transport. Servers can be supervised, so that ones that crash or hang are
+//! restarted; see [`McpSupervision`]. Servers may ask for LLM completions,
+//! which a [`Sampler`] answers.
+//!
+//! The other way round, [`serve_as_mcp`] offers an agent to MCP-only hosts as
+//! a `prompt` tool.
+
+pub(crate) mod client;
+mod content;
+mod credentials;
+#[cfg(feature = "mcp-http")]
+mod http;
+mod sampling;
+mod serve;
+mod supervisor;
+mod types;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock as StdRwLock};
+use tokio::sync::RwLock;
+use tokio::task::JoinSet;
+
+pub use client::{McpClient, HANDSHAKE_TIMEOUT};
+pub use content::MAX_EMBEDDED_RESOURCE_BYTES;
+pub use credentials::{CredentialFn, CredentialProvider, Credentials, EnvCredentials, KeyringCredentials};
+pub use sampling::Sampler;
+pub use serve::{serve_as_mcp, serve_server_as_mcp, DEFAULT_SESSION, PROMPT_TOOL};
+pub use supervisor::McpSupervision;
+pub use types::*;
+
+use crate::protocol::{AcpResult, McpServer, ToolInfo};
+
+/// Name under which the tool `tool` of the server `server` is proxied.
+pub fn proxied_tool_name(server: &str, tool: &str) -> String {
+    format!("{}__{}", server, tool)
+}
+
+/// The MCP servers the agent is connected to.
+///
+/// Cloning is cheap; all clones share the same set.
+#[derive(Clone, Default)]
+pub struct McpServers {
+    clients: Arc<RwLock<Vec<McpClient>>>,
+    proxied: Arc<RwLock<HashMap<String, (McpClient, McpTool)>>>,
+    supervisors: Arc<Mutex<JoinSet<()>>>,
+    credentials: Credentials,
+    sampler: Arc<StdRwLock<Option<Arc<dyn Sampler>>>>,
+}
+
+impl std::fmt::Debug for McpServers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("McpServers")
+            .field("credentials", &self.credentials)
+            .field("sampling", &self.sampler.read().unwrap().is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl McpServers {
+    /// Create an empty set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolve credential references with `credentials` instead of the
+    /// built-in `env:` and `keyring:` providers alone.
+    pub fn with_credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    /// Offer sampling to servers connected from now on, answering their
+    /// requests with `sampler`, or stop offering it with `None`.
+    pub fn set_sampler(&self, sampler: Option<Arc<dyn Sampler>>) {
+        *self.sampler.write().unwrap() = sampler;
+    }
+
+    /// Connect to `servers` in parallel and add the ones that answer.
+    ///
+    /// A server that can't be started or doesn't complete the handshake is
+    /// reported on stderr and left out, so one broken server doesn't keep the
+    /// agent from starting.
+    pub async fn connect(&self, servers: &[McpServer]) {
+        let mut connecting = tokio::task::JoinSet::new();
+        for (i, server) in servers.iter().cloned().enumerate() {
+            let this = self.clone();
+            connecting.spawn(async move { (i, server.name.clone(), this.start(&server).await) });
+        }
+
+        let mut connected = Vec::new();
+        while let Some(joined) = connecting.join_next().await {
+            match joined {
+                Ok((i, _, Ok(client))) => connected.push((i, client)),
+                Ok((_, name, Err(e))) => eprintln!("Failed to connect to MCP server {}: {}", name, e),
+                Err(e) => eprintln!("Failed to connect to MCP server: {}", e),
+            }
+        }
+        // Keep the order the editor listed them in
+        connected.sort_by_key(|(i, _)| *i);
+        for (_, client) in connected {
+            self.add(client).await;
+        }
+    }
+
+    /// Resolve the credentials of `server`, then connect to it.
+    async fn start(&self, server: &McpServer) -> AcpResult<McpClient> {
+        let server = self.credentials.resolve(server).await?;
+        let sampler = self.sampler.read().unwrap().clone();
+        match sampler {
+            Some(sampler) => McpClient::connect_with_sampler(&server, sampler).await,
+            None => McpClient::connect(&server).await,
+        }
+    }
+
+    /// Add a connected client, replacing any with the same name.
+    ///
+    /// Proxied tools of a replaced client are called on the new one.
+    pub async fn add(&self, client: McpClient) {
+        for (proxied, _) in self.proxied.write().await.values_mut() {
+            if proxied.name() == client.name() {
+                *proxied = client.clone();
+            }
+        }
+        let mut clients = self.clients.write().await;
+        clients.retain(|c| c.name() != client.name());
+        clients.push(client);
+    }
+
+    /// Remove the client for the server named `name`.
+    pub async fn remove(&self, name: &str) -> Option<McpClient> {
+        let mut clients = self.clients.write().await;
+        let i = clients.iter().position(|c| c.name() == name)?;
+        Some(clients.remove(i))
+    }
+
+    /// Get the client for the server named `name`.
+    pub async fn get(&self, name: &str) -> Option<McpClient> {
+        self.clients.read().await.iter().find(|c| c.name() == name).cloned()
+    }
+
+    /// All connected clients.
+    pub async fn clients(&self) -> Vec<McpClient> {
+        self.clients.read().await.clone()
+    }
+
+    /// Check whether no servers are connected.
+    pub async fn is_empty(&self) -> bool {
+        self.clients.read().await.is_empty()
+    }
+
+    /// List the tools of every connected server as ACP tools named by
+    /// [`proxied_tool_name`], and let
+    /// [`ToolRegistry::call`](crate::server::ToolRegistry::call) route calls
+    /// to them.
+    ///
+    /// Servers whose tools can't be listed are reported on stderr and left
+    /// out.
+    pub async fn proxy_tools(&self) -> Vec<ToolInfo> {
+        let mut infos = Vec::new();
+        let mut proxied = HashMap::new();
+        for client in self.clients().await {
+            let tools = match client.list_tools().await {
+                Ok(tools) => tools,
+                Err(e) => {
+                    eprintln!("Failed to list tools of MCP server {}: {}", client.name(), e);
+                    continue;
+                }
+            };
+            for tool in tools {
+                let info = tool.tool_info(client.name());
+                proxied.insert(info.name.clone(), (client.clone(), tool));
+                infos.push(info);
+            }
+        }
+        *self.proxied.write().await = proxied;
+        infos
+    }
+
+    /// Get the client and tool behind a name returned by
+    /// [`proxy_tools`](Self::proxy_tools).
+    pub async fn proxied_tool(&self, name: &str) -> Option<(McpClient, McpTool)> {
+        self.proxied.read().await.get(name).cloned()
+    }
+
+    /// Stop supervising and drop all clients, stopping the server processes
+    /// no one else holds.
+    pub async fn close(&self) {
+        self.supervisors.lock().unwrap().abort_all();
+        self.proxied.write().await.clear();
+        self.clients.write().await.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_connect_skips_broken_servers() {
+        let servers = McpServers::new();
+        servers
+            .connect(&[McpServer {
+                name: "broken".to_string(),
+                url: "/nonexistent/mcp-server".to_string(),
+                credentials: HashMap::new(),
+            }])
+            .await;
+        assert!(servers.is_empty().await);
+
+        servers.add(client::tests::fake_client("fake").await).await;
+        servers.add(client::tests::fake_client("other").await).await;
+        servers.add(client::tests::fake_client("fake").await).await;
+        let names: Vec<_> = servers.clients().await.iter().map(|c| c.name().to_string()).collect();
+        assert_eq!(names, ["other", "fake"]);
+        assert!(servers.get("other").await.is_some());
+
+        servers.close().await;
+        assert!(servers.get("other").await.is_none());
+    }
+}
@@ -0,0 +1,248 @@
+//! Passing MCP servers' sampling requests on to the ACP client.
+//!
+//! MCP servers may ask their client to run the LLM for them with
+//! `sampling/createMessage`. A client connected with a [`Sampler`] offers
+//! sampling in the handshake and hands such requests to it; the
+//! [`AgentConnection`] sampler forwards them to the editor as
+//! `session/sampling_request`, so the editor's configured model answers.
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::protocol::*;
+use crate::server::AgentConnection;
+
+/// Answers MCP servers' requests for a completion.
+#[async_trait]
+pub trait Sampler: Send + Sync {
+    /// Generate the next message of `params.messages`.
+    async fn create_message(&self, params: SessionSamplingRequestParams) -> AcpResult<SessionSamplingRequestResult>;
+}
+
+/// Asks the ACP client, which must have the `sampling` capability.
+#[async_trait]
+impl Sampler for AgentConnection {
+    async fn create_message(&self, params: SessionSamplingRequestParams) -> AcpResult<SessionSamplingRequestResult> {
+        self.request_sampling(params).await
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateMessageParams {
+    messages: Vec<Message>,
+    system_prompt: Option<String>,
+    max_tokens: u32,
+    temperature: Option<f64>,
+    #[serde(default)]
+    stop_sequences: Vec<String>,
+    model_preferences: Option<Value>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateMessageResult {
+    role: Role,
+    content: Content,
+    model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    role: Role,
+    content: Content,
+}
+
+/// Content of a sampling message, as MCP spells it.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Content {
+    Text {
+        text: String,
+    },
+    Image {
+        data: String,
+        #[serde(rename = "mimeType")]
+        mime_type: String,
+    },
+    Audio {
+        data: String,
+        #[serde(rename = "mimeType")]
+        mime_type: String,
+    },
+}
+
+impl From<Content> for ContentBlock {
+    fn from(content: Content) -> Self {
+        match content {
+            Content::Text { text } => ContentBlock::text(text),
+            Content::Image { data, mime_type } => ContentBlock::Image {
+                format: mime_type.trim_start_matches("image/").to_string(),
+                data,
+                annotations: None,
+            },
+            Content::Audio { data, mime_type } => ContentBlock::Audio {
+                format: mime_type.trim_start_matches("audio/").to_string(),
+                data,
+                annotations: None,
+            },
+        }
+    }
+}
+
+impl TryFrom<ContentBlock> for Content {
+    type Error = AcpError;
+
+    fn try_from(block: ContentBlock) -> AcpResult<Self> {
+        match block {
+            ContentBlock::Text { text, .. } => Ok(Content::Text { text }),
+            ContentBlock::Image { format, data, .. } => Ok(Content::Image {
+                data,
+                mime_type: format!("image/{}", format),
+            }),
+            ContentBlock::Audio { format, data, .. } => Ok(Content::Audio {
+                data,
+                mime_type: format!("audio/{}", format),
+            }),
+            _ => Err(AcpError::InvalidParams(
+                "sampling results must be text, image or audio".to_string(),
+            )),
+        }
+    }
+}
+
+/// Answer the `sampling/createMessage` request `params` of the MCP server
+/// named `server` with `sampler`.
+pub(super) async fn create_message(sampler: &dyn Sampler, server: &str, params: Value) -> AcpResult<Value> {
+    let params: CreateMessageParams =
+        serde_json::from_value(params).map_err(|e| AcpError::InvalidParams(e.to_string()))?;
+    let request = SessionSamplingRequestParams {
+        server: server.to_string(),
+        messages: params
+            .messages
+            .into_iter()
+            .map(|message| SamplingMessage {
+                role: message.role,
+                content: message.content.into(),
+            })
+            .collect(),
+        system_prompt: params.system_prompt,
+        max_tokens: params.max_tokens,
+        temperature: params.temperature,
+        stop_sequences: params.stop_sequences,
+        model_preferences: params.model_preferences,
+    };
+    let result = sampler.create_message(request).await?;
+    Ok(serde_json::to_value(CreateMessageResult {
+        role: result.role,
+        content: result.content.try_into()?,
+        model: result.model,
+        stop_reason: result.stop_reason,
+    })?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::{McpClient, MCP_PROTOCOL_VERSION};
+    use serde_json::json;
+    use std::sync::Arc;
+    use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+    async fn send(writer: &mut (impl AsyncWrite + Unpin), msg: Value) {
+        writer.write_all(format!("{}\n", msg).as_bytes()).await.unwrap();
+    }
+
+    /// Answers with the text of the last message, reversed.
+    struct Reverser;
+
+    #[async_trait]
+    impl Sampler for Reverser {
+        async fn create_message(&self, params: SessionSamplingRequestParams) -> AcpResult<SessionSamplingRequestResult> {
+            assert_eq!(params.server, "notes");
+            assert_eq!(params.system_prompt.as_deref(), Some("Be brief"));
+            let Some(ContentBlock::Text { text, .. }) = params.messages.last().map(|m| &m.content) else {
+                return Err(AcpError::InvalidParams("expected text".to_string()));
+            };
+            Ok(SessionSamplingRequestResult {
+                role: Role::Assistant,
+                content: ContentBlock::text(text.chars().rev().collect::<String>()),
+                model: "editor-model".to_string(),
+                stop_reason: Some("endTurn".to_string()),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sampling_passthrough() {
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let (client_read, client_write) = tokio::io::split(client_io);
+        let (server_read, mut server_write) = tokio::io::split(server_io);
+        let mut lines = BufReader::new(server_read).lines();
+
+        let client = tokio::spawn(McpClient::from_streams_with_sampler(
+            "notes",
+            client_read,
+            client_write,
+            Arc::new(Reverser),
+        ));
+        let initialize: Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(initialize["params"]["capabilities"], json!({ "sampling": {} }));
+        send(&mut server_write, json!({ "jsonrpc": "2.0", "id": initialize["id"], "result": {
+            "protocolVersion": MCP_PROTOCOL_VERSION,
+            "capabilities": {},
+            "serverInfo": { "name": "notes" },
+        }}))
+        .await;
+        let _client = client.await.unwrap().unwrap();
+        lines.next_line().await.unwrap(); // notifications/initialized
+
+        let messages = json!([{ "role": "user", "content": { "type": "text", "text": "stressed" } }]);
+        send(&mut server_write, json!({ "jsonrpc": "2.0", "id": "s1", "method": "sampling/createMessage", "params": {
+            "messages": messages,
+            "systemPrompt": "Be brief",
+            "maxTokens": 50,
+        }}))
+        .await;
+        let reply: Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(reply["id"], "s1");
+        assert_eq!(
+            reply["result"],
+            json!({
+                "role": "assistant",
+                "content": { "type": "text", "text": "desserts" },
+                "model": "editor-model",
+                "stopReason": "endTurn",
+            })
+        );
+
+        let image = json!([{ "role": "user", "content": { "type": "image", "data": "iVBORw==", "mimeType": "image/png" } }]);
+        send(&mut server_write, json!({ "jsonrpc": "2.0", "id": "s2", "method": "sampling/createMessage", "params": {
+            "messages": image,
+            "systemPrompt": "Be brief",
+            "maxTokens": 50,
+        }}))
+        .await;
+        let reply: Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(reply["error"]["code"], codes::INVALID_PARAMS);
+    }
+
+    #[test]
+    fn test_content_conversion() {
+        let content: Content = serde_json::from_value(json!({ "type": "audio", "data": "UklG", "mimeType": "audio/wav" })).unwrap();
+        let block = ContentBlock::from(content);
+        assert!(matches!(&block, ContentBlock::Audio { format, .. } if format == "wav"));
+        let content = Content::try_from(block).unwrap();
+        assert_eq!(serde_json::to_value(content).unwrap()["mimeType"], "audio/wav");
+
+        let link = ContentBlock::ResourceLink {
+            uri: "file:///a".to_string(),
+            mime_type: "text/plain".to_string(),
+            annotations: None,
+        };
+        assert!(Content::try_from(link).is_err());
+    }
+}
@@ -0,0 +1,394 @@
+//! Offering an ACP agent to MCP hosts.
+//!
+//! [`serve_as_mcp`] speaks MCP on stdio and offers the agent as a single
+//! [`PROMPT_TOOL`]. Each call runs `session/prompt`: the agent's message
+//! chunks become the tool's text result, and its updates are sent as
+//! `notifications/progress` when the host asked for progress. The agent sees
+//! a client without optional capabilities, so it can't reach the host's
+//! files or terminals.
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{mpsc, Mutex};
+
+use super::MCP_PROTOCOL_VERSION;
+use crate::protocol::*;
+use crate::server::{error_response, Agent, AgentConnection, Server};
+
+/// Name of the tool the agent is offered as.
+pub const PROMPT_TOOL: &str = "prompt";
+
+/// Session continued by tool calls that don't name one.
+pub const DEFAULT_SESSION: &str = "default";
+
+/// Run `agent` as an MCP server on stdin and stdout until the host
+/// disconnects.
+pub async fn serve_as_mcp<A: Agent>(agent: A) -> AcpResult<()> {
+    serve_server_as_mcp(Server::new(agent), tokio::io::stdin(), tokio::io::stdout()).await
+}
+
+/// Run a configured server's agent as an MCP server over `reader` and
+/// `writer`, e.g. to keep its tools and middleware.
+pub async fn serve_server_as_mcp<A, R, W>(server: Server<A>, reader: R, writer: W) -> AcpResult<()>
+where
+    A: Agent,
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Send + Unpin + 'static,
+{
+    let (outgoing, mut outgoing_rx) = mpsc::channel::<String>(100);
+    tokio::spawn(async move {
+        let mut writer = writer;
+        while let Some(msg) = outgoing_rx.recv().await {
+            let line = format!("{}\n", msg);
+            if writer.write_all(line.as_bytes()).await.is_err() || writer.flush().await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let adapter = Arc::new(Adapter::new(server, outgoing));
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        // Responses from the host are never expected
+        let Ok(request) = serde_json::from_str::<JsonRpcRequest>(&line) else {
+            continue;
+        };
+        // Run requests concurrently, so a cancellation can reach a running
+        // prompt
+        let adapter = adapter.clone();
+        tokio::spawn(async move { adapter.handle(request).await });
+    }
+    adapter.server.shutdown().await;
+    Ok(())
+}
+
+struct Adapter<A: Agent> {
+    server: Server<A>,
+    connection: AgentConnection,
+    outgoing: mpsc::Sender<String>,
+    next_id: AtomicI64,
+    agent_name: Mutex<String>,
+    /// Session the agent created for each session name the host used.
+    sessions: Mutex<HashMap<String, String>>,
+    /// Session of each running tool call, for cancellation.
+    running: Mutex<HashMap<RequestId, String>>,
+}
+
+/// What a prompt has produced so far.
+struct PromptOutput {
+    text: String,
+    progress_token: Option<Value>,
+    progress: u64,
+}
+
+impl<A: Agent> Adapter<A> {
+    fn new(server: Server<A>, outgoing: mpsc::Sender<String>) -> Self {
+        // Requests the agent makes of the client have no MCP counterpart
+        let (client_tx, mut client_rx) = mpsc::channel::<String>(100);
+        let connection = server.connect(client_tx);
+        let client = connection.clone();
+        tokio::spawn(async move {
+            while let Some(msg) = client_rx.recv().await {
+                if let Ok(JsonRpcRequest { id: Some(id), method, .. }) = serde_json::from_str(&msg) {
+                    let message = format!("Method not found: {}", method);
+                    client.complete(error_response(id, codes::METHOD_NOT_FOUND, message)).await;
+                }
+            }
+        });
+
+        Self {
+            server,
+            connection,
+            outgoing,
+            next_id: AtomicI64::new(1),
+            agent_name: Mutex::new(String::new()),
+            sessions: Mutex::new(HashMap::new()),
+            running: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn handle(&self, request: JsonRpcRequest) {
+        let params = request.params.unwrap_or(Value::Null);
+        let Some(id) = request.id else {
+            if request.method == "notifications/cancelled" {
+                self.cancel(&params).await;
+            }
+            return;
+        };
+        let result = match request.method.as_str() {
+            "initialize" => self.initialize(&params).await,
+            "ping" => Ok(json!({})),
+            "tools/list" => self.list_tools().await,
+            "tools/call" => self.call_tool(id.clone(), &params).await,
+            method => Err(AcpError::MethodNotFound(method.to_string())),
+        };
+        let response = match result {
+            Ok(result) => JsonRpcResponse {
+                jsonrpc: "2.0".to_string(),
+                id,
+                result: Some(result),
+                error: None,
+            },
+            Err(e) => error_response(id, e.code(), e.message()),
+        };
+        self.send(&response).await;
+    }
+
+    async fn initialize(&self, params: &Value) -> AcpResult<Value> {
+        let client_info = ClientInfo {
+            name: params["clientInfo"]["name"].as_str().unwrap_or("mcp-host").to_string(),
+            version: params["clientInfo"]["version"].as_str().unwrap_or_default().to_string(),
+        };
+        let working_directory = std::env::current_dir()
+            .map(|dir| dir.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| "/".to_string());
+        let acp_params = InitializeParams {
+            protocol_version: PROTOCOL_VERSION.to_string(),
+            client_info,
+            capabilities: ClientCapabilities::default(),
+            working_directory,
+            mcp_servers: Vec::new(),
+        };
+        let result: InitializeResult = self.dispatch(method::INITIALIZE, acp_params, None).await?;
+        *self.agent_name.lock().await = result.agent_info.name.clone();
+
+        let mut response = json!({
+            "protocolVersion": params["protocolVersion"].as_str().unwrap_or(MCP_PROTOCOL_VERSION),
+            "capabilities": { "tools": {} },
+            "serverInfo": { "name": result.agent_info.name, "version": result.agent_info.version },
+        });
+        if let Some(instructions) = result.instructions {
+            response["instructions"] = json!(instructions);
+        }
+        Ok(response)
+    }
+
+    async fn list_tools(&self) -> AcpResult<Value> {
+        let agent_name = self.agent_name.lock().await.clone();
+        Ok(json!({ "tools": [{
+            "name": PROMPT_TOOL,
+            "description": format!("Ask the {} agent to do something.", agent_name),
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "prompt": { "type": "string", "description": "What to ask the agent" },
+                    "session_id": {
+                        "type": "string",
+                        "description": "Conversation to continue; calls without one share a default conversation",
+                    },
+                },
+                "required": ["prompt"],
+            },
+        }]}))
+    }
+
+    async fn call_tool(&self, id: RequestId, params: &Value) -> AcpResult<Value> {
+        if params["name"] != PROMPT_TOOL {
+            return Err(AcpError::InvalidParams(format!("Unknown tool: {}", params["name"])));
+        }
+        let arguments = &params["arguments"];
+        let prompt = arguments["prompt"]
+            .as_str()
+            .ok_or_else(|| AcpError::InvalidParams("arguments.prompt must be a string".to_string()))?;
+        let name = arguments["session_id"].as_str().unwrap_or(DEFAULT_SESSION);
+        let session_id = self.session(name).await?;
+
+        let mut output = PromptOutput {
+            text: String::new(),
+            progress_token: params["_meta"].get("progressToken").cloned(),
+            progress: 0,
+        };
+        self.running.lock().await.insert(id.clone(), session_id.clone());
+        let (update_tx, mut update_rx) = mpsc::channel(100);
+        let prompt = SessionPromptParams {
+            session_id,
+            content: vec![ContentBlock::text(prompt)],
+        };
+        let response = self.dispatch::<_, SessionPromptResult>(method::SESSION_PROMPT, prompt, Some(update_tx));
+        tokio::pin!(response);
+        let result = loop {
+            tokio::select! {
+                result = &mut response => break result,
+                Some(update) = update_rx.recv() => self.on_update(update, &mut output).await,
+            }
+        };
+        while let Ok(update) = update_rx.try_recv() {
+            self.on_update(update, &mut output).await;
+        }
+        self.running.lock().await.remove(&id);
+
+        // Failed prompts are reported in the result, as MCP asks of tools
+        let (text, is_error) = match result {
+            Ok(_) => (output.text, false),
+            Err(e) => (e.message(), true),
+        };
+        Ok(json!({ "content": [{ "type": "text", "text": text }], "isError": is_error }))
+    }
+
+    /// The agent's session for the host's session name, created on first use.
+    async fn session(&self, name: &str) -> AcpResult<String> {
+        let mut sessions = self.sessions.lock().await;
+        if let Some(session_id) = sessions.get(name) {
+            return Ok(session_id.clone());
+        }
+        let params = SessionNewParams {
+            session_id: name.to_string(),
+            mode: None,
+            agent: None,
+        };
+        let result: SessionNewResult = self.dispatch(method::SESSION_NEW, params, None).await?;
+        sessions.insert(name.to_string(), result.session_id.clone());
+        Ok(result.session_id)
+    }
+
+    async fn on_update(&self, update: SessionUpdate, output: &mut PromptOutput) {
+        let message = match update.update_type {
+            SessionUpdateType::AgentMessageChunk { text } => {
+                output.text.push_str(&text);
+                text
+            }
+            SessionUpdateType::AgentThoughtChunk { text } => text,
+            SessionUpdateType::ToolCall(call) => format!("Calling {}", call.name),
+            _ => return,
+        };
+        let Some(token) = &output.progress_token else {
+            return;
+        };
+        output.progress += 1;
+        let notification = JsonRpcNotification {
+            jsonrpc: "2.0".to_string(),
+            method: "notifications/progress".to_string(),
+            params: Some(json!({ "progressToken": token, "progress": output.progress, "message": message })),
+        };
+        self.send(&notification).await;
+    }
+
+    async fn cancel(&self, params: &Value) {
+        let Ok(request_id) = serde_json::from_value::<RequestId>(params["requestId"].clone()) else {
+            return;
+        };
+        let Some(session_id) = self.running.lock().await.get(&request_id).cloned() else {
+            return;
+        };
+        let params = SessionCancelParams {
+            session_id,
+            prompt_id: None,
+        };
+        let _: AcpResult<Value> = self.dispatch(method::SESSION_CANCEL, params, None).await;
+    }
+
+    /// Run an ACP request through the server, sending session updates to
+    /// `updates` if given.
+    async fn dispatch<P: Serialize, R: serde::de::DeserializeOwned>(
+        &self,
+        method: &str,
+        params: P,
+        updates: Option<mpsc::Sender<SessionUpdate>>,
+    ) -> AcpResult<R> {
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: Some(RequestId::Number(self.next_id.fetch_add(1, Ordering::Relaxed))),
+            method: method.to_string(),
+            params: Some(serde_json::to_value(params)?),
+        };
+        let updates = updates.unwrap_or_else(|| mpsc::channel(1).0);
+        let result = self.server.dispatch(request, updates, self.connection.clone()).await?;
+        serde_json::from_value(result).map_err(|e| AcpError::InvalidParams(e.to_string()))
+    }
+
+    async fn send(&self, msg: &impl Serialize) {
+        if let Ok(msg) = serde_json::to_string(msg) {
+            let _ = self.outgoing.send(msg).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::McpClient;
+    use crate::server::{AgentContext, SessionHandle};
+    use async_trait::async_trait;
+
+    /// Repeats the prompt, counting the prompts in the session.
+    struct ParrotAgent;
+
+    #[async_trait]
+    impl Agent for ParrotAgent {
+        async fn initialize(&self, _params: InitializeParams) -> AcpResult<InitializeResult> {
+            Ok(InitializeResult {
+                agent_info: AgentInfo {
+                    name: "parrot".to_string(),
+                    version: "1.0.0".to_string(),
+                },
+                capabilities: AgentCapabilities::default(),
+                instructions: Some("Repeats what it is told".to_string()),
+            })
+        }
+
+        async fn session_new(&self, params: SessionNewParams, _ctx: AgentContext) -> AcpResult<SessionNewResult> {
+            Ok(SessionNewResult {
+                session_id: params.session_id,
+            })
+        }
+
+        async fn session_prompt(
+            &self,
+            params: SessionPromptParams,
+            session: SessionHandle,
+            ctx: AgentContext,
+        ) -> AcpResult<SessionPromptResult> {
+            let ContentBlock::Text { text, .. } = &params.content[0] else {
+                return Err(AcpError::InvalidParams("expected text".to_string()));
+            };
+            if text == "read" {
+                // The host can't serve files
+                ctx.connection().read_file("/etc/hosts").await?;
+            }
+            let count = ctx.sessions().get(&params.session_id).await.unwrap().prompt_count;
+            session.send_message_chunk(format!("{} ", count)).await?;
+            session.send_message_chunk(text.clone()).await?;
+            Ok(SessionPromptResult {
+                status: "ok".to_string(),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serve_as_mcp() {
+        let (host_io, agent_io) = tokio::io::duplex(64 * 1024);
+        let (agent_read, agent_write) = tokio::io::split(agent_io);
+        tokio::spawn(serve_server_as_mcp(Server::new(ParrotAgent), agent_read, agent_write));
+        let (host_read, host_write) = tokio::io::split(host_io);
+        let host = McpClient::from_streams("parrot", host_read, host_write).await.unwrap();
+
+        assert_eq!(host.info().server_info.name, "parrot");
+        assert_eq!(host.info().instructions.as_deref(), Some("Repeats what it is told"));
+        let tools = host.list_tools().await.unwrap();
+        assert_eq!(tools.len(), 1);
+        assert_eq!(tools[0].name, PROMPT_TOOL);
+
+        let result = host.call_tool(PROMPT_TOOL, json!({ "prompt": "hello" })).await.unwrap();
+        assert!(!result.is_error);
+        assert_eq!(result.text(), "1 hello");
+        // The default session continues; a named one starts afresh
+        let result = host.call_tool(PROMPT_TOOL, json!({ "prompt": "again" })).await.unwrap();
+        assert_eq!(result.text(), "2 again");
+        let result = host
+            .call_tool(PROMPT_TOOL, json!({ "prompt": "hi", "session_id": "other" }))
+            .await
+            .unwrap();
+        assert_eq!(result.text(), "1 hi");
+
+        let result = host.call_tool(PROMPT_TOOL, json!({ "prompt": "read" })).await.unwrap();
+        assert!(result.is_error);
+        assert!(result.text().contains("text_files"), "{}", result.text());
+
+        let err = host.call_tool("other", json!({})).await.unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_PARAMS);
+    }
+}
@@ -0,0 +1,247 @@
+//! Keeping MCP servers running.
+//!
+//! Supervised servers are pinged periodically. A server whose connection is
+//! lost or that stops answering is reported unavailable, dropped from the
+//! set and started again with exponential backoff.
+
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use super::{McpClient, McpServers};
+use crate::protocol::*;
+
+/// How to watch over MCP servers and restart the ones that fail.
+///
+/// ```rust
+/// use heroacp::mcp::McpSupervision;
+/// use std::time::Duration;
+///
+/// let supervision = McpSupervision::new()
+///     .ping(Duration::from_secs(10), Duration::from_secs(2))
+///     .backoff(Duration::from_millis(500), Duration::from_secs(30))
+///     .max_restarts(5);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct McpSupervision {
+    ping_interval: Duration,
+    ping_timeout: Duration,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    max_restarts: u32,
+}
+
+impl Default for McpSupervision {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl McpSupervision {
+    /// Supervise with the defaults: ping every 30 s and give up on a server
+    /// that doesn't answer within 10 s, wait 1 s before the first restart and
+    /// double the wait up to 60 s, and give up after 10 failed restarts in a
+    /// row.
+    pub fn new() -> Self {
+        Self {
+            ping_interval: Duration::from_secs(30),
+            ping_timeout: Duration::from_secs(10),
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            max_restarts: 10,
+        }
+    }
+
+    /// Ping every `interval`, treating a server that takes longer than
+    /// `timeout` to answer as hung.
+    pub fn ping(mut self, interval: Duration, timeout: Duration) -> Self {
+        self.ping_interval = interval;
+        self.ping_timeout = timeout;
+        self
+    }
+
+    /// Wait `initial` before the first restart, doubling each time up to
+    /// `max`.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    /// Give up on a server after `max` failed restarts in a row.
+    pub fn max_restarts(mut self, max: u32) -> Self {
+        self.max_restarts = max;
+        self
+    }
+
+    /// How long to wait before restart number `attempt` (starting at 1).
+    fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt - 1);
+        self.initial_backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+
+    /// Wait until `client` is lost or hung, and say why.
+    async fn watch(&self, client: &McpClient) -> String {
+        loop {
+            tokio::select! {
+                _ = client.closed() => return "connection closed".to_string(),
+                _ = tokio::time::sleep(self.ping_interval) => {}
+            }
+            match tokio::time::timeout(self.ping_timeout, client.ping()).await {
+                Err(_) => return "ping timed out".to_string(),
+                Ok(Err(e @ (AcpError::ConnectionClosed | AcpError::ChannelError(_)))) => return e.message(),
+                // Any answer, even an error, shows the server is alive
+                Ok(_) => {}
+            }
+        }
+    }
+}
+
+impl McpServers {
+    /// Watch over `servers`, restarting the ones that crash or hang, until
+    /// [`close`](Self::close).
+    ///
+    /// Servers not connected yet, e.g. because they failed to start, are
+    /// started too. Changes in their availability are sent to `status`.
+    pub fn supervise(&self, servers: &[McpServer], supervision: McpSupervision, status: mpsc::Sender<McpServerStatus>) {
+        let mut supervisors = self.supervisors.lock().unwrap();
+        for server in servers.iter().cloned() {
+            let servers = self.clone();
+            let supervision = supervision.clone();
+            let status = status.clone();
+            supervisors.spawn(async move { servers.keep_running(server, supervision, status).await });
+        }
+    }
+
+    async fn keep_running(&self, server: McpServer, supervision: McpSupervision, status: mpsc::Sender<McpServerStatus>) {
+        let report = |state, message: Option<String>| {
+            let status = status.clone();
+            let name = server.name.clone();
+            async move {
+                let _ = status.send(McpServerStatus { name, state, message }).await;
+            }
+        };
+
+        loop {
+            let reason = match self.get(&server.name).await {
+                Some(client) => {
+                    let reason = supervision.watch(&client).await;
+                    self.remove(&server.name).await;
+                    reason
+                }
+                None => "not connected".to_string(),
+            };
+            eprintln!("MCP server {} is unavailable: {}", server.name, reason);
+            report(McpServerState::Unavailable, Some(reason)).await;
+
+            let mut attempt = 0;
+            loop {
+                if attempt == supervision.max_restarts {
+                    let message = format!("gave up after {} failed restarts", attempt);
+                    eprintln!("MCP server {} {}", server.name, message);
+                    report(McpServerState::Failed, Some(message)).await;
+                    return;
+                }
+                attempt += 1;
+                tokio::time::sleep(supervision.delay(attempt)).await;
+                match self.start(&server).await {
+                    Ok(client) => {
+                        self.add(client).await;
+                        report(McpServerState::Connected, None).await;
+                        break;
+                    }
+                    Err(e) => eprintln!("Failed to restart MCP server {}: {}", server.name, e),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::{client, MCP_PROTOCOL_VERSION};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    #[test]
+    fn test_delay() {
+        let supervision = McpSupervision::new().backoff(Duration::from_millis(100), Duration::from_millis(300));
+        let delays: Vec<_> = (1..=4).map(|attempt| supervision.delay(attempt)).collect();
+        assert_eq!(delays, [100, 200, 300, 300].map(Duration::from_millis));
+    }
+
+    /// Connect to an in-memory MCP server that completes the handshake and
+    /// then ignores everything. Aborting the returned task stops the server.
+    async fn silent_client(name: &str) -> (McpClient, tokio::task::JoinHandle<()>) {
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let (client_read, client_write) = tokio::io::split(client_io);
+        let server = tokio::spawn(async move {
+            let (server_read, mut server_write) = tokio::io::split(server_io);
+            let mut lines = BufReader::new(server_read).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let msg: Value = serde_json::from_str(&line).unwrap();
+                if msg["method"] == "initialize" {
+                    let reply = json!({ "jsonrpc": "2.0", "id": msg["id"], "result": {
+                        "protocolVersion": MCP_PROTOCOL_VERSION,
+                        "capabilities": {},
+                        "serverInfo": { "name": "silent" },
+                    }});
+                    server_write.write_all(format!("{}\n", reply).as_bytes()).await.unwrap();
+                }
+            }
+            std::future::pending::<()>().await;
+        });
+        let client = McpClient::from_streams(name, client_read, client_write).await.unwrap();
+        (client, server)
+    }
+
+    #[tokio::test]
+    async fn test_supervise() {
+        let servers = McpServers::new();
+        let (hung, _hung_server) = silent_client("hung").await;
+        let (crashed, crashed_server) = silent_client("crashed").await;
+        servers.add(hung).await;
+        servers.add(crashed).await;
+        servers.add(client::tests::fake_client("healthy").await).await;
+
+        // None of them can be started again
+        let config = |name: &str| McpServer {
+            name: name.to_string(),
+            url: "/nonexistent/mcp-server".to_string(),
+            credentials: HashMap::new(),
+        };
+        let supervision = McpSupervision::new()
+            .ping(Duration::from_millis(20), Duration::from_millis(20))
+            .backoff(Duration::from_millis(1), Duration::from_millis(1))
+            .max_restarts(2);
+        let (status_tx, mut status_rx) = mpsc::channel(16);
+        servers.supervise(
+            &[config("hung"), config("crashed"), config("healthy"), config("missing")],
+            supervision,
+            status_tx,
+        );
+        crashed_server.abort();
+
+        let mut statuses: HashMap<String, Vec<(McpServerState, Option<String>)>> = HashMap::new();
+        for _ in 0..6 {
+            let status = tokio::time::timeout(Duration::from_secs(5), status_rx.recv()).await.unwrap().unwrap();
+            statuses.entry(status.name).or_default().push((status.state, status.message));
+        }
+        let gave_up = Some("gave up after 2 failed restarts".to_string());
+        assert_eq!(
+            statuses["hung"],
+            [
+                (McpServerState::Unavailable, Some("ping timed out".to_string())),
+                (McpServerState::Failed, gave_up.clone())
+            ]
+        );
+        assert_eq!(statuses["crashed"][0], (McpServerState::Unavailable, Some("connection closed".to_string())));
+        assert_eq!(statuses["missing"][0], (McpServerState::Unavailable, Some("not connected".to_string())));
+        assert!(!statuses.contains_key("healthy"));
+
+        let names: Vec<_> = servers.clients().await.iter().map(|c| c.name().to_string()).collect();
+        assert_eq!(names, ["healthy"]);
+        servers.close().await;
+    }
+}
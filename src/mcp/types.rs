@@ -0,0 +1,122 @@
+//! MCP message types used by [`McpClient`](super::McpClient).
+//!
+//! Only the parts of the MCP schema the client reads are modeled; MCP uses
+//! camelCase field names.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::protocol::ToolInfo;
+
+/// MCP protocol version sent in the handshake.
+pub const MCP_PROTOCOL_VERSION: &str = "2025-03-26";
+
+/// Name and version an MCP server reports in its handshake.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct McpServerInfo {
+    /// Name of the server.
+    pub name: String,
+    /// Version of the server.
+    #[serde(default)]
+    pub version: String,
+}
+
+/// Result of the MCP `initialize` handshake.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpInitializeResult {
+    /// Protocol version the server settled on.
+    pub protocol_version: String,
+    /// Features the server offers, such as `tools` and `resources`.
+    #[serde(default)]
+    pub capabilities: Value,
+    /// Information about the server.
+    #[serde(default)]
+    pub server_info: McpServerInfo,
+    /// Hints on how to use the server.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<String>,
+}
+
+/// A tool offered by an MCP server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpTool {
+    /// Name of the tool.
+    pub name: String,
+    /// Description of what the tool does.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// JSON schema for the tool's arguments.
+    #[serde(default)]
+    pub input_schema: Value,
+}
+
+impl McpTool {
+    /// Describe the tool as an ACP tool of the server named `server`, under
+    /// its [proxied name](super::proxied_tool_name).
+    pub fn tool_info(&self, server: &str) -> ToolInfo {
+        ToolInfo {
+            name: super::proxied_tool_name(server, &self.name),
+            description: self.description.clone().unwrap_or_default(),
+            parameters: self.input_schema.clone(),
+        }
+    }
+}
+
+/// A resource offered by an MCP server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpResource {
+    /// URI of the resource.
+    pub uri: String,
+    /// Name of the resource.
+    pub name: String,
+    /// Description of the resource.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// MIME type of the resource.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+}
+
+/// Contents of a resource, as text or base64 `blob`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpResourceContents {
+    /// URI of the resource.
+    pub uri: String,
+    /// MIME type of the contents.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    /// Text contents.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// Binary contents, base64 encoded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob: Option<String>,
+}
+
+/// Result of calling an MCP tool.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpToolResult {
+    /// MCP content blocks, such as `{"type": "text", "text": "..."}`.
+    #[serde(default)]
+    pub content: Vec<Value>,
+    /// Whether the tool failed. The content then describes the failure.
+    #[serde(default)]
+    pub is_error: bool,
+}
+
+impl McpToolResult {
+    /// The text content blocks, joined by newlines.
+    pub fn text(&self) -> String {
+        self.content
+            .iter()
+            .filter(|block| block["type"] == "text")
+            .filter_map(|block| block["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
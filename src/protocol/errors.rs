@@ -24,6 +24,14 @@ pub mod codes {
     pub const INVALID_STATE: i32 = -32003;
     /// Capability not supported.
     pub const CAPABILITY_NOT_SUPPORTED: i32 = -32004;
+    /// The request did not complete in time.
+    pub const TIMEOUT: i32 = -32005;
+    /// Too many requests; retry later.
+    pub const RATE_LIMITED: i32 = -32006;
+    /// File is binary or not valid UTF-8.
+    pub const INVALID_ENCODING: i32 = -32007;
+    /// File changed since the agent read it; the edit was not applied.
+    pub const EDIT_CONFLICT: i32 = -32008;
 }
 
 /// ACP protocol error.
@@ -84,6 +92,25 @@ pub enum AcpError {
     /// Request timeout.
     #[error("Request timeout")]
     Timeout,
+
+    /// Rate limit exceeded.
+    #[error("Rate limited: {0}")]
+    RateLimited(String),
+
+    /// File content is not valid UTF-8 text.
+    #[error("Invalid encoding: {0}")]
+    InvalidEncoding(String),
+
+    /// An error response from the peer with a code of its own or extra data.
+    #[error("{message}")]
+    Custom {
+        /// JSON-RPC error code.
+        code: i32,
+        /// Error message.
+        message: String,
+        /// Additional error data.
+        data: Option<serde_json::Value>,
+    },
 }
 
 impl AcpError {
@@ -103,7 +130,18 @@ impl AcpError {
             AcpError::JsonError(_) => codes::PARSE_ERROR,
             AcpError::ChannelError(_) => codes::INTERNAL_ERROR,
             AcpError::ConnectionClosed => codes::INTERNAL_ERROR,
-            AcpError::Timeout => codes::INTERNAL_ERROR,
+            AcpError::Timeout => codes::TIMEOUT,
+            AcpError::RateLimited(_) => codes::RATE_LIMITED,
+            AcpError::InvalidEncoding(_) => codes::INVALID_ENCODING,
+            AcpError::Custom { code, .. } => *code,
+        }
+    }
+
+    /// Get the additional error data sent by the peer, if any.
+    pub fn data(&self) -> Option<&serde_json::Value> {
+        match self {
+            AcpError::Custom { data, .. } => data.as_ref(),
+            _ => None,
         }
     }
 
@@ -131,6 +169,10 @@ mod tests {
         assert_eq!(codes::PERMISSION_DENIED, -32002);
         assert_eq!(codes::INVALID_STATE, -32003);
         assert_eq!(codes::CAPABILITY_NOT_SUPPORTED, -32004);
+        assert_eq!(codes::TIMEOUT, -32005);
+        assert_eq!(codes::RATE_LIMITED, -32006);
+        assert_eq!(codes::INVALID_ENCODING, -32007);
+        assert_eq!(codes::EDIT_CONFLICT, -32008);
     }
 
     #[test]
@@ -187,6 +229,12 @@ mod tests {
         assert_eq!(error.code(), codes::CAPABILITY_NOT_SUPPORTED);
     }
 
+    #[test]
+    fn test_invalid_encoding_code() {
+        let error = AcpError::InvalidEncoding("/bin/ls is a binary file".to_string());
+        assert_eq!(error.code(), codes::INVALID_ENCODING);
+    }
+
     #[test]
     fn test_channel_error_code() {
         let error = AcpError::ChannelError("channel closed".to_string());
@@ -202,7 +250,8 @@ mod tests {
     #[test]
     fn test_timeout_code() {
         let error = AcpError::Timeout;
-        assert_eq!(error.code(), codes::INTERNAL_ERROR);
+        assert_eq!(error.code(), codes::TIMEOUT);
+        assert_eq!(codes::TIMEOUT, -32005);
     }
 
     #[test]
@@ -220,6 +269,19 @@ mod tests {
         assert_eq!(error.message(), "Request timeout");
     }
 
+    #[test]
+    fn test_custom_error() {
+        let error = AcpError::Custom {
+            code: -32050,
+            message: "Quota exceeded".to_string(),
+            data: Some(serde_json::json!({"retry_after": 30})),
+        };
+        assert_eq!(error.code(), -32050);
+        assert_eq!(error.message(), "Quota exceeded");
+        assert_eq!(error.data().unwrap()["retry_after"], 30);
+        assert!(AcpError::Timeout.data().is_none());
+    }
+
     #[test]
     fn test_error_display() {
         let error = AcpError::ResourceNotFound("/test.txt".to_string());
@@ -245,6 +307,8 @@ mod tests {
     }
 
     #[test]
+    // Unwrapping the literal is the point of the test
+    #[allow(clippy::unnecessary_literal_unwrap)]
     fn test_acp_result_ok() {
         let result: AcpResult<i32> = Ok(42);
         assert!(result.is_ok());
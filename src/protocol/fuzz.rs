@@ -0,0 +1,228 @@
+//! Arbitrary protocol values, for property tests and fuzzing.
+//!
+//! Enabled with the `arbitrary` feature, which derives
+//! [`Arbitrary`](arbitrary::Arbitrary) for every message and type in
+//! [`protocol`](super). Fields holding free-form JSON or floats are
+//! generated by the functions here, which only produce values JSON can
+//! carry exactly, so a value that doesn't survive a round trip through
+//! serde points at the protocol types rather than at JSON itself:
+//!
+//! ```rust,ignore
+//! use arbitrary::{Arbitrary, Unstructured};
+//! use heroacp::protocol::SessionUpdate;
+//!
+//! fuzz_target!(|data: &[u8]| {
+//!     if let Ok(update) = SessionUpdate::arbitrary(&mut Unstructured::new(data)) {
+//!         let json = serde_json::to_string(&update).unwrap();
+//!         let _: SessionUpdate = serde_json::from_str(&json).unwrap();
+//!     }
+//! });
+//! ```
+
+use arbitrary::{Result, Unstructured};
+use serde_json::{Map, Number, Value};
+use std::collections::HashMap;
+
+use super::RequestId;
+
+/// How deeply generated arrays and objects nest.
+const MAX_DEPTH: usize = 3;
+
+/// Most elements in a generated array or object.
+const MAX_LEN: usize = 4;
+
+/// Any JSON value.
+pub(crate) fn json(u: &mut Unstructured<'_>) -> Result<Value> {
+    value(u, MAX_DEPTH)
+}
+
+/// An optional JSON value. Never `Some(null)`, which JSON can't tell apart
+/// from `None`.
+pub(crate) fn optional_json(u: &mut Unstructured<'_>) -> Result<Option<Value>> {
+    if u.arbitrary()? {
+        Ok(match json(u)? {
+            Value::Null => None,
+            value => Some(value),
+        })
+    } else {
+        Ok(None)
+    }
+}
+
+/// An optional request ID. Never `Some(RequestId::Null)`: a request with
+/// `"id": null` is read as a notification.
+pub(crate) fn request_id(u: &mut Unstructured<'_>) -> Result<Option<RequestId>> {
+    Ok(match u.arbitrary()? {
+        Some(RequestId::Null) => None,
+        id => id,
+    })
+}
+
+/// A map of JSON values, as in experimental capabilities.
+pub(crate) fn json_map(u: &mut Unstructured<'_>) -> Result<HashMap<String, Value>> {
+    let len = u.int_in_range(0..=MAX_LEN)?;
+    (0..len).map(|_| Ok((u.arbitrary()?, json(u)?))).collect()
+}
+
+/// An optional number between 0 and 1, like a priority or temperature.
+pub(crate) fn optional_f64(u: &mut Unstructured<'_>) -> Result<Option<f64>> {
+    if u.arbitrary()? {
+        Ok(Some(fraction(u)?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// A number between 0 and 1 in thousandths, which prints and parses back
+/// exactly.
+fn fraction(u: &mut Unstructured<'_>) -> Result<f64> {
+    Ok(f64::from(u.int_in_range(0..=1000u16)?) / 1000.0)
+}
+
+fn value(u: &mut Unstructured<'_>, depth: usize) -> Result<Value> {
+    let kinds = if depth == 0 { 5 } else { 7 };
+    Ok(match u.choose_index(kinds)? {
+        0 => Value::Null,
+        1 => Value::Bool(u.arbitrary()?),
+        2 => Value::Number(u.arbitrary::<i64>()?.into()),
+        3 => Value::Number(Number::from_f64(fraction(u)?).expect("fractions are finite")),
+        4 => Value::String(u.arbitrary()?),
+        5 => {
+            let len = u.int_in_range(0..=MAX_LEN)?;
+            Value::Array((0..len).map(|_| value(u, depth - 1)).collect::<Result<_>>()?)
+        }
+        _ => {
+            let len = u.int_in_range(0..=MAX_LEN)?;
+            let mut map = Map::new();
+            for _ in 0..len {
+                map.insert(u.arbitrary()?, value(u, depth - 1)?);
+            }
+            Value::Object(map)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use arbitrary::{Arbitrary, Unstructured};
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+
+    /// Values generated per type.
+    const CASES: u64 = 256;
+
+    /// Deterministic bytes for case `seed` (splitmix64).
+    fn bytes(seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..512)
+            .flat_map(|_| {
+                state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+                let mut z = state;
+                z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                (z ^ (z >> 31)).to_le_bytes()
+            })
+            .collect()
+    }
+
+    /// Every generated `T` serializes to JSON that parses back into a `T`
+    /// serializing to the same JSON.
+    fn assert_round_trips<T>()
+    where
+        T: for<'a> Arbitrary<'a> + Serialize + DeserializeOwned,
+    {
+        for seed in 0..CASES {
+            let bytes = bytes(seed);
+            let value = T::arbitrary(&mut Unstructured::new(&bytes)).unwrap();
+            let json = serde_json::to_value(&value).unwrap();
+            let text = serde_json::to_string(&value).unwrap();
+            let parsed: T = serde_json::from_str(&text).unwrap_or_else(|e| {
+                panic!("{} doesn't parse back ({}): {}", std::any::type_name::<T>(), e, text)
+            });
+            assert_eq!(
+                serde_json::to_value(&parsed).unwrap(),
+                json,
+                "{} changed in a round trip",
+                std::any::type_name::<T>()
+            );
+        }
+    }
+
+    macro_rules! round_trip {
+        ($($ty:ident),* $(,)?) => {
+            $(
+                #[test]
+                #[allow(non_snake_case)]
+                fn $ty() {
+                    assert_round_trips::<$ty>();
+                }
+            )*
+        };
+    }
+
+    round_trip!(
+        JsonRpcRequest,
+        JsonRpcResponse,
+        JsonRpcNotification,
+        InitializeParams,
+        InitializeResult,
+        AuthenticateParams,
+        AuthenticateResult,
+        AgentStatusResult,
+        SessionNewParams,
+        SessionNewResult,
+        SessionLoadParams,
+        SessionLoadResult,
+        SessionPromptParams,
+        SessionPromptResult,
+        PromptStreamStartParams,
+        PromptStreamStartResult,
+        PromptStreamChunkParams,
+        PromptStreamEndParams,
+        SessionCancelParams,
+        SessionSetModelParams,
+        SessionStatsParams,
+        SessionStats,
+        SessionUpdate,
+        McpServerStatus,
+        RequestPermissionParams,
+        RequestPermissionResult,
+        SessionSamplingRequestParams,
+        SessionSamplingRequestResult,
+        FsReadTextFileParams,
+        FsReadTextFileResult,
+        FsWriteTextFileParams,
+        FsWriteTextFileResult,
+        FsApplyEditParams,
+        FsApplyEditResult,
+        FsReadFileParams,
+        FsReadFileResult,
+        FsWriteFileParams,
+        FsReadStreamStartParams,
+        FsReadStreamStartResult,
+        FsReadStreamParams,
+        FsReadChunkResult,
+        FsListDirectoryParams,
+        FsListDirectoryResult,
+        FsGlobParams,
+        FsGlobResult,
+        FsGrepParams,
+        FsGrepResult,
+        FsDeleteParams,
+        FsRenameParams,
+        FsCreateDirectoryParams,
+        TerminalCreateParams,
+        TerminalCreateResult,
+        TerminalOutputParams,
+        TerminalOutputResult,
+        TerminalInputParams,
+        TerminalInputResult,
+        TerminalWaitForExitParams,
+        TerminalWaitForExitResult,
+        TerminalKillParams,
+        TerminalKillResult,
+        TerminalReleaseParams,
+        TerminalReleaseResult,
+    );
+}
@@ -1,34 +1,92 @@
 //! JSON-RPC message types for ACP.
 
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 
+use super::errors::{codes, AcpError, AcpResult};
 use super::types::*;
 
+/// JSON-RPC 2.0 request identifier.
+///
+/// Peers may use numbers or strings; both are matched exactly when correlating
+/// responses with requests.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[serde(untagged)]
+pub enum RequestId {
+    /// Numeric ID.
+    Number(i64),
+    /// String ID.
+    String(String),
+    /// Null ID, used when responding to a request whose ID could not be determined.
+    Null,
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestId::Number(n) => write!(f, "{}", n),
+            RequestId::String(s) => f.write_str(s),
+            RequestId::Null => f.write_str("null"),
+        }
+    }
+}
+
+impl From<i64> for RequestId {
+    fn from(id: i64) -> Self {
+        RequestId::Number(id)
+    }
+}
+
+impl From<String> for RequestId {
+    fn from(id: String) -> Self {
+        RequestId::String(id)
+    }
+}
+
+impl From<&str> for RequestId {
+    fn from(id: &str) -> Self {
+        RequestId::String(id.to_string())
+    }
+}
+
 /// JSON-RPC 2.0 request message.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct JsonRpcRequest {
     /// JSON-RPC version (always "2.0").
     pub jsonrpc: String,
     /// Request ID (omitted for notifications).
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub id: Option<Value>,
+    #[cfg_attr(feature = "arbitrary", arbitrary(with = super::fuzz::request_id))]
+    pub id: Option<RequestId>,
     /// Method name.
     pub method: String,
     /// Method parameters.
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "arbitrary", arbitrary(with = super::fuzz::optional_json))]
     pub params: Option<Value>,
 }
 
 /// JSON-RPC 2.0 response message.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct JsonRpcResponse {
     /// JSON-RPC version (always "2.0").
     pub jsonrpc: String,
     /// Request ID this responds to.
-    pub id: Value,
+    pub id: RequestId,
     /// Result (on success).
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "arbitrary", arbitrary(with = super::fuzz::optional_json))]
     pub result: Option<Value>,
     /// Error (on failure).
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -37,6 +95,8 @@ pub struct JsonRpcResponse {
 
 /// JSON-RPC 2.0 error object.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct JsonRpcError {
     /// Error code.
     pub code: i32,
@@ -44,11 +104,56 @@ pub struct JsonRpcError {
     pub message: String,
     /// Additional error data.
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "arbitrary", arbitrary(with = super::fuzz::optional_json))]
     pub data: Option<Value>,
 }
 
+impl From<JsonRpcError> for AcpError {
+    /// Turn an error response back into the variant for its code.
+    ///
+    /// Errors with an unknown code or with `data` become
+    /// [`AcpError::Custom`], so the code and data are kept.
+    fn from(error: JsonRpcError) -> Self {
+        let variant: fn(String) -> AcpError = match error.code {
+            _ if error.data.is_some() => return custom(error),
+            codes::PARSE_ERROR => AcpError::ParseError,
+            codes::INVALID_REQUEST => AcpError::InvalidRequest,
+            codes::METHOD_NOT_FOUND => AcpError::MethodNotFound,
+            codes::INVALID_PARAMS => AcpError::InvalidParams,
+            codes::INTERNAL_ERROR => AcpError::InternalError,
+            codes::RESOURCE_NOT_FOUND => AcpError::ResourceNotFound,
+            codes::PERMISSION_DENIED => AcpError::PermissionDenied,
+            codes::INVALID_STATE => AcpError::InvalidState,
+            codes::CAPABILITY_NOT_SUPPORTED => AcpError::CapabilityNotSupported,
+            codes::TIMEOUT => |_| AcpError::Timeout,
+            codes::RATE_LIMITED => AcpError::RateLimited,
+            codes::INVALID_ENCODING => AcpError::InvalidEncoding,
+            _ => return custom(error),
+        };
+        // Our own peers send `AcpError::message()`; drop the prefix the
+        // variant adds again when displayed
+        let prefix = variant(String::new()).to_string();
+        let message = error
+            .message
+            .strip_prefix(&prefix)
+            .map(str::to_string)
+            .unwrap_or(error.message);
+        variant(message)
+    }
+}
+
+fn custom(error: JsonRpcError) -> AcpError {
+    AcpError::Custom {
+        code: error.code,
+        message: error.message,
+        data: error.data,
+    }
+}
+
 /// JSON-RPC 2.0 notification (request without id).
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct JsonRpcNotification {
     /// JSON-RPC version (always "2.0").
     pub jsonrpc: String,
@@ -56,6 +161,7 @@ pub struct JsonRpcNotification {
     pub method: String,
     /// Method parameters.
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "arbitrary", arbitrary(with = super::fuzz::optional_json))]
     pub params: Option<Value>,
 }
 
@@ -65,6 +171,8 @@ pub struct JsonRpcNotification {
 
 /// Parameters for the initialize request.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct InitializeParams {
     /// Protocol version the client supports.
     pub protocol_version: String,
@@ -81,6 +189,8 @@ pub struct InitializeParams {
 
 /// Result of the initialize request.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct InitializeResult {
     /// Information about the agent.
     pub agent_info: AgentInfo,
@@ -97,6 +207,8 @@ pub struct InitializeResult {
 
 /// Parameters for the authenticate request.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct AuthenticateParams {
     /// Authentication type.
     #[serde(rename = "type")]
@@ -108,27 +220,75 @@ pub struct AuthenticateParams {
 
 /// Result of the authenticate request.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct AuthenticateResult {
     /// Whether authentication was successful.
     pub success: bool,
 }
 
+// ============================================================================
+// Status
+// ============================================================================
+
+/// Result of the `agent/status` request.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct AgentStatusResult {
+    /// Milliseconds since the agent started.
+    pub uptime_ms: u64,
+    /// Sessions the agent knows.
+    pub active_sessions: u64,
+    /// Prompts running now.
+    pub running_prompts: u64,
+    /// Prompts waiting for another prompt in their session to finish.
+    pub queued_prompts: u64,
+    /// What the agent's process uses, as far as the agent can tell.
+    #[serde(default)]
+    pub resources: ResourceUsage,
+}
+
+/// Resources an agent's process uses. Fields the platform doesn't report
+/// are left out.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct ResourceUsage {
+    /// Process ID.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    /// Resident memory in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_bytes: Option<u64>,
+    /// Number of threads.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threads: Option<u64>,
+}
+
 // ============================================================================
 // Session Management
 // ============================================================================
 
 /// Parameters for creating a new session.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct SessionNewParams {
     /// Unique session ID.
     pub session_id: String,
     /// Operational mode.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub mode: Option<String>,
+    /// Name of the agent to handle the session, for servers hosting several agents.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<String>,
 }
 
 /// Result of creating a new session.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct SessionNewResult {
     /// The session ID.
     pub session_id: String,
@@ -136,6 +296,8 @@ pub struct SessionNewResult {
 
 /// Parameters for loading an existing session.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct SessionLoadParams {
     /// Session ID to load.
     pub session_id: String,
@@ -143,6 +305,8 @@ pub struct SessionLoadParams {
 
 /// Result of loading a session.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct SessionLoadResult {
     /// The session ID.
     pub session_id: String,
@@ -152,6 +316,8 @@ pub struct SessionLoadResult {
 
 /// Parameters for sending a prompt.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct SessionPromptParams {
     /// Session ID.
     pub session_id: String,
@@ -161,16 +327,196 @@ pub struct SessionPromptParams {
 
 /// Result of sending a prompt.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct SessionPromptResult {
     /// Status of the prompt processing.
     pub status: String,
 }
 
+/// Parameters for starting a streamed prompt.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct PromptStreamStartParams {
+    /// Session the prompt is for.
+    pub session_id: String,
+}
+
+/// Result of starting a streamed prompt.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct PromptStreamStartResult {
+    /// Identifier to send the chunks under.
+    pub stream_id: String,
+}
+
+/// Parameters for sending part of a streamed prompt.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct PromptStreamChunkParams {
+    /// Stream returned by `session/prompt_stream_start`.
+    pub stream_id: String,
+    /// Content blocks to append to the prompt.
+    pub content: Vec<ContentBlock>,
+    /// Whether the first block continues the last block of the previous
+    /// chunk. Only text blocks can be continued.
+    #[serde(default)]
+    pub continuation: bool,
+}
+
+/// Parameters for finishing a streamed prompt.
+///
+/// The result is a [`SessionPromptResult`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct PromptStreamEndParams {
+    /// Stream returned by `session/prompt_stream_start`.
+    pub stream_id: String,
+}
+
 /// Parameters for cancelling a session.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct SessionCancelParams {
     /// Session ID to cancel.
     pub session_id: String,
+    /// Cancel only this queued prompt. Without it, the running prompt and
+    /// all queued prompts are cancelled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_id: Option<String>,
+}
+
+/// Parameters for switching the model of a session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct SessionSetModelParams {
+    /// Session ID.
+    pub session_id: String,
+    /// ID of the model to use from now on, one of
+    /// [`AgentCapabilities::models`] if the agent lists any.
+    pub model_id: String,
+}
+
+/// Parameters for asking the usage of a session.
+///
+/// The result is a [`SessionStats`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct SessionStatsParams {
+    /// Session ID.
+    pub session_id: String,
+}
+
+/// Usage of a session since it was created or loaded.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct SessionStats {
+    /// Prompts received.
+    pub prompts: u64,
+    /// `agent_message_chunk` updates sent.
+    pub message_chunks: u64,
+    /// Tool calls announced, by tool name.
+    #[serde(default)]
+    pub tool_calls: BTreeMap<String, u64>,
+    /// Bytes of prompt content received.
+    pub bytes_received: u64,
+    /// Bytes of `session/update` notifications sent.
+    pub bytes_sent: u64,
+    /// Milliseconds spent answering prompts, not counting time queued.
+    pub prompt_time_ms: u64,
+}
+
+// ============================================================================
+// Permissions
+// ============================================================================
+
+/// Parameters for asking the user to approve a tool call.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct RequestPermissionParams {
+    /// Session the tool call belongs to.
+    pub session_id: String,
+    /// The tool call awaiting approval.
+    pub tool_call: ToolCall,
+    /// Options to present to the user.
+    pub options: Vec<PermissionOption>,
+}
+
+/// Result of a permission request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct RequestPermissionResult {
+    /// The user's decision.
+    pub outcome: PermissionOutcome,
+}
+
+// ============================================================================
+// Sampling
+// ============================================================================
+
+/// A message in the conversation a sampling request asks to continue.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct SamplingMessage {
+    /// Who sent the message.
+    pub role: Role,
+    /// Text, image or audio content of the message.
+    pub content: ContentBlock,
+}
+
+/// Parameters for asking the client's model for a completion on behalf of an
+/// MCP server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct SessionSamplingRequestParams {
+    /// Name of the MCP server asking.
+    pub server: String,
+    /// Conversation to continue.
+    pub messages: Vec<SamplingMessage>,
+    /// System prompt the server asks for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    /// Most tokens to generate.
+    pub max_tokens: u32,
+    /// Sampling temperature.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "arbitrary", arbitrary(with = super::fuzz::optional_f64))]
+    pub temperature: Option<f64>,
+    /// Sequences that end the completion.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop_sequences: Vec<String>,
+    /// The server's model hints and priorities, as MCP defines them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "arbitrary", arbitrary(with = super::fuzz::optional_json))]
+    pub model_preferences: Option<Value>,
+}
+
+/// Result of a sampling request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct SessionSamplingRequestResult {
+    /// Role of the generated message, normally `assistant`.
+    pub role: Role,
+    /// The generated content.
+    pub content: ContentBlock,
+    /// Name of the model that generated it.
+    pub model: String,
+    /// Why generation stopped, e.g. `endTurn` or `maxTokens`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_reason: Option<String>,
 }
 
 // ============================================================================
@@ -179,49 +525,484 @@ pub struct SessionCancelParams {
 
 /// Parameters for reading a text file.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct FsReadTextFileParams {
     /// Absolute path to the file.
     pub path: String,
+    /// Line to start reading at, counting from 1. Defaults to the first line.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<u32>,
+    /// Maximum number of lines to read. Defaults to the rest of the file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
 }
 
 /// Result of reading a text file.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct FsReadTextFileResult {
     /// Content of the file.
     pub content: String,
 }
 
 /// Parameters for writing a text file.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct FsWriteTextFileParams {
     /// Absolute path to the file.
     pub path: String,
     /// Content to write.
     pub content: String,
+    /// Create missing parent directories. Defaults to the client's setting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub create_dirs: Option<bool>,
+    /// Write to a temporary file and rename it over the target, so the file
+    /// is never left half written. Defaults to the client's setting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub atomic: Option<bool>,
+    /// Session the write is for, so a client that asks before writes can
+    /// remember "allow always" per session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
 }
 
 /// Result of writing a text file.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct FsWriteTextFileResult {
     /// Whether the write was successful.
     pub success: bool,
 }
 
+/// Parameters for editing a text file with a unified diff.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct FsApplyEditParams {
+    /// Absolute path to the file.
+    pub path: String,
+    /// Unified diff against the file, as printed by `diff -u` or `git diff`.
+    /// File headers are optional.
+    pub unified_diff: String,
+    /// [`content_hash`] of the file the diff was made against. When set, the
+    /// edit fails if the file has changed at all since.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_hash: Option<String>,
+    /// Session the edit is for, as in [`FsWriteTextFileParams`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+}
+
+/// Result of editing a text file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct FsApplyEditResult {
+    /// [`content_hash`] of the edited file, to use as the base of the next
+    /// edit.
+    pub hash: String,
+}
+
+/// Why an edit was refused with [`codes::EDIT_CONFLICT`], sent as the
+/// error's data.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct EditConflict {
+    /// [`content_hash`] of the file as it is now.
+    pub current_hash: String,
+    /// The hunk, counting from 1, whose lines are no longer in the file.
+    /// `None` if the file didn't match `base_hash`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hunk: Option<u32>,
+}
+
+impl EditConflict {
+    /// The error to answer `fs/apply_edit` with.
+    pub fn into_error(self, message: impl Into<String>) -> AcpError {
+        AcpError::Custom {
+            code: codes::EDIT_CONFLICT,
+            message: message.into(),
+            data: serde_json::to_value(self).ok(),
+        }
+    }
+
+    /// The conflict an `fs/apply_edit` error reports, if it is one.
+    pub fn from_error(error: &AcpError) -> Option<Self> {
+        if error.code() != codes::EDIT_CONFLICT {
+            return None;
+        }
+        serde_json::from_value(error.data()?.clone()).ok()
+    }
+}
+
+/// Hash of a text file's content, as used by `fs/apply_edit`: the lowercase
+/// hex SHA-256 of its UTF-8 bytes.
+pub fn content_hash(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+/// Parameters for reading a file of any kind.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct FsReadFileParams {
+    /// Absolute path to the file.
+    pub path: String,
+}
+
+/// Result of reading a file of any kind.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct FsReadFileResult {
+    /// Base64-encoded content of the file.
+    pub data: String,
+    /// MIME type guessed from the file name, `application/octet-stream` if
+    /// unknown.
+    pub mime_type: String,
+}
+
+impl FsReadFileResult {
+    /// Encode `bytes` read from a file of type `mime_type`.
+    pub fn new(bytes: &[u8], mime_type: impl Into<String>) -> Self {
+        Self {
+            data: BASE64.encode(bytes),
+            mime_type: mime_type.into(),
+        }
+    }
+
+    /// Decode the content of the file.
+    pub fn bytes(&self) -> AcpResult<Vec<u8>> {
+        decode_base64(&self.data)
+    }
+}
+
+/// Parameters for writing a file of any kind.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct FsWriteFileParams {
+    /// Absolute path to the file.
+    pub path: String,
+    /// Base64-encoded content to write.
+    pub data: String,
+    /// Create missing parent directories. Defaults to the client's setting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub create_dirs: Option<bool>,
+    /// Write to a temporary file and rename it over the target, so the file
+    /// is never left half written. Defaults to the client's setting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub atomic: Option<bool>,
+    /// Session the write is for, so a client that asks before writes can
+    /// remember "allow always" per session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+}
+
+impl FsWriteFileParams {
+    /// Write `bytes` to `path` with the client's default options.
+    pub fn new(path: impl Into<String>, bytes: &[u8]) -> Self {
+        Self {
+            path: path.into(),
+            data: BASE64.encode(bytes),
+            ..Default::default()
+        }
+    }
+
+    /// Decode the content to write.
+    pub fn bytes(&self) -> AcpResult<Vec<u8>> {
+        decode_base64(&self.data)
+    }
+}
+
+/// Parameters for starting to read a file in chunks.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct FsReadStreamStartParams {
+    /// Absolute path to the file.
+    pub path: String,
+    /// Most bytes per chunk. Defaults to the client's chunk size; the client
+    /// may lower it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_size: Option<u32>,
+}
+
+/// Result of starting to read a file in chunks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct FsReadStreamStartResult {
+    /// ID to pass to `fs/read_chunk` and `fs/read_stream_close`.
+    pub stream_id: String,
+    /// Size of the file in bytes, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// MIME type guessed from the file name.
+    pub mime_type: String,
+    /// Most bytes the client sends per chunk.
+    pub chunk_size: u32,
+}
+
+/// Parameters naming a file read stream.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct FsReadStreamParams {
+    /// ID from `fs/read_stream_start`.
+    pub stream_id: String,
+}
+
+/// One chunk of a file read stream.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct FsReadChunkResult {
+    /// Base64-encoded bytes of this chunk; empty at the end of the file.
+    pub data: String,
+    /// Whether the file has been read to the end. The stream is closed
+    /// once this is set.
+    pub eof: bool,
+}
+
+impl FsReadChunkResult {
+    /// Encode a chunk of `bytes`.
+    pub fn new(bytes: &[u8], eof: bool) -> Self {
+        Self {
+            data: BASE64.encode(bytes),
+            eof,
+        }
+    }
+
+    /// Decode the bytes of this chunk.
+    pub fn bytes(&self) -> AcpResult<Vec<u8>> {
+        decode_base64(&self.data)
+    }
+}
+
+fn decode_base64(data: &str) -> AcpResult<Vec<u8>> {
+    BASE64
+        .decode(data)
+        .map_err(|e| AcpError::InvalidEncoding(format!("invalid base64 data: {}", e)))
+}
+
+/// Parameters for listing a directory.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct FsListDirectoryParams {
+    /// Absolute path to the directory.
+    pub path: String,
+    /// Also list the contents of subdirectories.
+    #[serde(default)]
+    pub recursive: bool,
+    /// Maximum number of entries to return. Defaults to the client's limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_entries: Option<u32>,
+}
+
+/// What a [`DirectoryEntry`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[serde(rename_all = "snake_case")]
+pub enum DirectoryEntryKind {
+    /// A regular file.
+    File,
+    /// A directory.
+    Directory,
+    /// A symbolic link, which is not followed.
+    Symlink,
+    /// Anything else, such as a socket or device.
+    Other,
+}
+
+/// One entry of a directory listing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct DirectoryEntry {
+    /// Path relative to the listed directory, with `/` separators.
+    pub name: String,
+    /// What the entry is.
+    pub kind: DirectoryEntryKind,
+    /// Size in bytes, for files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+/// Result of listing a directory.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct FsListDirectoryResult {
+    /// Entries sorted by name within each directory, a directory's own
+    /// entries before those of its subdirectories.
+    pub entries: Vec<DirectoryEntry>,
+    /// Whether entries were left out because of `max_entries`.
+    #[serde(default)]
+    pub truncated: bool,
+}
+
+/// Parameters for finding files by pattern.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct FsGlobParams {
+    /// Glob pattern relative to `cwd`, e.g. `src/**/*.rs`. `*` and `?` don't
+    /// match `/`; `**` matches any number of directories.
+    pub pattern: String,
+    /// Absolute path of the directory to search.
+    pub cwd: String,
+    /// Maximum number of paths to return. Defaults to the client's limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_results: Option<u32>,
+}
+
+/// Result of finding files by pattern.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct FsGlobResult {
+    /// Absolute paths of the matching files, sorted.
+    pub paths: Vec<String>,
+    /// Whether matches were left out because of `max_results`.
+    #[serde(default)]
+    pub truncated: bool,
+}
+
+/// Parameters for searching file contents.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct FsGrepParams {
+    /// Text to search for, matched within single lines.
+    pub query: String,
+    /// Treat `query` as a regular expression instead of literal text.
+    #[serde(default)]
+    pub regex: bool,
+    /// Absolute path of the file or directory to search.
+    pub path: String,
+    /// Maximum number of matching lines to return. Defaults to the client's
+    /// limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_matches: Option<u32>,
+}
+
+/// One line matching a search.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct GrepMatch {
+    /// Absolute path of the file.
+    pub path: String,
+    /// Line number, starting at 1.
+    pub line: u32,
+    /// The matching line, without its line ending.
+    pub snippet: String,
+}
+
+/// Result of searching file contents.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct FsGrepResult {
+    /// Matching lines, sorted by path and line.
+    pub matches: Vec<GrepMatch>,
+    /// Whether matches were left out because of `max_matches`.
+    #[serde(default)]
+    pub truncated: bool,
+}
+
+/// Parameters for deleting a file or directory.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct FsDeleteParams {
+    /// Absolute path to delete.
+    pub path: String,
+    /// Also delete the contents of a directory that isn't empty.
+    #[serde(default)]
+    pub recursive: bool,
+    /// Session the change is for, so a client that asks before changes can
+    /// remember "allow always" per session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+}
+
+/// Parameters for moving a file or directory.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct FsRenameParams {
+    /// Absolute path to move.
+    pub from: String,
+    /// Absolute path to move it to.
+    pub to: String,
+    /// Replace `to` if it already exists.
+    #[serde(default)]
+    pub overwrite: bool,
+    /// Session the change is for, so a client that asks before changes can
+    /// remember "allow always" per session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+}
+
+/// Parameters for creating a directory.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct FsCreateDirectoryParams {
+    /// Absolute path of the directory.
+    pub path: String,
+    /// Also create missing parent directories, and succeed if the directory
+    /// already exists.
+    #[serde(default)]
+    pub recursive: bool,
+    /// Session the change is for, so a client that asks before changes can
+    /// remember "allow always" per session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+}
+
 // ============================================================================
 // Terminal Operations
 // ============================================================================
 
 /// Parameters for creating a terminal.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct TerminalCreateParams {
     /// Working directory.
     pub cwd: String,
-    /// Command to execute.
+    /// Command to execute, as a shell command line unless `no_shell` is set.
     pub command: String,
+    /// Arguments for the command. With a shell they are available as `$1`,
+    /// `$2`, ... so they need no quoting.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// Environment variables to set on top of the client's own.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+    /// Run `command` as a program with `args` instead of through a shell.
+    #[serde(default)]
+    pub no_shell: bool,
+    /// Keep at most this many bytes of output, dropping the oldest. The
+    /// client picks a limit if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_byte_limit: Option<u64>,
 }
 
 /// Result of creating a terminal.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct TerminalCreateResult {
     /// Terminal ID.
     pub terminal_id: String,
@@ -229,6 +1010,8 @@ pub struct TerminalCreateResult {
 
 /// Parameters for getting terminal output.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct TerminalOutputParams {
     /// Terminal ID.
     pub terminal_id: String,
@@ -236,18 +1019,54 @@ pub struct TerminalOutputParams {
 
 /// Result of getting terminal output.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct TerminalOutputResult {
-    /// Output text.
+    /// Output text, stdout and stderr interleaved as written.
     pub output: String,
+    /// The part of the output written to stderr, if the client tracks it.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub stderr: String,
     /// Whether the terminal has exited.
     pub exited: bool,
     /// Exit code (if exited).
     #[serde(skip_serializing_if = "Option::is_none")]
     pub exit_code: Option<i32>,
+    /// Whether older output was dropped to stay within the byte limit.
+    #[serde(default)]
+    pub truncated: bool,
+    /// Bytes of output produced so far, including any that were dropped.
+    #[serde(default)]
+    pub total_bytes: u64,
+}
+
+/// Parameters for writing to a terminal's stdin.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct TerminalInputParams {
+    /// Terminal ID.
+    pub terminal_id: String,
+    /// Text to write, e.g. `"y\n"` to answer a prompt.
+    pub data: String,
+    /// Close stdin after writing, so the command sees end of input.
+    #[serde(default)]
+    pub eof: bool,
+}
+
+/// Result of writing to a terminal's stdin.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct TerminalInputResult {
+    /// Whether the input was written.
+    pub success: bool,
 }
 
 /// Parameters for waiting for terminal exit.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct TerminalWaitForExitParams {
     /// Terminal ID.
     pub terminal_id: String,
@@ -255,15 +1074,28 @@ pub struct TerminalWaitForExitParams {
 
 /// Result of waiting for terminal exit.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct TerminalWaitForExitResult {
     /// Exit code.
     pub exit_code: i32,
-    /// Final output.
+    /// Final output, stdout and stderr interleaved as written.
     pub output: String,
+    /// The part of the output written to stderr, if the client tracks it.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub stderr: String,
+    /// Whether older output was dropped to stay within the byte limit.
+    #[serde(default)]
+    pub truncated: bool,
+    /// Bytes of output produced, including any that were dropped.
+    #[serde(default)]
+    pub total_bytes: u64,
 }
 
 /// Parameters for killing a terminal.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct TerminalKillParams {
     /// Terminal ID.
     pub terminal_id: String,
@@ -271,6 +1103,8 @@ pub struct TerminalKillParams {
 
 /// Result of killing a terminal.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct TerminalKillResult {
     /// Whether the kill was successful.
     pub success: bool,
@@ -278,6 +1112,8 @@ pub struct TerminalKillResult {
 
 /// Parameters for releasing a terminal.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct TerminalReleaseParams {
     /// Terminal ID.
     pub terminal_id: String,
@@ -285,6 +1121,8 @@ pub struct TerminalReleaseParams {
 
 /// Result of releasing a terminal.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct TerminalReleaseResult {
     /// Whether the release was successful.
     pub success: bool,
@@ -299,7 +1137,7 @@ mod tests {
     fn test_json_rpc_request_serialization() {
         let request = JsonRpcRequest {
             jsonrpc: "2.0".to_string(),
-            id: Some(Value::Number(1.into())),
+            id: Some(RequestId::Number(1)),
             method: "initialize".to_string(),
             params: Some(serde_json::json!({"test": "value"})),
         };
@@ -312,6 +1150,40 @@ mod tests {
         assert_eq!(deserialized.method, "initialize");
     }
 
+    #[test]
+    fn test_request_id_number_and_string() {
+        let id: RequestId = serde_json::from_str("42").unwrap();
+        assert_eq!(id, RequestId::Number(42));
+        assert_eq!(serde_json::to_string(&id).unwrap(), "42");
+
+        let id: RequestId = serde_json::from_str("\"abc\"").unwrap();
+        assert_eq!(id, RequestId::from("abc"));
+        assert_eq!(id.to_string(), "abc");
+        assert_eq!(serde_json::to_string(&id).unwrap(), "\"abc\"");
+
+        // A string that looks like a number is not the same ID as the number.
+        let id: RequestId = serde_json::from_str("\"1\"").unwrap();
+        assert_ne!(id, RequestId::Number(1));
+    }
+
+    #[test]
+    fn test_request_id_null() {
+        let id: RequestId = serde_json::from_str("null").unwrap();
+        assert_eq!(id, RequestId::Null);
+        assert_eq!(serde_json::to_string(&RequestId::Null).unwrap(), "null");
+    }
+
+    #[test]
+    fn test_request_id_as_map_key() {
+        let mut pending = HashMap::new();
+        pending.insert(RequestId::from("req-1"), "first");
+        pending.insert(RequestId::Number(1), "second");
+
+        let parsed: JsonRpcResponse =
+            serde_json::from_str(r#"{"jsonrpc":"2.0","id":"req-1","result":null}"#).unwrap();
+        assert_eq!(pending.get(&parsed.id), Some(&"first"));
+    }
+
     #[test]
     fn test_json_rpc_request_notification() {
         let notification = JsonRpcRequest {
@@ -329,7 +1201,7 @@ mod tests {
     fn test_json_rpc_response_success() {
         let response = JsonRpcResponse {
             jsonrpc: "2.0".to_string(),
-            id: Value::Number(1.into()),
+            id: RequestId::Number(1),
             result: Some(serde_json::json!({"status": "ok"})),
             error: None,
         };
@@ -342,7 +1214,7 @@ mod tests {
     fn test_json_rpc_response_error() {
         let response = JsonRpcResponse {
             jsonrpc: "2.0".to_string(),
-            id: Value::Number(1.into()),
+            id: RequestId::Number(1),
             result: None,
             error: Some(JsonRpcError {
                 code: -32601,
@@ -369,6 +1241,38 @@ mod tests {
         assert_eq!(deserialized.message, "Invalid Request");
     }
 
+    #[test]
+    fn test_json_rpc_error_into_acp_error() {
+        let error = |code, message: &str, data| {
+            AcpError::from(JsonRpcError {
+                code,
+                message: message.to_string(),
+                data,
+            })
+        };
+
+        let denied = error(codes::PERMISSION_DENIED, "Permission denied: /etc/shadow", None);
+        assert!(matches!(&denied, AcpError::PermissionDenied(path) if path == "/etc/shadow"));
+        assert_eq!(denied.to_string(), "Permission denied: /etc/shadow");
+        assert!(matches!(
+            error(codes::METHOD_NOT_FOUND, "no such method", None),
+            AcpError::MethodNotFound(m) if m == "no such method"
+        ));
+        assert!(matches!(error(codes::TIMEOUT, "Request timeout", None), AcpError::Timeout));
+
+        let custom = error(-32050, "Quota exceeded", None);
+        assert_eq!(custom.code(), -32050);
+        assert_eq!(custom.to_string(), "Quota exceeded");
+
+        let detailed = error(
+            codes::INVALID_PARAMS,
+            "Invalid params",
+            Some(serde_json::json!({"field": "path"})),
+        );
+        assert_eq!(detailed.code(), codes::INVALID_PARAMS);
+        assert_eq!(detailed.data().unwrap()["field"], "path");
+    }
+
     #[test]
     fn test_json_rpc_notification_serialization() {
         let notification = JsonRpcNotification {
@@ -433,6 +1337,8 @@ mod tests {
                 image: true,
                 supported_modes: vec!["agent".to_string()],
                 tools: vec![],
+                custom_methods: vec![],
+                models: vec![],
             },
             instructions: Some("Hello!".to_string()),
         };
@@ -482,6 +1388,7 @@ mod tests {
         let params = SessionNewParams {
             session_id: "session_123".to_string(),
             mode: Some("agent".to_string()),
+            agent: None,
         };
         let json = serde_json::to_string(&params).unwrap();
         let deserialized: SessionNewParams = serde_json::from_str(&json).unwrap();
@@ -494,9 +1401,19 @@ mod tests {
         let params = SessionNewParams {
             session_id: "session_123".to_string(),
             mode: None,
+            agent: None,
         };
         let json = serde_json::to_string(&params).unwrap();
         assert!(!json.contains("mode"));
+        assert!(!json.contains("agent"));
+    }
+
+    #[test]
+    fn test_session_new_params_with_agent() {
+        let params: SessionNewParams =
+            serde_json::from_str(r#"{"session_id": "s1", "agent": "reviewer"}"#).unwrap();
+        assert_eq!(params.agent.as_deref(), Some("reviewer"));
+        assert_eq!(params.mode, None);
     }
 
     #[test]
@@ -534,9 +1451,7 @@ mod tests {
     fn test_session_prompt_params_serialization() {
         let params = SessionPromptParams {
             session_id: "session_123".to_string(),
-            content: vec![ContentBlock::Text {
-                text: "Hello, agent!".to_string(),
-            }],
+            content: vec![ContentBlock::text("Hello, agent!")],
         };
         let json = serde_json::to_string(&params).unwrap();
         let deserialized: SessionPromptParams = serde_json::from_str(&json).unwrap();
@@ -554,24 +1469,100 @@ mod tests {
         assert_eq!(deserialized.status, "ok");
     }
 
+    #[test]
+    fn test_request_permission_round_trip() {
+        let params = RequestPermissionParams {
+            session_id: "s1".to_string(),
+            tool_call: ToolCall {
+                id: "tool_1".to_string(),
+                name: "run_command".to_string(),
+                arguments: serde_json::json!({"command": "rm -rf build"}),
+            },
+            options: vec![PermissionOption {
+                option_id: "reject".to_string(),
+                name: "Reject".to_string(),
+                kind: PermissionOptionKind::RejectOnce,
+            }],
+        };
+        let json = serde_json::to_string(&params).unwrap();
+        let parsed: RequestPermissionParams = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.tool_call.id, "tool_1");
+        assert_eq!(parsed.options[0].kind, PermissionOptionKind::RejectOnce);
+
+        let result: RequestPermissionResult =
+            serde_json::from_str(r#"{"outcome": {"outcome": "selected", "option_id": "reject"}}"#)
+                .unwrap();
+        assert_eq!(
+            result.outcome,
+            PermissionOutcome::Selected {
+                option_id: "reject".to_string()
+            }
+        );
+    }
+
     #[test]
     fn test_session_cancel_params_serialization() {
         let params = SessionCancelParams {
             session_id: "session_123".to_string(),
+            prompt_id: None,
         };
         let json = serde_json::to_string(&params).unwrap();
+        assert!(!json.contains("prompt_id"));
         let deserialized: SessionCancelParams = serde_json::from_str(&json).unwrap();
         assert_eq!(deserialized.session_id, "session_123");
+        assert_eq!(deserialized.prompt_id, None);
+    }
+
+    #[test]
+    fn test_agent_status_result_serialization() {
+        let result: AgentStatusResult = serde_json::from_str(
+            r#"{"uptime_ms": 1500, "active_sessions": 2, "running_prompts": 1, "queued_prompts": 0}"#,
+        )
+        .unwrap();
+        assert_eq!(result.uptime_ms, 1500);
+        assert_eq!(result.resources, ResourceUsage::default());
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["resources"], serde_json::json!({}));
+    }
+
+    #[test]
+    fn test_session_set_model_params_serialization() {
+        let params: SessionSetModelParams =
+            serde_json::from_str(r#"{"session_id": "session_123", "model_id": "qwen2.5-coder:7b"}"#).unwrap();
+        assert_eq!(params.model_id, "qwen2.5-coder:7b");
+        let json = serde_json::to_value(&params).unwrap();
+        assert_eq!(json["session_id"], "session_123");
+    }
+
+    #[test]
+    fn test_session_stats_serialization() {
+        let stats = SessionStats {
+            prompts: 2,
+            tool_calls: BTreeMap::from([("read_file".to_string(), 3)]),
+            ..SessionStats::default()
+        };
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json["tool_calls"]["read_file"], 3);
+        assert_eq!(json["prompt_time_ms"], 0);
+        let parsed: SessionStats = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, stats);
     }
 
     #[test]
     fn test_fs_read_text_file_params_serialization() {
         let params = FsReadTextFileParams {
             path: "/home/user/test.txt".to_string(),
+            line: None,
+            limit: None,
         };
         let json = serde_json::to_string(&params).unwrap();
+        assert_eq!(json, r#"{"path":"/home/user/test.txt"}"#);
         let deserialized: FsReadTextFileParams = serde_json::from_str(&json).unwrap();
         assert_eq!(deserialized.path, "/home/user/test.txt");
+
+        let ranged: FsReadTextFileParams =
+            serde_json::from_str(r#"{"path":"/a.rs","line":10,"limit":5}"#).unwrap();
+        assert_eq!((ranged.line, ranged.limit), (Some(10), Some(5)));
     }
 
     #[test]
@@ -589,11 +1580,90 @@ mod tests {
         let params = FsWriteTextFileParams {
             path: "/home/user/output.txt".to_string(),
             content: "new content".to_string(),
+            ..Default::default()
         };
         let json = serde_json::to_string(&params).unwrap();
+        assert!(!json.contains("atomic"));
         let deserialized: FsWriteTextFileParams = serde_json::from_str(&json).unwrap();
         assert_eq!(deserialized.path, "/home/user/output.txt");
         assert_eq!(deserialized.content, "new content");
+        assert_eq!(deserialized.create_dirs, None);
+
+        let params = FsWriteTextFileParams {
+            create_dirs: Some(true),
+            atomic: Some(false),
+            ..params
+        };
+        let json = serde_json::to_value(&params).unwrap();
+        assert_eq!(json["create_dirs"], true);
+        assert_eq!(json["atomic"], false);
+    }
+
+    #[test]
+    fn test_edit_conflict_error() {
+        assert_eq!(
+            content_hash("hello\n"),
+            "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03"
+        );
+        let conflict = EditConflict {
+            current_hash: content_hash(""),
+            hunk: Some(2),
+        };
+        let error = conflict.clone().into_error("hunk 2 does not match");
+        assert_eq!(error.code(), codes::EDIT_CONFLICT);
+
+        // The conflict survives the trip through an error response
+        let wire = JsonRpcError {
+            code: error.code(),
+            message: error.message(),
+            data: error.data().cloned(),
+        };
+        let received = AcpError::from(wire);
+        assert_eq!(EditConflict::from_error(&received), Some(conflict));
+        assert_eq!(EditConflict::from_error(&AcpError::Timeout), None);
+    }
+
+    #[test]
+    fn test_binary_file_data_round_trip() {
+        let bytes = [0x89, b'P', b'N', b'G', 0, 0xff];
+        let params = FsWriteFileParams::new("/project/logo.png", &bytes);
+        assert_eq!(params.data, "iVBORwD/");
+        assert_eq!(params.bytes().unwrap(), bytes);
+
+        let result = FsReadFileResult {
+            data: "not base64!".to_string(),
+            mime_type: "image/png".to_string(),
+        };
+        assert_eq!(result.bytes().unwrap_err().code(), codes::INVALID_ENCODING);
+    }
+
+    #[test]
+    fn test_fs_list_directory_serialization() {
+        let params: FsListDirectoryParams =
+            serde_json::from_value(serde_json::json!({"path": "/project"})).unwrap();
+        assert!(!params.recursive);
+        assert_eq!(params.max_entries, None);
+
+        let result = FsListDirectoryResult {
+            entries: vec![
+                DirectoryEntry {
+                    name: "src".to_string(),
+                    kind: DirectoryEntryKind::Directory,
+                    size: None,
+                },
+                DirectoryEntry {
+                    name: "src/main.rs".to_string(),
+                    kind: DirectoryEntryKind::File,
+                    size: Some(120),
+                },
+            ],
+            truncated: false,
+        };
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["entries"][0], serde_json::json!({"name": "src", "kind": "directory"}));
+        assert_eq!(json["entries"][1]["size"], 120);
+        let deserialized: FsListDirectoryResult = serde_json::from_value(json).unwrap();
+        assert_eq!(deserialized.entries, result.entries);
     }
 
     #[test]
@@ -609,11 +1679,27 @@ mod tests {
         let params = TerminalCreateParams {
             cwd: "/home/user".to_string(),
             command: "ls -la".to_string(),
+            output_byte_limit: Some(4096),
+            ..Default::default()
         };
         let json = serde_json::to_string(&params).unwrap();
         let deserialized: TerminalCreateParams = serde_json::from_str(&json).unwrap();
         assert_eq!(deserialized.cwd, "/home/user");
         assert_eq!(deserialized.command, "ls -la");
+        assert_eq!(deserialized.output_byte_limit, Some(4096));
+        assert!(!json.contains("args") && !json.contains("env"));
+
+        let direct: TerminalCreateParams = serde_json::from_value(serde_json::json!({
+            "cwd": "/",
+            "command": "grep",
+            "args": ["-rn", "it's"],
+            "env": {"LC_ALL": "C"},
+            "no_shell": true
+        }))
+        .unwrap();
+        assert_eq!(direct.args, vec!["-rn", "it's"]);
+        assert_eq!(direct.env["LC_ALL"], "C");
+        assert!(direct.no_shell);
     }
 
     #[test]
@@ -626,36 +1712,76 @@ mod tests {
         assert_eq!(deserialized.terminal_id, "term_1");
     }
 
+    #[test]
+    fn test_prompt_stream_chunk_params() {
+        let params: PromptStreamChunkParams = serde_json::from_value(serde_json::json!({
+            "stream_id": "stream_1",
+            "content": [{"type": "text", "text": "more"}]
+        }))
+        .unwrap();
+        assert_eq!(params.stream_id, "stream_1");
+        assert_eq!(params.content.len(), 1);
+        assert!(!params.continuation);
+    }
+
     #[test]
     fn test_terminal_output_result_serialization() {
         let result = TerminalOutputResult {
             output: "command output".to_string(),
+            stderr: String::new(),
             exited: true,
             exit_code: Some(0),
+            truncated: true,
+            total_bytes: 1 << 20,
         };
         let json = serde_json::to_string(&result).unwrap();
         let deserialized: TerminalOutputResult = serde_json::from_str(&json).unwrap();
         assert_eq!(deserialized.output, "command output");
         assert!(deserialized.exited);
         assert_eq!(deserialized.exit_code, Some(0));
+        assert!(deserialized.truncated);
+        assert_eq!(deserialized.total_bytes, 1 << 20);
+        assert!(!json.contains("stderr"));
+
+        // Older clients don't report truncation
+        let old: TerminalOutputResult =
+            serde_json::from_str(r#"{"output":"x","exited":false}"#).unwrap();
+        assert!(!old.truncated);
     }
 
     #[test]
     fn test_terminal_output_result_not_exited() {
         let result = TerminalOutputResult {
             output: "partial output".to_string(),
+            stderr: String::new(),
             exited: false,
             exit_code: None,
+            truncated: false,
+            total_bytes: 14,
         };
         let json = serde_json::to_string(&result).unwrap();
         assert!(!json.contains("exit_code"));
     }
 
+    #[test]
+    fn test_terminal_input_params() {
+        let params: TerminalInputParams = serde_json::from_value(serde_json::json!({
+            "terminal_id": "term_1",
+            "data": "y\n"
+        }))
+        .unwrap();
+        assert_eq!(params.data, "y\n");
+        assert!(!params.eof);
+    }
+
     #[test]
     fn test_terminal_wait_for_exit_result_serialization() {
         let result = TerminalWaitForExitResult {
             exit_code: 0,
             output: "final output".to_string(),
+            stderr: String::new(),
+            truncated: false,
+            total_bytes: 12,
         };
         let json = serde_json::to_string(&result).unwrap();
         let deserialized: TerminalWaitForExitResult = serde_json::from_str(&json).unwrap();
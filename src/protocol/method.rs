@@ -0,0 +1,299 @@
+//! ACP method names.
+//!
+//! Use these constants (or the [`Method`] enum) instead of string literals
+//! when sending or dispatching messages.
+
+use std::fmt;
+use std::str::FromStr;
+
+use super::errors::AcpError;
+
+/// Liveness check (either direction).
+pub const PING: &str = "ping";
+/// Capability negotiation (client -> agent).
+pub const INITIALIZE: &str = "initialize";
+/// Optional authentication (client -> agent).
+pub const AUTHENTICATE: &str = "authenticate";
+/// Create a new session (client -> agent).
+pub const SESSION_NEW: &str = "session/new";
+/// Load an existing session (client -> agent).
+pub const SESSION_LOAD: &str = "session/load";
+/// Ask the agent to wind down before disconnecting (client -> agent).
+pub const SHUTDOWN: &str = "shutdown";
+/// Report the agent's uptime, load and resource use (client -> agent).
+pub const AGENT_STATUS: &str = "agent/status";
+/// Send a prompt (client -> agent).
+pub const SESSION_PROMPT: &str = "session/prompt";
+/// Begin a prompt sent in several chunks (client -> agent).
+pub const SESSION_PROMPT_STREAM_START: &str = "session/prompt_stream_start";
+/// Send part of a streamed prompt (client -> agent).
+pub const SESSION_PROMPT_STREAM_CHUNK: &str = "session/prompt_stream_chunk";
+/// Finish a streamed prompt and run it (client -> agent).
+pub const SESSION_PROMPT_STREAM_END: &str = "session/prompt_stream_end";
+/// Cancel the current operation (client -> agent).
+pub const SESSION_CANCEL: &str = "session/cancel";
+/// Switch the model a session uses (client -> agent).
+pub const SESSION_SET_MODEL: &str = "session/set_model";
+/// Ask the usage of a session (client -> agent).
+pub const SESSION_STATS: &str = "session/stats";
+/// Ask the user to approve a tool call (agent -> client).
+pub const SESSION_REQUEST_PERMISSION: &str = "session/request_permission";
+/// Streaming session update notification (agent -> client).
+pub const SESSION_UPDATE: &str = "session/update";
+/// Ask the client's model for a completion for an MCP server (agent -> client).
+pub const SESSION_SAMPLING_REQUEST: &str = "session/sampling_request";
+/// Availability change of a configured MCP server (agent -> client).
+pub const MCP_SERVER_STATUS: &str = "mcp/server_status";
+/// Read a text file (agent -> client).
+pub const FS_READ_TEXT_FILE: &str = "fs/read_text_file";
+/// Write a text file (agent -> client).
+pub const FS_WRITE_TEXT_FILE: &str = "fs/write_text_file";
+/// Start reading a file in chunks (agent -> client).
+pub const FS_READ_STREAM_START: &str = "fs/read_stream_start";
+/// Read the next chunk of a file read stream (agent -> client).
+pub const FS_READ_CHUNK: &str = "fs/read_chunk";
+/// Stop reading a file read stream early (agent -> client).
+pub const FS_READ_STREAM_CLOSE: &str = "fs/read_stream_close";
+/// Edit a text file with a unified diff (agent -> client).
+pub const FS_APPLY_EDIT: &str = "fs/apply_edit";
+/// Read a file of any kind as base64 (agent -> client).
+pub const FS_READ_FILE: &str = "fs/read_file";
+/// Write a file of any kind from base64 (agent -> client).
+pub const FS_WRITE_FILE: &str = "fs/write_file";
+/// List the entries of a directory (agent -> client).
+pub const FS_LIST_DIRECTORY: &str = "fs/list_directory";
+/// Find files matching a glob pattern (agent -> client).
+pub const FS_GLOB: &str = "fs/glob";
+/// Search the contents of files (agent -> client).
+pub const FS_GREP: &str = "fs/grep";
+/// Delete a file or directory (agent -> client).
+pub const FS_DELETE: &str = "fs/delete";
+/// Move a file or directory (agent -> client).
+pub const FS_RENAME: &str = "fs/rename";
+/// Create a directory (agent -> client).
+pub const FS_CREATE_DIRECTORY: &str = "fs/create_directory";
+/// Create a terminal (agent -> client).
+pub const TERMINAL_CREATE: &str = "terminal/create";
+/// Get terminal output (agent -> client).
+pub const TERMINAL_OUTPUT: &str = "terminal/output";
+/// Write to a terminal's stdin (agent -> client).
+pub const TERMINAL_INPUT: &str = "terminal/input";
+/// Wait for a terminal to exit (agent -> client).
+pub const TERMINAL_WAIT_FOR_EXIT: &str = "terminal/wait_for_exit";
+/// Kill a terminal (agent -> client).
+pub const TERMINAL_KILL: &str = "terminal/kill";
+/// Release a terminal (agent -> client).
+pub const TERMINAL_RELEASE: &str = "terminal/release";
+
+/// A method defined by the protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Method {
+    /// `ping`
+    Ping,
+    /// `initialize`
+    Initialize,
+    /// `authenticate`
+    Authenticate,
+    /// `session/new`
+    SessionNew,
+    /// `session/load`
+    SessionLoad,
+    /// `shutdown`
+    Shutdown,
+    /// `agent/status`
+    AgentStatus,
+    /// `session/prompt`
+    SessionPrompt,
+    /// `session/prompt_stream_start`
+    SessionPromptStreamStart,
+    /// `session/prompt_stream_chunk`
+    SessionPromptStreamChunk,
+    /// `session/prompt_stream_end`
+    SessionPromptStreamEnd,
+    /// `session/cancel`
+    SessionCancel,
+    /// `session/set_model`
+    SessionSetModel,
+    /// `session/stats`
+    SessionStats,
+    /// `session/request_permission`
+    SessionRequestPermission,
+    /// `session/update`
+    SessionUpdate,
+    /// `session/sampling_request`
+    SessionSamplingRequest,
+    /// `mcp/server_status`
+    McpServerStatus,
+    /// `fs/read_text_file`
+    FsReadTextFile,
+    /// `fs/write_text_file`
+    FsWriteTextFile,
+    /// `fs/apply_edit`
+    FsApplyEdit,
+    /// `fs/read_stream_start`
+    FsReadStreamStart,
+    /// `fs/read_chunk`
+    FsReadChunk,
+    /// `fs/read_stream_close`
+    FsReadStreamClose,
+    /// `fs/read_file`
+    FsReadFile,
+    /// `fs/write_file`
+    FsWriteFile,
+    /// `fs/list_directory`
+    FsListDirectory,
+    /// `fs/glob`
+    FsGlob,
+    /// `fs/grep`
+    FsGrep,
+    /// `fs/delete`
+    FsDelete,
+    /// `fs/rename`
+    FsRename,
+    /// `fs/create_directory`
+    FsCreateDirectory,
+    /// `terminal/create`
+    TerminalCreate,
+    /// `terminal/output`
+    TerminalOutput,
+    /// `terminal/input`
+    TerminalInput,
+    /// `terminal/wait_for_exit`
+    TerminalWaitForExit,
+    /// `terminal/kill`
+    TerminalKill,
+    /// `terminal/release`
+    TerminalRelease,
+}
+
+impl Method {
+    /// All methods defined by the protocol.
+    pub const ALL: &'static [Method] = &[
+        Method::Ping,
+        Method::Initialize,
+        Method::Authenticate,
+        Method::SessionNew,
+        Method::SessionLoad,
+        Method::Shutdown,
+        Method::AgentStatus,
+        Method::SessionPrompt,
+        Method::SessionPromptStreamStart,
+        Method::SessionPromptStreamChunk,
+        Method::SessionPromptStreamEnd,
+        Method::SessionCancel,
+        Method::SessionSetModel,
+        Method::SessionStats,
+        Method::SessionRequestPermission,
+        Method::SessionUpdate,
+        Method::SessionSamplingRequest,
+        Method::McpServerStatus,
+        Method::FsReadTextFile,
+        Method::FsWriteTextFile,
+        Method::FsApplyEdit,
+        Method::FsReadStreamStart,
+        Method::FsReadChunk,
+        Method::FsReadStreamClose,
+        Method::FsReadFile,
+        Method::FsWriteFile,
+        Method::FsListDirectory,
+        Method::FsGlob,
+        Method::FsGrep,
+        Method::FsDelete,
+        Method::FsRename,
+        Method::FsCreateDirectory,
+        Method::TerminalCreate,
+        Method::TerminalOutput,
+        Method::TerminalInput,
+        Method::TerminalWaitForExit,
+        Method::TerminalKill,
+        Method::TerminalRelease,
+    ];
+
+    /// Get the wire name of this method.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Ping => PING,
+            Method::Initialize => INITIALIZE,
+            Method::Authenticate => AUTHENTICATE,
+            Method::SessionNew => SESSION_NEW,
+            Method::SessionLoad => SESSION_LOAD,
+            Method::Shutdown => SHUTDOWN,
+            Method::AgentStatus => AGENT_STATUS,
+            Method::SessionPrompt => SESSION_PROMPT,
+            Method::SessionPromptStreamStart => SESSION_PROMPT_STREAM_START,
+            Method::SessionPromptStreamChunk => SESSION_PROMPT_STREAM_CHUNK,
+            Method::SessionPromptStreamEnd => SESSION_PROMPT_STREAM_END,
+            Method::SessionCancel => SESSION_CANCEL,
+            Method::SessionSetModel => SESSION_SET_MODEL,
+            Method::SessionStats => SESSION_STATS,
+            Method::SessionRequestPermission => SESSION_REQUEST_PERMISSION,
+            Method::SessionUpdate => SESSION_UPDATE,
+            Method::SessionSamplingRequest => SESSION_SAMPLING_REQUEST,
+            Method::McpServerStatus => MCP_SERVER_STATUS,
+            Method::FsReadTextFile => FS_READ_TEXT_FILE,
+            Method::FsWriteTextFile => FS_WRITE_TEXT_FILE,
+            Method::FsApplyEdit => FS_APPLY_EDIT,
+            Method::FsReadStreamStart => FS_READ_STREAM_START,
+            Method::FsReadChunk => FS_READ_CHUNK,
+            Method::FsReadStreamClose => FS_READ_STREAM_CLOSE,
+            Method::FsReadFile => FS_READ_FILE,
+            Method::FsWriteFile => FS_WRITE_FILE,
+            Method::FsListDirectory => FS_LIST_DIRECTORY,
+            Method::FsGlob => FS_GLOB,
+            Method::FsGrep => FS_GREP,
+            Method::FsDelete => FS_DELETE,
+            Method::FsRename => FS_RENAME,
+            Method::FsCreateDirectory => FS_CREATE_DIRECTORY,
+            Method::TerminalCreate => TERMINAL_CREATE,
+            Method::TerminalOutput => TERMINAL_OUTPUT,
+            Method::TerminalInput => TERMINAL_INPUT,
+            Method::TerminalWaitForExit => TERMINAL_WAIT_FOR_EXIT,
+            Method::TerminalKill => TERMINAL_KILL,
+            Method::TerminalRelease => TERMINAL_RELEASE,
+        }
+    }
+}
+
+impl FromStr for Method {
+    type Err = AcpError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Method::ALL
+            .iter()
+            .copied()
+            .find(|m| m.as_str() == s)
+            .ok_or_else(|| AcpError::MethodNotFound(s.to_string()))
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_method_round_trip() {
+        for method in Method::ALL {
+            let parsed: Method = method.as_str().parse().unwrap();
+            assert_eq!(parsed, *method);
+        }
+    }
+
+    #[test]
+    fn test_method_names() {
+        assert_eq!(Method::SessionPrompt.as_str(), "session/prompt");
+        assert_eq!(Method::TerminalWaitForExit.as_str(), "terminal/wait_for_exit");
+        assert_eq!(Method::FsReadTextFile.to_string(), "fs/read_text_file");
+    }
+
+    #[test]
+    fn test_unknown_method() {
+        let err = "unknown/method".parse::<Method>().unwrap_err();
+        assert!(matches!(err, AcpError::MethodNotFound(m) if m == "unknown/method"));
+    }
+}
@@ -6,7 +6,17 @@
 mod messages;
 mod types;
 mod errors;
+pub mod method;
+mod trace;
+#[cfg(feature = "schema")]
+mod schema;
+#[cfg(feature = "arbitrary")]
+mod fuzz;
 
 pub use messages::*;
 pub use types::*;
 pub use errors::*;
+pub use method::Method;
+pub use trace::TraceContext;
+#[cfg(feature = "schema")]
+pub use schema::protocol_schema;
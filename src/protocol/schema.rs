@@ -0,0 +1,113 @@
+//! JSON Schemas of the protocol types.
+//!
+//! Enabled with the `schema` feature, which derives
+//! [`JsonSchema`](schemars::JsonSchema) for every message and type in
+//! [`protocol`](super). [`protocol_schema`] bundles them into one document,
+//! which the `acp-schema` binary prints, so implementations in other
+//! languages can generate their types from it and validate messages against
+//! it.
+
+use schemars::generate::SchemaSettings;
+use serde_json::{json, Value};
+
+use super::*;
+
+/// Every request, response and notification type, and the types they
+/// use, as the `$defs` of one JSON Schema (draft 2020-12).
+///
+/// Definitions are named after the Rust types, e.g. `InitializeParams` or
+/// `SessionUpdate`, and refer to each other with `#/$defs/...`.
+pub fn protocol_schema() -> Value {
+    let mut generator = SchemaSettings::draft2020_12().into_generator();
+    macro_rules! define {
+        ($($ty:ty),* $(,)?) => {
+            $(generator.subschema_for::<$ty>();)*
+        };
+    }
+    define!(
+        JsonRpcRequest,
+        JsonRpcResponse,
+        JsonRpcNotification,
+        InitializeParams,
+        InitializeResult,
+        AuthenticateParams,
+        AuthenticateResult,
+        AgentStatusResult,
+        SessionNewParams,
+        SessionNewResult,
+        SessionLoadParams,
+        SessionLoadResult,
+        SessionPromptParams,
+        SessionPromptResult,
+        PromptStreamStartParams,
+        PromptStreamStartResult,
+        PromptStreamChunkParams,
+        PromptStreamEndParams,
+        SessionCancelParams,
+        SessionSetModelParams,
+        SessionStatsParams,
+        SessionStats,
+        SessionUpdate,
+        McpServerStatus,
+        RequestPermissionParams,
+        RequestPermissionResult,
+        SessionSamplingRequestParams,
+        SessionSamplingRequestResult,
+        FsReadTextFileParams,
+        FsReadTextFileResult,
+        FsWriteTextFileParams,
+        FsWriteTextFileResult,
+        FsApplyEditParams,
+        FsApplyEditResult,
+        FsReadFileParams,
+        FsReadFileResult,
+        FsWriteFileParams,
+        FsReadStreamStartParams,
+        FsReadStreamStartResult,
+        FsReadStreamParams,
+        FsReadChunkResult,
+        FsListDirectoryParams,
+        FsListDirectoryResult,
+        FsGlobParams,
+        FsGlobResult,
+        FsGrepParams,
+        FsGrepResult,
+        FsDeleteParams,
+        FsRenameParams,
+        FsCreateDirectoryParams,
+        TerminalCreateParams,
+        TerminalCreateResult,
+        TerminalOutputParams,
+        TerminalOutputResult,
+        TerminalInputParams,
+        TerminalInputResult,
+        TerminalWaitForExitParams,
+        TerminalWaitForExitResult,
+        TerminalKillParams,
+        TerminalKillResult,
+        TerminalReleaseParams,
+        TerminalReleaseResult,
+    );
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": format!("heroacp protocol {}", PROTOCOL_VERSION),
+        "$defs": generator.take_definitions(true),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protocol_schema() {
+        let schema = protocol_schema();
+        let defs = schema["$defs"].as_object().unwrap();
+        for name in ["InitializeParams", "SessionPromptResult", "SessionUpdate", "ContentBlock", "TerminalReleaseResult"] {
+            assert!(defs.contains_key(name), "missing {}", name);
+        }
+        assert_eq!(defs["InitializeParams"]["properties"]["client_info"]["$ref"], "#/$defs/ClientInfo");
+        // The flattened update type makes one branch per kind of update
+        assert!(defs["SessionUpdate"]["oneOf"].is_array());
+    }
+}
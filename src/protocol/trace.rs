@@ -0,0 +1,168 @@
+//! W3C trace context carried in a message's `_meta`.
+//!
+//! A request or notification may name the trace it belongs to under
+//! `params._meta.traceparent`, in the format of the `traceparent` HTTP
+//! header:
+//!
+//! ```json
+//! {"jsonrpc":"2.0","id":3,"method":"session/prompt","params":{"session_id":"s1","content":[],"_meta":{"traceparent":"00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"}}}
+//! ```
+//!
+//! The server records it on the span of the request; with the `otel`
+//! feature, the span joins the trace and the client fills the field in from
+//! the current span.
+
+use serde_json::Value;
+use std::fmt;
+
+/// Key of the metadata object in params.
+pub const META: &str = "_meta";
+
+/// Key of the trace context in [`META`].
+pub const TRACEPARENT: &str = "traceparent";
+
+/// Position of a message in a distributed trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceContext {
+    /// ID of the whole trace; never zero.
+    pub trace_id: u128,
+    /// ID of the caller's span; never zero.
+    pub parent_id: u64,
+    /// Whether the caller records the trace.
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    /// Parse a `traceparent` value, e.g.
+    /// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+    ///
+    /// Versions after `00` are read as far as `00` goes, as the W3C
+    /// specification asks.
+    pub fn parse(traceparent: &str) -> Option<Self> {
+        let mut parts = traceparent.trim().split('-');
+        let version = parts.next().filter(|v| v.len() == 2 && *v != "ff")?;
+        let trace_id = parts.next().filter(|id| id.len() == 32)?;
+        let parent_id = parts.next().filter(|id| id.len() == 16)?;
+        let flags = parts.next().filter(|flags| flags.len() == 2)?;
+        if version == "00" && parts.next().is_some() {
+            return None;
+        }
+        let hex = |s: &str| s.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b));
+        if ![version, trace_id, parent_id, flags].into_iter().all(hex) {
+            return None;
+        }
+        let context = Self {
+            trace_id: u128::from_str_radix(trace_id, 16).ok()?,
+            parent_id: u64::from_str_radix(parent_id, 16).ok()?,
+            sampled: u8::from_str_radix(flags, 16).ok()? & 1 == 1,
+        };
+        (context.trace_id != 0 && context.parent_id != 0).then_some(context)
+    }
+
+    /// The context in `params._meta.traceparent`, if there is a valid one.
+    pub fn from_params(params: &Value) -> Option<Self> {
+        Self::parse(params.get(META)?.get(TRACEPARENT)?.as_str()?)
+    }
+
+    /// Write the context to `params._meta.traceparent`, keeping the rest of
+    /// `_meta`. Params other than an object are left alone.
+    pub fn inject(&self, params: &mut Value) {
+        let Value::Object(params) = params else {
+            return;
+        };
+        let meta = params
+            .entry(META)
+            .or_insert_with(|| Value::Object(Default::default()));
+        if let Value::Object(meta) = meta {
+            meta.insert(TRACEPARENT.to_string(), Value::String(self.to_string()));
+        }
+    }
+}
+
+impl fmt::Display for TraceContext {
+    /// The `traceparent` value.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "00-{:032x}-{:016x}-{:02x}", self.trace_id, self.parent_id, self.sampled as u8)
+    }
+}
+
+#[cfg(feature = "otel")]
+mod otel {
+    use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    use super::TraceContext;
+
+    impl TraceContext {
+        /// The context of the current `tracing` span, if it belongs to an
+        /// OpenTelemetry trace.
+        pub fn current() -> Option<Self> {
+            let context = tracing::Span::current().context();
+            let span = context.span();
+            let span_context = span.span_context();
+            span_context.is_valid().then(|| Self {
+                trace_id: u128::from_be_bytes(span_context.trace_id().to_bytes()),
+                parent_id: u64::from_be_bytes(span_context.span_id().to_bytes()),
+                sampled: span_context.is_sampled(),
+            })
+        }
+
+        /// Make `span` a child of the remote span this context names.
+        pub fn attach(&self, span: &tracing::Span) {
+            let flags = if self.sampled { TraceFlags::SAMPLED } else { TraceFlags::default() };
+            let remote = SpanContext::new(
+                TraceId::from_bytes(self.trace_id.to_be_bytes()),
+                SpanId::from_bytes(self.parent_id.to_be_bytes()),
+                flags,
+                true,
+                TraceState::default(),
+            );
+            // Fails only once the span has started, which ours hasn't
+            let _ = span.set_parent(opentelemetry::Context::new().with_remote_span_context(remote));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACEPARENT_00: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn test_parse_and_format() {
+        let context = TraceContext::parse(TRACEPARENT_00).unwrap();
+        assert_eq!(context.trace_id, 0x4bf92f3577b34da6a3ce929d0e0e4736);
+        assert_eq!(context.parent_id, 0x00f067aa0ba902b7);
+        assert!(context.sampled);
+        assert_eq!(context.to_string(), TRACEPARENT_00);
+
+        // Later versions may append fields
+        assert!(TraceContext::parse(&format!("01{}-extra", &TRACEPARENT_00[2..])).is_some());
+        for bad in [
+            "",
+            &format!("{}-extra", TRACEPARENT_00),
+            &format!("ff{}", &TRACEPARENT_00[2..]),
+            &TRACEPARENT_00.to_uppercase(),
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+        ] {
+            assert_eq!(TraceContext::parse(bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_inject_and_extract() {
+        let context = TraceContext::parse(TRACEPARENT_00).unwrap();
+        let mut params = serde_json::json!({"session_id": "s1", "_meta": {"vendor": 1}});
+        context.inject(&mut params);
+        assert_eq!(params["_meta"]["vendor"], 1);
+        assert_eq!(params["_meta"]["traceparent"], TRACEPARENT_00);
+        assert_eq!(TraceContext::from_params(&params), Some(context));
+
+        let mut params = serde_json::json!([1, 2]);
+        context.inject(&mut params);
+        assert_eq!(TraceContext::from_params(&params), None);
+    }
+}
@@ -2,12 +2,22 @@
 
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
+
+use super::errors::{AcpError, AcpResult};
 
 /// Protocol version string.
 pub const PROTOCOL_VERSION: &str = "2025.1";
 
+/// Maximum length of base64-encoded audio data in a single block or chunk.
+///
+/// Larger clips should be sent as a sequence of [`AudioChunk`] updates.
+pub const MAX_AUDIO_DATA_LEN: usize = 8 * 1024 * 1024;
+
 /// Information about a client (editor/IDE).
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct ClientInfo {
     /// Name of the client.
     pub name: String,
@@ -17,6 +27,8 @@ pub struct ClientInfo {
 
 /// Information about an agent.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct AgentInfo {
     /// Name of the agent.
     pub name: String,
@@ -26,6 +38,8 @@ pub struct AgentInfo {
 
 /// Capabilities that a client can provide.
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct ClientCapabilities {
     /// Can read/write text files.
     #[serde(default)]
@@ -33,6 +47,30 @@ pub struct ClientCapabilities {
     /// Can create and manage terminals.
     #[serde(default)]
     pub terminal: bool,
+    /// Can edit text files with unified diffs via `fs/apply_edit`.
+    #[serde(default)]
+    pub apply_edit: bool,
+    /// Can read and write files of any kind with `fs/read_file` and
+    /// `fs/write_file`.
+    #[serde(default)]
+    pub binary_files: bool,
+    /// Can serve large files in chunks with `fs/read_stream_start`,
+    /// `fs/read_chunk` and `fs/read_stream_close`.
+    #[serde(default)]
+    pub read_stream: bool,
+    /// Can list directories with `fs/list_directory`.
+    #[serde(default)]
+    pub list_directory: bool,
+    /// Can find files by pattern with `fs/glob`.
+    #[serde(default)]
+    pub glob: bool,
+    /// Can search file contents with `fs/grep`.
+    #[serde(default)]
+    pub grep: bool,
+    /// Can delete, move and create files and directories with `fs/delete`,
+    /// `fs/rename` and `fs/create_directory`.
+    #[serde(default)]
+    pub manage_files: bool,
     /// Supports embedded context in prompts.
     #[serde(default)]
     pub embedded_context: bool,
@@ -42,13 +80,20 @@ pub struct ClientCapabilities {
     /// Supports image content.
     #[serde(default)]
     pub image: bool,
+    /// Can answer sampling requests from MCP servers with the editor's model
+    /// via `session/sampling_request`.
+    #[serde(default)]
+    pub sampling: bool,
     /// Experimental capabilities.
     #[serde(default)]
+    #[cfg_attr(feature = "arbitrary", arbitrary(with = super::fuzz::json_map))]
     pub experimental: HashMap<String, serde_json::Value>,
 }
 
 /// Capabilities that an agent can provide.
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct AgentCapabilities {
     /// Supports streaming responses.
     #[serde(default)]
@@ -65,10 +110,64 @@ pub struct AgentCapabilities {
     /// Available tools.
     #[serde(default)]
     pub tools: Vec<ToolInfo>,
+    /// Vendor-specific methods the agent handles, e.g. `"myvendor/do_thing"`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub custom_methods: Vec<String>,
+    /// Models sessions can be switched to with `session/set_model`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub models: Vec<ModelInfo>,
+}
+
+impl AgentCapabilities {
+    /// Combine with another agent's capabilities.
+    ///
+    /// Feature flags are OR-ed; modes, tools, custom methods and models are
+    /// added unless already present.
+    pub fn merge(&mut self, other: AgentCapabilities) {
+        self.streaming |= other.streaming;
+        self.audio |= other.audio;
+        self.image |= other.image;
+        for mode in other.supported_modes {
+            if !self.supported_modes.contains(&mode) {
+                self.supported_modes.push(mode);
+            }
+        }
+        for tool in other.tools {
+            if !self.tools.iter().any(|t| t.name == tool.name) {
+                self.tools.push(tool);
+            }
+        }
+        for method in other.custom_methods {
+            if !self.custom_methods.contains(&method) {
+                self.custom_methods.push(method);
+            }
+        }
+        for model in other.models {
+            if !self.models.iter().any(|m| m.id == model.id) {
+                self.models.push(model);
+            }
+        }
+    }
+}
+
+/// A model the agent can run a session on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct ModelInfo {
+    /// ID to pass to `session/set_model`.
+    pub id: String,
+    /// Name to show the user.
+    pub name: String,
+    /// What the model is good at, or its size.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
 }
 
 /// Information about a tool available to the agent.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct ToolInfo {
     /// Name of the tool.
     pub name: String,
@@ -76,11 +175,16 @@ pub struct ToolInfo {
     pub description: String,
     /// JSON schema for tool parameters.
     #[serde(default)]
+    #[cfg_attr(feature = "arbitrary", arbitrary(with = super::fuzz::json))]
     pub parameters: serde_json::Value,
 }
 
 /// MCP server configuration.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+///
+/// The `Debug` output hides credential values, so servers can be logged.
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct McpServer {
     /// Name of the MCP server.
     pub name: String,
@@ -91,14 +195,95 @@ pub struct McpServer {
     pub credentials: HashMap<String, String>,
 }
 
+impl fmt::Debug for McpServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let credentials: HashMap<&str, &str> = self.credentials.keys().map(|k| (k.as_str(), "[REDACTED]")).collect();
+        f.debug_struct("McpServer")
+            .field("name", &self.name)
+            .field("url", &self.url)
+            .field("credentials", &credentials)
+            .finish()
+    }
+}
+
+/// Availability of a configured MCP server, sent in `mcp/server_status`
+/// notifications (agent -> client).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct McpServerStatus {
+    /// Name of the MCP server.
+    pub name: String,
+    /// New state of the server.
+    pub state: McpServerState,
+    /// Why the server became unavailable.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// State of a configured MCP server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[serde(rename_all = "snake_case")]
+pub enum McpServerState {
+    /// The server is connected again after being unavailable.
+    Connected,
+    /// The server crashed or stopped answering; the agent is restarting it.
+    Unavailable,
+    /// The agent gave up restarting the server.
+    Failed,
+}
+
+/// Intended audience of a piece of content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// The human user.
+    User,
+    /// The model/agent.
+    Assistant,
+}
+
+/// Optional metadata attached to a content block, mirroring MCP annotations.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Annotations {
+    /// Who the content is intended for. Empty means everyone.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub audience: Vec<Role>,
+    /// Importance from 0.0 (least) to 1.0 (most important).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "arbitrary", arbitrary(with = super::fuzz::optional_f64))]
+    pub priority: Option<f64>,
+    /// ISO 8601 timestamp of the last modification of the underlying data.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+impl Annotations {
+    /// Whether content with these annotations is intended for `role`.
+    pub fn includes(&self, role: Role) -> bool {
+        self.audience.is_empty() || self.audience.contains(&role)
+    }
+}
+
 /// Content block in a message.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ContentBlock {
     /// Text content.
     Text {
         /// The text content.
         text: String,
+        /// Optional annotations.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        annotations: Option<Annotations>,
     },
     /// Image content.
     Image {
@@ -106,6 +291,9 @@ pub enum ContentBlock {
         format: String,
         /// Base64-encoded image data.
         data: String,
+        /// Optional annotations.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        annotations: Option<Annotations>,
     },
     /// Audio content.
     Audio {
@@ -113,6 +301,9 @@ pub enum ContentBlock {
         format: String,
         /// Base64-encoded audio data.
         data: String,
+        /// Optional annotations.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        annotations: Option<Annotations>,
     },
     /// Resource content.
     Resource {
@@ -122,6 +313,9 @@ pub enum ContentBlock {
         mime_type: String,
         /// Content of the resource.
         content: String,
+        /// Optional annotations.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        annotations: Option<Annotations>,
     },
     /// Resource link (reference without content).
     ResourceLink {
@@ -129,22 +323,122 @@ pub enum ContentBlock {
         uri: String,
         /// MIME type.
         mime_type: String,
+        /// Optional annotations.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        annotations: Option<Annotations>,
     },
 }
 
+impl ContentBlock {
+    /// Create an unannotated text block.
+    pub fn text(text: impl Into<String>) -> Self {
+        ContentBlock::Text {
+            text: text.into(),
+            annotations: None,
+        }
+    }
+
+    /// Get the annotations of this block, if any.
+    pub fn annotations(&self) -> Option<&Annotations> {
+        match self {
+            ContentBlock::Text { annotations, .. }
+            | ContentBlock::Image { annotations, .. }
+            | ContentBlock::Audio { annotations, .. }
+            | ContentBlock::Resource { annotations, .. }
+            | ContentBlock::ResourceLink { annotations, .. } => annotations.as_ref(),
+        }
+    }
+
+    /// Attach annotations to this block, replacing any existing ones.
+    pub fn with_annotations(mut self, value: Annotations) -> Self {
+        match &mut self {
+            ContentBlock::Text { annotations, .. }
+            | ContentBlock::Image { annotations, .. }
+            | ContentBlock::Audio { annotations, .. }
+            | ContentBlock::Resource { annotations, .. }
+            | ContentBlock::ResourceLink { annotations, .. } => *annotations = Some(value),
+        }
+        self
+    }
+
+    /// Whether this block is intended for `role`.
+    ///
+    /// Blocks without annotations are intended for everyone.
+    pub fn is_for(&self, role: Role) -> bool {
+        self.annotations().is_none_or(|a| a.includes(role))
+    }
+
+    /// Whether this block carries audio data.
+    pub fn is_audio(&self) -> bool {
+        matches!(self, ContentBlock::Audio { .. })
+    }
+}
+
+/// Validate the audio blocks in `content` against the receiving peer.
+///
+/// Returns `CapabilityNotSupported` if any audio is present but the peer did
+/// not advertise audio support, and `InvalidParams` if a block is malformed or
+/// exceeds [`MAX_AUDIO_DATA_LEN`].
+pub fn validate_audio_content(content: &[ContentBlock], audio_supported: bool) -> AcpResult<()> {
+    for block in content {
+        if let ContentBlock::Audio { format, data, .. } = block {
+            if !audio_supported {
+                return Err(AcpError::CapabilityNotSupported("audio".to_string()));
+            }
+            validate_audio_data(format, data)?;
+        }
+    }
+    Ok(())
+}
+
+/// Validate the format and size of a piece of base64-encoded audio.
+pub fn validate_audio_data(format: &str, data: &str) -> AcpResult<()> {
+    if format.is_empty() {
+        return Err(AcpError::InvalidParams("Missing audio format".to_string()));
+    }
+    if data.len() > MAX_AUDIO_DATA_LEN {
+        return Err(AcpError::InvalidParams(format!(
+            "Audio data exceeds {} bytes",
+            MAX_AUDIO_DATA_LEN
+        )));
+    }
+    Ok(())
+}
+
+/// A piece of streamed audio output.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct AudioChunk {
+    /// Audio format (wav, mp3, etc.).
+    pub format: String,
+    /// Base64-encoded audio data for this chunk.
+    pub data: String,
+    /// Position of this chunk within the stream, starting at 0.
+    pub sequence: u32,
+    /// Whether this is the final chunk of the stream.
+    #[serde(default)]
+    pub last: bool,
+}
+
 /// A tool call made by the agent.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct ToolCall {
     /// Unique identifier for this tool call.
     pub id: String,
     /// Name of the tool being called.
     pub name: String,
     /// Arguments to the tool.
+    #[cfg_attr(feature = "arbitrary", arbitrary(with = super::fuzz::json))]
     pub arguments: serde_json::Value,
 }
 
 /// Update for a tool call.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct ToolCallUpdate {
     /// ID of the tool call being updated.
     pub id: String,
@@ -152,14 +446,40 @@ pub struct ToolCallUpdate {
     pub status: ToolCallStatus,
     /// Result of the tool call (if completed).
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "arbitrary", arbitrary(with = super::fuzz::optional_json))]
     pub result: Option<serde_json::Value>,
     /// Error message (if failed).
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<String>,
+    /// Content produced by the tool call so far.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub content: Vec<ToolCallContent>,
+}
+
+/// Content attached to a tool call update.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ToolCallContent {
+    /// A regular content block.
+    Content {
+        /// The content block.
+        content: ContentBlock,
+    },
+    /// A live terminal previously created via `terminal/create`.
+    ///
+    /// Clients can embed the terminal's view for this tool call.
+    Terminal {
+        /// ID of the terminal.
+        terminal_id: String,
+    },
 }
 
 /// Status of a tool call.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[serde(rename_all = "snake_case")]
 pub enum ToolCallStatus {
     /// Tool call is in progress.
@@ -170,8 +490,61 @@ pub enum ToolCallStatus {
     Failed,
 }
 
+/// An option offered to the user when the agent asks for permission.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct PermissionOption {
+    /// Identifier returned when this option is selected.
+    pub option_id: String,
+    /// Label shown to the user.
+    pub name: String,
+    /// What selecting this option means.
+    pub kind: PermissionOptionKind,
+}
+
+/// Kind of a permission option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[serde(rename_all = "snake_case")]
+pub enum PermissionOptionKind {
+    /// Allow this one operation.
+    AllowOnce,
+    /// Allow this and future operations of the same kind.
+    AllowAlways,
+    /// Reject this one operation.
+    RejectOnce,
+    /// Reject this and future operations of the same kind.
+    RejectAlways,
+}
+
+impl PermissionOptionKind {
+    /// Check whether this option grants permission.
+    pub fn is_allow(&self) -> bool {
+        matches!(self, Self::AllowOnce | Self::AllowAlways)
+    }
+}
+
+/// The user's answer to a permission request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum PermissionOutcome {
+    /// The request was dismissed, e.g. because the prompt was cancelled.
+    Cancelled,
+    /// The user picked one of the offered options.
+    Selected {
+        /// ID of the selected option.
+        option_id: String,
+    },
+}
+
 /// A plan consisting of multiple steps.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct Plan {
     /// Steps in the plan.
     pub steps: Vec<PlanStep>,
@@ -179,6 +552,8 @@ pub struct Plan {
 
 /// A step in a plan.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct PlanStep {
     /// Unique identifier for this step.
     pub id: u32,
@@ -190,6 +565,8 @@ pub struct PlanStep {
 
 /// Status of a plan step.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[serde(rename_all = "snake_case")]
 pub enum PlanStepStatus {
     /// Step is pending.
@@ -204,8 +581,26 @@ pub enum PlanStepStatus {
     Failed,
 }
 
+/// Activity state of the agent within a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[serde(rename_all = "snake_case")]
+pub enum AgentState {
+    /// Agent is not working on anything.
+    Idle,
+    /// Agent is reasoning or generating a response.
+    Thinking,
+    /// Agent is executing a tool call.
+    RunningTool,
+    /// Agent is blocked on a permission decision from the user.
+    WaitingForPermission,
+}
+
 /// Session update sent from agent to client.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct SessionUpdate {
     /// Session ID.
     pub session_id: String,
@@ -216,6 +611,8 @@ pub struct SessionUpdate {
 
 /// Types of session updates.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[serde(tag = "type", content = "data", rename_all = "snake_case")]
 pub enum SessionUpdateType {
     /// Chunk of agent message.
@@ -228,6 +625,8 @@ pub enum SessionUpdateType {
         /// Thought text.
         text: String,
     },
+    /// Chunk of streamed agent audio.
+    AudioChunk(AudioChunk),
     /// Agent is making a tool call.
     ToolCall(ToolCall),
     /// Update on a tool call.
@@ -239,6 +638,23 @@ pub enum SessionUpdateType {
         /// New mode.
         mode: String,
     },
+    /// The session runs on another model from now on.
+    ModelChange {
+        /// ID of the new model.
+        model: String,
+    },
+    /// Agent activity status change.
+    Status {
+        /// New activity state.
+        state: AgentState,
+    },
+    /// A prompt is waiting behind others in the session.
+    Queued {
+        /// ID to pass to `session/cancel` to drop the queued prompt.
+        prompt_id: String,
+        /// Place in line; 1 means it runs next.
+        position: usize,
+    },
     /// Agent is done with the response.
     Done,
 }
@@ -247,6 +663,58 @@ pub enum SessionUpdateType {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_agent_capabilities_merge() {
+        let tool = |name: &str| ToolInfo {
+            name: name.to_string(),
+            description: String::new(),
+            parameters: serde_json::json!({}),
+        };
+        let mut caps = AgentCapabilities {
+            streaming: true,
+            supported_modes: vec!["agent".to_string()],
+            tools: vec![tool("read_file")],
+            ..Default::default()
+        };
+        caps.merge(AgentCapabilities {
+            audio: true,
+            supported_modes: vec!["agent".to_string(), "ask".to_string()],
+            tools: vec![tool("read_file"), tool("run_command")],
+            ..Default::default()
+        });
+
+        assert!(caps.streaming && caps.audio && !caps.image);
+        assert_eq!(caps.supported_modes, vec!["agent", "ask"]);
+        assert_eq!(caps.tools.len(), 2);
+    }
+
+    #[test]
+    fn test_permission_option_serialization() {
+        let option = PermissionOption {
+            option_id: "allow".to_string(),
+            name: "Allow once".to_string(),
+            kind: PermissionOptionKind::AllowOnce,
+        };
+        let json = serde_json::to_value(&option).unwrap();
+        assert_eq!(json["kind"], "allow_once");
+        assert!(option.kind.is_allow());
+        assert!(!PermissionOptionKind::RejectAlways.is_allow());
+    }
+
+    #[test]
+    fn test_permission_outcome_serialization() {
+        let selected = PermissionOutcome::Selected {
+            option_id: "allow".to_string(),
+        };
+        let json = serde_json::to_value(&selected).unwrap();
+        assert_eq!(json["outcome"], "selected");
+        assert_eq!(json["option_id"], "allow");
+
+        let cancelled: PermissionOutcome =
+            serde_json::from_str(r#"{"outcome": "cancelled"}"#).unwrap();
+        assert_eq!(cancelled, PermissionOutcome::Cancelled);
+    }
+
     #[test]
     fn test_protocol_version() {
         assert_eq!(PROTOCOL_VERSION, "2025.1");
@@ -294,9 +762,17 @@ mod tests {
         let caps = ClientCapabilities {
             text_files: true,
             terminal: true,
+            apply_edit: false,
+            binary_files: false,
+            read_stream: false,
+            list_directory: false,
+            glob: false,
+            grep: false,
+            manage_files: false,
             embedded_context: false,
             audio: false,
             image: true,
+            sampling: false,
             experimental: HashMap::new(),
         };
         let json = serde_json::to_string(&caps).unwrap();
@@ -319,15 +795,13 @@ mod tests {
 
     #[test]
     fn test_content_block_text() {
-        let block = ContentBlock::Text {
-            text: "Hello, world!".to_string(),
-        };
+        let block = ContentBlock::text("Hello, world!");
         let json = serde_json::to_string(&block).unwrap();
         assert!(json.contains("\"type\":\"text\""));
         assert!(json.contains("Hello, world!"));
 
         let deserialized: ContentBlock = serde_json::from_str(&json).unwrap();
-        if let ContentBlock::Text { text } = deserialized {
+        if let ContentBlock::Text { text, .. } = deserialized {
             assert_eq!(text, "Hello, world!");
         } else {
             panic!("Expected Text block");
@@ -339,12 +813,13 @@ mod tests {
         let block = ContentBlock::Image {
             format: "png".to_string(),
             data: "base64data".to_string(),
+            annotations: None,
         };
         let json = serde_json::to_string(&block).unwrap();
         assert!(json.contains("\"type\":\"image\""));
 
         let deserialized: ContentBlock = serde_json::from_str(&json).unwrap();
-        if let ContentBlock::Image { format, data } = deserialized {
+        if let ContentBlock::Image { format, data, .. } = deserialized {
             assert_eq!(format, "png");
             assert_eq!(data, "base64data");
         } else {
@@ -358,11 +833,108 @@ mod tests {
             uri: "file:///test.txt".to_string(),
             mime_type: "text/plain".to_string(),
             content: "file content".to_string(),
+            annotations: None,
         };
         let json = serde_json::to_string(&block).unwrap();
         assert!(json.contains("\"type\":\"resource\""));
     }
 
+    #[test]
+    fn test_content_block_without_annotations() {
+        let json = serde_json::to_string(&ContentBlock::text("plain")).unwrap();
+        assert!(!json.contains("annotations"));
+
+        let block: ContentBlock = serde_json::from_str(r#"{"type":"text","text":"plain"}"#).unwrap();
+        assert!(block.annotations().is_none());
+        assert!(block.is_for(Role::User));
+        assert!(block.is_for(Role::Assistant));
+    }
+
+    #[test]
+    fn test_content_block_annotations() {
+        let block = ContentBlock::ResourceLink {
+            uri: "file:///notes.md".to_string(),
+            mime_type: "text/markdown".to_string(),
+            annotations: None,
+        }
+        .with_annotations(Annotations {
+            audience: vec![Role::Assistant],
+            priority: Some(0.8),
+            last_modified: Some("2025-01-12T15:00:58Z".to_string()),
+        });
+
+        let json = serde_json::to_string(&block).unwrap();
+        assert!(json.contains("\"audience\":[\"assistant\"]"));
+        assert!(json.contains("\"priority\":0.8"));
+
+        let deserialized: ContentBlock = serde_json::from_str(&json).unwrap();
+        let annotations = deserialized.annotations().unwrap();
+        assert_eq!(annotations.priority, Some(0.8));
+        assert!(!deserialized.is_for(Role::User));
+        assert!(deserialized.is_for(Role::Assistant));
+    }
+
+    #[test]
+    fn test_validate_audio_content() {
+        let content = vec![
+            ContentBlock::text("transcribe this"),
+            ContentBlock::Audio {
+                format: "wav".to_string(),
+                data: "UklGRg==".to_string(),
+                annotations: None,
+            },
+        ];
+        assert!(validate_audio_content(&content, true).is_ok());
+
+        let err = validate_audio_content(&content, false).unwrap_err();
+        assert!(matches!(err, AcpError::CapabilityNotSupported(_)));
+    }
+
+    #[test]
+    fn test_validate_audio_content_without_audio() {
+        let content = vec![ContentBlock::text("no audio here")];
+        assert!(validate_audio_content(&content, false).is_ok());
+    }
+
+    #[test]
+    fn test_validate_audio_data_limits() {
+        assert!(validate_audio_data("mp3", "AAAA").is_ok());
+        assert!(matches!(
+            validate_audio_data("", "AAAA"),
+            Err(AcpError::InvalidParams(_))
+        ));
+
+        let oversized = "A".repeat(MAX_AUDIO_DATA_LEN + 1);
+        assert!(matches!(
+            validate_audio_data("wav", &oversized),
+            Err(AcpError::InvalidParams(_))
+        ));
+    }
+
+    #[test]
+    fn test_session_update_audio_chunk() {
+        let update = SessionUpdate {
+            session_id: "session_1".to_string(),
+            update_type: SessionUpdateType::AudioChunk(AudioChunk {
+                format: "wav".to_string(),
+                data: "UklGRg==".to_string(),
+                sequence: 0,
+                last: true,
+            }),
+        };
+        let json = serde_json::to_string(&update).unwrap();
+        assert!(json.contains("\"type\":\"audio_chunk\""));
+        assert!(json.contains("\"sequence\":0"));
+
+        let deserialized: SessionUpdate = serde_json::from_str(&json).unwrap();
+        if let SessionUpdateType::AudioChunk(chunk) = deserialized.update_type {
+            assert_eq!(chunk.format, "wav");
+            assert!(chunk.last);
+        } else {
+            panic!("Expected AudioChunk update");
+        }
+    }
+
     #[test]
     fn test_tool_call_serialization() {
         let tool_call = ToolCall {
@@ -398,6 +970,7 @@ mod tests {
             status: ToolCallStatus::Completed,
             result: Some(serde_json::json!({"content": "test"})),
             error: None,
+            content: vec![],
         };
         let json = serde_json::to_string(&update).unwrap();
         assert!(json.contains("\"status\":\"completed\""));
@@ -407,6 +980,35 @@ mod tests {
         let deserialized: ToolCallUpdate = serde_json::from_str(&json).unwrap();
         assert_eq!(deserialized.id, "tool_1");
         assert!(matches!(deserialized.status, ToolCallStatus::Completed));
+        assert!(deserialized.content.is_empty());
+    }
+
+    #[test]
+    fn test_tool_call_update_with_terminal_content() {
+        let update = ToolCallUpdate {
+            id: "tool_2".to_string(),
+            status: ToolCallStatus::InProgress,
+            result: None,
+            error: None,
+            content: vec![
+                ToolCallContent::Terminal {
+                    terminal_id: "term_1".to_string(),
+                },
+                ToolCallContent::Content {
+                    content: ContentBlock::text("running tests"),
+                },
+            ],
+        };
+        let json = serde_json::to_string(&update).unwrap();
+        assert!(json.contains("\"type\":\"terminal\""));
+        assert!(json.contains("\"terminal_id\":\"term_1\""));
+
+        let deserialized: ToolCallUpdate = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.content.len(), 2);
+        assert!(matches!(
+            &deserialized.content[0],
+            ToolCallContent::Terminal { terminal_id } if terminal_id == "term_1"
+        ));
     }
 
     #[test]
@@ -503,6 +1105,42 @@ mod tests {
         assert!(json.contains("\"type\":\"done\""));
     }
 
+    #[test]
+    fn test_session_update_status() {
+        let update = SessionUpdate {
+            session_id: "session_1".to_string(),
+            update_type: SessionUpdateType::Status {
+                state: AgentState::RunningTool,
+            },
+        };
+        let json = serde_json::to_string(&update).unwrap();
+        assert!(json.contains("\"type\":\"status\""));
+        assert!(json.contains("\"state\":\"running_tool\""));
+
+        let deserialized: SessionUpdate = serde_json::from_str(&json).unwrap();
+        assert!(matches!(
+            deserialized.update_type,
+            SessionUpdateType::Status {
+                state: AgentState::RunningTool
+            }
+        ));
+    }
+
+    #[test]
+    fn test_agent_state_serialization() {
+        let states = vec![
+            (AgentState::Idle, "\"idle\""),
+            (AgentState::Thinking, "\"thinking\""),
+            (AgentState::RunningTool, "\"running_tool\""),
+            (AgentState::WaitingForPermission, "\"waiting_for_permission\""),
+        ];
+
+        for (state, expected) in states {
+            let json = serde_json::to_string(&state).unwrap();
+            assert_eq!(json, expected);
+        }
+    }
+
     #[test]
     fn test_mcp_server_serialization() {
         let server = McpServer {
@@ -514,6 +1152,27 @@ mod tests {
         let deserialized: McpServer = serde_json::from_str(&json).unwrap();
         assert_eq!(deserialized.name, "test-mcp");
         assert_eq!(deserialized.url, "stdio:///path/to/server");
+
+        let server = McpServer {
+            credentials: HashMap::from([("GITHUB_TOKEN".to_string(), "ghp_s3cret".to_string())]),
+            ..deserialized
+        };
+        let debug = format!("{:?}", server);
+        assert!(debug.contains("GITHUB_TOKEN") && !debug.contains("ghp_s3cret"), "{}", debug);
+
+        let status = McpServerStatus {
+            name: "test-mcp".to_string(),
+            state: McpServerState::Unavailable,
+            message: Some("connection closed".to_string()),
+        };
+        let json = serde_json::to_value(&status).unwrap();
+        assert_eq!(json["state"], "unavailable");
+        let status = McpServerStatus {
+            state: McpServerState::Connected,
+            message: None,
+            ..status
+        };
+        assert_eq!(serde_json::to_value(&status).unwrap(), serde_json::json!({ "name": "test-mcp", "state": "connected" }));
     }
 
     #[test]
@@ -0,0 +1,212 @@
+//! Enforcement of negotiated content capabilities.
+//!
+//! Prompts are checked against what the agent advertised, and session
+//! updates against what the client advertised.
+
+use crate::protocol::*;
+
+/// How the server treats content the receiving side can't handle.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CapabilityPolicy {
+    /// Reject prompts with `CAPABILITY_NOT_SUPPORTED` and drop updates
+    /// carrying unsupported content.
+    #[default]
+    Strict,
+    /// Strip unsupported content blocks and deliver the rest.
+    Lenient,
+}
+
+/// Content kinds a peer can receive.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ContentSupport {
+    image: bool,
+    audio: bool,
+}
+
+impl From<&AgentCapabilities> for ContentSupport {
+    fn from(caps: &AgentCapabilities) -> Self {
+        Self {
+            image: caps.image,
+            audio: caps.audio,
+        }
+    }
+}
+
+impl From<&ClientCapabilities> for ContentSupport {
+    fn from(caps: &ClientCapabilities) -> Self {
+        Self {
+            image: caps.image,
+            audio: caps.audio,
+        }
+    }
+}
+
+impl ContentSupport {
+    /// Check that a block is supported and well formed.
+    fn check(&self, block: &ContentBlock) -> AcpResult<()> {
+        match block {
+            ContentBlock::Image { .. } if !self.image => {
+                Err(AcpError::CapabilityNotSupported("image".to_string()))
+            }
+            ContentBlock::Audio { .. } if !self.audio => {
+                Err(AcpError::CapabilityNotSupported("audio".to_string()))
+            }
+            ContentBlock::Audio { format, data, .. } => validate_audio_data(format, data),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Apply `policy` to the content of a prompt.
+///
+/// Malformed blocks are rejected under either policy.
+pub(crate) fn enforce_prompt(
+    policy: CapabilityPolicy,
+    support: ContentSupport,
+    content: &mut Vec<ContentBlock>,
+) -> AcpResult<()> {
+    let mut result = Ok(());
+    content.retain(|block| match support.check(block) {
+        Ok(()) => true,
+        Err(AcpError::CapabilityNotSupported(_)) if policy == CapabilityPolicy::Lenient => false,
+        Err(e) => {
+            if result.is_ok() {
+                result = Err(e);
+            }
+            true
+        }
+    });
+    result
+}
+
+/// Apply `policy` to an outgoing session update.
+///
+/// Returns an error if the update must be dropped.
+pub(crate) fn enforce_update(
+    policy: CapabilityPolicy,
+    support: ContentSupport,
+    update: &mut SessionUpdate,
+) -> AcpResult<()> {
+    match &mut update.update_type {
+        SessionUpdateType::AudioChunk(chunk) => {
+            if !support.audio {
+                return Err(AcpError::CapabilityNotSupported("audio".to_string()));
+            }
+            validate_audio_data(&chunk.format, &chunk.data)
+        }
+        SessionUpdateType::ToolCallUpdate(tool_update) => {
+            let mut result = Ok(());
+            tool_update.content.retain(|item| {
+                let ToolCallContent::Content { content } = item else {
+                    return true;
+                };
+                match support.check(content) {
+                    Ok(()) => true,
+                    Err(_) if policy == CapabilityPolicy::Lenient => false,
+                    Err(e) => {
+                        if result.is_ok() {
+                            result = Err(e);
+                        }
+                        true
+                    }
+                }
+            });
+            result
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image() -> ContentBlock {
+        ContentBlock::Image {
+            format: "png".to_string(),
+            data: "aGk=".to_string(),
+            annotations: None,
+        }
+    }
+
+    fn text_only() -> ContentSupport {
+        ContentSupport {
+            image: false,
+            audio: false,
+        }
+    }
+
+    #[test]
+    fn test_strict_prompt_rejects_image() {
+        let mut content = vec![ContentBlock::text("look"), image()];
+        let err = enforce_prompt(CapabilityPolicy::Strict, text_only(), &mut content).unwrap_err();
+        assert_eq!(err.code(), codes::CAPABILITY_NOT_SUPPORTED);
+        assert_eq!(content.len(), 2);
+    }
+
+    #[test]
+    fn test_lenient_prompt_strips_image() {
+        let mut content = vec![ContentBlock::text("look"), image()];
+        enforce_prompt(CapabilityPolicy::Lenient, text_only(), &mut content).unwrap();
+        assert_eq!(content.len(), 1);
+        assert!(matches!(content[0], ContentBlock::Text { .. }));
+    }
+
+    #[test]
+    fn test_malformed_audio_rejected_when_lenient() {
+        let support = ContentSupport {
+            image: false,
+            audio: true,
+        };
+        let mut content = vec![ContentBlock::Audio {
+            format: String::new(),
+            data: String::new(),
+            annotations: None,
+        }];
+        let err = enforce_prompt(CapabilityPolicy::Lenient, support, &mut content).unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_PARAMS);
+    }
+
+    #[test]
+    fn test_update_tool_content() {
+        let update = || SessionUpdate {
+            session_id: "s1".to_string(),
+            update_type: SessionUpdateType::ToolCallUpdate(ToolCallUpdate {
+                id: "tool_1".to_string(),
+                status: ToolCallStatus::Completed,
+                result: None,
+                error: None,
+                content: vec![
+                    ToolCallContent::Content { content: image() },
+                    ToolCallContent::Terminal {
+                        terminal_id: "term_1".to_string(),
+                    },
+                ],
+            }),
+        };
+
+        let mut strict = update();
+        assert!(enforce_update(CapabilityPolicy::Strict, text_only(), &mut strict).is_err());
+
+        let mut lenient = update();
+        enforce_update(CapabilityPolicy::Lenient, text_only(), &mut lenient).unwrap();
+        let SessionUpdateType::ToolCallUpdate(tool_update) = lenient.update_type else {
+            unreachable!()
+        };
+        assert_eq!(tool_update.content.len(), 1);
+    }
+
+    #[test]
+    fn test_update_audio_chunk_dropped() {
+        let mut update = SessionUpdate {
+            session_id: "s1".to_string(),
+            update_type: SessionUpdateType::AudioChunk(AudioChunk {
+                format: "wav".to_string(),
+                data: "AAAA".to_string(),
+                sequence: 0,
+                last: true,
+            }),
+        };
+        assert!(enforce_update(CapabilityPolicy::Lenient, text_only(), &mut update).is_err());
+    }
+}
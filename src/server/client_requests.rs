@@ -0,0 +1,295 @@
+//! Higher-level helpers for requests the agent sends to the client.
+
+use tokio::sync::mpsc;
+
+use super::{Agent, AgentConnection, Server};
+use crate::protocol::*;
+
+/// An operation the agent wants the user to approve.
+#[derive(Debug, Clone)]
+pub struct PermissionRequest {
+    /// Session the operation belongs to.
+    pub session_id: String,
+    /// What the agent wants to do, e.g. `"write_file"` or `"run_command"`.
+    pub kind: String,
+    /// Human-readable description shown to the user.
+    pub description: String,
+    /// Choices to offer. Empty means allow once or reject once.
+    pub options: Vec<PermissionOptionKind>,
+}
+
+impl PermissionRequest {
+    /// Ask to allow or reject a single operation.
+    pub fn new(
+        session_id: impl Into<String>,
+        kind: impl Into<String>,
+        description: impl Into<String>,
+    ) -> Self {
+        Self {
+            session_id: session_id.into(),
+            kind: kind.into(),
+            description: description.into(),
+            options: Vec::new(),
+        }
+    }
+
+    /// Offer these choices instead of allow once / reject once.
+    pub fn options(mut self, options: impl IntoIterator<Item = PermissionOptionKind>) -> Self {
+        self.options = options.into_iter().collect();
+        self
+    }
+}
+
+/// Ask the user to approve an operation and wait for their choice.
+///
+/// Returns the kind of the allowing option the user picked. A rejection, a
+/// dismissed prompt or an unknown option fails with
+/// [`AcpError::PermissionDenied`].
+///
+/// ```rust,no_run
+/// # use heroacp::server::{client_requests::{request_permission, PermissionRequest}, AgentContext};
+/// # use heroacp::protocol::*;
+/// # async fn demo(ctx: AgentContext) -> AcpResult<()> {
+/// request_permission(
+///     ctx.connection(),
+///     PermissionRequest::new("session_1", "write_file", "Overwrite src/main.rs"),
+/// )
+/// .await?;
+/// // Permission granted; go ahead
+/// # Ok(())
+/// # }
+/// ```
+pub async fn request_permission(
+    conn: &AgentConnection,
+    request: PermissionRequest,
+) -> AcpResult<PermissionOptionKind> {
+    let kinds = if request.options.is_empty() {
+        vec![PermissionOptionKind::AllowOnce, PermissionOptionKind::RejectOnce]
+    } else {
+        request.options
+    };
+    let options: Vec<PermissionOption> = kinds
+        .iter()
+        .map(|kind| PermissionOption {
+            option_id: option_id(*kind).to_string(),
+            name: label(*kind).to_string(),
+            kind: *kind,
+        })
+        .collect();
+
+    let outcome = conn
+        .request_permission(RequestPermissionParams {
+            session_id: request.session_id,
+            tool_call: ToolCall {
+                id: format!("permission_{}", uuid::Uuid::new_v4()),
+                name: request.kind.clone(),
+                arguments: serde_json::json!({ "description": request.description }),
+            },
+            options,
+        })
+        .await?;
+
+    let chosen = match outcome {
+        PermissionOutcome::Selected { option_id: id } => {
+            kinds.into_iter().find(|kind| option_id(*kind) == id)
+        }
+        PermissionOutcome::Cancelled => None,
+    };
+    match chosen {
+        Some(kind) if kind.is_allow() => Ok(kind),
+        _ => Err(AcpError::PermissionDenied(request.kind)),
+    }
+}
+
+/// List every file under `dir` on the client, as absolute paths.
+///
+/// Directories and symlinks are left out. At most `max_entries` entries are
+/// examined, so a listing cut short by the client's limit returns the files
+/// found so far.
+///
+/// ```rust,no_run
+/// # use heroacp::server::{client_requests::list_files, AgentContext};
+/// # use heroacp::protocol::*;
+/// # async fn demo(ctx: AgentContext) -> AcpResult<()> {
+/// for path in list_files(ctx.connection(), "/project/src", None).await? {
+///     if path.ends_with(".rs") {
+///         let source = ctx.connection().read_file(&path).await?;
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub async fn list_files(
+    conn: &AgentConnection,
+    dir: &str,
+    max_entries: Option<u32>,
+) -> AcpResult<Vec<String>> {
+    let listing = conn.list_directory(dir, true, max_entries).await?;
+    let dir = dir.trim_end_matches(['/', '\\']);
+    Ok(listing
+        .entries
+        .into_iter()
+        .filter(|entry| entry.kind == DirectoryEntryKind::File)
+        .map(|entry| format!("{}/{}", dir, entry.name))
+        .collect())
+}
+
+/// Read a text file from the client.
+#[deprecated(note = "use `AgentConnection::read_file`")]
+pub async fn read_file(
+    server: &Server<impl Agent>,
+    path: &str,
+    _response_tx: &mpsc::Sender<String>,
+) -> AcpResult<String> {
+    server.served().await?.read_file(path).await
+}
+
+/// Write a text file via the client.
+#[deprecated(note = "use `AgentConnection::write_file`")]
+pub async fn write_file(
+    server: &Server<impl Agent>,
+    path: &str,
+    content: &str,
+    _response_tx: &mpsc::Sender<String>,
+) -> AcpResult<()> {
+    server.served().await?.write_file(path, content).await
+}
+
+/// Create a terminal session via the client.
+#[deprecated(note = "use `AgentConnection::create_terminal`")]
+pub async fn create_terminal(
+    server: &Server<impl Agent>,
+    cwd: &str,
+    command: &str,
+    _response_tx: &mpsc::Sender<String>,
+) -> AcpResult<String> {
+    server.served().await?.create_terminal(cwd, command).await
+}
+
+/// Get terminal output, whether the command exited and its exit code.
+#[deprecated(note = "use `AgentConnection::terminal_output`")]
+pub async fn get_terminal_output(
+    server: &Server<impl Agent>,
+    terminal_id: &str,
+    _response_tx: &mpsc::Sender<String>,
+) -> AcpResult<(String, bool, Option<i32>)> {
+    let output = server.served().await?.terminal_output(terminal_id).await?;
+    Ok((output.output, output.exited, output.exit_code))
+}
+
+/// Kill a terminal.
+#[deprecated(note = "use `AgentConnection::kill_terminal`")]
+pub async fn kill_terminal(
+    server: &Server<impl Agent>,
+    terminal_id: &str,
+    _response_tx: &mpsc::Sender<String>,
+) -> AcpResult<()> {
+    server.served().await?.kill_terminal(terminal_id).await
+}
+
+fn option_id(kind: PermissionOptionKind) -> &'static str {
+    match kind {
+        PermissionOptionKind::AllowOnce => "allow_once",
+        PermissionOptionKind::AllowAlways => "allow_always",
+        PermissionOptionKind::RejectOnce => "reject_once",
+        PermissionOptionKind::RejectAlways => "reject_always",
+    }
+}
+
+fn label(kind: PermissionOptionKind) -> &'static str {
+    match kind {
+        PermissionOptionKind::AllowOnce => "Allow",
+        PermissionOptionKind::AllowAlways => "Always allow",
+        PermissionOptionKind::RejectOnce => "Reject",
+        PermissionOptionKind::RejectAlways => "Always reject",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::sync::Arc;
+    use tokio::sync::{mpsc, RwLock};
+
+    /// Run `request` against a client that answers with `outcome`.
+    async fn ask(request: PermissionRequest, outcome: Value) -> (Value, AcpResult<PermissionOptionKind>) {
+        let (tx, mut rx) = mpsc::channel(8);
+        let conn = AgentConnection::new(tx, Arc::new(RwLock::new(ClientCapabilities::default())));
+
+        let asker = conn.clone();
+        let task = tokio::spawn(async move { request_permission(&asker, request).await });
+
+        let sent: JsonRpcRequest = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(sent.method, method::SESSION_REQUEST_PERMISSION);
+        conn.complete(JsonRpcResponse {
+            jsonrpc: "2.0".to_string(),
+            id: sent.id.unwrap(),
+            result: Some(serde_json::json!({ "outcome": outcome })),
+            error: None,
+        })
+        .await;
+        (sent.params.unwrap(), task.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_allowed() {
+        let request = PermissionRequest::new("s1", "run_command", "Run `cargo test`")
+            .options([PermissionOptionKind::AllowAlways, PermissionOptionKind::RejectOnce]);
+        let (params, result) = ask(
+            request,
+            serde_json::json!({"outcome": "selected", "option_id": "allow_always"}),
+        )
+        .await;
+
+        assert_eq!(result.unwrap(), PermissionOptionKind::AllowAlways);
+        assert_eq!(params["session_id"], "s1");
+        assert_eq!(params["tool_call"]["name"], "run_command");
+        assert_eq!(params["tool_call"]["arguments"]["description"], "Run `cargo test`");
+        assert_eq!(params["options"][0]["option_id"], "allow_always");
+        assert_eq!(params["options"][1]["kind"], "reject_once");
+    }
+
+    #[tokio::test]
+    async fn test_denied() {
+        let request = PermissionRequest::new("s1", "write_file", "Overwrite a.txt");
+        let (params, result) = ask(
+            request.clone(),
+            serde_json::json!({"outcome": "selected", "option_id": "reject_once"}),
+        )
+        .await;
+        assert_eq!(params["options"].as_array().unwrap().len(), 2);
+        assert!(matches!(result, Err(AcpError::PermissionDenied(kind)) if kind == "write_file"));
+
+        let (_, result) = ask(request, serde_json::json!({"outcome": "cancelled"})).await;
+        assert_eq!(result.unwrap_err().code(), codes::PERMISSION_DENIED);
+    }
+
+    #[tokio::test]
+    async fn test_list_files() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let capabilities = ClientCapabilities {
+            list_directory: true,
+            ..Default::default()
+        };
+        let conn = AgentConnection::new(tx, Arc::new(RwLock::new(capabilities)));
+
+        let lister = conn.clone();
+        let task = tokio::spawn(async move { list_files(&lister, "/project/", None).await });
+
+        let sent: JsonRpcRequest = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(sent.method, method::FS_LIST_DIRECTORY);
+        assert_eq!(sent.params.unwrap()["recursive"], true);
+        conn.complete(JsonRpcResponse {
+            jsonrpc: "2.0".to_string(),
+            id: sent.id.unwrap(),
+            result: Some(serde_json::json!({"entries": [
+                {"name": "src", "kind": "directory"},
+                {"name": "src/main.rs", "kind": "file", "size": 12},
+            ]})),
+            error: None,
+        })
+        .await;
+        assert_eq!(task.await.unwrap().unwrap(), vec!["/project/src/main.rs"]);
+    }
+}
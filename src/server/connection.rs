@@ -0,0 +1,623 @@
+//! Handle for calling back into the client.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot, Mutex, RwLock};
+
+use crate::protocol::*;
+
+/// Connection to the client, used by agents to request client operations.
+///
+/// Obtain one from [`AgentContext::connection`](super::AgentContext::connection).
+/// Cloning is cheap; all clones share the same connection.
+#[derive(Debug, Clone)]
+pub struct AgentConnection {
+    outgoing: mpsc::Sender<String>,
+    pending_requests: Arc<Mutex<HashMap<RequestId, oneshot::Sender<JsonRpcResponse>>>>,
+    next_request_id: Arc<Mutex<i64>>,
+    client_capabilities: Arc<RwLock<ClientCapabilities>>,
+}
+
+impl AgentConnection {
+    pub(crate) fn new(
+        outgoing: mpsc::Sender<String>,
+        client_capabilities: Arc<RwLock<ClientCapabilities>>,
+    ) -> Self {
+        Self {
+            outgoing,
+            pending_requests: Arc::new(Mutex::new(HashMap::new())),
+            next_request_id: Arc::new(Mutex::new(1)),
+            client_capabilities,
+        }
+    }
+
+    /// Deliver a response from the client to the request waiting for it.
+    pub(crate) async fn complete(&self, response: JsonRpcResponse) {
+        let mut pending = self.pending_requests.lock().await;
+        if let Some(tx) = pending.remove(&response.id) {
+            let _ = tx.send(response);
+        }
+    }
+
+    /// Send a request to the client and wait for a response.
+    pub async fn send_request(&self, method: &str, params: Value) -> AcpResult<Value> {
+        let id = {
+            let mut next_id = self.next_request_id.lock().await;
+            let id = *next_id;
+            *next_id += 1;
+            id
+        };
+
+        let id = RequestId::Number(id);
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: Some(id.clone()),
+            method: method.to_string(),
+            params: Some(params),
+        };
+        let msg = serde_json::to_string(&request)?;
+
+        // Registered before sending, as the response may arrive right away
+        let (tx, rx) = oneshot::channel();
+        self.pending_requests.lock().await.insert(id.clone(), tx);
+        if let Err(e) = self.outgoing.send(msg).await {
+            self.pending_requests.lock().await.remove(&id);
+            return Err(AcpError::ChannelError(e.to_string()));
+        }
+
+        let response = rx.await.map_err(|_| AcpError::ConnectionClosed)?;
+
+        if let Some(error) = response.error {
+            return Err(error.into());
+        }
+
+        Ok(response.result.unwrap_or(Value::Null))
+    }
+
+    /// Send a notification to the client.
+    ///
+    /// Use this for agent-specific events such as `workspace/indexing_complete`;
+    /// session updates should go through the prompt's update channel instead.
+    pub async fn notify(&self, method: &str, params: Value) -> AcpResult<()> {
+        let notification = JsonRpcNotification {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params: Some(params),
+        };
+        let msg = serde_json::to_string(&notification)?;
+        self.outgoing
+            .send(msg)
+            .await
+            .map_err(|e| AcpError::ChannelError(e.to_string()))
+    }
+
+    async fn call<P: Serialize, R: DeserializeOwned>(&self, method: &str, params: P) -> AcpResult<R> {
+        let result = self.send_request(method, serde_json::to_value(params)?).await?;
+        serde_json::from_value(result).map_err(|e| AcpError::InvalidParams(e.to_string()))
+    }
+
+    async fn require_text_files(&self) -> AcpResult<()> {
+        if self.client_capabilities.read().await.text_files {
+            Ok(())
+        } else {
+            Err(AcpError::CapabilityNotSupported("text_files".to_string()))
+        }
+    }
+
+    async fn require_terminal(&self) -> AcpResult<()> {
+        if self.client_capabilities.read().await.terminal {
+            Ok(())
+        } else {
+            Err(AcpError::CapabilityNotSupported("terminal".to_string()))
+        }
+    }
+
+    async fn require_binary_files(&self) -> AcpResult<()> {
+        if self.client_capabilities.read().await.binary_files {
+            Ok(())
+        } else {
+            Err(AcpError::CapabilityNotSupported("binary_files".to_string()))
+        }
+    }
+
+    async fn require_read_stream(&self) -> AcpResult<()> {
+        if self.client_capabilities.read().await.read_stream {
+            Ok(())
+        } else {
+            Err(AcpError::CapabilityNotSupported("read_stream".to_string()))
+        }
+    }
+
+    async fn require_sampling(&self) -> AcpResult<()> {
+        if self.client_capabilities.read().await.sampling {
+            Ok(())
+        } else {
+            Err(AcpError::CapabilityNotSupported("sampling".to_string()))
+        }
+    }
+
+    async fn require_manage_files(&self) -> AcpResult<()> {
+        if self.client_capabilities.read().await.manage_files {
+            Ok(())
+        } else {
+            Err(AcpError::CapabilityNotSupported("manage_files".to_string()))
+        }
+    }
+
+    /// Read a text file from the client.
+    pub async fn read_file(&self, path: &str) -> AcpResult<String> {
+        self.read_file_lines(path, None, None).await
+    }
+
+    /// Read at most `limit` lines of a text file from the client, starting at
+    /// `line` (counting from 1).
+    pub async fn read_file_lines(
+        &self,
+        path: &str,
+        line: Option<u32>,
+        limit: Option<u32>,
+    ) -> AcpResult<String> {
+        self.require_text_files().await?;
+        let result: FsReadTextFileResult = self
+            .call(
+                method::FS_READ_TEXT_FILE,
+                FsReadTextFileParams {
+                    path: path.to_string(),
+                    line,
+                    limit,
+                },
+            )
+            .await?;
+        Ok(result.content)
+    }
+
+    /// Write a text file via the client.
+    pub async fn write_file(&self, path: &str, content: &str) -> AcpResult<()> {
+        self.write_file_with(FsWriteTextFileParams {
+            path: path.to_string(),
+            content: content.to_string(),
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Write a text file with all of `fs/write_text_file`'s options, such as
+    /// creating parent directories or writing atomically.
+    pub async fn write_file_with(&self, params: FsWriteTextFileParams) -> AcpResult<()> {
+        self.require_text_files().await?;
+        let _: Value = self.call(method::FS_WRITE_TEXT_FILE, params).await?;
+        Ok(())
+    }
+
+    /// Apply a unified diff to a text file via the client and return the
+    /// [`content_hash`] of the result.
+    ///
+    /// With `base_hash` the edit is refused if the file changed since it was
+    /// hashed. Refused edits fail with [`codes::EDIT_CONFLICT`]; use
+    /// [`EditConflict::from_error`] to see why.
+    pub async fn apply_edit(&self, path: &str, unified_diff: &str, base_hash: Option<&str>) -> AcpResult<String> {
+        self.apply_edit_with(FsApplyEditParams {
+            path: path.to_string(),
+            unified_diff: unified_diff.to_string(),
+            base_hash: base_hash.map(str::to_string),
+            session_id: None,
+        })
+        .await
+    }
+
+    /// Apply a unified diff with all of `fs/apply_edit`'s parameters, such
+    /// as the session the edit is for.
+    pub async fn apply_edit_with(&self, params: FsApplyEditParams) -> AcpResult<String> {
+        if !self.client_capabilities.read().await.apply_edit {
+            return Err(AcpError::CapabilityNotSupported("apply_edit".to_string()));
+        }
+        let result: FsApplyEditResult = self.call(method::FS_APPLY_EDIT, params).await?;
+        Ok(result.hash)
+    }
+
+    /// Read a file of any kind from the client, returning its contents and
+    /// the MIME type the client guessed for it.
+    pub async fn read_binary_file(&self, path: &str) -> AcpResult<(Vec<u8>, String)> {
+        self.require_binary_files().await?;
+        let result: FsReadFileResult = self
+            .call(
+                method::FS_READ_FILE,
+                FsReadFileParams {
+                    path: path.to_string(),
+                },
+            )
+            .await?;
+        Ok((result.bytes()?, result.mime_type))
+    }
+
+    /// Write a file of any kind via the client.
+    pub async fn write_binary_file(&self, path: &str, data: &[u8]) -> AcpResult<()> {
+        self.write_binary_file_with(FsWriteFileParams::new(path, data)).await
+    }
+
+    /// Write a file of any kind with all of `fs/write_file`'s options.
+    pub async fn write_binary_file_with(&self, params: FsWriteFileParams) -> AcpResult<()> {
+        self.require_binary_files().await?;
+        let _: Value = self.call(method::FS_WRITE_FILE, params).await?;
+        Ok(())
+    }
+
+    /// Open a file on the client for reading in chunks of at most
+    /// `chunk_size` bytes. Read it with [`read_chunk`](Self::read_chunk).
+    pub async fn read_stream_start(&self, path: &str, chunk_size: Option<u32>) -> AcpResult<FsReadStreamStartResult> {
+        self.require_read_stream().await?;
+        self.call(
+            method::FS_READ_STREAM_START,
+            FsReadStreamStartParams {
+                path: path.to_string(),
+                chunk_size,
+            },
+        )
+        .await
+    }
+
+    /// Read the next chunk of a file stream. The second value is `true` at
+    /// the end of the file, after which the client has closed the stream.
+    pub async fn read_chunk(&self, stream_id: &str) -> AcpResult<(Vec<u8>, bool)> {
+        self.require_read_stream().await?;
+        let result: FsReadChunkResult = self
+            .call(
+                method::FS_READ_CHUNK,
+                FsReadStreamParams {
+                    stream_id: stream_id.to_string(),
+                },
+            )
+            .await?;
+        Ok((result.bytes()?, result.eof))
+    }
+
+    /// Close a file stream before reaching the end of the file.
+    pub async fn close_read_stream(&self, stream_id: &str) -> AcpResult<()> {
+        self.require_read_stream().await?;
+        let _: Value = self
+            .call(
+                method::FS_READ_STREAM_CLOSE,
+                FsReadStreamParams {
+                    stream_id: stream_id.to_string(),
+                },
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// List a directory via the client, descending into subdirectories if
+    /// `recursive` is set.
+    pub async fn list_directory(
+        &self,
+        path: &str,
+        recursive: bool,
+        max_entries: Option<u32>,
+    ) -> AcpResult<FsListDirectoryResult> {
+        if !self.client_capabilities.read().await.list_directory {
+            return Err(AcpError::CapabilityNotSupported("list_directory".to_string()));
+        }
+        self.call(
+            method::FS_LIST_DIRECTORY,
+            FsListDirectoryParams {
+                path: path.to_string(),
+                recursive,
+                max_entries,
+            },
+        )
+        .await
+    }
+
+    /// Find the files under `cwd` matching the glob `pattern` via the client.
+    /// Files ignored by `.gitignore` are left out.
+    pub async fn glob(&self, pattern: &str, cwd: &str, max_results: Option<u32>) -> AcpResult<FsGlobResult> {
+        if !self.client_capabilities.read().await.glob {
+            return Err(AcpError::CapabilityNotSupported("glob".to_string()));
+        }
+        self.call(
+            method::FS_GLOB,
+            FsGlobParams {
+                pattern: pattern.to_string(),
+                cwd: cwd.to_string(),
+                max_results,
+            },
+        )
+        .await
+    }
+
+    /// Search the files under `path` for lines matching `query` via the
+    /// client, treating `query` as a regular expression if `regex` is set.
+    /// Files ignored by `.gitignore` are left out.
+    pub async fn grep(
+        &self,
+        query: &str,
+        regex: bool,
+        path: &str,
+        max_matches: Option<u32>,
+    ) -> AcpResult<FsGrepResult> {
+        if !self.client_capabilities.read().await.grep {
+            return Err(AcpError::CapabilityNotSupported("grep".to_string()));
+        }
+        self.call(
+            method::FS_GREP,
+            FsGrepParams {
+                query: query.to_string(),
+                regex,
+                path: path.to_string(),
+                max_matches,
+            },
+        )
+        .await
+    }
+
+    /// Delete a file or directory via the client, with everything in it if
+    /// `recursive` is set.
+    pub async fn delete_path(&self, path: &str, recursive: bool) -> AcpResult<()> {
+        self.require_manage_files().await?;
+        let _: Value = self
+            .call(
+                method::FS_DELETE,
+                FsDeleteParams {
+                    path: path.to_string(),
+                    recursive,
+                    session_id: None,
+                },
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Move a file or directory via the client, replacing an existing `to`
+    /// only if `overwrite` is set.
+    pub async fn rename_path(&self, from: &str, to: &str, overwrite: bool) -> AcpResult<()> {
+        self.require_manage_files().await?;
+        let _: Value = self
+            .call(
+                method::FS_RENAME,
+                FsRenameParams {
+                    from: from.to_string(),
+                    to: to.to_string(),
+                    overwrite,
+                    session_id: None,
+                },
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Create a directory via the client, with its missing parents if
+    /// `recursive` is set.
+    pub async fn create_directory(&self, path: &str, recursive: bool) -> AcpResult<()> {
+        self.require_manage_files().await?;
+        let _: Value = self
+            .call(
+                method::FS_CREATE_DIRECTORY,
+                FsCreateDirectoryParams {
+                    path: path.to_string(),
+                    recursive,
+                    session_id: None,
+                },
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Create a terminal running `command` via the client. Returns the terminal ID.
+    pub async fn create_terminal(&self, cwd: &str, command: &str) -> AcpResult<String> {
+        self.create_terminal_with(TerminalCreateParams {
+            cwd: cwd.to_string(),
+            command: command.to_string(),
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Create a terminal with all of `terminal/create`'s options, such as
+    /// an output byte limit.
+    pub async fn create_terminal_with(&self, params: TerminalCreateParams) -> AcpResult<String> {
+        self.require_terminal().await?;
+        let result: TerminalCreateResult = self.call(method::TERMINAL_CREATE, params).await?;
+        Ok(result.terminal_id)
+    }
+
+    /// Get the current output of a terminal.
+    pub async fn terminal_output(&self, terminal_id: &str) -> AcpResult<TerminalOutputResult> {
+        self.require_terminal().await?;
+        self.call(
+            method::TERMINAL_OUTPUT,
+            TerminalOutputParams {
+                terminal_id: terminal_id.to_string(),
+            },
+        )
+        .await
+    }
+
+    /// Write `data` to a terminal's stdin, closing it afterwards if `eof` is
+    /// set.
+    pub async fn terminal_input(&self, terminal_id: &str, data: &str, eof: bool) -> AcpResult<()> {
+        self.require_terminal().await?;
+        let _: Value = self
+            .call(
+                method::TERMINAL_INPUT,
+                TerminalInputParams {
+                    terminal_id: terminal_id.to_string(),
+                    data: data.to_string(),
+                    eof,
+                },
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Wait for a terminal to exit.
+    pub async fn wait_for_terminal_exit(
+        &self,
+        terminal_id: &str,
+    ) -> AcpResult<TerminalWaitForExitResult> {
+        self.require_terminal().await?;
+        self.call(
+            method::TERMINAL_WAIT_FOR_EXIT,
+            TerminalWaitForExitParams {
+                terminal_id: terminal_id.to_string(),
+            },
+        )
+        .await
+    }
+
+    /// Kill a terminal.
+    pub async fn kill_terminal(&self, terminal_id: &str) -> AcpResult<()> {
+        self.require_terminal().await?;
+        let _: Value = self
+            .call(
+                method::TERMINAL_KILL,
+                TerminalKillParams {
+                    terminal_id: terminal_id.to_string(),
+                },
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Release a terminal.
+    pub async fn release_terminal(&self, terminal_id: &str) -> AcpResult<()> {
+        self.require_terminal().await?;
+        let _: Value = self
+            .call(
+                method::TERMINAL_RELEASE,
+                TerminalReleaseParams {
+                    terminal_id: terminal_id.to_string(),
+                },
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Ask the user to approve a tool call.
+    pub async fn request_permission(
+        &self,
+        params: RequestPermissionParams,
+    ) -> AcpResult<PermissionOutcome> {
+        let result: RequestPermissionResult = self
+            .call(method::SESSION_REQUEST_PERMISSION, params)
+            .await?;
+        Ok(result.outcome)
+    }
+
+    /// Ask the client's model for a completion, e.g. for an MCP server's
+    /// sampling request.
+    pub async fn request_sampling(
+        &self,
+        params: SessionSamplingRequestParams,
+    ) -> AcpResult<SessionSamplingRequestResult> {
+        self.require_sampling().await?;
+        self.call(method::SESSION_SAMPLING_REQUEST, params).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connection(capabilities: ClientCapabilities) -> (AgentConnection, mpsc::Receiver<String>) {
+        let (tx, rx) = mpsc::channel(8);
+        let conn = AgentConnection::new(tx, Arc::new(RwLock::new(capabilities)));
+        (conn, rx)
+    }
+
+    #[tokio::test]
+    async fn test_read_file_round_trip() {
+        let (conn, mut rx) = connection(ClientCapabilities {
+            text_files: true,
+            ..Default::default()
+        });
+
+        let reader = conn.clone();
+        let task = tokio::spawn(async move { reader.read_file_lines("/tmp/a.txt", Some(3), None).await });
+
+        let request: JsonRpcRequest = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(request.method, method::FS_READ_TEXT_FILE);
+        let params = request.params.unwrap();
+        assert_eq!(params["path"], "/tmp/a.txt");
+        assert_eq!(params["line"], 3);
+        assert!(params.get("limit").is_none());
+
+        conn.complete(JsonRpcResponse {
+            jsonrpc: "2.0".to_string(),
+            id: request.id.unwrap(),
+            result: Some(serde_json::json!({"content": "hello"})),
+            error: None,
+        })
+        .await;
+
+        assert_eq!(task.await.unwrap().unwrap(), "hello");
+    }
+
+    #[tokio::test]
+    async fn test_failed_send_is_not_left_pending() {
+        let (conn, rx) = connection(ClientCapabilities::default());
+        drop(rx);
+        let err = conn.send_request(method::PING, serde_json::json!({})).await.unwrap_err();
+        assert!(matches!(err, AcpError::ChannelError(_)));
+        assert!(conn.pending_requests.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_notify() {
+        let (conn, mut rx) = connection(ClientCapabilities::default());
+        conn.notify("workspace/indexing_complete", serde_json::json!({"files": 42}))
+            .await
+            .unwrap();
+
+        let msg: Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!(msg["method"], "workspace/indexing_complete");
+        assert_eq!(msg["params"]["files"], 42);
+        assert!(msg.get("id").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_requires_client_capability() {
+        let (conn, _rx) = connection(ClientCapabilities::default());
+
+        let err = conn.read_file("/tmp/a.txt").await.unwrap_err();
+        assert!(matches!(err, AcpError::CapabilityNotSupported(c) if c == "text_files"));
+
+        let err = conn.apply_edit("/tmp/a.txt", "", None).await.unwrap_err();
+        assert!(matches!(err, AcpError::CapabilityNotSupported(c) if c == "apply_edit"));
+
+        let err = conn.read_binary_file("/tmp/a.png").await.unwrap_err();
+        assert!(matches!(err, AcpError::CapabilityNotSupported(c) if c == "binary_files"));
+
+        let err = conn.read_stream_start("/tmp/a.log", None).await.unwrap_err();
+        assert!(matches!(err, AcpError::CapabilityNotSupported(c) if c == "read_stream"));
+
+        let err = conn.create_terminal("/", "ls").await.unwrap_err();
+        assert!(matches!(err, AcpError::CapabilityNotSupported(c) if c == "terminal"));
+
+        let err = conn.list_directory("/", false, None).await.unwrap_err();
+        assert!(matches!(err, AcpError::CapabilityNotSupported(c) if c == "list_directory"));
+
+        let err = conn.glob("**/*.rs", "/", None).await.unwrap_err();
+        assert!(matches!(err, AcpError::CapabilityNotSupported(c) if c == "glob"));
+
+        let err = conn.grep("TODO", false, "/", None).await.unwrap_err();
+        assert!(matches!(err, AcpError::CapabilityNotSupported(c) if c == "grep"));
+
+        let err = conn.delete_path("/tmp/a.txt", false).await.unwrap_err();
+        assert!(matches!(err, AcpError::CapabilityNotSupported(c) if c == "manage_files"));
+
+        let params = SessionSamplingRequestParams {
+            server: "db".to_string(),
+            messages: Vec::new(),
+            system_prompt: None,
+            max_tokens: 100,
+            temperature: None,
+            stop_sequences: Vec::new(),
+            model_preferences: None,
+        };
+        let err = conn.request_sampling(params).await.unwrap_err();
+        assert!(matches!(err, AcpError::CapabilityNotSupported(c) if c == "sampling"));
+    }
+}
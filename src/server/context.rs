@@ -0,0 +1,94 @@
+//! Per-call context handed to agent session methods.
+
+use tokio_util::sync::CancellationToken;
+
+use super::connection::AgentConnection;
+use crate::mcp::McpServers;
+use super::queue::PromptQueues;
+use super::session::SessionManager;
+use super::tools::ToolRegistry;
+
+/// Context passed to [`Agent`](super::Agent) session methods.
+///
+/// Gives the agent access to server-managed state. Cloning is cheap.
+#[derive(Debug, Clone)]
+pub struct AgentContext {
+    sessions: SessionManager,
+    connection: AgentConnection,
+    tools: ToolRegistry,
+    queues: PromptQueues,
+    mcp: McpServers,
+    cancellation: CancellationToken,
+}
+
+impl AgentContext {
+    pub(crate) fn new(sessions: SessionManager, connection: AgentConnection) -> Self {
+        Self {
+            sessions,
+            connection,
+            tools: ToolRegistry::new(),
+            queues: PromptQueues::default(),
+            mcp: McpServers::new(),
+            cancellation: CancellationToken::new(),
+        }
+    }
+
+    pub(crate) fn with_tools(mut self, tools: ToolRegistry) -> Self {
+        self.tools = tools;
+        self
+    }
+
+    pub(crate) fn with_queues(mut self, queues: PromptQueues) -> Self {
+        self.queues = queues;
+        self
+    }
+
+    pub(crate) fn with_mcp(mut self, mcp: McpServers) -> Self {
+        self.mcp = mcp;
+        self
+    }
+
+    pub(crate) fn with_cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    /// The server's session registry.
+    pub fn sessions(&self) -> &SessionManager {
+        &self.sessions
+    }
+
+    /// Connection for calling back into the client (files, terminals, permissions).
+    pub fn connection(&self) -> &AgentConnection {
+        &self.connection
+    }
+
+    /// Tools registered with the server.
+    pub fn tools(&self) -> &ToolRegistry {
+        &self.tools
+    }
+
+    /// The editor's MCP servers, connected if the server was built with
+    /// [`Server::with_mcp`](super::Server::with_mcp).
+    pub fn mcp(&self) -> &McpServers {
+        &self.mcp
+    }
+
+    /// Number of prompts waiting behind the running one in `session_id`.
+    pub fn queued_prompts(&self, session_id: &str) -> usize {
+        self.queues.len(session_id)
+    }
+
+    /// Token tripped when the client sends `session/cancel` for the session
+    /// of the current prompt.
+    ///
+    /// Outside `session_prompt` this token is never cancelled.
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.cancellation
+    }
+
+    /// Check whether the current prompt has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+}
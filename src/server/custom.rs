@@ -0,0 +1,98 @@
+//! Vendor-specific methods declared with [`acp_methods`](super::acp_methods).
+//!
+//! The macro implements [`CustomMethods`] for the agent; installing it with
+//! [`Server::with_custom_methods`](super::Server::with_custom_methods) routes
+//! requests for those methods to the agent and lists them in the
+//! `custom_methods` capability.
+
+use serde_json::Value;
+use std::future::Future;
+use std::pin::Pin;
+
+use crate::protocol::*;
+
+/// Future returned by [`CustomMethods::call_custom_method`].
+pub type CustomMethodFuture<'a> = Pin<Box<dyn Future<Output = Option<AcpResult<Value>>> + Send + 'a>>;
+
+/// Dispatch for methods outside the ACP method set.
+///
+/// Normally generated by [`acp_methods`](super::acp_methods) rather than
+/// implemented by hand.
+pub trait CustomMethods: Send + Sync + 'static {
+    /// Names of the methods this type handles.
+    fn custom_method_names(&self) -> Vec<String>;
+
+    /// Handle `method`, or return `None` if it isn't one of ours.
+    fn call_custom_method<'a>(&'a self, method: &'a str, params: Value) -> CustomMethodFuture<'a>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::acp_methods;
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Deserialize)]
+    struct AddParams {
+        a: i64,
+        b: i64,
+    }
+
+    #[derive(Serialize)]
+    struct AddResult {
+        sum: i64,
+    }
+
+    struct Calculator {
+        offset: i64,
+    }
+
+    #[acp_methods]
+    impl Calculator {
+        #[acp_method("calc/add")]
+        async fn add(&self, params: AddParams) -> AcpResult<AddResult> {
+            Ok(AddResult {
+                sum: params.a + params.b + self.offset,
+            })
+        }
+
+        #[acp_method("calc/fail")]
+        async fn fail(&self, _params: Value) -> AcpResult<Value> {
+            Err(AcpError::InvalidState("nope".to_string()))
+        }
+
+        fn unrelated(&self) -> i64 {
+            self.offset
+        }
+    }
+
+    #[tokio::test]
+    async fn test_generated_dispatch() {
+        let calc = Calculator { offset: 1 };
+        assert_eq!(calc.custom_method_names(), vec!["calc/add", "calc/fail"]);
+        assert_eq!(calc.unrelated(), 1);
+
+        let result = calc
+            .call_custom_method("calc/add", serde_json::json!({"a": 2, "b": 3}))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(result, serde_json::json!({"sum": 6}));
+
+        let err = calc
+            .call_custom_method("calc/add", serde_json::json!({"a": "two"}))
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_PARAMS);
+
+        let err = calc
+            .call_custom_method("calc/fail", Value::Null)
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_STATE);
+
+        assert!(calc.call_custom_method("calc/sub", Value::Null).await.is_none());
+    }
+}
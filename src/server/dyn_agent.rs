@@ -0,0 +1,160 @@
+//! Running agents chosen at runtime.
+//!
+//! `Agent` is object safe, and boxed or shared trait objects are agents too,
+//! so a host can pick an implementation from flags or config:
+//!
+//! ```rust,no_run
+//! # use heroacp::server::{Agent, Server};
+//! # fn demo(fast: impl Agent, thorough: impl Agent, use_fast: bool) {
+//! let agent: Box<dyn Agent> = if use_fast { Box::new(fast) } else { Box::new(thorough) };
+//! let server = Server::new(agent);
+//! # }
+//! ```
+
+use async_trait::async_trait;
+use serde_json::Value;
+use std::sync::Arc;
+
+use super::{Agent, AgentContext, SessionHandle};
+use crate::protocol::*;
+
+/// Forward every `Agent` method to the pointee.
+macro_rules! forward_agent {
+    ($pointer:ty) => {
+        #[async_trait]
+        impl Agent for $pointer {
+            async fn initialize(&self, params: InitializeParams) -> AcpResult<InitializeResult> {
+                (**self).initialize(params).await
+            }
+
+            async fn authenticate(&self, params: AuthenticateParams) -> AcpResult<AuthenticateResult> {
+                (**self).authenticate(params).await
+            }
+
+            async fn session_new(
+                &self,
+                params: SessionNewParams,
+                ctx: AgentContext,
+            ) -> AcpResult<SessionNewResult> {
+                (**self).session_new(params, ctx).await
+            }
+
+            async fn session_load(
+                &self,
+                params: SessionLoadParams,
+                ctx: AgentContext,
+            ) -> AcpResult<SessionLoadResult> {
+                (**self).session_load(params, ctx).await
+            }
+
+            async fn session_prompt(
+                &self,
+                params: SessionPromptParams,
+                session: SessionHandle,
+                ctx: AgentContext,
+            ) -> AcpResult<SessionPromptResult> {
+                (**self).session_prompt(params, session, ctx).await
+            }
+
+            async fn session_cancel(&self, params: SessionCancelParams, ctx: AgentContext) -> AcpResult<()> {
+                (**self).session_cancel(params, ctx).await
+            }
+
+            async fn session_set_model(&self, params: SessionSetModelParams, ctx: AgentContext) -> AcpResult<()> {
+                (**self).session_set_model(params, ctx).await
+            }
+
+            async fn handle_custom(&self, method: &str, params: Value) -> AcpResult<Value> {
+                (**self).handle_custom(method, params).await
+            }
+
+            async fn on_client_connected(&self, client: ClientInfo) -> AcpResult<()> {
+                (**self).on_client_connected(client).await
+            }
+
+            async fn on_client_disconnected(&self) {
+                (**self).on_client_disconnected().await
+            }
+
+            async fn on_shutdown(&self) {
+                (**self).on_shutdown().await
+            }
+        }
+    };
+}
+
+forward_agent!(Box<dyn Agent>);
+forward_agent!(Arc<dyn Agent>);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fixtures::init_result;
+    use crate::testing::{initialize_params, ServerTester};
+
+    struct Named(&'static str);
+
+    #[async_trait]
+    impl Agent for Named {
+        async fn initialize(&self, _params: InitializeParams) -> AcpResult<InitializeResult> {
+            Ok(init_result(self.0))
+        }
+
+        async fn session_new(
+            &self,
+            params: SessionNewParams,
+            _ctx: AgentContext,
+        ) -> AcpResult<SessionNewResult> {
+            Ok(SessionNewResult {
+                session_id: params.session_id,
+            })
+        }
+
+        async fn session_prompt(
+            &self,
+            _params: SessionPromptParams,
+            session: SessionHandle,
+            _ctx: AgentContext,
+        ) -> AcpResult<SessionPromptResult> {
+            session.send_message_chunk(self.0).await?;
+            Ok(SessionPromptResult {
+                status: "ok".to_string(),
+            })
+        }
+
+        async fn handle_custom(&self, method: &str, _params: Value) -> AcpResult<Value> {
+            Ok(Value::String(format!("{} handled {}", self.0, method)))
+        }
+    }
+
+    fn choose(name: &'static str) -> Box<dyn Agent> {
+        Box::new(Named(name))
+    }
+
+    #[tokio::test]
+    async fn test_boxed_agent() {
+        let tester = ServerTester::new(choose("beta"));
+        let result = tester.initialize(initialize_params()).await.unwrap();
+        assert_eq!(result.agent_info.name, "beta");
+        tester.session_new("s1").await.unwrap();
+
+        let (result, updates) = tester.prompt("s1", vec![]).await;
+        result.unwrap();
+        assert!(matches!(
+            &updates[0].update_type,
+            SessionUpdateType::AgentMessageChunk { text } if text == "beta"
+        ));
+
+        let value = tester.request("beta/ping", Value::Null).await.unwrap();
+        assert_eq!(value, "beta handled beta/ping");
+    }
+
+    #[tokio::test]
+    async fn test_shared_agent() {
+        let agent: Arc<dyn Agent> = Arc::new(Named("alpha"));
+        let tester = ServerTester::new(agent.clone());
+        let result = tester.initialize(initialize_params()).await.unwrap();
+        assert_eq!(result.agent_info.name, "alpha");
+        assert_eq!(Arc::strong_count(&agent), 2);
+    }
+}
@@ -0,0 +1,179 @@
+//! Sending updates for a single session.
+
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+use crate::protocol::*;
+
+/// Sends `session/update` notifications for one session.
+///
+/// Passed to [`Agent::session_prompt`](super::Agent::session_prompt); every
+/// update it sends is tagged with the prompt's session ID. Cloning is cheap.
+#[derive(Debug, Clone)]
+pub struct SessionHandle {
+    session_id: String,
+    updates: mpsc::Sender<SessionUpdate>,
+}
+
+impl SessionHandle {
+    /// Create a handle that sends updates for `session_id` into `updates`.
+    ///
+    /// The server creates handles itself; this is mainly useful for testing agents.
+    pub fn new(session_id: impl Into<String>, updates: mpsc::Sender<SessionUpdate>) -> Self {
+        Self {
+            session_id: session_id.into(),
+            updates,
+        }
+    }
+
+    /// The session this handle sends updates for.
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// Send an update for this session.
+    pub async fn send(&self, update_type: SessionUpdateType) -> AcpResult<()> {
+        self.updates
+            .send(SessionUpdate {
+                session_id: self.session_id.clone(),
+                update_type,
+            })
+            .await
+            .map_err(|e| AcpError::ChannelError(e.to_string()))
+    }
+
+    /// Stream a chunk of the agent's reply.
+    pub async fn send_message_chunk(&self, text: impl Into<String>) -> AcpResult<()> {
+        self.send(SessionUpdateType::AgentMessageChunk { text: text.into() })
+            .await
+    }
+
+    /// Stream a chunk of the agent's reasoning.
+    pub async fn send_thought(&self, text: impl Into<String>) -> AcpResult<()> {
+        self.send(SessionUpdateType::AgentThoughtChunk { text: text.into() })
+            .await
+    }
+
+    /// Stream a chunk of audio.
+    pub async fn send_audio(&self, chunk: AudioChunk) -> AcpResult<()> {
+        self.send(SessionUpdateType::AudioChunk(chunk)).await
+    }
+
+    /// Send the agent's current plan.
+    pub async fn send_plan(&self, plan: Plan) -> AcpResult<()> {
+        self.send(SessionUpdateType::Plan(plan)).await
+    }
+
+    /// Announce a tool call. Returns the generated tool call ID.
+    pub async fn start_tool_call(
+        &self,
+        name: impl Into<String>,
+        arguments: Value,
+    ) -> AcpResult<String> {
+        let id = format!("tool_{}", uuid::Uuid::new_v4());
+        self.send(SessionUpdateType::ToolCall(ToolCall {
+            id: id.clone(),
+            name: name.into(),
+            arguments,
+        }))
+        .await?;
+        Ok(id)
+    }
+
+    /// Send an update for a tool call started with [`start_tool_call`](Self::start_tool_call).
+    pub async fn update_tool_call(&self, update: ToolCallUpdate) -> AcpResult<()> {
+        self.send(SessionUpdateType::ToolCallUpdate(update)).await
+    }
+
+    /// Report that a tool call finished with `result`.
+    pub async fn complete_tool_call(&self, id: impl Into<String>, result: Value) -> AcpResult<()> {
+        self.update_tool_call(ToolCallUpdate {
+            id: id.into(),
+            status: ToolCallStatus::Completed,
+            result: Some(result),
+            error: None,
+            content: vec![],
+        })
+        .await
+    }
+
+    /// Report that a tool call failed.
+    pub async fn fail_tool_call(
+        &self,
+        id: impl Into<String>,
+        error: impl Into<String>,
+    ) -> AcpResult<()> {
+        self.update_tool_call(ToolCallUpdate {
+            id: id.into(),
+            status: ToolCallStatus::Failed,
+            result: None,
+            error: Some(error.into()),
+            content: vec![],
+        })
+        .await
+    }
+
+    /// Announce a mode change.
+    pub async fn send_mode_change(&self, mode: impl Into<String>) -> AcpResult<()> {
+        self.send(SessionUpdateType::ModeChange { mode: mode.into() })
+            .await
+    }
+
+    /// Announce that the session runs on another model, e.g. after falling
+    /// back from one that failed.
+    pub async fn send_model_change(&self, model: impl Into<String>) -> AcpResult<()> {
+        self.send(SessionUpdateType::ModelChange { model: model.into() })
+            .await
+    }
+
+    /// Report the agent's activity state.
+    pub async fn send_status(&self, state: AgentState) -> AcpResult<()> {
+        self.send(SessionUpdateType::Status { state }).await
+    }
+
+    /// Signal that the agent is done with its response.
+    pub async fn send_done(&self) -> AcpResult<()> {
+        self.send(SessionUpdateType::Done).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_updates_are_scoped_to_session() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let session = SessionHandle::new("s1", tx);
+
+        session.send_message_chunk("hello").await.unwrap();
+        let id = session
+            .start_tool_call("read_file", serde_json::json!({"path": "/a"}))
+            .await
+            .unwrap();
+        session.fail_tool_call(&id, "not found").await.unwrap();
+        session.send_done().await.unwrap();
+
+        let updates: Vec<SessionUpdate> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        assert_eq!(updates.len(), 4);
+        assert!(updates.iter().all(|u| u.session_id == "s1"));
+        assert!(matches!(
+            &updates[0].update_type,
+            SessionUpdateType::AgentMessageChunk { text } if text == "hello"
+        ));
+        let SessionUpdateType::ToolCallUpdate(update) = &updates[2].update_type else {
+            panic!("expected tool_call_update");
+        };
+        assert_eq!(update.id, id);
+        assert!(matches!(update.status, ToolCallStatus::Failed));
+        assert!(matches!(updates[3].update_type, SessionUpdateType::Done));
+    }
+
+    #[tokio::test]
+    async fn test_send_fails_when_closed() {
+        let (tx, rx) = mpsc::channel(1);
+        drop(rx);
+        let err = SessionHandle::new("s1", tx).send_done().await.unwrap_err();
+        assert!(matches!(err, AcpError::ChannelError(_)));
+    }
+}
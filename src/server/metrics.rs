@@ -0,0 +1,224 @@
+//! Prometheus metrics for a server.
+//!
+//! Enabled with the `metrics` feature. Attach a [`Metrics`] handle with
+//! [`Server::with_metrics`](super::Server::with_metrics) and either scrape it
+//! yourself with [`Metrics::encode`] or serve it over HTTP:
+//!
+//! ```rust,no_run
+//! # use heroacp::server::{Agent, Metrics, Server};
+//! # async fn demo(agent: impl Agent) -> heroacp::protocol::AcpResult<()> {
+//! let metrics = Metrics::new();
+//! metrics.serve("127.0.0.1:9464").await?;
+//! Server::new(agent).with_metrics(metrics).run().await
+//! # }
+//! ```
+//!
+//! Recorded are:
+//!
+//! - `acp_requests_total{method}`: requests and notifications received
+//! - `acp_errors_total{code}`: requests that failed, by JSON-RPC error code
+//! - `acp_active_sessions`: sessions the server knows
+//! - `acp_update_queue_depth`: session updates waiting to be written
+//! - `acp_prompt_duration_seconds`: time to answer a prompt
+
+use prometheus::{Encoder, Histogram, HistogramOpts, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+use std::net::SocketAddr;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, ToSocketAddrs};
+
+use crate::protocol::*;
+
+/// Upper bounds, in seconds, of the prompt latency buckets.
+const PROMPT_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0];
+
+/// Label for methods outside the ACP method set and the agent's own custom
+/// methods, so a client can't create a series per made-up name.
+pub(crate) const OTHER_METHOD: &str = "other";
+
+/// Handle to a server's metrics.
+///
+/// Cloning is cheap; all clones record into the same [`Registry`], which
+/// can hold metrics of the application's own too.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    errors: IntCounterVec,
+    active_sessions: IntGauge,
+    update_queue_depth: IntGauge,
+    prompt_duration: Histogram,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    /// Metrics in a registry of their own.
+    pub fn new() -> Self {
+        Self::with_registry(Registry::new())
+    }
+
+    /// Metrics in `registry`, e.g. to expose them next to others.
+    ///
+    /// # Panics
+    ///
+    /// If `registry` already holds metrics of the same names.
+    pub fn with_registry(registry: Registry) -> Self {
+        let requests = IntCounterVec::new(
+            Opts::new("acp_requests_total", "ACP requests and notifications received"),
+            &["method"],
+        )
+        .unwrap();
+        let errors = IntCounterVec::new(
+            Opts::new("acp_errors_total", "ACP requests that failed, by JSON-RPC error code"),
+            &["code"],
+        )
+        .unwrap();
+        let active_sessions = IntGauge::new("acp_active_sessions", "Sessions the server knows").unwrap();
+        let update_queue_depth =
+            IntGauge::new("acp_update_queue_depth", "Session updates waiting to be written").unwrap();
+        let prompt_duration = Histogram::with_opts(
+            HistogramOpts::new("acp_prompt_duration_seconds", "Time to answer a prompt")
+                .buckets(PROMPT_BUCKETS.to_vec()),
+        )
+        .unwrap();
+
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry.register(Box::new(errors.clone())).unwrap();
+        registry.register(Box::new(active_sessions.clone())).unwrap();
+        registry.register(Box::new(update_queue_depth.clone())).unwrap();
+        registry.register(Box::new(prompt_duration.clone())).unwrap();
+        Self {
+            registry,
+            requests,
+            errors,
+            active_sessions,
+            update_queue_depth,
+            prompt_duration,
+        }
+    }
+
+    /// The registry the metrics are in.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Everything in the registry, in the Prometheus text format.
+    pub fn encode(&self) -> String {
+        let mut buffer = Vec::new();
+        // Writing to a Vec only fails for malformed metrics, which ours aren't
+        let _ = TextEncoder::new().encode(&self.registry.gather(), &mut buffer);
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+
+    /// Serve [`encode`](Self::encode) at `/metrics` over HTTP on `addr`,
+    /// returning the address bound.
+    ///
+    /// The listener runs on its own task until the runtime shuts down.
+    pub async fn serve(&self, addr: impl ToSocketAddrs) -> AcpResult<SocketAddr> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        let metrics = self.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let metrics = metrics.clone();
+                tokio::spawn(async move {
+                    let mut stream = BufReader::new(stream);
+                    let mut request_line = String::new();
+                    if stream.read_line(&mut request_line).await.is_err() {
+                        return;
+                    }
+                    // Skip the headers; nothing in them changes the answer
+                    let mut header = String::new();
+                    while stream.read_line(&mut header).await.is_ok_and(|n| n > 2) {
+                        header.clear();
+                    }
+                    let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+                    let (status, body) = match path {
+                        "/metrics" => ("200 OK", metrics.encode()),
+                        _ => ("404 Not Found", String::new()),
+                    };
+                    let response = format!(
+                        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        status,
+                        body.len(),
+                        body
+                    );
+                    let _ = stream.get_mut().write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        Ok(local_addr)
+    }
+
+    /// Count a request for `method`, labelled as [`OTHER_METHOD`] by the
+    /// caller if it isn't one the server knows.
+    pub(crate) fn record_request(&self, method: &str) {
+        self.requests.with_label_values(&[method]).inc();
+    }
+
+    pub(crate) fn record_error(&self, code: i32) {
+        self.errors.with_label_values(&[&code.to_string()]).inc();
+    }
+
+    pub(crate) fn record_prompt(&self, duration: std::time::Duration) {
+        self.prompt_duration.observe(duration.as_secs_f64());
+    }
+
+    pub(crate) fn set_active_sessions(&self, sessions: usize) {
+        self.active_sessions.set(sessions as i64);
+    }
+
+    pub(crate) fn set_update_queue_depth(&self, depth: usize) {
+        self.update_queue_depth.set(depth as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::Server;
+    use crate::testing::{initialize_params, MockAgent, Scenario, ServerTester};
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpStream;
+
+    #[tokio::test]
+    async fn test_records_requests() {
+        let metrics = Metrics::new();
+        let server = Server::new(MockAgent::new(Scenario::default()).unwrap()).with_metrics(metrics.clone());
+        let tester = ServerTester::from_server(server);
+        tester.initialize(initialize_params()).await.unwrap();
+        tester.session_new("s1").await.unwrap();
+        tester.session_new("s2").await.unwrap();
+        let (result, _) = tester.prompt("s1", vec![ContentBlock::text("hi")]).await;
+        result.unwrap();
+        tester.request("made/up", serde_json::json!({})).await.unwrap_err();
+
+        let text = metrics.encode();
+        assert!(text.contains("acp_requests_total{method=\"session/new\"} 2"), "{}", text);
+        assert!(text.contains("acp_requests_total{method=\"other\"} 1"), "{}", text);
+        assert!(text.contains("acp_errors_total{code=\"-32601\"} 1"), "{}", text);
+        assert!(text.contains("acp_active_sessions 2"), "{}", text);
+        assert!(text.contains("acp_prompt_duration_seconds_count 1"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let metrics = Metrics::new();
+        metrics.record_request("ping");
+        let addr = metrics.serve("127.0.0.1:0").await.unwrap();
+
+        for (path, status, body) in [("/metrics", "200 OK", "acp_requests_total"), ("/", "404 Not Found", "")] {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            assert!(response.starts_with(&format!("HTTP/1.1 {}", status)), "{}", response);
+            assert!(response.contains(body));
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! Middleware for intercepting requests before they reach the agent.
+//!
+//! Layers are added with [`Server::layer`](super::Server::layer) and run in
+//! the order they were added. Each layer sees the parsed request and can
+//! observe it, rewrite it, or answer it without calling the rest of the stack.
+//!
+//! ```rust
+//! use async_trait::async_trait;
+//! use heroacp::protocol::*;
+//! use heroacp::server::{Middleware, Next};
+//! use serde_json::Value;
+//!
+//! /// Reject everything except `initialize` until the client authenticates.
+//! struct RequireToken;
+//!
+//! #[async_trait]
+//! impl Middleware for RequireToken {
+//!     async fn handle(&self, request: JsonRpcRequest, next: Next<'_>) -> AcpResult<Value> {
+//!         if request.method == method::SESSION_PROMPT {
+//!             return Err(AcpError::PermissionDenied("not authenticated".to_string()));
+//!         }
+//!         next.run(request).await
+//!     }
+//! }
+//! ```
+
+use async_trait::async_trait;
+use serde_json::Value;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use crate::protocol::*;
+
+/// Future returned by the innermost handler of a middleware stack.
+pub(crate) type EndpointFuture = Pin<Box<dyn Future<Output = AcpResult<Value>> + Send>>;
+
+/// The handler that runs once every layer has called [`Next::run`].
+pub(crate) type Endpoint = dyn Fn(JsonRpcRequest) -> EndpointFuture + Send + Sync;
+
+/// A layer in the server's middleware stack.
+///
+/// Notifications pass through the stack too; their `id` is `None` and the
+/// returned value is discarded.
+#[async_trait]
+pub trait Middleware: Send + Sync + 'static {
+    /// Handle a request, calling `next.run(request)` to continue down the stack.
+    async fn handle(&self, request: JsonRpcRequest, next: Next<'_>) -> AcpResult<Value>;
+}
+
+/// The remainder of the middleware stack.
+pub struct Next<'a> {
+    layers: &'a [Arc<dyn Middleware>],
+    endpoint: &'a Endpoint,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(layers: &'a [Arc<dyn Middleware>], endpoint: &'a Endpoint) -> Self {
+        Self { layers, endpoint }
+    }
+
+    /// Pass the request to the next layer, or to the agent if this was the last one.
+    pub async fn run(self, request: JsonRpcRequest) -> AcpResult<Value> {
+        match self.layers.split_first() {
+            Some((layer, rest)) => layer.handle(request, Next::new(rest, self.endpoint)).await,
+            None => (self.endpoint)(request).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    struct Record(Arc<Mutex<Vec<String>>>, &'static str);
+
+    #[async_trait]
+    impl Middleware for Record {
+        async fn handle(&self, request: JsonRpcRequest, next: Next<'_>) -> AcpResult<Value> {
+            self.0.lock().unwrap().push(format!("{}:{}", self.1, request.method));
+            next.run(request).await
+        }
+    }
+
+    struct Rename;
+
+    #[async_trait]
+    impl Middleware for Rename {
+        async fn handle(&self, mut request: JsonRpcRequest, next: Next<'_>) -> AcpResult<Value> {
+            request.method = method::PING.to_string();
+            next.run(request).await
+        }
+    }
+
+    struct Deny;
+
+    #[async_trait]
+    impl Middleware for Deny {
+        async fn handle(&self, _request: JsonRpcRequest, _next: Next<'_>) -> AcpResult<Value> {
+            Err(AcpError::PermissionDenied("denied".to_string()))
+        }
+    }
+
+    fn request(method: &str) -> JsonRpcRequest {
+        JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: Some(RequestId::Number(1)),
+            method: method.to_string(),
+            params: None,
+        }
+    }
+
+    fn echo_endpoint() -> Box<Endpoint> {
+        Box::new(|request: JsonRpcRequest| -> EndpointFuture {
+            Box::pin(async move { Ok(Value::String(request.method)) })
+        })
+    }
+
+    #[tokio::test]
+    async fn test_layers_run_in_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let layers: Vec<Arc<dyn Middleware>> = vec![
+            Arc::new(Record(log.clone(), "outer")),
+            Arc::new(Rename),
+            Arc::new(Record(log.clone(), "inner")),
+        ];
+        let endpoint = echo_endpoint();
+
+        let result = Next::new(&layers, endpoint.as_ref())
+            .run(request(method::SESSION_NEW))
+            .await
+            .unwrap();
+
+        assert_eq!(result, Value::String("ping".to_string()));
+        assert_eq!(*log.lock().unwrap(), vec!["outer:session/new", "inner:ping"]);
+    }
+
+    #[tokio::test]
+    async fn test_layer_short_circuits() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let layers: Vec<Arc<dyn Middleware>> =
+            vec![Arc::new(Deny), Arc::new(Record(log.clone(), "after"))];
+        let endpoint = echo_endpoint();
+
+        let err = Next::new(&layers, endpoint.as_ref())
+            .run(request(method::SESSION_PROMPT))
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.code(), codes::PERMISSION_DENIED);
+        assert!(log.lock().unwrap().is_empty());
+    }
+}
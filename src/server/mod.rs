@@ -6,10 +6,9 @@
 //! # Example
 //!
 //! ```rust,no_run
-//! use heroacp::server::{Agent, Server};
+//! use heroacp::server::{Agent, AgentContext, Server, SessionHandle};
 //! use heroacp::protocol::*;
 //! use async_trait::async_trait;
-//! use tokio::sync::mpsc;
 //!
 //! struct MyAgent;
 //!
@@ -32,6 +31,7 @@
 //!     async fn session_new(
 //!         &self,
 //!         params: SessionNewParams,
+//!         ctx: AgentContext,
 //!     ) -> AcpResult<SessionNewResult> {
 //!         Ok(SessionNewResult {
 //!             session_id: params.session_id,
@@ -41,8 +41,11 @@
 //!     async fn session_prompt(
 //!         &self,
 //!         params: SessionPromptParams,
-//!         update_tx: mpsc::Sender<SessionUpdate>,
+//!         session: SessionHandle,
+//!         ctx: AgentContext,
 //!     ) -> AcpResult<SessionPromptResult> {
+//!         session.send_message_chunk("Hello!").await?;
+//!         session.send_done().await?;
 //!         Ok(SessionPromptResult {
 //!             status: "ok".to_string(),
 //!         })
@@ -54,10 +57,54 @@ use async_trait::async_trait;
 use serde_json::Value;
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::sync::{mpsc, oneshot, Mutex};
+use std::time::Duration;
+use tokio::io::{self, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{mpsc, watch, Mutex, RwLock};
+use tracing::Instrument;
 
+use crate::mcp::{Credentials, McpServers, McpSupervision};
 use crate::protocol::*;
+use capabilities::{enforce_prompt, enforce_update, ContentSupport};
+use ordering::{request_session, ResponseOrder};
+use queue::{Entry, PromptQueues};
+use rate_limit::TokenBucket;
+use stream::PromptStreams;
+
+mod capabilities;
+pub mod client_requests;
+mod connection;
+mod context;
+mod custom;
+mod dyn_agent;
+mod handle;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod middleware;
+mod ordering;
+mod queue;
+mod rate_limit;
+mod router;
+mod session;
+mod status;
+mod stream;
+mod tools;
+mod transcript;
+
+pub use capabilities::CapabilityPolicy;
+pub use connection::AgentConnection;
+pub use context::AgentContext;
+pub use custom::{CustomMethodFuture, CustomMethods};
+pub use heroacp_macros::{acp_method, acp_methods};
+pub use handle::SessionHandle;
+#[cfg(feature = "metrics")]
+pub use metrics::Metrics;
+pub use middleware::{Middleware, Next};
+pub use rate_limit::RateLimit;
+pub use router::AgentRouter;
+pub use session::{SessionInfo, SessionManager, SessionState};
+pub use tools::{validate_arguments, ToolRegistry};
+pub use transcript::{Direction, Transcript};
+pub use tokio_util::sync::CancellationToken;
 
 /// Trait for implementing an ACP agent.
 ///
@@ -79,12 +126,23 @@ pub trait Agent: Send + Sync + 'static {
     }
 
     /// Handle creating a new session.
-    async fn session_new(&self, params: SessionNewParams) -> AcpResult<SessionNewResult>;
+    ///
+    /// The server records the session once this returns successfully.
+    async fn session_new(
+        &self,
+        params: SessionNewParams,
+        ctx: AgentContext,
+    ) -> AcpResult<SessionNewResult>;
 
     /// Handle loading an existing session.
     ///
-    /// Override this to support session persistence.
-    async fn session_load(&self, params: SessionLoadParams) -> AcpResult<SessionLoadResult> {
+    /// Override this to support session persistence. The server records the
+    /// session if `loaded` is true.
+    async fn session_load(
+        &self,
+        params: SessionLoadParams,
+        _ctx: AgentContext,
+    ) -> AcpResult<SessionLoadResult> {
         Ok(SessionLoadResult {
             session_id: params.session_id,
             loaded: false,
@@ -93,24 +151,146 @@ pub trait Agent: Send + Sync + 'static {
 
     /// Handle a prompt from the user.
     ///
-    /// Use the `update_tx` channel to send streaming updates back to the client.
+    /// Use `session` to stream updates back to the client.
+    /// Prompts for unknown sessions are rejected before reaching the agent.
+    /// Watch [`AgentContext::cancellation_token`] to stop work when the client
+    /// cancels the prompt.
     async fn session_prompt(
         &self,
         params: SessionPromptParams,
-        update_tx: mpsc::Sender<SessionUpdate>,
+        session: SessionHandle,
+        ctx: AgentContext,
     ) -> AcpResult<SessionPromptResult>;
 
     /// Handle cancellation of the current operation.
-    async fn session_cancel(&self, _params: SessionCancelParams) -> AcpResult<()> {
+    async fn session_cancel(
+        &self,
+        _params: SessionCancelParams,
+        _ctx: AgentContext,
+    ) -> AcpResult<()> {
+        Ok(())
+    }
+
+    /// Handle switching the model of a session.
+    ///
+    /// Override this along with listing the models in
+    /// [`AgentCapabilities::models`]. Requests for unknown sessions and, if
+    /// models are listed, for unlisted models are rejected before reaching
+    /// the agent; the server records the new model once this succeeds.
+    async fn session_set_model(
+        &self,
+        _params: SessionSetModelParams,
+        _ctx: AgentContext,
+    ) -> AcpResult<()> {
+        Err(AcpError::MethodNotFound(method::SESSION_SET_MODEL.to_string()))
+    }
+
+    /// Handle a request for a method outside the ACP method set.
+    ///
+    /// Called for methods the library doesn't know and that aren't
+    /// `#[acp_method]`s. This may run before `initialize`; the default
+    /// rejects everything with `METHOD_NOT_FOUND`.
+    async fn handle_custom(&self, method: &str, _params: Value) -> AcpResult<Value> {
+        Err(AcpError::MethodNotFound(method.to_string()))
+    }
+
+    /// Called after `initialize` succeeds.
+    ///
+    /// Allocate per-connection resources (model clients, caches, MCP
+    /// connections) here. An error fails the `initialize` request.
+    async fn on_client_connected(&self, _client: ClientInfo) -> AcpResult<()> {
         Ok(())
     }
+
+    /// Called when an initialized client closes the connection.
+    ///
+    /// Running prompts have already been cancelled.
+    async fn on_client_disconnected(&self) {}
+
+    /// Called once when the server stops, after any disconnect hook.
+    async fn on_shutdown(&self) {}
+}
+
+/// Where the connection is in the protocol lifecycle.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Waiting for `initialize`.
+    #[default]
+    Uninitialized,
+    /// `initialize` succeeded; sessions may be created.
+    Initialized,
+    /// The server has shut down, on request or because the client left.
+    ShutDown,
 }
 
 /// ACP server that runs an agent.
 pub struct Server<A: Agent> {
     agent: Arc<A>,
-    pending_requests: Arc<Mutex<HashMap<String, oneshot::Sender<JsonRpcResponse>>>>,
-    next_request_id: Arc<Mutex<u64>>,
+    client_capabilities: Arc<RwLock<ClientCapabilities>>,
+    agent_capabilities: Arc<RwLock<AgentCapabilities>>,
+    working_directory: Arc<RwLock<String>>,
+    sessions: SessionManager,
+    prompt_queues: PromptQueues,
+    active_prompts: Arc<Mutex<HashMap<String, CancellationToken>>>,
+    prompt_streams: PromptStreams,
+    layers: Arc<Vec<Arc<dyn Middleware>>>,
+    timeouts: Arc<HashMap<Method, Duration>>,
+    tools: ToolRegistry,
+    custom_methods: Option<Arc<dyn CustomMethods>>,
+    mcp: Option<McpServers>,
+    mcp_tools: bool,
+    mcp_supervision: Option<McpSupervision>,
+    mcp_sampling: bool,
+    capability_policy: CapabilityPolicy,
+    state: Arc<RwLock<ConnectionState>>,
+    /// The connection being served, for the deprecated [`Server::send_request`].
+    served: Arc<RwLock<Option<AgentConnection>>>,
+    state_checks: bool,
+    update_bandwidth: Option<u64>,
+    transcript: Transcript,
+    signal_handling: bool,
+    drain_timeout: Duration,
+    ordered_responses: bool,
+    session_stats: bool,
+    started: tokio::time::Instant,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Metrics>,
+}
+
+impl<A: Agent> Clone for Server<A> {
+    fn clone(&self) -> Self {
+        Self {
+            agent: self.agent.clone(),
+            client_capabilities: self.client_capabilities.clone(),
+            agent_capabilities: self.agent_capabilities.clone(),
+            working_directory: self.working_directory.clone(),
+            sessions: self.sessions.clone(),
+            prompt_queues: self.prompt_queues.clone(),
+            active_prompts: self.active_prompts.clone(),
+            prompt_streams: self.prompt_streams.clone(),
+            layers: self.layers.clone(),
+            timeouts: self.timeouts.clone(),
+            tools: self.tools.clone(),
+            custom_methods: self.custom_methods.clone(),
+            mcp: self.mcp.clone(),
+            mcp_tools: self.mcp_tools,
+            mcp_supervision: self.mcp_supervision.clone(),
+            mcp_sampling: self.mcp_sampling,
+            capability_policy: self.capability_policy,
+            state: self.state.clone(),
+            served: self.served.clone(),
+            state_checks: self.state_checks,
+            update_bandwidth: self.update_bandwidth,
+            transcript: self.transcript.clone(),
+            signal_handling: self.signal_handling,
+            drain_timeout: self.drain_timeout,
+            ordered_responses: self.ordered_responses,
+            session_stats: self.session_stats,
+            started: self.started,
+            #[cfg(feature = "metrics")]
+            metrics: self.metrics.clone(),
+        }
+    }
 }
 
 impl<A: Agent> Server<A> {
@@ -118,28 +298,409 @@ impl<A: Agent> Server<A> {
     pub fn new(agent: A) -> Self {
         Self {
             agent: Arc::new(agent),
-            pending_requests: Arc::new(Mutex::new(HashMap::new())),
-            next_request_id: Arc::new(Mutex::new(1)),
+            client_capabilities: Arc::new(RwLock::new(ClientCapabilities::default())),
+            agent_capabilities: Arc::new(RwLock::new(AgentCapabilities::default())),
+            working_directory: Arc::new(RwLock::new(String::new())),
+            sessions: SessionManager::new(),
+            prompt_queues: PromptQueues::default(),
+            active_prompts: Arc::new(Mutex::new(HashMap::new())),
+            prompt_streams: PromptStreams::default(),
+            layers: Arc::new(Vec::new()),
+            timeouts: Arc::new(default_timeouts()),
+            tools: ToolRegistry::new(),
+            custom_methods: None,
+            mcp: None,
+            mcp_tools: false,
+            mcp_supervision: None,
+            mcp_sampling: false,
+            capability_policy: CapabilityPolicy::default(),
+            state: Arc::new(RwLock::new(ConnectionState::default())),
+            served: Arc::new(RwLock::new(None)),
+            state_checks: true,
+            update_bandwidth: None,
+            transcript: Transcript::new(),
+            signal_handling: true,
+            drain_timeout: Duration::from_secs(5),
+            ordered_responses: false,
+            session_stats: false,
+            started: tokio::time::Instant::now(),
+            #[cfg(feature = "metrics")]
+            metrics: None,
+        }
+    }
+
+    /// Enable or disable graceful shutdown on SIGINT/SIGTERM.
+    ///
+    /// When enabled (the default), [`Server::run`] cancels running prompts on
+    /// Ctrl+C or SIGTERM, waits for their responses while still passing them
+    /// the client's answers, sends a final `done` update for each session and
+    /// then returns. Disable this if
+    /// the application handles signals itself.
+    pub fn with_signal_handling(mut self, enabled: bool) -> Self {
+        self.signal_handling = enabled;
+        self
+    }
+
+    /// Set how long shutdown waits for running prompts and queued messages.
+    ///
+    /// Defaults to 5 seconds.
+    pub fn with_drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// Write responses within a session in the order the requests arrived.
+    ///
+    /// Requests still run concurrently, but a response is held back until
+    /// all earlier requests naming the same `session_id` have been answered.
+    /// Such responses carry a per-session `seq` number starting at 1. Off by
+    /// default.
+    pub fn with_ordered_responses(mut self, enabled: bool) -> Self {
+        self.ordered_responses = enabled;
+        self
+    }
+
+    /// Use `transcript` to record the wire traffic.
+    ///
+    /// Only needed to customize redaction; every server already has a
+    /// transcript that can be started through [`Server::transcript`].
+    pub fn with_transcript(mut self, transcript: Transcript) -> Self {
+        self.transcript = transcript;
+        self
+    }
+
+    /// The transcript recorder. Start or stop it at any time, including
+    /// while the server runs.
+    pub fn transcript(&self) -> &Transcript {
+        &self.transcript
+    }
+
+    /// Apply rate limits to the client.
+    ///
+    /// Request limits are enforced by a middleware layer added at this point
+    /// in the stack; the update bandwidth limit throttles session updates.
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.update_bandwidth = limit.update_bandwidth();
+        self.layer(limit)
+    }
+
+    /// Enable or disable protocol state checks.
+    ///
+    /// When enabled (the default), out-of-order calls such as `session/new`
+    /// before `initialize`, a second `initialize`, or a prompt for an unknown
+    /// session fail with `INVALID_STATE`. Disable this for peers that skip
+    /// steps; unknown sessions are then registered on their first prompt.
+    pub fn with_state_checks(mut self, enabled: bool) -> Self {
+        self.state_checks = enabled;
+        self
+    }
+
+    /// Answer `session/stats` requests with the usage the server counted
+    /// for a session; see [`SessionManager::stats`].
+    ///
+    /// Off by default, so clients can't read the counters unless the host
+    /// means them to; the request then fails with `METHOD_NOT_FOUND`.
+    pub fn with_session_stats(mut self, enabled: bool) -> Self {
+        self.session_stats = enabled;
+        self
+    }
+
+    /// Current lifecycle state of the connection.
+    pub async fn connection_state(&self) -> ConnectionState {
+        *self.state.read().await
+    }
+
+    /// Set how content the other side didn't advertise support for is handled.
+    ///
+    /// Prompts are checked against the agent's capabilities and session
+    /// updates against the client's. Defaults to [`CapabilityPolicy::Strict`].
+    pub fn with_capability_policy(mut self, policy: CapabilityPolicy) -> Self {
+        self.capability_policy = policy;
+        self
+    }
+
+    /// Register tools for the agent.
+    ///
+    /// The tools are added to the capabilities returned from `initialize` and
+    /// are available to the agent through [`AgentContext::tools`].
+    pub fn with_tools(mut self, tools: ToolRegistry) -> Self {
+        self.tools = tools;
+        self
+    }
+
+    /// Route the agent's `#[acp_method]` methods to it.
+    ///
+    /// The methods are listed in the `custom_methods` capability returned
+    /// from `initialize`. Like session methods, they can only be called once
+    /// the connection is initialized.
+    pub fn with_custom_methods(mut self) -> Self
+    where
+        A: CustomMethods,
+    {
+        self.custom_methods = Some(self.agent.clone());
+        self
+    }
+
+    /// Connect to the MCP servers the client lists in `initialize`.
+    ///
+    /// Servers are connected before the agent's `initialize` runs and are
+    /// available to it through [`AgentContext::mcp`]. Servers that fail to
+    /// start are skipped. Off by default, in which case the agent gets the
+    /// list in `InitializeParams::mcp_servers` and may connect itself.
+    pub fn with_mcp(mut self, enabled: bool) -> Self {
+        self.mcp = if enabled { Some(self.mcp.take().unwrap_or_default()) } else { None };
+        self
+    }
+
+    /// Resolve the credentials of the client's MCP servers with
+    /// `credentials`, e.g. to look them up in a vault.
+    ///
+    /// Implies [`with_mcp`](Self::with_mcp). Without it, only `env:NAME`
+    /// and `keyring:SERVICE/ACCOUNT` references are resolved; see
+    /// [`Credentials`].
+    pub fn with_mcp_credentials(mut self, credentials: Credentials) -> Self {
+        self.mcp = Some(self.mcp.take().unwrap_or_default().with_credentials(credentials));
+        self
+    }
+
+    /// Offer the tools of the client's MCP servers as the agent's own.
+    ///
+    /// Implies [`with_mcp`](Self::with_mcp). The tools are listed during
+    /// `initialize`, added to the returned capabilities as
+    /// `<server>__<tool>`, and run on their server when called through
+    /// [`ToolRegistry::call`], which reports them to the client like any
+    /// other tool. Off by default.
+    pub fn with_mcp_tools(mut self, enabled: bool) -> Self {
+        self.mcp_tools = enabled;
+        if enabled && self.mcp.is_none() {
+            self.mcp = Some(McpServers::new());
         }
+        self
+    }
+
+    /// Let the client's MCP servers ask for LLM completions.
+    ///
+    /// Implies [`with_mcp`](Self::with_mcp). If the client has the
+    /// `sampling` capability, servers are offered sampling in the MCP
+    /// handshake and their `sampling/createMessage` requests are passed to
+    /// the client as `session/sampling_request`, to be answered by the
+    /// editor's model. Off by default.
+    pub fn with_mcp_sampling(mut self, enabled: bool) -> Self {
+        self.mcp_sampling = enabled;
+        if enabled && self.mcp.is_none() {
+            self.mcp = Some(McpServers::new());
+        }
+        self
+    }
+
+    /// Keep the client's MCP servers running.
+    ///
+    /// Implies [`with_mcp`](Self::with_mcp). Servers are pinged, and ones
+    /// that crash, hang or failed to start are restarted with backoff as
+    /// configured by `supervision`. The client is told when a server becomes
+    /// unavailable, is connected again or is given up on with
+    /// `mcp/server_status` notifications. Off by default.
+    pub fn with_mcp_supervision(mut self, supervision: McpSupervision) -> Self {
+        self.mcp_supervision = Some(supervision);
+        if self.mcp.is_none() {
+            self.mcp = Some(McpServers::new());
+        }
+        self
+    }
+
+    /// Set how long the agent may take to handle `method`.
+    ///
+    /// Requests that overrun fail with the `TIMEOUT` error code. Pass `None`
+    /// to let the method run indefinitely. By default setup methods time out
+    /// after a few seconds and `session/prompt` has no limit.
+    pub fn with_timeout(mut self, method: Method, timeout: Option<Duration>) -> Self {
+        let timeouts = Arc::make_mut(&mut self.timeouts);
+        match timeout {
+            Some(timeout) => timeouts.insert(method, timeout),
+            None => timeouts.remove(&method),
+        };
+        self
+    }
+
+    /// Get the timeout configured for `method`, if any.
+    pub fn timeout(&self, method: Method) -> Option<Duration> {
+        self.timeouts.get(&method).copied()
+    }
+
+    /// Add a middleware layer.
+    ///
+    /// Layers run in the order they are added, before the request reaches the agent.
+    pub fn layer(mut self, layer: impl Middleware) -> Self {
+        Arc::make_mut(&mut self.layers).push(Arc::new(layer));
+        self
+    }
+
+    /// Record request counts, errors, sessions, queued updates and prompt
+    /// latency in `metrics`.
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// The metrics set with [`with_metrics`](Self::with_metrics), if any.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> Option<&Metrics> {
+        self.metrics.as_ref()
+    }
+
+    /// The registry of sessions created through this server.
+    pub fn sessions(&self) -> &SessionManager {
+        &self.sessions
+    }
+
+    fn context(&self, connection: &AgentConnection) -> AgentContext {
+        AgentContext::new(self.sessions.clone(), connection.clone())
+            .with_tools(self.tools.clone())
+            .with_mcp(self.mcp.clone().unwrap_or_default())
+            .with_queues(self.prompt_queues.clone())
+    }
+
+    /// Send a request to the client and wait for a response.
+    ///
+    /// The request goes over the connection being served; `_response_tx` is
+    /// ignored. Fails with `ConnectionClosed` outside [`serve`](Self::serve).
+    #[deprecated(note = "use `AgentContext::connection` and `AgentConnection::send_request`")]
+    pub async fn send_request(
+        &self,
+        method: &str,
+        params: Value,
+        _response_tx: &mpsc::Sender<String>,
+    ) -> AcpResult<Value> {
+        self.served().await?.send_request(method, params).await
+    }
+
+    /// The connection being served.
+    pub(crate) async fn served(&self) -> AcpResult<AgentConnection> {
+        self.served.read().await.clone().ok_or(AcpError::ConnectionClosed)
+    }
+
+    /// Create a connection that sends client requests into `outgoing`.
+    pub(crate) fn connect(&self, outgoing: mpsc::Sender<String>) -> AgentConnection {
+        AgentConnection::new(outgoing, self.client_capabilities.clone())
+    }
+
+    /// Apply the capability policy to a session update bound for the client.
+    pub(crate) async fn check_update(&self, update: &mut SessionUpdate) -> AcpResult<()> {
+        let support = ContentSupport::from(&*self.client_capabilities.read().await);
+        enforce_update(self.capability_policy, support, update)
+    }
+
+    /// Check that `method` is allowed in the current connection state.
+    async fn check_state(&self, method: Method) -> AcpResult<()> {
+        if !self.state_checks {
+            return Ok(());
+        }
+        let state = *self.state.read().await;
+        match (method, state) {
+            (Method::Ping | Method::Shutdown | Method::AgentStatus, _) => Ok(()),
+            (_, ConnectionState::ShutDown) => {
+                Err(AcpError::InvalidState(format!("{} called after shutdown", method)))
+            }
+            (Method::Initialize, ConnectionState::Initialized) => {
+                Err(AcpError::InvalidState("Connection already initialized".to_string()))
+            }
+            (
+                Method::Authenticate
+                | Method::SessionNew
+                | Method::SessionLoad
+                | Method::SessionPrompt
+                | Method::SessionPromptStreamStart
+                | Method::SessionPromptStreamChunk
+                | Method::SessionPromptStreamEnd
+                | Method::SessionCancel
+                | Method::SessionSetModel
+                | Method::SessionStats,
+                ConnectionState::Uninitialized,
+            ) => Err(AcpError::InvalidState(format!("{} called before initialize", method))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Run a prompt through the agent.
+    async fn prompt(
+        &self,
+        mut params: SessionPromptParams,
+        update_tx: mpsc::Sender<SessionUpdate>,
+        connection: &AgentConnection,
+    ) -> AcpResult<SessionPromptResult> {
+        if !self.state_checks && !self.sessions.contains(&params.session_id).await {
+            let cwd = self.working_directory.read().await.clone();
+            self.sessions.insert(params.session_id.clone(), None, cwd).await;
+        }
+        self.sessions.touch(&params.session_id).await?;
+        let bytes = serde_json::to_string(&params.content).map_or(0, |content| content.len());
+        self.sessions.record_received(&params.session_id, bytes).await;
+        let support = ContentSupport::from(&*self.agent_capabilities.read().await);
+        enforce_prompt(self.capability_policy, support, &mut params.content)?;
+        let session_id = params.session_id.clone();
+        let session = SessionHandle::new(session_id.clone(), update_tx);
+
+        // Prompts within a session run one at a time; later ones wait in line
+        let _turn = match self.prompt_queues.enter(&session) {
+            Entry::Running(turn) => turn,
+            Entry::Queued {
+                prompt_id,
+                position,
+                wait,
+            } => {
+                session
+                    .send(SessionUpdateType::Queued { prompt_id, position })
+                    .await?;
+                match wait.turn().await {
+                    Some(turn) => turn,
+                    None => {
+                        return Ok(SessionPromptResult {
+                            status: "cancelled".to_string(),
+                        })
+                    }
+                }
+            }
+        };
+
+        let cancellation = CancellationToken::new();
+        self.active_prompts
+            .lock()
+            .await
+            .insert(session_id.clone(), cancellation.clone());
+        let ctx = self.context(connection).with_cancellation(cancellation);
+        let started = tokio::time::Instant::now();
+        let result = self.agent.session_prompt(params, session, ctx).await;
+        self.sessions.record_prompt_time(&session_id, started.elapsed()).await;
+        self.active_prompts.lock().await.remove(&session_id);
+        result
     }
 
     /// Run the server, reading from stdin and writing to stdout.
     pub async fn run(&self) -> AcpResult<()> {
-        let stdin = io::stdin();
-        let stdout = io::stdout();
+        self.serve(io::stdin(), io::stdout()).await
+    }
 
-        let reader = BufReader::new(stdin);
+    /// Run the server over `reader` and `writer` instead of stdio, such as
+    /// the ends of a [`tokio::io::duplex`] pipe for a client in the same
+    /// process.
+    pub async fn serve<R, W>(&self, reader: R, writer: W) -> AcpResult<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        let reader = BufReader::new(reader);
         let mut lines = reader.lines();
 
         let (update_tx, mut update_rx) = mpsc::channel::<SessionUpdate>(100);
         let (response_tx, mut response_rx) = mpsc::channel::<String>(100);
 
         // Spawn task to write responses
-        let stdout = Arc::new(Mutex::new(stdout));
-        let stdout_clone = stdout.clone();
-        tokio::spawn(async move {
+        let mut stdout = writer;
+        let transcript = self.transcript.clone();
+        let writer = tokio::spawn(async move {
             while let Some(msg) = response_rx.recv().await {
-                let mut stdout = stdout_clone.lock().await;
+                transcript.record(Direction::Outbound, &msg);
                 if let Err(e) = stdout.write_all(msg.as_bytes()).await {
                     eprintln!("Failed to write response: {}", e);
                     break;
@@ -153,79 +714,211 @@ impl<A: Agent> Server<A> {
                     break;
                 }
             }
+            // Signal end of stream to a client reading the other end
+            let _ = stdout.shutdown().await;
         });
 
         // Spawn task to send updates as notifications
         let response_tx_clone = response_tx.clone();
+        let server = self.clone();
+        let mut bandwidth = self.update_bandwidth.map(|rate| TokenBucket::new(rate as f64));
         tokio::spawn(async move {
-            while let Some(update) = update_rx.recv().await {
-                let notification = JsonRpcNotification {
-                    jsonrpc: "2.0".to_string(),
-                    method: "session/update".to_string(),
-                    params: Some(serde_json::to_value(&update).unwrap()),
-                };
-                let msg = serde_json::to_string(&notification).unwrap();
+            while let Some(mut update) = update_rx.recv().await {
+                #[cfg(feature = "metrics")]
+                if let Some(metrics) = &server.metrics {
+                    metrics.set_update_queue_depth(update_rx.len());
+                }
+                if let Err(e) = server.check_update(&mut update).await {
+                    eprintln!("Dropping update for session {}: {}", update.session_id, e);
+                    continue;
+                }
+                let msg = update_message(&update);
+                server.sessions.record_update(&update, msg.len()).await;
+                if let Some(bucket) = &mut bandwidth {
+                    tokio::time::sleep(bucket.reserve(msg.len() as f64)).await;
+                }
                 if response_tx_clone.send(msg).await.is_err() {
                     break;
                 }
             }
         });
 
-        // Main message loop
-        while let Ok(Some(line)) = lines.next_line().await {
+        let connection = self.connect(response_tx.clone());
+        *self.served.write().await = Some(connection.clone());
+        let order = self
+            .ordered_responses
+            .then(|| Arc::new(ResponseOrder::new(response_tx.clone())));
+
+        // Main message loop. Every message runs on its own task so a long prompt
+        // doesn't block cancellation or other sessions, and an agent calling
+        // back into the client gets the client's responses. Setup methods run
+        // one after another, and requests read after one wait for it to finish
+        // so that they observe its effects.
+        let mut setup: Option<watch::Receiver<()>> = None;
+        // After a signal, reading goes on while prompts drain so that the ones
+        // waiting for the client still get its answers
+        let drain_tx = update_tx.clone();
+        let mut drained = Box::pin(async move {
+            shutdown_signal(self.signal_handling).await;
+            eprintln!("Received shutdown signal, draining active prompts");
+            self.drain(&drain_tx).await;
+        });
+        loop {
+            let line = tokio::select! {
+                line = lines.next_line() => line,
+                _ = &mut drained => break,
+            };
+            let Ok(Some(line)) = line else {
+                break;
+            };
             if line.is_empty() {
                 continue;
             }
+            self.transcript.record(Direction::Inbound, &line);
 
-            let response = self
-                .handle_message(&line, update_tx.clone())
-                .await;
+            let msg: Value = match serde_json::from_str(&line) {
+                Ok(v) => v,
+                Err(e) => {
+                    eprintln!("Failed to parse message: {}", e);
+                    let resp = error_response(
+                        RequestId::Null,
+                        codes::PARSE_ERROR,
+                        format!("Parse error: {}", e),
+                    );
+                    if response_tx.send(serde_json::to_string(&resp)?).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            };
 
-            if let Some(resp) = response {
-                let msg = serde_json::to_string(&resp)?;
-                if response_tx.send(msg).await.is_err() {
-                    break;
+            let method = msg.get("method").and_then(|m| m.as_str());
+            let sequential = matches!(
+                method.and_then(|m| m.parse::<Method>().ok()),
+                Some(
+                    Method::Initialize
+                        | Method::Authenticate
+                        | Method::SessionNew
+                        | Method::SessionLoad
+                        | Method::Shutdown
+                        | Method::SessionPromptStreamStart
+                        | Method::SessionPromptStreamChunk
+                )
+            );
+
+            let ticket = match (&order, request_session(&msg)) {
+                (Some(order), Some(session_id)) => Some(order.reserve(session_id).await),
+                _ => None,
+            };
+
+            // Responses to the agent's requests never wait: the setup method
+            // ahead of them may be the one waiting for them
+            let previous = method.and(setup.clone());
+            let done = sequential.then(|| {
+                let (done, finished) = watch::channel(());
+                setup = Some(finished);
+                done
+            });
+            let server = self.clone();
+            let update_tx = update_tx.clone();
+            let response_tx = response_tx.clone();
+            let connection = connection.clone();
+            let order = order.clone();
+            tokio::spawn(async move {
+                after_setup(previous).await;
+                if let Some(resp) = server.handle_message(msg, update_tx, connection).await {
+                    if let Err(e) = respond(&response_tx, order.as_deref(), ticket, &resp).await {
+                        eprintln!("Failed to send response: {}", e);
+                    }
                 }
-            }
+                drop(done);
+            });
         }
 
+        self.shutdown().await;
+        self.served.write().await.take();
+
+        // Let queued responses and updates reach the client before returning
+        drop((drained, update_tx, response_tx, connection, order));
+        let _ = tokio::time::timeout(self.drain_timeout, writer).await;
         Ok(())
     }
 
+    /// Cancel running prompts, give them `drain_timeout` to respond, and
+    /// send a final `done` update for each of their sessions.
+    async fn drain(&self, update_tx: &mpsc::Sender<SessionUpdate>) {
+        self.prompt_queues.clear();
+        let sessions: Vec<String> = {
+            let active = self.active_prompts.lock().await;
+            active.values().for_each(|cancellation| cancellation.cancel());
+            active.keys().cloned().collect()
+        };
+
+        let deadline = tokio::time::Instant::now() + self.drain_timeout;
+        while !self.active_prompts.lock().await.is_empty()
+            && tokio::time::Instant::now() < deadline
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        for session_id in sessions {
+            let _ = SessionHandle::new(session_id, update_tx.clone()).send_done().await;
+        }
+    }
+
+    /// Cancel running prompts, drop unfinished prompt streams and run the
+    /// agent's teardown hooks, unless that has been done already.
+    pub(crate) async fn shutdown(&self) {
+        let previous = std::mem::replace(&mut *self.state.write().await, ConnectionState::ShutDown);
+        if previous == ConnectionState::ShutDown {
+            return;
+        }
+        self.prompt_queues.clear();
+        self.prompt_streams.clear().await;
+        for (_, cancellation) in self.active_prompts.lock().await.drain() {
+            cancellation.cancel();
+        }
+        if previous == ConnectionState::Initialized {
+            self.agent.on_client_disconnected().await;
+        }
+        self.agent.on_shutdown().await;
+        if let Some(mcp) = &self.mcp {
+            mcp.close().await;
+        }
+    }
+
     async fn handle_message(
         &self,
-        line: &str,
+        msg: Value,
         update_tx: mpsc::Sender<SessionUpdate>,
+        connection: AgentConnection,
     ) -> Option<JsonRpcResponse> {
-        // Try to parse as a request
-        let msg: Value = match serde_json::from_str(line) {
-            Ok(v) => v,
-            Err(e) => {
-                eprintln!("Failed to parse message: {}", e);
-                return Some(JsonRpcResponse {
-                    jsonrpc: "2.0".to_string(),
-                    id: Value::Null,
-                    result: None,
-                    error: Some(JsonRpcError {
-                        code: codes::PARSE_ERROR,
-                        message: format!("Parse error: {}", e),
-                        data: None,
-                    }),
-                });
+        // Check if it's a request (has id and method) or response (has id but no method)
+        let id = match msg.get("id").cloned().map(serde_json::from_value::<RequestId>) {
+            None => None,
+            Some(Ok(id)) => Some(id),
+            Some(Err(_)) => {
+                return Some(error_response(
+                    RequestId::Null,
+                    codes::INVALID_REQUEST,
+                    "Invalid request: id must be a string, number, or null".to_string(),
+                ));
             }
         };
-
-        // Check if it's a request (has id and method) or response (has id but no method)
-        let id = msg.get("id").cloned();
         let method = msg.get("method").and_then(|m| m.as_str());
 
         // If it has method, it's a request
         if let Some(method) = method {
-            let params = msg.get("params").cloned().unwrap_or(Value::Null);
+            let request = JsonRpcRequest {
+                jsonrpc: "2.0".to_string(),
+                id: id.clone(),
+                method: method.to_string(),
+                params: msg.get("params").cloned(),
+            };
+            let result = self.dispatch(request, update_tx, connection).await;
 
             // If it has id, it expects a response
             if let Some(id) = id {
-                let result = self.handle_request(method, params, update_tx).await;
                 return Some(match result {
                     Ok(value) => JsonRpcResponse {
                         jsonrpc: "2.0".to_string(),
@@ -233,202 +926,867 @@ impl<A: Agent> Server<A> {
                         result: Some(value),
                         error: None,
                     },
-                    Err(e) => JsonRpcResponse {
-                        jsonrpc: "2.0".to_string(),
-                        id,
-                        result: None,
-                        error: Some(JsonRpcError {
-                            code: e.code(),
-                            message: e.message(),
-                            data: None,
-                        }),
-                    },
+                    Err(e) => error_response(id, e.code(), e.message()),
                 });
             } else {
                 // Notification - no response needed
-                let _ = self.handle_request(method, params, update_tx).await;
                 return None;
             }
         } else if let Some(id) = id {
             // This is a response to our request
-            let id_str = id.to_string();
-            let mut pending = self.pending_requests.lock().await;
-            if let Some(tx) = pending.remove(&id_str) {
-                let response = JsonRpcResponse {
-                    jsonrpc: "2.0".to_string(),
-                    id,
-                    result: msg.get("result").cloned(),
-                    error: msg.get("error").and_then(|e| serde_json::from_value(e.clone()).ok()),
-                };
-                let _ = tx.send(response);
-            }
+            let response = JsonRpcResponse {
+                jsonrpc: "2.0".to_string(),
+                id,
+                result: msg.get("result").cloned(),
+                error: msg.get("error").and_then(|e| serde_json::from_value(e.clone()).ok()),
+            };
+            connection.complete(response).await;
         }
 
         None
     }
 
+    /// Run a request through the middleware stack and then the agent.
+    pub(crate) async fn dispatch(
+        &self,
+        request: JsonRpcRequest,
+        update_tx: mpsc::Sender<SessionUpdate>,
+        connection: AgentConnection,
+    ) -> AcpResult<Value> {
+        let server = self.clone();
+        let endpoint = move |request: JsonRpcRequest| -> middleware::EndpointFuture {
+            let server = server.clone();
+            let update_tx = update_tx.clone();
+            let connection = connection.clone();
+            Box::pin(async move {
+                let limit = request
+                    .method
+                    .parse::<Method>()
+                    .ok()
+                    .and_then(|method| server.timeout(method));
+                let params = request.params.unwrap_or(Value::Null);
+                let handle = server.handle_request(&request.method, params, update_tx, &connection);
+                match limit {
+                    Some(limit) => tokio::time::timeout(limit, handle)
+                        .await
+                        .map_err(|_| AcpError::Timeout)?,
+                    None => handle.await,
+                }
+            })
+        };
+        #[cfg(feature = "metrics")]
+        let measured = match &self.metrics {
+            Some(metrics) => Some((metrics, self.metrics_label(&request.method).await, tokio::time::Instant::now())),
+            None => None,
+        };
+        let span = request_span(&request);
+        let result = Next::new(&self.layers, &endpoint).run(request).instrument(span).await;
+        #[cfg(feature = "metrics")]
+        if let Some((metrics, method, started)) = measured {
+            metrics.record_request(&method);
+            if let Err(e) = &result {
+                metrics.record_error(e.code());
+            }
+            if method == method::SESSION_PROMPT || method == method::SESSION_PROMPT_STREAM_END {
+                metrics.record_prompt(started.elapsed());
+            }
+            metrics.set_active_sessions(self.sessions.len().await);
+        }
+        result
+    }
+
+    /// `method` if it's an ACP method or one the agent offers, for labelling
+    /// metrics.
+    #[cfg(feature = "metrics")]
+    async fn metrics_label(&self, method: &str) -> String {
+        let known = method.parse::<Method>().is_ok()
+            || self.agent_capabilities.read().await.custom_methods.iter().any(|m| m == method);
+        if known {
+            method.to_string()
+        } else {
+            metrics::OTHER_METHOD.to_string()
+        }
+    }
+
+    /// Handle a method outside the ACP method set, trying the agent's
+    /// `#[acp_method]`s before [`Agent::handle_custom`].
+    async fn handle_unknown(&self, method: &str, params: Value) -> AcpResult<Value> {
+        if let Some(custom) = &self.custom_methods {
+            if custom.custom_method_names().iter().any(|name| name == method) {
+                if self.state_checks {
+                    match *self.state.read().await {
+                        ConnectionState::Uninitialized => {
+                            return Err(AcpError::InvalidState(format!("{} called before initialize", method)));
+                        }
+                        ConnectionState::ShutDown => {
+                            return Err(AcpError::InvalidState(format!("{} called after shutdown", method)));
+                        }
+                        ConnectionState::Initialized => {}
+                    }
+                }
+                if let Some(result) = custom.call_custom_method(method, params.clone()).await {
+                    return result;
+                }
+            }
+        }
+        self.agent.handle_custom(method, params).await
+    }
+
     async fn handle_request(
         &self,
         method: &str,
         params: Value,
         update_tx: mpsc::Sender<SessionUpdate>,
+        connection: &AgentConnection,
     ) -> AcpResult<Value> {
+        let method = match method.parse::<Method>() {
+            Ok(method) => method,
+            Err(_) => return self.handle_unknown(method, params).await,
+        };
+        self.check_state(method).await?;
         match method {
-            "initialize" => {
+            Method::Ping => Ok(serde_json::json!({})),
+            Method::AgentStatus => Ok(serde_json::to_value(AgentStatusResult {
+                uptime_ms: self.started.elapsed().as_millis() as u64,
+                active_sessions: self.sessions.len().await as u64,
+                running_prompts: self.active_prompts.lock().await.len() as u64,
+                queued_prompts: self.prompt_queues.total() as u64,
+                resources: status::resource_usage(),
+            })?),
+            Method::Shutdown => {
+                // Let running prompts finish up, then run the teardown hooks
+                // now rather than when the client disconnects
+                self.drain(&update_tx).await;
+                self.shutdown().await;
+                Ok(serde_json::json!({}))
+            }
+            Method::Initialize => {
                 let params: InitializeParams = serde_json::from_value(params)
                     .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
-                let result = self.agent.initialize(params).await?;
+                let client_capabilities = params.capabilities.clone();
+                let working_directory = params.working_directory.clone();
+                let client_info = params.client_info.clone();
+                let mut tools = self.tools.infos();
+                // MCP servers may sample as they start, which the connection
+                // allows only once it knows the client can
+                *self.client_capabilities.write().await = client_capabilities.clone();
+                if let Some(mcp) = &self.mcp {
+                    if self.mcp_sampling && client_capabilities.sampling {
+                        mcp.set_sampler(Some(Arc::new(connection.clone())));
+                    }
+                    mcp.connect(&params.mcp_servers).await;
+                    if let Some(supervision) = &self.mcp_supervision {
+                        let (status_tx, mut status_rx) = mpsc::channel::<McpServerStatus>(16);
+                        let connection = connection.clone();
+                        tokio::spawn(async move {
+                            while let Some(status) = status_rx.recv().await {
+                                let params = serde_json::to_value(status).unwrap_or_default();
+                                let _ = connection.notify(method::MCP_SERVER_STATUS, params).await;
+                            }
+                        });
+                        mcp.supervise(&params.mcp_servers, supervision.clone(), status_tx);
+                    }
+                    if self.mcp_tools {
+                        tools.extend(mcp.proxy_tools().await);
+                    }
+                }
+                let mut result = self.agent.initialize(params).await?;
+                self.agent.on_client_connected(client_info).await?;
+                result.capabilities.merge(AgentCapabilities {
+                    tools,
+                    custom_methods: self
+                        .custom_methods
+                        .as_ref()
+                        .map(|custom| custom.custom_method_names())
+                        .unwrap_or_default(),
+                    ..Default::default()
+                });
+                *self.working_directory.write().await = working_directory;
+                *self.agent_capabilities.write().await = result.capabilities.clone();
+                *self.state.write().await = ConnectionState::Initialized;
                 Ok(serde_json::to_value(result)?)
             }
-            "authenticate" => {
+            Method::Authenticate => {
                 let params: AuthenticateParams = serde_json::from_value(params)
                     .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
                 let result = self.agent.authenticate(params).await?;
                 Ok(serde_json::to_value(result)?)
             }
-            "session/new" => {
+            Method::SessionNew => {
                 let params: SessionNewParams = serde_json::from_value(params)
                     .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
-                let result = self.agent.session_new(params).await?;
+                if self.state_checks && self.sessions.contains(&params.session_id).await {
+                    return Err(AcpError::InvalidState(format!(
+                        "Session already exists: {}",
+                        params.session_id
+                    )));
+                }
+                let mode = params.mode.clone();
+                let result = self.agent.session_new(params, self.context(connection)).await?;
+                let cwd = self.working_directory.read().await.clone();
+                self.sessions.insert(result.session_id.clone(), mode, cwd).await;
                 Ok(serde_json::to_value(result)?)
             }
-            "session/load" => {
+            Method::SessionLoad => {
                 let params: SessionLoadParams = serde_json::from_value(params)
                     .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
-                let result = self.agent.session_load(params).await?;
+                let result = self.agent.session_load(params, self.context(connection)).await?;
+                if result.loaded {
+                    let cwd = self.working_directory.read().await.clone();
+                    self.sessions.insert(result.session_id.clone(), None, cwd).await;
+                }
                 Ok(serde_json::to_value(result)?)
             }
-            "session/prompt" => {
+            Method::SessionPrompt => {
                 let params: SessionPromptParams = serde_json::from_value(params)
                     .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
-                let result = self.agent.session_prompt(params, update_tx).await?;
+                let result = self.prompt(params, update_tx, connection).await?;
                 Ok(serde_json::to_value(result)?)
             }
-            "session/cancel" => {
+            Method::SessionPromptStreamStart => {
+                let params: PromptStreamStartParams = serde_json::from_value(params)
+                    .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
+                if self.state_checks && !self.sessions.contains(&params.session_id).await {
+                    return Err(AcpError::InvalidState(format!(
+                        "Unknown session: {}",
+                        params.session_id
+                    )));
+                }
+                let stream_id = self.prompt_streams.start(params.session_id).await?;
+                Ok(serde_json::to_value(PromptStreamStartResult { stream_id })?)
+            }
+            Method::SessionPromptStreamChunk => {
+                let params: PromptStreamChunkParams = serde_json::from_value(params)
+                    .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
+                self.prompt_streams.append(params).await?;
+                Ok(serde_json::json!({}))
+            }
+            Method::SessionPromptStreamEnd => {
+                let params: PromptStreamEndParams = serde_json::from_value(params)
+                    .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
+                let prompt = self.prompt_streams.finish(&params.stream_id).await?;
+                let result = self.prompt(prompt, update_tx, connection).await?;
+                Ok(serde_json::to_value(result)?)
+            }
+            Method::SessionCancel => {
                 let params: SessionCancelParams = serde_json::from_value(params)
                     .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
-                self.agent.session_cancel(params).await?;
+                if let Some(prompt_id) = &params.prompt_id {
+                    // Only the queued prompt goes; it never reached the agent
+                    let removed = self.prompt_queues.cancel(&params.session_id, Some(prompt_id));
+                    if removed == 0 && self.state_checks {
+                        return Err(AcpError::InvalidState(format!(
+                            "No queued prompt {} in session {}",
+                            prompt_id, params.session_id
+                        )));
+                    }
+                    return Ok(Value::Null);
+                }
+                let known = self
+                    .sessions
+                    .set_state(&params.session_id, SessionState::Cancelled)
+                    .await;
+                if self.state_checks {
+                    known?;
+                }
+                self.prompt_queues.cancel(&params.session_id, None);
+                if let Some(cancellation) = self.active_prompts.lock().await.remove(&params.session_id) {
+                    cancellation.cancel();
+                }
+                self.agent.session_cancel(params, self.context(connection)).await?;
                 Ok(Value::Null)
             }
-            _ => Err(AcpError::MethodNotFound(method.to_string())),
+            Method::SessionSetModel => {
+                let params: SessionSetModelParams = serde_json::from_value(params)
+                    .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
+                if self.state_checks {
+                    if !self.sessions.contains(&params.session_id).await {
+                        return Err(AcpError::InvalidState(format!(
+                            "Unknown session: {}",
+                            params.session_id
+                        )));
+                    }
+                    let models = &self.agent_capabilities.read().await.models;
+                    if !models.is_empty() && !models.iter().any(|m| m.id == params.model_id) {
+                        return Err(AcpError::InvalidParams(format!("Unknown model: {}", params.model_id)));
+                    }
+                }
+                let session_id = params.session_id.clone();
+                let model_id = params.model_id.clone();
+                self.agent.session_set_model(params, self.context(connection)).await?;
+                let _ = self.sessions.set_model(&session_id, Some(model_id)).await;
+                Ok(serde_json::json!({}))
+            }
+            Method::SessionStats => {
+                if !self.session_stats {
+                    return Err(AcpError::MethodNotFound(method.to_string()));
+                }
+                let params: SessionStatsParams = serde_json::from_value(params)
+                    .map_err(|e| AcpError::InvalidParams(e.to_string()))?;
+                let stats = self
+                    .sessions
+                    .stats(&params.session_id)
+                    .await
+                    .ok_or_else(|| AcpError::InvalidState(format!("Unknown session: {}", params.session_id)))?;
+                Ok(serde_json::to_value(stats)?)
+            }
+            Method::SessionRequestPermission
+            | Method::SessionUpdate
+            | Method::SessionSamplingRequest
+            | Method::McpServerStatus
+            | Method::FsReadTextFile
+            | Method::FsWriteTextFile
+            | Method::FsApplyEdit
+            | Method::FsReadStreamStart
+            | Method::FsReadChunk
+            | Method::FsReadStreamClose
+            | Method::FsReadFile
+            | Method::FsWriteFile
+            | Method::FsListDirectory
+            | Method::FsGlob
+            | Method::FsGrep
+            | Method::FsDelete
+            | Method::FsRename
+            | Method::FsCreateDirectory
+            | Method::TerminalCreate
+            | Method::TerminalOutput
+            | Method::TerminalInput
+            | Method::TerminalWaitForExit
+            | Method::TerminalKill
+            | Method::TerminalRelease => Err(AcpError::MethodNotFound(method.to_string())),
         }
     }
+}
 
-    /// Send a request to the client and wait for a response.
-    ///
-    /// Use this to request file operations or terminal access from the client.
-    pub async fn send_request(
-        &self,
-        method: &str,
-        params: Value,
-        response_tx: &mpsc::Sender<String>,
-    ) -> AcpResult<Value> {
-        let id = {
-            let mut next_id = self.next_request_id.lock().await;
-            let id = *next_id;
-            *next_id += 1;
-            id
-        };
+/// Wait until the setup method `setup` belongs to has finished, which its
+/// task signals by dropping the sender.
+async fn after_setup(setup: Option<watch::Receiver<()>>) {
+    if let Some(mut setup) = setup {
+        let _ = setup.changed().await;
+    }
+}
 
-        let id_value = Value::Number(id.into());
-        let id_str = id_value.to_string();
+/// The `session/update` notification carrying `update`, serialized.
+pub(crate) fn update_message(update: &SessionUpdate) -> String {
+    let notification = JsonRpcNotification {
+        jsonrpc: "2.0".to_string(),
+        method: method::SESSION_UPDATE.to_string(),
+        params: Some(serde_json::to_value(update).unwrap()),
+    };
+    serde_json::to_string(&notification).unwrap()
+}
 
-        let (tx, rx) = oneshot::channel();
-        {
-            let mut pending = self.pending_requests.lock().await;
-            pending.insert(id_str, tx);
+/// The span a request is handled in, joined to the trace named in its
+/// `_meta`, if any.
+fn request_span(request: &JsonRpcRequest) -> tracing::Span {
+    let span = tracing::info_span!(
+        "acp.request",
+        method = %request.method,
+        trace_id = tracing::field::Empty,
+        parent_id = tracing::field::Empty,
+    );
+    if let Some(context) = request.params.as_ref().and_then(TraceContext::from_params) {
+        span.record("trace_id", tracing::field::display(format_args!("{:032x}", context.trace_id)));
+        span.record("parent_id", tracing::field::display(format_args!("{:016x}", context.parent_id)));
+        #[cfg(feature = "otel")]
+        context.attach(&span);
+    }
+    span
+}
+
+/// Resolve on SIGINT or SIGTERM, or never if `enabled` is false.
+async fn shutdown_signal(enabled: bool) {
+    if !enabled {
+        return std::future::pending().await;
+    }
+
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            tokio::select! {
+                result = tokio::signal::ctrl_c() => {
+                    if result.is_err() {
+                        terminate.recv().await;
+                    }
+                }
+                _ = terminate.recv() => {}
+            }
+            return;
         }
+    }
 
+    if tokio::signal::ctrl_c().await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Timeouts applied unless overridden with [`Server::with_timeout`].
+fn default_timeouts() -> HashMap<Method, Duration> {
+    HashMap::from([
+        (Method::Ping, Duration::from_secs(10)),
+        (Method::AgentStatus, Duration::from_secs(10)),
+        (Method::Initialize, Duration::from_secs(10)),
+        (Method::Authenticate, Duration::from_secs(30)),
+        (Method::SessionNew, Duration::from_secs(30)),
+        (Method::SessionLoad, Duration::from_secs(60)),
+        (Method::SessionPromptStreamStart, Duration::from_secs(10)),
+        (Method::SessionPromptStreamChunk, Duration::from_secs(10)),
+        (Method::SessionCancel, Duration::from_secs(10)),
+        (Method::SessionSetModel, Duration::from_secs(30)),
+        (Method::SessionStats, Duration::from_secs(10)),
+        (Method::Shutdown, Duration::from_secs(30)),
+    ])
+}
+
+/// Write a response, in session order if it has a ticket.
+async fn respond(
+    response_tx: &mpsc::Sender<String>,
+    order: Option<&ResponseOrder>,
+    ticket: Option<ordering::Ticket>,
+    resp: &JsonRpcResponse,
+) -> AcpResult<()> {
+    match (order, ticket) {
+        (Some(order), Some(ticket)) => order.send(ticket, resp).await,
+        _ => response_tx
+            .send(serde_json::to_string(resp)?)
+            .await
+            .map_err(|e| AcpError::ChannelError(e.to_string())),
+    }
+}
+
+pub(crate) fn error_response(id: RequestId, code: i32, message: String) -> JsonRpcResponse {
+    JsonRpcResponse {
+        jsonrpc: "2.0".to_string(),
+        id,
+        result: None,
+        error: Some(JsonRpcError {
+            code,
+            message,
+            data: None,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Agent whose `initialize` takes the given time and that records
+    /// lifecycle hooks.
+    struct SlowAgent {
+        delay: Duration,
+        hooks: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    impl SlowAgent {
+        fn new(delay: Duration) -> Self {
+            Self {
+                delay,
+                hooks: Default::default(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Agent for SlowAgent {
+        async fn initialize(&self, _params: InitializeParams) -> AcpResult<InitializeResult> {
+            tokio::time::sleep(self.delay).await;
+            Ok(InitializeResult {
+                agent_info: AgentInfo {
+                    name: "slow".to_string(),
+                    version: "0.0.0".to_string(),
+                },
+                capabilities: AgentCapabilities {
+                    models: ["small", "large"]
+                        .map(|id| ModelInfo {
+                            id: id.to_string(),
+                            name: id.to_uppercase(),
+                            description: None,
+                        })
+                        .to_vec(),
+                    ..Default::default()
+                },
+                instructions: None,
+            })
+        }
+
+        async fn session_new(
+            &self,
+            params: SessionNewParams,
+            _ctx: AgentContext,
+        ) -> AcpResult<SessionNewResult> {
+            Ok(SessionNewResult {
+                session_id: params.session_id,
+            })
+        }
+
+        async fn session_set_model(&self, params: SessionSetModelParams, _ctx: AgentContext) -> AcpResult<()> {
+            self.hooks.lock().unwrap().push(format!("model:{}", params.model_id));
+            Ok(())
+        }
+
+        async fn session_prompt(
+            &self,
+            params: SessionPromptParams,
+            _session: SessionHandle,
+            ctx: AgentContext,
+        ) -> AcpResult<SessionPromptResult> {
+            // "block" prompts run until cancelled
+            if matches!(params.content.first(), Some(ContentBlock::Text { text, .. }) if text == "block") {
+                ctx.cancellation_token().cancelled().await;
+            }
+            Ok(SessionPromptResult {
+                status: "ok".to_string(),
+            })
+        }
+
+        async fn handle_custom(&self, method: &str, params: Value) -> AcpResult<Value> {
+            match method {
+                "slow/echo" => Ok(params),
+                _ => Err(AcpError::MethodNotFound(method.to_string())),
+            }
+        }
+
+        async fn on_client_connected(&self, client: ClientInfo) -> AcpResult<()> {
+            self.hooks.lock().unwrap().push(format!("connected:{}", client.name));
+            Ok(())
+        }
+
+        async fn on_client_disconnected(&self) {
+            self.hooks.lock().unwrap().push("disconnected".to_string());
+        }
+
+        async fn on_shutdown(&self) {
+            self.hooks.lock().unwrap().push("shutdown".to_string());
+        }
+    }
+
+    async fn call(server: &Server<SlowAgent>, method: &str, params: Value) -> AcpResult<Value> {
+        let (update_tx, _update_rx) = mpsc::channel(8);
+        call_with_updates(server, method, params, update_tx).await
+    }
+
+    async fn call_with_updates(
+        server: &Server<SlowAgent>,
+        method: &str,
+        params: Value,
+        update_tx: mpsc::Sender<SessionUpdate>,
+    ) -> AcpResult<Value> {
+        let (outgoing, _outgoing_rx) = mpsc::channel(8);
+        let connection = AgentConnection::new(outgoing, server.client_capabilities.clone());
         let request = JsonRpcRequest {
             jsonrpc: "2.0".to_string(),
-            id: Some(id_value),
+            id: Some(RequestId::Number(1)),
             method: method.to_string(),
             params: Some(params),
         };
+        server.dispatch(request, update_tx, connection).await
+    }
+
+    fn initialize_params() -> Value {
+        serde_json::json!({
+            "protocol_version": PROTOCOL_VERSION,
+            "client_info": {"name": "test", "version": "1.0"},
+            "capabilities": {},
+            "working_directory": "/"
+        })
+    }
 
-        let msg = serde_json::to_string(&request)?;
-        response_tx
-            .send(msg)
+    #[test]
+    fn test_default_timeouts() {
+        let server = Server::new(SlowAgent::new(Duration::ZERO));
+        assert_eq!(server.timeout(Method::Initialize), Some(Duration::from_secs(10)));
+        assert_eq!(server.timeout(Method::SessionPrompt), None);
+
+        let server = server
+            .with_timeout(Method::SessionPrompt, Some(Duration::from_secs(300)))
+            .with_timeout(Method::Initialize, None);
+        assert_eq!(server.timeout(Method::SessionPrompt), Some(Duration::from_secs(300)));
+        assert_eq!(server.timeout(Method::Initialize), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_request_timeout() {
+        let server = Server::new(SlowAgent::new(Duration::from_secs(60)))
+            .with_timeout(Method::Initialize, Some(Duration::from_secs(10)));
+
+        let started = tokio::time::Instant::now();
+        let err = call(&server, method::INITIALIZE, initialize_params()).await.unwrap_err();
+        assert_eq!(err.code(), codes::TIMEOUT);
+        assert_eq!(started.elapsed(), Duration::from_secs(10));
+        assert_eq!(server.connection_state().await, ConnectionState::Uninitialized);
+    }
+
+    #[tokio::test]
+    async fn test_state_checks() {
+        let server = Server::new(SlowAgent::new(Duration::ZERO));
+        let session = serde_json::json!({"session_id": "s1"});
+        let prompt = serde_json::json!({"session_id": "s1", "content": []});
+
+        let err = call(&server, method::SESSION_NEW, session.clone()).await.unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_STATE);
+        call(&server, method::PING, Value::Null).await.unwrap();
+
+        call(&server, method::INITIALIZE, initialize_params()).await.unwrap();
+        assert_eq!(server.connection_state().await, ConnectionState::Initialized);
+        let err = call(&server, method::INITIALIZE, initialize_params()).await.unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_STATE);
+
+        let err = call(&server, method::SESSION_CANCEL, session.clone()).await.unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_STATE);
+
+        call(&server, method::SESSION_NEW, session.clone()).await.unwrap();
+        let err = call(&server, method::SESSION_NEW, session.clone()).await.unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_STATE);
+
+        call(&server, method::SESSION_CANCEL, session).await.unwrap();
+        let info = server.sessions().get("s1").await.unwrap();
+        assert_eq!(info.state, SessionState::Cancelled);
+
+        call(&server, method::SESSION_PROMPT, prompt).await.unwrap();
+        let info = server.sessions().get("s1").await.unwrap();
+        assert_eq!(info.state, SessionState::Active);
+    }
+
+    #[tokio::test]
+    async fn test_handle_custom_fallback() {
+        let server = Server::new(SlowAgent::new(Duration::ZERO));
+
+        let result = call(&server, "slow/echo", serde_json::json!({"n": 1})).await.unwrap();
+        assert_eq!(result, serde_json::json!({"n": 1}));
+        let err = call(&server, "slow/other", Value::Null).await.unwrap_err();
+        assert_eq!(err.code(), codes::METHOD_NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_state_checks_disabled() {
+        let server = Server::new(SlowAgent::new(Duration::ZERO)).with_state_checks(false);
+        let prompt = serde_json::json!({"session_id": "s1", "content": []});
+
+        call(&server, method::SESSION_PROMPT, prompt).await.unwrap();
+        assert_eq!(server.sessions().get("s1").await.unwrap().prompt_count, 1);
+        call(&server, method::SESSION_CANCEL, serde_json::json!({"session_id": "s2"}))
             .await
-            .map_err(|e| AcpError::ChannelError(e.to_string()))?;
+            .unwrap();
+    }
 
-        let response = rx.await.map_err(|_| AcpError::ConnectionClosed)?;
+    #[tokio::test]
+    async fn test_lifecycle_hooks() {
+        let agent = SlowAgent::new(Duration::ZERO);
+        let hooks = agent.hooks.clone();
+        let server = Server::new(agent);
+
+        call(&server, method::INITIALIZE, initialize_params()).await.unwrap();
+        let cancellation = CancellationToken::new();
+        server
+            .active_prompts
+            .lock()
+            .await
+            .insert("s1".to_string(), cancellation.clone());
+        server.shutdown().await;
 
-        if let Some(error) = response.error {
-            return Err(AcpError::InternalError(error.message));
+        assert!(cancellation.is_cancelled());
+        assert_eq!(*hooks.lock().unwrap(), vec!["connected:test", "disconnected", "shutdown"]);
+    }
+
+    #[tokio::test]
+    async fn test_request_span_records_trace() {
+        #[derive(Clone, Default)]
+        struct Logs(Arc<std::sync::Mutex<Vec<u8>>>);
+        impl std::io::Write for Logs {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                std::io::Write::write(&mut *self.0.lock().unwrap(), buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
         }
 
-        Ok(response.result.unwrap_or(Value::Null))
+        let logs = Logs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let server = Server::new(SlowAgent::new(Duration::ZERO));
+        let mut params = initialize_params();
+        params["_meta"] = serde_json::json!({"traceparent": "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"});
+        call(&server, method::INITIALIZE, params).await.unwrap();
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(
+            logs.contains("acp.request{method=initialize trace_id=4bf92f3577b34da6a3ce929d0e0e4736 parent_id=00f067aa0ba902b7}"),
+            "{}",
+            logs
+        );
     }
-}
 
-/// Helper functions for agents to request client operations.
-pub mod client_requests {
-    use super::*;
+    #[tokio::test]
+    async fn test_set_model() {
+        let agent = SlowAgent::new(Duration::ZERO);
+        let hooks = agent.hooks.clone();
+        let server = Server::new(agent);
+        let set = |session: &str, model: &str| serde_json::json!({"session_id": session, "model_id": model});
 
-    /// Read a text file from the client.
-    pub async fn read_file(
-        server: &Server<impl Agent>,
-        path: &str,
-        response_tx: &mpsc::Sender<String>,
-    ) -> AcpResult<String> {
-        let params = serde_json::json!({ "path": path });
-        let result = server.send_request("fs/read_text_file", params, response_tx).await?;
-        let content = result["content"]
-            .as_str()
-            .ok_or_else(|| AcpError::InvalidParams("Missing content".to_string()))?;
-        Ok(content.to_string())
-    }
-
-    /// Write a text file via the client.
-    pub async fn write_file(
-        server: &Server<impl Agent>,
-        path: &str,
-        content: &str,
-        response_tx: &mpsc::Sender<String>,
-    ) -> AcpResult<()> {
-        let params = serde_json::json!({ "path": path, "content": content });
-        server.send_request("fs/write_text_file", params, response_tx).await?;
-        Ok(())
+        let err = call(&server, method::SESSION_SET_MODEL, set("s1", "large")).await.unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_STATE);
+        call(&server, method::INITIALIZE, initialize_params()).await.unwrap();
+        call(&server, method::SESSION_NEW, serde_json::json!({"session_id": "s1"})).await.unwrap();
+
+        let err = call(&server, method::SESSION_SET_MODEL, set("s2", "large")).await.unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_STATE);
+        let err = call(&server, method::SESSION_SET_MODEL, set("s1", "huge")).await.unwrap_err();
+        assert!(matches!(err, AcpError::InvalidParams(m) if m == "Unknown model: huge"));
+
+        call(&server, method::SESSION_SET_MODEL, set("s1", "large")).await.unwrap();
+        assert_eq!(server.sessions.get("s1").await.unwrap().model.as_deref(), Some("large"));
+        assert_eq!(*hooks.lock().unwrap(), vec!["connected:test", "model:large"]);
     }
 
-    /// Create a terminal session via the client.
-    pub async fn create_terminal(
-        server: &Server<impl Agent>,
-        cwd: &str,
-        command: &str,
-        response_tx: &mpsc::Sender<String>,
-    ) -> AcpResult<String> {
-        let params = serde_json::json!({ "cwd": cwd, "command": command });
-        let result = server.send_request("terminal/create", params, response_tx).await?;
-        let terminal_id = result["terminal_id"]
-            .as_str()
-            .ok_or_else(|| AcpError::InvalidParams("Missing terminal_id".to_string()))?;
-        Ok(terminal_id.to_string())
-    }
-
-    /// Get terminal output.
-    pub async fn get_terminal_output(
-        server: &Server<impl Agent>,
-        terminal_id: &str,
-        response_tx: &mpsc::Sender<String>,
-    ) -> AcpResult<(String, bool, Option<i32>)> {
-        let params = serde_json::json!({ "terminal_id": terminal_id });
-        let result = server.send_request("terminal/output", params, response_tx).await?;
-        let output = result["output"].as_str().unwrap_or("").to_string();
-        let exited = result["exited"].as_bool().unwrap_or(false);
-        let exit_code = result["exit_code"].as_i64().map(|c| c as i32);
-        Ok((output, exited, exit_code))
-    }
-
-    /// Kill a terminal.
-    pub async fn kill_terminal(
-        server: &Server<impl Agent>,
-        terminal_id: &str,
-        response_tx: &mpsc::Sender<String>,
-    ) -> AcpResult<()> {
-        let params = serde_json::json!({ "terminal_id": terminal_id });
-        server.send_request("terminal/kill", params, response_tx).await?;
-        Ok(())
+    #[tokio::test]
+    async fn test_shutdown_request() {
+        let agent = SlowAgent::new(Duration::ZERO);
+        let hooks = agent.hooks.clone();
+        let server = Server::new(agent);
+
+        call(&server, method::INITIALIZE, initialize_params()).await.unwrap();
+        call(&server, method::SHUTDOWN, serde_json::json!({})).await.unwrap();
+        assert_eq!(server.connection_state().await, ConnectionState::ShutDown);
+
+        // Teardown runs once, and only ping and shutdown are still answered
+        server.shutdown().await;
+        call(&server, method::SHUTDOWN, serde_json::json!({})).await.unwrap();
+        assert_eq!(*hooks.lock().unwrap(), vec!["connected:test", "disconnected", "shutdown"]);
+        let err = call(&server, method::SESSION_NEW, serde_json::json!({"session_id": "s1"}))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_STATE);
+        call(&server, method::PING, serde_json::json!({})).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_without_client() {
+        let agent = SlowAgent::new(Duration::ZERO);
+        let hooks = agent.hooks.clone();
+        Server::new(agent).shutdown().await;
+        assert_eq!(*hooks.lock().unwrap(), vec!["shutdown"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_drain_finishes_active_sessions() {
+        let server =
+            Server::new(SlowAgent::new(Duration::ZERO)).with_drain_timeout(Duration::from_millis(50));
+        let cancellation = CancellationToken::new();
+        server
+            .active_prompts
+            .lock()
+            .await
+            .insert("s1".to_string(), cancellation.clone());
+
+        let (update_tx, mut update_rx) = mpsc::channel(8);
+        server.drain(&update_tx).await;
+
+        assert!(cancellation.is_cancelled());
+        let update = update_rx.try_recv().unwrap();
+        assert_eq!(update.session_id, "s1");
+        assert!(matches!(update.update_type, SessionUpdateType::Done));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_agent_status() {
+        let server = Server::new(SlowAgent::new(Duration::ZERO));
+        tokio::time::advance(Duration::from_secs(2)).await;
+
+        // Answered before initialize, like ping
+        let status = call(&server, method::AGENT_STATUS, Value::Null).await.unwrap();
+        let status: AgentStatusResult = serde_json::from_value(status).unwrap();
+        assert_eq!(status.uptime_ms, 2000);
+        assert_eq!(status.active_sessions, 0);
+        assert_eq!(status.resources.pid, Some(std::process::id()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_session_stats() {
+        let server = Server::new(SlowAgent::new(Duration::ZERO)).with_session_stats(true);
+        call(&server, method::INITIALIZE, initialize_params()).await.unwrap();
+        call(&server, method::SESSION_NEW, serde_json::json!({"session_id": "s1"})).await.unwrap();
+        let content = serde_json::json!([{"type": "text", "text": "block"}]);
+        let params = serde_json::json!({"session_id": "s1", "content": content});
+        let running = tokio::spawn({
+            let server = server.clone();
+            async move { call(&server, method::SESSION_PROMPT, params).await }
+        });
+        while !server.active_prompts.lock().await.contains_key("s1") {
+            tokio::task::yield_now().await;
+        }
+        tokio::time::advance(Duration::from_millis(1500)).await;
+        call(&server, method::SESSION_CANCEL, serde_json::json!({"session_id": "s1"})).await.unwrap();
+        running.await.unwrap().unwrap();
+
+        let stats = call(&server, method::SESSION_STATS, serde_json::json!({"session_id": "s1"})).await.unwrap();
+        let stats: SessionStats = serde_json::from_value(stats).unwrap();
+        assert_eq!(stats.prompts, 1);
+        assert_eq!(stats.bytes_received, content.to_string().len() as u64);
+        assert_eq!(stats.prompt_time_ms, 1500);
+        assert_eq!(stats, server.sessions().stats("s1").await.unwrap());
+
+        let err = call(&server, method::SESSION_STATS, serde_json::json!({"session_id": "s2"})).await.unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_STATE);
+
+        // Hidden unless the host opts in
+        let server = Server::new(SlowAgent::new(Duration::ZERO));
+        call(&server, method::INITIALIZE, initialize_params()).await.unwrap();
+        let err = call(&server, method::SESSION_STATS, serde_json::json!({"session_id": "s1"})).await.unwrap_err();
+        assert_eq!(err.code(), codes::METHOD_NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_queued_prompts() {
+        let server = Server::new(SlowAgent::new(Duration::ZERO));
+        call(&server, method::INITIALIZE, initialize_params()).await.unwrap();
+        call(&server, method::SESSION_NEW, serde_json::json!({"session_id": "s1"})).await.unwrap();
+        let (update_tx, mut update_rx) = mpsc::channel(8);
+        let prompt = |text: &str| {
+            let server = server.clone();
+            let update_tx = update_tx.clone();
+            let params = serde_json::json!({"session_id": "s1", "content": [{"type": "text", "text": text}]});
+            tokio::spawn(async move {
+                call_with_updates(&server, method::SESSION_PROMPT, params, update_tx).await
+            })
+        };
+        let queued = |update: SessionUpdate| match update.update_type {
+            SessionUpdateType::Queued { prompt_id, position } => (prompt_id, position),
+            other => panic!("expected queued, got {:?}", other),
+        };
+
+        let running = prompt("block");
+        while !server.active_prompts.lock().await.contains_key("s1") {
+            tokio::task::yield_now().await;
+        }
+        let second = prompt("second");
+        let (second_id, position) = queued(update_rx.recv().await.unwrap());
+        assert_eq!(position, 1);
+        let third = prompt("third");
+        assert_eq!(queued(update_rx.recv().await.unwrap()).1, 2);
+        assert_eq!(server.context(&server.connect(mpsc::channel(1).0)).queued_prompts("s1"), 2);
+        let status = call(&server, method::AGENT_STATUS, Value::Null).await.unwrap();
+        let status: AgentStatusResult = serde_json::from_value(status).unwrap();
+        assert_eq!((status.active_sessions, status.running_prompts, status.queued_prompts), (1, 1, 2));
+
+        // Dropping the second moves the third up
+        let cancel = serde_json::json!({"session_id": "s1", "prompt_id": second_id});
+        call(&server, method::SESSION_CANCEL, cancel.clone()).await.unwrap();
+        assert_eq!(second.await.unwrap().unwrap()["status"], "cancelled");
+        assert_eq!(queued(update_rx.recv().await.unwrap()).1, 1);
+        let err = call(&server, method::SESSION_CANCEL, cancel).await.unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_STATE);
+
+        call(&server, method::SESSION_CANCEL, serde_json::json!({"session_id": "s1"})).await.unwrap();
+        assert_eq!(third.await.unwrap().unwrap()["status"], "cancelled");
+        assert_eq!(running.await.unwrap().unwrap()["status"], "ok");
     }
 }
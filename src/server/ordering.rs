@@ -0,0 +1,129 @@
+//! In-order delivery of responses within a session.
+//!
+//! Requests run concurrently, so a quick `session/cancel` can be answered
+//! before the `session/prompt` sent ahead of it. With ordered responses
+//! enabled, every request that names a `session_id` is numbered as it is
+//! read; its response carries that number as `seq` and is held back until
+//! the responses to all earlier requests in the session have been written.
+
+use std::collections::{BTreeMap, HashMap};
+use tokio::sync::{mpsc, Mutex};
+
+use crate::protocol::*;
+
+/// A request's place in its session's response order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Ticket {
+    session_id: String,
+    seq: u64,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    /// Last sequence number handed out.
+    issued: u64,
+    /// Last sequence number written.
+    written: u64,
+    /// Responses waiting for earlier ones.
+    held: BTreeMap<u64, String>,
+}
+
+/// Writes responses to the client in per-session request order.
+#[derive(Debug)]
+pub(crate) struct ResponseOrder {
+    tx: mpsc::Sender<String>,
+    sessions: Mutex<HashMap<String, Queue>>,
+}
+
+impl ResponseOrder {
+    /// Write released responses into `tx`.
+    pub(crate) fn new(tx: mpsc::Sender<String>) -> Self {
+        Self {
+            tx,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Number the next request in `session_id`. Must be called in the order
+    /// requests are read.
+    pub(crate) async fn reserve(&self, session_id: &str) -> Ticket {
+        let mut sessions = self.sessions.lock().await;
+        let queue = sessions.entry(session_id.to_string()).or_default();
+        queue.issued += 1;
+        Ticket {
+            session_id: session_id.to_string(),
+            seq: queue.issued,
+        }
+    }
+
+    /// Send `response` once every earlier response in its session is sent.
+    pub(crate) async fn send(&self, ticket: Ticket, response: &JsonRpcResponse) -> AcpResult<()> {
+        let mut message = serde_json::to_value(response)?;
+        message["seq"] = ticket.seq.into();
+
+        // Hold the lock while sending so released batches can't interleave
+        let mut sessions = self.sessions.lock().await;
+        let queue = sessions.entry(ticket.session_id).or_default();
+        queue.held.insert(ticket.seq, message.to_string());
+        while let Some(message) = queue.held.remove(&(queue.written + 1)) {
+            queue.written += 1;
+            self.tx
+                .send(message)
+                .await
+                .map_err(|e| AcpError::ChannelError(e.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+/// The session a raw request is about, if any.
+pub(crate) fn request_session(msg: &serde_json::Value) -> Option<&str> {
+    msg.get("id")?;
+    msg.get("params")?.get("session_id")?.as_str()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(id: i64) -> JsonRpcResponse {
+        JsonRpcResponse {
+            jsonrpc: "2.0".to_string(),
+            id: RequestId::Number(id),
+            result: Some(serde_json::json!({})),
+            error: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_responses_follow_request_order() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let order = ResponseOrder::new(tx);
+
+        let first = order.reserve("a").await;
+        let second = order.reserve("a").await;
+        let other = order.reserve("b").await;
+
+        // The second response waits for the first; other sessions don't
+        order.send(second, &response(2)).await.unwrap();
+        order.send(other, &response(3)).await.unwrap();
+        let msg: serde_json::Value = serde_json::from_str(&rx.recv().await.unwrap()).unwrap();
+        assert_eq!((msg["id"].as_i64(), msg["seq"].as_u64()), (Some(3), Some(1)));
+        assert!(rx.try_recv().is_err());
+
+        order.send(first, &response(1)).await.unwrap();
+        let ids: Vec<(i64, u64)> = std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|m| serde_json::from_str::<serde_json::Value>(&m).unwrap())
+            .map(|m| (m["id"].as_i64().unwrap(), m["seq"].as_u64().unwrap()))
+            .collect();
+        assert_eq!(ids, vec![(1, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn test_request_session() {
+        let request = serde_json::json!({"id": 1, "method": "session/prompt", "params": {"session_id": "s1"}});
+        assert_eq!(request_session(&request), Some("s1"));
+        let notification = serde_json::json!({"method": "session/cancel", "params": {"session_id": "s1"}});
+        assert_eq!(request_session(&notification), None);
+    }
+}
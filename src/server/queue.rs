@@ -0,0 +1,259 @@
+//! Queueing of prompts within a session.
+//!
+//! A session runs one prompt at a time. Prompts that arrive while another is
+//! running wait in line; each is told its place with a `queued` update, and
+//! told again whenever the line moves.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+use super::SessionHandle;
+use crate::protocol::*;
+
+/// A prompt waiting for its turn.
+#[derive(Debug)]
+struct Waiter {
+    prompt_id: String,
+    session: SessionHandle,
+    ready: oneshot::Sender<()>,
+}
+
+#[derive(Debug, Default)]
+struct Line {
+    running: bool,
+    waiting: VecDeque<Waiter>,
+}
+
+/// The prompt queues of all sessions. Cloning is cheap.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PromptQueues {
+    lines: Arc<Mutex<HashMap<String, Line>>>,
+}
+
+/// Outcome of [`PromptQueues::enter`].
+pub(crate) enum Entry {
+    /// The session was idle; the prompt may run now.
+    Running(Turn),
+    /// The prompt is queued behind `position` - 1 others.
+    Queued {
+        prompt_id: String,
+        position: usize,
+        wait: Wait,
+    },
+}
+
+/// A queued prompt's claim on a future turn.
+pub(crate) struct Wait {
+    queues: PromptQueues,
+    session_id: String,
+    ready: Option<oneshot::Receiver<()>>,
+}
+
+impl Wait {
+    /// Wait for the prompt's turn. Returns `None` if it was cancelled.
+    pub(crate) async fn turn(mut self) -> Option<Turn> {
+        let granted = self.ready.as_mut()?.await.is_ok();
+        self.ready = None;
+        granted.then(|| self.queues.turn(self.session_id.clone()))
+    }
+}
+
+impl Drop for Wait {
+    fn drop(&mut self) {
+        // Pass on a turn that was granted but never taken
+        if let Some(mut ready) = self.ready.take() {
+            if ready.try_recv().is_ok() {
+                self.queues.advance(&self.session_id);
+            }
+        }
+    }
+}
+
+/// The right to run a prompt. Dropping it lets the next prompt in.
+pub(crate) struct Turn {
+    queues: PromptQueues,
+    session_id: String,
+}
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        self.queues.advance(&self.session_id);
+    }
+}
+
+impl PromptQueues {
+    /// Join the line for `session`.
+    pub(crate) fn enter(&self, session: &SessionHandle) -> Entry {
+        let session_id = session.session_id().to_string();
+        let mut lines = self.lines.lock().unwrap();
+        let line = lines.entry(session_id.clone()).or_default();
+        if !line.running {
+            line.running = true;
+            return Entry::Running(self.turn(session_id));
+        }
+        let prompt_id = format!("prompt_{}", uuid::Uuid::new_v4());
+        let (tx, rx) = oneshot::channel();
+        line.waiting.push_back(Waiter {
+            prompt_id: prompt_id.clone(),
+            session: session.clone(),
+            ready: tx,
+        });
+        Entry::Queued {
+            prompt_id,
+            position: line.waiting.len(),
+            wait: Wait {
+                queues: self.clone(),
+                session_id,
+                ready: Some(rx),
+            },
+        }
+    }
+
+    fn turn(&self, session_id: String) -> Turn {
+        Turn {
+            queues: self.clone(),
+            session_id,
+        }
+    }
+
+    /// Number of prompts waiting in `session_id`.
+    pub(crate) fn len(&self, session_id: &str) -> usize {
+        self.lines
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .map_or(0, |line| line.waiting.len())
+    }
+
+    /// Number of prompts waiting in all sessions.
+    pub(crate) fn total(&self) -> usize {
+        self.lines.lock().unwrap().values().map(|line| line.waiting.len()).sum()
+    }
+
+    /// Drop queued prompts in `session_id`: the one named `prompt_id`, or all
+    /// of them. Returns how many were removed.
+    pub(crate) fn cancel(&self, session_id: &str, prompt_id: Option<&str>) -> usize {
+        let mut lines = self.lines.lock().unwrap();
+        let Some(line) = lines.get_mut(session_id) else {
+            return 0;
+        };
+        let before = line.waiting.len();
+        line.waiting
+            .retain(|waiter| prompt_id.is_some_and(|id| id != waiter.prompt_id));
+        let removed = before - line.waiting.len();
+        if removed > 0 {
+            announce(&line.waiting);
+        }
+        removed
+    }
+
+    /// Drop every queued prompt in every session.
+    pub(crate) fn clear(&self) {
+        for line in self.lines.lock().unwrap().values_mut() {
+            line.waiting.clear();
+        }
+    }
+
+    /// Hand the session to the next live prompt in line.
+    fn advance(&self, session_id: &str) {
+        let mut lines = self.lines.lock().unwrap();
+        let Some(line) = lines.get_mut(session_id) else {
+            return;
+        };
+        // Prompts whose requests were dropped while waiting are skipped
+        while let Some(waiter) = line.waiting.pop_front() {
+            if waiter.ready.send(()).is_ok() {
+                announce(&line.waiting);
+                return;
+            }
+        }
+        lines.remove(session_id);
+    }
+}
+
+/// Tell each waiting prompt its new position.
+fn announce(waiting: &VecDeque<Waiter>) {
+    let updates: Vec<_> = waiting
+        .iter()
+        .enumerate()
+        .map(|(i, waiter)| (waiter.session.clone(), waiter.prompt_id.clone(), i + 1))
+        .collect();
+    if updates.is_empty() {
+        return;
+    }
+    tokio::spawn(async move {
+        for (session, prompt_id, position) in updates {
+            let _ = session
+                .send(SessionUpdateType::Queued { prompt_id, position })
+                .await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    fn queued(entry: Entry) -> (String, usize, Wait) {
+        match entry {
+            Entry::Queued {
+                prompt_id,
+                position,
+                wait,
+            } => (prompt_id, position, wait),
+            Entry::Running(_) => panic!("expected the prompt to queue"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prompts_take_turns() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let session = SessionHandle::new("s1", tx);
+        let queues = PromptQueues::default();
+
+        let Entry::Running(first) = queues.enter(&session) else {
+            panic!("expected the first prompt to run");
+        };
+        let (_, position, second) = queued(queues.enter(&session));
+        assert_eq!(position, 1);
+        let (third_id, position, third) = queued(queues.enter(&session));
+        assert_eq!(position, 2);
+        assert_eq!(queues.len("s1"), 2);
+
+        drop(first);
+        let second = second.turn().await.unwrap();
+        let update = rx.recv().await.unwrap();
+        assert!(matches!(
+            update.update_type,
+            SessionUpdateType::Queued { prompt_id, position: 1 } if prompt_id == third_id
+        ));
+
+        // A turn granted to a prompt that gave up is passed on
+        drop(second);
+        drop(third);
+        assert_eq!(queues.len("s1"), 0);
+        assert!(matches!(queues.enter(&session), Entry::Running(_)));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_queued() {
+        let (tx, _rx) = mpsc::channel(8);
+        let session = SessionHandle::new("s1", tx);
+        let queues = PromptQueues::default();
+
+        let _running = queues.enter(&session);
+        let (first_id, _, first) = queued(queues.enter(&session));
+        let (_, _, second) = queued(queues.enter(&session));
+        let (_, _, third) = queued(queues.enter(&session));
+
+        assert_eq!(queues.cancel("s1", Some(&first_id)), 1);
+        assert!(first.turn().await.is_none());
+        assert_eq!(queues.len("s1"), 2);
+
+        assert_eq!(queues.cancel("s1", None), 2);
+        assert!(second.turn().await.is_none());
+        assert!(third.turn().await.is_none());
+    }
+}
@@ -0,0 +1,372 @@
+//! Rate limiting for clients sharing an agent.
+//!
+//! Install a [`RateLimit`] with [`Server::with_rate_limit`](super::Server::with_rate_limit).
+//! Requests over the limit fail with `RATE_LIMITED`; session updates over the
+//! bandwidth limit are delayed rather than dropped. `session/cancel` and
+//! `ping` are never limited, so a throttled client can still stop a prompt.
+
+use async_trait::async_trait;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::time::{Duration, Instant};
+
+use super::{Middleware, Next};
+use crate::protocol::*;
+
+/// Token bucket refilled continuously at `rate` tokens per second.
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// Create a full bucket that allows bursts of up to one second's worth.
+    ///
+    /// # Panics
+    ///
+    /// If `rate` isn't a positive, finite number.
+    pub(crate) fn new(rate: f64) -> Self {
+        assert_rate(rate);
+        let capacity = rate.max(1.0);
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// Whether the bucket has refilled completely, so that it allows what
+    /// a new one would.
+    pub(crate) fn is_full(&mut self) -> bool {
+        self.refill();
+        self.tokens >= self.capacity
+    }
+
+    /// Take `amount` tokens if available.
+    pub(crate) fn try_take(&mut self, amount: f64) -> bool {
+        self.refill();
+        if self.tokens >= amount {
+            self.tokens -= amount;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Take `amount` tokens, going into debt if needed. Returns how long to
+    /// wait before the debt is paid off.
+    pub(crate) fn reserve(&mut self, amount: f64) -> Duration {
+        self.refill();
+        self.tokens -= amount;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// Check that `rate` can refill a bucket: a rate of zero, below zero or
+/// NaN never would, and an infinite one makes waits meaningless.
+fn assert_rate(rate: f64) {
+    assert!(
+        rate.is_finite() && rate > 0.0,
+        "rate must be positive and finite, got {}",
+        rate
+    );
+}
+
+/// Session requests between sweeps of the session buckets that have refilled.
+const PRUNE_EVERY: usize = 256;
+
+/// Limits on how hard a client may drive the agent.
+///
+/// All limits are off by default.
+///
+/// ```rust,no_run
+/// # use heroacp::server::{Agent, RateLimit, Server};
+/// # fn demo(agent: impl Agent) {
+/// let server = Server::new(agent).with_rate_limit(
+///     RateLimit::new()
+///         .requests_per_second(50.0)
+///         .session_requests_per_second(10.0)
+///         .max_concurrent_prompts(4)
+///         .update_bytes_per_second(1024 * 1024),
+/// );
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct RateLimit {
+    connection: Option<Mutex<TokenBucket>>,
+    session_rate: Option<f64>,
+    sessions: Mutex<HashMap<String, TokenBucket>>,
+    session_checks: AtomicUsize,
+    max_concurrent_prompts: Option<usize>,
+    active_prompts: Arc<AtomicUsize>,
+    update_bytes_per_second: Option<u64>,
+}
+
+impl RateLimit {
+    /// Create a rate limit with no limits set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit requests across the whole connection.
+    ///
+    /// # Panics
+    ///
+    /// If `rate` isn't a positive, finite number.
+    pub fn requests_per_second(mut self, rate: f64) -> Self {
+        self.connection = Some(Mutex::new(TokenBucket::new(rate)));
+        self
+    }
+
+    /// Limit requests that name a `session_id`, per session.
+    ///
+    /// # Panics
+    ///
+    /// If `rate` isn't a positive, finite number.
+    pub fn session_requests_per_second(mut self, rate: f64) -> Self {
+        assert_rate(rate);
+        self.session_rate = Some(rate);
+        self
+    }
+
+    /// Limit how many prompts may run at once across all sessions.
+    pub fn max_concurrent_prompts(mut self, max: usize) -> Self {
+        self.max_concurrent_prompts = Some(max);
+        self
+    }
+
+    /// Limit the bytes of session updates written to the client per second.
+    ///
+    /// # Panics
+    ///
+    /// If `rate` is zero.
+    pub fn update_bytes_per_second(mut self, rate: u64) -> Self {
+        assert_rate(rate as f64);
+        self.update_bytes_per_second = Some(rate);
+        self
+    }
+
+    /// The configured update bandwidth, if any.
+    pub fn update_bandwidth(&self) -> Option<u64> {
+        self.update_bytes_per_second
+    }
+
+    fn check_rate(&self, request: &JsonRpcRequest) -> AcpResult<()> {
+        if matches!(request.method.parse::<Method>(), Ok(Method::SessionCancel | Method::Ping)) {
+            return Ok(());
+        }
+        if let Some(bucket) = &self.connection {
+            if !bucket.lock().unwrap().try_take(1.0) {
+                return Err(AcpError::RateLimited("too many requests".to_string()));
+            }
+        }
+        let session_id = request
+            .params
+            .as_ref()
+            .and_then(|p| p.get("session_id"))
+            .and_then(|s| s.as_str());
+        if let (Some(rate), Some(session_id)) = (self.session_rate, session_id) {
+            let mut sessions = self.sessions.lock().unwrap();
+            // Only buckets still refilling matter, or the map would grow
+            // with every session ever named
+            if self.session_checks.fetch_add(1, Ordering::Relaxed) % PRUNE_EVERY == PRUNE_EVERY - 1 {
+                sessions.retain(|_, bucket| !bucket.is_full());
+            }
+            let bucket = sessions
+                .entry(session_id.to_string())
+                .or_insert_with(|| TokenBucket::new(rate));
+            if !bucket.try_take(1.0) {
+                return Err(AcpError::RateLimited(format!(
+                    "too many requests for session {}",
+                    session_id
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Releases a prompt slot when the prompt finishes or is dropped.
+struct PromptSlot(Arc<AtomicUsize>);
+
+impl Drop for PromptSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[async_trait]
+impl Middleware for RateLimit {
+    async fn handle(&self, request: JsonRpcRequest, next: Next<'_>) -> AcpResult<Value> {
+        self.check_rate(&request)?;
+
+        let is_prompt = matches!(
+            request.method.parse::<Method>(),
+            Ok(Method::SessionPrompt | Method::SessionPromptStreamEnd)
+        );
+        let _slot = match self.max_concurrent_prompts {
+            Some(max) if is_prompt => {
+                if self.active_prompts.fetch_add(1, Ordering::SeqCst) >= max {
+                    self.active_prompts.fetch_sub(1, Ordering::SeqCst);
+                    return Err(AcpError::RateLimited(format!(
+                        "at most {} prompts may run at once",
+                        max
+                    )));
+                }
+                Some(PromptSlot(self.active_prompts.clone()))
+            }
+            _ => None,
+        };
+
+        next.run(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::middleware::{Endpoint, EndpointFuture};
+    use super::*;
+
+    fn request(method: &str, session_id: &str) -> JsonRpcRequest {
+        JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: Some(RequestId::Number(1)),
+            method: method.to_string(),
+            params: Some(serde_json::json!({"session_id": session_id})),
+        }
+    }
+
+    fn endpoint() -> Box<Endpoint> {
+        Box::new(|_request: JsonRpcRequest| -> EndpointFuture {
+            Box::pin(async {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                Ok(Value::Null)
+            })
+        })
+    }
+
+    async fn run(layers: &[Arc<dyn Middleware>], request: JsonRpcRequest) -> AcpResult<Value> {
+        let endpoint = endpoint();
+        Next::new(layers, endpoint.as_ref()).run(request).await
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let mut bucket = TokenBucket::new(2.0);
+        assert!(bucket.try_take(1.0));
+        assert!(bucket.try_take(1.0));
+        assert!(!bucket.try_take(1.0));
+        assert!(bucket.reserve(1.0) > Duration::from_millis(400));
+    }
+
+    #[test]
+    fn test_rates_must_be_positive_and_finite() {
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let result = std::panic::catch_unwind(|| RateLimit::new().requests_per_second(rate));
+            assert!(result.is_err(), "accepted {}", rate);
+            let result = std::panic::catch_unwind(|| RateLimit::new().session_requests_per_second(rate));
+            assert!(result.is_err(), "accepted {}", rate);
+        }
+        assert!(std::panic::catch_unwind(|| RateLimit::new().update_bytes_per_second(0)).is_err());
+        RateLimit::new()
+            .requests_per_second(0.5)
+            .session_requests_per_second(1e9)
+            .update_bytes_per_second(1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_token_bucket_refills() {
+        let mut bucket = TokenBucket::new(2.0);
+        assert!(bucket.try_take(2.0));
+        assert!(!bucket.try_take(1.0));
+
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert!(bucket.try_take(1.0));
+        assert!(!bucket.try_take(1.0));
+
+        // Paying off debt takes as long as `reserve` says
+        let wait = bucket.reserve(3.0);
+        assert_eq!(wait, Duration::from_millis(1500));
+        tokio::time::advance(wait).await;
+        assert!(!bucket.try_take(0.1));
+        tokio::time::advance(Duration::from_millis(50)).await;
+        assert!(bucket.try_take(0.1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_request_rates() {
+        let layers: Vec<Arc<dyn Middleware>> = vec![Arc::new(
+            RateLimit::new()
+                .requests_per_second(3.0)
+                .session_requests_per_second(1.0),
+        )];
+
+        run(&layers, request(method::SESSION_PROMPT, "a")).await.unwrap();
+        let err = run(&layers, request(method::SESSION_PROMPT, "a")).await.unwrap_err();
+        assert_eq!(err.code(), codes::RATE_LIMITED);
+
+        run(&layers, request(method::SESSION_PROMPT, "b")).await.unwrap();
+        let err = run(&layers, request(method::SESSION_PROMPT, "c")).await.unwrap_err();
+        assert_eq!(err.code(), codes::RATE_LIMITED);
+
+        // A throttled client can still cancel and ping
+        run(&layers, request(method::SESSION_CANCEL, "a")).await.unwrap();
+        run(&layers, request(method::PING, "")).await.unwrap();
+
+        // A second later both limits allow requests again
+        tokio::time::advance(Duration::from_secs(1)).await;
+        run(&layers, request(method::SESSION_PROMPT, "a")).await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_session_buckets_are_dropped_once_full() {
+        let limit = Arc::new(RateLimit::new().session_requests_per_second(10.0));
+        let layers: Vec<Arc<dyn Middleware>> = vec![limit.clone()];
+        for session_id in ["a", "b", "c"] {
+            run(&layers, request(method::SESSION_PROMPT, session_id)).await.unwrap();
+        }
+        assert_eq!(limit.sessions.lock().unwrap().len(), 3);
+
+        // Refilled buckets go at the next sweep, not on every request
+        tokio::time::advance(Duration::from_secs(1)).await;
+        run(&layers, request(method::SESSION_PROMPT, "d")).await.unwrap();
+        assert_eq!(limit.sessions.lock().unwrap().len(), 4);
+        for _ in 4..PRUNE_EVERY {
+            let _ = limit.check_rate(&request(method::SESSION_PROMPT, "d"));
+        }
+        assert_eq!(limit.sessions.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_prompts() {
+        let layers: Vec<Arc<dyn Middleware>> =
+            vec![Arc::new(RateLimit::new().max_concurrent_prompts(1))];
+
+        let (first, second) = tokio::join!(
+            run(&layers, request(method::SESSION_PROMPT, "a")),
+            run(&layers, request(method::SESSION_PROMPT, "b")),
+        );
+        assert!(first.is_ok());
+        assert_eq!(second.unwrap_err().code(), codes::RATE_LIMITED);
+
+        // The slot is released once the prompt finishes
+        run(&layers, request(method::SESSION_PROMPT, "b")).await.unwrap();
+    }
+}
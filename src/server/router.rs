@@ -0,0 +1,317 @@
+//! Hosting several agents behind one server.
+
+use async_trait::async_trait;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use super::{Agent, AgentContext, SessionHandle};
+use crate::protocol::*;
+
+/// An [`Agent`] that dispatches each session to one of several named agents.
+///
+/// `session/new` picks the agent named by `agent`, falling back to the agent
+/// named by `mode`, and then to the first registered agent. Later requests for
+/// the session go to the same agent, until the client disconnects or the
+/// server shuts down. `initialize` is forwarded to every agent and their
+/// capabilities are merged.
+///
+/// ```rust,no_run
+/// # use heroacp::server::{Agent, AgentRouter, Server};
+/// # use heroacp::protocol::AgentInfo;
+/// # fn demo(coder: impl Agent, reviewer: impl Agent) {
+/// let router = AgentRouter::new(AgentInfo {
+///     name: "team".to_string(),
+///     version: "1.0.0".to_string(),
+/// })
+/// .route("coder", coder)
+/// .route("reviewer", reviewer);
+/// let server = Server::new(router);
+/// # }
+/// ```
+pub struct AgentRouter {
+    info: AgentInfo,
+    agents: Vec<(String, Arc<dyn Agent>)>,
+    sessions: RwLock<HashMap<String, usize>>,
+}
+
+impl AgentRouter {
+    /// Create a router that reports `info` to clients.
+    pub fn new(info: AgentInfo) -> Self {
+        Self {
+            info,
+            agents: Vec::new(),
+            sessions: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Register an agent under `name`.
+    ///
+    /// The first registered agent handles sessions that don't name one.
+    pub fn route(mut self, name: impl Into<String>, agent: impl Agent) -> Self {
+        self.agents.push((name.into(), Arc::new(agent)));
+        self
+    }
+
+    /// Names of the registered agents.
+    pub fn agent_names(&self) -> impl Iterator<Item = &str> {
+        self.agents.iter().map(|(name, _)| name.as_str())
+    }
+
+    fn find(&self, name: &str) -> Option<usize> {
+        self.agents.iter().position(|(n, _)| n == name)
+    }
+
+    fn select(&self, params: &SessionNewParams) -> AcpResult<usize> {
+        if self.agents.is_empty() {
+            return Err(AcpError::InternalError("No agents registered".to_string()));
+        }
+        if let Some(name) = &params.agent {
+            return self
+                .find(name)
+                .ok_or_else(|| AcpError::InvalidParams(format!("Unknown agent: {}", name)));
+        }
+        Ok(params.mode.as_deref().and_then(|mode| self.find(mode)).unwrap_or(0))
+    }
+
+    async fn agent_for(&self, session_id: &str) -> AcpResult<&Arc<dyn Agent>> {
+        let index = self
+            .sessions
+            .read()
+            .await
+            .get(session_id)
+            .copied()
+            .ok_or_else(|| AcpError::InvalidState(format!("Unknown session: {}", session_id)))?;
+        Ok(&self.agents[index].1)
+    }
+}
+
+#[async_trait]
+impl Agent for AgentRouter {
+    async fn initialize(&self, params: InitializeParams) -> AcpResult<InitializeResult> {
+        let mut capabilities = AgentCapabilities::default();
+        let mut instructions = Vec::new();
+        for (_, agent) in &self.agents {
+            let result = agent.initialize(params.clone()).await?;
+            capabilities.merge(result.capabilities);
+            instructions.extend(result.instructions);
+        }
+        Ok(InitializeResult {
+            agent_info: self.info.clone(),
+            capabilities,
+            instructions: (!instructions.is_empty()).then(|| instructions.join("\n\n")),
+        })
+    }
+
+    async fn authenticate(&self, params: AuthenticateParams) -> AcpResult<AuthenticateResult> {
+        for (_, agent) in &self.agents {
+            if !agent.authenticate(params.clone()).await?.success {
+                return Ok(AuthenticateResult { success: false });
+            }
+        }
+        Ok(AuthenticateResult { success: true })
+    }
+
+    async fn session_new(
+        &self,
+        params: SessionNewParams,
+        ctx: AgentContext,
+    ) -> AcpResult<SessionNewResult> {
+        let index = self.select(&params)?;
+        let result = self.agents[index].1.session_new(params, ctx).await?;
+        self.sessions
+            .write()
+            .await
+            .insert(result.session_id.clone(), index);
+        Ok(result)
+    }
+
+    async fn session_load(
+        &self,
+        params: SessionLoadParams,
+        ctx: AgentContext,
+    ) -> AcpResult<SessionLoadResult> {
+        // Sessions from a previous run are restored by the default agent
+        let known = self.sessions.read().await.get(&params.session_id).copied();
+        let index = known.unwrap_or(0);
+        let agent = self
+            .agents
+            .get(index)
+            .ok_or_else(|| AcpError::InternalError("No agents registered".to_string()))?;
+        let result = agent.1.session_load(params, ctx).await?;
+        if result.loaded {
+            self.sessions
+                .write()
+                .await
+                .insert(result.session_id.clone(), index);
+        }
+        Ok(result)
+    }
+
+    async fn session_prompt(
+        &self,
+        params: SessionPromptParams,
+        session: SessionHandle,
+        ctx: AgentContext,
+    ) -> AcpResult<SessionPromptResult> {
+        let agent = self.agent_for(&params.session_id).await?;
+        agent.session_prompt(params, session, ctx).await
+    }
+
+    async fn session_cancel(&self, params: SessionCancelParams, ctx: AgentContext) -> AcpResult<()> {
+        let agent = self.agent_for(&params.session_id).await?;
+        agent.session_cancel(params, ctx).await
+    }
+
+    async fn session_set_model(&self, params: SessionSetModelParams, ctx: AgentContext) -> AcpResult<()> {
+        let agent = self.agent_for(&params.session_id).await?;
+        agent.session_set_model(params, ctx).await
+    }
+
+    /// Offers the method to each agent in turn; the first answer other than
+    /// `METHOD_NOT_FOUND` wins.
+    async fn handle_custom(&self, method: &str, params: Value) -> AcpResult<Value> {
+        for (_, agent) in &self.agents {
+            match agent.handle_custom(method, params.clone()).await {
+                Err(AcpError::MethodNotFound(_)) => continue,
+                result => return result,
+            }
+        }
+        Err(AcpError::MethodNotFound(method.to_string()))
+    }
+
+    async fn on_client_connected(&self, client: ClientInfo) -> AcpResult<()> {
+        for (_, agent) in &self.agents {
+            agent.on_client_connected(client.clone()).await?;
+        }
+        Ok(())
+    }
+
+    async fn on_client_disconnected(&self) {
+        self.sessions.write().await.clear();
+        for (_, agent) in &self.agents {
+            agent.on_client_disconnected().await;
+        }
+    }
+
+    async fn on_shutdown(&self) {
+        self.sessions.write().await.clear();
+        for (_, agent) in &self.agents {
+            agent.on_shutdown().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{AgentConnection, SessionManager};
+    use super::*;
+    use crate::testing::fixtures::*;
+    use tokio::sync::mpsc;
+
+    struct Named(&'static str);
+
+    #[async_trait]
+    impl Agent for Named {
+        async fn initialize(&self, _params: InitializeParams) -> AcpResult<InitializeResult> {
+            let mut result = init_result(self.0).with_instructions(&format!("I am {}", self.0));
+            result.capabilities.supported_modes = vec![self.0.to_string()];
+            Ok(result)
+        }
+
+        async fn session_new(
+            &self,
+            params: SessionNewParams,
+            _ctx: AgentContext,
+        ) -> AcpResult<SessionNewResult> {
+            Ok(SessionNewResult {
+                session_id: params.session_id,
+            })
+        }
+
+        async fn session_prompt(
+            &self,
+            _params: SessionPromptParams,
+            _session: SessionHandle,
+            _ctx: AgentContext,
+        ) -> AcpResult<SessionPromptResult> {
+            Ok(SessionPromptResult {
+                status: self.0.to_string(),
+            })
+        }
+    }
+
+    fn router() -> AgentRouter {
+        AgentRouter::new(AgentInfo {
+            name: "team".to_string(),
+            version: "1.0.0".to_string(),
+        })
+        .route("coder", Named("coder"))
+        .route("reviewer", Named("reviewer"))
+    }
+
+    fn ctx() -> AgentContext {
+        let (tx, _rx) = mpsc::channel(1);
+        let connection = AgentConnection::new(tx, Default::default());
+        AgentContext::new(SessionManager::new(), connection)
+    }
+
+    async fn open(router: &AgentRouter, id: &str, mode: Option<&str>, agent: Option<&str>) -> AcpResult<String> {
+        let params = SessionNewParams {
+            mode: mode.map(str::to_string),
+            agent: agent.map(str::to_string),
+            ..session_new().in_session(id)
+        };
+        router.session_new(params, ctx()).await?;
+        let prompt = prompt("hi").in_session(id);
+        let (tx, _rx) = mpsc::channel(1);
+        let session = SessionHandle::new(id, tx);
+        Ok(router.session_prompt(prompt, session, ctx()).await?.status)
+    }
+
+    #[tokio::test]
+    async fn test_routes_sessions() {
+        let router = router();
+        assert_eq!(open(&router, "a", None, Some("reviewer")).await.unwrap(), "reviewer");
+        assert_eq!(open(&router, "b", Some("reviewer"), None).await.unwrap(), "reviewer");
+        assert_eq!(open(&router, "c", Some("ask"), None).await.unwrap(), "coder");
+        assert_eq!(open(&router, "d", None, None).await.unwrap(), "coder");
+    }
+
+    #[tokio::test]
+    async fn test_unknown_agent_and_session() {
+        let router = router();
+        let err = open(&router, "a", None, Some("tester")).await.unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_PARAMS);
+
+        let (tx, _rx) = mpsc::channel(1);
+        let prompt = SessionPromptParams {
+            content: vec![],
+            ..prompt("").in_session("missing")
+        };
+        let session = SessionHandle::new("missing", tx);
+        let err = router.session_prompt(prompt, session, ctx()).await.unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_STATE);
+    }
+
+    #[tokio::test]
+    async fn test_forgets_sessions_when_the_client_leaves() {
+        let router = router();
+        open(&router, "a", None, Some("reviewer")).await.unwrap();
+        router.on_client_disconnected().await;
+        assert!(router.sessions.read().await.is_empty());
+
+        open(&router, "b", None, None).await.unwrap();
+        router.on_shutdown().await;
+        assert!(router.sessions.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_initialize_merges_agents() {
+        let result = router().initialize(init_params()).await.unwrap();
+        assert_eq!(result.agent_info.name, "team");
+        assert_eq!(result.capabilities.supported_modes, vec!["coder", "reviewer"]);
+        assert_eq!(result.instructions.unwrap(), "I am coder\n\nI am reviewer");
+    }
+}
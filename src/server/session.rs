@@ -0,0 +1,302 @@
+//! Built-in session tracking for the server SDK.
+//!
+//! The [`Server`](super::Server) records every session the agent creates or
+//! loads in a [`SessionManager`], so agents don't have to keep their own
+//! registry. Agents reach it through [`AgentContext::sessions`](super::AgentContext::sessions).
+//!
+//! Alongside each session the server counts what it used, in
+//! [`SessionInfo::stats`], for hosts that bill or monitor agents.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::RwLock;
+
+use crate::protocol::{AcpError, AcpResult, SessionStats, SessionUpdate, SessionUpdateType};
+
+/// Where a session is in its lifecycle.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SessionState {
+    /// Ready for prompts.
+    #[default]
+    Active,
+    /// The client cancelled the session's last prompt. The next prompt
+    /// makes the session active again.
+    Cancelled,
+}
+
+/// State recorded for a single session.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionInfo {
+    /// Session identifier.
+    pub session_id: String,
+    /// Operating mode requested when the session was created.
+    pub mode: Option<String>,
+    /// Model the client switched the session to, if it did.
+    pub model: Option<String>,
+    /// Working directory of the session.
+    pub cwd: String,
+    /// When the session was created or loaded.
+    pub created_at: SystemTime,
+    /// When the session last received a prompt.
+    pub last_active: SystemTime,
+    /// Number of prompts handled in this session.
+    pub prompt_count: u64,
+    /// Lifecycle state of the session.
+    pub state: SessionState,
+    /// Usage of the session.
+    pub stats: SessionStats,
+}
+
+impl SessionInfo {
+    fn new(session_id: String, mode: Option<String>, cwd: String) -> Self {
+        let now = SystemTime::now();
+        Self {
+            session_id,
+            mode,
+            model: None,
+            cwd,
+            created_at: now,
+            last_active: now,
+            prompt_count: 0,
+            state: SessionState::Active,
+            stats: SessionStats::default(),
+        }
+    }
+}
+
+/// Registry of the sessions known to a server.
+///
+/// Cloning is cheap; all clones share the same registry.
+#[derive(Debug, Clone, Default)]
+pub struct SessionManager {
+    sessions: Arc<RwLock<HashMap<String, SessionInfo>>>,
+}
+
+impl SessionManager {
+    /// Create an empty session manager.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a session, replacing any previous entry with the same ID.
+    pub async fn insert(
+        &self,
+        session_id: impl Into<String>,
+        mode: Option<String>,
+        cwd: impl Into<String>,
+    ) -> SessionInfo {
+        let info = SessionInfo::new(session_id.into(), mode, cwd.into());
+        self.sessions
+            .write()
+            .await
+            .insert(info.session_id.clone(), info.clone());
+        info
+    }
+
+    /// Get a snapshot of a session.
+    pub async fn get(&self, session_id: &str) -> Option<SessionInfo> {
+        self.sessions.read().await.get(session_id).cloned()
+    }
+
+    /// Check whether a session is known.
+    pub async fn contains(&self, session_id: &str) -> bool {
+        self.sessions.read().await.contains_key(session_id)
+    }
+
+    /// Record prompt activity for a session and mark it active.
+    ///
+    /// Returns `InvalidState` if the session is unknown.
+    pub async fn touch(&self, session_id: &str) -> AcpResult<SessionInfo> {
+        let mut sessions = self.sessions.write().await;
+        let info = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| AcpError::InvalidState(format!("Unknown session: {}", session_id)))?;
+        info.last_active = SystemTime::now();
+        info.prompt_count += 1;
+        info.stats.prompts += 1;
+        info.state = SessionState::Active;
+        Ok(info.clone())
+    }
+
+    /// Get the usage of a session.
+    pub async fn stats(&self, session_id: &str) -> Option<SessionStats> {
+        self.sessions.read().await.get(session_id).map(|info| info.stats.clone())
+    }
+
+    /// Count `bytes` of prompt content received in a session.
+    pub(crate) async fn record_received(&self, session_id: &str, bytes: usize) {
+        if let Some(info) = self.sessions.write().await.get_mut(session_id) {
+            info.stats.bytes_received += bytes as u64;
+        }
+    }
+
+    /// Count `elapsed` spent answering a prompt in a session.
+    pub(crate) async fn record_prompt_time(&self, session_id: &str, elapsed: Duration) {
+        if let Some(info) = self.sessions.write().await.get_mut(session_id) {
+            info.stats.prompt_time_ms += elapsed.as_millis() as u64;
+        }
+    }
+
+    /// Count an update sent to the client as a message of `bytes`.
+    pub(crate) async fn record_update(&self, update: &SessionUpdate, bytes: usize) {
+        let mut sessions = self.sessions.write().await;
+        let Some(info) = sessions.get_mut(&update.session_id) else {
+            return;
+        };
+        info.stats.bytes_sent += bytes as u64;
+        match &update.update_type {
+            SessionUpdateType::AgentMessageChunk { .. } => info.stats.message_chunks += 1,
+            SessionUpdateType::ToolCall(call) => *info.stats.tool_calls.entry(call.name.clone()).or_default() += 1,
+            _ => {}
+        }
+    }
+
+    /// Change the mode of a session.
+    ///
+    /// Returns `InvalidState` if the session is unknown.
+    pub async fn set_mode(&self, session_id: &str, mode: Option<String>) -> AcpResult<()> {
+        let mut sessions = self.sessions.write().await;
+        let info = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| AcpError::InvalidState(format!("Unknown session: {}", session_id)))?;
+        info.mode = mode;
+        Ok(())
+    }
+
+    /// Change the model of a session.
+    ///
+    /// Returns `InvalidState` if the session is unknown.
+    pub async fn set_model(&self, session_id: &str, model: Option<String>) -> AcpResult<()> {
+        let mut sessions = self.sessions.write().await;
+        let info = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| AcpError::InvalidState(format!("Unknown session: {}", session_id)))?;
+        info.model = model;
+        Ok(())
+    }
+
+    /// Change the lifecycle state of a session.
+    ///
+    /// Returns `InvalidState` if the session is unknown.
+    pub async fn set_state(&self, session_id: &str, state: SessionState) -> AcpResult<()> {
+        let mut sessions = self.sessions.write().await;
+        let info = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| AcpError::InvalidState(format!("Unknown session: {}", session_id)))?;
+        info.state = state;
+        Ok(())
+    }
+
+    /// Remove a session, returning its last state.
+    pub async fn remove(&self, session_id: &str) -> Option<SessionInfo> {
+        self.sessions.write().await.remove(session_id)
+    }
+
+    /// Get snapshots of all sessions.
+    pub async fn list(&self) -> Vec<SessionInfo> {
+        self.sessions.read().await.values().cloned().collect()
+    }
+
+    /// Number of known sessions.
+    pub async fn len(&self) -> usize {
+        self.sessions.read().await.len()
+    }
+
+    /// Check whether no sessions are known.
+    pub async fn is_empty(&self) -> bool {
+        self.sessions.read().await.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_insert_and_get() {
+        let sessions = SessionManager::new();
+        sessions.insert("s1", Some("agent".to_string()), "/tmp").await;
+
+        let info = sessions.get("s1").await.unwrap();
+        assert_eq!(info.session_id, "s1");
+        assert_eq!(info.mode.as_deref(), Some("agent"));
+        assert_eq!(info.cwd, "/tmp");
+        assert_eq!(info.prompt_count, 0);
+        assert!(sessions.contains("s1").await);
+        assert!(!sessions.contains("s2").await);
+    }
+
+    #[tokio::test]
+    async fn test_touch_records_activity() {
+        let sessions = SessionManager::new();
+        let created = sessions.insert("s1", None, "/").await;
+
+        let info = sessions.touch("s1").await.unwrap();
+        assert_eq!(info.prompt_count, 1);
+        assert!(info.last_active >= created.last_active);
+    }
+
+    #[tokio::test]
+    async fn test_prompt_reactivates_cancelled_session() {
+        let sessions = SessionManager::new();
+        sessions.insert("s1", None, "/").await;
+        sessions.set_state("s1", SessionState::Cancelled).await.unwrap();
+        assert_eq!(sessions.get("s1").await.unwrap().state, SessionState::Cancelled);
+
+        let info = sessions.touch("s1").await.unwrap();
+        assert_eq!(info.state, SessionState::Active);
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let sessions = SessionManager::new();
+        sessions.insert("s1", None, "/").await;
+        sessions.touch("s1").await.unwrap();
+        sessions.record_received("s1", 40).await;
+        sessions.record_prompt_time("s1", Duration::from_millis(1500)).await;
+        let update = |update_type| SessionUpdate {
+            session_id: "s1".to_string(),
+            update_type,
+        };
+        let chunk = update(SessionUpdateType::AgentMessageChunk { text: "hi".to_string() });
+        let call = update(SessionUpdateType::ToolCall(crate::protocol::ToolCall {
+            id: "call-1".to_string(),
+            name: "read_file".to_string(),
+            arguments: serde_json::json!({}),
+        }));
+        sessions.record_update(&chunk, 10).await;
+        sessions.record_update(&chunk, 10).await;
+        sessions.record_update(&call, 30).await;
+        sessions.record_update(&update(SessionUpdateType::Done), 5).await;
+
+        let stats = sessions.stats("s1").await.unwrap();
+        assert_eq!(stats.prompts, 1);
+        assert_eq!(stats.message_chunks, 2);
+        assert_eq!(stats.tool_calls["read_file"], 1);
+        assert_eq!(stats.bytes_received, 40);
+        assert_eq!(stats.bytes_sent, 55);
+        assert_eq!(stats.prompt_time_ms, 1500);
+        assert_eq!(sessions.stats("s2").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_touch_unknown_session() {
+        let sessions = SessionManager::new();
+        let err = sessions.touch("missing").await.unwrap_err();
+        assert_eq!(err.code(), crate::protocol::codes::INVALID_STATE);
+    }
+
+    #[tokio::test]
+    async fn test_clones_share_registry() {
+        let sessions = SessionManager::new();
+        let other = sessions.clone();
+        sessions.insert("s1", None, "/").await;
+        other.set_mode("s1", Some("ask".to_string())).await.unwrap();
+
+        assert_eq!(sessions.get("s1").await.unwrap().mode.as_deref(), Some("ask"));
+        assert_eq!(other.len().await, 1);
+        assert!(other.remove("s1").await.is_some());
+        assert!(sessions.is_empty().await);
+    }
+}
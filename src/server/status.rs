@@ -0,0 +1,43 @@
+//! Resource figures for the `agent/status` request.
+
+use crate::protocol::*;
+
+/// What this process uses. Memory and threads are read from `/proc`, so
+/// only Linux reports them.
+pub(crate) fn resource_usage() -> ResourceUsage {
+    let mut usage = ResourceUsage {
+        pid: Some(std::process::id()),
+        ..ResourceUsage::default()
+    };
+    let Ok(status) = std::fs::read_to_string("/proc/self/status") else {
+        return usage;
+    };
+    for line in status.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let number = value.split_whitespace().next().and_then(|n| n.parse::<u64>().ok());
+        match key {
+            // In kB
+            "VmRSS" => usage.memory_bytes = number.map(|kb| kb * 1024),
+            "Threads" => usage.threads = number,
+            _ => {}
+        }
+    }
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resource_usage() {
+        let usage = resource_usage();
+        assert_eq!(usage.pid, Some(std::process::id()));
+        if cfg!(target_os = "linux") {
+            assert!(usage.memory_bytes.unwrap() > 0);
+            assert!(usage.threads.unwrap() >= 1);
+        }
+    }
+}
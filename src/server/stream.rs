@@ -0,0 +1,219 @@
+//! Reassembly of prompts sent in chunks.
+//!
+//! Clients send large prompts with `session/prompt_stream_start`, any number
+//! of `session/prompt_stream_chunk` calls and `session/prompt_stream_end`. The
+//! server buffers the content here and hands the agent a single
+//! [`SessionPromptParams`] once the stream ends.
+//!
+//! A connection may have [`MAX_OPEN_STREAMS`] streams open, each buffering up
+//! to [`MAX_STREAM_BYTES`] of content. Streams are dropped when the
+//! connection shuts down.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::protocol::*;
+
+/// Most prompt streams a connection may have open at once. Starting
+/// another fails with `RATE_LIMITED` until one ends.
+pub(crate) const MAX_OPEN_STREAMS: usize = 16;
+
+/// Most content one prompt stream may buffer, in bytes of JSON. The chunk
+/// that goes over fails with `INVALID_PARAMS` and the stream is dropped.
+pub(crate) const MAX_STREAM_BYTES: usize = 64 * 1024 * 1024;
+
+/// A prompt still being received.
+#[derive(Debug)]
+struct OpenStream {
+    prompt: SessionPromptParams,
+    bytes: usize,
+}
+
+/// Prompts that are still being received.
+#[derive(Debug, Clone)]
+pub(crate) struct PromptStreams {
+    streams: Arc<Mutex<HashMap<String, OpenStream>>>,
+    max_open: usize,
+    max_bytes: usize,
+}
+
+impl Default for PromptStreams {
+    fn default() -> Self {
+        Self {
+            streams: Arc::default(),
+            max_open: MAX_OPEN_STREAMS,
+            max_bytes: MAX_STREAM_BYTES,
+        }
+    }
+}
+
+impl PromptStreams {
+    /// Begin buffering a prompt for `session_id`. Returns the stream ID.
+    pub(crate) async fn start(&self, session_id: String) -> AcpResult<String> {
+        let mut streams = self.streams.lock().await;
+        if streams.len() >= self.max_open {
+            return Err(AcpError::RateLimited(format!(
+                "{} prompt streams are already open",
+                self.max_open
+            )));
+        }
+        let stream_id = format!("stream_{}", uuid::Uuid::new_v4());
+        let prompt = SessionPromptParams {
+            session_id,
+            content: Vec::new(),
+        };
+        streams.insert(stream_id.clone(), OpenStream { prompt, bytes: 0 });
+        Ok(stream_id)
+    }
+
+    /// Append a chunk to a stream.
+    pub(crate) async fn append(&self, chunk: PromptStreamChunkParams) -> AcpResult<()> {
+        let mut streams = self.streams.lock().await;
+        let stream = streams.get_mut(&chunk.stream_id).ok_or_else(|| unknown(&chunk.stream_id))?;
+        stream.bytes += serde_json::to_vec(&chunk.content).map_or(0, |json| json.len());
+        if stream.bytes > self.max_bytes {
+            streams.remove(&chunk.stream_id);
+            return Err(AcpError::InvalidParams(format!(
+                "Prompt stream {} is over {} bytes and was dropped",
+                chunk.stream_id, self.max_bytes
+            )));
+        }
+        append_content(&mut stream.prompt.content, chunk.content, chunk.continuation)
+    }
+
+    /// Stop buffering a stream and return the assembled prompt.
+    pub(crate) async fn finish(&self, stream_id: &str) -> AcpResult<SessionPromptParams> {
+        self.streams
+            .lock()
+            .await
+            .remove(stream_id)
+            .map(|stream| stream.prompt)
+            .ok_or_else(|| unknown(stream_id))
+    }
+
+    /// Drop every stream, as the connection is going away.
+    pub(crate) async fn clear(&self) {
+        self.streams.lock().await.clear();
+    }
+}
+
+fn unknown(stream_id: &str) -> AcpError {
+    AcpError::InvalidState(format!("Unknown prompt stream: {}", stream_id))
+}
+
+/// Append `chunk` to `content`, joining the first block onto the last one if
+/// `continuation` is set.
+fn append_content(
+    content: &mut Vec<ContentBlock>,
+    chunk: Vec<ContentBlock>,
+    continuation: bool,
+) -> AcpResult<()> {
+    let mut blocks = chunk.into_iter();
+    if continuation {
+        match (content.last_mut(), blocks.next()) {
+            (Some(ContentBlock::Text { text, .. }), Some(ContentBlock::Text { text: more, .. })) => {
+                text.push_str(&more);
+            }
+            (_, None) => {}
+            _ => {
+                return Err(AcpError::InvalidParams(
+                    "Only text blocks can be continued".to_string(),
+                ))
+            }
+        }
+    }
+    content.extend(blocks);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(stream_id: &str, content: Vec<ContentBlock>, continuation: bool) -> PromptStreamChunkParams {
+        PromptStreamChunkParams {
+            stream_id: stream_id.to_string(),
+            content,
+            continuation,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reassembles_prompt() {
+        let streams = PromptStreams::default();
+        let id = streams.start("s1".to_string()).await.unwrap();
+
+        streams
+            .append(chunk(&id, vec![ContentBlock::text("intro"), ContentBlock::text("log: ")], false))
+            .await
+            .unwrap();
+        streams
+            .append(chunk(&id, vec![ContentBlock::text("line 1\n")], true))
+            .await
+            .unwrap();
+        streams
+            .append(chunk(&id, vec![ContentBlock::text("line 2\n")], true))
+            .await
+            .unwrap();
+
+        let prompt = streams.finish(&id).await.unwrap();
+        assert_eq!(prompt.session_id, "s1");
+        let texts: Vec<_> = prompt
+            .content
+            .iter()
+            .map(|block| match block {
+                ContentBlock::Text { text, .. } => text.as_str(),
+                _ => panic!("expected text"),
+            })
+            .collect();
+        assert_eq!(texts, vec!["intro", "log: line 1\nline 2\n"]);
+
+        let err = streams.finish(&id).await.unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_STATE);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_bad_continuation() {
+        let streams = PromptStreams::default();
+        let id = streams.start("s1".to_string()).await.unwrap();
+
+        let err = streams
+            .append(chunk(&id, vec![ContentBlock::text("orphan")], true))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_PARAMS);
+
+        let err = streams
+            .append(chunk("stream_missing", vec![], false))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_STATE);
+    }
+
+    #[tokio::test]
+    async fn test_limits() {
+        let streams = PromptStreams {
+            max_open: 2,
+            max_bytes: 64,
+            ..PromptStreams::default()
+        };
+        let first = streams.start("s1".to_string()).await.unwrap();
+        let second = streams.start("s2".to_string()).await.unwrap();
+        let err = streams.start("s1".to_string()).await.unwrap_err();
+        assert_eq!(err.code(), codes::RATE_LIMITED);
+
+        // The chunk over the limit drops its stream, making room for another
+        streams.append(chunk(&first, vec![ContentBlock::text("x")], false)).await.unwrap();
+        let err = streams
+            .append(chunk(&first, vec![ContentBlock::text("x".repeat(64))], true))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_PARAMS);
+        assert_eq!(streams.finish(&first).await.unwrap_err().code(), codes::INVALID_STATE);
+        streams.start("s1".to_string()).await.unwrap();
+
+        streams.clear().await;
+        assert_eq!(streams.finish(&second).await.unwrap_err().code(), codes::INVALID_STATE);
+    }
+}
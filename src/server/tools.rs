@@ -0,0 +1,353 @@
+//! Registry of tools the agent can run.
+//!
+//! Register tools once with [`Server::with_tools`](super::Server::with_tools).
+//! The server advertises them in `initialize`, and agents run them through
+//! [`AgentContext::tools`](super::AgentContext::tools), which validates the
+//! arguments and reports progress to the client as tool call updates.
+//!
+//! Tools of the editor's MCP servers can be run the same way once proxied
+//! with [`Server::with_mcp_tools`](super::Server::with_mcp_tools).
+
+use serde_json::Value;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use super::{AgentContext, SessionHandle};
+use crate::mcp::{McpClient, McpTool};
+use crate::protocol::*;
+
+type ToolFuture = Pin<Box<dyn Future<Output = AcpResult<Value>> + Send>>;
+type ToolHandler = Arc<dyn Fn(Value, AgentContext) -> ToolFuture + Send + Sync>;
+
+#[derive(Clone)]
+struct RegisteredTool {
+    info: ToolInfo,
+    handler: ToolHandler,
+}
+
+/// A set of named tools with JSON-schema described arguments.
+///
+/// Cloning is cheap.
+#[derive(Clone, Default)]
+pub struct ToolRegistry {
+    tools: Arc<Vec<RegisteredTool>>,
+}
+
+impl std::fmt::Debug for ToolRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.tools.iter().map(|tool| &tool.info.name))
+            .finish()
+    }
+}
+
+impl ToolRegistry {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a tool.
+    ///
+    /// `parameters` is the JSON schema for the tool's arguments. A tool
+    /// registered under an existing name replaces it.
+    pub fn register<F, Fut>(
+        mut self,
+        name: impl Into<String>,
+        description: impl Into<String>,
+        parameters: Value,
+        handler: F,
+    ) -> Self
+    where
+        F: Fn(Value, AgentContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = AcpResult<Value>> + Send + 'static,
+    {
+        let info = ToolInfo {
+            name: name.into(),
+            description: description.into(),
+            parameters,
+        };
+        let handler: ToolHandler = Arc::new(move |args, ctx| Box::pin(handler(args, ctx)));
+        let tools = Arc::make_mut(&mut self.tools);
+        tools.retain(|tool| tool.info.name != info.name);
+        tools.push(RegisteredTool { info, handler });
+        self
+    }
+
+    /// Descriptions of all registered tools, for `AgentCapabilities::tools`.
+    pub fn infos(&self) -> Vec<ToolInfo> {
+        self.tools.iter().map(|tool| tool.info.clone()).collect()
+    }
+
+    /// Check whether a tool is registered.
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Check whether no tools are registered.
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    fn get(&self, name: &str) -> Option<&RegisteredTool> {
+        self.tools.iter().find(|tool| tool.info.name == name)
+    }
+
+    /// Run a tool on behalf of a session.
+    ///
+    /// Sends a `tool_call` update, validates `arguments` against the tool's
+    /// schema, runs the handler and finishes with a `tool_call_update`
+    /// carrying the result or error. Names not registered here are looked up
+    /// among the MCP tools proxied through [`AgentContext::mcp`].
+    pub async fn call(
+        &self,
+        session: &SessionHandle,
+        name: &str,
+        arguments: Value,
+        ctx: &AgentContext,
+    ) -> AcpResult<Value> {
+        let tool = match self.get(name) {
+            Some(tool) => tool.clone(),
+            None => match ctx.mcp().proxied_tool(name).await {
+                Some((client, tool)) => proxy(client, tool),
+                None => return Err(AcpError::InvalidParams(format!("Unknown tool: {}", name))),
+            },
+        };
+
+        let id = session.start_tool_call(name, arguments.clone()).await.ok();
+
+        let result = match validate_arguments(&tool.info.parameters, &arguments) {
+            Ok(()) => (tool.handler)(arguments, ctx.clone()).await,
+            Err(e) => Err(e),
+        };
+
+        if let Some(id) = id {
+            let _ = match &result {
+                Ok(value) => session.complete_tool_call(id, value.clone()).await,
+                Err(e) => session.fail_tool_call(id, e.message()).await,
+            };
+        }
+
+        result
+    }
+}
+
+/// A tool that runs `tool` on its MCP server. A result the server marks as
+/// an error becomes an `INTERNAL_ERROR` with the result's text.
+fn proxy(client: McpClient, tool: McpTool) -> RegisteredTool {
+    let info = tool.tool_info(client.name());
+    let handler: ToolHandler = Arc::new(move |args, _ctx| {
+        let (client, name) = (client.clone(), tool.name.clone());
+        Box::pin(async move {
+            let result = client.call_tool(&name, args).await?;
+            if result.is_error {
+                return Err(AcpError::InternalError(result.text()));
+            }
+            Ok(serde_json::to_value(result)?)
+        })
+    });
+    RegisteredTool { info, handler }
+}
+
+/// Check tool arguments against a JSON schema.
+///
+/// Supports the subset of JSON Schema used to describe tool parameters:
+/// `type`, `properties`, `required`, `additionalProperties: false`, `items`
+/// and `enum`. Other keywords are ignored.
+pub fn validate_arguments(schema: &Value, arguments: &Value) -> AcpResult<()> {
+    validate_at(schema, arguments, "arguments").map_err(AcpError::InvalidParams)
+}
+
+fn validate_at(schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+    if let Some(expected) = schema.get("type").and_then(|t| t.as_str()) {
+        let matches = match expected {
+            "object" => value.is_object(),
+            "array" => value.is_array(),
+            "string" => value.is_string(),
+            "boolean" => value.is_boolean(),
+            "integer" => value.is_i64() || value.is_u64(),
+            "number" => value.is_number(),
+            "null" => value.is_null(),
+            _ => true,
+        };
+        if !matches {
+            return Err(format!("{} must be of type {}", path, expected));
+        }
+    }
+
+    if let Some(allowed) = schema.get("enum").and_then(|e| e.as_array()) {
+        if !allowed.contains(value) {
+            return Err(format!("{} must be one of {}", path, Value::Array(allowed.clone())));
+        }
+    }
+
+    if let Some(object) = value.as_object() {
+        let properties = schema.get("properties").and_then(|p| p.as_object());
+        if let Some(required) = schema.get("required").and_then(|r| r.as_array()) {
+            for key in required.iter().filter_map(|k| k.as_str()) {
+                if !object.contains_key(key) {
+                    return Err(format!("{} is missing required field '{}'", path, key));
+                }
+            }
+        }
+        for (key, field) in object {
+            match properties.and_then(|p| p.get(key)) {
+                Some(field_schema) => validate_at(field_schema, field, &format!("{}.{}", path, key))?,
+                None if schema.get("additionalProperties") == Some(&Value::Bool(false)) => {
+                    return Err(format!("{} has unexpected field '{}'", path, key));
+                }
+                None => {}
+            }
+        }
+    }
+
+    if let (Some(items), Some(array)) = (schema.get("items"), value.as_array()) {
+        for (i, item) in array.iter().enumerate() {
+            validate_at(items, item, &format!("{}[{}]", path, i))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{AgentConnection, SessionManager};
+    use super::*;
+    use serde_json::json;
+    use tokio::sync::mpsc;
+
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": {"type": "string"},
+                "lines": {"type": "array", "items": {"type": "integer"}},
+                "mode": {"enum": ["read", "stat"]}
+            },
+            "required": ["path"],
+            "additionalProperties": false
+        })
+    }
+
+    fn ctx() -> AgentContext {
+        let (tx, _rx) = mpsc::channel(1);
+        AgentContext::new(SessionManager::new(), AgentConnection::new(tx, Default::default()))
+    }
+
+    fn registry() -> ToolRegistry {
+        ToolRegistry::new().register("echo", "Echo the path", schema(), |args, _ctx| async move {
+            Ok(args["path"].clone())
+        })
+    }
+
+    #[test]
+    fn test_validate_arguments() {
+        assert!(validate_arguments(&schema(), &json!({"path": "/a", "lines": [1, 2]})).is_ok());
+
+        let cases = [
+            (json!("/a"), "must be of type object"),
+            (json!({}), "missing required field 'path'"),
+            (json!({"path": 1}), "arguments.path must be of type string"),
+            (json!({"path": "/a", "lines": [1, "x"]}), "arguments.lines[1]"),
+            (json!({"path": "/a", "mode": "write"}), "must be one of"),
+            (json!({"path": "/a", "extra": true}), "unexpected field 'extra'"),
+        ];
+        for (args, expected) in cases {
+            let err = validate_arguments(&schema(), &args).unwrap_err();
+            assert!(err.message().contains(expected), "{}", err.message());
+        }
+    }
+
+    #[test]
+    fn test_infos() {
+        let registry = registry().register("echo", "Replaced", json!({}), |_, _| async {
+            Ok(Value::Null)
+        });
+        let infos = registry.infos();
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].description, "Replaced");
+        assert!(registry.contains("echo"));
+    }
+
+    #[tokio::test]
+    async fn test_call_emits_updates() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let result = registry()
+            .call(&SessionHandle::new("s1", tx), "echo", json!({"path": "/a"}), &ctx())
+            .await
+            .unwrap();
+        assert_eq!(result, json!("/a"));
+
+        let started = rx.recv().await.unwrap();
+        let SessionUpdateType::ToolCall(call) = started.update_type else {
+            panic!("expected tool_call");
+        };
+        assert_eq!(call.name, "echo");
+
+        let finished = rx.recv().await.unwrap();
+        let SessionUpdateType::ToolCallUpdate(update) = finished.update_type else {
+            panic!("expected tool_call_update");
+        };
+        assert_eq!(update.id, call.id);
+        assert!(matches!(update.status, ToolCallStatus::Completed));
+    }
+
+    #[tokio::test]
+    async fn test_call_rejects_invalid_arguments() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let err = registry()
+            .call(&SessionHandle::new("s1", tx), "echo", json!({"path": 42}), &ctx())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_PARAMS);
+
+        let _started = rx.recv().await.unwrap();
+        let SessionUpdateType::ToolCallUpdate(update) = rx.recv().await.unwrap().update_type else {
+            panic!("expected tool_call_update");
+        };
+        assert!(matches!(update.status, ToolCallStatus::Failed));
+        assert!(update.error.unwrap().contains("arguments.path"));
+    }
+
+    #[tokio::test]
+    async fn test_call_proxies_mcp_tools() {
+        let mcp = crate::mcp::McpServers::new();
+        mcp.add(crate::mcp::client::tests::fake_client("fake").await).await;
+        let infos = mcp.proxy_tools().await;
+        let names: Vec<_> = infos.iter().map(|info| info.name.as_str()).collect();
+        assert_eq!(names, ["fake__echo", "fake__add"]);
+        assert_eq!(infos[0].description, "Echo the text");
+
+        let ctx = ctx().with_mcp(mcp);
+        let (tx, mut rx) = mpsc::channel(8);
+        let session = SessionHandle::new("s1", tx);
+        let result = registry()
+            .call(&session, "fake__echo", json!({"text": "hi"}), &ctx)
+            .await
+            .unwrap();
+        assert_eq!(result["content"][0]["text"], "hi");
+
+        let SessionUpdateType::ToolCall(call) = rx.recv().await.unwrap().update_type else {
+            panic!("expected tool_call");
+        };
+        assert_eq!(call.name, "fake__echo");
+        let SessionUpdateType::ToolCallUpdate(update) = rx.recv().await.unwrap().update_type else {
+            panic!("expected tool_call_update");
+        };
+        assert!(matches!(update.status, ToolCallStatus::Completed));
+
+        // Arguments are checked against the server's schema
+        let err = registry()
+            .call(&session, "fake__echo", json!({"text": 1}), &ctx)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_PARAMS);
+        // Results the server marks as errors fail the call
+        let err = registry().call(&session, "fake__add", json!({}), &ctx).await.unwrap_err();
+        assert!(err.message().contains("no such tool"));
+        let err = registry().call(&session, "fake__missing", json!({}), &ctx).await.unwrap_err();
+        assert!(err.message().contains("Unknown tool"));
+    }
+}
@@ -0,0 +1,259 @@
+//! Recording of the raw JSON-RPC traffic.
+//!
+//! A [`Transcript`] appends every message the server reads or writes to a
+//! JSONL file, one entry per line, with the size of the message on the wire
+//! in bytes:
+//!
+//! ```json
+//! {"timestamp_ms":1717171717000,"direction":"in","size":62,"message":{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}}
+//! ```
+//!
+//! The same transcript logs a client's traffic when given to
+//! [`ClientBuilder::transcript`](crate::client::ClientBuilder::transcript).
+//!
+//! Values under keys that look like secrets (`token`, `password`,
+//! `credentials`, ...) are replaced with `"[REDACTED]"` before writing, and
+//! so are strings longer than [`redact_content_over`](Transcript::redact_content_over)
+//! allows, so the file is safe to attach to a bug report.
+
+use serde_json::Value;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::protocol::*;
+
+/// Key fragments whose values are redacted by default.
+const DEFAULT_SECRET_KEYS: &[&str] = &[
+    "token",
+    "password",
+    "secret",
+    "api_key",
+    "apikey",
+    "authorization",
+    "credential",
+];
+
+/// Placeholder written in place of redacted values.
+const REDACTED: &str = "[REDACTED]";
+
+/// Which way a message travelled, as seen by the agent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Sent by the client.
+    Inbound,
+    /// Sent by the agent.
+    Outbound,
+}
+
+impl Direction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Inbound => "in",
+            Direction::Outbound => "out",
+        }
+    }
+}
+
+/// Records JSON-RPC traffic to a file.
+///
+/// Recording can be started and stopped while the server runs. Cloning is
+/// cheap; all clones share the same file.
+#[derive(Debug, Clone)]
+pub struct Transcript {
+    file: Arc<Mutex<Option<File>>>,
+    secret_keys: Arc<Vec<String>>,
+    max_content_bytes: Option<usize>,
+}
+
+impl Default for Transcript {
+    fn default() -> Self {
+        Self {
+            file: Arc::new(Mutex::new(None)),
+            secret_keys: Arc::new(DEFAULT_SECRET_KEYS.iter().map(|k| k.to_string()).collect()),
+            max_content_bytes: None,
+        }
+    }
+}
+
+impl Transcript {
+    /// Create a transcript that isn't recording yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also redact values under keys containing `fragment` (case-insensitive).
+    pub fn redact_key(mut self, fragment: impl Into<String>) -> Self {
+        Arc::make_mut(&mut self.secret_keys).push(fragment.into().to_lowercase());
+        self
+    }
+
+    /// Also redact strings longer than `max_bytes` in params and results,
+    /// such as file contents and prompts, keeping the shape of each message
+    /// but not what's in it.
+    pub fn redact_content_over(mut self, max_bytes: usize) -> Self {
+        self.max_content_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Start appending messages to the file at `path`, creating it if needed.
+    pub fn start(&self, path: impl AsRef<Path>) -> AcpResult<()> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        *self.file.lock().unwrap() = Some(file);
+        Ok(())
+    }
+
+    /// Stop recording.
+    pub fn stop(&self) {
+        self.file.lock().unwrap().take();
+    }
+
+    /// Check whether messages are being recorded.
+    pub fn is_recording(&self) -> bool {
+        self.file.lock().unwrap().is_some()
+    }
+
+    /// Record a raw message.
+    ///
+    /// Lines that aren't valid JSON are recorded as strings. `size` is the
+    /// length of `message` in bytes, before redaction. Write errors are
+    /// reported on stderr and stop the recording.
+    pub fn record(&self, direction: Direction, message: &str) {
+        let mut file = self.file.lock().unwrap();
+        let Some(out) = file.as_mut() else {
+            return;
+        };
+
+        let size = message.len();
+        let mut message = serde_json::from_str(message)
+            .unwrap_or_else(|_| Value::String(message.to_string()));
+        self.redact(&mut message);
+        if let Some(max_bytes) = self.max_content_bytes {
+            for key in ["params", "result"] {
+                if let Some(value) = message.get_mut(key) {
+                    redact_content(value, max_bytes);
+                }
+            }
+        }
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let entry = serde_json::json!({
+            "timestamp_ms": timestamp_ms,
+            "direction": direction.as_str(),
+            "size": size,
+            "message": message,
+        });
+
+        if let Err(e) = writeln!(out, "{}", entry) {
+            eprintln!("Failed to write transcript, stopping recording: {}", e);
+            file.take();
+        }
+    }
+
+    fn is_secret(&self, key: &str) -> bool {
+        let key = key.to_lowercase();
+        self.secret_keys.iter().any(|fragment| key.contains(fragment.as_str()))
+    }
+
+    fn redact(&self, value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (key, field) in map.iter_mut() {
+                    if self.is_secret(key) {
+                        *field = Value::String(REDACTED.to_string());
+                    } else {
+                        self.redact(field);
+                    }
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(|item| self.redact(item)),
+            _ => {}
+        }
+    }
+}
+
+/// Replace strings in `value` longer than `max_bytes`.
+fn redact_content(value: &mut Value, max_bytes: usize) {
+    match value {
+        Value::Object(map) => map.values_mut().for_each(|field| redact_content(field, max_bytes)),
+        Value::Array(items) => items.iter_mut().for_each(|item| redact_content(item, max_bytes)),
+        Value::String(text) if text.len() > max_bytes => *text = REDACTED.to_string(),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("heroacp-{}-{}.jsonl", name, uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn test_records_and_redacts() {
+        let path = path("transcript");
+        let transcript = Transcript::new().redact_key("Cookie");
+        transcript.start(&path).unwrap();
+
+        transcript.record(
+            Direction::Inbound,
+            r#"{"jsonrpc":"2.0","id":1,"method":"authenticate","params":{"type":"token","token":"hunter2","session_cookie":"abc"}}"#,
+        );
+        transcript.record(Direction::Outbound, "not json");
+        transcript.stop();
+        transcript.record(Direction::Inbound, r#"{"ignored":true}"#);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        let entries: Vec<Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["direction"], "in");
+        assert_eq!(entries[0]["size"], 115);
+        assert!(entries[0]["timestamp_ms"].as_u64().unwrap() > 0);
+        let params = &entries[0]["message"]["params"];
+        assert_eq!(params["type"], "token");
+        assert_eq!(params["token"], REDACTED);
+        assert_eq!(params["session_cookie"], REDACTED);
+        assert_eq!(entries[1]["direction"], "out");
+        assert_eq!(entries[1]["message"], "not json");
+        assert_eq!(entries[1]["size"], 8);
+    }
+
+    #[test]
+    fn test_redacts_long_content() {
+        let path = path("content");
+        let transcript = Transcript::new().redact_content_over(8);
+        transcript.start(&path).unwrap();
+        transcript.record(
+            Direction::Outbound,
+            r#"{"jsonrpc":"2.0","id":1,"method":"fs/write_text_file","params":{"path":"/a.txt","content":"a file's worth"}}"#,
+        );
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        let entry: Value = serde_json::from_str(contents.trim_end()).unwrap();
+        assert_eq!(entry["message"]["method"], "fs/write_text_file");
+        assert_eq!(entry["message"]["params"]["path"], "/a.txt");
+        assert_eq!(entry["message"]["params"]["content"], REDACTED);
+    }
+
+    #[test]
+    fn test_redacts_nested_credentials() {
+        let transcript = Transcript::new();
+        let mut value = serde_json::json!({
+            "mcp_servers": [{"name": "db", "credentials": {"user": "me", "pass": "x"}}]
+        });
+        transcript.redact(&mut value);
+        assert_eq!(value["mcp_servers"][0]["name"], "db");
+        assert_eq!(value["mcp_servers"][0]["credentials"], REDACTED);
+    }
+}
@@ -0,0 +1,324 @@
+//! Fault injection between a client and an agent.
+//!
+//! [`Chaos`] relays NDJSON messages like [`Recorder::tap`](super::Recorder::tap),
+//! but degrades the connection on the way: it delays messages, drops
+//! notifications, sends responses twice and cuts the connection in the
+//! middle of a message. Wrap the streams to an agent to see how an editor
+//! copes, or those to an editor to see how an agent does:
+//!
+//! ```rust,no_run
+//! # use heroacp::server::{Agent, Server};
+//! # use heroacp::testing::Chaos;
+//! # use std::time::Duration;
+//! # async fn demo(server: Server<impl Agent + 'static>) -> heroacp::protocol::AcpResult<()> {
+//! let (input, output) = Chaos::new()
+//!     .latency(Duration::from_millis(10), Duration::from_millis(200))
+//!     .drop_notifications(0.1)
+//!     .wrap(tokio::io::stdin(), tokio::io::stdout());
+//! server.serve(input, output).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Faults are drawn from a seeded generator, so a run that misbehaves can
+//! be repeated with the same [`seed`](Chaos::seed).
+
+use serde_json::Value;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, DuplexStream, ReadHalf,
+    WriteHalf,
+};
+use tokio::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Bytes buffered between a wrapped peer and its user.
+pub(super) const PIPE_CAPACITY: usize = 64 * 1024;
+
+/// Seed used unless [`Chaos::seed`] sets one.
+const DEFAULT_SEED: u64 = 0x5eed;
+
+/// Faults to inject into a connection; none by default.
+#[derive(Debug, Clone)]
+pub struct Chaos {
+    min_latency: Duration,
+    max_latency: Duration,
+    drop_notifications: f64,
+    duplicate_responses: f64,
+    disconnect_after: Option<usize>,
+    seed: u64,
+}
+
+impl Default for Chaos {
+    fn default() -> Self {
+        Self {
+            min_latency: Duration::ZERO,
+            max_latency: Duration::ZERO,
+            drop_notifications: 0.0,
+            duplicate_responses: 0.0,
+            disconnect_after: None,
+            seed: DEFAULT_SEED,
+        }
+    }
+}
+
+impl Chaos {
+    /// Inject no faults until configured.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Delay each message by a random time between `min` and `max`.
+    ///
+    /// Messages keep their order, so a slow one holds up those behind it.
+    pub fn latency(mut self, min: Duration, max: Duration) -> Self {
+        self.min_latency = min;
+        self.max_latency = max.max(min);
+        self
+    }
+
+    /// Drop each notification, such as a session update, with
+    /// `probability` between 0 and 1.
+    pub fn drop_notifications(mut self, probability: f64) -> Self {
+        self.drop_notifications = probability.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Send each response twice with `probability` between 0 and 1.
+    pub fn duplicate_responses(mut self, probability: f64) -> Self {
+        self.duplicate_responses = probability.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Pass `messages` messages in either direction, then send half of the
+    /// next one and close the connection.
+    pub fn disconnect_after(mut self, messages: usize) -> Self {
+        self.disconnect_after = Some(messages);
+        self
+    }
+
+    /// Seed the generator that draws faults.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Degrade the connection to a peer that writes `output` and reads
+    /// `input`, returning streams to use in their place.
+    ///
+    /// Both directions are relayed on their own tasks. The returned reader
+    /// ends when the peer closes `output` or the connection is cut; `input`
+    /// is shut down when the returned writer is, or on the cut.
+    pub fn wrap<R, W>(self, output: R, input: W) -> (ReadHalf<DuplexStream>, WriteHalf<DuplexStream>)
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        let (near, far) = tokio::io::duplex(PIPE_CAPACITY);
+        let (far_read, far_write) = tokio::io::split(far);
+        let faults = Arc::new(Faults {
+            rng: Mutex::new(self.seed),
+            passed: AtomicUsize::new(0),
+            cut: CancellationToken::new(),
+            chaos: self,
+        });
+        tokio::spawn(faults.clone().relay(output, far_write));
+        tokio::spawn(faults.relay(far_read, input));
+        tokio::io::split(near)
+    }
+}
+
+/// What kind of message a line holds, as far as faults go.
+enum Kind {
+    Notification,
+    Response,
+    Other,
+}
+
+impl Kind {
+    fn of(line: &str) -> Self {
+        let Ok(message) = serde_json::from_str::<Value>(line) else {
+            return Kind::Other;
+        };
+        let has_id = !message.get("id").is_none_or(Value::is_null);
+        match (message.get("method").is_some(), has_id) {
+            (true, false) => Kind::Notification,
+            (false, true) => Kind::Response,
+            _ => Kind::Other,
+        }
+    }
+}
+
+/// State shared by the two directions of a wrapped connection.
+struct Faults {
+    chaos: Chaos,
+    /// State of the splitmix64 generator.
+    rng: Mutex<u64>,
+    /// Messages seen in either direction.
+    passed: AtomicUsize,
+    /// Cancelled once the connection is cut.
+    cut: CancellationToken,
+}
+
+impl Faults {
+    /// A random number in `0..1`.
+    fn random(&self) -> f64 {
+        let mut state = self.rng.lock().unwrap();
+        *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        ((z ^ (z >> 31)) >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn happens(&self, probability: f64) -> bool {
+        probability > 0.0 && self.random() < probability
+    }
+
+    fn latency(&self) -> Duration {
+        let Chaos {
+            min_latency,
+            max_latency,
+            ..
+        } = self.chaos;
+        min_latency + (max_latency - min_latency).mul_f64(self.random())
+    }
+
+    /// Copy lines from `from` to `to`, injecting faults, until either side
+    /// closes or the connection is cut.
+    async fn relay<R, W>(self: Arc<Self>, from: R, mut to: W)
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut lines = BufReader::new(from).lines();
+        loop {
+            let line = tokio::select! {
+                line = lines.next_line() => line,
+                _ = self.cut.cancelled() => break,
+            };
+            let Ok(Some(line)) = line else {
+                break;
+            };
+            if line.is_empty() {
+                continue;
+            }
+
+            let delay = self.latency();
+            if !delay.is_zero() {
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = self.cut.cancelled() => break,
+                }
+            }
+
+            let passed = self.passed.fetch_add(1, Ordering::SeqCst);
+            if self.chaos.disconnect_after == Some(passed) {
+                let _ = to.write_all(&line.as_bytes()[..line.len() / 2]).await;
+                let _ = to.flush().await;
+                self.cut.cancel();
+                break;
+            }
+            let copies = match Kind::of(&line) {
+                Kind::Notification if self.happens(self.chaos.drop_notifications) => 0,
+                Kind::Response if self.happens(self.chaos.duplicate_responses) => 2,
+                _ => 1,
+            };
+            for _ in 0..copies {
+                if super::replay::write_line(&mut to, &line).await.is_err() {
+                    return;
+                }
+            }
+        }
+        let _ = to.shutdown().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::Client;
+    use crate::protocol::*;
+    use crate::server::Server;
+    use crate::testing::fixtures::session_new;
+    use crate::testing::{initialize_params, MockAgent, Scenario};
+    use tokio::io::AsyncReadExt;
+
+    const NOTIFICATION: &str = r#"{"jsonrpc":"2.0","method":"session/update","params":{}}"#;
+    const RESPONSE: &str = r#"{"jsonrpc":"2.0","id":1,"result":{}}"#;
+    const REQUEST: &str = r#"{"jsonrpc":"2.0","id":2,"method":"ping"}"#;
+
+    /// Wrap a peer that writes `lines` and closes, returning all that comes
+    /// through.
+    async fn through(chaos: Chaos, lines: &[&str]) -> String {
+        let (peer, wrapped) = tokio::io::duplex(PIPE_CAPACITY);
+        let (wrapped_read, wrapped_write) = tokio::io::split(wrapped);
+        let (mut output, _input) = chaos.wrap(wrapped_read, wrapped_write);
+
+        let (_, mut peer_write) = tokio::io::split(peer);
+        for line in lines {
+            peer_write.write_all(format!("{}\n", line).as_bytes()).await.unwrap();
+        }
+        peer_write.shutdown().await.unwrap();
+
+        let mut received = String::new();
+        output.read_to_string(&mut received).await.unwrap();
+        received
+    }
+
+    #[tokio::test]
+    async fn test_no_faults() {
+        let received = through(Chaos::new(), &[NOTIFICATION, RESPONSE, REQUEST]).await;
+        assert_eq!(received, format!("{}\n{}\n{}\n", NOTIFICATION, RESPONSE, REQUEST));
+    }
+
+    #[tokio::test]
+    async fn test_drops_and_duplicates() {
+        let chaos = Chaos::new().drop_notifications(1.0).duplicate_responses(1.0);
+        let received = through(chaos, &[NOTIFICATION, RESPONSE, REQUEST]).await;
+        assert_eq!(received, format!("{}\n{}\n{}\n", RESPONSE, RESPONSE, REQUEST));
+    }
+
+    #[tokio::test]
+    async fn test_faults_follow_seed() {
+        let lines = [NOTIFICATION; 100];
+        let chaos = Chaos::new().drop_notifications(0.5).seed(7);
+        let first = through(chaos.clone(), &lines).await;
+        assert_eq!(through(chaos, &lines).await, first);
+        assert!((20..80).contains(&first.lines().count()));
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_mid_message() {
+        let received = through(Chaos::new().disconnect_after(1), &[RESPONSE, REQUEST, REQUEST]).await;
+        assert_eq!(received, format!("{}\n{}", RESPONSE, &REQUEST[..REQUEST.len() / 2]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_latency() {
+        let latency = Duration::from_millis(100);
+        let started = tokio::time::Instant::now();
+        through(Chaos::new().latency(latency, latency), &[REQUEST, REQUEST]).await;
+        assert_eq!(started.elapsed(), latency * 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_client_sees_disconnect() {
+        let (client_end, server_end) = tokio::io::duplex(PIPE_CAPACITY);
+        let (server_read, server_write) = tokio::io::split(server_end);
+        let server = Server::new(MockAgent::new(Scenario::default()).unwrap());
+        tokio::spawn(async move { server.serve(server_read, server_write).await });
+
+        // `initialize` and its response pass; `session/new` is cut short
+        let (client_read, client_write) = tokio::io::split(client_end);
+        let (output, input) = Chaos::new().disconnect_after(2).wrap(client_read, client_write);
+        let client = Client::attach(output, input).unwrap();
+        client.initialize(initialize_params()).await.unwrap();
+
+        let started = tokio::time::Instant::now();
+        let err = client.session_new(session_new()).await.unwrap_err();
+        assert!(matches!(err, AcpError::ConnectionClosed), "{:?}", err);
+        assert_eq!(started.elapsed(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,408 @@
+//! Recording what a client's agent sends, for assertions.
+
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tokio::time::{Duration, Instant};
+
+use crate::client::UpdateHandler;
+use crate::protocol::*;
+
+/// One [`UpdateHandler`] call, as recorded by an [`UpdateCollector`].
+#[derive(Debug, Clone)]
+pub enum UpdateEvent {
+    /// A message chunk.
+    Message {
+        /// Session it belongs to.
+        session_id: String,
+        /// Text of the chunk.
+        text: String,
+    },
+    /// A thought chunk.
+    Thought {
+        /// Session it belongs to.
+        session_id: String,
+        /// Text of the chunk.
+        text: String,
+    },
+    /// A chunk of audio.
+    AudioChunk {
+        /// Session it belongs to.
+        session_id: String,
+        /// The chunk.
+        chunk: AudioChunk,
+    },
+    /// A new tool call.
+    ToolCall {
+        /// Session it belongs to.
+        session_id: String,
+        /// The call.
+        tool: ToolCall,
+    },
+    /// An update to a tool call.
+    ToolUpdate {
+        /// Session it belongs to.
+        session_id: String,
+        /// The update.
+        update: ToolCallUpdate,
+    },
+    /// A terminal embedded in a tool call.
+    ToolTerminal {
+        /// Session it belongs to.
+        session_id: String,
+        /// The tool call.
+        tool_call_id: String,
+        /// The terminal.
+        terminal_id: String,
+    },
+    /// A new plan.
+    Plan {
+        /// Session it belongs to.
+        session_id: String,
+        /// The plan.
+        plan: Plan,
+    },
+    /// A change of mode.
+    ModeChange {
+        /// Session it belongs to.
+        session_id: String,
+        /// The new mode.
+        mode: String,
+    },
+    /// A switch to another model.
+    ModelChange {
+        /// Session it belongs to.
+        session_id: String,
+        /// The new model.
+        model: String,
+    },
+    /// A change of activity.
+    Status {
+        /// Session it belongs to.
+        session_id: String,
+        /// The new state.
+        state: AgentState,
+    },
+    /// A prompt waiting behind others.
+    Queued {
+        /// Session it belongs to.
+        session_id: String,
+        /// The waiting prompt.
+        prompt_id: String,
+        /// Prompts ahead of it.
+        position: usize,
+    },
+    /// The end of a prompt's updates.
+    Done {
+        /// Session it belongs to.
+        session_id: String,
+    },
+    /// A line from the agent's stderr.
+    AgentLog(String),
+    /// A change in an MCP server's availability.
+    McpServerStatus(McpServerStatus),
+    /// Another notification.
+    Notification {
+        /// Method name.
+        method: String,
+        /// Parameters, `null` if there were none.
+        params: Value,
+    },
+    /// A session update of a type the client doesn't know.
+    UnknownUpdate {
+        /// Session it belongs to.
+        session_id: String,
+        /// The update's type.
+        update_type: String,
+        /// Its data, `null` if there was none.
+        data: Value,
+    },
+}
+
+/// An [`UpdateEvent`] and when it arrived, on Tokio's clock.
+#[derive(Debug, Clone)]
+pub struct Collected {
+    /// When the handler was called.
+    pub at: Instant,
+    /// What it was called with.
+    pub event: UpdateEvent,
+}
+
+#[derive(Default)]
+struct Inner {
+    events: Mutex<Vec<Collected>>,
+    /// Woken on every event.
+    changed: Notify,
+}
+
+/// An [`UpdateHandler`] that records every call, for tests of clients and
+/// agents.
+///
+/// Clones share the record, so keep one and hand another to the client:
+///
+/// ```rust,no_run
+/// # use heroacp::client::Client;
+/// # use heroacp::testing::UpdateCollector;
+/// # async fn demo(client: Client) -> heroacp::protocol::AcpResult<()> {
+/// # use std::time::Duration;
+/// let updates = UpdateCollector::new();
+/// client.set_update_handler(Box::new(updates.clone())).await;
+/// // ... send a prompt ...
+/// updates.await_done(Duration::from_secs(5)).await?;
+/// updates.assert_message_contains("Hello");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct UpdateCollector {
+    inner: Arc<Inner>,
+}
+
+impl UpdateCollector {
+    /// Create an empty collector.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(&self, event: UpdateEvent) {
+        self.inner.events.lock().unwrap().push(Collected {
+            at: Instant::now(),
+            event,
+        });
+        self.inner.changed.notify_waiters();
+    }
+
+    /// Everything recorded so far, in order.
+    pub fn events(&self) -> Vec<Collected> {
+        self.inner.events.lock().unwrap().clone()
+    }
+
+    /// Forget everything recorded, e.g. between prompts.
+    pub fn clear(&self) {
+        self.inner.events.lock().unwrap().clear();
+    }
+
+    /// The text of every message chunk, joined.
+    pub fn messages(&self) -> String {
+        self.joined(|event| match event {
+            UpdateEvent::Message { text, .. } => Some(text),
+            _ => None,
+        })
+    }
+
+    /// The text of every thought chunk, joined.
+    pub fn thoughts(&self) -> String {
+        self.joined(|event| match event {
+            UpdateEvent::Thought { text, .. } => Some(text),
+            _ => None,
+        })
+    }
+
+    fn joined(&self, text: impl Fn(&UpdateEvent) -> Option<&String>) -> String {
+        let events = self.inner.events.lock().unwrap();
+        events.iter().filter_map(|c| text(&c.event)).map(String::as_str).collect()
+    }
+
+    /// Every tool call started, in order.
+    pub fn tool_calls(&self) -> Vec<ToolCall> {
+        let events = self.inner.events.lock().unwrap();
+        events
+            .iter()
+            .filter_map(|c| match &c.event {
+                UpdateEvent::ToolCall { tool, .. } => Some(tool.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// How many `done` updates arrived.
+    pub fn done_count(&self) -> usize {
+        let events = self.inner.events.lock().unwrap();
+        events
+            .iter()
+            .filter(|c| matches!(c.event, UpdateEvent::Done { .. }))
+            .count()
+    }
+
+    /// Wait until a `done` update has arrived, failing with
+    /// [`AcpError::Timeout`] if none does within `timeout`.
+    ///
+    /// Returns at once if one already has; [`clear`](Self::clear) between
+    /// prompts to wait for the next.
+    pub async fn await_done(&self, timeout: Duration) -> AcpResult<()> {
+        let wait = async {
+            loop {
+                let changed = self.inner.changed.notified();
+                if self.done_count() > 0 {
+                    return;
+                }
+                changed.await;
+            }
+        };
+        tokio::time::timeout(timeout, wait)
+            .await
+            .map_err(|_| AcpError::Timeout)
+    }
+
+    /// Panic unless the joined message chunks contain `needle`.
+    #[track_caller]
+    pub fn assert_message_contains(&self, needle: &str) {
+        let messages = self.messages();
+        assert!(
+            messages.contains(needle),
+            "agent messages don't contain {:?}: {:?}",
+            needle,
+            messages
+        );
+    }
+}
+
+impl UpdateHandler for UpdateCollector {
+    fn on_agent_message(&self, session_id: &str, text: &str) {
+        self.push(UpdateEvent::Message {
+            session_id: session_id.to_string(),
+            text: text.to_string(),
+        });
+    }
+
+    fn on_agent_thought(&self, session_id: &str, text: &str) {
+        self.push(UpdateEvent::Thought {
+            session_id: session_id.to_string(),
+            text: text.to_string(),
+        });
+    }
+
+    fn on_audio_chunk(&self, session_id: &str, chunk: &AudioChunk) {
+        self.push(UpdateEvent::AudioChunk {
+            session_id: session_id.to_string(),
+            chunk: chunk.clone(),
+        });
+    }
+
+    fn on_tool_call(&self, session_id: &str, tool: &ToolCall) {
+        self.push(UpdateEvent::ToolCall {
+            session_id: session_id.to_string(),
+            tool: tool.clone(),
+        });
+    }
+
+    fn on_tool_update(&self, session_id: &str, update: &ToolCallUpdate) {
+        self.push(UpdateEvent::ToolUpdate {
+            session_id: session_id.to_string(),
+            update: update.clone(),
+        });
+    }
+
+    fn on_tool_terminal(&self, session_id: &str, tool_call_id: &str, terminal_id: &str) {
+        self.push(UpdateEvent::ToolTerminal {
+            session_id: session_id.to_string(),
+            tool_call_id: tool_call_id.to_string(),
+            terminal_id: terminal_id.to_string(),
+        });
+    }
+
+    fn on_plan(&self, session_id: &str, plan: &Plan) {
+        self.push(UpdateEvent::Plan {
+            session_id: session_id.to_string(),
+            plan: plan.clone(),
+        });
+    }
+
+    fn on_mode_change(&self, session_id: &str, mode: &str) {
+        self.push(UpdateEvent::ModeChange {
+            session_id: session_id.to_string(),
+            mode: mode.to_string(),
+        });
+    }
+
+    fn on_model_change(&self, session_id: &str, model: &str) {
+        self.push(UpdateEvent::ModelChange {
+            session_id: session_id.to_string(),
+            model: model.to_string(),
+        });
+    }
+
+    fn on_status(&self, session_id: &str, state: AgentState) {
+        self.push(UpdateEvent::Status {
+            session_id: session_id.to_string(),
+            state,
+        });
+    }
+
+    fn on_queued(&self, session_id: &str, prompt_id: &str, position: usize) {
+        self.push(UpdateEvent::Queued {
+            session_id: session_id.to_string(),
+            prompt_id: prompt_id.to_string(),
+            position,
+        });
+    }
+
+    fn on_done(&self, session_id: &str) {
+        self.push(UpdateEvent::Done {
+            session_id: session_id.to_string(),
+        });
+    }
+
+    fn on_agent_log(&self, line: &str) {
+        self.push(UpdateEvent::AgentLog(line.to_string()));
+    }
+
+    fn on_mcp_server_status(&self, status: &McpServerStatus) {
+        self.push(UpdateEvent::McpServerStatus(status.clone()));
+    }
+
+    fn on_notification(&self, method: &str, params: &Value) {
+        self.push(UpdateEvent::Notification {
+            method: method.to_string(),
+            params: params.clone(),
+        });
+    }
+
+    fn on_unknown_update(&self, session_id: &str, update_type: &str, data: &Value) {
+        self.push(UpdateEvent::UnknownUpdate {
+            session_id: session_id.to_string(),
+            update_type: update_type.to_string(),
+            data: data.clone(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_await_done() {
+        let updates = UpdateCollector::new();
+        let err = updates.await_done(Duration::from_secs(1)).await.unwrap_err();
+        assert!(matches!(err, AcpError::Timeout));
+
+        let handler = updates.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            handler.on_agent_message("s1", "Hello, ");
+            handler.on_agent_thought("s1", "hmm");
+            handler.on_agent_message("s1", "world");
+            handler.on_done("s1");
+        });
+        let started = Instant::now();
+        updates.await_done(Duration::from_secs(1)).await.unwrap();
+        assert_eq!(started.elapsed(), Duration::from_millis(100));
+
+        updates.assert_message_contains("Hello, world");
+        assert_eq!(updates.thoughts(), "hmm");
+        assert_eq!(updates.events().len(), 4);
+        assert!(updates.events().iter().all(|c| c.at == started + Duration::from_millis(100)));
+
+        updates.clear();
+        assert_eq!(updates.done_count(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "don't contain \"bye\"")]
+    fn test_assert_message_contains() {
+        let updates = UpdateCollector::new();
+        updates.on_agent_message("s1", "hello");
+        updates.assert_message_contains("bye");
+    }
+}
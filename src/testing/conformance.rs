@@ -0,0 +1,507 @@
+//! Checking an agent against the protocol.
+//!
+//! [`Conformance`] spawns an agent and runs a battery of checks on its wire
+//! behavior: the shape of `initialize`, the error codes of the protocol
+//! states, cancellation, and the framing of session updates. Each check gets
+//! a fresh process, so one failure doesn't spill into the next:
+//!
+//! ```rust,no_run
+//! # use heroacp::testing::Conformance;
+//! # async fn demo() {
+//! let report = Conformance::new("./my-agent").run().await;
+//! print!("{}", report);
+//! assert!(report.passed());
+//! # }
+//! ```
+//!
+//! The `acp-conformance` binary runs the same checks from the command line.
+
+use serde_json::{json, Value};
+use std::fmt;
+use std::future::Future;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::time::timeout;
+
+use crate::protocol::*;
+
+/// How long the agent has to send each expected message by default.
+pub const DEFAULT_CHECK_DEADLINE: Duration = Duration::from_secs(10);
+
+/// How long updates may trail a prompt's response.
+const UPDATE_GRACE: Duration = Duration::from_millis(500);
+
+/// Runs the conformance checks against an agent command.
+#[derive(Debug, Clone)]
+pub struct Conformance {
+    command: String,
+    args: Vec<String>,
+    deadline: Duration,
+}
+
+impl Conformance {
+    /// Check the agent launched by `command`, found on `PATH` unless it's a
+    /// path.
+    pub fn new(command: impl Into<String>) -> Self {
+        Self {
+            command: command.into(),
+            args: Vec::new(),
+            deadline: DEFAULT_CHECK_DEADLINE,
+        }
+    }
+
+    /// Pass `args` to the agent.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Fail a check if the agent takes longer than `deadline` to send a
+    /// message it expects.
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Run every check, in order.
+    pub async fn run(&self) -> Report {
+        let mut report = Report::default();
+        report.checks.push(self.check("initialize", "initialize returns agent info and capabilities", initialize).await);
+        report.checks.push(self.check("initialize-twice", "a second initialize fails with -32003", initialize_twice).await);
+        report.checks.push(self.check("not-initialized", "session/new before initialize fails with -32003", not_initialized).await);
+        report.checks.push(self.check("ping", "ping is answered before initialize", ping).await);
+        report.checks.push(self.check("parse-error", "malformed JSON fails with -32700 and a null id", parse_error).await);
+        report.checks.push(self.check("method-not-found", "unknown methods fail with -32601", method_not_found).await);
+        report.checks.push(self.check("invalid-params", "malformed params fail with -32602", invalid_params).await);
+        report.checks.push(self.check("unknown-session", "prompts for unknown sessions fail with -32003", unknown_session).await);
+        report.checks.push(self.check("prompt-updates", "prompts stream well-formed updates ending in done", prompt_updates).await);
+        report.checks.push(self.check("cancel", "cancelled prompts respond and the session stays usable", cancel).await);
+        report
+    }
+
+    /// Run `check` against a fresh agent process.
+    async fn check<F, Fut>(&self, name: &'static str, description: &'static str, check: F) -> Check
+    where
+        F: FnOnce(Peer) -> Fut,
+        Fut: Future<Output = Result<(), String>>,
+    {
+        let failure = match self.spawn() {
+            Ok(peer) => check(peer).await.err(),
+            Err(e) => Some(format!("Failed to spawn agent {}: {}", self.command, e)),
+        };
+        Check {
+            name,
+            description,
+            failure,
+        }
+    }
+
+    fn spawn(&self) -> std::io::Result<Peer> {
+        let mut child = Command::new(&self.command)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+        let stdin = child.stdin.take().expect("agent stdin is piped");
+        let stdout = child.stdout.take().expect("agent stdout is piped");
+        Ok(Peer {
+            _child: child,
+            stdin,
+            lines: BufReader::new(stdout).lines(),
+            deadline: self.deadline,
+            next_id: 1,
+            responses: Vec::new(),
+            notifications: Vec::new(),
+        })
+    }
+}
+
+/// The outcome of one conformance check.
+#[derive(Debug, Clone)]
+pub struct Check {
+    /// Short name, e.g. `parse-error`.
+    pub name: &'static str,
+    /// What the check requires of the agent.
+    pub description: &'static str,
+    /// Why the agent failed the check, or `None` if it passed.
+    pub failure: Option<String>,
+}
+
+impl Check {
+    /// Whether the agent passed the check.
+    pub fn passed(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+/// The outcome of every check, printable as a pass/fail report.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    /// Checks in the order they ran.
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// Whether the agent passed every check.
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(Check::passed)
+    }
+
+    /// The checks the agent failed.
+    pub fn failures(&self) -> impl Iterator<Item = &Check> {
+        self.checks.iter().filter(|check| !check.passed())
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            match &check.failure {
+                None => writeln!(f, "PASS  {:<18} {}", check.name, check.description)?,
+                Some(failure) => {
+                    writeln!(f, "FAIL  {:<18} {}", check.name, check.description)?;
+                    writeln!(f, "      {:<18} {}", "", failure)?;
+                }
+            }
+        }
+        let passed = self.checks.iter().filter(|check| check.passed()).count();
+        writeln!(f, "\n{} of {} checks passed", passed, self.checks.len())
+    }
+}
+
+/// The client's side of a connection to the agent under test.
+struct Peer {
+    _child: Child,
+    stdin: ChildStdin,
+    lines: Lines<BufReader<ChildStdout>>,
+    deadline: Duration,
+    next_id: i64,
+    /// Responses not yet claimed by [`response`](Self::response).
+    responses: Vec<Value>,
+    /// `session/update` params received so far.
+    notifications: Vec<Value>,
+}
+
+impl Peer {
+    async fn send_line(&mut self, line: &str) -> Result<(), String> {
+        let write = async {
+            self.stdin.write_all(line.as_bytes()).await?;
+            self.stdin.write_all(b"\n").await?;
+            self.stdin.flush().await
+        };
+        write.await.map_err(|e| format!("Failed to write to the agent: {}", e))
+    }
+
+    /// Send a request without waiting for its response, returning its ID.
+    async fn send(&mut self, method: &str, params: Value) -> Result<i64, String> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params});
+        self.send_line(&request.to_string()).await?;
+        Ok(id)
+    }
+
+    /// Send a request and wait for its response.
+    async fn request(&mut self, method: &str, params: Value) -> Result<Value, String> {
+        let id = self.send(method, params).await?;
+        self.response(json!(id), method).await
+    }
+
+    /// Wait for the response with `id`, handling other messages meanwhile.
+    async fn response(&mut self, id: Value, method: &str) -> Result<Value, String> {
+        loop {
+            if let Some(index) = self.responses.iter().position(|r| r["id"] == id) {
+                return Ok(self.responses.remove(index));
+            }
+            self.receive(self.deadline)
+                .await?
+                .ok_or_else(|| format!("No response to {} within {:?}", method, self.deadline))?;
+        }
+    }
+
+    /// Read and handle one message; `None` if none arrived within `limit`.
+    async fn receive(&mut self, limit: Duration) -> Result<Option<()>, String> {
+        let line = match timeout(limit, self.lines.next_line()).await {
+            Err(_) => return Ok(None),
+            Ok(Err(e)) => return Err(format!("Failed to read from the agent: {}", e)),
+            Ok(Ok(None)) => return Err("The agent closed its output".to_string()),
+            Ok(Ok(Some(line))) => line,
+        };
+        if line.trim().is_empty() {
+            return Ok(Some(()));
+        }
+        let message = check_frame(&line)?;
+        match (message.get("method").and_then(Value::as_str), message.get("id")) {
+            (Some(method), Some(id)) => {
+                let reply = answer(method, id.clone());
+                self.send_line(&reply.to_string()).await?;
+            }
+            (Some(method::SESSION_UPDATE), None) => {
+                self.notifications.push(message["params"].clone());
+            }
+            (Some(_), None) => {}
+            (None, _) => self.responses.push(message),
+        }
+        Ok(Some(()))
+    }
+
+    async fn initialize(&mut self) -> Result<Value, String> {
+        let params = serde_json::to_value(initialize_params()).map_err(|e| e.to_string())?;
+        let response = self.request(method::INITIALIZE, params).await?;
+        result(&response, method::INITIALIZE).cloned()
+    }
+
+    async fn session_new(&mut self, session_id: &str) -> Result<(), String> {
+        let response = self
+            .request(method::SESSION_NEW, json!({"session_id": session_id}))
+            .await?;
+        result(&response, method::SESSION_NEW).map(|_| ())
+    }
+}
+
+/// Parse an NDJSON line and check it is a JSON-RPC 2.0 message.
+fn check_frame(line: &str) -> Result<Value, String> {
+    let message: Value = serde_json::from_str(line)
+        .map_err(|e| format!("Agent wrote a line that isn't JSON ({}): {}", e, line))?;
+    if !message.is_object() {
+        return Err(format!("Agent wrote a message that isn't an object: {}", line));
+    }
+    if message["jsonrpc"] != "2.0" {
+        return Err(format!("Message lacks \"jsonrpc\": \"2.0\": {}", line));
+    }
+    if let Some(method) = message.get("method") {
+        if !method.is_string() {
+            return Err(format!("Message has a non-string method: {}", line));
+        }
+        return Ok(message);
+    }
+    if message.get("id").is_none() {
+        return Err(format!("Response has no id: {}", line));
+    }
+    match (message.get("result"), message.get("error")) {
+        (Some(_), None) => Ok(message),
+        (None, Some(error)) => {
+            if !error["code"].is_i64() || !error["message"].is_string() {
+                return Err(format!("Error lacks an integer code and a message: {}", line));
+            }
+            Ok(message)
+        }
+        _ => Err(format!("Response must have exactly one of result and error: {}", line)),
+    }
+}
+
+/// The reply to a request from the agent: permission requests are
+/// dismissed, everything else is refused, as the checks advertise no client
+/// capabilities.
+fn answer(method: &str, id: Value) -> Value {
+    if method == method::SESSION_REQUEST_PERMISSION {
+        let result = RequestPermissionResult {
+            outcome: PermissionOutcome::Cancelled,
+        };
+        return json!({"jsonrpc": "2.0", "id": id, "result": result});
+    }
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {"code": codes::METHOD_NOT_FOUND, "message": format!("Method not found: {}", method)}
+    })
+}
+
+/// The result of `response`, or why there is none.
+fn result<'a>(response: &'a Value, method: &str) -> Result<&'a Value, String> {
+    match response.get("result") {
+        Some(result) => Ok(result),
+        None => Err(format!("{} failed: {}", method, response["error"])),
+    }
+}
+
+/// Check `response` is an error with `code`.
+fn expect_error(response: &Value, code: i32, what: &str) -> Result<(), String> {
+    match response.get("error") {
+        Some(error) if error["code"] == code => Ok(()),
+        Some(error) => Err(format!("{} failed with {} instead of {}", what, error["code"], code)),
+        None => Err(format!("{} succeeded, expected error {}", what, code)),
+    }
+}
+
+/// `initialize` params for a client without optional capabilities.
+fn initialize_params() -> InitializeParams {
+    let mut params = super::initialize_params();
+    params.client_info.name = "acp-conformance".to_string();
+    params
+}
+
+fn prompt_params(session_id: &str, text: &str) -> Value {
+    json!({"session_id": session_id, "content": [ContentBlock::text(text)]})
+}
+
+async fn initialize(mut peer: Peer) -> Result<(), String> {
+    let result = peer.initialize().await?;
+    let info = &result["agent_info"];
+    if info["name"].as_str().is_none_or(str::is_empty) {
+        return Err(format!("agent_info.name must be a non-empty string, got {}", info["name"]));
+    }
+    if !info["version"].is_string() {
+        return Err(format!("agent_info.version must be a string, got {}", info["version"]));
+    }
+    if !result["capabilities"].is_object() {
+        return Err(format!("capabilities must be an object, got {}", result["capabilities"]));
+    }
+    serde_json::from_value::<InitializeResult>(result.clone())
+        .map(|_| ())
+        .map_err(|e| format!("Result doesn't match InitializeResult: {}", e))
+}
+
+async fn initialize_twice(mut peer: Peer) -> Result<(), String> {
+    peer.initialize().await?;
+    let params = serde_json::to_value(initialize_params()).map_err(|e| e.to_string())?;
+    let response = peer.request(method::INITIALIZE, params).await?;
+    expect_error(&response, codes::INVALID_STATE, "The second initialize")
+}
+
+async fn not_initialized(mut peer: Peer) -> Result<(), String> {
+    let response = peer
+        .request(method::SESSION_NEW, json!({"session_id": "conformance"}))
+        .await?;
+    expect_error(&response, codes::INVALID_STATE, "session/new before initialize")
+}
+
+async fn ping(mut peer: Peer) -> Result<(), String> {
+    let response = peer.request(method::PING, json!({})).await?;
+    result(&response, method::PING).map(|_| ())
+}
+
+async fn parse_error(mut peer: Peer) -> Result<(), String> {
+    peer.send_line("{\"jsonrpc\": \"2.0\", \"id\": 1, ").await?;
+    let response = peer.response(Value::Null, "a malformed message").await?;
+    expect_error(&response, codes::PARSE_ERROR, "The malformed message")
+}
+
+async fn method_not_found(mut peer: Peer) -> Result<(), String> {
+    peer.initialize().await?;
+    let response = peer.request("conformance/no_such_method", json!({})).await?;
+    expect_error(&response, codes::METHOD_NOT_FOUND, "An unknown method")
+}
+
+async fn invalid_params(mut peer: Peer) -> Result<(), String> {
+    peer.initialize().await?;
+    let response = peer.request(method::SESSION_NEW, json!({"session_id": 42})).await?;
+    expect_error(&response, codes::INVALID_PARAMS, "session/new with a numeric session_id")
+}
+
+async fn unknown_session(mut peer: Peer) -> Result<(), String> {
+    peer.initialize().await?;
+    let response = peer
+        .request(method::SESSION_PROMPT, prompt_params("no-such-session", "hello"))
+        .await?;
+    expect_error(&response, codes::INVALID_STATE, "A prompt for an unknown session")
+}
+
+async fn prompt_updates(mut peer: Peer) -> Result<(), String> {
+    peer.initialize().await?;
+    peer.session_new("conformance").await?;
+    let response = peer
+        .request(method::SESSION_PROMPT, prompt_params("conformance", "hello"))
+        .await?;
+    let result = result(&response, method::SESSION_PROMPT)?;
+    if !result["status"].is_string() {
+        return Err(format!("The prompt result has no status: {}", result));
+    }
+
+    // Updates may trail the response; give them a moment
+    let is_done = |params: &Value| params["type"] == "done";
+    while !peer.notifications.iter().any(is_done) {
+        if peer.receive(UPDATE_GRACE).await?.is_none() {
+            return Err("The prompt sent no done update".to_string());
+        }
+    }
+
+    for params in &peer.notifications {
+        let update: SessionUpdate = serde_json::from_value(params.clone())
+            .map_err(|e| format!("Malformed session/update ({}): {}", e, params))?;
+        if update.session_id != "conformance" {
+            return Err(format!("Update for the wrong session: {}", params));
+        }
+    }
+    if peer.notifications.iter().filter(|params| is_done(params)).count() > 1 {
+        return Err("The prompt sent more than one done update".to_string());
+    }
+    Ok(())
+}
+
+async fn cancel(mut peer: Peer) -> Result<(), String> {
+    peer.initialize().await?;
+    peer.session_new("conformance").await?;
+    let prompt = peer
+        .send(method::SESSION_PROMPT, prompt_params("conformance", "Make a plan"))
+        .await?;
+    let cancel = peer
+        .request(method::SESSION_CANCEL, json!({"session_id": "conformance"}))
+        .await?;
+    result(&cancel, method::SESSION_CANCEL)?;
+
+    // An agent may finish before it sees the cancellation
+    let finished_first = peer.responses.iter().any(|r| r["id"] == prompt);
+    let response = peer.response(json!(prompt), "the cancelled prompt").await?;
+    let status = &result(&response, method::SESSION_PROMPT)?["status"];
+    if !finished_first && *status != "cancelled" {
+        return Err(format!("The cancelled prompt responded with status {}", status));
+    }
+
+    let response = peer
+        .request(method::SESSION_PROMPT, prompt_params("conformance", "hello"))
+        .await?;
+    result(&response, "A prompt after session/cancel").map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_frame() {
+        assert!(check_frame(r#"{"jsonrpc":"2.0","id":1,"result":null}"#).is_ok());
+        assert!(check_frame(r#"{"jsonrpc":"2.0","method":"session/update","params":{}}"#).is_ok());
+        assert!(check_frame(r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32700,"message":"x"}}"#).is_ok());
+
+        assert!(check_frame("not json").is_err());
+        assert!(check_frame("[1]").is_err());
+        assert!(check_frame(r#"{"id":1,"result":{}}"#).is_err());
+        assert!(check_frame(r#"{"jsonrpc":"2.0","result":{}}"#).is_err());
+        assert!(check_frame(r#"{"jsonrpc":"2.0","id":1}"#).is_err());
+        assert!(check_frame(r#"{"jsonrpc":"2.0","id":1,"result":1,"error":{"code":1,"message":"x"}}"#).is_err());
+        assert!(check_frame(r#"{"jsonrpc":"2.0","id":1,"error":{"code":"bad"}}"#).is_err());
+    }
+
+    #[test]
+    fn test_report() {
+        let report = Report {
+            checks: vec![
+                Check {
+                    name: "ping",
+                    description: "ping is answered",
+                    failure: None,
+                },
+                Check {
+                    name: "cancel",
+                    description: "cancel works",
+                    failure: Some("no response".to_string()),
+                },
+            ],
+        };
+        assert!(!report.passed());
+        assert_eq!(report.failures().map(|c| c.name).collect::<Vec<_>>(), vec!["cancel"]);
+        let text = report.to_string();
+        assert!(text.contains("PASS  ping"));
+        assert!(text.contains("FAIL  cancel"));
+        assert!(text.contains("1 of 2 checks passed"));
+    }
+}
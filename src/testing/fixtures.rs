@@ -0,0 +1,306 @@
+//! Ready-made protocol values for tests.
+//!
+//! Each constructor returns the protocol type itself, filled in with
+//! defaults a test rarely cares about; the extension traits here adjust
+//! them builder-style. Import everything at once:
+//!
+//! ```rust
+//! use heroacp::testing::fixtures::*;
+//!
+//! let init = init_params().with_capability("terminal").with_working_directory("/repo");
+//! assert!(init.capabilities.terminal);
+//!
+//! let prompt = prompt("hi").in_session("s2");
+//! let call = tool_call("read_file").with_arguments(serde_json::json!({"path": "/a.txt"}));
+//! # let _ = (prompt, call);
+//! ```
+//!
+//! Session-scoped values belong to [`SESSION_ID`] unless moved with
+//! [`InSession::in_session`].
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::protocol::*;
+
+/// Session the fixtures belong to by default.
+pub const SESSION_ID: &str = "s1";
+
+/// ID of the tool call from [`tool_call`].
+pub const TOOL_CALL_ID: &str = "call-1";
+
+/// `initialize` params for a client without optional capabilities; same as
+/// [`initialize_params`](super::initialize_params).
+pub fn init_params() -> InitializeParams {
+    super::initialize_params()
+}
+
+/// An `initialize` result for an agent named `name` without optional
+/// capabilities.
+pub fn init_result(name: &str) -> InitializeResult {
+    InitializeResult {
+        agent_info: AgentInfo {
+            name: name.to_string(),
+            version: "0.0.0".to_string(),
+        },
+        capabilities: AgentCapabilities::default(),
+        instructions: None,
+    }
+}
+
+/// `session/new` params for [`SESSION_ID`] without a mode or agent.
+pub fn session_new() -> SessionNewParams {
+    SessionNewParams {
+        session_id: SESSION_ID.to_string(),
+        mode: None,
+        agent: None,
+    }
+}
+
+/// A prompt of one text block in [`SESSION_ID`].
+pub fn prompt(text: &str) -> SessionPromptParams {
+    SessionPromptParams {
+        session_id: SESSION_ID.to_string(),
+        content: vec![ContentBlock::text(text)],
+    }
+}
+
+/// `session/cancel` params for everything in [`SESSION_ID`].
+pub fn cancel() -> SessionCancelParams {
+    SessionCancelParams {
+        session_id: SESSION_ID.to_string(),
+        prompt_id: None,
+    }
+}
+
+/// A call of the tool `name` with [`TOOL_CALL_ID`] and no arguments.
+pub fn tool_call(name: &str) -> ToolCall {
+    ToolCall {
+        id: TOOL_CALL_ID.to_string(),
+        name: name.to_string(),
+        arguments: serde_json::json!({}),
+    }
+}
+
+/// A permission request for `tool`, offering to allow or reject it once.
+pub fn permission_request(tool: ToolCall) -> RequestPermissionParams {
+    RequestPermissionParams {
+        session_id: SESSION_ID.to_string(),
+        tool_call: tool,
+        options: vec![
+            PermissionOption {
+                option_id: "allow".to_string(),
+                name: "Allow".to_string(),
+                kind: PermissionOptionKind::AllowOnce,
+            },
+            PermissionOption {
+                option_id: "reject".to_string(),
+                name: "Reject".to_string(),
+                kind: PermissionOptionKind::RejectOnce,
+            },
+        ],
+    }
+}
+
+/// A message chunk update in [`SESSION_ID`].
+pub fn message_chunk(text: &str) -> SessionUpdate {
+    update(SessionUpdateType::AgentMessageChunk {
+        text: text.to_string(),
+    })
+}
+
+/// A `done` update in [`SESSION_ID`].
+pub fn done() -> SessionUpdate {
+    update(SessionUpdateType::Done)
+}
+
+fn update(update_type: SessionUpdateType) -> SessionUpdate {
+    SessionUpdate {
+        session_id: SESSION_ID.to_string(),
+        update_type,
+    }
+}
+
+/// Set the flag `name`, as spelled on the wire, in `capabilities`. Names
+/// other than flags go in `experimental` if there is one.
+#[track_caller]
+fn with_flag<T: Serialize + DeserializeOwned>(capabilities: &T, name: &str) -> T {
+    let mut value = serde_json::to_value(capabilities).expect("capabilities serialize");
+    match value.get(name) {
+        Some(Value::Bool(_)) => value[name] = Value::Bool(true),
+        _ if value.get("experimental").is_some() => value["experimental"][name] = Value::Bool(true),
+        _ => panic!("no capability named {:?}", name),
+    }
+    serde_json::from_value(value).expect("capabilities deserialize")
+}
+
+/// Moves session-scoped fixtures to another session.
+pub trait InSession {
+    /// Belong to `session_id` instead.
+    fn in_session(self, session_id: &str) -> Self;
+}
+
+macro_rules! in_session {
+    ($($ty:ty),*) => {
+        $(
+            impl InSession for $ty {
+                fn in_session(mut self, session_id: &str) -> Self {
+                    self.session_id = session_id.to_string();
+                    self
+                }
+            }
+        )*
+    };
+}
+
+in_session!(
+    SessionNewParams,
+    SessionPromptParams,
+    SessionCancelParams,
+    RequestPermissionParams,
+    SessionUpdate
+);
+
+/// Builder methods for [`init_params`].
+pub trait InitializeParamsExt {
+    /// Advertise the capability `name` as spelled on the wire, e.g.
+    /// `"terminal"` or `"text_files"`. Unknown names are advertised as
+    /// experimental.
+    fn with_capability(self, name: &str) -> Self;
+
+    /// Report `dir` as the working directory.
+    fn with_working_directory(self, dir: &str) -> Self;
+
+    /// Ask the agent to connect to an MCP server.
+    fn with_mcp_server(self, name: &str, url: &str) -> Self;
+}
+
+impl InitializeParamsExt for InitializeParams {
+    #[track_caller]
+    fn with_capability(mut self, name: &str) -> Self {
+        self.capabilities = with_flag(&self.capabilities, name);
+        self
+    }
+
+    fn with_working_directory(mut self, dir: &str) -> Self {
+        self.working_directory = dir.to_string();
+        self
+    }
+
+    fn with_mcp_server(mut self, name: &str, url: &str) -> Self {
+        self.mcp_servers.push(McpServer {
+            name: name.to_string(),
+            url: url.to_string(),
+            credentials: Default::default(),
+        });
+        self
+    }
+}
+
+/// Builder methods for [`init_result`].
+pub trait InitializeResultExt {
+    /// Advertise the capability `name` as spelled on the wire, e.g.
+    /// `"streaming"`. Panics if there is no such flag.
+    fn with_capability(self, name: &str) -> Self;
+
+    /// Offer a tool `name` taking any object.
+    fn with_tool(self, name: &str) -> Self;
+
+    /// Give the client instructions.
+    fn with_instructions(self, instructions: &str) -> Self;
+}
+
+impl InitializeResultExt for InitializeResult {
+    #[track_caller]
+    fn with_capability(mut self, name: &str) -> Self {
+        self.capabilities = with_flag(&self.capabilities, name);
+        self
+    }
+
+    fn with_tool(mut self, name: &str) -> Self {
+        self.capabilities.tools.push(ToolInfo {
+            name: name.to_string(),
+            description: String::new(),
+            parameters: serde_json::json!({"type": "object"}),
+        });
+        self
+    }
+
+    fn with_instructions(mut self, instructions: &str) -> Self {
+        self.instructions = Some(instructions.to_string());
+        self
+    }
+}
+
+/// Builder methods for [`prompt`].
+pub trait PromptExt {
+    /// Add another content block.
+    fn with_block(self, block: ContentBlock) -> Self;
+}
+
+impl PromptExt for SessionPromptParams {
+    fn with_block(mut self, block: ContentBlock) -> Self {
+        self.content.push(block);
+        self
+    }
+}
+
+/// Builder methods for [`tool_call`].
+pub trait ToolCallExt {
+    /// Use `id` instead of [`TOOL_CALL_ID`].
+    fn with_id(self, id: &str) -> Self;
+
+    /// Call the tool with `arguments`.
+    fn with_arguments(self, arguments: Value) -> Self;
+}
+
+impl ToolCallExt for ToolCall {
+    fn with_id(mut self, id: &str) -> Self {
+        self.id = id.to_string();
+        self
+    }
+
+    fn with_arguments(mut self, arguments: Value) -> Self {
+        self.arguments = arguments;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities_by_name() {
+        let init = init_params()
+            .with_capability("terminal")
+            .with_capability("text_files")
+            .with_capability("vendor_thing");
+        assert!(init.capabilities.terminal && init.capabilities.text_files);
+        assert!(!init.capabilities.audio);
+        assert_eq!(init.capabilities.experimental["vendor_thing"], true);
+
+        let result = init_result("agent").with_capability("streaming").with_tool("read_file");
+        assert!(result.capabilities.streaming);
+        assert_eq!(result.capabilities.tools[0].name, "read_file");
+    }
+
+    #[test]
+    #[should_panic(expected = "no capability named \"telepathy\"")]
+    fn test_unknown_agent_capability() {
+        init_result("agent").with_capability("telepathy");
+    }
+
+    #[test]
+    fn test_session_fixtures() {
+        let prompt = prompt("hi").with_block(ContentBlock::text("there")).in_session("s2");
+        assert_eq!(prompt.session_id, "s2");
+        assert_eq!(prompt.content.len(), 2);
+        assert_eq!(done().in_session("s2").session_id, "s2");
+
+        let request = permission_request(tool_call("read_file").with_id("call-7"));
+        assert_eq!(request.tool_call.id, "call-7");
+        assert_eq!(request.session_id, SESSION_ID);
+    }
+}
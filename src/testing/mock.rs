@@ -0,0 +1,289 @@
+//! A scripted agent driven by scenario files.
+//!
+//! A [`Scenario`] lists rules that answer prompts matching a pattern with
+//! a sequence of steps: message and thought chunks, plans, tool calls,
+//! pauses and errors. [`MockAgent`] plays them, so client developers can
+//! reproduce a specific agent behavior on demand:
+//!
+//! ```json
+//! {
+//!   "rules": [
+//!     {
+//!       "match": "(?i)refactor",
+//!       "steps": [
+//!         { "thought": "Looking at the code..." },
+//!         { "tool_call": { "name": "read_file", "arguments": { "path": "src/main.rs" }, "result": "fn main() {}" } },
+//!         { "delay_ms": 500 },
+//!         { "message": "Done." }
+//!       ]
+//!     },
+//!     { "match": "flaky", "times": 1, "steps": [{ "error": { "code": -32603, "message": "Model overloaded" } }] }
+//!   ],
+//!   "fallback": [{ "message": "I don't know." }]
+//! }
+//! ```
+//!
+//! The bundled `acp-server` plays the scenario named by `ACP_SCENARIO`
+//! instead of its canned answers.
+
+use async_trait::async_trait;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::protocol::*;
+use crate::server::{Agent, AgentContext, SessionHandle};
+
+/// What a [`MockAgent`] does, usually loaded from a JSON file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Scenario {
+    /// Name and version to report; defaults to `mock-agent`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent_info: Option<AgentInfo>,
+    /// Capabilities to report.
+    #[serde(default)]
+    pub capabilities: AgentCapabilities,
+    /// Rules tried in order against each prompt; the first match answers.
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    /// Steps for prompts no rule matches. Without any, such prompts just
+    /// end.
+    #[serde(default)]
+    pub fallback: Vec<Step>,
+}
+
+/// Steps to play for prompts matching a pattern.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rule {
+    /// Regex the prompt's text must match; every prompt matches without one.
+    #[serde(default, rename = "match", skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    /// How many prompts the rule answers before it's skipped; unlimited
+    /// without a limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub times: Option<usize>,
+    /// What to do, in order.
+    pub steps: Vec<Step>,
+}
+
+/// One thing a [`MockAgent`] does while answering a prompt.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    /// Send a message chunk.
+    Message(String),
+    /// Send a thought chunk.
+    Thought(String),
+    /// Send a plan.
+    Plan(Vec<PlanStep>),
+    /// Report a tool call and its outcome.
+    ToolCall(MockToolCall),
+    /// Report a change of activity.
+    Status(AgentState),
+    /// Wait, unless the prompt is cancelled first.
+    DelayMs(u64),
+    /// Fail the prompt with this error.
+    Error {
+        /// JSON-RPC error code.
+        code: i32,
+        /// Error message.
+        message: String,
+    },
+}
+
+/// A tool call reported by a [`Step::ToolCall`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MockToolCall {
+    /// Tool name.
+    pub name: String,
+    /// Arguments the tool is called with.
+    #[serde(default)]
+    pub arguments: Value,
+    /// Result to complete the call with.
+    #[serde(default)]
+    pub result: Value,
+    /// Error to fail the call with instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// An agent that answers prompts as its [`Scenario`] says.
+pub struct MockAgent {
+    scenario: Scenario,
+    /// Compiled pattern of each rule, and how many prompts it answered.
+    rules: Vec<(Option<Regex>, AtomicUsize)>,
+}
+
+impl MockAgent {
+    /// Play `scenario`. Fails if a rule's pattern isn't a valid regex.
+    pub fn new(scenario: Scenario) -> AcpResult<Self> {
+        let rules = scenario
+            .rules
+            .iter()
+            .map(|rule| {
+                let pattern = rule
+                    .pattern
+                    .as_deref()
+                    .map(Regex::new)
+                    .transpose()
+                    .map_err(|e| AcpError::InvalidParams(format!("Invalid pattern: {}", e)))?;
+                Ok((pattern, AtomicUsize::new(0)))
+            })
+            .collect::<AcpResult<_>>()?;
+        Ok(Self { scenario, rules })
+    }
+
+    /// Play the JSON scenario in the file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> AcpResult<Self> {
+        let scenario = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        Self::new(scenario)
+    }
+
+    /// Steps answering `prompt`, counting the rule's use.
+    fn steps_for(&self, prompt: &str) -> &[Step] {
+        for (rule, (pattern, uses)) in self.scenario.rules.iter().zip(&self.rules) {
+            if pattern.as_ref().is_some_and(|pattern| !pattern.is_match(prompt)) {
+                continue;
+            }
+            let used = uses.fetch_add(1, Ordering::SeqCst);
+            if rule.times.is_some_and(|times| used >= times) {
+                continue;
+            }
+            return &rule.steps;
+        }
+        &self.scenario.fallback
+    }
+}
+
+#[async_trait]
+impl Agent for MockAgent {
+    async fn initialize(&self, _params: InitializeParams) -> AcpResult<InitializeResult> {
+        Ok(InitializeResult {
+            agent_info: self.scenario.agent_info.clone().unwrap_or(AgentInfo {
+                name: "mock-agent".to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+            }),
+            capabilities: self.scenario.capabilities.clone(),
+            instructions: None,
+        })
+    }
+
+    async fn session_new(&self, params: SessionNewParams, _ctx: AgentContext) -> AcpResult<SessionNewResult> {
+        Ok(SessionNewResult {
+            session_id: params.session_id,
+        })
+    }
+
+    async fn session_prompt(
+        &self,
+        params: SessionPromptParams,
+        session: SessionHandle,
+        ctx: AgentContext,
+    ) -> AcpResult<SessionPromptResult> {
+        let prompt: Vec<&str> = params
+            .content
+            .iter()
+            .filter_map(|block| match block {
+                ContentBlock::Text { text, .. } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        let cancel = ctx.cancellation_token();
+
+        for step in self.steps_for(&prompt.join("\n")) {
+            match step {
+                Step::Message(text) => session.send_message_chunk(text.clone()).await?,
+                Step::Thought(text) => session.send_thought(text.clone()).await?,
+                Step::Plan(steps) => session.send_plan(Plan { steps: steps.clone() }).await?,
+                Step::Status(state) => session.send_status(*state).await?,
+                Step::ToolCall(call) => {
+                    let id = session.start_tool_call(call.name.clone(), call.arguments.clone()).await?;
+                    match &call.error {
+                        Some(error) => session.fail_tool_call(id, error.clone()).await?,
+                        None => session.complete_tool_call(id, call.result.clone()).await?,
+                    }
+                }
+                Step::DelayMs(ms) => {
+                    let cancelled = tokio::select! {
+                        _ = cancel.cancelled() => true,
+                        _ = tokio::time::sleep(Duration::from_millis(*ms)) => false,
+                    };
+                    if cancelled {
+                        session.send_done().await?;
+                        return Ok(SessionPromptResult {
+                            status: "cancelled".to_string(),
+                        });
+                    }
+                }
+                Step::Error { code, message } => {
+                    return Err(AcpError::Custom {
+                        code: *code,
+                        message: message.clone(),
+                        data: None,
+                    })
+                }
+            }
+        }
+
+        session.send_done().await?;
+        Ok(SessionPromptResult {
+            status: "ok".to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{initialize_params, ServerTester};
+
+    fn scenario() -> Scenario {
+        serde_json::from_value(serde_json::json!({
+            "agent_info": { "name": "scripted", "version": "2.0" },
+            "rules": [
+                {
+                    "match": "(?i)read",
+                    "steps": [
+                        { "thought": "Reading" },
+                        { "tool_call": { "name": "read_file", "arguments": { "path": "a.txt" }, "result": "hi" } },
+                        { "message": "It says hi." }
+                    ]
+                },
+                { "match": "flaky", "times": 1, "steps": [{ "error": { "code": -32000, "message": "Overloaded" } }] }
+            ],
+            "fallback": [{ "message": "Fallback" }]
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_mock_agent_plays_rules() {
+        let tester = ServerTester::new(MockAgent::new(scenario()).unwrap());
+        let init = tester.initialize(initialize_params()).await.unwrap();
+        assert_eq!(init.agent_info.name, "scripted");
+        tester.session_new("s1").await.unwrap();
+
+        let (result, updates) = tester.prompt("s1", vec![ContentBlock::text("Please READ a.txt")]).await;
+        assert_eq!(result.unwrap().status, "ok");
+        assert!(matches!(&updates[0].update_type, SessionUpdateType::AgentThoughtChunk { text } if text == "Reading"));
+        assert!(matches!(&updates[1].update_type, SessionUpdateType::ToolCall(call) if call.name == "read_file"));
+        assert!(matches!(&updates[3].update_type, SessionUpdateType::AgentMessageChunk { text } if text == "It says hi."));
+
+        // The error is injected once, then the fallback answers
+        let (result, _) = tester.prompt("s1", vec![ContentBlock::text("flaky")]).await;
+        assert_eq!(result.unwrap_err().code(), -32000);
+        let (result, updates) = tester.prompt("s1", vec![ContentBlock::text("flaky")]).await;
+        assert_eq!(result.unwrap().status, "ok");
+        assert!(matches!(&updates[0].update_type, SessionUpdateType::AgentMessageChunk { text } if text == "Fallback"));
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let mut scenario = scenario();
+        scenario.rules[0].pattern = Some("(".to_string());
+        assert!(matches!(MockAgent::new(scenario), Err(AcpError::InvalidParams(_))));
+    }
+}
@@ -0,0 +1,369 @@
+//! A scripted client for testing agents.
+//!
+//! [`MockClient`] answers the requests an agent sends to its client from a
+//! table instead of a real editor: files for `fs/read_text_file` and
+//! `fs/write_text_file`, canned output for commands run through
+//! `terminal/*`. Any request can be scripted to fail, to be answered late or
+//! never to be answered, so an agent's error handling and timeouts can be
+//! tested:
+//!
+//! ```rust,no_run
+//! # use heroacp::server::Agent;
+//! # use heroacp::protocol::*;
+//! use heroacp::testing::{MockClient, Reply, ServerTester};
+//! use std::time::Duration;
+//!
+//! # async fn demo(agent: impl Agent) -> AcpResult<()> {
+//! let client = MockClient::new()
+//!     .file("/repo/src/main.rs", "fn main() {}\n")
+//!     .command("cargo test", "test result: ok\n", 0)
+//!     .once(method::FS_WRITE_TEXT_FILE, Reply::error(codes::PERMISSION_DENIED, "Read-only"))
+//!     .on(method::TERMINAL_WAIT_FOR_EXIT, Reply::Never);
+//! let tester = ServerTester::new(agent).with_client(client.clone());
+//! // ... initialize with the fs and terminal capabilities, prompt ...
+//! assert_eq!(client.requests(method::FS_READ_TEXT_FILE).len(), 1);
+//! # Ok(())
+//! # }
+//! ```
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::time::Duration;
+
+use crate::protocol::*;
+use crate::server::error_response;
+
+/// How a [`MockClient`] answers a request.
+#[derive(Debug, Clone)]
+pub enum Reply {
+    /// Succeed with this result.
+    Result(Value),
+    /// Fail with this error.
+    Error {
+        /// JSON-RPC error code.
+        code: i32,
+        /// Error message.
+        message: String,
+    },
+    /// Answer as the inner reply says after a while, on Tokio's clock.
+    Delayed(Duration, Box<Reply>),
+    /// Never answer, leaving the agent waiting.
+    Never,
+}
+
+impl Reply {
+    /// Fail with `code` and `message`.
+    pub fn error(code: i32, message: impl Into<String>) -> Self {
+        Reply::Error {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Answer the same way, but only after `delay`.
+    pub fn after(self, delay: Duration) -> Self {
+        Reply::Delayed(delay, Box::new(self))
+    }
+
+    fn result(value: impl Serialize) -> Self {
+        match serde_json::to_value(value) {
+            Ok(value) => Reply::Result(value),
+            Err(e) => Reply::error(codes::INTERNAL_ERROR, e.to_string()),
+        }
+    }
+}
+
+/// A scripted reply to requests for a method.
+struct Rule {
+    method: String,
+    /// The `path` the request must be for, if any.
+    path: Option<String>,
+    /// How many requests it answers; unlimited without a limit.
+    times: Option<usize>,
+    used: usize,
+    reply: Reply,
+}
+
+/// Output and exit code of a command.
+type Run = (String, i32);
+
+#[derive(Default)]
+struct State {
+    rules: Vec<Rule>,
+    files: HashMap<String, String>,
+    commands: HashMap<String, Run>,
+    terminals: HashMap<String, Run>,
+    next_terminal: usize,
+    /// Method and params of every request, in order.
+    requests: Vec<(String, Value)>,
+}
+
+/// Plays the client's side of an agent's requests from a script.
+///
+/// Scripted replies from [`on`](Self::on), [`on_path`](Self::on_path) and
+/// [`once`](Self::once) are tried in the order added; requests none of them
+/// match are answered from the files and commands, and requests those don't
+/// cover fail with `METHOD_NOT_FOUND`. Clones share the script and the
+/// record of requests.
+#[derive(Clone, Default)]
+pub struct MockClient {
+    state: Arc<Mutex<State>>,
+}
+
+impl MockClient {
+    /// A client without files, commands or scripted replies.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve `content` as the file at `path`.
+    ///
+    /// Writes to the file replace it; reading a file that was never added
+    /// or written fails with `RESOURCE_NOT_FOUND`.
+    pub fn file(self, path: impl Into<String>, content: impl Into<String>) -> Self {
+        self.state.lock().unwrap().files.insert(path.into(), content.into());
+        self
+    }
+
+    /// Have the terminal command line `command` print `output` and exit with
+    /// `exit_code` at once.
+    ///
+    /// Other commands print a shell's "command not found" and exit with 127.
+    pub fn command(self, command: impl Into<String>, output: impl Into<String>, exit_code: i32) -> Self {
+        let run = (output.into(), exit_code);
+        self.state.lock().unwrap().commands.insert(command.into(), run);
+        self
+    }
+
+    /// Answer every request for `method` with `reply`.
+    pub fn on(self, method: impl Into<String>, reply: Reply) -> Self {
+        self.rule(method.into(), None, None, reply)
+    }
+
+    /// Answer requests for `method` on the file `path` with `reply`.
+    pub fn on_path(self, method: impl Into<String>, path: impl Into<String>, reply: Reply) -> Self {
+        self.rule(method.into(), Some(path.into()), None, reply)
+    }
+
+    /// Answer the next request for `method` with `reply`, and later ones as
+    /// before.
+    pub fn once(self, method: impl Into<String>, reply: Reply) -> Self {
+        self.rule(method.into(), None, Some(1), reply)
+    }
+
+    fn rule(self, method: String, path: Option<String>, times: Option<usize>, reply: Reply) -> Self {
+        self.state.lock().unwrap().rules.push(Rule {
+            method,
+            path,
+            times,
+            used: 0,
+            reply,
+        });
+        self
+    }
+
+    /// Params of every request for `method` so far, in order.
+    pub fn requests(&self, method: &str) -> Vec<Value> {
+        let state = self.state.lock().unwrap();
+        state
+            .requests
+            .iter()
+            .filter(|(m, _)| m == method)
+            .map(|(_, params)| params.clone())
+            .collect()
+    }
+
+    /// Current content of the file at `path`, including what the agent
+    /// wrote.
+    pub fn contents(&self, path: &str) -> Option<String> {
+        self.state.lock().unwrap().files.get(path).cloned()
+    }
+
+    /// Answer a request from the agent, or return `None` if it's never to
+    /// be answered.
+    pub(super) async fn respond(&self, id: RequestId, method: &str, params: Value) -> Option<JsonRpcResponse> {
+        let mut reply = self.reply(method, params);
+        loop {
+            match reply {
+                Reply::Result(value) => {
+                    return Some(JsonRpcResponse {
+                        jsonrpc: "2.0".to_string(),
+                        id,
+                        result: Some(value),
+                        error: None,
+                    })
+                }
+                Reply::Error { code, message } => return Some(error_response(id, code, message)),
+                Reply::Delayed(delay, then) => {
+                    tokio::time::sleep(delay).await;
+                    reply = *then;
+                }
+                Reply::Never => return None,
+            }
+        }
+    }
+
+    fn reply(&self, method: &str, params: Value) -> Reply {
+        let mut state = self.state.lock().unwrap();
+        state.requests.push((method.to_string(), params.clone()));
+
+        let path = params.get("path").and_then(Value::as_str);
+        for rule in &mut state.rules {
+            let matches = rule.method == method
+                && rule.path.as_deref().is_none_or(|p| Some(p) == path)
+                && rule.times.is_none_or(|times| rule.used < times);
+            if matches {
+                rule.used += 1;
+                return rule.reply.clone();
+            }
+        }
+        state.answer(method, params).unwrap_or_else(|reply| reply)
+    }
+}
+
+/// Deserialize a request's params, failing with `INVALID_PARAMS`.
+fn parse<T: DeserializeOwned>(params: Value) -> Result<T, Reply> {
+    serde_json::from_value(params).map_err(|e| Reply::error(codes::INVALID_PARAMS, e.to_string()))
+}
+
+impl State {
+    /// Answer from the files and commands.
+    fn answer(&mut self, method: &str, params: Value) -> Result<Reply, Reply> {
+        let reply = match method {
+            method::FS_READ_TEXT_FILE => {
+                let params: FsReadTextFileParams = parse(params)?;
+                let content = self.files.get(&params.path).ok_or_else(|| not_found("File", &params.path))?;
+                let start = params.line.unwrap_or(1).saturating_sub(1) as usize;
+                let limit = params.limit.map_or(usize::MAX, |limit| limit as usize);
+                let content = content.split_inclusive('\n').skip(start).take(limit).collect();
+                Reply::result(FsReadTextFileResult { content })
+            }
+            method::FS_WRITE_TEXT_FILE => {
+                let params: FsWriteTextFileParams = parse(params)?;
+                self.files.insert(params.path, params.content);
+                Reply::result(FsWriteTextFileResult { success: true })
+            }
+            method::TERMINAL_CREATE => {
+                let params: TerminalCreateParams = parse(params)?;
+                let run = self.commands.get(&params.command).cloned().unwrap_or_else(|| {
+                    (format!("{}: command not found\n", params.command), 127)
+                });
+                self.next_terminal += 1;
+                let terminal_id = format!("term-{}", self.next_terminal);
+                self.terminals.insert(terminal_id.clone(), run);
+                Reply::result(TerminalCreateResult { terminal_id })
+            }
+            method::TERMINAL_OUTPUT => {
+                let (output, exit_code) = self.terminal(params)?;
+                Reply::result(TerminalOutputResult {
+                    total_bytes: output.len() as u64,
+                    output,
+                    stderr: String::new(),
+                    exited: true,
+                    exit_code: Some(exit_code),
+                    truncated: false,
+                })
+            }
+            method::TERMINAL_WAIT_FOR_EXIT => {
+                let (output, exit_code) = self.terminal(params)?;
+                Reply::result(TerminalWaitForExitResult {
+                    exit_code,
+                    total_bytes: output.len() as u64,
+                    output,
+                    stderr: String::new(),
+                    truncated: false,
+                })
+            }
+            method::TERMINAL_INPUT | method::TERMINAL_KILL => {
+                self.terminal(params)?;
+                Reply::result(serde_json::json!({"success": true}))
+            }
+            method::TERMINAL_RELEASE => {
+                let params: TerminalReleaseParams = parse(params)?;
+                self.terminals
+                    .remove(&params.terminal_id)
+                    .ok_or_else(|| not_found("Terminal", &params.terminal_id))?;
+                Reply::result(TerminalReleaseResult { success: true })
+            }
+            _ => Reply::error(codes::METHOD_NOT_FOUND, AcpError::MethodNotFound(method.to_string()).message()),
+        };
+        Ok(reply)
+    }
+
+    /// The run of the terminal a request is for.
+    fn terminal(&self, params: Value) -> Result<Run, Reply> {
+        let params: TerminalOutputParams = parse(params)?;
+        self.terminals
+            .get(&params.terminal_id)
+            .cloned()
+            .ok_or_else(|| not_found("Terminal", &params.terminal_id))
+    }
+}
+
+fn not_found(what: &str, name: &str) -> Reply {
+    Reply::error(codes::RESOURCE_NOT_FOUND, format!("{} not found: {}", what, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    async fn call(client: &MockClient, method: &str, params: Value) -> Option<JsonRpcResponse> {
+        client.respond(RequestId::Number(1), method, params).await
+    }
+
+    #[tokio::test]
+    async fn test_files_and_commands() {
+        let client = MockClient::new()
+            .file("/a.txt", "one\ntwo\nthree\n")
+            .command("make", "built\n", 2);
+
+        let read = call(&client, method::FS_READ_TEXT_FILE, json!({"path": "/a.txt", "line": 2, "limit": 1})).await;
+        assert_eq!(read.unwrap().result.unwrap()["content"], "two\n");
+        let missing = call(&client, method::FS_READ_TEXT_FILE, json!({"path": "/b.txt"})).await;
+        assert_eq!(missing.unwrap().error.unwrap().code, codes::RESOURCE_NOT_FOUND);
+
+        call(&client, method::FS_WRITE_TEXT_FILE, json!({"path": "/b.txt", "content": "new"})).await;
+        assert_eq!(client.contents("/b.txt").as_deref(), Some("new"));
+
+        for (command, output, exit_code) in [("make", "built\n", 2), ("nope", "nope: command not found\n", 127)] {
+            let created = call(&client, method::TERMINAL_CREATE, json!({"cwd": "/", "command": command})).await;
+            let terminal_id = created.unwrap().result.unwrap()["terminal_id"].clone();
+            let exited = call(&client, method::TERMINAL_WAIT_FOR_EXIT, json!({"terminal_id": terminal_id})).await;
+            let exited = exited.unwrap().result.unwrap();
+            assert_eq!((exited["output"].as_str(), exited["exit_code"].as_i64()), (Some(output), Some(exit_code)));
+        }
+        assert_eq!(client.requests(method::TERMINAL_CREATE).len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_scripted_replies() {
+        let client = MockClient::new()
+            .file("/a.txt", "a")
+            .once(method::FS_READ_TEXT_FILE, Reply::error(codes::INTERNAL_ERROR, "Disk on fire"))
+            .on_path(
+                method::FS_READ_TEXT_FILE,
+                "/slow.txt",
+                Reply::Result(json!({"content": "late"})).after(Duration::from_secs(3)),
+            )
+            .on(method::SESSION_REQUEST_PERMISSION, Reply::Never);
+
+        let first = call(&client, method::FS_READ_TEXT_FILE, json!({"path": "/a.txt"})).await;
+        assert_eq!(first.unwrap().error.unwrap().message, "Disk on fire");
+        let second = call(&client, method::FS_READ_TEXT_FILE, json!({"path": "/a.txt"})).await;
+        assert_eq!(second.unwrap().result.unwrap()["content"], "a");
+
+        let started = tokio::time::Instant::now();
+        let slow = call(&client, method::FS_READ_TEXT_FILE, json!({"path": "/slow.txt"})).await;
+        assert_eq!(slow.unwrap().result.unwrap()["content"], "late");
+        assert_eq!(started.elapsed(), Duration::from_secs(3));
+
+        assert!(call(&client, method::SESSION_REQUEST_PERMISSION, json!({})).await.is_none());
+        let unknown = call(&client, "fs/unknown", json!({})).await;
+        assert_eq!(unknown.unwrap().error.unwrap().code, codes::METHOD_NOT_FOUND);
+    }
+}
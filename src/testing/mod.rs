@@ -0,0 +1,665 @@
+//! Unit-testing agents without a process or stdio.
+//!
+//! [`ServerTester`] sends requests through the same middleware and dispatch
+//! path as [`Server::run`], but in memory:
+//!
+//! ```rust,no_run
+//! # use heroacp::server::Agent;
+//! # use heroacp::protocol::*;
+//! use heroacp::testing::{initialize_params, ServerTester};
+//!
+//! # async fn demo(agent: impl Agent) -> AcpResult<()> {
+//! let tester = ServerTester::new(agent);
+//! tester.initialize(initialize_params()).await?;
+//! tester.session_new("s1").await?;
+//!
+//! let (result, updates) = tester.prompt("s1", vec![ContentBlock::text("hi")]).await;
+//! assert_eq!(result?.status, "ok");
+//! assert!(!updates.is_empty());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Recorder`] and [`Replayer`] record a live connection and play either
+//! side of it back against a real peer. [`MockAgent`] plays a scripted
+//! [`Scenario`] for testing clients, and [`MockClient`] answers an agent's
+//! requests from a script. [`Conformance`] checks a spawned agent
+//! against the protocol. [`in_process`] connects a real [`Client`] to a
+//! server without spawning one, and [`UpdateCollector`] records what the
+//! client receives. [`Chaos`] degrades a connection to see how either side
+//! copes. [`fixtures`] has ready-made protocol values.
+
+mod chaos;
+mod collector;
+mod conformance;
+pub mod fixtures;
+mod mock;
+mod mock_client;
+mod replay;
+
+pub use chaos::Chaos;
+pub use collector::{Collected, UpdateCollector, UpdateEvent};
+pub use conformance::{Check, Conformance, Report, DEFAULT_CHECK_DEADLINE};
+pub use mock::{MockAgent, MockToolCall, Rule, Scenario, Step};
+pub use mock_client::{MockClient, Reply};
+pub use replay::{Recorder, Replayer, Timing};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::mpsc;
+
+use crate::client::Client;
+use crate::protocol::*;
+use crate::server::{error_response, Agent, AgentConnection, Server};
+
+/// Answers requests the agent sends to the client.
+type ClientHandler = dyn Fn(&str, Value) -> AcpResult<Value> + Send + Sync;
+
+/// What plays the client's side of requests the agent sends.
+enum Responder {
+    Handler(Box<ClientHandler>),
+    Mock(MockClient),
+}
+
+/// Drives a [`Server`] in memory for tests.
+///
+/// Requests the agent sends to the client (files, terminals, permissions) are
+/// answered by the handler set with [`on_client_request`](Self::on_client_request)
+/// or the [`MockClient`] set with [`with_client`](Self::with_client); by
+/// default they fail with `METHOD_NOT_FOUND`.
+pub struct ServerTester<A: Agent> {
+    server: Server<A>,
+    connection: AgentConnection,
+    update_tx: mpsc::Sender<SessionUpdate>,
+    update_rx: tokio::sync::Mutex<mpsc::Receiver<SessionUpdate>>,
+    notifications: Arc<Mutex<Vec<JsonRpcNotification>>>,
+    responder: Arc<RwLock<Responder>>,
+    next_id: AtomicI64,
+}
+
+impl<A: Agent> ServerTester<A> {
+    /// Test `agent` behind a default server.
+    pub fn new(agent: A) -> Self {
+        Self::from_server(Server::new(agent))
+    }
+
+    /// Test a configured server, e.g. with tools or middleware.
+    pub fn from_server(server: Server<A>) -> Self {
+        let (update_tx, update_rx) = mpsc::channel(100);
+        let (outgoing_tx, mut outgoing_rx) = mpsc::channel::<String>(100);
+        let connection = server.connect(outgoing_tx);
+        let notifications = Arc::new(Mutex::new(Vec::new()));
+        let responder = Arc::new(RwLock::new(Responder::Handler(Box::new(
+            |method: &str, _params: Value| Err(AcpError::MethodNotFound(method.to_string())),
+        ))));
+
+        // Play the client's side for messages the agent sends
+        let client = connection.clone();
+        let received = notifications.clone();
+        let responder_clone = responder.clone();
+        tokio::spawn(async move {
+            while let Some(msg) = outgoing_rx.recv().await {
+                if let Ok(request) = serde_json::from_str::<JsonRpcRequest>(&msg) {
+                    if let Some(id) = request.id {
+                        let params = request.params.unwrap_or(Value::Null);
+                        let response = match &*responder_clone.read().unwrap() {
+                            Responder::Handler(handler) => match handler(&request.method, params) {
+                                Ok(value) => Some(JsonRpcResponse {
+                                    jsonrpc: "2.0".to_string(),
+                                    id,
+                                    result: Some(value),
+                                    error: None,
+                                }),
+                                Err(e) => Some(error_response(id, e.code(), e.message())),
+                            },
+                            // Answered on its own task, so a delayed reply
+                            // doesn't hold up the others
+                            Responder::Mock(mock) => {
+                                let (mock, client) = (mock.clone(), client.clone());
+                                tokio::spawn(async move {
+                                    if let Some(response) = mock.respond(id, &request.method, params).await {
+                                        client.complete(response).await;
+                                    }
+                                });
+                                None
+                            }
+                        };
+                        if let Some(response) = response {
+                            client.complete(response).await;
+                        }
+                        continue;
+                    }
+                }
+                if let Ok(notification) = serde_json::from_str(&msg) {
+                    received.lock().unwrap().push(notification);
+                }
+            }
+        });
+
+        Self {
+            server,
+            connection,
+            update_tx,
+            update_rx: tokio::sync::Mutex::new(update_rx),
+            notifications,
+            responder,
+            next_id: AtomicI64::new(1),
+        }
+    }
+
+    /// Answer requests from the agent with `handler`.
+    pub fn on_client_request(
+        self,
+        handler: impl Fn(&str, Value) -> AcpResult<Value> + Send + Sync + 'static,
+    ) -> Self {
+        *self.responder.write().unwrap() = Responder::Handler(Box::new(handler));
+        self
+    }
+
+    /// Answer requests from the agent with `client`, keeping a clone to
+    /// check what the agent asked for.
+    pub fn with_client(self, client: MockClient) -> Self {
+        *self.responder.write().unwrap() = Responder::Mock(client);
+        self
+    }
+
+    /// The server under test.
+    pub fn server(&self) -> &Server<A> {
+        &self.server
+    }
+
+    /// Send a raw request and return its result.
+    ///
+    /// Session updates it produces are discarded; use [`prompt`](Self::prompt)
+    /// to collect them.
+    pub async fn request(&self, method: &str, params: Value) -> AcpResult<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: Some(RequestId::Number(id)),
+            method: method.to_string(),
+            params: Some(params),
+        };
+        self.server
+            .dispatch(request, self.update_tx.clone(), self.connection.clone())
+            .await
+    }
+
+    async fn call<P: Serialize, R: DeserializeOwned>(&self, method: &str, params: P) -> AcpResult<R> {
+        let result = self.request(method, serde_json::to_value(params)?).await?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    /// Send `initialize`.
+    pub async fn initialize(&self, params: InitializeParams) -> AcpResult<InitializeResult> {
+        self.call(method::INITIALIZE, params).await
+    }
+
+    /// Create a session.
+    pub async fn session_new(&self, session_id: impl Into<String>) -> AcpResult<SessionNewResult> {
+        self.call(
+            method::SESSION_NEW,
+            SessionNewParams {
+                session_id: session_id.into(),
+                mode: None,
+                agent: None,
+            },
+        )
+        .await
+    }
+
+    /// Send a prompt and wait for the response.
+    ///
+    /// Returns the response together with the session updates sent while the
+    /// prompt ran, after capability filtering. Prompts are run one at a time;
+    /// other requests, such as [`cancel`](Self::cancel), may run alongside.
+    pub async fn prompt(
+        &self,
+        session_id: impl Into<String>,
+        content: Vec<ContentBlock>,
+    ) -> (AcpResult<SessionPromptResult>, Vec<SessionUpdate>) {
+        let params = SessionPromptParams {
+            session_id: session_id.into(),
+            content,
+        };
+        let mut update_rx = self.update_rx.lock().await;
+
+        // Stale updates from earlier requests aren't part of this prompt
+        while update_rx.try_recv().is_ok() {}
+
+        let mut updates = Vec::new();
+        let response = self.call(method::SESSION_PROMPT, params);
+        tokio::pin!(response);
+        let result = loop {
+            tokio::select! {
+                result = &mut response => break result,
+                Some(update) = update_rx.recv() => updates.push(update),
+            }
+        };
+        while let Ok(update) = update_rx.try_recv() {
+            updates.push(update);
+        }
+
+        let mut filtered = Vec::with_capacity(updates.len());
+        for mut update in updates {
+            if self.server.check_update(&mut update).await.is_ok() {
+                let bytes = crate::server::update_message(&update).len();
+                self.server.sessions().record_update(&update, bytes).await;
+                filtered.push(update);
+            }
+        }
+        (result, filtered)
+    }
+
+    /// Cancel the running prompt in a session.
+    pub async fn cancel(&self, session_id: impl Into<String>) -> AcpResult<()> {
+        let _: Value = self
+            .call(
+                method::SESSION_CANCEL,
+                SessionCancelParams {
+                    session_id: session_id.into(),
+                    prompt_id: None,
+                },
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Switch the model of a session.
+    pub async fn set_model(&self, session_id: impl Into<String>, model_id: impl Into<String>) -> AcpResult<()> {
+        let _: Value = self
+            .call(
+                method::SESSION_SET_MODEL,
+                SessionSetModelParams {
+                    session_id: session_id.into(),
+                    model_id: model_id.into(),
+                },
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Take the notifications other than session updates the agent has sent.
+    pub fn notifications(&self) -> Vec<JsonRpcNotification> {
+        std::mem::take(&mut *self.notifications.lock().unwrap())
+    }
+
+    /// Disconnect, running the agent's teardown hooks.
+    pub async fn shutdown(self) {
+        self.server.shutdown().await;
+    }
+}
+
+/// `initialize` params for a client without optional capabilities.
+pub fn initialize_params() -> InitializeParams {
+    InitializeParams {
+        protocol_version: PROTOCOL_VERSION.to_string(),
+        client_info: ClientInfo {
+            name: "heroacp-tester".to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        },
+        capabilities: ClientCapabilities::default(),
+        working_directory: "/".to_string(),
+        mcp_servers: Vec::new(),
+    }
+}
+
+/// Bytes buffered in each direction of the [`in_process`] pipe.
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+/// Run `server` on a task and connect a [`Client`] to it over an in-memory
+/// pipe.
+///
+/// Unlike [`ServerTester`], messages take the whole way a spawned agent's
+/// would: serialized, framed, through the server's message loop and back
+/// into the client's handlers, without the cost of a process. The server
+/// stops when the client is dropped or shut down.
+pub fn in_process<A: Agent + 'static>(server: Server<A>) -> AcpResult<Client> {
+    let (client_end, server_end) = tokio::io::duplex(PIPE_CAPACITY);
+    let (server_read, server_write) = tokio::io::split(server_end);
+    tokio::spawn(async move {
+        if let Err(e) = server.serve(server_read, server_write).await {
+            eprintln!("In-process server failed: {}", e);
+        }
+    });
+    let (client_read, client_write) = tokio::io::split(client_end);
+    Client::attach(client_read, client_write)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fixtures::{InitializeParamsExt, PromptExt};
+    use super::*;
+    use crate::server::{AgentContext, SessionHandle};
+    use async_trait::async_trait;
+    use tokio::time::Duration;
+
+    /// Echoes the prompt back, reading a file first if asked to.
+    struct EchoAgent;
+
+    #[async_trait]
+    impl Agent for EchoAgent {
+        async fn initialize(&self, _params: InitializeParams) -> AcpResult<InitializeResult> {
+            Ok(fixtures::init_result("echo"))
+        }
+
+        async fn session_new(
+            &self,
+            params: SessionNewParams,
+            _ctx: AgentContext,
+        ) -> AcpResult<SessionNewResult> {
+            Ok(SessionNewResult {
+                session_id: params.session_id,
+            })
+        }
+
+        async fn session_prompt(
+            &self,
+            params: SessionPromptParams,
+            session: SessionHandle,
+            ctx: AgentContext,
+        ) -> AcpResult<SessionPromptResult> {
+            for block in params.content {
+                if let ContentBlock::Text { text, .. } = block {
+                    let text = match text.strip_prefix("read ") {
+                        Some(path) => ctx.connection().read_file(path).await?,
+                        None => text,
+                    };
+                    session.send_message_chunk(text).await?;
+                }
+            }
+            ctx.connection()
+                .notify("echo/finished", serde_json::json!({}))
+                .await?;
+            session.send_done().await?;
+            Ok(SessionPromptResult {
+                status: "ok".to_string(),
+            })
+        }
+    }
+
+    /// Asks for permission while creating a session.
+    struct AskingAgent {
+        outcomes: std::sync::Arc<std::sync::Mutex<Vec<PermissionOutcome>>>,
+    }
+
+    #[async_trait]
+    impl Agent for AskingAgent {
+        async fn initialize(&self, _params: InitializeParams) -> AcpResult<InitializeResult> {
+            Ok(fixtures::init_result("asking"))
+        }
+
+        async fn session_new(
+            &self,
+            params: SessionNewParams,
+            ctx: AgentContext,
+        ) -> AcpResult<SessionNewResult> {
+            let request = fixtures::permission_request(fixtures::tool_call("setup"));
+            let outcome = ctx.connection().request_permission(request).await?;
+            self.outcomes.lock().unwrap().push(outcome);
+            Ok(SessionNewResult {
+                session_id: params.session_id,
+            })
+        }
+
+        async fn session_prompt(
+            &self,
+            _params: SessionPromptParams,
+            _session: SessionHandle,
+            _ctx: AgentContext,
+        ) -> AcpResult<SessionPromptResult> {
+            Ok(SessionPromptResult {
+                status: "ok".to_string(),
+            })
+        }
+    }
+
+    /// An MCP server that asks for a completion before answering
+    /// `tools/list`, and names its only tool after the answer it got.
+    const SAMPLING_MCP_SERVER: &str = r#"
+read -r line
+printf '{"jsonrpc":"2.0","id":1,"result":{"protocolVersion":"2024-11-05","capabilities":{"tools":{}},"serverInfo":{"name":"sampler"}}}\n'
+read -r line
+read -r line
+printf '{"jsonrpc":"2.0","id":"s1","method":"sampling/createMessage","params":{"messages":[{"role":"user","content":{"type":"text","text":"hi"}}],"maxTokens":10}}\n'
+read -r reply
+case "$reply" in *'"result"'*) name=sampled ;; *) name=refused ;; esac
+printf '{"jsonrpc":"2.0","id":2,"result":{"tools":[{"name":"%s","inputSchema":{"type":"object"}}]}}\n' "$name"
+cat > /dev/null
+"#;
+
+    struct Sampler;
+
+    #[async_trait]
+    impl crate::client::SamplingHandler for Sampler {
+        async fn create_message(
+            &self,
+            _params: SessionSamplingRequestParams,
+        ) -> AcpResult<SessionSamplingRequestResult> {
+            Ok(SessionSamplingRequestResult {
+                role: Role::Assistant,
+                content: ContentBlock::text("hello"),
+                model: "editor-model".to_string(),
+                stop_reason: None,
+            })
+        }
+    }
+
+    /// Takes a while to read any file.
+    struct SlowFiles;
+
+    #[async_trait]
+    impl crate::client::FileSystemHandler for SlowFiles {
+        async fn read_text_file(&self, path: &str) -> AcpResult<String> {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            Ok(format!("contents of {}", path))
+        }
+    }
+
+    fn chunks(updates: &[SessionUpdate]) -> Vec<&str> {
+        updates
+            .iter()
+            .filter_map(|u| match &u.update_type {
+                SessionUpdateType::AgentMessageChunk { text } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_prompt_collects_updates() {
+        let tester = ServerTester::new(EchoAgent);
+        let init = tester.initialize(initialize_params()).await.unwrap();
+        assert_eq!(init.agent_info.name, "echo");
+        tester.session_new("s1").await.unwrap();
+
+        let content: Vec<_> = (0..150).map(|i| ContentBlock::text(i.to_string())).collect();
+        let (result, updates) = tester.prompt("s1", content).await;
+        assert_eq!(result.unwrap().status, "ok");
+        assert_eq!(updates.len(), 151);
+        assert_eq!(chunks(&updates)[149], "149");
+        assert!(matches!(updates[150].update_type, SessionUpdateType::Done));
+
+        let notifications = tester.notifications();
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].method, "echo/finished");
+    }
+
+    #[tokio::test]
+    async fn test_client_requests() {
+        let mut params = initialize_params();
+        params.capabilities.text_files = true;
+
+        let tester = ServerTester::new(EchoAgent).on_client_request(|method, params| {
+            assert_eq!(method, method::FS_READ_TEXT_FILE);
+            Ok(serde_json::json!({"content": format!("contents of {}", params["path"].as_str().unwrap())}))
+        });
+        tester.initialize(params).await.unwrap();
+        tester.session_new("s1").await.unwrap();
+
+        let (result, updates) = tester.prompt("s1", vec![ContentBlock::text("read /a.txt")]).await;
+        result.unwrap();
+        assert_eq!(chunks(&updates), vec!["contents of /a.txt"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_mock_client() {
+        let denied = Reply::error(codes::PERMISSION_DENIED, "Denied").after(Duration::from_secs(2));
+        let client = MockClient::new()
+            .file("/a.txt", "contents")
+            .once(method::FS_READ_TEXT_FILE, denied);
+        let tester = ServerTester::new(EchoAgent).with_client(client.clone());
+        tester.initialize(fixtures::init_params().with_capability("text_files")).await.unwrap();
+        tester.session_new("s1").await.unwrap();
+
+        let started = tokio::time::Instant::now();
+        let (result, _) = tester.prompt("s1", vec![ContentBlock::text("read /a.txt")]).await;
+        assert_eq!(result.unwrap_err().code(), codes::PERMISSION_DENIED);
+        assert_eq!(started.elapsed(), Duration::from_secs(2));
+
+        let (result, updates) = tester.prompt("s1", vec![ContentBlock::text("read /a.txt")]).await;
+        result.unwrap();
+        assert_eq!(chunks(&updates), vec!["contents"]);
+        assert_eq!(client.requests(method::FS_READ_TEXT_FILE).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_errors_are_returned() {
+        let tester = ServerTester::new(EchoAgent);
+        let err = tester.session_new("s1").await.unwrap_err();
+        assert_eq!(err.code(), codes::INVALID_STATE);
+
+        tester.initialize(initialize_params()).await.unwrap();
+        let (result, updates) = tester.prompt("missing", vec![]).await;
+        assert_eq!(result.unwrap_err().code(), codes::INVALID_STATE);
+        assert!(updates.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_in_process() {
+        let mut client = in_process(Server::new(EchoAgent)).unwrap();
+        let updates = UpdateCollector::new();
+        client.set_update_handler(Box::new(updates.clone())).await;
+
+        let init = client.initialize(initialize_params()).await.unwrap();
+        assert_eq!(init.agent_info.name, "echo");
+        client.session_new(fixtures::session_new()).await.unwrap();
+        let prompt = fixtures::prompt("hello ").with_block(ContentBlock::text("there"));
+        let result = client.session_prompt(prompt).await.unwrap();
+        assert_eq!(result.status, "ok");
+
+        updates.await_done(std::time::Duration::from_secs(1)).await.unwrap();
+        assert_eq!(updates.messages(), "hello there");
+
+        // Closing the client's end stops the server, which closes the other
+        assert!(client.is_running());
+        client.shutdown(std::time::Duration::from_secs(1)).await.unwrap();
+        assert!(!client.is_running());
+    }
+
+    #[tokio::test]
+    async fn test_in_process_refused_prompt_stream_opens_nothing() {
+        let client = in_process(Server::new(EchoAgent)).unwrap();
+        client.initialize(initialize_params()).await.unwrap();
+        let session_id = client.session_new(fixtures::session_new()).await.unwrap().session_id;
+        let audio = ContentBlock::Audio {
+            format: "wav".to_string(),
+            data: String::new(),
+            annotations: None,
+        };
+
+        // More refusals than the 16 streams the agent keeps open
+        for _ in 0..20 {
+            let content = [ContentBlock::text("listen"), audio.clone()];
+            let err = client.session_prompt_stream(&session_id, content).await.unwrap_err();
+            assert!(matches!(err, AcpError::CapabilityNotSupported(_)));
+        }
+        let result = client.session_prompt_stream(&session_id, [ContentBlock::text("hi")]).await;
+        assert_eq!(result.unwrap().status, "ok");
+    }
+
+    #[tokio::test]
+    async fn test_in_process_mcp_sampling_during_initialize() {
+        let script = std::env::temp_dir().join(format!("heroacp-sampling-mcp-{}.sh", std::process::id()));
+        std::fs::write(&script, SAMPLING_MCP_SERVER).unwrap();
+        let server = Server::new(EchoAgent).with_mcp_tools(true).with_mcp_sampling(true);
+        let client = in_process(server).unwrap();
+        client.set_sampling_handler(Box::new(Sampler)).await;
+
+        // tools/list is answered only once the client's completion got through
+        let params = fixtures::init_params()
+            .with_capability("sampling")
+            .with_mcp_server("sampler", &format!("sh {}", script.display()));
+        let init = tokio::time::timeout(Duration::from_secs(5), client.initialize(params)).await;
+        std::fs::remove_file(&script).unwrap();
+        let tools = init.expect("initialize deadlocked").unwrap().capabilities.tools;
+        assert_eq!(tools.iter().map(|tool| tool.name.as_str()).collect::<Vec<_>>(), ["sampler__sampled"]);
+    }
+
+    #[tokio::test]
+    async fn test_in_process_shutdown_drains_client_calls() {
+        let server = Server::new(EchoAgent).with_drain_timeout(Duration::from_secs(5));
+        let client = in_process(server).unwrap();
+        client.set_fs_handler(Box::new(SlowFiles)).await;
+        client.initialize(fixtures::init_params().with_capability("text_files")).await.unwrap();
+        client.session_new(fixtures::session_new()).await.unwrap();
+
+        // The prompt gets the client's answer while shutdown drains it,
+        // rather than holding shutdown up until the drain timeout
+        let started = tokio::time::Instant::now();
+        let shutdown = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            client.request_raw(method::SHUTDOWN, serde_json::json!({})).await
+        };
+        let (prompt, shutdown) = tokio::join!(client.session_prompt(fixtures::prompt("read /a.txt")), shutdown);
+        shutdown.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(prompt.unwrap().status, "ok");
+    }
+
+    #[tokio::test]
+    async fn test_in_process_setup_calls_client() {
+        let outcomes = std::sync::Arc::default();
+        let agent = AskingAgent {
+            outcomes: std::sync::Arc::clone(&outcomes),
+        };
+        let client = in_process(Server::new(agent)).unwrap();
+        client.initialize(initialize_params()).await.unwrap();
+
+        // The server keeps reading the client's answer while session/new waits for it
+        let created = tokio::time::timeout(Duration::from_secs(5), client.session_new(fixtures::session_new())).await;
+        created.expect("session/new deadlocked").unwrap();
+        let prompt = client.session_prompt(fixtures::prompt("hello")).await.unwrap();
+        assert_eq!(prompt.status, "ok");
+        assert_eq!(
+            *outcomes.lock().unwrap(),
+            [PermissionOutcome::Selected {
+                option_id: "reject".to_string()
+            }]
+        );
+    }
+
+    #[tokio::test]
+    #[allow(deprecated)]
+    async fn test_deprecated_send_request() {
+        let server = Server::new(EchoAgent);
+        let handle = server.clone();
+        let (unused_tx, _unused_rx) = tokio::sync::mpsc::channel(1);
+        let err = handle
+            .send_request(method::FS_READ_TEXT_FILE, serde_json::json!({}), &unused_tx)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AcpError::ConnectionClosed));
+
+        let client = in_process(server).unwrap();
+        client.set_fs_handler(Box::new(SlowFiles)).await;
+        client.initialize(fixtures::init_params().with_capability("text_files")).await.unwrap();
+        let content = crate::server::client_requests::read_file(&handle, "/notes.md", &unused_tx)
+            .await
+            .unwrap();
+        assert_eq!(content, "contents of /notes.md");
+    }
+}
@@ -0,0 +1,423 @@
+//! Recording live connections and replaying them in tests.
+//!
+//! A [`Recorder`] sits between a client and an agent and writes every
+//! message to a [`Transcript`]. A [`Replayer`] plays one side of such a
+//! transcript, or of one recorded with [`Server::with_transcript`], against
+//! a live peer, so a real agent interaction becomes a deterministic
+//! regression test:
+//!
+//! ```rust,no_run
+//! # use heroacp::testing::Replayer;
+//! # async fn demo(agent_out: tokio::process::ChildStdout, agent_in: tokio::process::ChildStdin) -> heroacp::protocol::AcpResult<()> {
+//! // Send the recorded client's messages and check the agent answers alike
+//! Replayer::load("tests/fixtures/session.jsonl")?
+//!     .play_client(agent_out, agent_in)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Server::with_transcript`]: crate::server::Server::with_transcript
+
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, DuplexStream, Lines, ReadHalf, WriteHalf,
+};
+
+use crate::protocol::*;
+use crate::server::{Direction, Transcript};
+
+/// Placeholder [`Transcript`] writes for secrets; matches any value.
+const REDACTED: &str = "[REDACTED]";
+
+/// Records the messages between a client and an agent.
+///
+/// Messages from the client are recorded as `in` and those from the agent
+/// as `out`, as the agent's [`Transcript`] would, secrets redacted.
+pub struct Recorder {
+    transcript: Transcript,
+}
+
+impl Recorder {
+    /// Record to the file at `path`, appending if it exists.
+    pub fn new(path: impl AsRef<Path>) -> AcpResult<Self> {
+        let transcript = Transcript::new();
+        transcript.start(path)?;
+        Ok(Self { transcript })
+    }
+
+    /// Record to `transcript`, e.g. one with extra keys to redact.
+    pub fn with_transcript(transcript: Transcript) -> Self {
+        Self { transcript }
+    }
+
+    /// Record the connection to an agent that writes `output` and reads
+    /// `input`, returning streams for the client to use in their place.
+    ///
+    /// Like [`Chaos::wrap`](super::Chaos::wrap), the messages are relayed
+    /// on a task of their own.
+    pub fn wrap<R, W>(self, output: R, input: W) -> (ReadHalf<DuplexStream>, WriteHalf<DuplexStream>)
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        let (near, far) = tokio::io::duplex(super::chaos::PIPE_CAPACITY);
+        let (far_read, far_write) = tokio::io::split(far);
+        tokio::spawn(async move {
+            let _ = self.tap(far_read, far_write, output, input).await;
+        });
+        tokio::io::split(near)
+    }
+
+    /// Relay NDJSON messages between a client and an agent, recording each.
+    ///
+    /// When the client closes its side, `agent_in` is shut down and
+    /// dropped; returns once the agent closes `agent_out`.
+    pub async fn tap<CR, CW, AR, AW>(
+        &self,
+        client_out: CR,
+        mut client_in: CW,
+        agent_out: AR,
+        mut agent_in: AW,
+    ) -> AcpResult<()>
+    where
+        CR: AsyncRead + Unpin,
+        CW: AsyncWrite + Unpin,
+        AR: AsyncRead + Unpin,
+        AW: AsyncWrite + Unpin,
+    {
+        let transcript = &self.transcript;
+        let to_agent = async move {
+            let mut lines = BufReader::new(client_out).lines();
+            while let Some(line) = lines.next_line().await? {
+                if line.is_empty() {
+                    continue;
+                }
+                transcript.record(Direction::Inbound, &line);
+                write_line(&mut agent_in, &line).await?;
+            }
+            agent_in.shutdown().await?;
+            drop(agent_in);
+            AcpResult::Ok(())
+        };
+        let to_client = async {
+            let mut lines = BufReader::new(agent_out).lines();
+            while let Some(line) = lines.next_line().await? {
+                if line.is_empty() {
+                    continue;
+                }
+                transcript.record(Direction::Outbound, &line);
+                write_line(&mut client_in, &line).await?;
+            }
+            AcpResult::Ok(())
+        };
+
+        tokio::pin!(to_client);
+        tokio::select! {
+            result = to_agent => {
+                result?;
+                to_client.await
+            }
+            result = &mut to_client => result,
+        }
+    }
+}
+
+/// How a [`Replayer`] paces the messages it sends.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Timing {
+    /// Send each message as soon as the previous step is done.
+    #[default]
+    Instant,
+    /// Keep the recorded gaps between messages.
+    Recorded,
+    /// Keep the recorded gaps multiplied by a factor, e.g. `0.1` to play
+    /// ten times faster.
+    Scaled(f64),
+}
+
+/// Plays one side of a recorded transcript against a live peer.
+///
+/// The messages of the played side are sent in order; before each message
+/// of the other side, the next line from the peer is read and must match
+/// it. By default, a match is a message of the same kind: a request or
+/// notification with the same method, or a response to the same request.
+/// [`strict`](Self::strict) also compares params and results.
+///
+/// Responses to the peer's requests are sent with the ID the peer used,
+/// so peers that number their requests differently still line up.
+#[derive(Debug, Clone)]
+pub struct Replayer {
+    entries: Vec<Entry>,
+    timing: Timing,
+    strict: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    timestamp_ms: u64,
+    direction: Direction,
+    message: Value,
+}
+
+impl Replayer {
+    /// Load the transcript at `path`.
+    pub fn load(path: impl AsRef<Path>) -> AcpResult<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parse a transcript, one JSON entry per line.
+    pub fn parse(transcript: &str) -> AcpResult<Self> {
+        let mut entries = Vec::new();
+        for (i, line) in transcript.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: Value = serde_json::from_str(line)?;
+            let direction = match entry["direction"].as_str() {
+                Some("in") => Direction::Inbound,
+                Some("out") => Direction::Outbound,
+                _ => return Err(AcpError::ParseError(format!("line {}: no direction", i + 1))),
+            };
+            entries.push(Entry {
+                timestamp_ms: entry["timestamp_ms"].as_u64().unwrap_or(0),
+                direction,
+                message: entry["message"].clone(),
+            });
+        }
+        Ok(Self {
+            entries,
+            timing: Timing::default(),
+            strict: false,
+        })
+    }
+
+    /// Set how messages are paced. Defaults to [`Timing::Instant`].
+    pub fn timing(mut self, timing: Timing) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    /// Also require params and results to match those recorded. Redacted
+    /// values match anything.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Play the recorded agent against a client: read the client's
+    /// messages from `client_out` and write the agent's to `client_in`.
+    pub async fn play_agent<R, W>(&self, client_out: R, client_in: W) -> AcpResult<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        self.play(Direction::Outbound, client_out, client_in).await
+    }
+
+    /// Play the recorded client against an agent: read the agent's
+    /// messages from `agent_out` and write the client's to `agent_in`.
+    pub async fn play_client<R, W>(&self, agent_out: R, agent_in: W) -> AcpResult<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        self.play(Direction::Inbound, agent_out, agent_in).await
+    }
+
+    async fn play<R, W>(&self, ours: Direction, reader: R, mut writer: W) -> AcpResult<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut lines = BufReader::new(reader).lines();
+        // Recorded ID of each of the peer's requests, to the live one
+        let mut ids: HashMap<String, Value> = HashMap::new();
+        let mut last_sent: Option<u64> = None;
+
+        for (step, entry) in self.entries.iter().enumerate() {
+            if entry.direction == ours {
+                if let Some(last) = last_sent {
+                    tokio::time::sleep(self.delay(entry.timestamp_ms.saturating_sub(last))).await;
+                }
+                last_sent = Some(entry.timestamp_ms);
+                let mut message = entry.message.clone();
+                if is_response(&message) {
+                    if let Some(id) = ids.remove(&message["id"].to_string()) {
+                        message["id"] = id;
+                    }
+                }
+                write_line(&mut writer, &message.to_string()).await?;
+            } else {
+                let live = next_message(&mut lines, step).await?;
+                self.check(step, &entry.message, &live)?;
+                if !is_response(&live) {
+                    if let Some(id) = live.get("id") {
+                        ids.insert(entry.message["id"].to_string(), id.clone());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn delay(&self, gap_ms: u64) -> Duration {
+        let gap = Duration::from_millis(gap_ms);
+        match self.timing {
+            Timing::Instant => Duration::ZERO,
+            Timing::Recorded => gap,
+            Timing::Scaled(factor) => gap.mul_f64(factor.max(0.0)),
+        }
+    }
+
+    /// Check that `live` matches the `recorded` message at `step`.
+    fn check(&self, step: usize, recorded: &Value, live: &Value) -> AcpResult<()> {
+        let same_kind = match recorded.get("method") {
+            Some(method) => live.get("method") == Some(method),
+            None => is_response(live) && (live["id"] == recorded["id"] || live["id"].is_null()),
+        };
+        let same_payload = !self.strict
+            || ["params", "result", "error"]
+                .iter()
+                .all(|key| matches(&recorded[key], &live[key]));
+        if same_kind && same_payload {
+            Ok(())
+        } else {
+            Err(AcpError::InvalidState(format!(
+                "step {}: expected {}, got {}",
+                step + 1,
+                recorded,
+                live
+            )))
+        }
+    }
+}
+
+/// Whether `message` is a response rather than a request or notification.
+fn is_response(message: &Value) -> bool {
+    message.get("method").is_none()
+}
+
+/// Whether `live` equals `recorded`, with redacted values matching anything.
+fn matches(recorded: &Value, live: &Value) -> bool {
+    match (recorded, live) {
+        (Value::String(s), _) if s == REDACTED => true,
+        (Value::Object(recorded), Value::Object(live)) => {
+            recorded.len() == live.len()
+                && recorded
+                    .iter()
+                    .all(|(key, value)| live.get(key).is_some_and(|live| matches(value, live)))
+        }
+        (Value::Array(recorded), Value::Array(live)) => {
+            recorded.len() == live.len() && recorded.iter().zip(live).all(|(r, l)| matches(r, l))
+        }
+        _ => recorded == live,
+    }
+}
+
+/// Read the next non-empty line from the peer as JSON.
+async fn next_message<R: AsyncRead + Unpin>(lines: &mut Lines<BufReader<R>>, step: usize) -> AcpResult<Value> {
+    loop {
+        match lines.next_line().await? {
+            Some(line) if line.is_empty() => continue,
+            Some(line) => return Ok(serde_json::from_str(&line)?),
+            None => {
+                return Err(AcpError::InvalidState(format!(
+                    "step {}: peer closed the connection",
+                    step + 1
+                )))
+            }
+        }
+    }
+}
+
+/// Write one NDJSON line and flush it.
+pub(super) async fn write_line<W: AsyncWrite + Unpin>(writer: &mut W, line: &str) -> AcpResult<()> {
+    writer.write_all(line.as_bytes()).await?;
+    writer.write_all(b"\n").await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::io::duplex;
+
+    const TRANSCRIPT: &str = r#"{"timestamp_ms":1000,"direction":"in","message":{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"token":"[REDACTED]"}}}
+{"timestamp_ms":1010,"direction":"out","message":{"jsonrpc":"2.0","method":"session/update","params":{"session_id":"s1","type":"done"}}}
+{"timestamp_ms":1020,"direction":"out","message":{"jsonrpc":"2.0","id":1,"result":{"ok":true}}}
+"#;
+
+    #[tokio::test]
+    async fn test_play_agent_maps_ids() {
+        let replayer = Replayer::parse(TRANSCRIPT).unwrap().strict(true);
+        let (client, agent) = duplex(4096);
+        let (agent_read, agent_write) = tokio::io::split(agent);
+        let agent = tokio::spawn(async move { replayer.play_agent(agent_read, agent_write).await });
+
+        let (client_read, mut client_write) = tokio::io::split(client);
+        let mut lines = BufReader::new(client_read).lines();
+        let initialize = json!({ "jsonrpc": "2.0", "id": 7, "method": "initialize", "params": { "token": "secret" } });
+        write_line(&mut client_write, &initialize.to_string()).await.unwrap();
+
+        let update = next_message(&mut lines, 0).await.unwrap();
+        assert_eq!(update["method"], "session/update");
+        let response = next_message(&mut lines, 0).await.unwrap();
+        assert_eq!(response, json!({ "jsonrpc": "2.0", "id": 7, "result": { "ok": true } }));
+        agent.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_play_client_reports_mismatch() {
+        let replayer = Replayer::parse(TRANSCRIPT).unwrap();
+        let (client, agent) = duplex(4096);
+        let (client_read, client_write) = tokio::io::split(client);
+        let client = tokio::spawn(async move { replayer.play_client(client_read, client_write).await });
+
+        let (agent_read, mut agent_write) = tokio::io::split(agent);
+        let mut lines = BufReader::new(agent_read).lines();
+        assert_eq!(next_message(&mut lines, 0).await.unwrap()["method"], "initialize");
+        write_line(&mut agent_write, r#"{"jsonrpc":"2.0","id":1,"result":{}}"#).await.unwrap();
+
+        let err = client.await.unwrap().unwrap_err();
+        assert!(err.to_string().contains("step 2"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_recorder_tap() {
+        let path = std::env::temp_dir().join(format!("heroacp-tap-{}.jsonl", uuid::Uuid::new_v4()));
+        let recorder = Recorder::new(&path).unwrap();
+        let (client, client_end) = duplex(4096);
+        let (agent, agent_end) = duplex(4096);
+        let (client_out, client_in) = tokio::io::split(client_end);
+        let (agent_out, agent_in) = tokio::io::split(agent_end);
+        let tap = tokio::spawn(async move { recorder.tap(client_out, client_in, agent_out, agent_in).await });
+
+        // The recorded transcript replays against the same exchange
+        let replayer = Replayer::parse(TRANSCRIPT).unwrap();
+        let (agent_read, agent_write) = tokio::io::split(agent);
+        let agent = tokio::spawn(async move { replayer.play_agent(agent_read, agent_write).await });
+        let (client_read, mut client_write) = tokio::io::split(client);
+        let mut lines = BufReader::new(client_read).lines();
+        write_line(&mut client_write, r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"token":"t"}}"#)
+            .await
+            .unwrap();
+        next_message(&mut lines, 0).await.unwrap();
+        next_message(&mut lines, 0).await.unwrap();
+        agent.await.unwrap().unwrap();
+        drop(client_write);
+        drop(lines);
+        tap.await.unwrap().unwrap();
+
+        let recorded = Replayer::load(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(recorded.entries.len(), 3);
+        assert_eq!(recorded.entries[0].message["params"]["token"], REDACTED);
+        assert_eq!(recorded.entries[2].direction, Direction::Outbound);
+    }
+}
@@ -0,0 +1,59 @@
+//! Tests of the C ABI against the example agent.
+//!
+//! Enabled with the `ffi` feature:
+//!
+//! ```text
+//! cargo test --features ffi --test ffi
+//! ```
+#![cfg(feature = "ffi")]
+
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::sync::Mutex;
+
+use heroacp::ffi::*;
+use serde_json::{json, Value};
+
+extern "C" fn collect(user_data: *mut c_void, update_json: *const c_char) {
+    let updates = unsafe { &*(user_data as *const Mutex<Vec<String>>) };
+    let json = unsafe { CStr::from_ptr(update_json) }.to_str().unwrap().to_string();
+    updates.lock().unwrap().push(json);
+}
+
+#[test]
+fn test_ffi_prompt() {
+    let command = CString::new(env!("CARGO_BIN_EXE_acp-server")).unwrap();
+    let name = CString::new("ffi-test").unwrap();
+    let version = CString::new("1.0").unwrap();
+    let updates: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    unsafe {
+        let client = heroacp_client_spawn(command.as_ptr(), name.as_ptr(), version.as_ptr());
+        assert!(!client.is_null());
+        let user_data = &updates as *const _ as *mut c_void;
+        assert_eq!(heroacp_client_on_update(client, collect, user_data), 0);
+
+        let text = CString::new("Hello").unwrap();
+        assert_eq!(heroacp_client_prompt(client, text.as_ptr()), 0);
+        assert_eq!(heroacp_client_prompt(client, std::ptr::null()), -1);
+        assert_eq!(CStr::from_ptr(heroacp_last_error()).to_str().unwrap(), "text is null");
+        heroacp_client_free(client);
+    }
+
+    let updates = updates.into_inner().unwrap();
+    let types: Vec<Value> = updates
+        .iter()
+        .map(|json| serde_json::from_str::<Value>(json).unwrap()["type"].take())
+        .collect();
+    assert!(types.contains(&json!("agent_message_chunk")));
+}
+
+#[test]
+fn test_ffi_spawn_failure() {
+    let command = CString::new("/nonexistent/agent").unwrap();
+    let name = CString::new("ffi-test").unwrap();
+    unsafe {
+        let client = heroacp_client_spawn(command.as_ptr(), name.as_ptr(), name.as_ptr());
+        assert!(client.is_null());
+        assert!(!heroacp_last_error().is_null());
+    }
+}
@@ -1,13 +1,51 @@
 //! Integration tests for HeroACP.
 //!
-//! These tests verify the end-to-end functionality of the ACP server and client.
+//! These tests verify the end-to-end functionality of the ACP server and client
+//! against the example agent, `acp-server`. Cargo builds it for the tests in
+//! the same profile, so a plain `cargo test` runs them.
 
+use heroacp::client::{Client, ClientBuilder};
+use heroacp::protocol::AgentStatusResult;
+use std::path::Path;
 use std::process::Stdio;
 use std::time::Duration;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::process::Command;
 use tokio::time::timeout;
 
+/// The example agent as built by Cargo for this test run.
+const AGENT: &str = env!("CARGO_BIN_EXE_acp-server");
+
+/// Program and arguments that start the example agent: the binary Cargo
+/// built or, should it have gone since (e.g. after `cargo clean`), `cargo run`.
+fn agent_program() -> (String, Vec<String>) {
+    if Path::new(AGENT).exists() {
+        return (AGENT.to_string(), Vec::new());
+    }
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+    let args = ["run", "--quiet", "--bin", "acp-server", "--manifest-path"]
+        .into_iter()
+        .map(String::from)
+        .chain([manifest.to_string_lossy().into_owned()])
+        .collect();
+    (cargo, args)
+}
+
+/// A command that starts the example agent.
+fn agent_command() -> Command {
+    let (program, args) = agent_program();
+    let mut command = Command::new(program);
+    command.args(args);
+    command
+}
+
+/// A client builder for the example agent.
+fn agent() -> ClientBuilder {
+    let (program, args) = agent_program();
+    Client::builder(program).args(args)
+}
+
 /// Helper to send a JSON-RPC request and receive a response.
 async fn send_receive(
     stdin: &mut tokio::process::ChildStdin,
@@ -39,7 +77,7 @@ async fn receive_notification(
 
 #[tokio::test]
 async fn test_server_initialize() {
-    let mut child = Command::new("./target/release/acp-server")
+    let mut child = agent_command()
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::null())
@@ -79,7 +117,7 @@ async fn test_server_initialize() {
 
 #[tokio::test]
 async fn test_server_session_new() {
-    let mut child = Command::new("./target/release/acp-server")
+    let mut child = agent_command()
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::null())
@@ -128,7 +166,7 @@ async fn test_server_session_new() {
 
 #[tokio::test]
 async fn test_server_session_prompt_streaming() {
-    let mut child = Command::new("./target/release/acp-server")
+    let mut child = agent_command()
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::null())
@@ -220,7 +258,7 @@ async fn test_server_session_prompt_streaming() {
 
 #[tokio::test]
 async fn test_server_method_not_found() {
-    let mut child = Command::new("./target/release/acp-server")
+    let mut child = agent_command()
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::null())
@@ -252,7 +290,7 @@ async fn test_server_method_not_found() {
 
 #[tokio::test]
 async fn test_server_invalid_params() {
-    let mut child = Command::new("./target/release/acp-server")
+    let mut child = agent_command()
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::null())
@@ -288,7 +326,7 @@ async fn test_server_invalid_params() {
 
 #[tokio::test]
 async fn test_server_parse_error() {
-    let mut child = Command::new("./target/release/acp-server")
+    let mut child = agent_command()
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::null())
@@ -313,7 +351,7 @@ async fn test_server_parse_error() {
 
 #[tokio::test]
 async fn test_server_capabilities() {
-    let mut child = Command::new("./target/release/acp-server")
+    let mut child = agent_command()
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::null())
@@ -357,7 +395,7 @@ async fn test_server_capabilities() {
 
 #[tokio::test]
 async fn test_server_session_cancel() {
-    let mut child = Command::new("./target/release/acp-server")
+    let mut child = agent_command()
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::null())
@@ -413,7 +451,7 @@ async fn test_server_session_cancel() {
 
 #[tokio::test]
 async fn test_multiple_sessions() {
-    let mut child = Command::new("./target/release/acp-server")
+    let mut child = agent_command()
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::null())
@@ -464,3 +502,693 @@ async fn test_multiple_sessions() {
 
     child.kill().await.ok();
 }
+
+#[tokio::test]
+async fn test_server_rejects_audio_without_capability() {
+    let mut child = agent_command()
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start acp-server");
+
+    let mut stdin = child.stdin.take().unwrap();
+    let stdout = child.stdout.take().unwrap();
+    let mut lines = BufReader::new(stdout).lines();
+
+    // Initialize (the bogus agent does not advertise audio)
+    let init_request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "initialize",
+        "params": {
+            "protocol_version": "2025.1",
+            "client_info": {"name": "test", "version": "1.0"},
+            "capabilities": {"audio": true},
+            "working_directory": "/"
+        }
+    });
+    let _ = send_receive(&mut stdin, &mut lines, &init_request.to_string()).await;
+
+    let session_request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "session/new",
+        "params": {"session_id": "audio-session"}
+    });
+    let _ = send_receive(&mut stdin, &mut lines, &session_request.to_string()).await;
+
+    let prompt_request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 3,
+        "method": "session/prompt",
+        "params": {
+            "session_id": "audio-session",
+            "content": [{"type": "audio", "format": "wav", "data": "UklGRg=="}]
+        }
+    });
+
+    let response = send_receive(&mut stdin, &mut lines, &prompt_request.to_string())
+        .await
+        .expect("Failed to get response");
+
+    assert_eq!(response["id"], 3);
+    assert_eq!(response["error"]["code"], -32004); // CAPABILITY_NOT_SUPPORTED
+
+    child.kill().await.ok();
+}
+
+#[tokio::test]
+async fn test_server_string_request_id() {
+    let mut child = agent_command()
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start acp-server");
+
+    let mut stdin = child.stdin.take().unwrap();
+    let stdout = child.stdout.take().unwrap();
+    let mut lines = BufReader::new(stdout).lines();
+
+    let request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": "init-abc",
+        "method": "initialize",
+        "params": {
+            "protocol_version": "2025.1",
+            "client_info": {"name": "test", "version": "1.0"},
+            "capabilities": {},
+            "working_directory": "/"
+        }
+    });
+
+    let response = send_receive(&mut stdin, &mut lines, &request.to_string())
+        .await
+        .expect("Failed to get response");
+
+    // String IDs are echoed back verbatim, not re-encoded
+    assert_eq!(response["id"], "init-abc");
+    assert!(response["result"].is_object());
+
+    child.kill().await.ok();
+}
+
+#[tokio::test]
+async fn test_server_rejects_prompt_for_unknown_session() {
+    let mut child = agent_command()
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start acp-server");
+
+    let mut stdin = child.stdin.take().unwrap();
+    let stdout = child.stdout.take().unwrap();
+    let mut lines = BufReader::new(stdout).lines();
+
+    let prompt_req = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "session/prompt",
+        "params": {
+            "session_id": "never-created",
+            "content": [{"type": "text", "text": "Hello"}]
+        }
+    });
+
+    let response = send_receive(&mut stdin, &mut lines, &prompt_req.to_string())
+        .await
+        .expect("Failed to get response");
+
+    assert_eq!(response["id"], 1);
+    assert_eq!(response["error"]["code"], -32003);
+
+    child.kill().await.ok();
+}
+
+#[tokio::test]
+async fn test_server_ping() {
+    let mut child = agent_command()
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start acp-server");
+
+    let mut stdin = child.stdin.take().unwrap();
+    let stdout = child.stdout.take().unwrap();
+    let mut lines = BufReader::new(stdout).lines();
+
+    let ping = serde_json::json!({"jsonrpc": "2.0", "id": 1, "method": "ping"});
+    let response = send_receive(&mut stdin, &mut lines, &ping.to_string())
+        .await
+        .expect("Failed to ping");
+
+    assert_eq!(response["id"], 1);
+    assert!(response["result"].is_object());
+
+    child.kill().await.ok();
+}
+
+#[tokio::test]
+async fn test_client_healthcheck() {
+    let (program, args) = agent_program();
+    let output = Command::new(env!("CARGO_BIN_EXE_acp-client"))
+        .args(["--healthcheck", "--timeout", "30", &program])
+        .args(args)
+        .stderr(Stdio::null())
+        .output()
+        .await
+        .expect("Failed to run acp-client");
+    assert!(output.status.success());
+    let status: AgentStatusResult = serde_json::from_slice(&output.stdout).expect("Status is JSON");
+    assert_eq!(status.active_sessions, 0);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_acp-client"))
+        .args(["--healthcheck", "--timeout", "0.5", "sh", "-c", "sleep 5"])
+        .output()
+        .await
+        .expect("Failed to run acp-client");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[tokio::test]
+async fn test_server_cancel_during_prompt() {
+    let mut child = agent_command()
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start acp-server");
+
+    let mut stdin = child.stdin.take().unwrap();
+    let stdout = child.stdout.take().unwrap();
+    let mut lines = BufReader::new(stdout).lines();
+
+    let init_request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "initialize",
+        "params": {
+            "protocol_version": "2025.1",
+            "client_info": {"name": "test", "version": "1.0"},
+            "capabilities": {},
+            "working_directory": "/"
+        }
+    });
+    let _ = send_receive(&mut stdin, &mut lines, &init_request.to_string()).await;
+
+    let session_request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "session/new",
+        "params": {"session_id": "busy-session"}
+    });
+    let _ = send_receive(&mut stdin, &mut lines, &session_request.to_string()).await;
+
+    // Start a prompt and cancel it without waiting for the prompt to finish
+    let prompt_request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 3,
+        "method": "session/prompt",
+        "params": {
+            "session_id": "busy-session",
+            "content": [{"type": "text", "text": "Make a plan"}]
+        }
+    });
+    let cancel_request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 4,
+        "method": "session/cancel",
+        "params": {"session_id": "busy-session"}
+    });
+    for request in [prompt_request, cancel_request] {
+        stdin.write_all(request.to_string().as_bytes()).await.unwrap();
+        stdin.write_all(b"\n").await.unwrap();
+    }
+    stdin.flush().await.unwrap();
+
+    // The cancel response must arrive before the prompt completes
+    loop {
+        let msg = receive_notification(&mut lines)
+            .await
+            .expect("Failed to receive message");
+        assert_ne!(msg["id"], 3, "prompt finished before cancel was answered");
+        if msg["id"] == 4 {
+            assert!(msg.get("error").is_none());
+            break;
+        }
+    }
+
+    // The agent observes the cancellation and stops early
+    loop {
+        let msg = receive_notification(&mut lines)
+            .await
+            .expect("Failed to receive message");
+        if msg["id"] == 3 {
+            assert_eq!(msg["result"]["status"], "cancelled");
+            break;
+        }
+    }
+
+    child.kill().await.ok();
+}
+
+#[tokio::test]
+async fn test_server_streamed_prompt() {
+    let mut child = agent_command()
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start acp-server");
+
+    let mut stdin = child.stdin.take().unwrap();
+    let stdout = child.stdout.take().unwrap();
+    let mut lines = BufReader::new(stdout).lines();
+
+    let init_request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "initialize",
+        "params": {
+            "protocol_version": "2025.1",
+            "client_info": {"name": "test", "version": "1.0"},
+            "capabilities": {},
+            "working_directory": "/"
+        }
+    });
+    let _ = send_receive(&mut stdin, &mut lines, &init_request.to_string()).await;
+
+    let session_request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "session/new",
+        "params": {"session_id": "stream-session"}
+    });
+    let _ = send_receive(&mut stdin, &mut lines, &session_request.to_string()).await;
+
+    let start_request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 3,
+        "method": "session/prompt_stream_start",
+        "params": {"session_id": "stream-session"}
+    });
+    let response = send_receive(&mut stdin, &mut lines, &start_request.to_string())
+        .await
+        .expect("Failed to start stream");
+    let stream_id = response["result"]["stream_id"].as_str().unwrap().to_string();
+
+    for (id, text, continuation) in [(4, "Streamed ", false), (5, "prompt", true)] {
+        let chunk_request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "session/prompt_stream_chunk",
+            "params": {
+                "stream_id": stream_id,
+                "content": [{"type": "text", "text": text}],
+                "continuation": continuation
+            }
+        });
+        let response = send_receive(&mut stdin, &mut lines, &chunk_request.to_string())
+            .await
+            .expect("Failed to send chunk");
+        assert!(response.get("error").is_none());
+    }
+
+    let end_request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 6,
+        "method": "session/prompt_stream_end",
+        "params": {"stream_id": stream_id}
+    });
+    stdin.write_all(end_request.to_string().as_bytes()).await.unwrap();
+    stdin.write_all(b"\n").await.unwrap();
+    stdin.flush().await.unwrap();
+
+    // The agent sees the reassembled prompt and echoes it back
+    let mut reply = String::new();
+    loop {
+        let msg = receive_notification(&mut lines)
+            .await
+            .expect("Failed to receive message");
+        if msg["id"] == 6 {
+            assert_eq!(msg["result"]["status"], "ok");
+            break;
+        }
+        if msg["params"]["type"] == "agent_message_chunk" {
+            reply.push_str(msg["params"]["data"]["text"].as_str().unwrap());
+        }
+    }
+    assert!(reply.contains("Streamed prompt"), "{}", reply);
+
+    child.kill().await.ok();
+}
+
+#[tokio::test]
+async fn test_server_custom_method() {
+    let mut child = agent_command()
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start acp-server");
+
+    let mut stdin = child.stdin.take().unwrap();
+    let stdout = child.stdout.take().unwrap();
+    let mut lines = BufReader::new(stdout).lines();
+
+    let echo_request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "bogus/echo",
+        "params": {"text": "hi"}
+    });
+
+    // Custom methods need an initialized connection
+    let response = send_receive(&mut stdin, &mut lines, &echo_request.to_string())
+        .await
+        .expect("Failed to get response");
+    assert_eq!(response["error"]["code"], -32003); // INVALID_STATE
+
+    let init_request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "initialize",
+        "params": {
+            "protocol_version": "2025.1",
+            "client_info": {"name": "test", "version": "1.0"},
+            "capabilities": {},
+            "working_directory": "/"
+        }
+    });
+    let response = send_receive(&mut stdin, &mut lines, &init_request.to_string())
+        .await
+        .expect("Failed to initialize");
+    assert_eq!(
+        response["result"]["capabilities"]["custom_methods"],
+        serde_json::json!(["bogus/echo"])
+    );
+
+    let response = send_receive(&mut stdin, &mut lines, &echo_request.to_string())
+        .await
+        .expect("Failed to get response");
+    assert_eq!(response["result"]["text"], "hi");
+
+    child.kill().await.ok();
+}
+
+#[tokio::test]
+async fn test_client_prompt_stream() {
+    use heroacp::protocol::*;
+    use heroacp::testing::fixtures::*;
+
+    let client = agent().spawn().await.expect("Failed to start acp-server");
+    client
+        .initialize(heroacp::testing::initialize_params())
+        .await
+        .expect("Failed to initialize");
+    client
+        .session_new(session_new().in_session("stream-client"))
+        .await
+        .expect("Failed to create session");
+
+    let mut stream = client
+        .prompt_stream(prompt("Hello").in_session("stream-client"))
+        .await
+        .expect("Failed to send prompt");
+
+    let mut updates = Vec::new();
+    while let Some(update) = timeout(Duration::from_secs(10), stream.next())
+        .await
+        .expect("Timed out waiting for updates")
+    {
+        assert_eq!(update.session_id, "stream-client");
+        updates.push(update);
+    }
+    assert!(updates
+        .iter()
+        .any(|u| matches!(u.update_type, SessionUpdateType::AgentMessageChunk { .. })));
+    assert!(matches!(
+        updates.last().unwrap().update_type,
+        SessionUpdateType::Done
+    ));
+
+    let result = stream.result().await.expect("Prompt failed");
+    assert_eq!(result.status, "ok");
+}
+
+#[tokio::test]
+async fn test_client_request_raw() {
+    let client = agent().spawn().await.expect("Failed to start acp-server");
+    client
+        .initialize(heroacp::testing::initialize_params())
+        .await
+        .expect("Failed to initialize");
+
+    let result = client
+        .request_raw("bogus/echo", serde_json::json!({"text": "raw"}))
+        .await
+        .expect("Custom method failed");
+    assert_eq!(result["text"], "raw");
+
+    let err = client
+        .request_raw("bogus/missing", serde_json::json!({}))
+        .await
+        .unwrap_err();
+    assert!(err.message().contains("bogus/missing"));
+}
+
+#[tokio::test]
+async fn test_client_graceful_shutdown() {
+    let mut client = agent().spawn().await.expect("Failed to start acp-server");
+    client
+        .initialize(heroacp::testing::initialize_params())
+        .await
+        .expect("Failed to initialize");
+
+    // The server exits on its own once its input ends
+    let started = std::time::Instant::now();
+    client
+        .shutdown(Duration::from_secs(5))
+        .await
+        .expect("Shutdown failed");
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert!(!client.is_running());
+}
+
+#[tokio::test]
+async fn test_agent_pool_routes_sessions() {
+    use heroacp::client::AgentPool;
+    use heroacp::protocol::*;
+    use heroacp::testing::fixtures::*;
+
+    let pool = AgentPool::new();
+    for name in ["first", "second"] {
+        pool.add(name, agent(), heroacp::testing::initialize_params())
+            .await
+            .expect("Failed to add agent");
+    }
+    assert_eq!(pool.names(), vec!["first", "second"]);
+
+    // Each server only knows its own session, so a misrouted prompt fails
+    for (agent, session) in [("first", "pool-a"), ("second", "pool-b")] {
+        pool.session_new(agent, session_new().in_session(session))
+            .await
+            .expect("Failed to create session");
+    }
+    assert_eq!(pool.session_agent("pool-b").as_deref(), Some("second"));
+    for session in ["pool-a", "pool-b"] {
+        let result = pool
+            .session_prompt(prompt("Hi").in_session(session))
+            .await
+            .expect("Prompt failed");
+        assert_eq!(result.status, "ok");
+    }
+
+    let err = pool
+        .session_prompt(SessionPromptParams {
+            session_id: "unknown".to_string(),
+            content: vec![],
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), codes::RESOURCE_NOT_FOUND);
+
+    let caps = pool.capabilities().await;
+    assert!(caps.custom_methods.contains(&"bogus/echo".to_string()));
+    // Both agents are the same server, so each tool is offered twice
+    let tools = pool.tools().await;
+    let first = tools.iter().filter(|(agent, _)| agent == "first").count();
+    assert!(first > 0);
+    assert_eq!(tools.len(), first * 2);
+
+    pool.shutdown(Duration::from_secs(5)).await;
+}
+
+#[tokio::test]
+async fn test_client_session_history() {
+    use heroacp::client::TranscriptEntry;
+    use heroacp::protocol::*;
+    use heroacp::testing::fixtures::*;
+
+    let client = agent().spawn().await.expect("Failed to start acp-server");
+    client
+        .initialize(heroacp::testing::initialize_params())
+        .await
+        .expect("Failed to initialize");
+    client
+        .session_new(session_new().in_session("history"))
+        .await
+        .expect("Failed to create session");
+    assert!(client.session_history("history").is_none());
+
+    for text in ["Hello", "Again"] {
+        let mut stream = client
+            .prompt_stream(prompt(text).in_session("history"))
+            .await
+            .expect("Failed to send prompt");
+        while timeout(Duration::from_secs(10), stream.next())
+            .await
+            .expect("Timed out waiting for updates")
+            .is_some()
+        {}
+    }
+
+    let transcript = client.session_history("history").expect("No transcript");
+    let prompts: Vec<_> = transcript
+        .entries
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| matches!(e, TranscriptEntry::Prompt(_)))
+        .map(|(i, _)| i)
+        .collect();
+    assert_eq!(prompts.len(), 2);
+    assert_eq!(prompts[0], 0);
+    // Each prompt is followed by the agent's reply
+    assert!(matches!(
+        transcript.entries[prompts[1] - 1],
+        TranscriptEntry::Update(SessionUpdateType::Done)
+    ));
+    assert!(transcript.entries.iter().any(|e| matches!(
+        e,
+        TranscriptEntry::Update(SessionUpdateType::AgentMessageChunk { .. })
+    )));
+
+    client.clear_session_history("history");
+    assert!(client.session_history("history").is_none());
+}
+
+#[tokio::test]
+async fn test_client_connect() {
+    use heroacp::client::ClientSetup;
+    use heroacp::protocol::*;
+
+    let mut setup = ClientSetup::new("integration-test", "1.0.0");
+    setup.cwd = Some("/tmp".to_string());
+    let conn = agent().connect(setup).await.expect("Failed to connect");
+    assert!(!conn.init.agent_info.name.is_empty());
+    assert!(conn.client.agent_capabilities().await.is_some());
+
+    let result = conn
+        .session()
+        .prompt(vec![ContentBlock::text("Hello")])
+        .await
+        .expect("Prompt failed");
+    assert_eq!(result.status, "ok");
+    assert_eq!(conn.session().session_id(), conn.session_id);
+}
+
+#[tokio::test]
+async fn test_record_and_replay_agent() {
+    use heroacp::testing::{Recorder, Replayer};
+    use tokio::io::duplex;
+
+    let spawn = || {
+        agent_command()
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to start acp-server")
+    };
+    let path = std::env::temp_dir().join(format!("heroacp-replay-{}.jsonl", std::process::id()));
+
+    // Record a session with the agent
+    let mut child = spawn();
+    let (agent_out, agent_in) = (child.stdout.take().unwrap(), child.stdin.take().unwrap());
+    let (client, client_end) = duplex(64 * 1024);
+    let (client_out, client_in) = tokio::io::split(client_end);
+    let recorder = Recorder::new(&path).unwrap();
+    let tap = tokio::spawn(async move { recorder.tap(client_out, client_in, agent_out, agent_in).await });
+
+    let (client_read, mut client_write) = tokio::io::split(client);
+    let mut lines = BufReader::new(client_read).lines();
+    let requests = [
+        r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocol_version":"2025.1","client_info":{"name":"test","version":"1.0"},"capabilities":{},"working_directory":"/tmp"}}"#,
+        r#"{"jsonrpc":"2.0","id":2,"method":"session/new","params":{"session_id":"replay"}}"#,
+        r#"{"jsonrpc":"2.0","id":3,"method":"session/prompt","params":{"session_id":"replay","content":[{"type":"text","text":"Hello"}]}}"#,
+    ];
+    for (i, request) in requests.iter().enumerate() {
+        client_write.write_all(format!("{}\n", request).as_bytes()).await.unwrap();
+        loop {
+            let line = timeout(Duration::from_secs(5), lines.next_line()).await.unwrap().unwrap().unwrap();
+            let msg: serde_json::Value = serde_json::from_str(&line).unwrap();
+            if msg["id"] == i + 1 && msg.get("method").is_none() {
+                break;
+            }
+        }
+    }
+    // Let the last updates arrive before closing
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    drop(client_write);
+    drop(lines);
+    timeout(Duration::from_secs(5), tap).await.unwrap().unwrap().unwrap();
+
+    // The agent answers the replayed client the same way
+    let mut child = spawn();
+    let (agent_out, agent_in) = (child.stdout.take().unwrap(), child.stdin.take().unwrap());
+    let replayer = Replayer::load(&path).unwrap().strict(true);
+    std::fs::remove_file(&path).ok();
+    timeout(Duration::from_secs(10), replayer.play_client(agent_out, agent_in))
+        .await
+        .unwrap()
+        .unwrap();
+    child.kill().await.ok();
+}
+
+#[tokio::test]
+async fn test_scenario_agent() {
+    use heroacp::client::ClientSetup;
+    use heroacp::protocol::*;
+
+    let conn = agent()
+        .env("ACP_SCENARIO", "tests/fixtures/scenarios/tools.json")
+        .connect(ClientSetup::new("integration-test", "1.0.0"))
+        .await
+        .expect("Failed to connect");
+    assert_eq!(conn.init.agent_info.name, "scripted-agent");
+
+    let result = conn.session().prompt(vec![ContentBlock::text("Please fix the test")]).await;
+    assert_eq!(result.expect("Prompt failed").status, "ok");
+
+    // The injected error only happens once
+    let err = conn
+        .session()
+        .prompt(vec![ContentBlock::text("overload")])
+        .await
+        .expect_err("Error not injected");
+    assert_eq!(err.code(), -32005);
+    let result = conn.session().prompt(vec![ContentBlock::text("overload")]).await;
+    assert_eq!(result.expect("Prompt failed").status, "ok");
+}
+
+#[tokio::test]
+async fn test_server_passes_conformance() {
+    use heroacp::testing::Conformance;
+
+    let (program, args) = agent_program();
+    let report = Conformance::new(program).args(args).run().await;
+    assert!(report.passed(), "{}", report);
+}
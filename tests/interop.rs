@@ -0,0 +1,50 @@
+//! Interop tests against real agents.
+//!
+//! Enabled with the `interop` feature. They need the agent installed and
+//! configured with a model, so they are left out of the default test run:
+//!
+//! ```text
+//! GOOSE_BIN=/path/to/goose cargo test --features interop --test interop
+//! ```
+#![cfg(feature = "interop")]
+
+use std::time::Duration;
+
+use heroacp::client::{Client, ClientSetup, Dialect};
+use heroacp::protocol::*;
+
+/// Connect to the agent at `$env` (or `default` on `PATH`) with `args`.
+async fn connect(env: &str, default: &str, args: &[&str], dialect: Dialect) -> heroacp::client::Connection {
+    let command = std::env::var(env).unwrap_or_else(|_| default.to_string());
+    Client::builder(command)
+        .args(args.iter().copied())
+        .dialect(dialect)
+        .request_timeout(Some(Duration::from_secs(120)))
+        .connect(ClientSetup::new("heroacp-interop", env!("CARGO_PKG_VERSION")))
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_goose_prompt() {
+    let conn = connect("GOOSE_BIN", "goose", &["acp"], Dialect::Goose).await;
+    assert!(!conn.session_id.is_empty());
+
+    let mut stream = conn
+        .client
+        .prompt_stream(SessionPromptParams {
+            session_id: conn.session_id.clone(),
+            content: vec![ContentBlock::text("Reply with the single word: pong")],
+        })
+        .await
+        .unwrap();
+    let mut reply = String::new();
+    while let Some(update) = stream.next().await {
+        assert_eq!(update.session_id, conn.session_id);
+        if let SessionUpdateType::AgentMessageChunk { text } = update.update_type {
+            reply.push_str(&text);
+        }
+    }
+    assert_eq!(stream.result().await.unwrap().status, "ok");
+    assert!(reply.to_lowercase().contains("pong"), "{}", reply);
+}